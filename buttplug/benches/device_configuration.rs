@@ -0,0 +1,96 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Benchmarks for matching discovered devices against the device configuration.
+//!
+//! Run with `cargo bench --bench device_configuration`. Matching runs once per advertisement during
+//! scanning, so it's measured against both the shipped config and one padded out with a couple
+//! thousand extra protocols, for advertisements we support and the far more common ones we don't.
+
+use buttplug::{
+  server::device::configuration::{
+    BluetoothLESpecifier,
+    DeviceConfigurationManager,
+    DeviceConfigurationManagerBuilder,
+    ProtocolCommunicationSpecifier,
+  },
+  util::device_configuration::load_protocol_configs,
+};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+const EXTRA_PROTOCOLS: u128 = 2000;
+
+fn btle(names: &[&str], services: &[Uuid]) -> ProtocolCommunicationSpecifier {
+  ProtocolCommunicationSpecifier::BluetoothLE(BluetoothLESpecifier::new(
+    names.iter().map(|name| name.to_string()).collect(),
+    vec![],
+    services.iter().copied().collect(),
+    HashMap::new(),
+  ))
+}
+
+fn large_config_builder() -> DeviceConfigurationManagerBuilder {
+  let mut builder = load_protocol_configs(None, None, false).expect("Default config is valid");
+  for i in 0..EXTRA_PROTOCOLS {
+    let exact = format!("Bench Device {}", i);
+    let prefix = format!("BENCH-{}-*", i);
+    builder.communication_specifier(
+      &format!("bench-protocol-{}", i),
+      btle(&[&exact, &prefix], &[Uuid::from_u128(0xbe0c_0000 + i)]),
+    );
+  }
+  builder
+}
+
+fn advertisements() -> Vec<(&'static str, ProtocolCommunicationSpecifier)> {
+  vec![
+    ("exact name", btle(&["Flamingo"], &[])),
+    ("wildcard name", btle(&["LVS-Z36D"], &[])),
+    (
+      "unsupported",
+      btle(
+        &["Pixel 7"],
+        &[Uuid::from_u128(0xfe9f), Uuid::from_u128(0x180f)],
+      ),
+    ),
+  ]
+}
+
+fn bench_protocol_specializers(c: &mut Criterion) {
+  let configs = [
+    ("default config", DeviceConfigurationManager::default()),
+    (
+      "large config",
+      large_config_builder()
+        .finish()
+        .expect("Large config is valid"),
+    ),
+  ];
+  let mut group = c.benchmark_group("protocol_specializers");
+  for (config_name, dcm) in &configs {
+    for (advertisement_name, specifier) in advertisements() {
+      group.bench_with_input(
+        BenchmarkId::new(*config_name, advertisement_name),
+        &specifier,
+        |b, specifier| b.iter(|| dcm.protocol_specializers(black_box(specifier))),
+      );
+    }
+  }
+  group.finish();
+}
+
+fn bench_finish(c: &mut Criterion) {
+  let mut builder = large_config_builder();
+  c.bench_function("finish large config", |b| {
+    b.iter(|| builder.finish().expect("Large config is valid"))
+  });
+}
+
+criterion_group!(benches, bench_protocol_specializers, bench_finish);
+criterion_main!(benches);
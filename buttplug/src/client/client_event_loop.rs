@@ -47,6 +47,12 @@ pub enum ButtplugClientRequest {
   /// Bundled future should have reply set and waker called when this is
   /// finished.
   Message(ButtplugClientMessageFuturePair),
+  /// Client request to send a set of messages via the connector as a single
+  /// batch.
+  ///
+  /// Each bundled future should have its reply set and waker called when its
+  /// message is finished.
+  MessageBatch(Vec<ButtplugClientMessageFuturePair>),
 }
 
 /// Event loop for running [ButtplugClient] connections.
@@ -239,10 +245,27 @@ where
           self.send_client_event(ButtplugClientEvent::Error(ButtplugDeviceError::DeviceConnectionError("Device removal requested for a device the client does not know about. Server may be in a weird state.".to_owned()).into()));
         }
       }
+      ButtplugCurrentSpecServerMessage::DeviceAttributes(msg) => {
+        let info = DeviceMessageInfo::from(msg);
+        let device = if let Some(device) = self.device_map.get(&info.device_index()) {
+          Arc::new(device.value().with_device_info(&info))
+        } else {
+          error!("Received DeviceAttributes for non-existent device index");
+          return;
+        };
+        self.device_map.insert(info.device_index(), device.clone());
+        self.send_client_event(ButtplugClientEvent::DeviceAttributesChanged(device));
+      }
       ButtplugCurrentSpecServerMessage::ScanningFinished(_) => {
         trace!("Scanning finished event received, forwarding to client.");
         self.send_client_event(ButtplugClientEvent::ScanningFinished);
       }
+      ButtplugCurrentSpecServerMessage::OperationProgress(msg) => {
+        self.send_client_event(ButtplugClientEvent::OperationProgress(
+          msg.request_id(),
+          msg.progress(),
+        ));
+      }
       ButtplugCurrentSpecServerMessage::RawReading(msg) => {
         let device_idx = msg.device_index();
         if let Some(device) = self.device_map.get(&device_idx) {
@@ -264,7 +287,7 @@ where
         }
       }
       ButtplugCurrentSpecServerMessage::Error(e) => {
-        self.send_client_event(ButtplugClientEvent::Error(e.into()));
+        self.send_client_event(ButtplugClientEvent::Error(e.original_error()));
       }
       _ => error!("Cannot process message, dropping: {:?}", msg),
     }
@@ -287,6 +310,34 @@ where
     }
   }
 
+  /// Send a set of messages from the [ButtplugClient] to the [ButtplugClientConnector] as a single
+  /// batch.
+  ///
+  /// If any message in the batch isn't valid, none of them are sent, and every message in the batch
+  /// is replied to with the error.
+  async fn send_message_batch(&mut self, mut msg_futs: Vec<ButtplugClientMessageFuturePair>) {
+    for msg_fut in &msg_futs {
+      if let Err(e) = &msg_fut.msg.is_valid() {
+        error!("Message not valid: {:?} - Error: {}", msg_fut.msg, e);
+        for batch_msg_fut in &msg_futs {
+          batch_msg_fut
+            .waker
+            .set_reply(Err(ButtplugError::from(e.clone()).into()));
+        }
+        return;
+      }
+    }
+
+    trace!("Sending batch of {} messages to connector.", msg_futs.len());
+    for msg_fut in &mut msg_futs {
+      self.sorter.register_future(msg_fut);
+    }
+    let msgs = msg_futs.into_iter().map(|msg_fut| msg_fut.msg).collect();
+    if self.connector.send_batch(msgs).await.is_err() {
+      error!("Sending message batch failed, connector most likely no longer connected.");
+    }
+  }
+
   /// Parses message types from the client, returning false when disconnect
   /// happens.
   ///
@@ -302,6 +353,11 @@ where
         self.send_message(msg_fut).await;
         true
       }
+      ButtplugClientRequest::MessageBatch(msg_futs) => {
+        trace!("Sending message batch through connector.");
+        self.send_message_batch(msg_futs).await;
+        true
+      }
       ButtplugClientRequest::Disconnect(state) => {
         trace!("Client requested disconnect");
         state.set_reply(self.connector.disconnect().await);
@@ -358,6 +414,9 @@ where
       .iter()
       .for_each(|k| self.disconnect_device(*k));
     self.connected_status.store(false, Ordering::SeqCst);
+    if self.connector.connection_lost() {
+      self.send_client_event(ButtplugClientEvent::ConnectionLost);
+    }
     self.send_client_event(ButtplugClientEvent::ServerDisconnect);
 
     debug!("Exiting client event loop.");
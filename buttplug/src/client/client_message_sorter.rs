@@ -8,11 +8,7 @@
 //! Handling of remote message pairing and future resolution.
 
 use crate::{
-  client::{
-    ButtplugClientError,
-    ButtplugClientMessageFuturePair,
-    ButtplugServerMessageStateShared,
-  },
+  client::{ButtplugClientMessageFuturePair, ButtplugServerMessageStateShared},
   core::message::{ButtplugCurrentSpecServerMessage, ButtplugMessage, ButtplugMessageValidator},
 };
 use dashmap::DashMap;
@@ -93,7 +89,7 @@ impl ClientMessageSorter {
         trace!("Resolved id {} to a future.", id);
         if let Err(e) = msg.is_valid() {
           error!("Message not valid: {:?} - Error: {}", msg, e);
-          state.set_reply(Err(ButtplugClientError::ButtplugError(e.into())));
+          state.set_reply(Err(e.into()));
         } else if let ButtplugCurrentSpecServerMessage::Error(e) = msg {
           state.set_reply(Err(e.original_error().into()))
         } else {
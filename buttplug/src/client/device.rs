@@ -14,26 +14,35 @@ use super::{
 };
 use crate::{
   core::{
+    connector::ButtplugConnectorError,
     errors::{ButtplugDeviceError, ButtplugError, ButtplugMessageError},
     message::{
       ActuatorType,
       ButtplugCurrentSpecClientMessage,
       ButtplugCurrentSpecServerMessage,
+      ButtplugDeviceMessage,
       ButtplugDeviceMessageType,
       ClientDeviceMessageAttributes,
       ClientGenericDeviceMessageAttributes,
       DeviceMessageInfo,
       Endpoint,
+      EstimArmCmd,
+      FirmwareModeCmd,
       LinearCmd,
+      PatternCmd,
+      PatternKeyframe,
       RawReadCmd,
       RawSubscribeCmd,
       RawUnsubscribeCmd,
       RawWriteCmd,
+      RequestDeviceAttributes,
       RotateCmd,
       RotationSubcommand,
       ScalarCmd,
       ScalarSubcommand,
       SensorReadCmd,
+      SensorDeviceMessageAttributes,
+      SensorReading,
       SensorSubscribeCmd,
       SensorType,
       SensorUnsubscribeCmd,
@@ -43,15 +52,17 @@ use crate::{
   },
   util::stream::convert_broadcast_receiver_to_stream,
 };
-use futures::{FutureExt, Stream};
+use futures::{future, stream, FutureExt, Stream, StreamExt};
 use getset::{CopyGetters, Getters};
 use std::{
   collections::HashMap,
   fmt,
+  pin::Pin,
   sync::{
     atomic::{AtomicBool, Ordering},
     Arc,
   },
+  task::{Context, Poll},
 };
 use tokio::sync::broadcast;
 
@@ -140,6 +151,169 @@ pub enum LinearCommand {
   LinearMap(HashMap<u32, (u32, f64)>),
 }
 
+/// A single device command, for sending as part of a scene via
+/// [ButtplugClient::send_scene][super::ButtplugClient::send_scene].
+///
+/// Each command carries the index of the device it's addressed to, so a scene can change the state
+/// of multiple devices at once.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeviceCommand {
+  Scalar(ScalarCmd),
+  Rotate(RotateCmd),
+  Linear(LinearCmd),
+  Stop(StopDeviceCmd),
+}
+
+impl DeviceCommand {
+  /// Index of the device the command is addressed to.
+  pub fn device_index(&self) -> u32 {
+    match self {
+      DeviceCommand::Scalar(msg) => msg.device_index(),
+      DeviceCommand::Rotate(msg) => msg.device_index(),
+      DeviceCommand::Linear(msg) => msg.device_index(),
+      DeviceCommand::Stop(msg) => msg.device_index(),
+    }
+  }
+}
+
+impl From<DeviceCommand> for ButtplugCurrentSpecClientMessage {
+  fn from(cmd: DeviceCommand) -> Self {
+    match cmd {
+      DeviceCommand::Scalar(msg) => msg.into(),
+      DeviceCommand::Rotate(msg) => msg.into(),
+      DeviceCommand::Linear(msg) => msg.into(),
+      DeviceCommand::Stop(msg) => msg.into(),
+    }
+  }
+}
+
+/// A feature of a device, for stopping through a [DeviceHold].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeldFeature {
+  /// Feature at this index of the device's ScalarCmd attributes.
+  Scalar(u32),
+  /// Feature at this index of the device's RotateCmd attributes.
+  Rotate(u32),
+}
+
+/// Stops a device, or some of its features, when dropped.
+///
+/// Obtained from [ButtplugClientDevice::hold] or [ButtplugClientDevice::hold_features]. Keep the
+/// hold alive while the device is meant to be running, and it will stop once the hold goes out of
+/// scope, whether through a normal return, a panic unwinding, or the task owning it being
+/// cancelled.
+///
+/// Delivery is best effort. Dropping can't wait on the server, so the stop is queued with the
+/// client and never confirmed, and nothing is sent if the client or device has already
+/// disconnected.
+#[must_use = "The device is stopped as soon as the hold is dropped."]
+pub struct DeviceHold {
+  device_index: u32,
+  stop_messages: Vec<ButtplugCurrentSpecClientMessage>,
+  event_loop_sender: Arc<ButtplugClientMessageSender>,
+  device_connected: Arc<AtomicBool>,
+  client_connected: Arc<AtomicBool>,
+}
+
+impl DeviceHold {
+  /// Index of the held device.
+  pub fn device_index(&self) -> u32 {
+    self.device_index
+  }
+
+  /// Gives up the hold without stopping anything.
+  pub fn release(mut self) {
+    self.stop_messages.clear();
+  }
+}
+
+impl Drop for DeviceHold {
+  fn drop(&mut self) {
+    if self.stop_messages.is_empty()
+      || !self.client_connected.load(Ordering::SeqCst)
+      || !self.device_connected.load(Ordering::SeqCst)
+    {
+      return;
+    }
+    for msg in self.stop_messages.drain(..) {
+      if !self.event_loop_sender.send_message_without_reply(msg) {
+        warn!(
+          "Could not stop device {} when releasing hold, client has stopped.",
+          self.device_index
+        );
+      }
+    }
+  }
+}
+
+impl fmt::Debug for DeviceHold {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("DeviceHold")
+      .field("device_index", &self.device_index)
+      .field("stop_messages", &self.stop_messages)
+      .finish()
+  }
+}
+
+/// Levels of one of a device's sensors, from 0.0 to 1.0 across the sensor's range, which
+/// unsubscribes from the sensor when dropped.
+///
+/// Obtained from [ButtplugClientDevice::subscribe_battery] or
+/// [ButtplugClientDevice::subscribe_rssi]. The stream starts with the current level if the sensor
+/// can be read, then yields new levels as the server sends them, and ends when the device is
+/// removed or the client disconnects. As with [DeviceHold], unsubscribing on drop is best effort.
+#[must_use = "The sensor is unsubscribed from as soon as the stream is dropped."]
+pub struct SensorLevelStream {
+  device_index: u32,
+  levels: Pin<Box<dyn Stream<Item = f64> + Send>>,
+  unsubscribe_message: Option<ButtplugCurrentSpecClientMessage>,
+  event_loop_sender: Arc<ButtplugClientMessageSender>,
+  device_connected: Arc<AtomicBool>,
+  client_connected: Arc<AtomicBool>,
+}
+
+impl SensorLevelStream {
+  /// Index of the device the sensor belongs to.
+  pub fn device_index(&self) -> u32 {
+    self.device_index
+  }
+}
+
+impl Stream for SensorLevelStream {
+  type Item = f64;
+
+  fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    self.levels.poll_next_unpin(cx)
+  }
+}
+
+impl Drop for SensorLevelStream {
+  fn drop(&mut self) {
+    if !self.client_connected.load(Ordering::SeqCst)
+      || !self.device_connected.load(Ordering::SeqCst)
+    {
+      return;
+    }
+    if let Some(msg) = self.unsubscribe_message.take() {
+      if !self.event_loop_sender.send_message_without_reply(msg) {
+        warn!(
+          "Could not unsubscribe from sensor on device {}, client has stopped.",
+          self.device_index
+        );
+      }
+    }
+  }
+}
+
+impl fmt::Debug for SensorLevelStream {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("SensorLevelStream")
+      .field("device_index", &self.device_index)
+      .field("unsubscribe_message", &self.unsubscribe_message)
+      .finish()
+  }
+}
+
 #[derive(Getters, CopyGetters)]
 /// Client-usable representation of device connected to the corresponding
 /// [ButtplugServer][crate::server::ButtplugServer]
@@ -154,6 +328,9 @@ pub struct ButtplugClientDevice {
   /// Display name of the device
   #[getset(get = "pub")]
   display_name: Option<String>,
+  /// Description of the device, in the language the client asked for if the server has one.
+  #[getset(get = "pub")]
+  description: Option<String>,
   /// Index of the device, matching the index in the
   /// [ButtplugServer][crate::server::ButtplugServer]'s
   /// [DeviceManager][crate::server::device_manager::DeviceManager].
@@ -210,6 +387,7 @@ impl ButtplugClientDevice {
     Self {
       name: name.to_owned(),
       display_name: display_name.clone(),
+      description: None,
       index,
       message_attributes: message_attributes.clone(),
       event_loop_sender: message_sender.clone(),
@@ -223,19 +401,89 @@ impl ButtplugClientDevice {
     info: &DeviceMessageInfo,
     sender: &Arc<ButtplugClientMessageSender>,
   ) -> Self {
-    ButtplugClientDevice::new(
+    let mut device = ButtplugClientDevice::new(
       info.device_name(),
       info.device_display_name(),
       info.device_index(),
       info.device_messages(),
       sender,
-    )
+    );
+    device.description = info.device_description().clone();
+    device
+  }
+
+  /// Copy of this device with the names and message attributes in `info`, sharing its connection
+  /// state and event stream.
+  pub(super) fn with_device_info(&self, info: &DeviceMessageInfo) -> Self {
+    Self {
+      name: info.device_name().clone(),
+      display_name: info.device_display_name().clone(),
+      description: info.device_description().clone(),
+      index: self.index,
+      message_attributes: info.device_messages().clone(),
+      event_loop_sender: self.event_loop_sender.clone(),
+      internal_event_sender: self.internal_event_sender.clone(),
+      device_connected: self.device_connected.clone(),
+      client_connected: self.client_connected.clone(),
+    }
   }
 
   pub fn connected(&self) -> bool {
     self.device_connected.load(Ordering::SeqCst)
   }
 
+  /// Sends a message through the owning [ButtplugClient][super::ButtplugClient].
+  ///
+  /// Fails immediately if either the client or the device has disconnected, rather than sending a
+  /// command the server can no longer route.
+  fn send_message(
+    &self,
+    msg: ButtplugCurrentSpecClientMessage,
+  ) -> ButtplugClientResultFuture<ButtplugCurrentSpecServerMessage> {
+    if !self.client_connected.load(Ordering::SeqCst) {
+      error!("Client not connected, cannot run device command");
+      return future::ready(Err(ButtplugConnectorError::ConnectorNotConnected.into())).boxed();
+    } else if !self.connected() {
+      error!("Device not connected, cannot run device command");
+      return create_boxed_future_client_error(
+        ButtplugDeviceError::DeviceDisconnected(self.name.clone()).into(),
+      );
+    }
+    self.event_loop_sender.send_message(msg)
+  }
+
+  /// Sends a message, expecting back an [Ok][crate::core::message::Ok] message.
+  fn send_message_expect_ok(
+    &self,
+    msg: ButtplugCurrentSpecClientMessage,
+  ) -> ButtplugClientResultFuture {
+    let send_fut = self.send_message(msg);
+    async move { send_fut.await.map(|_| ()) }.boxed()
+  }
+
+  /// Asks the server for the current attributes of the device.
+  ///
+  /// This device keeps the attributes it was created with. When the server changes them, the
+  /// client sends a [DeviceAttributesChanged](super::ButtplugClientEvent::DeviceAttributesChanged)
+  /// event with an updated device.
+  pub fn request_attributes(&self) -> ButtplugClientResultFuture<DeviceMessageInfo> {
+    let send_fut = self.send_message(RequestDeviceAttributes::new(self.index).into());
+    async move {
+      match send_fut.await? {
+        ButtplugCurrentSpecServerMessage::DeviceAttributes(msg) => Ok(msg.into()),
+        ButtplugCurrentSpecServerMessage::Error(err) => Err(ButtplugError::from(err).into()),
+        msg => Err(
+          ButtplugError::from(ButtplugMessageError::UnexpectedMessageType(format!(
+            "{:?}",
+            msg
+          )))
+          .into(),
+        ),
+      }
+    }
+    .boxed()
+  }
+
   pub fn event_stream(&self) -> Box<dyn Stream<Item = ButtplugClientDeviceEvent> + Send + Unpin> {
     Box::new(Box::pin(convert_broadcast_receiver_to_stream(
       self.internal_event_sender.subscribe(),
@@ -265,6 +513,35 @@ impl ButtplugClientDevice {
     }
   }
 
+  /// Names of the physical parts the device config places this device's actuators on, in order of
+  /// first appearance. Empty if the config doesn't say where its actuators are.
+  pub fn parts(&self) -> Vec<String> {
+    let mut parts: Vec<String> = vec![];
+    let attrs = [
+      self.message_attributes.scalar_cmd(),
+      self.message_attributes.rotate_cmd(),
+      self.message_attributes.linear_cmd(),
+    ];
+    for attr in attrs.iter().copied().flatten().flatten() {
+      if let Some(placement) = attr.placement() {
+        if !parts.iter().any(|part| placement.is_part(part)) {
+          parts.push(placement.part().clone());
+        }
+      }
+    }
+    parts
+  }
+
+  /// Scalar actuators on the physical part named `part`. Their indexes can be used with
+  /// [ScalarCommand::ScalarMap] to drive only that part.
+  pub fn scalar_attributes_on_part(&self, part: &str) -> Vec<ClientGenericDeviceMessageAttributes> {
+    self
+      .scalar_attributes()
+      .into_iter()
+      .filter(|attr| matches!(attr.placement(), Some(placement) if placement.is_part(part)))
+      .collect()
+  }
+
   // The amount of hoop jumping it takes to pull this off is fucking ridiculous.
   //
   // In what will probably be the last time I use arrays with contextual indexing in Buttplug
@@ -336,7 +613,7 @@ impl ButtplugClientDevice {
     }
     let msg = ScalarCmd::new(self.index, scalar_vec).into();
     info!("{:?}", msg);
-    self.event_loop_sender.send_message_expect_ok(msg)
+    self.send_message_expect_ok(msg)
   }
 
   pub fn vibrate_attributes(&self) -> Vec<ClientGenericDeviceMessageAttributes> {
@@ -416,7 +693,7 @@ impl ButtplugClientDevice {
       }
     }
     let msg = ScalarCmd::new(self.index, scalar_vec).into();
-    self.event_loop_sender.send_message_expect_ok(msg)
+    self.send_message_expect_ok(msg)
   }
 
   pub fn linear_attributes(&self) -> Vec<ClientGenericDeviceMessageAttributes> {
@@ -474,7 +751,7 @@ impl ButtplugClientDevice {
       }
     }
     let msg = LinearCmd::new(self.index, linear_vec).into();
-    self.event_loop_sender.send_message_expect_ok(msg)
+    self.send_message_expect_ok(msg)
   }
 
   pub fn rotate_attributes(&self) -> Vec<ClientGenericDeviceMessageAttributes> {
@@ -532,7 +809,7 @@ impl ButtplugClientDevice {
       }
     }
     let msg = RotateCmd::new(self.index, rotate_vec).into();
-    self.event_loop_sender.send_message_expect_ok(msg)
+    self.send_message_expect_ok(msg)
   }
 
   pub fn subscribe_sensor(
@@ -547,7 +824,33 @@ impl ButtplugClientDevice {
       );
     }
     let msg = SensorSubscribeCmd::new(self.index, sensor_index, sensor_type).into();
-    self.event_loop_sender.send_message_expect_ok(msg)
+    self.send_message_expect_ok(msg)
+  }
+
+  /// Subscribe to a sensor, limiting how many readings the server sends. Readings less than
+  /// `min_interval` milliseconds apart are dropped, and only every `decimation`th reading is sent.
+  pub fn subscribe_sensor_with_sampling(
+    &self,
+    sensor_index: u32,
+    sensor_type: SensorType,
+    min_interval: Option<u32>,
+    decimation: Option<u32>,
+  ) -> ButtplugClientResultFuture {
+    if self.message_attributes.sensor_subscribe_cmd().is_none() {
+      return create_boxed_future_client_error(
+        ButtplugDeviceError::MessageNotSupported(ButtplugDeviceMessageType::SensorSubscribeCmd)
+          .into(),
+      );
+    }
+    let msg = SensorSubscribeCmd::new_with_sampling(
+      self.index,
+      sensor_index,
+      sensor_type,
+      min_interval,
+      decimation,
+    )
+    .into();
+    self.send_message_expect_ok(msg)
   }
 
   pub fn unsubscribe_sensor(
@@ -562,7 +865,31 @@ impl ButtplugClientDevice {
       );
     }
     let msg = SensorUnsubscribeCmd::new(self.index, sensor_index, sensor_type).into();
-    self.event_loop_sender.send_message_expect_ok(msg)
+    self.send_message_expect_ok(msg)
+  }
+
+  /// Readings from one of the device's sensors, for use with the transformations in
+  /// [sensor_stream](super::sensor_stream). Readings only arrive while subscribed to the sensor
+  /// (see [subscribe_sensor](Self::subscribe_sensor)), and the stream ends when the device is
+  /// removed or the client disconnects.
+  pub fn sensor_reading_stream(
+    &self,
+    sensor_index: u32,
+    sensor_type: SensorType,
+  ) -> impl Stream<Item = SensorReading> + Send + Unpin {
+    self
+      .event_stream()
+      .take_while(|event| future::ready(matches!(event, ButtplugClientDeviceEvent::Message(_))))
+      .filter_map(move |event| {
+        future::ready(match event {
+          ButtplugClientDeviceEvent::Message(ButtplugCurrentSpecServerMessage::SensorReading(
+            reading,
+          )) if reading.sensor_index() == sensor_index && reading.sensor_type() == sensor_type => {
+            Some(reading)
+          }
+          _ => None,
+        })
+      })
   }
 
   fn read_single_sensor(&self, sensor_type: &SensorType) -> ButtplugClientResultFuture<Vec<i32>> {
@@ -587,7 +914,7 @@ impl ButtplugClientDevice {
       );
     }
     let msg = SensorReadCmd::new(self.index, sensor_indexes[0], *sensor_type).into();
-    let reply = self.event_loop_sender.send_message(msg);
+    let reply = self.send_message(msg);
     async move {
       if let ButtplugCurrentSpecServerMessage::SensorReading(data) = reply.await? {
         Ok(data.data().clone())
@@ -636,6 +963,94 @@ impl ButtplugClientDevice {
     })
   }
 
+  /// Battery level of the device as it changes, from 0.0 (empty) to 1.0 (full). See
+  /// [SensorLevelStream].
+  pub fn subscribe_battery(&self) -> ButtplugClientResultFuture<SensorLevelStream> {
+    self.subscribe_sensor_level(SensorType::Battery)
+  }
+
+  /// Signal strength of the device's connection as it changes, from 0.0 (about to disconnect) to
+  /// 1.0 (strongest). See [SensorLevelStream].
+  pub fn subscribe_rssi(&self) -> ButtplugClientResultFuture<SensorLevelStream> {
+    self.subscribe_sensor_level(SensorType::RSSI)
+  }
+
+  fn subscribe_sensor_level(
+    &self,
+    sensor_type: SensorType,
+  ) -> ButtplugClientResultFuture<SensorLevelStream> {
+    let find_sensor = |sensors: &Option<Vec<SensorDeviceMessageAttributes>>| {
+      sensors.as_ref().and_then(|sensors| {
+        sensors
+          .iter()
+          .enumerate()
+          .find(|(_, sensor)| *sensor.sensor_type() == sensor_type)
+          .and_then(|(index, sensor)| {
+            sensor
+              .sensor_range()
+              .first()
+              .map(|range| (index as u32, range.clone()))
+          })
+      })
+    };
+    let read_sensor = find_sensor(self.message_attributes.sensor_read_cmd());
+    let subscribe_sensor = find_sensor(self.message_attributes.sensor_subscribe_cmd());
+    let range = if let Some((_, range)) = read_sensor.as_ref().or(subscribe_sensor.as_ref()) {
+      range.clone()
+    } else {
+      // Not create_boxed_future_client_error, as streams aren't Sync.
+      let err = ButtplugError::from(ButtplugDeviceError::ProtocolSensorNotSupported(sensor_type));
+      return future::ready(Err(err.into())).boxed();
+    };
+    let start = *range.start() as f64;
+    let span = range.end().saturating_sub(*range.start()).max(1) as f64;
+    let normalize = move |value: &i32| ((*value as f64 - start) / span).clamp(0.0, 1.0);
+    // Readings are only sent as events, so start listening before subscribing to make sure none
+    // are missed.
+    let levels = self
+      .event_stream()
+      .take_while(|event| future::ready(matches!(event, ButtplugClientDeviceEvent::Message(_))))
+      .filter_map(move |event| {
+        future::ready(match event {
+          ButtplugClientDeviceEvent::Message(ButtplugCurrentSpecServerMessage::SensorReading(
+            reading,
+          )) if reading.sensor_type() == sensor_type => reading.data().first().map(normalize),
+          _ => None,
+        })
+      });
+    let read_fut = read_sensor.map(|(index, _)| {
+      self.send_message(SensorReadCmd::new(self.index, index, sensor_type).into())
+    });
+    let subscribe_fut = subscribe_sensor
+      .as_ref()
+      .map(|(index, _)| self.subscribe_sensor(*index, sensor_type));
+    let unsubscribe_message = subscribe_sensor
+      .map(|(index, _)| SensorUnsubscribeCmd::new(self.index, index, sensor_type).into());
+    let mut level_stream = SensorLevelStream {
+      device_index: self.index,
+      levels: Box::pin(stream::empty()),
+      unsubscribe_message: None,
+      event_loop_sender: self.event_loop_sender.clone(),
+      device_connected: self.device_connected.clone(),
+      client_connected: self.client_connected.clone(),
+    };
+    async move {
+      let mut current = None;
+      if let Some(read_fut) = read_fut {
+        if let ButtplugCurrentSpecServerMessage::SensorReading(reading) = read_fut.await? {
+          current = reading.data().first().map(normalize);
+        }
+      }
+      if let Some(subscribe_fut) = subscribe_fut {
+        subscribe_fut.await?;
+        level_stream.unsubscribe_message = unsubscribe_message;
+      }
+      level_stream.levels = Box::pin(stream::iter(current).chain(levels));
+      Ok(level_stream)
+    }
+    .boxed()
+  }
+
   pub fn raw_write(
     &self,
     endpoint: Endpoint,
@@ -653,7 +1068,7 @@ impl ButtplugClientDevice {
       data,
       write_with_response,
     ));
-    self.event_loop_sender.send_message_expect_ok(msg)
+    self.send_message_expect_ok(msg)
   }
 
   pub fn raw_read(
@@ -673,7 +1088,7 @@ impl ButtplugClientDevice {
       expected_length,
       timeout,
     ));
-    let send_fut = self.event_loop_sender.send_message(msg);
+    let send_fut = self.send_message(msg);
     async move {
       match send_fut.await? {
         ButtplugCurrentSpecServerMessage::RawReading(reading) => Ok(reading.data().clone()),
@@ -698,7 +1113,7 @@ impl ButtplugClientDevice {
     }
     let msg =
       ButtplugCurrentSpecClientMessage::RawSubscribeCmd(RawSubscribeCmd::new(self.index, endpoint));
-    self.event_loop_sender.send_message_expect_ok(msg)
+    self.send_message_expect_ok(msg)
   }
 
   pub fn raw_unsubscribe(&self, endpoint: Endpoint) -> ButtplugClientResultFuture {
@@ -710,15 +1125,99 @@ impl ButtplugClientDevice {
     let msg = ButtplugCurrentSpecClientMessage::RawUnsubscribeCmd(RawUnsubscribeCmd::new(
       self.index, endpoint,
     ));
-    self.event_loop_sender.send_message_expect_ok(msg)
+    self.send_message_expect_ok(msg)
+  }
+
+  /// Puts the device in or out of firmware mode, holding off protocol keepalives while a firmware
+  /// update is sent to the device's Firmware endpoint with [raw_write](Self::raw_write). Needs raw
+  /// messages to be allowed for the device. See [FirmwareModeCmd].
+  pub fn firmware_mode(&self, enabled: bool) -> ButtplugClientResultFuture {
+    if self.message_attributes.raw_write_cmd().is_none() {
+      return create_boxed_future_client_error(
+        ButtplugDeviceError::MessageNotSupported(ButtplugDeviceMessageType::RawWriteCmd).into(),
+      );
+    }
+    self.send_message_expect_ok(FirmwareModeCmd::new(self.index, enabled).into())
+  }
+
+  /// Arms or disarms an e-stim device. E-stim devices refuse output until they're armed, and are
+  /// disarmed again (and stopped) when disarmed here, when the client disconnects, or when stop all
+  /// devices is sent. Only e-stim devices accept this. See [EstimArmCmd].
+  pub fn estim_arm(&self, armed: bool) -> ButtplugClientResultFuture {
+    self.send_message_expect_ok(EstimArmCmd::new(self.index, armed).into())
+  }
+
+  /// Plays keyframes on the device from the server, so they don't have to be sent one at a time.
+  /// Ends any pattern already playing. If `looped` is true, the pattern starts over once the time of
+  /// the last keyframe has passed, until the device is stopped. See [PatternCmd].
+  pub fn pattern(
+    &self,
+    keyframes: Vec<PatternKeyframe>,
+    looped: bool,
+  ) -> ButtplugClientResultFuture {
+    self.send_message_expect_ok(PatternCmd::new(self.index, keyframes, looped).into())
   }
 
   /// Commands device to stop all movement.
   pub fn stop(&self) -> ButtplugClientResultFuture {
     // All devices accept StopDeviceCmd
-    self
-      .event_loop_sender
-      .send_message_expect_ok(StopDeviceCmd::new(self.index).into())
+    self.send_message_expect_ok(StopDeviceCmd::new(self.index).into())
+  }
+
+  /// Returns a guard that stops the whole device when dropped. See [DeviceHold].
+  pub fn hold(&self) -> DeviceHold {
+    self.create_hold(vec![StopDeviceCmd::new(self.index).into()])
+  }
+
+  /// Returns a guard that stops only the given features when dropped, leaving the rest of the
+  /// device alone. Linear features have no stopped state to return to, so only
+  /// [hold](Self::hold) covers them. See [DeviceHold].
+  pub fn hold_features(&self, features: &[HeldFeature]) -> Result<DeviceHold, ButtplugDeviceError> {
+    let scalar_attrs = self.scalar_attributes();
+    let rotate_count = self
+      .message_attributes
+      .rotate_cmd()
+      .as_ref()
+      .map_or(0, |attrs| attrs.len() as u32);
+    let mut scalar_vec = vec![];
+    let mut rotate_vec = vec![];
+    for feature in features {
+      match *feature {
+        HeldFeature::Scalar(index) => {
+          let attr = scalar_attrs.get(index as usize).ok_or(
+            ButtplugDeviceError::DeviceFeatureIndexError(scalar_attrs.len() as u32, index),
+          )?;
+          scalar_vec.push(ScalarSubcommand::new(index, 0.0, *attr.actuator_type()));
+        }
+        HeldFeature::Rotate(index) => {
+          if index >= rotate_count {
+            return Err(ButtplugDeviceError::DeviceFeatureIndexError(
+              rotate_count,
+              index,
+            ));
+          }
+          rotate_vec.push(RotationSubcommand::new(index, 0.0, false));
+        }
+      }
+    }
+    let mut stop_messages = vec![];
+    if !scalar_vec.is_empty() {
+      stop_messages.push(ScalarCmd::new(self.index, scalar_vec).into());
+    }
+    if !rotate_vec.is_empty() {
+      stop_messages.push(RotateCmd::new(self.index, rotate_vec).into());
+    }
+    Ok(self.create_hold(stop_messages))
+  }
+
+  fn create_hold(&self, stop_messages: Vec<ButtplugCurrentSpecClientMessage>) -> DeviceHold {
+    DeviceHold {
+      device_index: self.index,
+      stop_messages,
+      event_loop_sender: self.event_loop_sender.clone(),
+      device_connected: self.device_connected.clone(),
+      client_connected: self.client_connected.clone(),
+    }
   }
 
   pub(super) fn set_device_connected(&self, connected: bool) {
@@ -9,18 +9,30 @@
 pub mod client_event_loop;
 pub mod client_message_sorter;
 pub mod device;
+pub mod sensor_stream;
 
 use crate::{
   core::{
     connector::{ButtplugConnector, ButtplugConnectorError, ButtplugConnectorFuture},
-    errors::{ButtplugError, ButtplugHandshakeError},
+    errors::{
+      ButtplugDeviceError,
+      ButtplugError,
+      ButtplugHandshakeError,
+      ButtplugMessageError,
+      ButtplugPingError,
+      ButtplugUnknownError,
+    },
     message::{
       ButtplugCurrentSpecClientMessage,
       ButtplugCurrentSpecServerMessage,
+      ClientCapability,
+      DeviceList,
+      DisconnectAllDevices,
       Ping,
       RequestDeviceList,
       RequestServerInfo,
       StartScanning,
+      StartTargetedScanning,
       StopAllDevices,
       StopScanning,
       BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
@@ -37,10 +49,14 @@ use dashmap::DashMap;
 pub use device::{
   ButtplugClientDevice,
   ButtplugClientDeviceEvent,
+  DeviceCommand,
+  DeviceHold,
+  HeldFeature,
   LinearCommand,
   RotateCommand,
   ScalarCommand,
   ScalarValueCommand,
+  SensorLevelStream,
 };
 use futures::{
   future::{self, BoxFuture, FutureExt},
@@ -101,19 +117,116 @@ impl ButtplugClientMessageFuturePair {
 
 /// Represents all of the different types of errors a ButtplugClient can return.
 ///
-/// Clients can return two types of errors:
+/// Errors are sorted by where they came from, so applications can match on what went wrong instead
+/// of inspecting messages. [ButtplugClientError::is_retryable] and
+/// [ButtplugClientError::is_user_actionable] give hints on what to do about them.
 ///
-/// - [ButtplugConnectorError], which means there was a problem with the connection between the
-/// client and the server, like a network connection issue.
-/// - [ButtplugError], which is an error specific to the Buttplug Protocol.
+/// Errors sent by a remote server only carry an error code and a message, so they will arrive as
+/// the `UntypedDeserializedError` variant of the matching error type.
 #[derive(Debug, Error)]
 pub enum ButtplugClientError {
-  /// Connector error
+  /// Problem with the connection between the client and the server, like a network issue.
   #[error(transparent)]
   ButtplugConnectorError(#[from] ButtplugConnectorError),
-  /// Protocol error
+  /// The handshake with the server failed, or the connection was taken over by another client.
+  #[error(transparent)]
+  ButtplugHandshakeError(ButtplugHandshakeError),
+  /// A device is gone, failed, or can't do what was asked of it.
+  #[error(transparent)]
+  ButtplugDeviceError(ButtplugDeviceError),
+  /// A message was invalid, either caught by the client before sending or rejected by the server.
   #[error(transparent)]
-  ButtplugError(#[from] ButtplugError),
+  ButtplugValidationError(ButtplugMessageError),
+  /// The server's ping timer ran out, or pinging wasn't set up correctly.
+  #[error(transparent)]
+  ButtplugPingError(ButtplugPingError),
+  /// The server ran into something it couldn't classify.
+  #[error(transparent)]
+  ButtplugUnknownError(ButtplugUnknownError),
+}
+
+impl From<ButtplugError> for ButtplugClientError {
+  fn from(error: ButtplugError) -> Self {
+    match error {
+      ButtplugError::ButtplugHandshakeError(e) => Self::ButtplugHandshakeError(e),
+      ButtplugError::ButtplugDeviceError(e) => Self::ButtplugDeviceError(e),
+      ButtplugError::ButtplugMessageError(e) => Self::ButtplugValidationError(e),
+      ButtplugError::ButtplugPingError(e) => Self::ButtplugPingError(e),
+      ButtplugError::ButtplugUnknownError(e) => Self::ButtplugUnknownError(e),
+    }
+  }
+}
+
+impl From<ButtplugDeviceError> for ButtplugClientError {
+  fn from(error: ButtplugDeviceError) -> Self {
+    Self::ButtplugDeviceError(error)
+  }
+}
+
+impl From<ButtplugMessageError> for ButtplugClientError {
+  fn from(error: ButtplugMessageError) -> Self {
+    Self::ButtplugValidationError(error)
+  }
+}
+
+impl From<ButtplugHandshakeError> for ButtplugClientError {
+  fn from(error: ButtplugHandshakeError) -> Self {
+    Self::ButtplugHandshakeError(error)
+  }
+}
+
+impl ButtplugClientError {
+  /// True if trying the same call again, without changing anything, could succeed. This covers
+  /// transient failures like a flaky network or a device that didn't answer in time.
+  pub fn is_retryable(&self) -> bool {
+    match self {
+      Self::ButtplugConnectorError(e) => matches!(
+        e,
+        ButtplugConnectorError::ConnectorGenericError(_)
+          | ButtplugConnectorError::TransportSpecificError(_)
+      ),
+      Self::ButtplugDeviceError(e) => matches!(
+        e,
+        ButtplugDeviceError::DeviceCommunicationError(_)
+          | ButtplugDeviceError::DeviceConnectionError(_)
+          | ButtplugDeviceError::DeviceCommandTimeout(_)
+          | ButtplugDeviceError::DevicesFailedToStop(_)
+      ),
+      Self::ButtplugHandshakeError(_)
+      | Self::ButtplugValidationError(_)
+      | Self::ButtplugPingError(_)
+      | Self::ButtplugUnknownError(_) => false,
+    }
+  }
+
+  /// True if the user can likely fix this themselves, like by turning on or moving a device closer,
+  /// starting the server, or updating it. False for errors that point at a bug in the application
+  /// or library instead.
+  pub fn is_user_actionable(&self) -> bool {
+    match self {
+      Self::ButtplugConnectorError(e) => {
+        !matches!(e, ButtplugConnectorError::ConnectorAlreadyConnected)
+      }
+      Self::ButtplugHandshakeError(e) => matches!(
+        e,
+        ButtplugHandshakeError::MessageSpecVersionMismatch(..)
+          | ButtplugHandshakeError::ConnectionTakenOver
+      ),
+      Self::ButtplugDeviceError(e) => matches!(
+        e,
+        ButtplugDeviceError::DeviceNotConnected(_)
+          | ButtplugDeviceError::DeviceDisconnected(_)
+          | ButtplugDeviceError::DeviceConnectionError(_)
+          | ButtplugDeviceError::DeviceCommunicationError(_)
+          | ButtplugDeviceError::DeviceCommandTimeout(_)
+          | ButtplugDeviceError::DeviceNotAvailable(_)
+          | ButtplugDeviceError::DevicePermissionError(_)
+      ),
+      Self::ButtplugPingError(e) => matches!(e, ButtplugPingError::PingedOut),
+      Self::ButtplugUnknownError(e) => matches!(e, ButtplugUnknownError::NoDeviceCommManagers),
+      Self::ButtplugValidationError(_) => false,
+    }
+  }
 }
 
 /// Enum representing different events that can be emitted by a client.
@@ -132,6 +245,11 @@ pub enum ButtplugClientEvent {
   /// Emitted when a device has been removed from the server. Includes a
   /// [ButtplugClientDevice] object representing the device.
   DeviceRemoved(Arc<ButtplugClientDevice>),
+  /// Emitted when the server changes the attributes of a connected device (i.e. its display name
+  /// or step limits). Includes a [ButtplugClientDevice] with the new attributes, which replaces the
+  /// old one in [ButtplugClient::devices]. Existing handles to the device keep working, but keep
+  /// the old attributes.
+  DeviceAttributesChanged(Arc<ButtplugClientDevice>),
   /// Emitted when a client has not pinged the server in a sufficient amount of
   /// time.
   PingTimeout,
@@ -139,6 +257,14 @@ pub enum ButtplugClientEvent {
   ServerConnect,
   /// Emitted when a client connector detects that the server has disconnected.
   ServerDisconnect,
+  /// Emitted (followed by [ButtplugClientEvent::ServerDisconnect]) when the connector's transport
+  /// stopped hearing from the server, i.e. the network connection died instead of being closed.
+  /// Unlike [ButtplugClientEvent::PingTimeout], this is about the connection, not the client
+  /// failing to ping the server.
+  ConnectionLost,
+  /// Emitted while the server works on a long-running request, with the id of the request and how
+  /// much of it is done (0.0 to 1.0). The request's own reply still comes back as usual.
+  OperationProgress(u32, f64),
   /// Emitted when an error that cannot be matched to a request is received from
   /// the server.
   Error(ButtplugError),
@@ -153,7 +279,7 @@ pub(super) fn create_boxed_future_client_error<T>(
 where
   T: 'static + Send + Sync,
 {
-  future::ready(Err(ButtplugClientError::ButtplugError(err))).boxed()
+  future::ready(Err(err.into())).boxed()
 }
 
 pub(super) struct ButtplugClientMessageSender {
@@ -232,6 +358,57 @@ impl ButtplugClientMessageSender {
     .boxed()
   }
 
+  /// Queues a ButtplugMessage for the server without waiting on its reply, for callers that can't
+  /// await, like drop handlers. Returns false if the client is disconnected or its event loop has
+  /// stopped, in which case the message was not sent.
+  pub fn send_message_without_reply(&self, msg: ButtplugCurrentSpecClientMessage) -> bool {
+    if !self.connected.load(Ordering::Relaxed) {
+      return false;
+    }
+    // Nobody waits on the reply, but the event loop still needs somewhere to put it.
+    let fut = ButtplugServerMessageFuture::default();
+    self
+      .message_sender
+      .send(ButtplugClientRequest::Message(
+        ButtplugClientMessageFuturePair::new(msg, fut.get_state_clone()),
+      ))
+      .is_ok()
+  }
+
+  /// Sends a set of ButtplugMessages from client to server as a single batch. Expects to receive an
+  /// [Ok] type ButtplugMessage back from the server for each message.
+  ///
+  /// Returns the first error received, if any.
+  pub fn send_message_batch_expect_ok(
+    &self,
+    msgs: Vec<ButtplugCurrentSpecClientMessage>,
+  ) -> ButtplugClientResultFuture {
+    if !self.connected.load(Ordering::Relaxed) {
+      return future::ready(Err(ButtplugConnectorError::ConnectorNotConnected.into())).boxed();
+    }
+    let (msg_futs, futs): (Vec<_>, Vec<_>) = msgs
+      .into_iter()
+      .map(|msg| {
+        let fut = ButtplugServerMessageFuture::default();
+        (
+          ButtplugClientMessageFuturePair::new(msg, fut.get_state_clone()),
+          fut,
+        )
+      })
+      .unzip();
+
+    // Send messages to internal loop and wait for all of the returns.
+    let send_fut = self.send_message_to_event_loop(ButtplugClientRequest::MessageBatch(msg_futs));
+    async move {
+      send_fut.await?;
+      for result in future::join_all(futs).await {
+        result?;
+      }
+      Ok(())
+    }
+    .boxed()
+  }
+
   /// Sends a ButtplugMessage from client to server. Expects to receive an [Ok]
   /// type ButtplugMessage back from the server.
   pub fn send_message_expect_ok(
@@ -263,8 +440,17 @@ pub struct ButtplugClient {
   /// The client name. Depending on the connection type and server being used,
   /// this name is sometimes shown on the server logs or GUI.
   client_name: String,
+  /// Language tag sent to the server on handshake, so it can send device names and descriptions in
+  /// that language if it has them.
+  locale: Option<String>,
+  /// Capabilities declared to the server on handshake, if any. Servers hold the client to these.
+  capabilities: Option<Vec<ClientCapability>>,
   /// The server name that we're current connected to.
   server_name: Arc<Mutex<Option<String>>>,
+  /// Version of the device configuration file the server has loaded, if it sent one.
+  server_device_config_version: Arc<Mutex<Option<String>>>,
+  /// Version of the library the server is running, if it sent one.
+  server_library_version: Arc<Mutex<Option<String>>>,
   event_stream: broadcast::Sender<ButtplugClientEvent>,
   // Sender to relay messages to the internal client loop
   message_sender: Arc<ButtplugClientMessageSender>,
@@ -279,7 +465,11 @@ impl ButtplugClient {
     let connected = Arc::new(AtomicBool::new(false));
     Self {
       client_name: name.to_owned(),
+      locale: None,
+      capabilities: None,
       server_name: Arc::new(Mutex::new(None)),
+      server_device_config_version: Arc::new(Mutex::new(None)),
+      server_library_version: Arc::new(Mutex::new(None)),
       event_stream,
       message_sender: Arc::new(ButtplugClientMessageSender::new(
         &message_sender,
@@ -290,6 +480,23 @@ impl ButtplugClient {
     }
   }
 
+  /// Create a client that asks the server for device names and descriptions in the language of
+  /// `locale`, a language tag like "de" or "pt-BR". Devices without names in that language keep
+  /// their usual names.
+  pub fn new_with_locale(name: &str, locale: &str) -> Self {
+    let mut client = Self::new(name);
+    client.locale = Some(locale.to_owned());
+    client
+  }
+
+  /// Create a client that tells the server which [ClientCapability]s it will use. The server can
+  /// refuse the connection if it won't grant them, and will reject messages outside of them.
+  pub fn new_with_capabilities(name: &str, capabilities: &[ClientCapability]) -> Self {
+    let mut client = Self::new(name);
+    client.capabilities = Some(capabilities.to_vec());
+    client
+  }
+
   pub async fn connect<ConnectorType>(
     &self,
     mut connector: ConnectorType,
@@ -342,17 +549,22 @@ impl ButtplugClient {
   async fn run_handshake(&self) -> ButtplugClientResult {
     // Run our handshake
     info!("Running handshake with server.");
+    let mut request_server_info =
+      RequestServerInfo::new(&self.client_name, BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION);
+    request_server_info.set_locale(self.locale.clone());
+    request_server_info.set_capabilities(self.capabilities.clone());
     let msg = self
       .message_sender
-      .send_message_ignore_connect_status(
-        RequestServerInfo::new(&self.client_name, BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
-      )
+      .send_message_ignore_connect_status(request_server_info.into())
       .await?;
 
     debug!("Got ServerInfo return.");
     if let ButtplugCurrentSpecServerMessage::ServerInfo(server_info) = msg {
       info!("Connected to {}", server_info.server_name());
       *self.server_name.lock().await = Some(server_info.server_name().clone());
+      *self.server_device_config_version.lock().await =
+        server_info.device_config_version().clone();
+      *self.server_library_version.lock().await = server_info.library_version().clone();
       // Don't set ourselves as connected until after ServerInfo has been
       // received. This means we avoid possible races with the RequestServerInfo
       // handshake.
@@ -361,25 +573,56 @@ impl ButtplugClient {
       // Get currently connected devices. The event loop will
       // handle sending the message and getting the return, and
       // will send the client updates as events.
-      let msg = self
+      let device_list = self
+        .request_device_list(server_info.device_list_page_size().is_some())
+        .await?;
+      self
         .message_sender
-        .send_message(RequestDeviceList::default().into())
+        .send_message_to_event_loop(ButtplugClientRequest::HandleDeviceList(device_list))
         .await?;
-      if let ButtplugCurrentSpecServerMessage::DeviceList(m) = msg {
-        self
-          .message_sender
-          .send_message_to_event_loop(ButtplugClientRequest::HandleDeviceList(m))
-          .await?;
-      }
       Ok(())
     } else {
       self.disconnect().await?;
-      Err(ButtplugClientError::ButtplugError(
-        ButtplugHandshakeError::UnexpectedHandshakeMessageReceived(format!("{:?}", msg)).into(),
+      Err(ButtplugClientError::ButtplugHandshakeError(
+        ButtplugHandshakeError::UnexpectedHandshakeMessageReceived(format!("{:?}", msg)),
       ))
     }
   }
 
+  /// Requests the device list from the server. If the server supports paging, the list is
+  /// requested a page at a time and reassembled here, so large device counts don't have to fit in
+  /// a single message.
+  async fn request_device_list(&self, paged: bool) -> Result<DeviceList, ButtplugClientError> {
+    let mut devices = vec![];
+    let mut start_device_index = 0;
+    loop {
+      let request = if paged {
+        RequestDeviceList::new_page(start_device_index)
+      } else {
+        RequestDeviceList::default()
+      };
+      let page = match self.message_sender.send_message(request.into()).await? {
+        ButtplugCurrentSpecServerMessage::DeviceList(page) => page,
+        msg => {
+          return Err(
+            ButtplugError::from(ButtplugMessageError::UnexpectedMessageType(format!(
+              "{:?}",
+              msg
+            )))
+            .into(),
+          )
+        }
+      };
+      devices.extend(page.devices().iter().cloned());
+      match page.next_device_index() {
+        // Guard against a server handing back a cursor that doesn't move forward, otherwise we'd
+        // loop forever.
+        Some(next) if paged && next > start_device_index => start_device_index = next,
+        _ => return Ok(DeviceList::new(devices)),
+      }
+    }
+  }
+
   /// Returns true if client is currently connected.
   pub fn connected(&self) -> bool {
     self.connected.load(Ordering::SeqCst)
@@ -418,6 +661,18 @@ impl ButtplugClient {
       .send_message_expect_ok(StartScanning::default().into())
   }
 
+  /// Tells server to scan for a single device that was connected before, by the index it had. This
+  /// finds the device faster, using less power, than a full scan. Scanning stops once the device is
+  /// added, otherwise it finishes like any other scan.
+  ///
+  /// Returns Err([ButtplugClientError]) if the server doesn't know of a device that had the index,
+  /// or if request fails due to issues with DeviceManagers on the server, disconnection, etc.
+  pub fn start_targeted_scanning(&self, device_index: u32) -> ButtplugClientResultFuture {
+    self
+      .message_sender
+      .send_message_expect_ok(StartTargetedScanning::new(device_index).into())
+  }
+
   /// Tells server to stop scanning for devices.
   ///
   /// Returns Err([ButtplugClientError]) if request fails due to issues with
@@ -438,6 +693,41 @@ impl ButtplugClient {
       .send_message_expect_ok(StopAllDevices::default().into())
   }
 
+  /// Tells server to stop and disconnect all devices, so other programs can connect to them. The
+  /// server keeps running, and devices come back on the next scan.
+  ///
+  /// Returns Err([ButtplugClientError]) if request fails due to issues with
+  /// DeviceManagers on the server, disconnection, etc.
+  pub fn disconnect_all_devices(&self) -> ButtplugClientResultFuture {
+    self
+      .message_sender
+      .send_message_expect_ok(DisconnectAllDevices::default().into())
+  }
+
+  /// Sends a scene, a set of commands for one or more devices, to the server as a single batch.
+  ///
+  /// The server dispatches all of the commands in the scene together, so state changes across
+  /// multiple devices happen as close to the same time as the hardware allows. If any command is
+  /// invalid or addressed to a device the client doesn't know about, nothing in the scene is sent.
+  ///
+  /// # Errors
+  ///
+  /// Returns Err([ButtplugClientError]) if any command in the scene fails, or on disconnection. The
+  /// first error received is returned.
+  pub fn send_scene(&self, commands: Vec<DeviceCommand>) -> ButtplugClientResultFuture {
+    if let Some(cmd) = commands
+      .iter()
+      .find(|cmd| !self.device_map.contains_key(&cmd.device_index()))
+    {
+      return create_boxed_future_client_error(
+        ButtplugDeviceError::DeviceNotAvailable(cmd.device_index()).into(),
+      );
+    }
+    self
+      .message_sender
+      .send_message_batch_expect_ok(commands.into_iter().map(|cmd| cmd.into()).collect())
+  }
+
   pub fn event_stream(&self) -> impl Stream<Item = ButtplugClientEvent> {
     let stream = convert_broadcast_receiver_to_stream(self.event_stream.subscribe());
     // We can either Box::pin here or force the user to pin_mut!() on their
@@ -477,4 +767,27 @@ impl ButtplugClient {
       None
     }
   }
+
+  /// Version of the device configuration file loaded by the server we're connected to.
+  ///
+  /// Useful for prompting users to update their server when their hardware requires a newer
+  /// configuration. Will be None if the server is too old to send this information. Like
+  /// [ButtplugClient::server_name], this never changes throughout the life of the connection.
+  pub fn server_device_config_version(&self) -> Option<String> {
+    if let Ok(version) = self.server_device_config_version.try_lock() {
+      version.clone()
+    } else {
+      None
+    }
+  }
+
+  /// Version of the Buttplug library the server we're connected to is running. Will be None if the
+  /// server is too old to send this information.
+  pub fn server_library_version(&self) -> Option<String> {
+    if let Ok(version) = self.server_library_version.try_lock() {
+      version.clone()
+    } else {
+      None
+    }
+  }
 }
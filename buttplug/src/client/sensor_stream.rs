@@ -0,0 +1,184 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Transformations for streams of sensor readings.
+//!
+//! Readings from [ButtplugClientDevice::sensor_reading_stream](super::ButtplugClientDevice::sensor_reading_stream)
+//! come straight from the hardware, which tends to be noisy and to send far more often than an app
+//! wants to react. These cover the usual first steps of working with them, and can be chained:
+//!
+//! ```ignore
+//! let readings = device.sensor_reading_stream(0, SensorType::Pressure);
+//! let squeezes = threshold(smooth(rate_limit(readings, Duration::from_millis(50)), 5), 0, 100, 300);
+//! ```
+
+use crate::core::message::{SensorReading, SensorType};
+use futures::{future, Stream, StreamExt};
+use std::{
+  collections::{HashMap, VecDeque},
+  time::{Duration, Instant},
+};
+
+/// Replaces each reading with the average of the last `window` readings from the same sensor,
+/// taken value by value. Readings are sent as they arrive, so the first few average over fewer
+/// readings. A window of 0 is treated as 1.
+pub fn smooth(
+  readings: impl Stream<Item = SensorReading>,
+  window: usize,
+) -> impl Stream<Item = SensorReading> {
+  let window = window.max(1);
+  let mut history: HashMap<(u32, SensorType), VecDeque<Vec<i32>>> = HashMap::new();
+  readings.map(move |mut reading| {
+    let samples = history
+      .entry((reading.sensor_index(), reading.sensor_type()))
+      .or_default();
+    if samples.len() == window {
+      samples.pop_front();
+    }
+    samples.push_back(reading.data().clone());
+    for (index, value) in reading.data_mut().iter_mut().enumerate() {
+      let values: Vec<i64> = samples
+        .iter()
+        .filter_map(|sample| sample.get(index).map(|value| *value as i64))
+        .collect();
+      *value = (values.iter().sum::<i64>() as f64 / values.len() as f64).round() as i32;
+    }
+    reading
+  })
+}
+
+/// Side of a threshold a sensor value has crossed to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SensorThresholdEvent {
+  /// Value rose to or past the high threshold. Carries the reading that crossed it.
+  Above(SensorReading),
+  /// Value fell to or past the low threshold. Carries the reading that crossed it.
+  Below(SensorReading),
+}
+
+/// Turns readings into events for when the value at `value_index` crosses a threshold.
+///
+/// A value has to reach `high` to send [SensorThresholdEvent::Above], then has to drop back to
+/// `low` to send [SensorThresholdEvent::Below], so values hovering around a single threshold don't
+/// send a flood of events. The first reading at or past either threshold sends an event, and
+/// readings without a value at `value_index` are ignored. `low` should be below `high`.
+pub fn threshold(
+  readings: impl Stream<Item = SensorReading>,
+  value_index: usize,
+  low: i32,
+  high: i32,
+) -> impl Stream<Item = SensorThresholdEvent> {
+  let mut above = None;
+  readings.filter_map(move |reading| {
+    let event = match (reading.data().get(value_index), above) {
+      (Some(value), None | Some(false)) if *value >= high => {
+        above = Some(true);
+        Some(SensorThresholdEvent::Above(reading))
+      }
+      (Some(value), None | Some(true)) if *value <= low => {
+        above = Some(false);
+        Some(SensorThresholdEvent::Below(reading))
+      }
+      _ => None,
+    };
+    future::ready(event)
+  })
+}
+
+/// Drops readings that arrive less than `min_interval` after the last reading let through, so apps
+/// that only redraw or react every so often don't have to keep up with the sensor.
+///
+/// This is done on the client, so the readings still cross the connection. If the server supports
+/// it, [ButtplugClientDevice::subscribe_sensor_with_sampling](super::ButtplugClientDevice::subscribe_sensor_with_sampling)
+/// keeps them from being sent at all.
+pub fn rate_limit(
+  readings: impl Stream<Item = SensorReading>,
+  min_interval: Duration,
+) -> impl Stream<Item = SensorReading> {
+  let mut last_sent: HashMap<(u32, SensorType), Instant> = HashMap::new();
+  readings.filter(move |reading| {
+    let now = Instant::now();
+    let key = (reading.sensor_index(), reading.sensor_type());
+    let send =
+      !matches!(last_sent.get(&key), Some(last) if now.duration_since(*last) < min_interval);
+    if send {
+      last_sent.insert(key, now);
+    }
+    future::ready(send)
+  })
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use futures::stream;
+
+  fn pressure(values: &[i32]) -> Vec<SensorReading> {
+    values
+      .iter()
+      .map(|value| SensorReading::new(0, 0, SensorType::Pressure, vec![*value]))
+      .collect()
+  }
+
+  fn data(readings: Vec<SensorReading>) -> Vec<i32> {
+    readings.iter().map(|reading| reading.data()[0]).collect()
+  }
+
+  #[tokio::test]
+  async fn test_smooth() {
+    let smoothed: Vec<SensorReading> = smooth(stream::iter(pressure(&[10, 20, 30, 40, 0])), 3)
+      .collect()
+      .await;
+    assert_eq!(data(smoothed), vec![10, 15, 20, 30, 23]);
+
+    // Different sensors are averaged separately.
+    let readings = vec![
+      SensorReading::new(0, 0, SensorType::Pressure, vec![10]),
+      SensorReading::new(0, 1, SensorType::Pressure, vec![100]),
+      SensorReading::new(0, 0, SensorType::Pressure, vec![20]),
+    ];
+    let smoothed: Vec<SensorReading> = smooth(stream::iter(readings), 3).collect().await;
+    assert_eq!(data(smoothed), vec![10, 100, 15]);
+  }
+
+  #[tokio::test]
+  async fn test_threshold() {
+    let events: Vec<SensorThresholdEvent> = threshold(
+      stream::iter(pressure(&[50, 120, 90, 130, 150, 60, 40, 55, 200])),
+      0,
+      50,
+      100,
+    )
+    .collect()
+    .await;
+    let crossings: Vec<(bool, i32)> = events
+      .iter()
+      .map(|event| match event {
+        SensorThresholdEvent::Above(reading) => (true, reading.data()[0]),
+        SensorThresholdEvent::Below(reading) => (false, reading.data()[0]),
+      })
+      .collect();
+    assert_eq!(
+      crossings,
+      vec![(false, 50), (true, 120), (false, 40), (true, 200)]
+    );
+  }
+
+  #[tokio::test]
+  async fn test_rate_limit() {
+    let limited: Vec<SensorReading> =
+      rate_limit(stream::iter(pressure(&[1, 2, 3])), Duration::from_secs(60))
+        .collect()
+        .await;
+    assert_eq!(data(limited), vec![1]);
+    let limited: Vec<SensorReading> =
+      rate_limit(stream::iter(pressure(&[1, 2, 3])), Duration::ZERO)
+        .collect()
+        .await;
+    assert_eq!(data(limited), vec![1, 2, 3]);
+  }
+}
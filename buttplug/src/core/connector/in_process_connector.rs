@@ -133,7 +133,7 @@ impl ButtplugConnector<ButtplugCurrentSpecClientMessage, ButtplugCurrentSpecServ
             // in-process conversion, we can unwrap because we know our
             // try_into() will always succeed (which may not be the case with
             // remote connections that have different spec versions).
-            if send.send(event.try_into().expect("This is in-process so we're always on the latest message spec, this will always work.")).await.is_err() {
+            if send.send(Arc::unwrap_or_clone(event).try_into().expect("This is in-process so we're always on the latest message spec, this will always work.")).await.is_err() {
               break;
             }
           }
@@ -178,4 +178,30 @@ impl ButtplugConnector<ButtplugCurrentSpecClientMessage, ButtplugCurrentSpecServ
     }
     .boxed()
   }
+
+  fn send_batch(
+    &self,
+    msgs: Vec<ButtplugCurrentSpecClientMessage>,
+  ) -> ButtplugConnectorResultFuture {
+    if !self.connected.load(Ordering::SeqCst) {
+      return ButtplugConnectorError::ConnectorNotConnected.into();
+    }
+    let inputs = msgs.into_iter().map(|msg| msg.into()).collect();
+    let output_fut = self.server.parse_message_batch(inputs);
+    let sender = self.server_outbound_sender.clone();
+    async move {
+      for output in output_fut.await {
+        let output: ButtplugCurrentSpecServerMessage = output
+          .unwrap_or_else(|e| e.into())
+          .try_into()
+          .expect("This is in-process so message conversions will always work.");
+        sender
+          .send(output)
+          .await
+          .map_err(|_| ButtplugConnectorError::ConnectorNotConnected)?;
+      }
+      Ok(())
+    }
+    .boxed()
+  }
 }
@@ -65,12 +65,16 @@
 
 #[cfg(all(feature = "server", feature = "client", not(feature = "wasm")))]
 mod in_process_connector;
+mod quota;
 pub mod remote_connector;
 pub mod transport;
 
 use crate::{
   core::message::{serializer::ButtplugSerializedMessage, ButtplugMessage},
-  util::future::{ButtplugFuture, ButtplugFutureStateShared},
+  util::{
+    async_manager,
+    future::{ButtplugFuture, ButtplugFutureStateShared},
+  },
 };
 use displaydoc::Display;
 use futures::future::{self, BoxFuture, FutureExt};
@@ -79,18 +83,30 @@ pub use in_process_connector::{
   ButtplugInProcessClientConnector,
   ButtplugInProcessClientConnectorBuilder,
 };
-pub use remote_connector::{
-  ButtplugRemoteClientConnector,
-  ButtplugRemoteConnector,
-  ButtplugRemoteServerConnector,
-};
+pub use quota::ButtplugConnectionQuota;
+#[cfg(feature = "client")]
+pub use remote_connector::ButtplugRemoteClientConnector;
+pub use remote_connector::{ButtplugRemoteConnector, ButtplugRemoteServerConnector};
 use thiserror::Error;
-use tokio::sync::mpsc::Sender;
-#[cfg(feature = "websockets")]
-pub use transport::ButtplugWebsocketClientTransport;
+use tokio::sync::mpsc::{channel, Sender};
+#[cfg(all(feature = "grpc", feature = "client"))]
+pub use transport::ButtplugGrpcClientTransport;
+#[cfg(feature = "grpc")]
+pub use transport::{ButtplugGrpcServerTransport, ButtplugGrpcServerTransportBuilder};
+#[cfg(all(feature = "mdns", feature = "client"))]
+pub use transport::{discover_buttplug_servers, ButtplugDiscoveredServer};
+#[cfg(feature = "wasm-websockets")]
+pub use transport::ButtplugWasmWebsocketClientTransport;
+#[cfg(all(feature = "websockets", feature = "client"))]
+pub use transport::{ButtplugWebsocketClientTransport, ButtplugWebsocketClientTransportBuilder};
 
 #[cfg(feature = "websockets")]
-pub use transport::{ButtplugWebsocketServerTransport, ButtplugWebsocketServerTransportBuilder};
+pub use transport::{
+  ButtplugWebsocketHeartbeat,
+  ButtplugWebsocketServerListener,
+  ButtplugWebsocketServerTransport,
+  ButtplugWebsocketServerTransportBuilder,
+};
 
 pub type ButtplugConnectorResult = Result<(), ButtplugConnectorError>;
 pub type ButtplugConnectorStateShared =
@@ -164,6 +180,35 @@ where
     &mut self,
     message_receiver: Sender<InboundMessageType>,
   ) -> BoxFuture<'static, Result<(), ButtplugConnectorError>>;
+  /// Connects like [ButtplugConnector::connect], but hands over incoming messages in the sets they
+  /// arrived in, so a batch sent with [ButtplugConnector::send_batch] on the other side can be
+  /// handled as one.
+  ///
+  /// The default implementation hands over every message on its own. Connectors that receive
+  /// whole sets at once (for instance, as one serialized message array) should override this.
+  ///
+  /// # Errors
+  ///
+  /// Same as [ButtplugConnector::connect].
+  fn connect_batched(
+    &mut self,
+    batch_receiver: Sender<Vec<InboundMessageType>>,
+  ) -> BoxFuture<'static, Result<(), ButtplugConnectorError>> {
+    let (message_sender, mut message_receiver) = channel(256);
+    let connect_fut = self.connect(message_sender);
+    async move {
+      connect_fut.await?;
+      async_manager::spawn(async move {
+        while let Some(msg) = message_receiver.recv().await {
+          if batch_receiver.send(vec![msg]).await.is_err() {
+            break;
+          }
+        }
+      });
+      Ok(())
+    }
+    .boxed()
+  }
   /// Disconnects the client from the server.
   ///
   /// Returns a [ButtplugConnectorError] if there is a problem with the
@@ -181,15 +226,69 @@ where
   /// If the connector is not currently connected, or an error happens during
   /// the send operation, this will return a [ButtplugConnectorError]
   fn send(&self, msg: OutboundMessageType) -> ButtplugConnectorResultFuture;
+  /// Sends a set of messages of outbound message type `O` to the other connector, as a single
+  /// unit where the connector allows it.
+  ///
+  /// The default implementation just sends the messages one after another. Connectors that can
+  /// move the whole set at once (for instance, as one serialized message array) should override
+  /// this.
+  ///
+  /// # Errors
+  ///
+  /// If the connector is not currently connected, or an error happens during
+  /// the send operation, this will return a [ButtplugConnectorError]
+  fn send_batch(&self, msgs: Vec<OutboundMessageType>) -> ButtplugConnectorResultFuture {
+    let send_futs: Vec<ButtplugConnectorResultFuture> =
+      msgs.into_iter().map(|msg| self.send(msg)).collect();
+    async move {
+      for send_fut in send_futs {
+        send_fut.await?;
+      }
+      Ok(())
+    }
+    .boxed()
+  }
+  /// Returns true if the last connection ended because the transport stopped hearing from the
+  /// remote, rather than either side closing it. Lets owners tell a dead network connection apart
+  /// from a normal disconnect.
+  ///
+  /// Connectors without a transport level keepalive can't tell, and always return false.
+  fn connection_lost(&self) -> bool {
+    false
+  }
+  /// Resolves when a new client is waiting to take over the connection from the current one,
+  /// which the owner finishes by calling [ButtplugConnector::complete_takeover].
+  ///
+  /// Connectors that only ever talk to one remote never resolve this.
+  fn takeover_requested(&self) -> BoxFuture<'static, ()> {
+    future::pending().boxed()
+  }
+  /// Drops the current remote in favor of the one that caused
+  /// [ButtplugConnector::takeover_requested] to resolve. Messages sent before this is called still
+  /// go to the current remote.
+  ///
+  /// # Errors
+  ///
+  /// Returns a [ButtplugConnectorError] if the connector is not connected or doesn't support
+  /// takeover.
+  fn complete_takeover(&self) -> ButtplugConnectorResultFuture {
+    ButtplugConnectorError::ConnectorGenericError(
+      "Connector does not support client takeover".to_owned(),
+    )
+    .into()
+  }
 }
 
-#[cfg(all(feature = "websockets", feature = "serialize-json"))]
+#[cfg(any(
+  all(feature = "websockets", feature = "serialize-json", feature = "client"),
+  feature = "wasm-websockets"
+))]
 use crate::core::message::{ButtplugCurrentSpecClientMessage, ButtplugCurrentSpecServerMessage};
 
 /// Convenience method for creating a new Buttplug Client Websocket connector that uses the JSON
 /// serializer. This is pretty much the only connector used for IPC right now, so this makes it easy
 /// to create one without having to fill in the generic types.
-#[cfg(all(feature = "websockets", feature = "serialize-json"))]
+#[cfg(all(feature = "websockets", feature = "serialize-json", feature = "client"))]
 pub fn new_json_ws_client_connector(
   address: &str,
 ) -> impl ButtplugConnector<ButtplugCurrentSpecClientMessage, ButtplugCurrentSpecServerMessage> {
@@ -202,3 +301,40 @@ pub fn new_json_ws_client_connector(
     address,
   ))
 }
+
+/// Convenience method for creating a new Buttplug Client Websocket connector for use in browsers,
+/// using the JSON serializer. Works the same as [new_json_ws_client_connector], but goes through the
+/// browser's WebSocket API.
+#[cfg(feature = "wasm-websockets")]
+pub fn new_json_wasm_ws_client_connector(
+  address: &str,
+) -> impl ButtplugConnector<ButtplugCurrentSpecClientMessage, ButtplugCurrentSpecServerMessage> {
+  use crate::core::message::serializer::ButtplugClientJSONSerializer;
+
+  ButtplugRemoteClientConnector::<
+      ButtplugWasmWebsocketClientTransport,
+      ButtplugClientJSONSerializer,
+    >::new(ButtplugWasmWebsocketClientTransport::new(address))
+}
+
+/// Convenience method for creating a new Buttplug Server Websocket connector that uses the JSON
+/// serializer, listening on the given port. Pairs with
+/// [ButtplugRemoteServer](crate::server::ButtplugRemoteServer) to serve clients connecting via
+/// [new_json_ws_client_connector].
+#[cfg(all(feature = "websockets", feature = "serialize-json", feature = "server"))]
+pub fn new_json_ws_server_connector(
+  port: u16,
+  listen_on_all_interfaces: bool,
+) -> impl ButtplugConnector<
+  crate::core::message::ButtplugServerMessage,
+  crate::core::message::ButtplugClientMessage,
+> {
+  use crate::core::message::serializer::ButtplugServerJSONSerializer;
+
+  ButtplugRemoteServerConnector::<ButtplugWebsocketServerTransport, ButtplugServerJSONSerializer>::new(
+    ButtplugWebsocketServerTransportBuilder::default()
+      .port(port)
+      .listen_on_all_interfaces(listen_on_all_interfaces)
+      .finish(),
+  )
+}
@@ -0,0 +1,231 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Per-connection limits for remote connectors that face untrusted clients.
+
+use crate::core::{
+  errors::{ButtplugError, ButtplugMessageError},
+  message::{self, serializer::ButtplugSerializedMessage, ButtplugMessage},
+};
+use std::{collections::HashSet, time::Instant};
+
+/// Limits on what a single remote connection can send.
+///
+/// Quotas are checked by the remote connector before incoming frames are deserialized, so a client
+/// flooding the connection (or sending huge payloads) is turned away before it can cost the server
+/// much work. Each violation is answered with an Error message, and once a connection has used up
+/// its [max_violations](ButtplugConnectionQuota::max_violations) the next one drops it.
+///
+/// All limits are off by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ButtplugConnectionQuota {
+  /// Most incoming frames accepted per second, with bursts of up to one second's worth allowed.
+  /// Counts transport frames, so a message array counts once.
+  pub max_messages_per_second: Option<u32>,
+  /// Largest incoming frame accepted, in bytes.
+  pub max_payload_size: Option<usize>,
+  /// Most commands that can be waiting on a reply from the server at once.
+  pub max_pending_commands: Option<usize>,
+  /// How many violations a connection gets away with before it is disconnected.
+  pub max_violations: u32,
+}
+
+impl Default for ButtplugConnectionQuota {
+  fn default() -> Self {
+    Self {
+      max_messages_per_second: None,
+      max_payload_size: None,
+      max_pending_commands: None,
+      max_violations: 5,
+    }
+  }
+}
+
+/// What the connector should do about a message that went over quota.
+pub(super) struct QuotaViolation<OutboundMessageType> {
+  /// Error to send back to the remote.
+  pub reply: OutboundMessageType,
+  /// True if the connection has run out of violations and should be dropped.
+  pub disconnect: bool,
+}
+
+/// Tracks a single connection's usage against its [ButtplugConnectionQuota].
+pub(super) struct ConnectionQuotaTracker<OutboundMessageType>
+where
+  OutboundMessageType: ButtplugMessage + 'static,
+{
+  quota: ButtplugConnectionQuota,
+  /// Builds the outbound message used to tell the remote about a violation.
+  error_reply: fn(message::Error) -> OutboundMessageType,
+  tokens: f64,
+  last_refill: Instant,
+  pending_commands: HashSet<u32>,
+  violations: u32,
+}
+
+impl<OutboundMessageType> ConnectionQuotaTracker<OutboundMessageType>
+where
+  OutboundMessageType: ButtplugMessage + 'static,
+{
+  pub fn new(
+    quota: ButtplugConnectionQuota,
+    error_reply: fn(message::Error) -> OutboundMessageType,
+  ) -> Self {
+    Self {
+      quota,
+      error_reply,
+      tokens: quota.max_messages_per_second.unwrap_or(0) as f64,
+      last_refill: Instant::now(),
+      pending_commands: HashSet::new(),
+      violations: 0,
+    }
+  }
+
+  /// Checks the rate and size limits for an incoming frame. Cheap enough to run before the frame is
+  /// deserialized.
+  pub fn check_frame(
+    &mut self,
+    msg: &ButtplugSerializedMessage,
+  ) -> Result<(), QuotaViolation<OutboundMessageType>> {
+    self.check_frame_at(msg, Instant::now())
+  }
+
+  /// [check_frame](Self::check_frame) as of `now`, so the rate limit can be tested without waiting
+  /// on the clock.
+  fn check_frame_at(
+    &mut self,
+    msg: &ButtplugSerializedMessage,
+    now: Instant,
+  ) -> Result<(), QuotaViolation<OutboundMessageType>> {
+    if let Some(max_payload_size) = self.quota.max_payload_size {
+      let size = match msg {
+        ButtplugSerializedMessage::Text(text) => text.len(),
+        ButtplugSerializedMessage::Binary(data) => data.len(),
+      };
+      if size > max_payload_size {
+        return Err(self.violation(
+          0,
+          format!(
+            "Message of {} bytes is over the {} byte limit",
+            size, max_payload_size
+          ),
+        ));
+      }
+    }
+    if let Some(max_messages_per_second) = self.quota.max_messages_per_second {
+      let rate = max_messages_per_second as f64;
+      let elapsed = now.duration_since(self.last_refill);
+      self.last_refill = now;
+      self.tokens = (self.tokens + elapsed.as_secs_f64() * rate).min(rate);
+      if self.tokens < 1.0 {
+        return Err(self.violation(
+          0,
+          format!(
+            "More than {} messages per second received",
+            max_messages_per_second
+          ),
+        ));
+      }
+      self.tokens -= 1.0;
+    }
+    Ok(())
+  }
+
+  /// Registers a deserialized command as waiting on a reply, unless that would put the connection
+  /// over its pending command limit.
+  pub fn start_command(&mut self, id: u32) -> Result<(), QuotaViolation<OutboundMessageType>> {
+    // Id 0 is reserved for server events, nothing will ever reply to it.
+    if id == 0 {
+      return Ok(());
+    }
+    if let Some(max_pending_commands) = self.quota.max_pending_commands {
+      if self.pending_commands.len() >= max_pending_commands
+        && !self.pending_commands.contains(&id)
+      {
+        return Err(self.violation(
+          id,
+          format!(
+            "More than {} commands waiting on replies",
+            max_pending_commands
+          ),
+        ));
+      }
+      self.pending_commands.insert(id);
+    }
+    Ok(())
+  }
+
+  /// Clears the command an outgoing reply answers.
+  pub fn finish_command(&mut self, id: u32) {
+    self.pending_commands.remove(&id);
+  }
+
+  /// Starts over for a new remote taking over the connection.
+  pub fn reset(&mut self) {
+    *self = Self::new(self.quota, self.error_reply);
+  }
+
+  fn violation(&mut self, id: u32, reason: String) -> QuotaViolation<OutboundMessageType> {
+    self.violations += 1;
+    warn!(
+      "Remote connection went over quota ({} violations): {}",
+      self.violations, reason
+    );
+    let mut err =
+      message::Error::from(ButtplugError::from(ButtplugMessageError::QuotaExceeded(reason)));
+    err.set_id(id);
+    QuotaViolation {
+      reply: (self.error_reply)(err),
+      disconnect: self.violations > self.quota.max_violations,
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::core::message::ButtplugServerMessage;
+  use std::time::Duration;
+
+  fn tracker(quota: ButtplugConnectionQuota) -> ConnectionQuotaTracker<ButtplugServerMessage> {
+    ConnectionQuotaTracker::new(quota, |err| err.into())
+  }
+
+  #[test]
+  fn test_quota_message_rate() {
+    let mut tracker = tracker(ButtplugConnectionQuota {
+      max_messages_per_second: Some(10),
+      ..Default::default()
+    });
+    let msg = ButtplugSerializedMessage::Text("[]".to_owned());
+    let start = tracker.last_refill;
+    for _ in 0..10 {
+      assert!(tracker.check_frame_at(&msg, start).is_ok());
+    }
+    assert!(tracker.check_frame_at(&msg, start).is_err());
+    // Refills at 10 per second, so this earns back one and a half messages.
+    let later = start + Duration::from_millis(150);
+    assert!(tracker.check_frame_at(&msg, later).is_ok());
+    assert!(tracker.check_frame_at(&msg, later).is_err());
+  }
+
+  #[test]
+  fn test_quota_pending_commands_and_violations() {
+    let mut tracker = tracker(ButtplugConnectionQuota {
+      max_pending_commands: Some(1),
+      max_violations: 1,
+      ..Default::default()
+    });
+    assert!(tracker.start_command(1).is_ok());
+    let violation = tracker.start_command(2).expect_err("Over pending limit");
+    assert_eq!(violation.reply.id(), 2);
+    assert!(!violation.disconnect);
+    tracker.finish_command(1);
+    assert!(tracker.start_command(2).is_ok());
+    assert!(tracker.start_command(3).expect_err("Over pending limit").disconnect);
+  }
+}
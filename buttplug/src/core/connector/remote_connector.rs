@@ -8,35 +8,51 @@
 //! Generic remote transport handling methods and traits
 
 use super::{
+  quota::{ButtplugConnectionQuota, ConnectionQuotaTracker, QuotaViolation},
   transport::{ButtplugConnectorTransport, ButtplugTransportIncomingMessage},
   ButtplugConnector,
   ButtplugConnectorError,
   ButtplugConnectorResultFuture,
 };
 use crate::{
-  core::message::{
-    serializer::{
-      ButtplugClientJSONSerializer,
-      ButtplugMessageSerializer,
-      ButtplugSerializedMessage,
+  core::{
+    errors::{ButtplugError, ButtplugMessageError},
+    message::{
+      self,
+      serializer::{ButtplugMessageSerializer, ButtplugSerializedMessage},
+      ButtplugClientMessage,
+      ButtplugMessage,
+      ButtplugServerMessage,
     },
-    ButtplugClientMessage,
-    ButtplugCurrentSpecClientMessage,
-    ButtplugCurrentSpecServerMessage,
-    ButtplugMessage,
-    ButtplugServerMessage,
   },
   util::async_manager,
 };
+#[cfg(feature = "client")]
+use crate::core::message::{
+  serializer::ButtplugClientJSONSerializer,
+  ButtplugCurrentSpecClientMessage,
+  ButtplugCurrentSpecServerMessage,
+};
 use futures::{future::BoxFuture, select, FutureExt};
-use std::marker::PhantomData;
-use tokio::sync::mpsc::{channel, Receiver, Sender};
+use std::{
+  marker::PhantomData,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
+};
+use tokio::sync::{
+  mpsc::{channel, Receiver, Sender},
+  Notify,
+};
 
 enum ButtplugRemoteConnectorMessage<T>
 where
   T: ButtplugMessage + 'static,
 {
   Message(T),
+  Batch(Vec<T>),
+  CompleteTakeover,
   Close,
 }
 
@@ -49,6 +65,45 @@ where
   Outgoing(ButtplugRemoteConnectorMessage<T>),
 }
 
+/// Tells the remote about a quota violation. Returns true if the connection should be dropped,
+/// either because the remote is out of violations or because the transport is gone.
+async fn send_quota_violation<SerializerType, OutboundMessageType>(
+  serializer: &SerializerType,
+  transport_outgoing_sender: &Sender<ButtplugSerializedMessage>,
+  violation: QuotaViolation<OutboundMessageType>,
+) -> bool
+where
+  SerializerType: ButtplugMessageSerializer<Outbound = OutboundMessageType>,
+{
+  let serialized_msg = serializer.serialize(&[violation.reply]);
+  if transport_outgoing_sender.send(serialized_msg).await.is_err() {
+    error!("Transport has disconnected, exiting remote connector loop.");
+    return true;
+  }
+  if violation.disconnect {
+    warn!("Remote connection is out of quota violations, disconnecting.");
+  }
+  violation.disconnect
+}
+
+/// Builds the reply telling the remote an incoming message was rejected before it reached the other
+/// side of the connector. None if the serializer has no way to reply.
+fn rejection<SerializerType, OutboundMessageType>(
+  serializer: &SerializerType,
+  id: u32,
+  err: ButtplugMessageError,
+) -> Option<ButtplugSerializedMessage>
+where
+  SerializerType: ButtplugMessageSerializer<Outbound = OutboundMessageType>,
+{
+  let mut err_msg = message::Error::from(ButtplugError::from(err));
+  err_msg.set_id(id);
+  serializer
+    .error_reply(err_msg)
+    .map(|reply| serializer.serialize(&[reply]))
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn remote_connector_event_loop<
   TransportType,
   SerializerType,
@@ -57,13 +112,19 @@ async fn remote_connector_event_loop<
 >(
   // Takes messages from the client
   mut connector_outgoing_recv: Receiver<ButtplugRemoteConnectorMessage<OutboundMessageType>>,
-  // Sends messages not matched in the sorter to the client.
-  connector_incoming_sender: Sender<InboundMessageType>,
+  // Sends messages not matched in the sorter to the client, one set per incoming message array.
+  connector_incoming_sender: Sender<Vec<InboundMessageType>>,
   transport: TransportType,
   // Sends sorter processed messages to the transport.
   transport_outgoing_sender: Sender<ButtplugSerializedMessage>,
   // Takes data coming in from the transport.
   mut transport_incoming_recv: Receiver<ButtplugTransportIncomingMessage>,
+  // Set if the transport tells us it lost the connection.
+  connection_lost: Arc<AtomicBool>,
+  // Notified if the transport has a new client waiting to take over.
+  takeover_notifier: Arc<Notify>,
+  // Limits on what the remote can send, if any.
+  mut quota_tracker: Option<ConnectionQuotaTracker<OutboundMessageType>>,
 ) where
   TransportType: ButtplugConnectorTransport + 'static,
   SerializerType: ButtplugMessageSerializer<Inbound = InboundMessageType, Outbound = OutboundMessageType>
@@ -72,7 +133,7 @@ async fn remote_connector_event_loop<
   InboundMessageType: ButtplugMessage + 'static,
 {
   // Message sorter that receives messages that come in from the client.
-  let serializer = SerializerType::default();
+  let mut serializer = SerializerType::default();
   loop {
     // We use two Options instead of an enum because we may never get anything.
     //
@@ -103,18 +164,74 @@ async fn remote_connector_event_loop<
       StreamValue::Incoming(remote_msg) => {
         match remote_msg {
           ButtplugTransportIncomingMessage::Message(serialized_msg) => {
+            // Quota checks have to happen before deserialization, which is where the expensive work
+            // is.
+            if let Some(tracker) = quota_tracker.as_mut() {
+              if let Err(violation) = tracker.check_frame(&serialized_msg) {
+                if send_quota_violation(&serializer, &transport_outgoing_sender, violation).await {
+                  if let Err(e) = transport.disconnect().await {
+                    error!("Error disconnecting transport: {:?}", e);
+                  }
+                  break;
+                }
+                continue;
+              }
+            }
             match serializer.deserialize(&serialized_msg) {
+              Ok(array) if array.is_empty() => {
+                warn!("Got empty message array from remote Buttplug connection.");
+                let err =
+                  ButtplugMessageError::InvalidMessageContents("Message array is empty".to_owned());
+                if let Some(reply) = rejection(&serializer, 0, err) {
+                  if transport_outgoing_sender.send(reply).await.is_err() {
+                    error!("Transport has disconnected, exiting remote connector loop.");
+                    return;
+                  }
+                }
+              }
               Ok(array) => {
+                // Everything from one array is handed over together, so batches stay batches.
+                let mut batch = vec![];
                 for smsg in array {
-                  // TODO Test validity here.
-                  if connector_incoming_sender.send(smsg).await.is_err() {
-                    error!("Connector has disconnected, ending remote connector loop.");
-                    return;
+                  // Messages that are obviously wrong are answered here, so they never make it into
+                  // the other side's dispatch. If the serializer can't build a reply, the message
+                  // is passed on for the other side to deal with, as the remote would otherwise be
+                  // left waiting on a reply.
+                  if let Err(e) = smsg.is_valid() {
+                    warn!(
+                      "Got invalid message {} from remote Buttplug connection: {}",
+                      smsg.id(),
+                      e
+                    );
+                    if let Some(reply) = rejection(&serializer, smsg.id(), e) {
+                      if transport_outgoing_sender.send(reply).await.is_err() {
+                        error!("Transport has disconnected, exiting remote connector loop.");
+                        return;
+                      }
+                      continue;
+                    }
+                  }
+                  if let Some(tracker) = quota_tracker.as_mut() {
+                    if let Err(violation) = tracker.start_command(smsg.id()) {
+                      if send_quota_violation(&serializer, &transport_outgoing_sender, violation)
+                        .await
+                      {
+                        if let Err(e) = transport.disconnect().await {
+                          error!("Error disconnecting transport: {:?}", e);
+                        }
+                        return;
+                      }
+                      continue;
+                    }
                   }
+                  batch.push(smsg);
+                }
+                if !batch.is_empty() && connector_incoming_sender.send(batch).await.is_err() {
+                  error!("Connector has disconnected, ending remote connector loop.");
+                  return;
                 }
               }
               Err(e) => {
-                // TODO Not sure where to relay this.
                 error!(
                   "{}",
                   format!(
@@ -123,6 +240,14 @@ async fn remote_connector_event_loop<
                     e
                   )
                 );
+                // We can't trust anything in the frame, including message ids, so the error goes
+                // out as a system message.
+                if let Some(reply) = rejection(&serializer, 0, ButtplugMessageError::from(e)) {
+                  if transport_outgoing_sender.send(reply).await.is_err() {
+                    error!("Transport has disconnected, exiting remote connector loop.");
+                    return;
+                  }
+                }
               }
             }
           }
@@ -130,6 +255,15 @@ async fn remote_connector_event_loop<
             info!("Connector closing connection {}", s);
             break;
           }
+          ButtplugTransportIncomingMessage::ConnectionLost(s) => {
+            warn!("Connector lost connection: {}", s);
+            connection_lost.store(true, Ordering::SeqCst);
+            break;
+          }
+          ButtplugTransportIncomingMessage::TakeoverRequested => {
+            info!("Connector has a new client waiting to take over the connection.");
+            takeover_notifier.notify_one();
+          }
           // TODO We should probably make connecting an event?
           ButtplugTransportIncomingMessage::Connected => {}
           // TODO We should probably figure out what this even does?
@@ -141,6 +275,9 @@ async fn remote_connector_event_loop<
       StreamValue::Outgoing(ref mut buttplug_msg) => {
         match buttplug_msg {
           ButtplugRemoteConnectorMessage::Message(msg) => {
+            if let Some(tracker) = quota_tracker.as_mut() {
+              tracker.finish_command(msg.id());
+            }
             // Create future sets our message ID, so make sure this
             // happens before we send out the message.
             let serialized_msg = serializer.serialize(&[msg.clone()]);
@@ -153,6 +290,33 @@ async fn remote_connector_event_loop<
               return;
             }
           }
+          ButtplugRemoteConnectorMessage::Batch(msgs) => {
+            if let Some(tracker) = quota_tracker.as_mut() {
+              msgs.iter().for_each(|msg| tracker.finish_command(msg.id()));
+            }
+            // Batches go out as a single message array, so the other side receives them all at
+            // once.
+            let serialized_msg = serializer.serialize(msgs);
+            if transport_outgoing_sender
+              .send(serialized_msg)
+              .await
+              .is_err()
+            {
+              error!("Transport has disconnected, exiting remote connector loop.");
+              return;
+            }
+          }
+          ButtplugRemoteConnectorMessage::CompleteTakeover => {
+            // Serializers keep state from the handshake (like the message spec version), which
+            // the new client will redo.
+            serializer = SerializerType::default();
+            if let Some(tracker) = quota_tracker.as_mut() {
+              tracker.reset();
+            }
+            if let Err(e) = transport.complete_takeover().await {
+              error!("Error completing client takeover: {:?}", e);
+            }
+          }
           ButtplugRemoteConnectorMessage::Close => {
             if let Err(e) = transport.disconnect().await {
               error!("Error disconnecting transport: {:?}", e);
@@ -165,6 +329,7 @@ async fn remote_connector_event_loop<
   }
 }
 
+#[cfg(feature = "client")]
 pub type ButtplugRemoteClientConnector<
   TransportType,
   SerializerType = ButtplugClientJSONSerializer,
@@ -206,6 +371,12 @@ pub struct ButtplugRemoteConnector<
   transport: Option<TransportType>,
   /// Sender for forwarding outgoing messages to the connector event loop.
   event_loop_sender: Option<Sender<ButtplugRemoteConnectorMessage<OutboundMessageType>>>,
+  /// Set by the event loop if the transport lost the connection, as opposed to it being closed.
+  connection_lost: Arc<AtomicBool>,
+  /// Notified by the event loop when a new client is waiting to take over the connection.
+  takeover_notifier: Arc<Notify>,
+  /// Limits on what the remote can send. Taken by the event loop on connect, like the transport.
+  quota_tracker: Option<ConnectionQuotaTracker<OutboundMessageType>>,
   dummy_serializer: PhantomData<SerializerType>,
 }
 
@@ -222,11 +393,30 @@ where
     Self {
       transport: Some(transport),
       event_loop_sender: None,
+      connection_lost: Arc::new(AtomicBool::new(false)),
+      takeover_notifier: Arc::new(Notify::new()),
+      quota_tracker: None,
       dummy_serializer: PhantomData::default(),
     }
   }
 }
 
+impl<TransportType, SerializerType>
+  ButtplugRemoteServerConnector<TransportType, SerializerType>
+where
+  TransportType: ButtplugConnectorTransport + 'static,
+  SerializerType: ButtplugMessageSerializer<Inbound = ButtplugClientMessage, Outbound = ButtplugServerMessage>
+    + 'static,
+{
+  /// Creates a server connector that holds the remote to `quota`, for servers that may be reached
+  /// by clients that can't be trusted to behave.
+  pub fn new_with_quota(transport: TransportType, quota: ButtplugConnectionQuota) -> Self {
+    let mut connector = Self::new(transport);
+    connector.quota_tracker = Some(ConnectionQuotaTracker::new(quota, ButtplugServerMessage::from));
+    connector
+  }
+}
+
 impl<TransportType, SerializerType, OutboundMessageType, InboundMessageType>
   ButtplugConnector<OutboundMessageType, InboundMessageType>
   for ButtplugRemoteConnector<
@@ -245,6 +435,28 @@ where
   fn connect(
     &mut self,
     connector_incoming_sender: Sender<InboundMessageType>,
+  ) -> BoxFuture<'static, Result<(), ButtplugConnectorError>> {
+    let (batch_sender, mut batch_receiver) = channel(256);
+    let connect_fut = self.connect_batched(batch_sender);
+    async move {
+      connect_fut.await?;
+      async_manager::spawn(async move {
+        while let Some(batch) = batch_receiver.recv().await {
+          for msg in batch {
+            if connector_incoming_sender.send(msg).await.is_err() {
+              return;
+            }
+          }
+        }
+      });
+      Ok(())
+    }
+    .boxed()
+  }
+
+  fn connect_batched(
+    &mut self,
+    connector_incoming_sender: Sender<Vec<InboundMessageType>>,
   ) -> BoxFuture<'static, Result<(), ButtplugConnectorError>> {
     if self.transport.is_some() {
       let transport = self
@@ -253,6 +465,10 @@ where
         .expect("Already checked that this would be a valid take().");
       let (connector_outgoing_sender, connector_outgoing_receiver) = channel(256);
       self.event_loop_sender = Some(connector_outgoing_sender);
+      let connection_lost = self.connection_lost.clone();
+      connection_lost.store(false, Ordering::SeqCst);
+      let takeover_notifier = self.takeover_notifier.clone();
+      let quota_tracker = self.quota_tracker.take();
       async move {
         let (transport_outgoing_sender, transport_outgoing_receiver) = channel(256);
         let (transport_incoming_sender, transport_incoming_receiver) = channel(256);
@@ -275,6 +491,9 @@ where
                 transport,
                 transport_outgoing_sender,
                 transport_incoming_receiver,
+                connection_lost,
+                takeover_notifier,
+                quota_tracker,
               )
               .await
             });
@@ -318,4 +537,43 @@ where
       ButtplugConnectorError::ConnectorNotConnected.into()
     }
   }
+
+  fn send_batch(&self, msgs: Vec<OutboundMessageType>) -> ButtplugConnectorResultFuture {
+    if let Some(ref sender) = self.event_loop_sender {
+      let sender_clone = sender.clone();
+      async move {
+        sender_clone
+          .send(ButtplugRemoteConnectorMessage::Batch(msgs))
+          .await
+          .map_err(|_| ButtplugConnectorError::ConnectorNotConnected)
+      }
+      .boxed()
+    } else {
+      ButtplugConnectorError::ConnectorNotConnected.into()
+    }
+  }
+
+  fn connection_lost(&self) -> bool {
+    self.connection_lost.load(Ordering::SeqCst)
+  }
+
+  fn takeover_requested(&self) -> BoxFuture<'static, ()> {
+    let takeover_notifier = self.takeover_notifier.clone();
+    async move { takeover_notifier.notified().await }.boxed()
+  }
+
+  fn complete_takeover(&self) -> ButtplugConnectorResultFuture {
+    if let Some(ref sender) = self.event_loop_sender {
+      let sender_clone = sender.clone();
+      async move {
+        sender_clone
+          .send(ButtplugRemoteConnectorMessage::CompleteTakeover)
+          .await
+          .map_err(|_| ButtplugConnectorError::ConnectorNotConnected)
+      }
+      .boxed()
+    } else {
+      ButtplugConnectorError::ConnectorNotConnected.into()
+    }
+  }
 }
@@ -0,0 +1,16 @@
+///  One serialized message (or array of messages) sent by either side.
+#[derive(Clone, Eq, PartialEq, ::prost::Message)]
+pub struct ButtplugFrame {
+    #[prost(oneof="buttplug_frame::Payload", tags="1, 2")]
+    pub payload: ::core::option::Option<buttplug_frame::Payload>,
+}
+/// Nested message and enum types in `ButtplugFrame`.
+pub mod buttplug_frame {
+    #[derive(Clone, Eq, PartialEq, ::prost::Oneof)]
+    pub enum Payload {
+        #[prost(string, tag="1")]
+        Text(::prost::alloc::string::String),
+        #[prost(bytes, tag="2")]
+        Binary(::prost::alloc::vec::Vec<u8>),
+    }
+}
@@ -0,0 +1,123 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::{
+  grpc_error,
+  run_session_loop,
+  SessionSide,
+  GRPC_CONTENT_TYPE,
+  GRPC_SESSION_PATH,
+};
+use crate::{
+  core::{
+    connector::{
+      transport::{ButtplugConnectorTransport, ButtplugTransportIncomingMessage},
+      ButtplugConnectorError,
+      ButtplugConnectorResultFuture,
+    },
+    message::serializer::ButtplugSerializedMessage,
+  },
+  util::async_manager,
+};
+use futures::{future::BoxFuture, FutureExt};
+use std::sync::Arc;
+use tokio::{
+  net::TcpStream,
+  sync::{
+    mpsc::{Receiver, Sender},
+    Notify,
+  },
+};
+
+/// gRPC client transport, opening a session call over plaintext HTTP/2.
+pub struct ButtplugGrpcClientTransport {
+  /// Address of the server we'll connect to, as "host:port".
+  address: String,
+  /// Internally held sender, used for when disconnect is called.
+  disconnect_notifier: Arc<Notify>,
+}
+
+impl ButtplugGrpcClientTransport {
+  /// Creates a transport that connects to the server at `address`, i.e. "127.0.0.1:12346".
+  pub fn new(address: &str) -> Self {
+    Self {
+      address: address.to_owned(),
+      disconnect_notifier: Arc::new(Notify::new()),
+    }
+  }
+}
+
+impl ButtplugConnectorTransport for ButtplugGrpcClientTransport {
+  fn connect(
+    &self,
+    outgoing_receiver: Receiver<ButtplugSerializedMessage>,
+    incoming_sender: Sender<ButtplugTransportIncomingMessage>,
+  ) -> BoxFuture<'static, Result<(), ButtplugConnectorError>> {
+    let disconnect_notifier = self.disconnect_notifier.clone();
+    let address = self.address.clone();
+    async move {
+      let stream = TcpStream::connect(&address)
+        .await
+        .map_err(|err| grpc_error(format!("{:?}", err)))?;
+      let (send_request, connection) = h2::client::handshake(stream)
+        .await
+        .map_err(|err| grpc_error(format!("{:?}", err)))?;
+      async_manager::spawn(async move {
+        if let Err(err) = connection.await {
+          warn!("gRPC connection error: {:?}", err);
+        }
+      });
+      let mut send_request = send_request
+        .ready()
+        .await
+        .map_err(|err| grpc_error(format!("{:?}", err)))?;
+      let request = http::Request::builder()
+        .method("POST")
+        .uri(format!("http://{}{}", address, GRPC_SESSION_PATH))
+        .header("content-type", GRPC_CONTENT_TYPE)
+        .header("te", "trailers")
+        .body(())
+        .map_err(|err| grpc_error(format!("{:?}", err)))?;
+      let (response, send_stream) = send_request
+        .send_request(request, false)
+        .map_err(|err| grpc_error(format!("{:?}", err)))?;
+      let response = response
+        .await
+        .map_err(|err| grpc_error(format!("{:?}", err)))?;
+      // Calls the server turns away come back with their status in the headers and nothing else.
+      if let Some(status) = response.headers().get("grpc-status") {
+        if status != "0" {
+          return Err(grpc_error(format!(
+            "gRPC server refused session: {:?} {:?}",
+            status,
+            response.headers().get("grpc-message")
+          )));
+        }
+      }
+      info!("gRPC: Session opened with {}", address);
+      async_manager::spawn(run_session_loop(
+        send_stream,
+        response.into_body(),
+        outgoing_receiver,
+        incoming_sender,
+        disconnect_notifier,
+        SessionSide::Client,
+      ));
+      Ok(())
+    }
+    .boxed()
+  }
+
+  fn disconnect(self) -> ButtplugConnectorResultFuture {
+    let disconnect_notifier = self.disconnect_notifier;
+    async move {
+      disconnect_notifier.notify_waiters();
+      Ok(())
+    }
+    .boxed()
+  }
+}
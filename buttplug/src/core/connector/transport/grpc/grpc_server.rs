@@ -0,0 +1,189 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::{
+  grpc_error,
+  run_session_loop,
+  SessionSide,
+  GRPC_CONTENT_TYPE,
+  GRPC_SESSION_PATH,
+};
+use crate::{
+  core::{
+    connector::{
+      transport::{ButtplugConnectorTransport, ButtplugTransportIncomingMessage},
+      ButtplugConnectorError,
+      ButtplugConnectorResultFuture,
+    },
+    message::serializer::ButtplugSerializedMessage,
+  },
+  util::async_manager,
+};
+use bytes::Bytes;
+use futures::{future::BoxFuture, FutureExt};
+use h2::server::{Connection, SendResponse};
+use std::sync::Arc;
+use tokio::{
+  net::{TcpListener, TcpStream},
+  sync::{
+    mpsc::{Receiver, Sender},
+    Notify,
+  },
+};
+
+#[derive(Clone, Debug)]
+pub struct ButtplugGrpcServerTransportBuilder {
+  /// If true, listens all on available interfaces. Otherwise, only listens on 127.0.0.1.
+  listen_on_all_interfaces: bool,
+  /// Port for listening for plaintext HTTP/2 connections.
+  port: u16,
+}
+
+impl Default for ButtplugGrpcServerTransportBuilder {
+  fn default() -> Self {
+    Self {
+      listen_on_all_interfaces: false,
+      port: 12346,
+    }
+  }
+}
+
+impl ButtplugGrpcServerTransportBuilder {
+  pub fn listen_on_all_interfaces(&mut self, listen_on_all_interfaces: bool) -> &mut Self {
+    self.listen_on_all_interfaces = listen_on_all_interfaces;
+    self
+  }
+
+  pub fn port(&mut self, port: u16) -> &mut Self {
+    self.port = port;
+    self
+  }
+
+  pub fn finish(&self) -> ButtplugGrpcServerTransport {
+    ButtplugGrpcServerTransport {
+      port: self.port,
+      listen_on_all_interfaces: self.listen_on_all_interfaces,
+      disconnect_notifier: Arc::new(Notify::new()),
+    }
+  }
+}
+
+/// Answers a call that isn't the session call, or a session call while one is already open, with
+/// a gRPC error status.
+fn reject_call(mut respond: SendResponse<Bytes>, status: &'static str, message: &'static str) {
+  let response = http::Response::builder()
+    .status(200)
+    .header("content-type", GRPC_CONTENT_TYPE)
+    .header("grpc-status", status)
+    .header("grpc-message", message)
+    .body(())
+    .expect("Response is built from static, valid parts.");
+  if let Err(err) = respond.send_response(response, true) {
+    warn!("Cannot reject gRPC call: {:?}", err);
+  }
+}
+
+/// Keeps the HTTP/2 connection running once the session call is open, turning away any other
+/// calls made on it. Exits when the connection closes.
+async fn serve_connection(mut connection: Connection<TcpStream, Bytes>) {
+  while let Some(request) = connection.accept().await {
+    match request {
+      Ok((_, respond)) => {
+        // UNAVAILABLE
+        reject_call(respond, "14", "Only one Buttplug session is served at a time")
+      }
+      Err(err) => {
+        warn!("gRPC connection error: {:?}", err);
+        return;
+      }
+    }
+  }
+}
+
+/// gRPC server transport, serving one session call at a time over plaintext HTTP/2.
+pub struct ButtplugGrpcServerTransport {
+  port: u16,
+  listen_on_all_interfaces: bool,
+  disconnect_notifier: Arc<Notify>,
+}
+
+impl ButtplugConnectorTransport for ButtplugGrpcServerTransport {
+  fn connect(
+    &self,
+    outgoing_receiver: Receiver<ButtplugSerializedMessage>,
+    incoming_sender: Sender<ButtplugTransportIncomingMessage>,
+  ) -> BoxFuture<'static, Result<(), ButtplugConnectorError>> {
+    let disconnect_notifier = self.disconnect_notifier.clone();
+    let base_addr = if self.listen_on_all_interfaces {
+      "0.0.0.0"
+    } else {
+      "127.0.0.1"
+    };
+    let addr = format!("{}:{}", base_addr, self.port);
+    async move {
+      debug!("gRPC: Trying to listen on {}", addr);
+      let listener = TcpListener::bind(&addr)
+        .await
+        .map_err(|err| grpc_error(format!("{:?}", err)))?;
+      debug!("gRPC: Listening on: {}", addr);
+      let (stream, _) = listener
+        .accept()
+        .await
+        .map_err(|err| grpc_error(format!("{:?}", err)))?;
+      info!("gRPC: Got connection");
+      let mut connection = h2::server::handshake(stream)
+        .await
+        .map_err(|err| grpc_error(format!("{:?}", err)))?;
+      // Wait for the session call, turning away anything else the client tries first.
+      loop {
+        let (request, mut respond) = match connection.accept().await {
+          Some(request) => request.map_err(|err| grpc_error(format!("{:?}", err)))?,
+          None => {
+            return Err(grpc_error(
+              "gRPC client closed connection before opening a session".to_owned(),
+            ))
+          }
+        };
+        if request.uri().path() != GRPC_SESSION_PATH {
+          warn!("gRPC: Client called unknown method {}", request.uri().path());
+          // UNIMPLEMENTED
+          reject_call(respond, "12", "Unknown method");
+          continue;
+        }
+        let response = http::Response::builder()
+          .status(200)
+          .header("content-type", GRPC_CONTENT_TYPE)
+          .body(())
+          .expect("Response is built from static, valid parts.");
+        let send_stream = respond
+          .send_response(response, false)
+          .map_err(|err| grpc_error(format!("{:?}", err)))?;
+        let recv_stream = request.into_body();
+        async_manager::spawn(serve_connection(connection));
+        async_manager::spawn(run_session_loop(
+          send_stream,
+          recv_stream,
+          outgoing_receiver,
+          incoming_sender,
+          disconnect_notifier,
+          SessionSide::Server,
+        ));
+        return Ok(());
+      }
+    }
+    .boxed()
+  }
+
+  fn disconnect(self) -> ButtplugConnectorResultFuture {
+    let disconnect_notifier = self.disconnect_notifier;
+    async move {
+      disconnect_notifier.notify_waiters();
+      Ok(())
+    }
+    .boxed()
+  }
+}
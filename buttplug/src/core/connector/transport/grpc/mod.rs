@@ -0,0 +1,260 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! gRPC connector for client/server communication
+//!
+//! Speaks the `buttplug.Buttplug/Session` bidirectional streaming call described in
+//! `buttplug.proto`, next to this file, so applications in languages with good gRPC tooling can
+//! generate a client instead of writing a websocket one. Each frame carries a message serialized by
+//! the connector's serializer, so the handshake and message flow are the same as any other
+//! transport. Only plaintext HTTP/2 is supported.
+
+#[cfg(feature = "client")]
+pub mod grpc_client;
+pub mod grpc_server;
+
+#[cfg(feature = "client")]
+pub use grpc_client::ButtplugGrpcClientTransport;
+pub use grpc_server::{ButtplugGrpcServerTransport, ButtplugGrpcServerTransportBuilder};
+
+use crate::core::{
+  connector::{transport::ButtplugTransportIncomingMessage, ButtplugConnectorError},
+  message::serializer::ButtplugSerializedMessage,
+};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::FutureExt;
+use h2::{RecvStream, SendStream};
+use prost::Message;
+use std::sync::Arc;
+use tokio::sync::{
+  mpsc::{Receiver, Sender},
+  Notify,
+};
+
+#[allow(clippy::all)]
+mod proto {
+  include!("./buttplug.rs");
+}
+
+use proto::{buttplug_frame::Payload, ButtplugFrame};
+
+/// Path of the session call, as `/<package>.<service>/<method>`.
+const GRPC_SESSION_PATH: &str = "/buttplug.Buttplug/Session";
+const GRPC_CONTENT_TYPE: &str = "application/grpc";
+/// gRPC messages start with a compression flag byte, then the message length as a big endian u32.
+const GRPC_MESSAGE_HEADER_LENGTH: usize = 5;
+
+fn grpc_error(message: String) -> ButtplugConnectorError {
+  ButtplugConnectorError::TransportSpecificError(
+    super::ButtplugConnectorTransportSpecificError::GenericNetworkError(message),
+  )
+}
+
+/// Wraps a serialized message in a gRPC length prefixed frame.
+fn encode_message(msg: ButtplugSerializedMessage) -> Bytes {
+  let payload = match msg {
+    ButtplugSerializedMessage::Text(text) => Payload::Text(text),
+    ButtplugSerializedMessage::Binary(binary) => Payload::Binary(binary),
+  };
+  let frame = ButtplugFrame {
+    payload: Some(payload),
+  };
+  let mut buffer = BytesMut::with_capacity(GRPC_MESSAGE_HEADER_LENGTH + frame.encoded_len());
+  buffer.put_u8(0);
+  buffer.put_u32(frame.encoded_len() as u32);
+  frame
+    .encode(&mut buffer)
+    .expect("Buffer is reserved to the encoded length, encoding can't run out of space.");
+  buffer.freeze()
+}
+
+/// Collects data from an HTTP/2 stream, which can split or join gRPC messages however it likes,
+/// and hands back whole messages.
+#[derive(Default)]
+struct MessageDecoder {
+  buffer: BytesMut,
+}
+
+impl MessageDecoder {
+  fn push(&mut self, data: &[u8]) {
+    self.buffer.extend_from_slice(data);
+  }
+
+  /// Returns the next whole message, if one has arrived.
+  fn next_message(&mut self) -> Result<Option<ButtplugSerializedMessage>, String> {
+    if self.buffer.len() < GRPC_MESSAGE_HEADER_LENGTH {
+      return Ok(None);
+    }
+    if self.buffer[0] != 0 {
+      return Err("Compressed gRPC messages are not supported".to_owned());
+    }
+    let length = u32::from_be_bytes([
+      self.buffer[1],
+      self.buffer[2],
+      self.buffer[3],
+      self.buffer[4],
+    ]) as usize;
+    if self.buffer.len() < GRPC_MESSAGE_HEADER_LENGTH + length {
+      return Ok(None);
+    }
+    self.buffer.advance(GRPC_MESSAGE_HEADER_LENGTH);
+    let frame = ButtplugFrame::decode(self.buffer.split_to(length).freeze())
+      .map_err(|err| format!("Cannot decode gRPC frame: {}", err))?;
+    match frame.payload {
+      Some(Payload::Text(text)) => Ok(Some(ButtplugSerializedMessage::Text(text))),
+      Some(Payload::Binary(binary)) => Ok(Some(ButtplugSerializedMessage::Binary(binary))),
+      None => Err("gRPC frame has no payload".to_owned()),
+    }
+  }
+}
+
+/// Which end of the call a session loop is running on, which decides how it ends the call.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SessionSide {
+  Client,
+  Server,
+}
+
+/// Ends our half of the call. Servers finish with an OK status in the trailers, clients by ending
+/// their request stream.
+fn finish_session(send_stream: &mut SendStream<Bytes>, side: SessionSide) {
+  let result = match side {
+    SessionSide::Server => {
+      let mut trailers = http::HeaderMap::new();
+      trailers.insert("grpc-status", http::HeaderValue::from_static("0"));
+      send_stream.send_trailers(trailers)
+    }
+    SessionSide::Client => send_stream.send_data(Bytes::new(), true),
+  };
+  if let Err(err) = result {
+    warn!("Cannot end gRPC call, assuming connection already closed: {:?}", err);
+  }
+}
+
+/// Passes messages between the connector and an open session call until either side closes it.
+async fn run_session_loop(
+  mut send_stream: SendStream<Bytes>,
+  mut recv_stream: RecvStream,
+  mut outgoing_receiver: Receiver<ButtplugSerializedMessage>,
+  incoming_sender: Sender<ButtplugTransportIncomingMessage>,
+  disconnect_notifier: Arc<Notify>,
+  side: SessionSide,
+) {
+  info!("Starting gRPC connection event loop.");
+  let mut decoder = MessageDecoder::default();
+  loop {
+    select! {
+      _ = disconnect_notifier.notified().fuse() => {
+        info!("gRPC connector requested disconnect.");
+        finish_session(&mut send_stream, side);
+        return;
+      },
+      serialized_msg = outgoing_receiver.recv().fuse() => match serialized_msg {
+        Some(serialized_msg) => {
+          if send_stream.send_data(encode_message(serialized_msg), false).is_err() {
+            warn!("Cannot send message over gRPC, considering connection closed.");
+            let _ = incoming_sender
+              .send(ButtplugTransportIncomingMessage::Close("gRPC call closed".to_owned()))
+              .await;
+            return;
+          }
+        }
+        None => {
+          info!("gRPC connector owner dropped, ending call.");
+          finish_session(&mut send_stream, side);
+          return;
+        }
+      },
+      data = recv_stream.data().fuse() => match data {
+        Some(Ok(data)) => {
+          // Let the remote keep sending, we're about to be done with this data.
+          let _ = recv_stream.flow_control().release_capacity(data.len());
+          decoder.push(&data);
+          loop {
+            match decoder.next_message() {
+              Ok(Some(msg)) => {
+                if incoming_sender
+                  .send(ButtplugTransportIncomingMessage::Message(msg))
+                  .await
+                  .is_err()
+                {
+                  warn!("Connector that owns transport no longer available, exiting.");
+                  return;
+                }
+              }
+              Ok(None) => break,
+              Err(err) => {
+                error!("{}", err);
+                let _ = incoming_sender
+                  .send(ButtplugTransportIncomingMessage::Error(err))
+                  .await;
+                finish_session(&mut send_stream, side);
+                return;
+              }
+            }
+          }
+        }
+        Some(Err(err)) => {
+          warn!("Error from gRPC call, assuming disconnection: {:?}", err);
+          let _ = incoming_sender
+            .send(ButtplugTransportIncomingMessage::Close("gRPC call closed".to_owned()))
+            .await;
+          return;
+        }
+        None => {
+          info!("Remote ended gRPC call.");
+          let _ = incoming_sender
+            .send(ButtplugTransportIncomingMessage::Close("gRPC call closed".to_owned()))
+            .await;
+          finish_session(&mut send_stream, side);
+          return;
+        }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_message_decoder_split_and_joined_frames() {
+    let first = encode_message(ButtplugSerializedMessage::Text(
+      "[{\"Ok\":{\"Id\":1}}]".to_owned(),
+    ));
+    let second = encode_message(ButtplugSerializedMessage::Binary(vec![1, 2, 3]));
+    let mut decoder = MessageDecoder::default();
+    // Half of the first message doesn't make a message yet.
+    decoder.push(&first[..first.len() / 2]);
+    assert_eq!(decoder.next_message(), Ok(None));
+    // The rest of the first, with the second in the same chunk.
+    let mut rest = first[first.len() / 2..].to_vec();
+    rest.extend_from_slice(&second);
+    decoder.push(&rest);
+    assert_eq!(
+      decoder.next_message(),
+      Ok(Some(ButtplugSerializedMessage::Text(
+        "[{\"Ok\":{\"Id\":1}}]".to_owned()
+      )))
+    );
+    assert_eq!(
+      decoder.next_message(),
+      Ok(Some(ButtplugSerializedMessage::Binary(vec![1, 2, 3])))
+    );
+    assert_eq!(decoder.next_message(), Ok(None));
+  }
+
+  #[test]
+  fn test_message_decoder_rejects_compressed_frames() {
+    let mut frame = encode_message(ButtplugSerializedMessage::Text("[]".to_owned())).to_vec();
+    frame[0] = 1;
+    let mut decoder = MessageDecoder::default();
+    decoder.push(&frame);
+    assert!(decoder.next_message().is_err());
+  }
+}
@@ -0,0 +1,184 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::{
+  local_address,
+  multicast_socket,
+  Packet,
+  Question,
+  Record,
+  RecordData,
+  MDNS_ADDRESS,
+  MDNS_PORT,
+  SERVICE_TYPE,
+};
+use crate::{
+  core::message::BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
+  util::{async_manager, sleep},
+};
+use futures::{future, FutureExt};
+use std::{io, net::SocketAddr, time::Duration};
+use tokio::net::UdpSocket;
+use tokio_util::sync::CancellationToken;
+
+/// How long others can cache our records for.
+const RECORD_TTL: u32 = 120;
+/// Longest TTL allowed in answers to queriers that aren't full mDNS responders.
+const LEGACY_UNICAST_TTL: u32 = 10;
+/// Announcements are repeated once, in case the first is lost.
+const ANNOUNCEMENT_COUNT: u8 = 2;
+const ANNOUNCEMENT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Advertises a server on the local network for as long as it's held.
+pub(in crate::core::connector::transport) struct MdnsAdvertisement {
+  cancellation_token: CancellationToken,
+}
+
+impl MdnsAdvertisement {
+  /// Starts advertising a server called `name`, listening on `port` of this machine.
+  pub fn start(name: &str, port: u16) -> io::Result<Self> {
+    let socket = multicast_socket()?;
+    let address = local_address()?;
+    // Dots would split the name into several labels, which other browsers would read as part of
+    // the service type.
+    let instance = format!("{}.{}", name.replace('.', " "), SERVICE_TYPE);
+    let host = format!("buttplug-{}.local", address.to_string().replace('.', "-"));
+    let records = vec![
+      Record {
+        name: SERVICE_TYPE.to_owned(),
+        cache_flush: false,
+        ttl: RECORD_TTL,
+        data: RecordData::Ptr(instance.clone()),
+      },
+      Record {
+        name: instance.clone(),
+        cache_flush: true,
+        ttl: RECORD_TTL,
+        data: RecordData::Srv {
+          port,
+          target: host.clone(),
+        },
+      },
+      Record {
+        name: instance.clone(),
+        cache_flush: true,
+        ttl: RECORD_TTL,
+        data: RecordData::Txt(vec![format!(
+          "version={}",
+          BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION as u32
+        )]),
+      },
+      Record {
+        name: host,
+        cache_flush: true,
+        ttl: RECORD_TTL,
+        data: RecordData::A(address),
+      },
+    ];
+    info!("mDNS: Advertising {} at {}:{}", instance, address, port);
+    let cancellation_token = CancellationToken::new();
+    async_manager::spawn(run_advertisement(
+      socket,
+      records,
+      cancellation_token.child_token(),
+    ));
+    Ok(Self { cancellation_token })
+  }
+}
+
+impl Drop for MdnsAdvertisement {
+  fn drop(&mut self) {
+    self.cancellation_token.cancel();
+  }
+}
+
+fn response(id: u16, questions: Vec<Question>, records: &[Record], ttl: u32) -> Vec<u8> {
+  Packet {
+    id,
+    response: true,
+    questions,
+    records: records
+      .iter()
+      .map(|record| Record {
+        ttl: record.ttl.min(ttl),
+        ..record.clone()
+      })
+      .collect(),
+  }
+  .encode()
+}
+
+async fn next_announcement(announcements_left: u8) {
+  if announcements_left == ANNOUNCEMENT_COUNT {
+    return;
+  }
+  if announcements_left == 0 {
+    future::pending::<()>().await;
+  }
+  sleep(ANNOUNCEMENT_INTERVAL).await;
+}
+
+async fn run_advertisement(socket: UdpSocket, records: Vec<Record>, token: CancellationToken) {
+  let group = SocketAddr::from((MDNS_ADDRESS, MDNS_PORT));
+  let mut announcements_left = ANNOUNCEMENT_COUNT;
+  let mut buffer = vec![0; 9000];
+  loop {
+    select! {
+      _ = token.cancelled().fuse() => {
+        // Tell everyone who cached our records that they're gone.
+        if let Err(err) = socket.send_to(&response(0, vec![], &records, 0), group).await {
+          warn!("mDNS: Cannot send goodbye: {:?}", err);
+        }
+        return;
+      },
+      _ = next_announcement(announcements_left).fuse() => {
+        announcements_left -= 1;
+        if let Err(err) = socket.send_to(&response(0, vec![], &records, RECORD_TTL), group).await {
+          warn!("mDNS: Cannot send announcement: {:?}", err);
+        }
+      },
+      received = socket.recv_from(&mut buffer).fuse() => {
+        let (length, source) = match received {
+          Ok(received) => received,
+          Err(err) => {
+            error!("mDNS: Cannot receive, stopping advertisement: {:?}", err);
+            return;
+          }
+        };
+        let query = match Packet::parse(&buffer[..length]) {
+          Some(query) if !query.response => query,
+          _ => continue,
+        };
+        // There are only a handful of records, so anyone asking about one gets all of them.
+        if !query
+          .questions
+          .iter()
+          .any(|question| records.iter().any(|record| question.matches(record)))
+        {
+          continue;
+        }
+        let result = if source.port() != MDNS_PORT {
+          // Queriers that aren't on the mDNS port are simple resolvers, and only listen for
+          // answers sent straight back to them.
+          socket
+            .send_to(
+              &response(query.id, query.questions, &records, LEGACY_UNICAST_TTL),
+              source,
+            )
+            .await
+        } else {
+          socket
+            .send_to(&response(0, vec![], &records, RECORD_TTL), group)
+            .await
+        };
+        if let Err(err) = result {
+          warn!("mDNS: Cannot answer query: {:?}", err);
+        }
+      }
+    }
+  }
+}
@@ -0,0 +1,225 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::{
+  Packet,
+  Question,
+  Record,
+  RecordData,
+  MDNS_ADDRESS,
+  MDNS_PORT,
+  SERVICE_TYPE,
+  TYPE_PTR,
+};
+use crate::{
+  core::connector::{transport::ButtplugConnectorTransportSpecificError, ButtplugConnectorError},
+  util::sleep,
+};
+use futures::FutureExt;
+use getset::{CopyGetters, Getters};
+use std::{
+  collections::HashMap,
+  net::{IpAddr, Ipv4Addr, SocketAddr},
+  time::Duration,
+};
+use tokio::net::UdpSocket;
+
+/// Buttplug server found on the local network by [discover_buttplug_servers].
+#[derive(Debug, Clone, PartialEq, Eq, Getters, CopyGetters)]
+pub struct ButtplugDiscoveredServer {
+  /// Name the server advertised itself under.
+  #[getset(get = "pub")]
+  name: String,
+  /// Address the server's websocket can be reached at.
+  #[getset(get_copy = "pub")]
+  address: SocketAddr,
+  /// Latest message spec version the server speaks, if it said.
+  #[getset(get_copy = "pub")]
+  version: Option<u32>,
+}
+
+fn mdns_error(err: std::io::Error) -> ButtplugConnectorError {
+  ButtplugConnectorError::TransportSpecificError(
+    ButtplugConnectorTransportSpecificError::GenericNetworkError(format!("{:?}", err)),
+  )
+}
+
+/// Everything heard about one advertised instance.
+#[derive(Default)]
+struct Instance {
+  port: Option<u16>,
+  host: Option<String>,
+  version: Option<u32>,
+  /// Where the records came from, used if the host's address never shows up.
+  source: Option<IpAddr>,
+}
+
+/// Works out the servers advertised in the records that were heard.
+fn servers_from_records(records: &[(Record, IpAddr)]) -> Vec<ButtplugDiscoveredServer> {
+  let mut instances: HashMap<String, Instance> = HashMap::new();
+  let mut hosts: HashMap<String, Ipv4Addr> = HashMap::new();
+  let suffix = format!(".{}", SERVICE_TYPE);
+  let instance_name = |name: &str| {
+    if name.len() > suffix.len() && name.to_ascii_lowercase().ends_with(&suffix) {
+      Some(name[..name.len() - suffix.len()].to_owned())
+    } else {
+      None
+    }
+  };
+  for (record, source) in records {
+    match &record.data {
+      RecordData::Ptr(target) if record.name.eq_ignore_ascii_case(SERVICE_TYPE) => {
+        if let Some(name) = instance_name(target) {
+          instances.entry(name).or_default().source = Some(*source);
+        }
+      }
+      RecordData::Srv { port, target } => {
+        if let Some(name) = instance_name(&record.name) {
+          let instance = instances.entry(name).or_default();
+          instance.port = Some(*port);
+          instance.host = Some(target.to_ascii_lowercase());
+          instance.source = Some(*source);
+        }
+      }
+      RecordData::Txt(entries) => {
+        if let Some(name) = instance_name(&record.name) {
+          instances.entry(name).or_default().version = entries
+            .iter()
+            .find_map(|entry| entry.strip_prefix("version="))
+            .and_then(|version| version.parse().ok());
+        }
+      }
+      RecordData::A(address) => {
+        hosts.insert(record.name.to_ascii_lowercase(), *address);
+      }
+      _ => {}
+    }
+  }
+  let mut servers: Vec<ButtplugDiscoveredServer> = instances
+    .into_iter()
+    .filter_map(|(name, instance)| {
+      // Instances that went away say so with a TTL of 0, and never tell us their port.
+      let port = instance.port?;
+      let ip = instance
+        .host
+        .and_then(|host| hosts.get(&host).copied().map(IpAddr::V4))
+        .or(instance.source)?;
+      Some(ButtplugDiscoveredServer {
+        name,
+        address: SocketAddr::new(ip, port),
+        version: instance.version,
+      })
+    })
+    .collect();
+  servers.sort_by(|a, b| a.name.cmp(&b.name));
+  servers
+}
+
+/// Asks the local network for advertised Buttplug servers, returning the ones that answered
+/// within `timeout`.
+///
+/// Servers are advertised by the websocket server transport, see
+/// [ButtplugWebsocketServerTransportBuilder::advertise](crate::core::connector::ButtplugWebsocketServerTransportBuilder::advertise).
+pub async fn discover_buttplug_servers(
+  timeout: Duration,
+) -> Result<Vec<ButtplugDiscoveredServer>, ButtplugConnectorError> {
+  // Asking from a port other than the mDNS port means answers come straight back to us, and we
+  // don't have to share the port with any other responder on this machine.
+  let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+    .await
+    .map_err(mdns_error)?;
+  let query = Packet {
+    questions: vec![Question {
+      name: SERVICE_TYPE.to_owned(),
+      record_type: TYPE_PTR,
+    }],
+    ..Default::default()
+  };
+  socket
+    .send_to(&query.encode(), (MDNS_ADDRESS, MDNS_PORT))
+    .await
+    .map_err(mdns_error)?;
+  let mut records = vec![];
+  let mut buffer = vec![0; 9000];
+  let deadline = sleep(timeout).fuse();
+  futures::pin_mut!(deadline);
+  loop {
+    select! {
+      _ = deadline => break,
+      received = socket.recv_from(&mut buffer).fuse() => {
+        let (length, source) = received.map_err(mdns_error)?;
+        if let Some(packet) = Packet::parse(&buffer[..length]) {
+          if packet.response {
+            records.extend(packet.records.into_iter().map(|record| (record, source.ip())));
+          }
+        }
+      }
+    }
+  }
+  // Goodbyes drop the instance from what we've heard.
+  records.retain(|(record, _)| record.ttl != 0);
+  Ok(servers_from_records(&records))
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_servers_from_records() {
+    let source = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 5));
+    let instance = format!("Intiface Central.{}", SERVICE_TYPE);
+    let record = |name: &str, data| Record {
+      name: name.to_owned(),
+      cache_flush: false,
+      ttl: 120,
+      data,
+    };
+    let records = vec![
+      (
+        record(SERVICE_TYPE, RecordData::Ptr(instance.clone())),
+        source,
+      ),
+      (
+        record(
+          &instance,
+          RecordData::Srv {
+            port: 12345,
+            target: "Host.local".to_owned(),
+          },
+        ),
+        source,
+      ),
+      (
+        record(&instance, RecordData::Txt(vec!["version=3".to_owned()])),
+        source,
+      ),
+      (
+        record("host.local", RecordData::A(Ipv4Addr::new(10, 0, 0, 6))),
+        source,
+      ),
+      // Pointer to an instance we never hear the port of.
+      (
+        record(
+          SERVICE_TYPE,
+          RecordData::Ptr(format!("Gone.{}", SERVICE_TYPE)),
+        ),
+        source,
+      ),
+    ];
+    assert_eq!(
+      servers_from_records(&records),
+      vec![ButtplugDiscoveredServer {
+        name: "Intiface Central".to_owned(),
+        address: "10.0.0.6:12345"
+          .parse()
+          .expect("Test, assuming infallible."),
+        version: Some(3),
+      }]
+    );
+  }
+}
@@ -0,0 +1,371 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! mDNS (zeroconf) advertisement and discovery of Buttplug servers on the local network
+//!
+//! Servers are advertised as DNS-SD instances of the `_buttplug._tcp.local` service type, with the
+//! message spec version they speak in a `version` TXT entry. Only the small part of mDNS needed for
+//! that is implemented here: answering questions about our own records, and asking for and
+//! collecting the records of other servers. IPv4 only.
+
+mod advertisement;
+#[cfg(feature = "client")]
+mod discovery;
+
+pub(super) use advertisement::MdnsAdvertisement;
+#[cfg(feature = "client")]
+pub use discovery::{discover_buttplug_servers, ButtplugDiscoveredServer};
+
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+  io,
+  net::{Ipv4Addr, SocketAddr, UdpSocket as StdUdpSocket},
+};
+use tokio::net::UdpSocket;
+
+const MDNS_ADDRESS: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_PORT: u16 = 5353;
+/// DNS-SD service type Buttplug servers are advertised under.
+const SERVICE_TYPE: &str = "_buttplug._tcp.local";
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+/// Top bit of the class, meaning "cache flush" on records and "unicast response wanted" on
+/// questions.
+const CLASS_TOP_BIT: u16 = 0x8000;
+/// Set in the header flags of responses.
+const FLAG_RESPONSE: u16 = 0x8000;
+/// Names can point back at earlier names, limit how many times one can do so we can't be looped.
+const MAX_NAME_POINTERS: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum RecordData {
+  A(Ipv4Addr),
+  Ptr(String),
+  Txt(Vec<String>),
+  Srv {
+    port: u16,
+    target: String,
+  },
+  /// Record types we don't care about.
+  Other,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Record {
+  name: String,
+  /// Whether other records of this name and type should be dropped from caches.
+  cache_flush: bool,
+  ttl: u32,
+  data: RecordData,
+}
+
+impl Record {
+  fn record_type(&self) -> u16 {
+    match self.data {
+      RecordData::A(_) => TYPE_A,
+      RecordData::Ptr(_) => TYPE_PTR,
+      RecordData::Txt(_) => TYPE_TXT,
+      RecordData::Srv { .. } => TYPE_SRV,
+      RecordData::Other => 0,
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Question {
+  name: String,
+  record_type: u16,
+}
+
+impl Question {
+  fn matches(&self, record: &Record) -> bool {
+    self.name.eq_ignore_ascii_case(&record.name)
+      && (self.record_type == TYPE_ANY || self.record_type == record.record_type())
+  }
+}
+
+/// A DNS message, with the answer, authority and additional sections lumped together as records.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Packet {
+  id: u16,
+  response: bool,
+  questions: Vec<Question>,
+  records: Vec<Record>,
+}
+
+fn encode_name(buffer: &mut Vec<u8>, name: &str) {
+  for label in name.split('.').filter(|label| !label.is_empty()) {
+    let label = &label.as_bytes()[..label.len().min(63)];
+    buffer.push(label.len() as u8);
+    buffer.extend_from_slice(label);
+  }
+  buffer.push(0);
+}
+
+fn read_u16(buffer: &[u8], position: &mut usize) -> Option<u16> {
+  let bytes = buffer.get(*position..*position + 2)?;
+  *position += 2;
+  Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(buffer: &[u8], position: &mut usize) -> Option<u32> {
+  let bytes = buffer.get(*position..*position + 4)?;
+  *position += 4;
+  Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn read_name(buffer: &[u8], position: &mut usize) -> Option<String> {
+  let mut labels = vec![];
+  let mut cursor = *position;
+  // Where parsing continues once the name is read, set at the first pointer if there is one.
+  let mut end = None;
+  let mut pointers = 0;
+  loop {
+    let length = *buffer.get(cursor)? as usize;
+    if length == 0 {
+      cursor += 1;
+      break;
+    }
+    if length & 0xC0 == 0xC0 {
+      pointers += 1;
+      if pointers > MAX_NAME_POINTERS {
+        return None;
+      }
+      let offset = ((length & 0x3F) << 8) | *buffer.get(cursor + 1)? as usize;
+      end.get_or_insert(cursor + 2);
+      cursor = offset;
+      continue;
+    }
+    let label = buffer.get(cursor + 1..cursor + 1 + length)?;
+    labels.push(String::from_utf8_lossy(label).into_owned());
+    cursor += 1 + length;
+  }
+  *position = end.unwrap_or(cursor);
+  Some(labels.join("."))
+}
+
+impl Packet {
+  fn encode(&self) -> Vec<u8> {
+    let mut buffer = vec![];
+    buffer.extend_from_slice(&self.id.to_be_bytes());
+    // Responses are always authoritative, we only answer for our own records.
+    let flags = if self.response {
+      FLAG_RESPONSE | 0x0400
+    } else {
+      0
+    };
+    buffer.extend_from_slice(&flags.to_be_bytes());
+    buffer.extend_from_slice(&(self.questions.len() as u16).to_be_bytes());
+    buffer.extend_from_slice(&(self.records.len() as u16).to_be_bytes());
+    buffer.extend_from_slice(&[0, 0, 0, 0]);
+    for question in &self.questions {
+      encode_name(&mut buffer, &question.name);
+      buffer.extend_from_slice(&question.record_type.to_be_bytes());
+      buffer.extend_from_slice(&CLASS_IN.to_be_bytes());
+    }
+    for record in &self.records {
+      encode_name(&mut buffer, &record.name);
+      buffer.extend_from_slice(&record.record_type().to_be_bytes());
+      let class = if record.cache_flush {
+        CLASS_IN | CLASS_TOP_BIT
+      } else {
+        CLASS_IN
+      };
+      buffer.extend_from_slice(&class.to_be_bytes());
+      buffer.extend_from_slice(&record.ttl.to_be_bytes());
+      let mut data = vec![];
+      match &record.data {
+        RecordData::A(address) => data.extend_from_slice(&address.octets()),
+        RecordData::Ptr(name) => encode_name(&mut data, name),
+        RecordData::Txt(entries) => {
+          for entry in entries {
+            let entry = &entry.as_bytes()[..entry.len().min(255)];
+            data.push(entry.len() as u8);
+            data.extend_from_slice(entry);
+          }
+        }
+        RecordData::Srv { port, target } => {
+          // Priority and weight, which only matter when there's more than one server.
+          data.extend_from_slice(&[0, 0, 0, 0]);
+          data.extend_from_slice(&port.to_be_bytes());
+          encode_name(&mut data, target);
+        }
+        RecordData::Other => {}
+      }
+      buffer.extend_from_slice(&(data.len() as u16).to_be_bytes());
+      buffer.extend_from_slice(&data);
+    }
+    buffer
+  }
+
+  /// Parses a DNS message, returning None if it's cut short or otherwise broken.
+  fn parse(buffer: &[u8]) -> Option<Self> {
+    let mut position = 0;
+    let id = read_u16(buffer, &mut position)?;
+    let flags = read_u16(buffer, &mut position)?;
+    let question_count = read_u16(buffer, &mut position)?;
+    let mut record_count = 0;
+    for _ in 0..3 {
+      record_count += read_u16(buffer, &mut position)? as usize;
+    }
+    let mut packet = Packet {
+      id,
+      response: flags & FLAG_RESPONSE != 0,
+      ..Default::default()
+    };
+    for _ in 0..question_count {
+      let name = read_name(buffer, &mut position)?;
+      let record_type = read_u16(buffer, &mut position)?;
+      read_u16(buffer, &mut position)?;
+      packet.questions.push(Question { name, record_type });
+    }
+    for _ in 0..record_count {
+      let name = read_name(buffer, &mut position)?;
+      let record_type = read_u16(buffer, &mut position)?;
+      let class = read_u16(buffer, &mut position)?;
+      let ttl = read_u32(buffer, &mut position)?;
+      let length = read_u16(buffer, &mut position)? as usize;
+      let data_end = position + length;
+      let raw = buffer.get(position..data_end)?;
+      let data = match record_type {
+        TYPE_A if length == 4 => RecordData::A(Ipv4Addr::new(raw[0], raw[1], raw[2], raw[3])),
+        TYPE_PTR => RecordData::Ptr(read_name(buffer, &mut position.clone())?),
+        TYPE_TXT => {
+          let mut entries = vec![];
+          let mut cursor = 0;
+          while cursor < raw.len() {
+            let entry_length = raw[cursor] as usize;
+            let entry = raw.get(cursor + 1..cursor + 1 + entry_length)?;
+            entries.push(String::from_utf8_lossy(entry).into_owned());
+            cursor += 1 + entry_length;
+          }
+          RecordData::Txt(entries)
+        }
+        TYPE_SRV => {
+          let mut cursor = position + 4;
+          let port = read_u16(buffer, &mut cursor)?;
+          let target = read_name(buffer, &mut cursor)?;
+          RecordData::Srv { port, target }
+        }
+        _ => RecordData::Other,
+      };
+      position = data_end;
+      packet.records.push(Record {
+        name,
+        cache_flush: class & CLASS_TOP_BIT != 0,
+        ttl,
+        data,
+      });
+    }
+    Some(packet)
+  }
+}
+
+/// Socket bound to the mDNS port and joined to the mDNS group, shared with any other mDNS
+/// responders on the machine.
+fn multicast_socket() -> io::Result<UdpSocket> {
+  let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+  socket.set_reuse_address(true)?;
+  #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+  socket.set_reuse_port(true)?;
+  socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, MDNS_PORT)).into())?;
+  socket.join_multicast_v4(&MDNS_ADDRESS, &Ipv4Addr::UNSPECIFIED)?;
+  socket.set_multicast_loop_v4(true)?;
+  socket.set_nonblocking(true)?;
+  UdpSocket::from_std(socket.into())
+}
+
+/// Address of the interface multicast goes out on, which is the one we can be reached at by anyone
+/// who hears us.
+fn local_address() -> io::Result<Ipv4Addr> {
+  let socket = StdUdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+  // Connecting a UDP socket doesn't send anything, it only picks the route.
+  socket.connect((MDNS_ADDRESS, MDNS_PORT))?;
+  match socket.local_addr()? {
+    SocketAddr::V4(address) => Ok(*address.ip()),
+    SocketAddr::V6(_) => Err(io::Error::new(
+      io::ErrorKind::AddrNotAvailable,
+      "No IPv4 address to advertise",
+    )),
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_packet_round_trip() {
+    let packet = Packet {
+      id: 0,
+      response: true,
+      questions: vec![Question {
+        name: SERVICE_TYPE.to_owned(),
+        record_type: TYPE_PTR,
+      }],
+      records: vec![
+        Record {
+          name: SERVICE_TYPE.to_owned(),
+          cache_flush: false,
+          ttl: 120,
+          data: RecordData::Ptr(format!("Intiface Central.{}", SERVICE_TYPE)),
+        },
+        Record {
+          name: format!("Intiface Central.{}", SERVICE_TYPE),
+          cache_flush: true,
+          ttl: 120,
+          data: RecordData::Srv {
+            port: 12345,
+            target: "buttplug-192-168-1-2.local".to_owned(),
+          },
+        },
+        Record {
+          name: format!("Intiface Central.{}", SERVICE_TYPE),
+          cache_flush: true,
+          ttl: 120,
+          data: RecordData::Txt(vec!["version=3".to_owned()]),
+        },
+        Record {
+          name: "buttplug-192-168-1-2.local".to_owned(),
+          cache_flush: true,
+          ttl: 120,
+          data: RecordData::A(Ipv4Addr::new(192, 168, 1, 2)),
+        },
+      ],
+    };
+    assert_eq!(Packet::parse(&packet.encode()), Some(packet));
+  }
+
+  #[test]
+  fn test_parse_compressed_names() {
+    // Question for _buttplug._tcp.local, and a PTR answer whose name points back at the question's
+    // name and whose data points at it after a label of its own.
+    let mut buffer = vec![0, 0, 0x84, 0, 0, 1, 0, 1, 0, 0, 0, 0];
+    encode_name(&mut buffer, SERVICE_TYPE);
+    buffer.extend_from_slice(&[0, 12, 0, 1]);
+    buffer.extend_from_slice(&[0xC0, 12, 0, 12, 0, 1, 0, 0, 0, 120, 0, 7]);
+    buffer.extend_from_slice(&[4, b'T', b'e', b's', b't', 0xC0, 12]);
+    let packet = Packet::parse(&buffer).expect("Test, assuming infallible.");
+    assert_eq!(packet.records[0].name, SERVICE_TYPE);
+    assert_eq!(
+      packet.records[0].data,
+      RecordData::Ptr(format!("Test.{}", SERVICE_TYPE))
+    );
+  }
+
+  #[test]
+  fn test_parse_rejects_pointer_loops() {
+    let mut buffer = vec![0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+    buffer.extend_from_slice(&[0xC0, 12, 0, 12, 0, 1]);
+    assert_eq!(Packet::parse(&buffer), None);
+  }
+}
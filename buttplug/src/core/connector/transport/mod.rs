@@ -7,6 +7,12 @@
 
 //! Transports for remote (IPC/network/etc) communication between clients and servers
 
+#[cfg(feature = "grpc")]
+mod grpc;
+#[cfg(feature = "mdns")]
+mod mdns;
+#[cfg(feature = "wasm-websockets")]
+mod wasm_websocket;
 #[cfg(feature = "websockets")]
 mod websocket;
 use crate::core::connector::{
@@ -15,11 +21,22 @@ use crate::core::connector::{
   ButtplugSerializedMessage,
 };
 use futures::future::BoxFuture;
+#[cfg(all(feature = "grpc", feature = "client"))]
+pub use grpc::ButtplugGrpcClientTransport;
+#[cfg(feature = "grpc")]
+pub use grpc::{ButtplugGrpcServerTransport, ButtplugGrpcServerTransportBuilder};
+#[cfg(all(feature = "mdns", feature = "client"))]
+pub use mdns::{discover_buttplug_servers, ButtplugDiscoveredServer};
 use thiserror::Error;
 use tokio::sync::mpsc::{Receiver, Sender};
+#[cfg(feature = "wasm-websockets")]
+pub use wasm_websocket::ButtplugWasmWebsocketClientTransport;
+#[cfg(all(feature = "websockets", feature = "client"))]
+pub use websocket::{ButtplugWebsocketClientTransport, ButtplugWebsocketClientTransportBuilder};
 #[cfg(feature = "websockets")]
 pub use websocket::{
-  ButtplugWebsocketClientTransport,
+  ButtplugWebsocketHeartbeat,
+  ButtplugWebsocketServerListener,
   ButtplugWebsocketServerTransport,
   ButtplugWebsocketServerTransportBuilder,
   TungsteniteError,
@@ -37,6 +54,25 @@ pub enum ButtplugTransportIncomingMessage {
   Error(String),
   /// Connector (or remote server) itself closed the connection.
   Close(String),
+  /// Transport stopped hearing from the remote (for instance, a heartbeat timeout) and gave up on
+  /// the connection.
+  ConnectionLost(String),
+  /// A new client connected while this one was being served, and is waiting to take over the
+  /// connection. See [ButtplugTakeoverPolicy].
+  TakeoverRequested,
+}
+
+/// What a transport that serves a single client does when another client connects while one is
+/// already being served.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ButtplugTakeoverPolicy {
+  /// Refuse new clients until the current client leaves.
+  #[default]
+  Reject,
+  /// Hand the connection over to the new client, which is what users expect when they restart
+  /// their app. The current client is stopped and told why it was disconnected before the new
+  /// client's handshake goes through.
+  Takeover,
 }
 
 pub trait ButtplugConnectorTransport: Send + Sync {
@@ -46,6 +82,15 @@ pub trait ButtplugConnectorTransport: Send + Sync {
     incoming_sender: Sender<ButtplugTransportIncomingMessage>,
   ) -> BoxFuture<'static, Result<(), ButtplugConnectorError>>;
   fn disconnect(self) -> ButtplugConnectorResultFuture;
+  /// Drops the current client in favor of the one announced by
+  /// [ButtplugTransportIncomingMessage::TakeoverRequested]. Anything sent before this is called is
+  /// still delivered to the current client.
+  fn complete_takeover(&self) -> ButtplugConnectorResultFuture {
+    ButtplugConnectorError::ConnectorGenericError(
+      "Transport does not support client takeover".to_owned(),
+    )
+    .into()
+  }
 }
 
 #[derive(Error, Debug)]
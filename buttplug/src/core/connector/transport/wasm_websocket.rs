@@ -0,0 +1,264 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Handling of websockets in browsers, using the WebSocket API via [web_sys]
+
+use crate::core::{
+  connector::{
+    transport::{
+      ButtplugConnectorTransport,
+      ButtplugConnectorTransportSpecificError,
+      ButtplugTransportIncomingMessage,
+    },
+    ButtplugConnectorError,
+    ButtplugConnectorResultFuture,
+  },
+  message::serializer::ButtplugSerializedMessage,
+};
+use futures::{future::BoxFuture, FutureExt};
+use js_sys::{ArrayBuffer, Uint8Array};
+use std::sync::Arc;
+use tokio::sync::{
+  mpsc::{unbounded_channel, Receiver, Sender, UnboundedSender},
+  oneshot,
+  Notify,
+};
+use wasm_bindgen::{closure::Closure, JsCast};
+use wasm_bindgen_futures::spawn_local;
+use web_sys::{BinaryType, CloseEvent, ErrorEvent, MessageEvent, WebSocket};
+
+/// Events coming out of the browser's WebSocket callbacks.
+enum WasmWebsocketEvent {
+  Open,
+  Message(ButtplugSerializedMessage),
+  Error(String),
+  Close(String),
+}
+
+/// Websocket connector for ButtplugClients running in a browser, using the WebSocket API.
+///
+/// Browsers handle TLS (and certificate verification) themselves, so unlike
+/// [ButtplugWebsocketClientTransport](super::ButtplugWebsocketClientTransport) there is only one
+/// constructor, which takes either a "ws://" or "wss://" address.
+pub struct ButtplugWasmWebsocketClientTransport {
+  /// Address of the server we'll connect to.
+  address: String,
+  /// Internally held sender, used for when disconnect is called.
+  disconnect_notifier: Arc<Notify>,
+}
+
+impl ButtplugWasmWebsocketClientTransport {
+  /// Creates a new connector. Address should be the full URL of the server, i.e.
+  /// "ws://127.0.0.1:12345"
+  pub fn new(address: &str) -> Self {
+    Self {
+      address: address.to_owned(),
+      disconnect_notifier: Arc::new(Notify::new()),
+    }
+  }
+}
+
+fn network_error(msg: String) -> ButtplugConnectorError {
+  ButtplugConnectorError::TransportSpecificError(
+    ButtplugConnectorTransportSpecificError::GenericNetworkError(msg),
+  )
+}
+
+async fn run_websocket_loop(
+  address: String,
+  mut outgoing_receiver: Receiver<ButtplugSerializedMessage>,
+  incoming_sender: Sender<ButtplugTransportIncomingMessage>,
+  disconnect_notifier: Arc<Notify>,
+  connect_result: oneshot::Sender<Result<(), ButtplugConnectorError>>,
+) {
+  let ws = match WebSocket::new(&address) {
+    Ok(ws) => ws,
+    Err(err) => {
+      let _ = connect_result.send(Err(network_error(format!("{:?}", err))));
+      return;
+    }
+  };
+  ws.set_binary_type(BinaryType::Arraybuffer);
+
+  // The browser calls back into these from its event loop, so all they do is forward into a channel
+  // we can select on. They need to stay alive for as long as the socket is in use.
+  let (event_sender, mut event_receiver) = unbounded_channel();
+  let onopen = forward_event(&event_sender, |_: web_sys::Event| {
+    Some(WasmWebsocketEvent::Open)
+  });
+  let onmessage = forward_event(&event_sender, |event: MessageEvent| {
+    let data = event.data();
+    if let Some(text) = data.as_string() {
+      Some(WasmWebsocketEvent::Message(
+        ButtplugSerializedMessage::Text(text),
+      ))
+    } else if let Ok(buffer) = data.dyn_into::<ArrayBuffer>() {
+      Some(WasmWebsocketEvent::Message(
+        ButtplugSerializedMessage::Binary(Uint8Array::new(&buffer).to_vec()),
+      ))
+    } else {
+      warn!("Websocket received message of unknown type, ignoring.");
+      None
+    }
+  });
+  let onerror = forward_event(&event_sender, |event: ErrorEvent| {
+    Some(WasmWebsocketEvent::Error(event.message()))
+  });
+  let onclose = forward_event(&event_sender, |event: CloseEvent| {
+    Some(WasmWebsocketEvent::Close(format!(
+      "Server closed connection ({}): {}",
+      event.code(),
+      event.reason()
+    )))
+  });
+  ws.set_onopen(Some(onopen.as_ref().unchecked_ref()));
+  ws.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+  ws.set_onerror(Some(onerror.as_ref().unchecked_ref()));
+  ws.set_onclose(Some(onclose.as_ref().unchecked_ref()));
+
+  // Wait for the socket to open (or fail) before we report back to the connector.
+  let mut connect_result = Some(connect_result);
+  while let Some(event) = event_receiver.recv().await {
+    match event {
+      WasmWebsocketEvent::Open => {
+        if let Some(result) = connect_result.take() {
+          let _ = result.send(Ok(()));
+        }
+        break;
+      }
+      WasmWebsocketEvent::Error(err) | WasmWebsocketEvent::Close(err) => {
+        if let Some(result) = connect_result.take() {
+          let _ = result.send(Err(network_error(err)));
+        }
+        break;
+      }
+      WasmWebsocketEvent::Message(_) => {
+        warn!("Websocket received message before open, ignoring.");
+      }
+    }
+  }
+
+  if connect_result.is_none() && ws.ready_state() == WebSocket::OPEN {
+    loop {
+      select! {
+        msg = outgoing_receiver.recv().fuse() => {
+          if let Some(msg) = msg {
+            let sent = match msg {
+              ButtplugSerializedMessage::Text(text) => ws.send_with_str(&text),
+              ButtplugSerializedMessage::Binary(bin) => ws.send_with_u8_array(&bin),
+            };
+            if let Err(err) = sent {
+              error!("Websocket send failed: {:?}", err);
+            }
+          } else {
+            info!("Connector holding websocket dropped, returning");
+            let _ = incoming_sender
+              .send(ButtplugTransportIncomingMessage::Close(
+                "Server closed connection".to_owned(),
+              ))
+              .await;
+            break;
+          }
+        },
+        event = event_receiver.recv().fuse() => match event {
+          Some(WasmWebsocketEvent::Message(msg)) => {
+            trace!("Websocket receiving: {:?}", msg);
+            if incoming_sender
+              .send(ButtplugTransportIncomingMessage::Message(msg))
+              .await
+              .is_err()
+            {
+              warn!("Websocket holder has closed, exiting websocket loop.");
+              break;
+            }
+          }
+          Some(WasmWebsocketEvent::Error(err)) => {
+            // Browsers follow errors up with a close event, so we'll exit there.
+            error!("Error in websocket client loop: {}", err);
+          }
+          Some(WasmWebsocketEvent::Close(reason)) => {
+            info!("Websocket has requested close.");
+            let _ = incoming_sender
+              .send(ButtplugTransportIncomingMessage::Close(reason))
+              .await;
+            break;
+          }
+          Some(WasmWebsocketEvent::Open) | None => {}
+        },
+        _ = disconnect_notifier.notified().fuse() => {
+          info!("Websocket requested to disconnect.");
+          let _ = incoming_sender
+            .send(ButtplugTransportIncomingMessage::Close(
+              "Disconnect notifier triggered, closed connection".to_owned(),
+            ))
+            .await;
+          break;
+        }
+      }
+    }
+  }
+
+  if let Err(err) = ws.close() {
+    error!("Error closing websocket: {:?}", err);
+  }
+  // Unhook our callbacks before they're dropped, so the browser doesn't call into freed closures.
+  ws.set_onopen(None);
+  ws.set_onmessage(None);
+  ws.set_onerror(None);
+  ws.set_onclose(None);
+}
+
+fn forward_event<E, F>(
+  sender: &UnboundedSender<WasmWebsocketEvent>,
+  convert: F,
+) -> Closure<dyn FnMut(E)>
+where
+  E: wasm_bindgen::convert::FromWasmAbi + 'static,
+  F: Fn(E) -> Option<WasmWebsocketEvent> + 'static,
+{
+  let sender = sender.clone();
+  Closure::wrap(Box::new(move |event: E| {
+    if let Some(event) = convert(event) {
+      let _ = sender.send(event);
+    }
+  }) as Box<dyn FnMut(E)>)
+}
+
+impl ButtplugConnectorTransport for ButtplugWasmWebsocketClientTransport {
+  fn connect(
+    &self,
+    outgoing_receiver: Receiver<ButtplugSerializedMessage>,
+    incoming_sender: Sender<ButtplugTransportIncomingMessage>,
+  ) -> BoxFuture<'static, Result<(), ButtplugConnectorError>> {
+    // Browser objects can't leave the thread they were made on, so the socket lives entirely in a
+    // local task, and we just wait here for it to tell us how connecting went.
+    let (result_sender, result_receiver) = oneshot::channel();
+    spawn_local(run_websocket_loop(
+      self.address.clone(),
+      outgoing_receiver,
+      incoming_sender,
+      self.disconnect_notifier.clone(),
+      result_sender,
+    ));
+    async move {
+      result_receiver
+        .await
+        .unwrap_or(Err(ButtplugConnectorError::ConnectorChannelClosed))
+    }
+    .boxed()
+  }
+
+  fn disconnect(self) -> ButtplugConnectorResultFuture {
+    let disconnect_notifier = self.disconnect_notifier;
+    async move {
+      // If we can't send the message, we have no loop, so we're not connected.
+      disconnect_notifier.notify_waiters();
+      Ok(())
+    }
+    .boxed()
+  }
+}
@@ -7,13 +7,135 @@
 
 //! Websocket connector for client/server communication
 
+#[cfg(feature = "client")]
 pub mod websocket_client;
 pub mod websocket_server;
 
 pub use async_tungstenite::tungstenite::Error as TungsteniteError;
-pub use websocket_client::ButtplugWebsocketClientTransport;
+use futures::future::{self, BoxFuture, FutureExt};
+use std::time::Duration;
+use tokio::time::{sleep_until, Instant};
+#[cfg(feature = "client")]
+pub use websocket_client::{
+  ButtplugWebsocketClientTransport,
+  ButtplugWebsocketClientTransportBuilder,
+};
 
 pub use websocket_server::{
+  ButtplugWebsocketServerListener,
   ButtplugWebsocketServerTransport,
   ButtplugWebsocketServerTransportBuilder,
 };
+
+/// Transport level keepalive for websocket connections.
+///
+/// This is separate from the Buttplug protocol's Ping message, which checks that the client
+/// application is still alive. The heartbeat sends websocket pings and expects to hear something
+/// back from the remote within the timeout, so connections where the other side has disappeared
+/// without closing the socket (sleeping laptops, dropped wifi, etc) are noticed within seconds
+/// instead of whenever TCP gets around to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ButtplugWebsocketHeartbeat {
+  /// How often to send a websocket ping.
+  pub interval: Duration,
+  /// How long the remote can go without sending anything before the connection is considered lost.
+  pub timeout: Duration,
+}
+
+impl Default for ButtplugWebsocketHeartbeat {
+  fn default() -> Self {
+    Self {
+      interval: Duration::from_secs(2),
+      timeout: Duration::from_secs(6),
+    }
+  }
+}
+
+/// What a websocket loop should do when its heartbeat deadline comes up.
+enum HeartbeatAction {
+  SendPing,
+  ConnectionLost,
+}
+
+/// Tracks heartbeat deadlines for a websocket connection loop.
+///
+/// Deadlines are absolute, so a loop can rebuild [HeartbeatTimer::wait] every time through a
+/// `select!` without traffic on other branches pushing the next ping back.
+struct HeartbeatTimer {
+  heartbeat: Option<ButtplugWebsocketHeartbeat>,
+  last_heard: Instant,
+  next_ping: Instant,
+}
+
+impl HeartbeatTimer {
+  fn new(heartbeat: Option<ButtplugWebsocketHeartbeat>) -> Self {
+    let now = Instant::now();
+    Self {
+      next_ping: now + heartbeat.map(|h| h.interval).unwrap_or_default(),
+      last_heard: now,
+      heartbeat,
+    }
+  }
+
+  /// Call whenever anything is received from the remote.
+  fn heard(&mut self) {
+    self.last_heard = Instant::now();
+  }
+
+  /// Resolves at the next heartbeat deadline. Never resolves if the heartbeat is disabled.
+  fn wait(&self) -> BoxFuture<'static, ()> {
+    if self.heartbeat.is_some() {
+      sleep_until(self.next_ping).boxed()
+    } else {
+      future::pending().boxed()
+    }
+  }
+
+  /// Decides what to do once [HeartbeatTimer::wait] resolves.
+  fn action(&mut self) -> HeartbeatAction {
+    let heartbeat = self
+      .heartbeat
+      .expect("wait() never resolves without a heartbeat.");
+    if self.last_heard.elapsed() >= heartbeat.timeout {
+      HeartbeatAction::ConnectionLost
+    } else {
+      self.next_ping = Instant::now() + heartbeat.interval;
+      HeartbeatAction::SendPing
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[tokio::test]
+  async fn test_heartbeat_times_out_without_traffic() {
+    let mut timer = HeartbeatTimer::new(Some(ButtplugWebsocketHeartbeat {
+      interval: Duration::from_millis(10),
+      timeout: Duration::from_millis(35),
+    }));
+    let mut pings = 0;
+    loop {
+      timer.wait().await;
+      match timer.action() {
+        HeartbeatAction::SendPing => pings += 1,
+        HeartbeatAction::ConnectionLost => break,
+      }
+    }
+    assert!(pings >= 2);
+  }
+
+  #[tokio::test]
+  async fn test_heartbeat_stays_alive_with_traffic() {
+    let mut timer = HeartbeatTimer::new(Some(ButtplugWebsocketHeartbeat {
+      interval: Duration::from_millis(10),
+      timeout: Duration::from_millis(35),
+    }));
+    for _ in 0..10 {
+      timer.wait().await;
+      timer.heard();
+      assert!(matches!(timer.action(), HeartbeatAction::SendPing));
+    }
+  }
+}
@@ -6,7 +6,12 @@
 // for full license information.
 
 //! Handling of websockets using async-tungstenite
+//!
+//! Connecting to "wss://" addresses needs one of the TLS backends, picked with the
+//! `websockets-native-tls` or `websockets-rustls` features. Without either, only "ws://" addresses
+//! can be connected to.
 
+use super::{ButtplugWebsocketHeartbeat, HeartbeatAction, HeartbeatTimer};
 use crate::{
   core::{
     connector::{
@@ -22,58 +27,216 @@ use crate::{
   },
   util::async_manager,
 };
-use async_tungstenite::{tokio::connect_async_with_tls_connector, tungstenite::protocol::Message};
+use async_tungstenite::tungstenite::protocol::Message;
 use futures::{future::BoxFuture, FutureExt, SinkExt, StreamExt};
 use std::sync::Arc;
 use tokio::sync::{
   mpsc::{Receiver, Sender},
   Notify,
 };
-use tokio_native_tls::native_tls::TlsConnector as NativeTlsConnector;
-use tokio_native_tls::TlsConnector;
 use tracing::Instrument;
 
+#[derive(Clone, Debug)]
+pub struct ButtplugWebsocketClientTransportBuilder {
+  /// Address of the server we'll connect to.
+  address: String,
+  /// Transport level keepalive, if any.
+  heartbeat: Option<ButtplugWebsocketHeartbeat>,
+  /// If true, don't verify the certificate of "wss://" servers.
+  dangerously_accept_invalid_certs: bool,
+}
+
+impl ButtplugWebsocketClientTransportBuilder {
+  /// Starts building a connector to `address`, which should be the full URL of the server, i.e.
+  /// "ws://127.0.0.1:12345". "wss://" addresses are connected to over TLS.
+  pub fn new(address: &str) -> Self {
+    Self {
+      address: address.to_owned(),
+      heartbeat: Some(ButtplugWebsocketHeartbeat::default()),
+      dangerously_accept_invalid_certs: false,
+    }
+  }
+
+  /// Sets the transport heartbeat, or turns it off if `None`. On by default, using
+  /// [ButtplugWebsocketHeartbeat::default].
+  pub fn heartbeat(&mut self, heartbeat: Option<ButtplugWebsocketHeartbeat>) -> &mut Self {
+    self.heartbeat = heartbeat;
+    self
+  }
+
+  /// If true, the certificate of "wss://" servers isn't verified at all, so servers using
+  /// self-signed certs can be connected to. This also lets anyone between the client and server
+  /// read and change everything sent over the connection. Off by default.
+  pub fn dangerously_accept_invalid_certs(&mut self, accept_invalid_certs: bool) -> &mut Self {
+    self.dangerously_accept_invalid_certs = accept_invalid_certs;
+    self
+  }
+
+  pub fn finish(&self) -> ButtplugWebsocketClientTransport {
+    ButtplugWebsocketClientTransport {
+      address: self.address.clone(),
+      heartbeat: self.heartbeat,
+      dangerously_accept_invalid_certs: self.dangerously_accept_invalid_certs,
+      disconnect_notifier: Arc::new(Notify::new()),
+    }
+  }
+}
+
 /// Websocket connector for ButtplugClients, using [async_tungstenite]
 pub struct ButtplugWebsocketClientTransport {
   /// Address of the server we'll connect to.
   address: String,
-  /// If true, use a TLS wrapper on our connection.
-  should_use_tls: bool,
+  /// Transport level keepalive, if any.
+  heartbeat: Option<ButtplugWebsocketHeartbeat>,
   /// If true, bypass certificate verification. Should be true for self-signed
   /// certs.
-  bypass_cert_verify: bool,
+  dangerously_accept_invalid_certs: bool,
   /// Internally held sender, used for when disconnect is called.
   disconnect_notifier: Arc<Notify>,
 }
 
 impl ButtplugWebsocketClientTransport {
-  fn create(address: &str, should_use_tls: bool, bypass_cert_verify: bool) -> Self {
-    Self {
-      should_use_tls,
-      address: address.to_owned(),
-      bypass_cert_verify,
-      disconnect_notifier: Arc::new(Notify::new()),
-    }
-  }
-
   /// Creates a new connector for "ws://" addresses
   ///
   /// Returns a websocket connector for connecting over insecure websockets to a
   /// server. Address should be the full URL of the server, i.e.
   /// "ws://127.0.0.1:12345"
   pub fn new_insecure_connector(address: &str) -> Self {
-    ButtplugWebsocketClientTransport::create(address, false, false)
+    ButtplugWebsocketClientTransportBuilder::new(address).finish()
   }
 
   /// Creates a new connector for "wss://" addresses
   ///
   /// Returns a websocket connector for connecting over secure websockets to a
   /// server. Address should be the full URL of the server, i.e.
-  /// "ws://127.0.0.1:12345". If `bypass_cert_verify` is true, then the
+  /// "wss://127.0.0.1:12345". If `bypass_cert_verify` is true, then the
   /// certificate of the server will not be verified (useful for servers using
   /// self-signed certs).
   pub fn new_secure_connector(address: &str, bypass_cert_verify: bool) -> Self {
-    ButtplugWebsocketClientTransport::create(address, true, bypass_cert_verify)
+    ButtplugWebsocketClientTransportBuilder::new(address)
+      .dangerously_accept_invalid_certs(bypass_cert_verify)
+      .finish()
+  }
+
+  /// Sets the transport heartbeat, or turns it off if `None`. On by default, using
+  /// [ButtplugWebsocketHeartbeat::default].
+  pub fn heartbeat(mut self, heartbeat: Option<ButtplugWebsocketHeartbeat>) -> Self {
+    self.heartbeat = heartbeat;
+    self
+  }
+}
+
+type ClientWebsocketStream =
+  async_tungstenite::WebSocketStream<async_tungstenite::tokio::ConnectStream>;
+
+/// Connects to `address`, over TLS for "wss://" addresses.
+#[cfg(any(feature = "websockets-native-tls", feature = "websockets-rustls"))]
+async fn connect_websocket(
+  address: &str,
+  accept_invalid_certs: bool,
+) -> Result<ClientWebsocketStream, ButtplugConnectorError> {
+  let tls_connector = if address.starts_with("wss://") {
+    let connector = tls::connector(accept_invalid_certs).map_err(|err| {
+      ButtplugConnectorError::ConnectorGenericError(format!("Cannot set up TLS: {}", err))
+    })?;
+    Some(connector)
+  } else {
+    None
+  };
+  async_tungstenite::tokio::connect_async_with_tls_connector(address, tls_connector)
+    .await
+    .map(|(stream, _)| stream)
+    .map_err(|err| {
+      ButtplugConnectorError::TransportSpecificError(
+        ButtplugConnectorTransportSpecificError::TungsteniteError(err),
+      )
+    })
+}
+
+/// Connects to `address`, which can't be a "wss://" address without a TLS backend.
+#[cfg(not(any(feature = "websockets-native-tls", feature = "websockets-rustls")))]
+async fn connect_websocket(
+  address: &str,
+  _accept_invalid_certs: bool,
+) -> Result<ClientWebsocketStream, ButtplugConnectorError> {
+  if address.starts_with("wss://") {
+    return Err(ButtplugConnectorError::ConnectorGenericError(
+      "Connecting to wss:// addresses needs the websockets-native-tls or websockets-rustls feature."
+        .to_owned(),
+    ));
+  }
+  async_tungstenite::tokio::connect_async(address)
+    .await
+    .map(|(stream, _)| stream)
+    .map_err(|err| {
+      ButtplugConnectorError::TransportSpecificError(
+        ButtplugConnectorTransportSpecificError::TungsteniteError(err),
+      )
+    })
+}
+
+#[cfg(feature = "websockets-native-tls")]
+mod tls {
+  use tokio_native_tls::{native_tls, TlsConnector};
+
+  pub fn connector(accept_invalid_certs: bool) -> Result<TlsConnector, native_tls::Error> {
+    native_tls::TlsConnector::builder()
+      .danger_accept_invalid_certs(accept_invalid_certs)
+      .build()
+      .map(TlsConnector::from)
+  }
+}
+
+// async-tungstenite only builds one TLS backend, and picks native-tls when both are on.
+#[cfg(all(feature = "websockets-rustls", not(feature = "websockets-native-tls")))]
+mod tls {
+  use rustls::{
+    client::{ServerCertVerified, ServerCertVerifier},
+    Certificate,
+    ClientConfig,
+    OwnedTrustAnchor,
+    RootCertStore,
+    ServerName,
+  };
+  use std::{sync::Arc, time::SystemTime};
+  use tokio_rustls::TlsConnector;
+
+  /// Accepts whatever certificate the server has, for
+  /// [dangerously_accept_invalid_certs](super::ButtplugWebsocketClientTransportBuilder::dangerously_accept_invalid_certs).
+  struct AcceptAnyCertificate;
+
+  impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+      &self,
+      _end_entity: &Certificate,
+      _intermediates: &[Certificate],
+      _server_name: &ServerName,
+      _scts: &mut dyn Iterator<Item = &[u8]>,
+      _ocsp_response: &[u8],
+      _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+      Ok(ServerCertVerified::assertion())
+    }
+  }
+
+  pub fn connector(accept_invalid_certs: bool) -> Result<TlsConnector, rustls::Error> {
+    let config = ClientConfig::builder().with_safe_defaults();
+    let config = if accept_invalid_certs {
+      config
+        .with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate))
+        .with_no_client_auth()
+    } else {
+      let mut roots = RootCertStore::empty();
+      roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+        OwnedTrustAnchor::from_subject_spki_name_constraints(
+          anchor.subject,
+          anchor.spki,
+          anchor.name_constraints,
+        )
+      }));
+      config.with_root_certificates(roots).with_no_client_auth()
+    };
+    Ok(TlsConnector::from(Arc::new(config)))
   }
 }
 
@@ -84,36 +247,13 @@ impl ButtplugConnectorTransport for ButtplugWebsocketClientTransport {
     incoming_sender: Sender<ButtplugTransportIncomingMessage>,
   ) -> BoxFuture<'static, Result<(), ButtplugConnectorError>> {
     let disconnect_notifier = self.disconnect_notifier.clone();
-
-    // If we're supposed to be a secure connection, generate a TLS connector
-    // based on our certificate verfication needs. Otherwise, just pass None in
-    // which case we won't wrap.
-    let tls_connector: Option<TlsConnector> = if self.should_use_tls {
-      if self.bypass_cert_verify {
-        Some(
-          NativeTlsConnector::builder()
-            .danger_accept_invalid_certs(true)
-            .build()
-            .expect("Should always succeed, we're not setting any fallible options.")
-            .into(),
-        )
-      } else {
-        Some(
-          NativeTlsConnector::new()
-            .expect("Should always succeed, not setting options.")
-            .into(),
-        )
-      }
-    } else {
-      // If we're not using a secure connection, just return None, at which
-      // point async_tungstenite won't use a wrapper.
-      None
-    };
     let address = self.address.clone();
+    let accept_invalid_certs = self.dangerously_accept_invalid_certs;
+    let mut heartbeat = HeartbeatTimer::new(self.heartbeat);
 
     async move {
-      match connect_async_with_tls_connector(&address, tls_connector).await {
-        Ok((stream, _)) => {
+      match connect_websocket(&address, accept_invalid_certs).await {
+        Ok(stream) => {
           let (mut writer, mut reader) = stream.split();
 
           async_manager::spawn(
@@ -141,6 +281,25 @@ impl ButtplugConnectorTransport for ButtplugWebsocketClientTransport {
                       return;
                     }
                   },
+                  _ = heartbeat.wait().fuse() => match heartbeat.action() {
+                    HeartbeatAction::SendPing => {
+                      if writer.send(Message::Ping(vec![])).await.is_err() {
+                        warn!("Cannot send websocket ping, waiting for heartbeat timeout.");
+                      }
+                    }
+                    HeartbeatAction::ConnectionLost => {
+                      // Don't bother with a close handshake, there's nobody there to answer it.
+                      warn!("Websocket heartbeat timed out, considering connection lost.");
+                      if incoming_sender
+                        .send(ButtplugTransportIncomingMessage::ConnectionLost("Websocket heartbeat timed out".to_owned()))
+                        .await
+                        .is_err()
+                      {
+                        warn!("Websocket holder has closed, exiting websocket loop.");
+                      }
+                      return;
+                    }
+                  },
                   response = reader.next().fuse() => {
                     trace!("Websocket receiving: {:?}", response);
                     if response.is_none() {
@@ -148,6 +307,7 @@ impl ButtplugConnectorTransport for ButtplugWebsocketClientTransport {
                       writer.close().await.unwrap_or_else(|err| error!("{}", err));
                       return;
                     }
+                    heartbeat.heard();
                     match response.expect("Already checked for none.") {
                       Ok(msg) => match msg {
                         Message::Text(t) => {
@@ -225,9 +385,7 @@ impl ButtplugConnectorTransport for ButtplugWebsocketClientTransport {
           );
           Ok(())
         }
-        Err(websocket_error) => Err(ButtplugConnectorError::TransportSpecificError(
-          ButtplugConnectorTransportSpecificError::TungsteniteError(websocket_error),
-        )),
+        Err(err) => Err(err),
       }
     }
     .boxed()
@@ -5,12 +5,16 @@
 // Licensed under the BSD 3-Clause license. See LICENSE file in the project root
 // for full license information.
 
+use super::{ButtplugWebsocketHeartbeat, HeartbeatAction, HeartbeatTimer};
+#[cfg(feature = "mdns")]
+use crate::core::connector::transport::mdns::MdnsAdvertisement;
 use crate::{
   core::{
     connector::{
       transport::{
         ButtplugConnectorTransport,
         ButtplugConnectorTransportSpecificError,
+        ButtplugTakeoverPolicy,
         ButtplugTransportIncomingMessage,
       },
       ButtplugConnectorError,
@@ -20,23 +24,39 @@ use crate::{
   },
   util::async_manager,
 };
-use futures::{future::BoxFuture, AsyncRead, AsyncWrite, FutureExt, SinkExt, StreamExt};
-use std::{sync::Arc, time::Duration};
+use async_tungstenite::{tokio::TokioAdapter, tungstenite::Message, WebSocketStream};
+use futures::{
+  future::{self, BoxFuture},
+  stream::{self, SplitSink},
+  FutureExt,
+  SinkExt,
+  Stream,
+  StreamExt,
+};
+use std::sync::{Arc, Mutex};
 use tokio::{
-  net::TcpListener,
+  net::{TcpListener, TcpStream},
   sync::{
-    mpsc::{Receiver, Sender},
+    mpsc::{channel, Receiver, Sender},
     Notify,
   },
-  time::sleep,
 };
 
+type ServerWebsocketStream = WebSocketStream<TokioAdapter<TcpStream>>;
+
 #[derive(Clone, Debug)]
 pub struct ButtplugWebsocketServerTransportBuilder {
   /// If true, listens all on available interfaces. Otherwise, only listens on 127.0.0.1.
   listen_on_all_interfaces: bool,
   /// Insecure port for listening for websocket connections.
   port: u16,
+  /// Transport level keepalive, if any.
+  heartbeat: Option<ButtplugWebsocketHeartbeat>,
+  /// What to do with clients that connect while one is already connected.
+  takeover_policy: ButtplugTakeoverPolicy,
+  /// Name to advertise the server under over mDNS, if any.
+  #[cfg(feature = "mdns")]
+  advertised_name: Option<String>,
 }
 
 impl Default for ButtplugWebsocketServerTransportBuilder {
@@ -44,6 +64,10 @@ impl Default for ButtplugWebsocketServerTransportBuilder {
     Self {
       listen_on_all_interfaces: false,
       port: 12345,
+      heartbeat: Some(ButtplugWebsocketHeartbeat::default()),
+      takeover_policy: ButtplugTakeoverPolicy::default(),
+      #[cfg(feature = "mdns")]
+      advertised_name: None,
     }
   }
 }
@@ -59,30 +83,266 @@ impl ButtplugWebsocketServerTransportBuilder {
     self
   }
 
+  /// Sets the transport heartbeat, or turns it off if `None`. On by default, using
+  /// [ButtplugWebsocketHeartbeat::default].
+  pub fn heartbeat(&mut self, heartbeat: Option<ButtplugWebsocketHeartbeat>) -> &mut Self {
+    self.heartbeat = heartbeat;
+    self
+  }
+
+  /// Sets what happens when a client connects while another one is connected. Defaults to
+  /// [ButtplugTakeoverPolicy::Reject].
+  pub fn takeover_policy(&mut self, takeover_policy: ButtplugTakeoverPolicy) -> &mut Self {
+    self.takeover_policy = takeover_policy;
+    self
+  }
+
+  /// Advertises the server on the local network over mDNS under `name` while it's waiting for
+  /// clients, so they can find it with
+  /// [discover_buttplug_servers](crate::core::connector::discover_buttplug_servers). Off by
+  /// default. Only useful when listening on all interfaces.
+  #[cfg(feature = "mdns")]
+  pub fn advertise(&mut self, name: Option<String>) -> &mut Self {
+    self.advertised_name = name;
+    self
+  }
+
   pub fn finish(&self) -> ButtplugWebsocketServerTransport {
     ButtplugWebsocketServerTransport {
       port: self.port,
       listen_on_all_interfaces: self.listen_on_all_interfaces,
+      heartbeat: self.heartbeat,
+      takeover_policy: self.takeover_policy,
+      #[cfg(feature = "mdns")]
+      advertised_name: self.advertised_name.clone(),
       disconnect_notifier: Arc::new(Notify::new()),
+      takeover_notifier: Arc::new(Notify::new()),
+      accepted_client: Mutex::new(None),
     }
   }
+
+  /// Binds the listening socket and returns a [ButtplugWebsocketServerListener], which accepts any
+  /// number of clients instead of the one a transport from [finish](Self::finish) serves. The
+  /// takeover policy doesn't apply, since no client has to make room for another.
+  pub async fn listen(&self) -> Result<ButtplugWebsocketServerListener, ButtplugConnectorError> {
+    let listener = bind_listener(self.port, self.listen_on_all_interfaces).await?;
+    Ok(ButtplugWebsocketServerListener {
+      listener,
+      port: self.port,
+      listen_on_all_interfaces: self.listen_on_all_interfaces,
+      heartbeat: self.heartbeat,
+      #[cfg(feature = "mdns")]
+      _advertisement: start_advertisement(
+        self.advertised_name.clone(),
+        self.port,
+        self.listen_on_all_interfaces,
+      ),
+    })
+  }
 }
 
-async fn run_connection_loop<S>(
-  ws_stream: async_tungstenite::WebSocketStream<S>,
+/// Listens for websocket clients, handing each one that connects its own
+/// [ButtplugWebsocketServerTransport]. Made by [ButtplugWebsocketServerTransportBuilder::listen],
+/// and meant to be used with
+/// [ButtplugRemoteServer::start_sessions](crate::server::ButtplugRemoteServer::start_sessions) to
+/// serve several clients at once.
+///
+/// The socket stays bound (and advertised, if the builder was set to) until the listener is
+/// dropped.
+pub struct ButtplugWebsocketServerListener {
+  listener: TcpListener,
+  port: u16,
+  listen_on_all_interfaces: bool,
+  heartbeat: Option<ButtplugWebsocketHeartbeat>,
+  #[cfg(feature = "mdns")]
+  _advertisement: Option<MdnsAdvertisement>,
+}
+
+impl ButtplugWebsocketServerListener {
+  /// Waits for the next client to connect, returning a transport that serves it. Clients whose
+  /// websocket handshake fails are skipped.
+  pub async fn accept(&self) -> Result<ButtplugWebsocketServerTransport, ButtplugConnectorError> {
+    loop {
+      let (stream, _) = self.listener.accept().await.map_err(|e| {
+        ButtplugConnectorError::TransportSpecificError(
+          ButtplugConnectorTransportSpecificError::GenericNetworkError(format!("{:?}", e)),
+        )
+      })?;
+      match async_tungstenite::tokio::accept_async(stream).await {
+        Ok(ws_stream) => {
+          info!("Websocket: Got connection");
+          return Ok(ButtplugWebsocketServerTransport {
+            port: self.port,
+            listen_on_all_interfaces: self.listen_on_all_interfaces,
+            heartbeat: self.heartbeat,
+            takeover_policy: ButtplugTakeoverPolicy::Reject,
+            #[cfg(feature = "mdns")]
+            advertised_name: None,
+            disconnect_notifier: Arc::new(Notify::new()),
+            takeover_notifier: Arc::new(Notify::new()),
+            accepted_client: Mutex::new(Some(ws_stream)),
+          });
+        }
+        Err(err) => warn!("Websocket server accept error: {:?}", err),
+      }
+    }
+  }
+
+  /// Turns the listener into a stream of transports, one per client, which ends if the socket
+  /// stops accepting connections.
+  pub fn transports(self) -> impl Stream<Item = ButtplugWebsocketServerTransport> + Send {
+    stream::unfold(self, |listener| async move {
+      match listener.accept().await {
+        Ok(transport) => Some((transport, listener)),
+        Err(err) => {
+          error!("Websocket server stopped accepting clients: {:?}", err);
+          None
+        }
+      }
+    })
+  }
+}
+
+async fn bind_listener(
+  port: u16,
+  listen_on_all_interfaces: bool,
+) -> Result<TcpListener, ButtplugConnectorError> {
+  let base_addr = if listen_on_all_interfaces {
+    "0.0.0.0"
+  } else {
+    "127.0.0.1"
+  };
+  let addr = format!("{}:{}", base_addr, port);
+  debug!("Websocket: Trying to listen on {}", addr);
+  // Create the event loop and TCP listener we'll accept connections on.
+  let listener = TcpListener::bind(&addr).await.map_err(|e| {
+    ButtplugConnectorError::TransportSpecificError(
+      ButtplugConnectorTransportSpecificError::GenericNetworkError(format!("{:?}", e)),
+    )
+  })?;
+  debug!("Websocket: Listening on: {}", addr);
+  Ok(listener)
+}
+
+/// Accepts clients that want to take over the current connection, handing them to the connection
+/// loop once their websocket handshake is done. Exits when the connection loop does.
+async fn accept_takeover_clients(
+  listener: TcpListener,
+  client_sender: Sender<ServerWebsocketStream>,
+) {
+  loop {
+    let stream = select! {
+      _ = client_sender.closed().fuse() => return,
+      accepted = listener.accept().fuse() => match accepted {
+        Ok((stream, _)) => stream,
+        Err(err) => {
+          error!("Websocket server accept error, no longer accepting takeovers: {:?}", err);
+          return;
+        }
+      }
+    };
+    match async_tungstenite::tokio::accept_async(stream).await {
+      Ok(ws_stream) => {
+        if client_sender.send(ws_stream).await.is_err() {
+          return;
+        }
+      }
+      Err(err) => warn!(
+        "Websocket server accept error for takeover client: {:?}",
+        err
+      ),
+    }
+  }
+}
+
+async fn next_takeover_client(
+  client_receiver: &mut Option<Receiver<ServerWebsocketStream>>,
+) -> ServerWebsocketStream {
+  if let Some(receiver) = client_receiver {
+    if let Some(client) = receiver.recv().await {
+      return client;
+    }
+  }
+  future::pending().await
+}
+
+/// Starts advertising the server over mDNS, if it's meant to be. Failing to advertise isn't fatal,
+/// clients can still connect if they know the address.
+#[cfg(feature = "mdns")]
+fn start_advertisement(
+  name: Option<String>,
+  port: u16,
+  listen_on_all_interfaces: bool,
+) -> Option<MdnsAdvertisement> {
+  let name = name?;
+  if !listen_on_all_interfaces {
+    warn!("Websocket: Advertising server over mDNS while only listening on 127.0.0.1, other machines won't be able to connect.");
+  }
+  match MdnsAdvertisement::start(&name, port) {
+    Ok(advertisement) => Some(advertisement),
+    Err(err) => {
+      warn!(
+        "Websocket: Cannot advertise server over mDNS, continuing without: {:?}",
+        err
+      );
+      None
+    }
+  }
+}
+
+fn websocket_message(msg: ButtplugSerializedMessage) -> Message {
+  match msg {
+    ButtplugSerializedMessage::Text(text_msg) => Message::Text(text_msg),
+    ButtplugSerializedMessage::Binary(binary_msg) => Message::Binary(binary_msg),
+  }
+}
+
+/// Sends anything still queued for the current client, then closes its connection.
+async fn close_for_takeover(
+  mut websocket_server_sender: SplitSink<ServerWebsocketStream, Message>,
+  request_receiver: &mut Receiver<ButtplugSerializedMessage>,
+) {
+  while let Ok(serialized_msg) = request_receiver.try_recv() {
+    if websocket_server_sender
+      .send(websocket_message(serialized_msg))
+      .await
+      .is_err()
+    {
+      warn!("Cannot send to client being taken over, dropping remaining messages.");
+      break;
+    }
+  }
+  if let Err(e) = websocket_server_sender.close().await {
+    warn!(
+      "Cannot close connection to client being taken over: {:?}",
+      e
+    );
+  }
+}
+
+async fn run_connection_loop(
+  ws_stream: ServerWebsocketStream,
   mut request_receiver: Receiver<ButtplugSerializedMessage>,
   response_sender: Sender<ButtplugTransportIncomingMessage>,
   disconnect_notifier: Arc<Notify>,
-) where
-  S: AsyncRead + AsyncWrite + Unpin,
-{
+  heartbeat_config: Option<ButtplugWebsocketHeartbeat>,
+  takeover_listener: Option<TcpListener>,
+  takeover_notifier: Arc<Notify>,
+) {
   info!("Starting websocket server connection event loop.");
 
   let (mut websocket_server_sender, mut websocket_server_receiver) = ws_stream.split();
 
-  // Start pong count at 1, so we'll clear it after sending our first ping.
-  let mut pong_count = 1u32;
+  let mut takeover_receiver = takeover_listener.map(|listener| {
+    let (client_sender, client_receiver) = channel(1);
+    async_manager::spawn(accept_takeover_clients(listener, client_sender));
+    client_receiver
+  });
+  let mut takeover_client = None;
+
+  let mut heartbeat = HeartbeatTimer::new(heartbeat_config);
   loop {
+    let mut complete_takeover = false;
     select! {
       _ = disconnect_notifier.notified().fuse() => {
         info!("Websocket server connector requested disconnect.");
@@ -91,20 +351,31 @@ async fn run_connection_loop<S>(
           return;
         }
       },
-      _ = sleep(Duration::from_millis(10000)).fuse() => {
-        if pong_count == 0 {
-          warn!("No pongs received, considering connection closed.");
+      _ = heartbeat.wait().fuse() => match heartbeat.action() {
+        HeartbeatAction::SendPing => {
+          if websocket_server_sender
+            .send(async_tungstenite::tungstenite::Message::Ping(vec!(0)))
+            .await
+            .is_err() {
+            warn!("Cannot send ping to client, waiting for heartbeat timeout.");
+          }
+        }
+        HeartbeatAction::ConnectionLost => {
+          warn!("Websocket heartbeat timed out, considering connection lost.");
+          let _ = response_sender.send(ButtplugTransportIncomingMessage::ConnectionLost("Websocket heartbeat timed out".to_owned())).await;
           return;
         }
-        pong_count = 0;
-        if websocket_server_sender
-          .send(async_tungstenite::tungstenite::Message::Ping(vec!(0)))
-          .await
-          .is_err() {
-          warn!("Cannot send ping to client, considering connection closed.");
+      },
+      new_client = next_takeover_client(&mut takeover_receiver).fuse() => {
+        info!("Websocket: Got connection from new client, asking to take over current connection.");
+        // If an earlier client was still waiting, the newest one wins.
+        takeover_client = Some(new_client);
+        if response_sender.send(ButtplugTransportIncomingMessage::TakeoverRequested).await.is_err() {
+          warn!("Connector that owns transport no longer available, exiting.");
           return;
         }
       },
+      _ = takeover_notifier.notified().fuse() => complete_takeover = true,
       serialized_msg = request_receiver.recv().fuse() => {
         if let Some(serialized_msg) = serialized_msg {
           match serialized_msg {
@@ -138,6 +409,7 @@ async fn run_connection_loop<S>(
       }
       websocket_server_msg = websocket_server_receiver.next().fuse() => match websocket_server_msg {
         Some(ws_data) => {
+          heartbeat.heard();
           match ws_data {
             Ok(msg) => {
               match msg {
@@ -171,7 +443,6 @@ async fn run_connection_loop<S>(
                   continue;
                 }
                 async_tungstenite::tungstenite::Message::Pong(_) => {
-                  pong_count += 1;
                   continue;
                 }
                 async_tungstenite::tungstenite::Message::Binary(_) => {
@@ -192,14 +463,39 @@ async fn run_connection_loop<S>(
         }
       }
     }
+    if complete_takeover {
+      if let Some(new_client) = takeover_client.take() {
+        let (new_sender, new_receiver) = new_client.split();
+        let old_sender = std::mem::replace(&mut websocket_server_sender, new_sender);
+        websocket_server_receiver = new_receiver;
+        close_for_takeover(old_sender, &mut request_receiver).await;
+        heartbeat = HeartbeatTimer::new(heartbeat_config);
+        info!("Websocket: New client has taken over connection.");
+      } else {
+        warn!("Websocket: Takeover completed with no new client waiting, ignoring.");
+      }
+    }
   }
 }
 
 /// Websocket connector for ButtplugClients, using [async_tungstenite]
+///
+/// A transport serves one client. Built with [ButtplugWebsocketServerTransportBuilder::finish], it
+/// listens for that client when connected, and what happens when another client connects is set by
+/// the [ButtplugTakeoverPolicy]. To serve several clients at once, get a transport for each from a
+/// [ButtplugWebsocketServerListener] instead.
 pub struct ButtplugWebsocketServerTransport {
   port: u16,
   listen_on_all_interfaces: bool,
+  heartbeat: Option<ButtplugWebsocketHeartbeat>,
+  takeover_policy: ButtplugTakeoverPolicy,
+  #[cfg(feature = "mdns")]
+  advertised_name: Option<String>,
   disconnect_notifier: Arc<Notify>,
+  takeover_notifier: Arc<Notify>,
+  /// Client already accepted by a [ButtplugWebsocketServerListener], served on connect instead of
+  /// listening for one.
+  accepted_client: Mutex<Option<ServerWebsocketStream>>,
 }
 
 impl ButtplugConnectorTransport for ButtplugWebsocketServerTransport {
@@ -209,27 +505,37 @@ impl ButtplugConnectorTransport for ButtplugWebsocketServerTransport {
     incoming_sender: Sender<ButtplugTransportIncomingMessage>,
   ) -> BoxFuture<'static, Result<(), ButtplugConnectorError>> {
     let disconnect_notifier = self.disconnect_notifier.clone();
+    let accepted_client = self
+      .accepted_client
+      .lock()
+      .expect("Accepted client lock should never be poisoned.")
+      .take();
+    if let Some(ws_stream) = accepted_client {
+      async_manager::spawn(run_connection_loop(
+        ws_stream,
+        outgoing_receiver,
+        incoming_sender,
+        disconnect_notifier,
+        self.heartbeat,
+        None,
+        self.takeover_notifier.clone(),
+      ));
+      return future::ready(Ok(())).boxed();
+    }
 
-    let base_addr = if self.listen_on_all_interfaces {
-      "0.0.0.0"
-    } else {
-      "127.0.0.1"
-    };
-
-    let addr = format!("{}:{}", base_addr, self.port);
-    debug!("Websocket: Trying to listen on {}", addr);
+    let port = self.port;
+    let listen_on_all_interfaces = self.listen_on_all_interfaces;
     let response_sender_clone = incoming_sender;
     let disconnect_notifier_clone = disconnect_notifier;
+    let heartbeat = self.heartbeat;
+    let takeover_policy = self.takeover_policy;
+    let takeover_notifier = self.takeover_notifier.clone();
+    #[cfg(feature = "mdns")]
+    let advertised_name = self.advertised_name.clone();
     let fut = async move {
-      // Create the event loop and TCP listener we'll accept connections on.
-      let try_socket = TcpListener::bind(&addr).await;
-      debug!("Websocket: Socket bound.");
-      let listener = try_socket.map_err(|e| {
-        ButtplugConnectorError::TransportSpecificError(
-          ButtplugConnectorTransportSpecificError::GenericNetworkError(format!("{:?}", e)),
-        )
-      })?;
-      debug!("Websocket: Listening on: {}", addr);
+      let listener = bind_listener(port, listen_on_all_interfaces).await?;
+      #[cfg(feature = "mdns")]
+      let advertisement = start_advertisement(advertised_name, port, listen_on_all_interfaces);
       if let Ok((stream, _)) = listener.accept().await {
         info!("Websocket: Got connection");
         let ws_fut = async_tungstenite::tokio::accept_async(stream);
@@ -239,12 +545,27 @@ impl ButtplugConnectorTransport for ButtplugWebsocketServerTransport {
             ButtplugConnectorTransportSpecificError::TungsteniteError(err),
           )
         })?;
+        // Only a single client is served at a time, so unless new clients can take over, stop
+        // listening.
+        let takeover_listener = if takeover_policy == ButtplugTakeoverPolicy::Takeover {
+          Some(listener)
+        } else {
+          None
+        };
+        // Keep advertising for as long as new clients can still connect.
+        #[cfg(feature = "mdns")]
+        let advertisement = takeover_listener.as_ref().and(advertisement);
         async_manager::spawn(async move {
+          #[cfg(feature = "mdns")]
+          let _advertisement = advertisement;
           run_connection_loop(
             ws_stream,
             outgoing_receiver,
             response_sender_clone,
             disconnect_notifier_clone,
+            heartbeat,
+            takeover_listener,
+            takeover_notifier,
           )
           .await;
         });
@@ -267,4 +588,9 @@ impl ButtplugConnectorTransport for ButtplugWebsocketServerTransport {
     }
     .boxed()
   }
+
+  fn complete_takeover(&self) -> ButtplugConnectorResultFuture {
+    self.takeover_notifier.notify_one();
+    future::ready(Ok(())).boxed()
+  }
 }
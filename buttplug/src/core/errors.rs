@@ -17,11 +17,11 @@ use super::message::{
   ErrorCode,
   SensorType,
 };
-#[cfg(feature = "server")]
-use crate::server::device::hardware::communication::HardwareSpecificError;
 use displaydoc::Display;
 use futures::future::BoxFuture;
+use getset::{CopyGetters, Getters};
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use thiserror::Error;
 
 pub type ButtplugResult<T = ()> = Result<T, ButtplugError>;
@@ -52,6 +52,10 @@ pub enum ButtplugHandshakeError {
   MessageSpecVersionMismatch(ButtplugMessageSpecVersion, ButtplugMessageSpecVersion),
   /// Untyped Deserialized Error: {0}
   UntypedDeserializedError(String),
+  /// Another client connected to the server and took over this connection.
+  ConnectionTakenOver,
+  /// Server does not grant client capability {0}
+  CapabilityNotAvailable(String),
 }
 
 /// Message errors occur when a message is somehow malformed on creation, or
@@ -85,6 +89,10 @@ pub enum ButtplugMessageError {
   MessageSerializationError(#[from] ButtplugSerializerError),
   /// Untyped Deserialized Error: {0}
   UntypedDeserializedError(String),
+  /// Connection quota exceeded: {0}
+  QuotaExceeded(String),
+  /// Message not allowed by the capabilities the client declared: {0}
+  ClientCapabilityError(String),
 }
 
 /// Ping errors occur when a server requires a ping response (set up during
@@ -128,6 +136,8 @@ where
 pub enum ButtplugDeviceError {
   /// Device {0} not connected
   DeviceNotConnected(String),
+  /// Device {0} disconnected before command could be completed
+  DeviceDisconnected(String),
   /// Device does not support message type {0}.
   MessageNotSupported(ButtplugDeviceMessageType),
   /// Device only has {0} features, but {1} commands were sent.
@@ -144,11 +154,6 @@ pub enum ButtplugDeviceError {
   InvalidEndpoint(Endpoint),
   /// Device does not handle command type: {0}
   UnhandledCommand(String),
-  #[cfg(feature = "server")]
-  #[error(transparent)]
-  /// Device type specific error: {0}.
-  DeviceSpecificError(#[from] HardwareSpecificError),
-  #[cfg(not(feature = "server"))]
   /// Device type specific error: {0}.
   DeviceSpecificError(String),
   /// No device available at index {0}
@@ -179,6 +184,49 @@ pub enum ButtplugDeviceError {
   DeviceSensorTypeMismatch(u32, SensorType, SensorType),
   /// Protocol does not have an implementation available for Sensor Type {0}
   ProtocolSensorNotSupported(SensorType),
+  /// Devices failed to stop: {0}
+  DevicesFailedToStop(DeviceStopFailures),
+  /// Device command timed out after {0}ms
+  DeviceCommandTimeout(u32),
+  /// Command script error: {0}
+  CommandScriptError(String),
+  /// Device {0} dropped {1} readings, as they were sent faster than they could be delivered
+  DeviceNotificationsDropped(u32, u32),
+}
+
+/// A device that was still failing to stop after a StopAllDevices call, and why.
+#[derive(Debug, Clone, PartialEq, Eq, Getters, CopyGetters)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct DeviceStopFailure {
+  #[getset(get_copy = "pub")]
+  device_index: u32,
+  #[getset(get = "pub")]
+  reason: String,
+}
+
+impl DeviceStopFailure {
+  pub fn new(device_index: u32, reason: &str) -> Self {
+    Self {
+      device_index,
+      reason: reason.to_owned(),
+    }
+  }
+}
+
+/// All devices that failed to stop during a StopAllDevices call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct DeviceStopFailures(pub Vec<DeviceStopFailure>);
+
+impl fmt::Display for DeviceStopFailures {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let failures: Vec<String> = self
+      .0
+      .iter()
+      .map(|failure| format!("device {} ({})", failure.device_index, failure.reason))
+      .collect();
+    write!(f, "{}", failures.join(", "))
+  }
 }
 
 /// Unknown errors occur in exceptional circumstances where no other error type
@@ -42,3 +42,19 @@ impl ButtplugMessageValidator for BatteryLevelReading {
     )
   }
 }
+
+#[cfg(test)]
+mod test {
+  use super::{BatteryLevelReading, ButtplugMessage, ButtplugMessageValidator};
+
+  #[test]
+  pub fn test_battery_level_reading_validity() {
+    assert!(BatteryLevelReading::new(0, 0.0).is_valid().is_ok());
+    assert!(BatteryLevelReading::new(0, 1.0).is_valid().is_ok());
+    assert!(BatteryLevelReading::new(0, 1.1).is_valid().is_err());
+    assert!(BatteryLevelReading::new(0, -0.1).is_valid().is_err());
+    let mut msg = BatteryLevelReading::new(0, 0.5);
+    msg.set_id(0);
+    assert!(msg.is_valid().is_err());
+  }
+}
@@ -13,7 +13,7 @@ use getset::{Getters, MutGetters, Setters};
 use serde::{ser::SerializeSeq, Deserialize, Serialize, Serializer};
 use std::ops::RangeInclusive;
 
-#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum ActuatorType {
   Unknown,
   Vibrate,
@@ -25,20 +25,67 @@ pub enum ActuatorType {
   // For instances where we specify a position to move to ASAP. Usually servos, probably for the
   // OSR-2/SR-6.
   Position,
+  // Angular position of a rotating axis, for rotators that can seek to an angle instead of spinning
+  // continuously (i.e. the twist axis of an OSR-2/SR-6). Only valid as a LinearCmd actuator, where
+  // the position is the angle across the range of the axis.
+  RotatePosition,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Display)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Display)]
 pub enum SensorType {
   Unknown,
   Battery,
   RSSI,
   Button,
   Pressure,
+  // Position of a linear axis across its range. Devices without position feedback report a position
+  // estimated from the commands they've been sent.
+  Position,
   // Temperature,
   // Accelerometer,
   // Gyro,
 }
 
+/// Where a feature sits on its physical part of a device.
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum FeaturePosition {
+  // Inside the body when the device is in use.
+  Internal,
+  // Outside the body when the device is in use.
+  External,
+  // End of a shaft or arm.
+  Tip,
+  // Where a shaft or arm meets the rest of the device.
+  Base,
+}
+
+/// Which physical part of a device a feature belongs to, e.g. the insertable shaft or the external
+/// arm of a rabbit style toy. Features with the same part move together, so apps can address them
+/// as a group.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Getters)]
+#[getset(get = "pub")]
+pub struct FeaturePlacement {
+  #[serde(rename = "Part")]
+  part: String,
+  #[serde(rename = "Position")]
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  position: Option<FeaturePosition>,
+}
+
+impl FeaturePlacement {
+  pub fn new(part: &str, position: Option<FeaturePosition>) -> Self {
+    Self {
+      part: part.to_owned(),
+      position,
+    }
+  }
+
+  /// True if this placement is on `part`. Part names are compared without case.
+  pub fn is_part(&self, part: &str) -> bool {
+    self.part.eq_ignore_ascii_case(part)
+  }
+}
+
 // This will look almost exactly like ServerDeviceMessageAttributes. However, it will only contain
 // information we want the client to know, i.e. step counts versus specific step ranges. This is
 // what will be sent to the client as part of DeviceAdded/DeviceList messages. It should not be used
@@ -249,6 +296,10 @@ pub struct ClientGenericDeviceMessageAttributes {
   #[serde(rename = "StepCount")]
   #[getset(get = "pub")]
   step_count: u32,
+  #[getset(get = "pub", set = "pub")]
+  #[serde(rename = "Placement")]
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  placement: Option<FeaturePlacement>,
   // TODO This needs to actually be part of the device info relayed to the client in spec v4.
   #[getset(get = "pub")]
   #[serde(skip, default)]
@@ -261,6 +312,7 @@ impl ClientGenericDeviceMessageAttributes {
       feature_descriptor: feature_descriptor.to_owned(),
       actuator_type,
       step_count,
+      placement: None,
       index: 0,
     }
   }
@@ -312,19 +364,31 @@ pub struct SensorDeviceMessageAttributes {
   #[getset(get = "pub")]
   #[serde(rename = "SensorRange", serialize_with = "range_sequence_serialize")]
   sensor_range: Vec<RangeInclusive<u32>>,
+  #[getset(get = "pub", set = "pub")]
+  #[serde(rename = "Placement")]
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  placement: Option<FeaturePlacement>,
   // TODO This needs to actually be part of the device info relayed to the client in spec v4.
   #[getset(get = "pub")]
   #[serde(skip, default)]
   index: u32,
 }
 
-/*
 impl SensorDeviceMessageAttributes {
-  pub fn new(feature_descriptor: &str, sensor_type: SensorType) -> Self {
-    Self { feature_descriptor: feature_descriptor.to_owned(), sensor_type }
+  pub fn new(
+    feature_descriptor: &str,
+    sensor_type: SensorType,
+    sensor_range: Vec<RangeInclusive<u32>>,
+  ) -> Self {
+    Self {
+      feature_descriptor: feature_descriptor.to_owned(),
+      sensor_type,
+      sensor_range,
+      placement: None,
+      index: 0,
+    }
   }
 }
- */
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Getters, Setters)]
 pub struct ClientDeviceMessageAttributesV2 {
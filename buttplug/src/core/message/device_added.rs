@@ -8,13 +8,13 @@
 use super::device_message_info::{DeviceMessageInfoV0, DeviceMessageInfoV1, DeviceMessageInfoV2};
 use super::*;
 
-use getset::{CopyGetters, Getters};
+use getset::{CopyGetters, Getters, Setters};
 
 #[cfg(feature = "serialize-json")]
 use serde::{Deserialize, Serialize};
 
 /// Notification that a device has been found and connected to the server.
-#[derive(ButtplugMessage, Clone, Debug, PartialEq, Eq, Getters, CopyGetters)]
+#[derive(ButtplugMessage, Clone, Debug, PartialEq, Eq, Getters, CopyGetters, Setters)]
 #[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
 pub struct DeviceAdded {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
@@ -33,6 +33,17 @@ pub struct DeviceAdded {
   )]
   #[getset(get = "pub")]
   device_display_name: Option<String>,
+  /// Description of the device, in the language the client asked for if the device config has one.
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(
+      rename = "DeviceDescription",
+      default,
+      skip_serializing_if = "Option::is_none"
+    )
+  )]
+  #[getset(get = "pub", set = "pub")]
+  device_description: Option<String>,
   #[cfg_attr(
     feature = "serialize-json",
     serde(
@@ -60,6 +71,7 @@ impl DeviceAdded {
       device_index,
       device_name: device_name.to_string(),
       device_display_name: device_display_name.clone(),
+      device_description: None,
       device_message_timing_gap: *device_message_timing_gap,
       device_messages: device_messages.clone(),
     };
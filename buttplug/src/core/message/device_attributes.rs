@@ -0,0 +1,49 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::*;
+use getset::Getters;
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+
+/// Attributes of a single device, laid out like a [DeviceList] entry.
+///
+/// Sent in reply to [RequestDeviceAttributes], and as an event (with the system id of 0) whenever
+/// the server changes the attributes of a connected device, i.e. when its display name or step
+/// limits are modified.
+#[derive(Debug, ButtplugMessage, Clone, PartialEq, Eq, Getters)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct DeviceAttributes {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  id: u32,
+  #[cfg_attr(feature = "serialize-json", serde(flatten))]
+  #[getset(get = "pub")]
+  device: DeviceMessageInfo,
+}
+
+impl DeviceAttributes {
+  pub fn new(device: DeviceMessageInfo) -> Self {
+    let mut obj = Self { id: 0, device };
+    obj.finalize();
+    obj
+  }
+}
+
+impl ButtplugMessageValidator for DeviceAttributes {
+}
+
+impl ButtplugMessageFinalizer for DeviceAttributes {
+  fn finalize(&mut self) {
+    self.device.device_messages_mut().finalize();
+  }
+}
+
+impl From<DeviceAttributes> for DeviceMessageInfo {
+  fn from(msg: DeviceAttributes) -> Self {
+    msg.device
+  }
+}
@@ -7,12 +7,12 @@
 
 use super::device_message_info::{DeviceMessageInfoV0, DeviceMessageInfoV1, DeviceMessageInfoV2};
 use super::*;
-use getset::Getters;
+use getset::{CopyGetters, Getters};
 #[cfg(feature = "serialize-json")]
 use serde::{Deserialize, Serialize};
 
 /// List of all devices currently connected to the server.
-#[derive(Default, Clone, Debug, PartialEq, Eq, ButtplugMessage, Getters)]
+#[derive(Default, Clone, Debug, PartialEq, Eq, ButtplugMessage, Getters, CopyGetters)]
 #[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
 pub struct DeviceList {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
@@ -20,11 +20,33 @@ pub struct DeviceList {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Devices"))]
   #[getset(get = "pub")]
   devices: Vec<DeviceMessageInfo>,
+  /// If this list is a page of a larger device list, the device index to request the next page
+  /// from. Only set in replies to paged [RequestDeviceList] messages.
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(rename = "NextDeviceIndex", default, skip_serializing_if = "Option::is_none")
+  )]
+  #[getset(get_copy = "pub")]
+  next_device_index: Option<u32>,
 }
 
 impl DeviceList {
   pub fn new(devices: Vec<DeviceMessageInfo>) -> Self {
-    Self { id: 1, devices }
+    Self {
+      id: 1,
+      devices,
+      next_device_index: None,
+    }
+  }
+
+  /// Creates a single page of a device list, with the index the following page starts at (if
+  /// there is one).
+  pub fn new_page(devices: Vec<DeviceMessageInfo>, next_device_index: Option<u32>) -> Self {
+    Self {
+      id: 1,
+      devices,
+      next_device_index,
+    }
   }
 }
 
@@ -6,12 +6,12 @@
 // for full license information.
 
 use super::*;
-use getset::{CopyGetters, Getters, MutGetters};
+use getset::{CopyGetters, Getters, MutGetters, Setters};
 #[cfg(feature = "serialize-json")]
 use serde::{Deserialize, Serialize};
 
 /// Substructure of device messages, used for attribute information (name, messages supported, etc...)
-#[derive(Clone, Debug, PartialEq, Eq, MutGetters, Getters, CopyGetters)]
+#[derive(Clone, Debug, PartialEq, Eq, MutGetters, Getters, CopyGetters, Setters)]
 #[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
 pub struct DeviceMessageInfo {
   #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceIndex"))]
@@ -26,6 +26,17 @@ pub struct DeviceMessageInfo {
   )]
   #[getset(get = "pub")]
   device_display_name: Option<String>,
+  /// Description of the device, in the language the client asked for if the device config has one.
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(
+      rename = "DeviceDescription",
+      default,
+      skip_serializing_if = "Option::is_none"
+    )
+  )]
+  #[getset(get = "pub", set = "pub")]
+  device_description: Option<String>,
   #[cfg_attr(
     feature = "serialize-json",
     serde(
@@ -52,6 +63,7 @@ impl DeviceMessageInfo {
       device_index,
       device_name: device_name.to_owned(),
       device_display_name: device_display_name.clone(),
+      device_description: None,
       device_message_timing_gap: *device_message_timing_gap,
       device_messages,
     }
@@ -64,6 +76,7 @@ impl From<DeviceAdded> for DeviceMessageInfo {
       device_index: device_added.device_index(),
       device_name: device_added.device_name().clone(),
       device_display_name: device_added.device_display_name().clone(),
+      device_description: device_added.device_description().clone(),
       device_message_timing_gap: *device_added.device_message_timing_gap(),
       device_messages: device_added.device_messages().clone(),
     }
@@ -83,21 +83,122 @@ impl Error {
   }
 
   pub fn original_error(&self) -> ButtplugError {
+    self
+      .typed_error()
+      .unwrap_or_else(|| ButtplugError::from(self.clone()))
+  }
+
+  /// Returns the [ButtplugError] this message was built from, if it can be recovered either from
+  /// the stored error or by deserializing the error message field.
+  fn typed_error(&self) -> Option<ButtplugError> {
     if self.original_error.is_some() {
-      self
-        .original_error
-        .clone()
-        .expect("Already checked that it's valid.")
-    } else {
-      // Try deserializing what's in the error_message field
-      #[cfg(feature = "serialize-json")]
-      {
-        if let Ok(deserialized_msg) = serde_json::from_str(&self.error_message) {
-          return deserialized_msg;
-        }
+      return self.original_error.clone();
+    }
+    // Try deserializing what's in the error_message field
+    #[cfg(feature = "serialize-json")]
+    {
+      if let Ok(deserialized_msg) = serde_json::from_str(&self.error_message) {
+        return Some(deserialized_msg);
       }
-      ButtplugError::from(self.clone())
     }
+    None
+  }
+}
+
+type LegacyErrorMatcher = fn(&ButtplugError) -> bool;
+
+/// Error code remapping for spec v0/v1 clients. Those spec versions predate the current error
+/// categories, and used ErrorMessage for anything caused by bad message contents or permissions,
+/// and ErrorDevice for anything to do with device management. Errors that don't match an entry
+/// here keep their current code.
+const LEGACY_ERROR_CODE_MAP: &[(LegacyErrorMatcher, ErrorCode)] = &[
+  (
+    |e| {
+      matches!(
+        e,
+        ButtplugError::ButtplugDeviceError(ButtplugDeviceError::DeviceFeatureCountMismatch(..))
+      )
+    },
+    ErrorCode::ErrorMessage,
+  ),
+  (
+    |e| {
+      matches!(
+        e,
+        ButtplugError::ButtplugDeviceError(ButtplugDeviceError::DeviceFeatureIndexError(..))
+      )
+    },
+    ErrorCode::ErrorMessage,
+  ),
+  (
+    |e| {
+      matches!(
+        e,
+        ButtplugError::ButtplugDeviceError(ButtplugDeviceError::DeviceSensorIndexError(..))
+      )
+    },
+    ErrorCode::ErrorMessage,
+  ),
+  (
+    |e| {
+      matches!(
+        e,
+        ButtplugError::ButtplugDeviceError(ButtplugDeviceError::DeviceActuatorTypeMismatch(..))
+      )
+    },
+    ErrorCode::ErrorMessage,
+  ),
+  (
+    |e| {
+      matches!(
+        e,
+        ButtplugError::ButtplugDeviceError(ButtplugDeviceError::DeviceSensorTypeMismatch(..))
+      )
+    },
+    ErrorCode::ErrorMessage,
+  ),
+  (
+    |e| {
+      matches!(
+        e,
+        ButtplugError::ButtplugDeviceError(ButtplugDeviceError::DevicePermissionError(..))
+      )
+    },
+    ErrorCode::ErrorMessage,
+  ),
+  (
+    |e| {
+      matches!(
+        e,
+        ButtplugError::ButtplugUnknownError(ButtplugUnknownError::NoDeviceCommManagers)
+      )
+    },
+    ErrorCode::ErrorDevice,
+  ),
+  (
+    |e| {
+      matches!(
+        e,
+        ButtplugError::ButtplugUnknownError(ButtplugUnknownError::DeviceManagerNotRunning)
+      )
+    },
+    ErrorCode::ErrorDevice,
+  ),
+];
+
+/// Finds the closest legacy error code and a human readable message for an [Error], for clients
+/// that don't know how to parse serialized [ButtplugError] messages.
+fn legacy_error_parts(error: &Error) -> (ErrorCode, String) {
+  match error.typed_error() {
+    Some(typed_error) => {
+      let code = LEGACY_ERROR_CODE_MAP
+        .iter()
+        .find(|(matcher, _)| matcher(&typed_error))
+        .map(|(_, code)| *code)
+        .unwrap_or(error.error_code);
+      (code, typed_error.to_string())
+    }
+    None => (error.error_code, error.error_message.clone()),
   }
 }
 
@@ -159,7 +260,8 @@ impl ErrorV0 {
 
 impl From<Error> for ErrorV0 {
   fn from(error: Error) -> Self {
-    let mut err = ErrorV0::new(error.error_code, &error.error_message);
+    let (error_code, error_message) = legacy_error_parts(&error);
+    let mut err = ErrorV0::new(error_code, &error_message);
     err.set_id(error.id());
     err
   }
@@ -168,7 +270,10 @@ impl From<Error> for ErrorV0 {
 #[cfg(feature = "serialize-json")]
 #[cfg(test)]
 mod test {
-  use crate::core::message::{ButtplugCurrentSpecServerMessage, Error, ErrorCode};
+  use crate::core::{
+    errors::{ButtplugDeviceError, ButtplugError, ButtplugPingError, ButtplugUnknownError},
+    message::{ButtplugCurrentSpecServerMessage, ButtplugMessage, Error, ErrorCode, ErrorV0},
+  };
 
   const ERROR_STR: &str = "{\"Error\":{\"Id\":0,\"ErrorCode\":1,\"ErrorMessage\":\"Test Error\"}}";
 
@@ -196,4 +301,56 @@ mod test {
       union
     );
   }
+
+  #[test]
+  fn test_error_v0_downgrade() {
+    let cases: Vec<(ButtplugError, ErrorCode)> = vec![
+      (ButtplugPingError::PingedOut.into(), ErrorCode::ErrorPing),
+      (
+        ButtplugDeviceError::DeviceNotAvailable(3).into(),
+        ErrorCode::ErrorDevice,
+      ),
+      (
+        ButtplugDeviceError::DeviceFeatureIndexError(1, 2).into(),
+        ErrorCode::ErrorMessage,
+      ),
+      (
+        ButtplugDeviceError::DevicePermissionError("Denied".to_owned()).into(),
+        ErrorCode::ErrorMessage,
+      ),
+      (
+        ButtplugUnknownError::NoDeviceCommManagers.into(),
+        ErrorCode::ErrorDevice,
+      ),
+    ];
+    for (error, legacy_code) in cases {
+      let mut modern = Error::from(error.clone());
+      modern.set_id(5);
+      let legacy = ErrorV0::from(modern);
+      assert_eq!(legacy, {
+        let mut expected = ErrorV0::new(legacy_code, &error.to_string());
+        expected.set_id(5);
+        expected
+      });
+    }
+  }
+
+  #[test]
+  fn test_error_v0_downgrade_from_serialized_message() {
+    let serialized =
+      serde_json::to_string(&ButtplugError::from(ButtplugUnknownError::DeviceManagerNotRunning))
+        .expect("Infallible serialization.");
+    let legacy = ErrorV0::from(Error::new(ErrorCode::ErrorUnknown, &serialized, None));
+    assert_eq!(legacy.error_code(), ErrorCode::ErrorDevice);
+    assert_eq!(
+      *legacy.error_message(),
+      ButtplugUnknownError::DeviceManagerNotRunning.to_string()
+    );
+  }
+
+  #[test]
+  fn test_error_v0_downgrade_untyped_message() {
+    let legacy = ErrorV0::from(Error::new(ErrorCode::ErrorHandshake, "Test Error", None));
+    assert_eq!(legacy, ErrorV0::new(ErrorCode::ErrorHandshake, "Test Error"));
+  }
 }
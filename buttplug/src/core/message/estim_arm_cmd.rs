@@ -0,0 +1,47 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::*;
+use getset::CopyGetters;
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+
+/// Server extension arming or disarming an e-stim device. E-stim devices refuse output until they
+/// are armed, and ramp up from nothing once they are. Disarming stops the device.
+///
+/// The server also disarms e-stim devices whenever it stops all devices, including when its client
+/// disconnects or misses a ping, so clients have to arm devices again after reconnecting. Only
+/// devices with an e-stim interlock in their config accept this.
+#[derive(
+  Debug, ButtplugDeviceMessage, ButtplugMessageFinalizer, PartialEq, Eq, Clone, CopyGetters,
+)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct EstimArmCmd {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  id: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceIndex"))]
+  device_index: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Armed"))]
+  #[getset(get_copy = "pub")]
+  armed: bool,
+}
+
+impl EstimArmCmd {
+  pub fn new(device_index: u32, armed: bool) -> Self {
+    Self {
+      id: 1,
+      device_index,
+      armed,
+    }
+  }
+}
+
+impl ButtplugMessageValidator for EstimArmCmd {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_not_system_id(self.id)
+  }
+}
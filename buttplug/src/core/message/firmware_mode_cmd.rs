@@ -0,0 +1,46 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::*;
+use getset::CopyGetters;
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+
+/// Server extension putting a device in or out of firmware mode, for updating its firmware over
+/// raw messages to its Firmware endpoint. While in firmware mode the server holds off keepalives
+/// the device's protocol would otherwise send, so they don't land in the middle of the update.
+///
+/// Only devices with raw messages allowed and a Firmware endpoint in their config accept this.
+#[derive(
+  Debug, ButtplugDeviceMessage, ButtplugMessageFinalizer, PartialEq, Eq, Clone, CopyGetters,
+)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct FirmwareModeCmd {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  id: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceIndex"))]
+  device_index: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Enabled"))]
+  #[getset(get_copy = "pub")]
+  enabled: bool,
+}
+
+impl FirmwareModeCmd {
+  pub fn new(device_index: u32, enabled: bool) -> Self {
+    Self {
+      id: 1,
+      device_index,
+      enabled,
+    }
+  }
+}
+
+impl ButtplugMessageValidator for FirmwareModeCmd {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_not_system_id(self.id)
+  }
+}
@@ -70,3 +70,31 @@ impl ButtplugMessageValidator for LinearCmd {
     Ok(())
   }
 }
+
+#[cfg(test)]
+mod test {
+  use super::{ButtplugMessage, ButtplugMessageValidator, LinearCmd, VectorSubcommand};
+
+  #[test]
+  pub fn test_linear_cmd_validity() {
+    let valid = LinearCmd::new(
+      0,
+      vec![
+        VectorSubcommand::new(0, 500, 0.0),
+        VectorSubcommand::new(1, 500, 1.0),
+      ],
+    );
+    assert!(valid.is_valid().is_ok());
+    let out_of_range = LinearCmd::new(
+      0,
+      vec![
+        VectorSubcommand::new(0, 500, 0.5),
+        VectorSubcommand::new(1, 500, 1.5),
+      ],
+    );
+    assert!(out_of_range.is_valid().is_err());
+    let mut system_id = valid;
+    system_id.set_id(0);
+    assert!(system_id.is_valid().is_err());
+  }
+}
@@ -17,11 +17,15 @@ mod battery_level_cmd;
 mod battery_level_reading;
 mod client_device_message_attributes;
 mod device_added;
+mod device_attributes;
 mod device_list;
 mod device_message_info;
 mod device_removed;
+mod disconnect_all_devices;
 mod endpoint;
 mod error;
+mod estim_arm_cmd;
+mod firmware_mode_cmd;
 mod fleshlight_launch_fw12_cmd;
 mod kiiroo_cmd;
 mod linear_cmd;
@@ -29,12 +33,15 @@ mod log;
 mod log_level;
 mod lovense_cmd;
 mod ok;
+mod operation_progress;
+mod pattern_cmd;
 mod ping;
 mod raw_read_cmd;
 mod raw_reading;
 mod raw_subscribe_cmd;
 mod raw_unsubscribe_cmd;
 mod raw_write_cmd;
+mod request_device_attributes;
 mod request_device_list;
 mod request_log;
 mod request_server_info;
@@ -51,6 +58,7 @@ pub mod serializer;
 mod server_info;
 mod single_motor_vibrate_cmd;
 mod start_scanning;
+mod start_targeted_scanning;
 mod stop_all_devices;
 mod stop_device_cmd;
 mod stop_scanning;
@@ -68,12 +76,15 @@ pub use client_device_message_attributes::{
   ClientDeviceMessageAttributesV1,
   ClientDeviceMessageAttributesV2,
   ClientGenericDeviceMessageAttributes,
+  FeaturePlacement,
+  FeaturePosition,
   NullDeviceMessageAttributes,
   RawDeviceMessageAttributes,
   SensorDeviceMessageAttributes,
   SensorType,
 };
 pub use device_added::{DeviceAdded, DeviceAddedV0, DeviceAddedV1, DeviceAddedV2};
+pub use device_attributes::DeviceAttributes;
 pub use device_list::{DeviceList, DeviceListV0, DeviceListV1, DeviceListV2};
 pub use device_message_info::{
   DeviceMessageInfo,
@@ -82,23 +93,29 @@ pub use device_message_info::{
   DeviceMessageInfoV2,
 };
 pub use device_removed::DeviceRemoved;
+pub use disconnect_all_devices::DisconnectAllDevices;
 pub use endpoint::Endpoint;
 pub use error::{Error, ErrorCode, ErrorV0};
+pub use estim_arm_cmd::EstimArmCmd;
+pub use firmware_mode_cmd::FirmwareModeCmd;
 pub use fleshlight_launch_fw12_cmd::FleshlightLaunchFW12Cmd;
 pub use kiiroo_cmd::KiirooCmd;
 pub use linear_cmd::{LinearCmd, VectorSubcommand};
 pub use log_level::LogLevel;
 pub use lovense_cmd::LovenseCmd;
 pub use ok::Ok;
+pub use operation_progress::OperationProgress;
+pub use pattern_cmd::{PatternCmd, PatternKeyframe};
 pub use ping::Ping;
 pub use raw_read_cmd::RawReadCmd;
 pub use raw_reading::RawReading;
 pub use raw_subscribe_cmd::RawSubscribeCmd;
 pub use raw_unsubscribe_cmd::RawUnsubscribeCmd;
 pub use raw_write_cmd::RawWriteCmd;
+pub use request_device_attributes::RequestDeviceAttributes;
 pub use request_device_list::RequestDeviceList;
 pub use request_log::RequestLog;
-pub use request_server_info::RequestServerInfo;
+pub use request_server_info::{ClientCapability, RequestServerInfo};
 pub use rotate_cmd::{RotateCmd, RotationSubcommand};
 pub use rssi_level_cmd::RSSILevelCmd;
 pub use rssi_level_reading::RSSILevelReading;
@@ -111,6 +128,7 @@ pub use sensor_unsubscribe_cmd::SensorUnsubscribeCmd;
 pub use server_info::{ServerInfo, ServerInfoV0};
 pub use single_motor_vibrate_cmd::SingleMotorVibrateCmd;
 pub use start_scanning::StartScanning;
+pub use start_targeted_scanning::StartTargetedScanning;
 pub use stop_all_devices::StopAllDevices;
 pub use stop_device_cmd::StopDeviceCmd;
 pub use stop_scanning::StopScanning;
@@ -276,10 +294,13 @@ pub enum ButtplugClientMessage {
   RequestServerInfo(RequestServerInfo),
   // Device enumeration messages
   StartScanning(StartScanning),
+  StartTargetedScanning(StartTargetedScanning),
   StopScanning(StopScanning),
   RequestDeviceList(RequestDeviceList),
+  RequestDeviceAttributes(RequestDeviceAttributes),
   // Generic commands
   StopAllDevices(StopAllDevices),
+  DisconnectAllDevices(DisconnectAllDevices),
   VibrateCmd(VibrateCmd),
   LinearCmd(LinearCmd),
   RotateCmd(RotateCmd),
@@ -288,7 +309,10 @@ pub enum ButtplugClientMessage {
   StopDeviceCmd(StopDeviceCmd),
   RawSubscribeCmd(RawSubscribeCmd),
   RawUnsubscribeCmd(RawUnsubscribeCmd),
+  FirmwareModeCmd(FirmwareModeCmd),
+  EstimArmCmd(EstimArmCmd),
   ScalarCmd(ScalarCmd),
+  PatternCmd(PatternCmd),
   // Sensor commands
   BatteryLevelCmd(BatteryLevelCmd),
   RSSILevelCmd(RSSILevelCmd),
@@ -302,6 +326,8 @@ pub enum ButtplugClientMessage {
   LovenseCmd(LovenseCmd),
   KiirooCmd(KiirooCmd),
   VorzeA10CycloneCmd(VorzeA10CycloneCmd),
+  // Deprecated status messages, only reachable from spec v0/v1 connections.
+  Test(Test),
   // To Add:
 }
 
@@ -322,7 +348,6 @@ pub enum ButtplugServerMessage {
   // Status messages
   Ok(Ok),
   Error(Error),
-  Test(Test),
   Log(Log),
   // Handshake messages
   ServerInfo(ServerInfo),
@@ -330,7 +355,10 @@ pub enum ButtplugServerMessage {
   DeviceList(DeviceList),
   DeviceAdded(DeviceAdded),
   DeviceRemoved(DeviceRemoved),
+  DeviceAttributes(DeviceAttributes),
   ScanningFinished(ScanningFinished),
+  // Long-running operations
+  OperationProgress(OperationProgress),
   // Generic commands
   RawReading(RawReading),
   // Sensor Reading Messages
@@ -338,6 +366,7 @@ pub enum ButtplugServerMessage {
   // Deprecated Server Messages
   BatteryLevelReading(BatteryLevelReading),
   RSSILevelReading(RSSILevelReading),
+  Test(Test),
 }
 
 /// Represents all possible messages a [ButtplugServer][crate::server::ButtplugServer] can send to a
@@ -395,10 +424,13 @@ pub enum ButtplugSpecV3ClientMessage {
   Ping(Ping),
   // Device enumeration messages
   StartScanning(StartScanning),
+  StartTargetedScanning(StartTargetedScanning),
   StopScanning(StopScanning),
   RequestDeviceList(RequestDeviceList),
+  RequestDeviceAttributes(RequestDeviceAttributes),
   // Generic commands
   StopAllDevices(StopAllDevices),
+  DisconnectAllDevices(DisconnectAllDevices),
   VibrateCmd(VibrateCmd),
   LinearCmd(LinearCmd),
   RotateCmd(RotateCmd),
@@ -407,7 +439,10 @@ pub enum ButtplugSpecV3ClientMessage {
   StopDeviceCmd(StopDeviceCmd),
   RawSubscribeCmd(RawSubscribeCmd),
   RawUnsubscribeCmd(RawUnsubscribeCmd),
+  FirmwareModeCmd(FirmwareModeCmd),
+  EstimArmCmd(EstimArmCmd),
   ScalarCmd(ScalarCmd),
+  PatternCmd(PatternCmd),
   // Sensor commands
   SensorReadCmd(SensorReadCmd),
   SensorSubscribeCmd(SensorSubscribeCmd),
@@ -436,7 +471,10 @@ pub enum ButtplugSpecV3ServerMessage {
   DeviceList(DeviceList),
   DeviceAdded(DeviceAdded),
   DeviceRemoved(DeviceRemoved),
+  DeviceAttributes(DeviceAttributes),
   ScanningFinished(ScanningFinished),
+  // Long-running operations
+  OperationProgress(OperationProgress),
   // Generic commands
   RawReading(RawReading),
   // Sensor commands
@@ -448,6 +486,7 @@ impl ButtplugMessageFinalizer for ButtplugSpecV3ServerMessage {
     match self {
       ButtplugSpecV3ServerMessage::DeviceAdded(da) => da.finalize(),
       ButtplugSpecV3ServerMessage::DeviceList(dl) => dl.finalize(),
+      ButtplugSpecV3ServerMessage::DeviceAttributes(da) => da.finalize(),
       _ => return,
     }
   }
@@ -582,6 +621,8 @@ pub enum ButtplugSpecV1ClientMessage {
   LovenseCmd(LovenseCmd),
   KiirooCmd(KiirooCmd),
   VorzeA10CycloneCmd(VorzeA10CycloneCmd),
+  // Deprecated status messages
+  Test(Test),
 }
 
 /// Represents all server-to-client messages in v2 of the Buttplug Spec
@@ -601,6 +642,7 @@ pub enum ButtplugSpecV1ServerMessage {
   Ok(Ok),
   Error(ErrorV0),
   Log(Log),
+  Test(Test),
   // Handshake messages
   ServerInfo(ServerInfoV0),
   // Device enumeration messages
@@ -620,6 +662,7 @@ impl TryFrom<ButtplugServerMessage> for ButtplugSpecV1ServerMessage {
       ButtplugServerMessage::Ok(msg) => Ok(ButtplugSpecV1ServerMessage::Ok(msg)),
       ButtplugServerMessage::Error(msg) => Ok(ButtplugSpecV1ServerMessage::Error(msg.into())),
       ButtplugServerMessage::Log(msg) => Ok(ButtplugSpecV1ServerMessage::Log(msg)),
+      ButtplugServerMessage::Test(msg) => Ok(ButtplugSpecV1ServerMessage::Test(msg)),
       ButtplugServerMessage::ServerInfo(msg) => {
         Ok(ButtplugSpecV1ServerMessage::ServerInfo(msg.into()))
       }
@@ -675,6 +718,8 @@ pub enum ButtplugSpecV0ClientMessage {
   LovenseCmd(LovenseCmd),
   KiirooCmd(KiirooCmd),
   VorzeA10CycloneCmd(VorzeA10CycloneCmd),
+  // Deprecated status messages
+  Test(Test),
 }
 
 /// Represents all server-to-client messages in v0 of the Buttplug Spec
@@ -694,6 +739,7 @@ pub enum ButtplugSpecV0ServerMessage {
   Ok(Ok),
   Error(ErrorV0),
   Log(Log),
+  Test(Test),
   // Handshake messages
   ServerInfo(ServerInfoV0),
   // Device enumeration messages
@@ -713,6 +759,7 @@ impl TryFrom<ButtplugServerMessage> for ButtplugSpecV0ServerMessage {
       ButtplugServerMessage::Ok(msg) => Ok(ButtplugSpecV0ServerMessage::Ok(msg)),
       ButtplugServerMessage::Error(msg) => Ok(ButtplugSpecV0ServerMessage::Error(msg.into())),
       ButtplugServerMessage::Log(msg) => Ok(ButtplugSpecV0ServerMessage::Log(msg)),
+      ButtplugServerMessage::Test(msg) => Ok(ButtplugSpecV0ServerMessage::Test(msg)),
       ButtplugServerMessage::ServerInfo(msg) => {
         Ok(ButtplugSpecV0ServerMessage::ServerInfo(msg.into()))
       }
@@ -743,7 +790,6 @@ impl TryFrom<ButtplugServerMessage> for ButtplugSpecV0ServerMessage {
   Debug,
   Clone,
   PartialEq,
-  Eq,
   ButtplugMessage,
   ButtplugMessageValidator,
   ButtplugClientMessageType,
@@ -753,9 +799,14 @@ impl TryFrom<ButtplugServerMessage> for ButtplugSpecV0ServerMessage {
 )]
 pub enum ButtplugDeviceManagerMessageUnion {
   RequestDeviceList(RequestDeviceList),
+  RequestDeviceAttributes(RequestDeviceAttributes),
   StopAllDevices(StopAllDevices),
+  DisconnectAllDevices(DisconnectAllDevices),
   StartScanning(StartScanning),
+  StartTargetedScanning(StartTargetedScanning),
   StopScanning(StopScanning),
+  // Played by the device manager, which sends the keyframes to the device as they come up.
+  PatternCmd(PatternCmd),
 }
 
 /// Represents all possible device command message types.
@@ -785,6 +836,8 @@ pub enum ButtplugDeviceCommandMessageUnion {
   StopDeviceCmd(StopDeviceCmd),
   RawSubscribeCmd(RawSubscribeCmd),
   RawUnsubscribeCmd(RawUnsubscribeCmd),
+  FirmwareModeCmd(FirmwareModeCmd),
+  EstimArmCmd(EstimArmCmd),
   BatteryLevelCmd(BatteryLevelCmd),
   RSSILevelCmd(RSSILevelCmd),
   ScalarCmd(ScalarCmd),
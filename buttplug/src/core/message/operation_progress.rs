@@ -0,0 +1,71 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::*;
+use getset::CopyGetters;
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+
+/// Progress of a long-running operation, sent by the server while it works.
+///
+/// Operations that take a while report progress as events tagged with the id of the message that
+/// started them, then finish by replying to that message as usual (with Ok, a result message, or
+/// an Error). Progress events carry the system id of 0 so they aren't mistaken for the reply.
+#[derive(Debug, ButtplugMessage, ButtplugMessageFinalizer, Clone, PartialEq, CopyGetters)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct OperationProgress {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  id: u32,
+  /// Id of the message that started the operation.
+  #[cfg_attr(feature = "serialize-json", serde(rename = "RequestId"))]
+  #[getset(get_copy = "pub")]
+  request_id: u32,
+  /// How much of the operation is done, from 0.0 to 1.0.
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Progress"))]
+  #[getset(get_copy = "pub")]
+  progress: f64,
+}
+
+impl OperationProgress {
+  pub fn new(request_id: u32, progress: f64) -> Self {
+    Self {
+      id: 0,
+      request_id,
+      progress,
+    }
+  }
+}
+
+impl ButtplugMessageValidator for OperationProgress {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_system_id(self.id)?;
+    if !(0.0..=1.0).contains(&self.progress) {
+      return Err(ButtplugMessageError::InvalidMessageContents(format!(
+        "Progress {} is not between 0.0 and 1.0",
+        self.progress
+      )));
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{ButtplugMessage, ButtplugMessageValidator, OperationProgress};
+
+  #[test]
+  pub fn test_operation_progress_validity() {
+    assert!(OperationProgress::new(1, 0.0).is_valid().is_ok());
+    assert!(OperationProgress::new(1, 1.0).is_valid().is_ok());
+    assert!(OperationProgress::new(1, 1.5).is_valid().is_err());
+    assert!(OperationProgress::new(1, -0.5).is_valid().is_err());
+    // Progress is an event, so it should never carry a reply id.
+    let mut msg = OperationProgress::new(1, 0.5);
+    msg.set_id(1);
+    assert!(msg.is_valid().is_err());
+  }
+}
@@ -0,0 +1,209 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::*;
+use getset::{CopyGetters, Getters};
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+
+/// Outputs to set at a point in a [PatternCmd], using the subcommands of [ScalarCmd], [LinearCmd]
+/// and [RotateCmd].
+#[derive(Debug, Default, PartialEq, Clone, Getters, CopyGetters)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct PatternKeyframe {
+  /// Milliseconds from the start of the pattern.
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Time"))]
+  #[getset(get_copy = "pub")]
+  time: u32,
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(rename = "Scalars", default, skip_serializing_if = "Vec::is_empty")
+  )]
+  #[getset(get = "pub")]
+  scalars: Vec<ScalarSubcommand>,
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(rename = "Vectors", default, skip_serializing_if = "Vec::is_empty")
+  )]
+  #[getset(get = "pub")]
+  vectors: Vec<VectorSubcommand>,
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(rename = "Rotations", default, skip_serializing_if = "Vec::is_empty")
+  )]
+  #[getset(get = "pub")]
+  rotations: Vec<RotationSubcommand>,
+}
+
+impl PatternKeyframe {
+  pub fn new(
+    time: u32,
+    scalars: Vec<ScalarSubcommand>,
+    vectors: Vec<VectorSubcommand>,
+    rotations: Vec<RotationSubcommand>,
+  ) -> Self {
+    Self {
+      time,
+      scalars,
+      vectors,
+      rotations,
+    }
+  }
+}
+
+/// Plays a list of keyframes on a device, so clients don't have to stream every change over the
+/// connection.
+///
+/// The server replies once playback starts. Sending another PatternCmd, or a [StopDeviceCmd] or
+/// [StopAllDevices], ends playback. Other commands sent to the device during playback only last
+/// until the next keyframe.
+#[derive(Debug, Default, ButtplugDeviceMessage, ButtplugMessageFinalizer, PartialEq, Clone)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct PatternCmd {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  id: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceIndex"))]
+  device_index: u32,
+  /// Keyframes in the order they're played, so times can't go backwards.
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Keyframes"))]
+  keyframes: Vec<PatternKeyframe>,
+  /// If true, the pattern starts over once the time of the last keyframe has passed.
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Loop", default))]
+  looped: bool,
+}
+
+impl PatternCmd {
+  pub fn new(device_index: u32, keyframes: Vec<PatternKeyframe>, looped: bool) -> Self {
+    Self {
+      id: 1,
+      device_index,
+      keyframes,
+      looped,
+    }
+  }
+
+  pub fn keyframes(&self) -> &Vec<PatternKeyframe> {
+    &self.keyframes
+  }
+
+  pub fn looped(&self) -> bool {
+    self.looped
+  }
+}
+
+impl ButtplugMessageValidator for PatternCmd {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_not_system_id(self.id)?;
+    if self.keyframes.is_empty() {
+      return Err(ButtplugMessageError::InvalidMessageContents(
+        "PatternCmd should have at least one keyframe.".to_owned(),
+      ));
+    }
+    let mut last_time = 0;
+    for keyframe in &self.keyframes {
+      if keyframe.time < last_time {
+        return Err(ButtplugMessageError::InvalidMessageContents(format!(
+          "PatternCmd keyframe at {}ms comes after one at {}ms, keyframes should be in time order.",
+          keyframe.time, last_time
+        )));
+      }
+      last_time = keyframe.time;
+      if keyframe.scalars.is_empty() && keyframe.vectors.is_empty() && keyframe.rotations.is_empty()
+      {
+        return Err(ButtplugMessageError::InvalidMessageContents(format!(
+          "PatternCmd keyframe at {}ms has no subcommands.",
+          keyframe.time
+        )));
+      }
+      for scalar in &keyframe.scalars {
+        self.is_in_command_range(
+          scalar.scalar(),
+          format!(
+            "Level {} for PatternCmd index {} at {}ms is invalid, should be between 0.0 and 1.0",
+            scalar.scalar(),
+            scalar.index(),
+            keyframe.time
+          ),
+        )?;
+      }
+      for vector in &keyframe.vectors {
+        self.is_in_command_range(
+          vector.position(),
+          format!(
+            "Position {} for PatternCmd index {} at {}ms is invalid, should be between 0.0 and 1.0",
+            vector.position(),
+            vector.index(),
+            keyframe.time
+          ),
+        )?;
+      }
+      for rotation in &keyframe.rotations {
+        self.is_in_command_range(
+          rotation.speed(),
+          format!(
+            "Speed {} for PatternCmd index {} at {}ms is invalid, should be between 0.0 and 1.0",
+            rotation.speed(),
+            rotation.index(),
+            keyframe.time
+          ),
+        )?;
+      }
+    }
+    // A looped pattern with no length would replay as fast as the server could send commands.
+    if self.looped && last_time == 0 {
+      return Err(ButtplugMessageError::InvalidMessageContents(
+        "Looped PatternCmd should last longer than 0ms.".to_owned(),
+      ));
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{
+    ActuatorType,
+    ButtplugMessageValidator,
+    PatternCmd,
+    PatternKeyframe,
+    ScalarSubcommand,
+  };
+
+  fn keyframe(time: u32, scalar: f64) -> PatternKeyframe {
+    PatternKeyframe::new(
+      time,
+      vec![ScalarSubcommand::new(0, scalar, ActuatorType::Vibrate)],
+      vec![],
+      vec![],
+    )
+  }
+
+  #[test]
+  pub fn test_pattern_cmd_validity() {
+    assert!(PatternCmd::new(0, vec![keyframe(0, 0.5), keyframe(100, 0.0)], true)
+      .is_valid()
+      .is_ok());
+    assert!(PatternCmd::new(0, vec![], false).is_valid().is_err());
+    assert!(PatternCmd::new(0, vec![keyframe(100, 0.5), keyframe(0, 0.0)], false)
+      .is_valid()
+      .is_err());
+    assert!(PatternCmd::new(0, vec![keyframe(0, 1.5)], false)
+      .is_valid()
+      .is_err());
+    assert!(
+      PatternCmd::new(0, vec![PatternKeyframe::new(0, vec![], vec![], vec![])], false)
+        .is_valid()
+        .is_err()
+    );
+    assert!(PatternCmd::new(0, vec![keyframe(0, 0.5)], false)
+      .is_valid()
+      .is_ok());
+    assert!(PatternCmd::new(0, vec![keyframe(0, 0.5)], true)
+      .is_valid()
+      .is_err());
+  }
+}
@@ -6,19 +6,42 @@
 // for full license information.
 
 use super::*;
+use getset::CopyGetters;
 #[cfg(feature = "serialize-json")]
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, ButtplugMessage, ButtplugMessageFinalizer, Clone, PartialEq, Eq)]
+#[derive(Debug, ButtplugMessage, ButtplugMessageFinalizer, Clone, PartialEq, Eq, CopyGetters)]
 #[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
 pub struct RequestDeviceList {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
   id: u32,
+  /// If set, requests a single page of the device list, starting at the first device with an index
+  /// equal to or greater than this value. Page size is advertised by the server in
+  /// [ServerInfo].
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(rename = "StartDeviceIndex", default, skip_serializing_if = "Option::is_none")
+  )]
+  #[getset(get_copy = "pub")]
+  start_device_index: Option<u32>,
+}
+
+impl RequestDeviceList {
+  /// Creates a request for the page of the device list starting at the given device index.
+  pub fn new_page(start_device_index: u32) -> Self {
+    Self {
+      id: 1,
+      start_device_index: Some(start_device_index),
+    }
+  }
 }
 
 impl Default for RequestDeviceList {
   fn default() -> Self {
-    Self { id: 1 }
+    Self {
+      id: 1,
+      start_device_index: None,
+    }
   }
 }
 
@@ -6,15 +6,38 @@
 // for full license information.
 
 use super::*;
-use getset::{CopyGetters, Getters};
+use getset::{CopyGetters, Getters, Setters};
 #[cfg(feature = "serialize-json")]
 use serde::{Deserialize, Serialize};
 
 fn return_version0() -> ButtplugMessageSpecVersion {
   ButtplugMessageSpecVersion::Version0
 }
+
+/// Features a client can declare it will use, in [RequestServerInfo]. Clients that declare
+/// capabilities are held to them for the rest of the connection, and servers can turn away clients
+/// that ask for things they won't grant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub enum ClientCapability {
+  /// Client will read or subscribe to device sensors.
+  Sensors,
+  /// Client will send raw endpoint commands. Needs a server that allows raw messages.
+  Raw,
+  /// Client only watches. Anything that moves devices or starts scanning is rejected, though it
+  /// can still stop devices.
+  Spectator,
+}
 #[derive(
-  Debug, ButtplugMessage, ButtplugMessageFinalizer, Clone, PartialEq, Eq, Getters, CopyGetters,
+  Debug,
+  ButtplugMessage,
+  ButtplugMessageFinalizer,
+  Clone,
+  PartialEq,
+  Eq,
+  Getters,
+  CopyGetters,
+  Setters,
 )]
 #[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
 pub struct RequestServerInfo {
@@ -32,6 +55,22 @@ pub struct RequestServerInfo {
   )]
   #[getset(get_copy = "pub")]
   message_version: ButtplugMessageSpecVersion,
+  /// Language tag (e.g. "de" or "pt-BR") of the language the client would like device names and
+  /// descriptions in, if the device config has them.
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(rename = "Locale", default, skip_serializing_if = "Option::is_none")
+  )]
+  #[getset(get = "pub", set = "pub")]
+  locale: Option<String>,
+  /// Features the client will use. If this isn't sent, the client can use anything the server
+  /// allows.
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(rename = "Capabilities", default, skip_serializing_if = "Option::is_none")
+  )]
+  #[getset(get = "pub", set = "pub")]
+  capabilities: Option<Vec<ClientCapability>>,
 }
 
 impl RequestServerInfo {
@@ -40,6 +79,8 @@ impl RequestServerInfo {
       id: 1,
       client_name: client_name.to_string(),
       message_version,
+      locale: None,
+      capabilities: None,
     }
   }
 }
@@ -52,7 +93,7 @@ impl ButtplugMessageValidator for RequestServerInfo {
 
 #[cfg(test)]
 mod test {
-  use super::{ButtplugMessageSpecVersion, RequestServerInfo};
+  use super::{ButtplugMessageSpecVersion, ClientCapability, RequestServerInfo};
 
   #[cfg(feature = "serialize-json")]
   #[test]
@@ -68,6 +109,8 @@ mod test {
       id: 1,
       client_name: "Test Client".to_owned(),
       message_version: ButtplugMessageSpecVersion::Version2,
+      locale: None,
+      capabilities: None,
     };
     assert_eq!(
       serde_json::from_str::<RequestServerInfo>(new_json).expect("Test unwrap"),
@@ -88,10 +131,53 @@ mod test {
       id: 1,
       client_name: "Test Client".to_owned(),
       message_version: ButtplugMessageSpecVersion::Version0,
+      locale: None,
+      capabilities: None,
     };
     assert_eq!(
       serde_json::from_str::<RequestServerInfo>(old_json).expect("Test unwrap"),
       old_msg
     );
   }
+
+  #[cfg(feature = "serialize-json")]
+  #[test]
+  fn test_request_server_info_locale_json_conversion() {
+    let json = r#"
+{
+        "Id": 1,
+        "ClientName": "Test Client",
+        "MessageVersion": 3,
+        "Locale": "de-AT"
+}
+        "#;
+    let mut msg = RequestServerInfo::new("Test Client", ButtplugMessageSpecVersion::Version3);
+    msg.set_locale(Some("de-AT".to_owned()));
+    assert_eq!(
+      serde_json::from_str::<RequestServerInfo>(json).expect("Test unwrap"),
+      msg
+    );
+  }
+
+  #[cfg(feature = "serialize-json")]
+  #[test]
+  fn test_request_server_info_capabilities_json_conversion() {
+    let json = r#"
+{
+        "Id": 1,
+        "ClientName": "Test Client",
+        "MessageVersion": 3,
+        "Capabilities": ["Sensors", "Spectator"]
+}
+        "#;
+    let mut msg = RequestServerInfo::new("Test Client", ButtplugMessageSpecVersion::Version3);
+    msg.set_capabilities(Some(vec![
+      ClientCapability::Sensors,
+      ClientCapability::Spectator,
+    ]));
+    assert_eq!(
+      serde_json::from_str::<RequestServerInfo>(json).expect("Test unwrap"),
+      msg
+    );
+  }
 }
@@ -70,3 +70,25 @@ impl ButtplugMessageValidator for RotateCmd {
     Ok(())
   }
 }
+
+#[cfg(test)]
+mod test {
+  use super::{ButtplugMessage, ButtplugMessageValidator, RotateCmd, RotationSubcommand};
+
+  #[test]
+  pub fn test_rotate_cmd_validity() {
+    let valid = RotateCmd::new(
+      0,
+      vec![
+        RotationSubcommand::new(0, 0.0, true),
+        RotationSubcommand::new(1, 1.0, false),
+      ],
+    );
+    assert!(valid.is_valid().is_ok());
+    let out_of_range = RotateCmd::new(0, vec![RotationSubcommand::new(0, -0.5, true)]);
+    assert!(out_of_range.is_valid().is_err());
+    let mut system_id = valid;
+    system_id.set_id(0);
+    assert!(system_id.is_valid().is_err());
+  }
+}
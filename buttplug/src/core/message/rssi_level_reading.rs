@@ -47,3 +47,18 @@ impl ButtplugMessageValidator for RSSILevelReading {
     }
   }
 }
+
+#[cfg(test)]
+mod test {
+  use super::{ButtplugMessage, ButtplugMessageValidator, RSSILevelReading};
+
+  #[test]
+  pub fn test_rssi_level_reading_validity() {
+    assert!(RSSILevelReading::new(0, -40).is_valid().is_ok());
+    assert!(RSSILevelReading::new(0, 0).is_valid().is_ok());
+    assert!(RSSILevelReading::new(0, 10).is_valid().is_err());
+    let mut msg = RSSILevelReading::new(0, -40);
+    msg.set_id(0);
+    assert!(msg.is_valid().is_err());
+  }
+}
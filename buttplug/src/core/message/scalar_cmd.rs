@@ -87,3 +87,37 @@ impl From<VibrateCmd> for ScalarCmd {
     }
   }
 }
+
+#[cfg(test)]
+mod test {
+  use super::{
+    ActuatorType,
+    ButtplugMessage,
+    ButtplugMessageValidator,
+    ScalarCmd,
+    ScalarSubcommand,
+  };
+
+  #[test]
+  pub fn test_scalar_cmd_validity() {
+    let valid = ScalarCmd::new(
+      0,
+      vec![
+        ScalarSubcommand::new(0, 0.0, ActuatorType::Vibrate),
+        ScalarSubcommand::new(1, 1.0, ActuatorType::Constrict),
+      ],
+    );
+    assert!(valid.is_valid().is_ok());
+    let out_of_range = ScalarCmd::new(
+      0,
+      vec![
+        ScalarSubcommand::new(0, 0.5, ActuatorType::Vibrate),
+        ScalarSubcommand::new(1, 2.0, ActuatorType::Vibrate),
+      ],
+    );
+    assert!(out_of_range.is_valid().is_err());
+    let mut system_id = valid;
+    system_id.set_id(0);
+    assert!(system_id.is_valid().is_err());
+  }
+}
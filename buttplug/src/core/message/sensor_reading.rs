@@ -6,7 +6,7 @@
 // for full license information.
 
 use super::*;
-use getset::{CopyGetters, Getters};
+use getset::{CopyGetters, Getters, MutGetters};
 #[cfg(feature = "serialize-json")]
 use serde::{Deserialize, Serialize};
 
@@ -20,6 +20,7 @@ use serde::{Deserialize, Serialize};
   Clone,
   Getters,
   CopyGetters,
+  MutGetters,
   PartialEq,
   Eq,
 )]
@@ -36,7 +37,7 @@ pub struct SensorReading {
   #[getset[get_copy="pub"]]
   sensor_type: SensorType,
   #[cfg_attr(feature = "serialize-json", serde(rename = "Data"))]
-  #[getset[get="pub"]]
+  #[getset[get="pub", get_mut="pub(crate)"]]
   data: Vec<i32>,
 }
 
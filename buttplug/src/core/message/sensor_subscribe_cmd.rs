@@ -6,11 +6,20 @@
 // for full license information.
 
 use super::*;
-use getset::Getters;
+use getset::{CopyGetters, Getters};
 #[cfg(feature = "serialize-json")]
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, ButtplugDeviceMessage, ButtplugMessageFinalizer, PartialEq, Eq, Clone, Getters)]
+#[derive(
+  Debug,
+  ButtplugDeviceMessage,
+  ButtplugMessageFinalizer,
+  PartialEq,
+  Eq,
+  Clone,
+  Getters,
+  CopyGetters,
+)]
 #[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
 pub struct SensorSubscribeCmd {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
@@ -23,6 +32,20 @@ pub struct SensorSubscribeCmd {
   #[getset(get = "pub")]
   #[cfg_attr(feature = "serialize-json", serde(rename = "SensorType"))]
   sensor_type: SensorType,
+  /// Minimum time between readings sent for this subscription, in milliseconds.
+  #[getset(get_copy = "pub")]
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(rename = "MinInterval", default, skip_serializing_if = "Option::is_none")
+  )]
+  min_interval: Option<u32>,
+  /// Only every Nth reading is sent for this subscription.
+  #[getset(get_copy = "pub")]
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(rename = "Decimation", default, skip_serializing_if = "Option::is_none")
+  )]
+  decimation: Option<u32>,
 }
 
 impl SensorSubscribeCmd {
@@ -32,12 +55,90 @@ impl SensorSubscribeCmd {
       device_index,
       sensor_index,
       sensor_type,
+      min_interval: None,
+      decimation: None,
+    }
+  }
+
+  /// Subscribe with a limit on how many readings are sent. Readings arriving less than
+  /// `min_interval` milliseconds after the last sent reading are dropped, as are all but every
+  /// `decimation`th reading.
+  pub fn new_with_sampling(
+    device_index: u32,
+    sensor_index: u32,
+    sensor_type: SensorType,
+    min_interval: Option<u32>,
+    decimation: Option<u32>,
+  ) -> Self {
+    Self {
+      id: 1,
+      device_index,
+      sensor_index,
+      sensor_type,
+      min_interval,
+      decimation,
     }
   }
 }
 
 impl ButtplugMessageValidator for SensorSubscribeCmd {
   fn is_valid(&self) -> Result<(), ButtplugMessageError> {
-    self.is_not_system_id(self.id)
+    self.is_not_system_id(self.id)?;
+    if self.decimation == Some(0) {
+      return Err(ButtplugMessageError::InvalidMessageContents(
+        "SensorSubscribeCmd Decimation must be at least 1.".to_owned(),
+      ));
+    }
+    Ok(())
+  }
+}
+
+#[cfg(feature = "serialize-json")]
+#[cfg(test)]
+mod test {
+  use crate::core::message::{
+    ButtplugCurrentSpecClientMessage,
+    ButtplugMessageValidator,
+    SensorSubscribeCmd,
+    SensorType,
+  };
+
+  #[test]
+  fn test_sensor_subscribe_cmd_sampling_deserialize() {
+    let msg_str = "{\"SensorSubscribeCmd\":{\"Id\":1,\"DeviceIndex\":0,\"SensorIndex\":0,\
+      \"SensorType\":\"Pressure\",\"MinInterval\":50,\"Decimation\":2}}";
+    let union: ButtplugCurrentSpecClientMessage =
+      serde_json::from_str(msg_str).expect("Infallible deserialization.");
+    assert_eq!(
+      ButtplugCurrentSpecClientMessage::SensorSubscribeCmd(SensorSubscribeCmd::new_with_sampling(
+        0,
+        0,
+        SensorType::Pressure,
+        Some(50),
+        Some(2)
+      )),
+      union
+    );
+  }
+
+  #[test]
+  fn test_sensor_subscribe_cmd_without_sampling_serialize() {
+    let union = ButtplugCurrentSpecClientMessage::SensorSubscribeCmd(SensorSubscribeCmd::new(
+      0,
+      0,
+      SensorType::Pressure,
+    ));
+    let js = serde_json::to_string(&union).expect("Infallible serialization.");
+    assert_eq!(
+      js,
+      "{\"SensorSubscribeCmd\":{\"Id\":1,\"DeviceIndex\":0,\"SensorIndex\":0,\
+       \"SensorType\":\"Pressure\"}}"
+    );
+  }
+
+  #[test]
+  fn test_sensor_subscribe_cmd_zero_decimation_invalid() {
+    let msg = SensorSubscribeCmd::new_with_sampling(0, 0, SensorType::Pressure, None, Some(0));
+    assert!(msg.is_valid().is_err());
   }
 }
@@ -11,7 +11,6 @@ use crate::core::{
   message::{
     self,
     ButtplugClientMessage,
-    ButtplugCurrentSpecClientMessage,
     ButtplugCurrentSpecServerMessage,
     ButtplugMessage,
     ButtplugMessageFinalizer,
@@ -27,10 +26,12 @@ use crate::core::{
     ButtplugSpecV3ServerMessage,
   },
 };
+#[cfg(feature = "client")]
+use crate::core::message::ButtplugCurrentSpecClientMessage;
 use jsonschema::JSONSchema;
 use once_cell::sync::OnceCell;
 use serde::{Deserialize, Serialize};
-use std::{convert::TryFrom, fmt::Debug};
+use std::{borrow::Borrow, convert::TryFrom, fmt::Debug};
 
 static MESSAGE_JSON_SCHEMA: &str =
   include_str!("../../../../buttplug-schema/schema/buttplug-schema.json");
@@ -120,15 +121,15 @@ where
     })
 }
 
-fn serialize_to_version(
-  version: ButtplugMessageSpecVersion,
-  msgs: &[ButtplugServerMessage],
-) -> ButtplugSerializedMessage {
+fn serialize_to_version<T>(version: ButtplugMessageSpecVersion, msgs: &[T]) -> ButtplugSerializedMessage
+where
+  T: Borrow<ButtplugServerMessage>,
+{
   ButtplugSerializedMessage::Text(match version {
     ButtplugMessageSpecVersion::Version0 => {
       let msg_vec: Vec<ButtplugSpecV0ServerMessage> = msgs
         .iter()
-        .cloned()
+        .map(|msg| msg.borrow().clone())
         .map(|msg| match ButtplugSpecV0ServerMessage::try_from(msg) {
           Ok(msgv0) => msgv0,
           Err(err) => ButtplugSpecV0ServerMessage::Error(
@@ -141,7 +142,7 @@ fn serialize_to_version(
     ButtplugMessageSpecVersion::Version1 => {
       let msg_vec: Vec<ButtplugSpecV1ServerMessage> = msgs
         .iter()
-        .cloned()
+        .map(|msg| msg.borrow().clone())
         .map(|msg| match ButtplugSpecV1ServerMessage::try_from(msg) {
           Ok(msgv0) => msgv0,
           Err(err) => ButtplugSpecV1ServerMessage::Error(
@@ -154,7 +155,7 @@ fn serialize_to_version(
     ButtplugMessageSpecVersion::Version2 => {
       let msg_vec: Vec<ButtplugSpecV2ServerMessage> = msgs
         .iter()
-        .cloned()
+        .map(|msg| msg.borrow().clone())
         .map(|msg| match ButtplugSpecV2ServerMessage::try_from(msg) {
           Ok(msgv0) => msgv0,
           Err(err) => ButtplugSpecV2ServerMessage::Error(ButtplugError::from(err).into()),
@@ -165,7 +166,7 @@ fn serialize_to_version(
     ButtplugMessageSpecVersion::Version3 => {
       let msg_vec: Vec<ButtplugSpecV3ServerMessage> = msgs
         .iter()
-        .cloned()
+        .map(|msg| msg.borrow().clone())
         .map(|msg| match ButtplugSpecV3ServerMessage::try_from(msg) {
           Ok(msgv0) => msgv0,
           Err(err) => ButtplugSpecV3ServerMessage::Error(ButtplugError::from(err).into()),
@@ -247,14 +248,17 @@ impl ButtplugMessageSerializer for ButtplugServerJSONSerializer {
     Ok(msg_union.iter().cloned().map(|m| m.into()).collect())
   }
 
-  fn serialize(&self, msgs: &[ButtplugServerMessage]) -> ButtplugSerializedMessage {
+  fn serialize<T>(&self, msgs: &[T]) -> ButtplugSerializedMessage
+  where
+    T: Borrow<ButtplugServerMessage>,
+  {
     if let Some(version) = self.message_version.get() {
       serialize_to_version(*version, msgs)
     } else {
       // In the rare event that there is a problem with the
       // RequestServerInfo message (so we can't set up our known spec
       // version), just encode to the latest and return.
-      if let ButtplugServerMessage::Error(_) = &msgs[0] {
+      if let ButtplugServerMessage::Error(_) = msgs[0].borrow() {
         serialize_to_version(ButtplugMessageSpecVersion::Version3, msgs)
       } else {
         // If we don't even have enough info to know which message
@@ -267,12 +271,18 @@ impl ButtplugMessageSerializer for ButtplugServerJSONSerializer {
       }
     }
   }
+
+  fn error_reply(&self, err: message::Error) -> Option<ButtplugServerMessage> {
+    Some(err.into())
+  }
 }
 
+#[cfg(feature = "client")]
 pub struct ButtplugClientJSONSerializerImpl {
   validator: JSONSchema,
 }
 
+#[cfg(feature = "client")]
 impl Default for ButtplugClientJSONSerializerImpl {
   fn default() -> Self {
     Self {
@@ -281,6 +291,7 @@ impl Default for ButtplugClientJSONSerializerImpl {
   }
 }
 
+#[cfg(feature = "client")]
 impl ButtplugClientJSONSerializerImpl {
   pub fn deserialize<T>(
     &self,
@@ -296,19 +307,25 @@ impl ButtplugClientJSONSerializerImpl {
     }
   }
 
-  pub fn serialize<T>(&self, msg: &[T]) -> ButtplugSerializedMessage
+  pub fn serialize<T, M>(&self, msgs: &[M]) -> ButtplugSerializedMessage
   where
     T: ButtplugMessage + Serialize + Deserialize<'static>,
+    M: Borrow<T>,
   {
-    ButtplugSerializedMessage::Text(vec_to_protocol_json(msg))
+    let msgs: Vec<&T> = msgs.iter().map(Borrow::borrow).collect();
+    ButtplugSerializedMessage::Text(
+      serde_json::to_string(&msgs).expect("Infallible serialization"),
+    )
   }
 }
 
+#[cfg(feature = "client")]
 #[derive(Default)]
 pub struct ButtplugClientJSONSerializer {
   serializer_impl: ButtplugClientJSONSerializerImpl,
 }
 
+#[cfg(feature = "client")]
 impl ButtplugMessageSerializer for ButtplugClientJSONSerializer {
   type Inbound = ButtplugCurrentSpecServerMessage;
   type Outbound = ButtplugCurrentSpecClientMessage;
@@ -320,8 +337,11 @@ impl ButtplugMessageSerializer for ButtplugClientJSONSerializer {
     self.serializer_impl.deserialize(msg)
   }
 
-  fn serialize(&self, msg: &[Self::Outbound]) -> ButtplugSerializedMessage {
-    self.serializer_impl.serialize(msg)
+  fn serialize<T>(&self, msgs: &[T]) -> ButtplugSerializedMessage
+  where
+    T: Borrow<Self::Outbound>,
+  {
+    self.serializer_impl.serialize::<Self::Outbound, _>(msgs)
   }
 }
 
@@ -364,6 +384,7 @@ mod test {
   }
 
   #[test]
+  #[cfg(feature = "client")]
   fn test_client_incorrect_messages() {
     let incorrect_incoming_messages = vec![
       // Not valid JSON
@@ -386,11 +407,9 @@ mod test {
       "[{\"Ok\":{\"NotAField\":\"NotAValue\",\"Id\":1}}]",
     ];
     let serializer = ButtplugClientJSONSerializer::default();
-    let _ = serializer.serialize(&vec![RequestServerInfo::new(
-      "test client",
-      BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
-    )
-    .into()]);
+    let _ = serializer.serialize(&[ButtplugCurrentSpecClientMessage::from(
+      RequestServerInfo::new("test client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION),
+    )]);
     for msg in incorrect_incoming_messages {
       let res = serializer.deserialize(&ButtplugSerializedMessage::Text(msg.to_owned()));
       assert!(res.is_err(), "{} should be an error", msg);
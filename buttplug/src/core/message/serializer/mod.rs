@@ -10,14 +10,13 @@
 #[cfg(feature = "serialize-json")]
 mod json_serializer;
 #[cfg(feature = "serialize-json")]
-pub use json_serializer::{
-  vec_to_protocol_json,
-  ButtplugClientJSONSerializer,
-  ButtplugClientJSONSerializerImpl,
-  ButtplugServerJSONSerializer,
-};
+pub use json_serializer::{vec_to_protocol_json, ButtplugServerJSONSerializer};
+#[cfg(all(feature = "serialize-json", feature = "client"))]
+pub use json_serializer::{ButtplugClientJSONSerializer, ButtplugClientJSONSerializerImpl};
 
+use crate::core::message;
 use serde::{Deserialize, Serialize};
+use std::borrow::Borrow;
 use thiserror::Error;
 pub type ButtplugSerializerResult<T> = Result<T, ButtplugSerializerError>;
 
@@ -63,5 +62,15 @@ pub trait ButtplugMessageSerializer: Default + Sync + Send {
     &self,
     msg: &ButtplugSerializedMessage,
   ) -> ButtplugSerializerResult<Vec<Self::Inbound>>;
-  fn serialize(&self, msg: &[Self::Outbound]) -> ButtplugSerializedMessage;
+  /// Serializes messages, which can be owned or behind references (i.e. server events, which are
+  /// shared as [Arc](std::sync::Arc)s), without taking them.
+  fn serialize<T>(&self, msgs: &[T]) -> ButtplugSerializedMessage
+  where
+    T: Borrow<Self::Outbound>;
+  /// Wraps an error in an outbound message, for connectors rejecting incoming messages they can't
+  /// accept. Returns None if the other side doesn't expect replies (i.e. servers never get replies
+  /// from clients), in which case incoming messages are passed on unchecked.
+  fn error_reply(&self, _err: message::Error) -> Option<Self::Outbound> {
+    None
+  }
 }
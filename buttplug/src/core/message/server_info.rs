@@ -6,12 +6,20 @@
 // for full license information.
 
 use super::*;
-use getset::{CopyGetters, Getters};
+use getset::{CopyGetters, Getters, Setters};
 #[cfg(feature = "serialize-json")]
 use serde::{Deserialize, Serialize};
 
 #[derive(
-  Debug, ButtplugMessage, ButtplugMessageFinalizer, PartialEq, Eq, Clone, Getters, CopyGetters,
+  Debug,
+  ButtplugMessage,
+  ButtplugMessageFinalizer,
+  PartialEq,
+  Eq,
+  Clone,
+  Getters,
+  CopyGetters,
+  Setters,
 )]
 #[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
 pub struct ServerInfo {
@@ -26,6 +34,41 @@ pub struct ServerInfo {
   #[cfg_attr(feature = "serialize-json", serde(rename = "ServerName"))]
   #[getset(get = "pub")]
   server_name: String,
+  /// Version of the device configuration file loaded by the server. Only sent to Spec v3+ clients.
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(
+      rename = "DeviceConfigVersion",
+      default,
+      skip_serializing_if = "Option::is_none"
+    )
+  )]
+  #[getset(get = "pub", set = "pub")]
+  device_config_version: Option<String>,
+  /// Version of the library the server is running. Only sent to Spec v3+ clients.
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(
+      rename = "LibraryVersion",
+      default,
+      skip_serializing_if = "Option::is_none"
+    )
+  )]
+  #[getset(get = "pub", set = "pub")]
+  library_version: Option<String>,
+  /// Maximum number of devices the server will return in reply to a paged
+  /// [RequestDeviceList](crate::core::message::RequestDeviceList). If not set, the server does not
+  /// support paging and will always return the full list. Only sent to Spec v3+ clients.
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(
+      rename = "DeviceListPageSize",
+      default,
+      skip_serializing_if = "Option::is_none"
+    )
+  )]
+  #[getset(get_copy = "pub", set = "pub")]
+  device_list_page_size: Option<u32>,
 }
 
 impl ServerInfo {
@@ -39,6 +82,9 @@ impl ServerInfo {
       message_version,
       max_ping_time,
       server_name: server_name.to_string(),
+      device_config_version: None,
+      library_version: None,
+      device_list_page_size: None,
     }
   }
 }
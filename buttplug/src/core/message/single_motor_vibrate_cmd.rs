@@ -44,3 +44,18 @@ impl ButtplugMessageValidator for SingleMotorVibrateCmd {
     )
   }
 }
+
+#[cfg(test)]
+mod test {
+  use super::{ButtplugMessage, ButtplugMessageValidator, SingleMotorVibrateCmd};
+
+  #[test]
+  pub fn test_single_motor_vibrate_cmd_validity() {
+    assert!(SingleMotorVibrateCmd::new(0, 0.0).is_valid().is_ok());
+    assert!(SingleMotorVibrateCmd::new(0, 1.0).is_valid().is_ok());
+    assert!(SingleMotorVibrateCmd::new(0, 1.1).is_valid().is_err());
+    let mut msg = SingleMotorVibrateCmd::new(0, 0.5);
+    msg.set_id(0);
+    assert!(msg.is_valid().is_err());
+  }
+}
@@ -59,3 +59,22 @@ impl ButtplugMessageValidator for VibrateCmd {
     Ok(())
   }
 }
+
+#[cfg(test)]
+mod test {
+  use super::{ButtplugMessage, ButtplugMessageValidator, VibrateCmd, VibrateSubcommand};
+
+  #[test]
+  pub fn test_vibrate_cmd_validity() {
+    let valid = VibrateCmd::new(
+      0,
+      vec![VibrateSubcommand::new(0, 0.0), VibrateSubcommand::new(1, 1.0)],
+    );
+    assert!(valid.is_valid().is_ok());
+    let out_of_range = VibrateCmd::new(0, vec![VibrateSubcommand::new(0, -1.0)]);
+    assert!(out_of_range.is_valid().is_err());
+    let mut system_id = valid;
+    system_id.set_id(0);
+    assert!(system_id.is_valid().is_err());
+  }
+}
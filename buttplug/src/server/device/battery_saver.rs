@@ -0,0 +1,285 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Battery saver, for long sessions with devices that run off a battery.
+//!
+//! While the battery saver is on, every intensity sent to any device is scaled down by the same
+//! factor, and devices can also be duty cycled, alternating between running and resting windows.
+//! As with [warm up](super::RampPolicy), only intensities are changed, and positions are left
+//! alone. Devices keep the output clients last asked for, so it can be sent again, rescaled,
+//! whenever a window starts or the battery saver is changed or turned off.
+
+use super::{output_ramp::scale_output, ServerDevice};
+use crate::{
+  core::{
+    errors::ButtplugDeviceError,
+    message::{
+      ButtplugDeviceCommandMessageUnion,
+      RotateCmd,
+      RotationSubcommand,
+      ScalarCmd,
+      ScalarSubcommand,
+    },
+  },
+  util::{async_manager, sleep},
+};
+use dashmap::DashMap;
+use futures::future;
+use getset::CopyGetters;
+use std::{
+  collections::BTreeMap,
+  sync::{Arc, Mutex, RwLock},
+  time::{Duration, Instant},
+};
+use tokio_util::sync::CancellationToken;
+
+/// Running and resting windows for duty cycling devices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, CopyGetters)]
+#[getset(get_copy = "pub")]
+pub struct DutyCycle {
+  /// How long devices run for before resting.
+  on: Duration,
+  /// How long devices rest for, with their output stopped.
+  off: Duration,
+}
+
+impl DutyCycle {
+  pub fn new(on: Duration, off: Duration) -> Self {
+    Self { on, off }
+  }
+
+  /// Returns whether devices are resting `elapsed` into the cycle, and how long until that changes.
+  fn window(&self, elapsed: Duration) -> (bool, Duration) {
+    let period = (self.on + self.off).as_nanos();
+    let position = Duration::from_nanos((elapsed.as_nanos() % period) as u64);
+    if position < self.on {
+      (false, self.on - position)
+    } else {
+      (true, self.on + self.off - position)
+    }
+  }
+}
+
+/// Battery saver settings, applied to every device.
+#[derive(Debug, Clone, Copy, PartialEq, CopyGetters)]
+#[getset(get_copy = "pub")]
+pub struct BatterySaverSettings {
+  /// Factor every intensity is multiplied by, from 0.0 to 1.0.
+  intensity_scale: f64,
+  /// Windows devices alternate between, if they're duty cycled.
+  duty_cycle: Option<DutyCycle>,
+}
+
+impl BatterySaverSettings {
+  pub fn new(intensity_scale: f64, duty_cycle: Option<DutyCycle>) -> Self {
+    Self {
+      intensity_scale,
+      duty_cycle,
+    }
+  }
+
+  fn check(&self) -> Result<(), ButtplugDeviceError> {
+    if !(0.0..=1.0).contains(&self.intensity_scale) {
+      return Err(ButtplugDeviceError::DeviceConfigurationError(format!(
+        "Battery saver intensity scale {} is outside of 0.0 to 1.0.",
+        self.intensity_scale
+      )));
+    }
+    if let Some(duty_cycle) = self.duty_cycle {
+      if duty_cycle.on.is_zero() || duty_cycle.off.is_zero() {
+        return Err(ButtplugDeviceError::DeviceConfigurationError(
+          "Battery saver duty cycle windows need to be longer than zero.".to_owned(),
+        ));
+      }
+    }
+    Ok(())
+  }
+
+  /// Factor to multiply intensities by `elapsed` after the settings took effect.
+  fn output_scale(&self, elapsed: Duration) -> f64 {
+    match self.duty_cycle {
+      Some(duty_cycle) if duty_cycle.window(elapsed).0 => 0.0,
+      _ => self.intensity_scale,
+    }
+  }
+}
+
+/// Battery saver state shared by the device manager, which changes it, and every device, which
+/// applies it to their output.
+#[derive(Default)]
+pub(super) struct BatterySaver {
+  /// Current settings, along with when they took effect, which is when the duty cycle starts.
+  settings: RwLock<Option<(BatterySaverSettings, Instant)>>,
+  /// Cancels the task that refreshes device output as duty cycle windows start.
+  duty_cycle_token: Mutex<Option<CancellationToken>>,
+}
+
+impl BatterySaver {
+  pub fn settings(&self) -> Option<BatterySaverSettings> {
+    self
+      .settings
+      .read()
+      .expect("Battery saver lock should never be poisoned.")
+      .map(|(settings, _)| settings)
+  }
+
+  /// Changes the settings, or turns the battery saver off with None, then sends every device in
+  /// `devices` its output again under the new settings.
+  pub fn set(
+    &self,
+    settings: Option<BatterySaverSettings>,
+    devices: Arc<DashMap<u32, Arc<ServerDevice>>>,
+  ) -> Result<(), ButtplugDeviceError> {
+    if let Some(settings) = &settings {
+      settings.check()?;
+    }
+    let started = Instant::now();
+    *self
+      .settings
+      .write()
+      .expect("Battery saver lock should never be poisoned.") =
+      settings.map(|settings| (settings, started));
+    let mut duty_cycle_token = self
+      .duty_cycle_token
+      .lock()
+      .expect("Battery saver lock should never be poisoned.");
+    if let Some(token) = duty_cycle_token.take() {
+      token.cancel();
+    }
+    if let Some(duty_cycle) = settings.and_then(|settings| settings.duty_cycle) {
+      let token = CancellationToken::new();
+      async_manager::spawn(run_duty_cycle(
+        duty_cycle,
+        started,
+        devices.clone(),
+        token.child_token(),
+      ));
+      *duty_cycle_token = Some(token);
+    }
+    async_manager::spawn(refresh_devices(devices));
+    Ok(())
+  }
+
+  /// Runs the intensities in a command through the battery saver, if it's on.
+  pub fn apply(
+    &self,
+    message: ButtplugDeviceCommandMessageUnion,
+  ) -> ButtplugDeviceCommandMessageUnion {
+    let scale = self
+      .settings
+      .read()
+      .expect("Battery saver lock should never be poisoned.")
+      .map(|(settings, started)| settings.output_scale(started.elapsed()));
+    match scale {
+      Some(scale) => scale_output(message, |level| level * scale),
+      None => message,
+    }
+  }
+}
+
+/// Sends every device its output again, so it's scaled by whatever the battery saver is now.
+async fn refresh_devices(devices: Arc<DashMap<u32, Arc<ServerDevice>>>) {
+  let refreshes: Vec<_> = devices
+    .iter()
+    .map(|device| device.value().refresh_output())
+    .collect();
+  for result in future::join_all(refreshes).await {
+    if let Err(err) = result {
+      warn!("Battery saver could not update device output: {}", err);
+    }
+  }
+}
+
+async fn run_duty_cycle(
+  duty_cycle: DutyCycle,
+  started: Instant,
+  devices: Arc<DashMap<u32, Arc<ServerDevice>>>,
+  token: CancellationToken,
+) {
+  loop {
+    let (_, until_change) = duty_cycle.window(started.elapsed());
+    tokio::select! {
+      _ = sleep(until_change) => {},
+      _ = token.cancelled() => return,
+    }
+    refresh_devices(devices.clone()).await;
+  }
+}
+
+/// Output a client last asked a device for, by feature, before the battery saver scaled it.
+#[derive(Default)]
+pub(super) struct RequestedOutput {
+  scalars: BTreeMap<u32, ScalarSubcommand>,
+  rotations: BTreeMap<u32, RotationSubcommand>,
+}
+
+impl RequestedOutput {
+  pub fn record_scalars<'a>(&mut self, scalars: impl Iterator<Item = &'a ScalarSubcommand>) {
+    for scalar in scalars {
+      self.scalars.insert(scalar.index(), scalar.clone());
+    }
+  }
+
+  pub fn record_rotations<'a>(&mut self, rotations: impl Iterator<Item = &'a RotationSubcommand>) {
+    for rotation in rotations {
+      self.rotations.insert(rotation.index(), rotation.clone());
+    }
+  }
+
+  pub fn clear(&mut self) {
+    self.scalars.clear();
+    self.rotations.clear();
+  }
+
+  /// Commands that bring back all of the requested output.
+  pub fn commands(&self) -> Vec<ButtplugDeviceCommandMessageUnion> {
+    let mut commands = vec![];
+    if !self.scalars.is_empty() {
+      commands.push(ScalarCmd::new(0, self.scalars.values().cloned().collect()).into());
+    }
+    if !self.rotations.is_empty() {
+      commands.push(RotateCmd::new(0, self.rotations.values().cloned().collect()).into());
+    }
+    commands
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_duty_cycle_windows() {
+    let duty_cycle = DutyCycle::new(Duration::from_secs(3), Duration::from_secs(1));
+    assert_eq!(
+      duty_cycle.window(Duration::ZERO),
+      (false, Duration::from_secs(3))
+    );
+    assert_eq!(
+      duty_cycle.window(Duration::from_secs(3)),
+      (true, Duration::from_secs(1))
+    );
+    assert_eq!(
+      duty_cycle.window(Duration::from_secs(9)),
+      (false, Duration::from_secs(2))
+    );
+    let settings = BatterySaverSettings::new(0.5, Some(duty_cycle));
+    assert_eq!(settings.output_scale(Duration::from_secs(1)), 0.5);
+    assert_eq!(settings.output_scale(Duration::from_millis(3500)), 0.0);
+  }
+
+  #[test]
+  fn test_settings_check() {
+    assert!(BatterySaverSettings::new(0.5, None).check().is_ok());
+    assert!(BatterySaverSettings::new(1.5, None).check().is_err());
+    assert!(BatterySaverSettings::new(f64::NAN, None).check().is_err());
+    let duty_cycle = DutyCycle::new(Duration::from_secs(1), Duration::ZERO);
+    assert!(BatterySaverSettings::new(0.5, Some(duty_cycle))
+      .check()
+      .is_err());
+  }
+}
@@ -0,0 +1,148 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::hardware::communication::HardwareCommunicationManagerStatus;
+use std::{
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    RwLock,
+  },
+  time::Duration,
+};
+
+/// Scan and connection counters for a single hardware communication manager, so transport problems
+/// (flaky dongles, adapters that see devices but can't connect to them) can be measured.
+#[derive(Debug)]
+pub struct CommManagerMetrics {
+  name: String,
+  advertisements_seen: AtomicU64,
+  devices_matched: AtomicU64,
+  connects_attempted: AtomicU64,
+  connects_succeeded: AtomicU64,
+  connects_failed: AtomicU64,
+  total_connect_time_ms: AtomicU64,
+  status: RwLock<HardwareCommunicationManagerStatus>,
+}
+
+impl CommManagerMetrics {
+  pub(super) fn new(name: &str, status: HardwareCommunicationManagerStatus) -> Self {
+    Self {
+      name: name.to_owned(),
+      advertisements_seen: AtomicU64::new(0),
+      devices_matched: AtomicU64::new(0),
+      connects_attempted: AtomicU64::new(0),
+      connects_succeeded: AtomicU64::new(0),
+      connects_failed: AtomicU64::new(0),
+      total_connect_time_ms: AtomicU64::new(0),
+      status: RwLock::new(status),
+    }
+  }
+
+  /// Name of the communication manager these metrics are for.
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+
+  /// Last status the manager reported. Updated when scanning starts or stops, and when the manager
+  /// reports a change on its own.
+  pub fn status(&self) -> HardwareCommunicationManagerStatus {
+    self
+      .status
+      .read()
+      .expect("Lock only held for assignment")
+      .clone()
+  }
+
+  /// Number of times the manager reported finding hardware. Transports that work off of
+  /// advertisements (like bluetooth) will report the same device many times.
+  pub fn advertisements_seen(&self) -> u64 {
+    self.advertisements_seen.load(Ordering::Relaxed)
+  }
+
+  /// Number of found hardware reports that matched a protocol in the device configuration.
+  pub fn devices_matched(&self) -> u64 {
+    self.devices_matched.load(Ordering::Relaxed)
+  }
+
+  /// Number of times the server tried to connect to matched hardware.
+  pub fn connects_attempted(&self) -> u64 {
+    self.connects_attempted.load(Ordering::Relaxed)
+  }
+
+  pub fn connects_succeeded(&self) -> u64 {
+    self.connects_succeeded.load(Ordering::Relaxed)
+  }
+
+  pub fn connects_failed(&self) -> u64 {
+    self.connects_failed.load(Ordering::Relaxed)
+  }
+
+  /// Average time from starting to connect to a device until it was ready to use, over all
+  /// successful connections. None if nothing has connected yet.
+  pub fn average_connect_time(&self) -> Option<Duration> {
+    let succeeded = self.connects_succeeded();
+    if succeeded == 0 {
+      return None;
+    }
+    Some(Duration::from_millis(
+      self.total_connect_time_ms.load(Ordering::Relaxed) / succeeded,
+    ))
+  }
+
+  pub(super) fn set_status(&self, status: HardwareCommunicationManagerStatus) {
+    *self.status.write().expect("Lock only held for assignment") = status;
+  }
+
+  pub(super) fn record_advertisement(&self) {
+    self.advertisements_seen.fetch_add(1, Ordering::Relaxed);
+  }
+
+  pub(super) fn record_match(&self) {
+    self.devices_matched.fetch_add(1, Ordering::Relaxed);
+  }
+
+  pub(super) fn record_connect_attempt(&self) {
+    self.connects_attempted.fetch_add(1, Ordering::Relaxed);
+  }
+
+  pub(super) fn record_connect_success(&self, connect_time: Duration) {
+    self
+      .total_connect_time_ms
+      .fetch_add(connect_time.as_millis() as u64, Ordering::Relaxed);
+    self.connects_succeeded.fetch_add(1, Ordering::Relaxed);
+  }
+
+  pub(super) fn record_connect_failure(&self) {
+    self.connects_failed.fetch_add(1, Ordering::Relaxed);
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_average_connect_time() {
+    let metrics = CommManagerMetrics::new(
+      "TestCommunicationManager",
+      HardwareCommunicationManagerStatus::Ready,
+    );
+    assert!(metrics.average_connect_time().is_none());
+    metrics.record_connect_attempt();
+    metrics.record_connect_success(Duration::from_millis(100));
+    metrics.record_connect_attempt();
+    metrics.record_connect_success(Duration::from_millis(300));
+    metrics.record_connect_attempt();
+    metrics.record_connect_failure();
+    assert_eq!(metrics.connects_attempted(), 3);
+    assert_eq!(metrics.connects_failed(), 1);
+    assert_eq!(
+      metrics.average_connect_time(),
+      Some(Duration::from_millis(200))
+    );
+  }
+}
@@ -0,0 +1,194 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Output rate limiting, for devices that can't keep up with how fast applications send commands.
+//!
+//! Games can send ScalarCmd and RotateCmd well over a hundred times a second, which is more than a
+//! bluetooth connection can carry, so writes back up and the device lags further and further
+//! behind. With a max update rate configured for a device or its protocol, the first output command
+//! in each update interval is sent right away, and anything sent during the rest of the interval
+//! is merged, the latest value for each feature winning. The merged output is sent once the
+//! interval is over. Stopping the device throws away anything still waiting to be sent.
+
+use super::battery_saver::RequestedOutput;
+use crate::core::message::ButtplugDeviceCommandMessageUnion;
+use std::{
+  sync::Mutex,
+  time::{Duration, Instant},
+};
+
+/// What to do with an output command handed to the coalescer.
+pub(super) enum Coalesced {
+  /// Nothing has been sent this interval, so send the command now.
+  Send(ButtplugDeviceCommandMessageUnion),
+  /// The command was merged into output that's already waiting to be sent.
+  Merged,
+  /// The command was held, and is the first one this interval, so the merged output needs sending
+  /// after the given delay.
+  FlushAfter(Duration),
+}
+
+#[derive(Default)]
+struct CoalescerState {
+  /// When output was last sent to the device.
+  last_sent: Option<Instant>,
+  /// Output merged since then, waiting for the interval to finish.
+  pending: Option<RequestedOutput>,
+}
+
+pub(super) struct CommandCoalescer {
+  interval: Duration,
+  state: Mutex<CoalescerState>,
+  /// Held while merged output is sent, so stops can wait for it instead of racing it.
+  flushing: tokio::sync::Mutex<()>,
+}
+
+impl CommandCoalescer {
+  /// Coalescer sending output at most `max_update_rate` times a second. Rates of zero aren't
+  /// limited.
+  pub fn new(max_update_rate: u32) -> Option<Self> {
+    if max_update_rate == 0 {
+      return None;
+    }
+    Some(Self {
+      interval: Duration::from_secs(1) / max_update_rate,
+      state: Mutex::new(CoalescerState::default()),
+      flushing: tokio::sync::Mutex::new(()),
+    })
+  }
+
+  /// Decides whether a command goes out now or waits for the end of the interval. Only scalar and
+  /// rotation output is held, everything else is always sent.
+  pub fn coalesce(&self, command: ButtplugDeviceCommandMessageUnion) -> Coalesced {
+    if !matches!(
+      command,
+      ButtplugDeviceCommandMessageUnion::ScalarCmd(_)
+        | ButtplugDeviceCommandMessageUnion::RotateCmd(_)
+    ) {
+      return Coalesced::Send(command);
+    }
+    let mut state = self
+      .state
+      .lock()
+      .expect("Coalescer state lock should never be poisoned.");
+    let now = Instant::now();
+    let since_sent = match state.last_sent {
+      Some(last_sent) if state.pending.is_some() || now - last_sent < self.interval => {
+        now - last_sent
+      }
+      _ => {
+        state.last_sent = Some(now);
+        return Coalesced::Send(command);
+      }
+    };
+    let first = state.pending.is_none();
+    let pending = state.pending.get_or_insert_with(RequestedOutput::default);
+    match &command {
+      ButtplugDeviceCommandMessageUnion::ScalarCmd(msg) => {
+        pending.record_scalars(msg.scalars().iter())
+      }
+      ButtplugDeviceCommandMessageUnion::RotateCmd(msg) => {
+        pending.record_rotations(msg.rotations().iter())
+      }
+      _ => unreachable!("Only output commands are held."),
+    }
+    if first {
+      Coalesced::FlushAfter(self.interval.saturating_sub(since_sent))
+    } else {
+      Coalesced::Merged
+    }
+  }
+
+  /// Takes the merged output waiting to be sent, starting a new interval if there was any.
+  pub fn take_pending(&self) -> Vec<ButtplugDeviceCommandMessageUnion> {
+    let mut state = self
+      .state
+      .lock()
+      .expect("Coalescer state lock should never be poisoned.");
+    match state.pending.take() {
+      Some(pending) => {
+        state.last_sent = Some(Instant::now());
+        pending.commands()
+      }
+      None => vec![],
+    }
+  }
+
+  /// Throws away the output waiting to be sent, for when newer output replaces it.
+  pub fn clear(&self) {
+    self
+      .state
+      .lock()
+      .expect("Coalescer state lock should never be poisoned.")
+      .pending = None;
+  }
+
+  /// Held while sending merged output.
+  pub async fn flushing(&self) -> tokio::sync::MutexGuard<'_, ()> {
+    self.flushing.lock().await
+  }
+
+  /// Waits for merged output that's being sent to finish.
+  pub async fn wait_for_flush(&self) {
+    let _flushing = self.flushing.lock().await;
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::core::message::{ActuatorType, ScalarCmd, ScalarSubcommand};
+
+  fn vibrate(index: u32, scalar: f64) -> ButtplugDeviceCommandMessageUnion {
+    ScalarCmd::new(
+      0,
+      vec![ScalarSubcommand::new(index, scalar, ActuatorType::Vibrate)],
+    )
+    .into()
+  }
+
+  #[test]
+  fn test_coalesce_within_interval() {
+    let coalescer = CommandCoalescer::new(10).expect("Test, assuming infallible.");
+    assert!(matches!(
+      coalescer.coalesce(vibrate(0, 0.1)),
+      Coalesced::Send(_)
+    ));
+    assert!(matches!(
+      coalescer.coalesce(vibrate(0, 0.2)),
+      Coalesced::FlushAfter(delay) if delay <= Duration::from_millis(100)
+    ));
+    assert!(matches!(
+      coalescer.coalesce(vibrate(1, 0.3)),
+      Coalesced::Merged
+    ));
+    assert!(matches!(
+      coalescer.coalesce(vibrate(0, 0.4)),
+      Coalesced::Merged
+    ));
+    // Latest value per feature wins.
+    assert_eq!(
+      coalescer.take_pending(),
+      vec![ButtplugDeviceCommandMessageUnion::from(ScalarCmd::new(
+        0,
+        vec![
+          ScalarSubcommand::new(0, 0.4, ActuatorType::Vibrate),
+          ScalarSubcommand::new(1, 0.3, ActuatorType::Vibrate)
+        ]
+      ))]
+    );
+    assert!(coalescer.take_pending().is_empty());
+    // Taking the merged output starts a new interval.
+    assert!(matches!(
+      coalescer.coalesce(vibrate(0, 0.5)),
+      Coalesced::FlushAfter(_)
+    ));
+    coalescer.clear();
+    assert!(coalescer.take_pending().is_empty());
+    assert!(CommandCoalescer::new(0).is_none());
+  }
+}
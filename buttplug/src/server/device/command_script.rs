@@ -0,0 +1,463 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! User scripts that rewrite or generate device commands on the server.
+
+use super::ServerDevice;
+use crate::core::{
+  errors::{ButtplugDeviceError, ButtplugError},
+  message::{ActuatorType, ButtplugDeviceMessage, ScalarCmd, ScalarSubcommand, SensorReading},
+};
+use dashmap::DashMap;
+use futures::future::{self, BoxFuture, FutureExt};
+use getset::CopyGetters;
+use rhai::{Array, CallFnOptions, Dynamic, Engine, Map, Scope, AST};
+use std::{
+  sync::{Arc, Mutex},
+  time::{Duration, Instant},
+};
+
+const ON_COMMAND: &str = "on_command";
+const ON_SENSOR: &str = "on_sensor";
+
+/// Bounds on how much work a single script call can do, so a broken or hostile script can't stall
+/// the server or eat its memory. Calls that go past a limit fail with
+/// [ButtplugDeviceError::CommandScriptError].
+#[derive(Debug, Clone, Copy, CopyGetters)]
+#[getset(get_copy = "pub")]
+pub struct CommandScriptLimits {
+  /// Maximum number of operations (roughly, expressions evaluated) per call.
+  max_operations: u64,
+  /// Maximum wall clock time per call.
+  max_run_time: Duration,
+  /// Maximum depth of nested function calls.
+  max_call_depth: usize,
+  /// Maximum length of any string, in bytes.
+  max_string_size: usize,
+  /// Maximum number of items in any array or map.
+  max_collection_size: usize,
+}
+
+impl Default for CommandScriptLimits {
+  fn default() -> Self {
+    Self {
+      max_operations: 100_000,
+      max_run_time: Duration::from_millis(10),
+      max_call_depth: 16,
+      max_string_size: 1024,
+      max_collection_size: 256,
+    }
+  }
+}
+
+impl CommandScriptLimits {
+  pub fn new(
+    max_operations: u64,
+    max_run_time: Duration,
+    max_call_depth: usize,
+    max_string_size: usize,
+    max_collection_size: usize,
+  ) -> Self {
+    Self {
+      max_operations,
+      max_run_time,
+      max_call_depth,
+      max_string_size,
+      max_collection_size,
+    }
+  }
+}
+
+/// A compiled user script, which rewrites or generates device commands on the server.
+///
+/// Scripts are written in [Rhai](https://rhai.rs) and can define either or both of these functions:
+///
+/// - `on_command(cmd)`: Called with every ScalarCmd a client sends. Return an array of commands to
+///   send instead, which can target any device and can be empty to drop the command, or return
+///   nothing to send the original command unchanged.
+/// - `on_sensor(reading)`: Called with every sensor reading a device sends. Return an array of
+///   commands to send, or nothing.
+///
+/// Commands look like `#{ device: 0, scalars: [#{ index: 0, scalar: 0.5, actuator: "Vibrate" }] }`
+/// and readings look like `#{ device: 0, sensor_index: 0, sensor_type: "Pressure", data: [120] }`.
+/// Mirroring device 0 to device 1 at 80% looks like:
+///
+/// ```text
+/// fn on_command(cmd) {
+///   if cmd.device != 0 { return; }
+///   let mirrored = #{ device: 1, scalars: [] };
+///   for s in cmd.scalars {
+///     mirrored.scalars.push(#{ index: s.index, scalar: s.scalar * 0.8, actuator: s.actuator });
+///   }
+///   [cmd, mirrored]
+/// }
+/// ```
+///
+/// Commands returned by scripts are sent straight to devices, without going through `on_command`
+/// again. Each call starts from a clean scope, so scripts can't keep state between calls, and every
+/// call is bounded by [CommandScriptLimits].
+pub struct CommandScript {
+  engine: Engine,
+  ast: AST,
+  limits: CommandScriptLimits,
+  /// Held for the whole of each call, as the engine's progress check can only see one deadline.
+  call_lock: Mutex<()>,
+  /// Time the running call has to finish by.
+  deadline: Arc<Mutex<Option<Instant>>>,
+  has_on_command: bool,
+  has_on_sensor: bool,
+}
+
+impl CommandScript {
+  pub fn new(script: &str, limits: CommandScriptLimits) -> Result<Self, ButtplugDeviceError> {
+    let mut engine = Engine::new();
+    engine
+      .set_max_operations(limits.max_operations)
+      .set_max_call_levels(limits.max_call_depth)
+      .set_max_string_size(limits.max_string_size)
+      .set_max_array_size(limits.max_collection_size)
+      .set_max_map_size(limits.max_collection_size)
+      // Rhai's defaults are lower in debug builds, and too low for nested command literals in
+      // functions, so pin them.
+      .set_max_expr_depths(64, 64)
+      .disable_symbol("eval");
+    let deadline: Arc<Mutex<Option<Instant>>> = Arc::new(Mutex::new(None));
+    let progress_deadline = deadline.clone();
+    engine.on_progress(move |_| {
+      let expired = matches!(
+        *progress_deadline
+          .lock()
+          .expect("Script deadline lock should never be poisoned."),
+        Some(deadline) if Instant::now() > deadline
+      );
+      if expired {
+        Some("Script ran past its time limit".into())
+      } else {
+        None
+      }
+    });
+    let ast = engine
+      .compile(script)
+      .map_err(|e| ButtplugDeviceError::CommandScriptError(e.to_string()))?;
+    let has_function = |name: &str| {
+      ast
+        .iter_functions()
+        .any(|function| function.name == name && function.params.len() == 1)
+    };
+    let has_on_command = has_function(ON_COMMAND);
+    let has_on_sensor = has_function(ON_SENSOR);
+    if !has_on_command && !has_on_sensor {
+      return Err(ButtplugDeviceError::CommandScriptError(format!(
+        "Script must define {}(cmd) or {}(reading)",
+        ON_COMMAND, ON_SENSOR
+      )));
+    }
+    Ok(Self {
+      engine,
+      ast,
+      limits,
+      call_lock: Mutex::new(()),
+      deadline,
+      has_on_command,
+      has_on_sensor,
+    })
+  }
+
+  /// Runs `on_command` for a command sent by a client. Returns None if the script doesn't define
+  /// `on_command` or left the command alone.
+  pub fn on_command(&self, msg: &ScalarCmd) -> Result<Option<Vec<ScalarCmd>>, ButtplugDeviceError> {
+    if !self.has_on_command {
+      return Ok(None);
+    }
+    self.call(ON_COMMAND, scalar_cmd_to_dynamic(msg))
+  }
+
+  /// Runs `on_sensor` for a sensor reading sent by a device. Returns the commands the script
+  /// generated, which may be none.
+  pub fn on_sensor(&self, reading: &SensorReading) -> Result<Vec<ScalarCmd>, ButtplugDeviceError> {
+    if !self.has_on_sensor {
+      return Ok(vec![]);
+    }
+    Ok(
+      self
+        .call(ON_SENSOR, sensor_reading_to_dynamic(reading))?
+        .unwrap_or_default(),
+    )
+  }
+
+  fn call(&self, name: &str, arg: Dynamic) -> Result<Option<Vec<ScalarCmd>>, ButtplugDeviceError> {
+    let _call_guard = self
+      .call_lock
+      .lock()
+      .expect("Script call lock should never be poisoned.");
+    *self
+      .deadline
+      .lock()
+      .expect("Script deadline lock should never be poisoned.") =
+      Some(Instant::now() + self.limits.max_run_time);
+    let result = self
+      .engine
+      .call_fn_with_options::<Dynamic>(
+        CallFnOptions::new().eval_ast(false),
+        &mut Scope::new(),
+        &self.ast,
+        name,
+        (arg,),
+      )
+      .map_err(|e| ButtplugDeviceError::CommandScriptError(format!("{}: {}", name, e)))?;
+    if result.is_unit() {
+      return Ok(None);
+    }
+    let commands = result.try_cast::<Array>().ok_or_else(|| {
+      ButtplugDeviceError::CommandScriptError(format!(
+        "{} must return an array of commands or nothing",
+        name
+      ))
+    })?;
+    commands
+      .into_iter()
+      .map(scalar_cmd_from_dynamic)
+      .collect::<Result<Vec<_>, _>>()
+      .map(Some)
+  }
+}
+
+/// Sends commands a script returned to their devices, resolving once all of them have been handled.
+/// Fails with the first error if any device is missing or rejects its command.
+pub(super) fn send_script_commands(
+  devices: &DashMap<u32, Arc<ServerDevice>>,
+  commands: Vec<ScalarCmd>,
+) -> BoxFuture<'static, Result<(), ButtplugError>> {
+  let mut futures = vec![];
+  for command in commands {
+    let device_index = command.device_index();
+    match devices.get(&device_index) {
+      Some(device) => futures.push(device.parse_message(command.into())),
+      None => return ButtplugDeviceError::DeviceNotAvailable(device_index).into(),
+    }
+  }
+  async move {
+    for result in future::join_all(futures).await {
+      result?;
+    }
+    Ok(())
+  }
+  .boxed()
+}
+
+fn scalar_cmd_to_dynamic(msg: &ScalarCmd) -> Dynamic {
+  let scalars: Array = msg
+    .scalars()
+    .iter()
+    .map(|scalar| {
+      let mut map = Map::new();
+      map.insert("index".into(), (scalar.index() as i64).into());
+      map.insert("scalar".into(), scalar.scalar().into());
+      map.insert("actuator".into(), scalar.actuator_type().to_string().into());
+      map.into()
+    })
+    .collect();
+  let mut map = Map::new();
+  map.insert("device".into(), (msg.device_index() as i64).into());
+  map.insert("scalars".into(), scalars.into());
+  map.into()
+}
+
+fn sensor_reading_to_dynamic(reading: &SensorReading) -> Dynamic {
+  let data: Array = reading
+    .data()
+    .iter()
+    .map(|value| (*value as i64).into())
+    .collect();
+  let mut map = Map::new();
+  map.insert("device".into(), (reading.device_index() as i64).into());
+  map.insert(
+    "sensor_index".into(),
+    (reading.sensor_index() as i64).into(),
+  );
+  map.insert(
+    "sensor_type".into(),
+    reading.sensor_type().to_string().into(),
+  );
+  map.insert("data".into(), data.into());
+  map.into()
+}
+
+fn script_type_error(expected: &str) -> ButtplugDeviceError {
+  ButtplugDeviceError::CommandScriptError(format!("Script returned a command without {}", expected))
+}
+
+fn get_index(map: &Map, key: &str) -> Result<u32, ButtplugDeviceError> {
+  map
+    .get(key)
+    .and_then(|value| value.as_int().ok())
+    .and_then(|value| u32::try_from(value).ok())
+    .ok_or_else(|| script_type_error(&format!("a valid \"{}\"", key)))
+}
+
+fn scalar_cmd_from_dynamic(value: Dynamic) -> Result<ScalarCmd, ButtplugDeviceError> {
+  let map = value
+    .try_cast::<Map>()
+    .ok_or_else(|| script_type_error("being a map"))?;
+  let scalars = map
+    .get("scalars")
+    .and_then(|scalars| scalars.clone().try_cast::<Array>())
+    .ok_or_else(|| script_type_error("a \"scalars\" array"))?
+    .into_iter()
+    .map(|scalar| {
+      let scalar = scalar
+        .try_cast::<Map>()
+        .ok_or_else(|| script_type_error("scalars being maps"))?;
+      let value = scalar
+        .get("scalar")
+        .and_then(|value| {
+          value
+            .as_float()
+            .ok()
+            .or_else(|| value.as_int().ok().map(|value| value as f64))
+        })
+        .ok_or_else(|| script_type_error("a numeric \"scalar\""))?;
+      let actuator: ActuatorType = scalar
+        .get("actuator")
+        .and_then(|actuator| actuator.clone().into_string().ok())
+        .and_then(|actuator| serde_json::from_value(serde_json::Value::String(actuator)).ok())
+        .ok_or_else(|| script_type_error("a known \"actuator\""))?;
+      Ok(ScalarSubcommand::new(
+        get_index(&scalar, "index")?,
+        value,
+        actuator,
+      ))
+    })
+    .collect::<Result<Vec<_>, ButtplugDeviceError>>()?;
+  Ok(ScalarCmd::new(get_index(&map, "device")?, scalars))
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::core::message::SensorType;
+
+  fn vibrate(device_index: u32, scalar: f64) -> ScalarCmd {
+    ScalarCmd::new(
+      device_index,
+      vec![ScalarSubcommand::new(0, scalar, ActuatorType::Vibrate)],
+    )
+  }
+
+  #[test]
+  fn test_command_script_mirror() {
+    let script = CommandScript::new(
+      r#"
+      fn on_command(cmd) {
+        if cmd.device != 0 { return; }
+        let mirrored = #{ device: 1, scalars: [] };
+        for s in cmd.scalars {
+          mirrored.scalars.push(#{ index: s.index, scalar: s.scalar * 0.8, actuator: s.actuator });
+        }
+        [cmd, mirrored]
+      }
+      "#,
+      CommandScriptLimits::default(),
+    )
+    .expect("Test, assuming infallible");
+    assert_eq!(
+      script
+        .on_command(&vibrate(0, 0.5))
+        .expect("Test, assuming infallible"),
+      Some(vec![vibrate(0, 0.5), vibrate(1, 0.4)])
+    );
+    assert_eq!(
+      script
+        .on_command(&vibrate(1, 0.5))
+        .expect("Test, assuming infallible"),
+      None
+    );
+    // Scripts without on_sensor never generate commands from readings.
+    assert!(script
+      .on_sensor(&SensorReading::new(0, 0, SensorType::Pressure, vec![100]))
+      .expect("Test, assuming infallible")
+      .is_empty());
+  }
+
+  #[test]
+  fn test_command_script_sensor() {
+    let script = CommandScript::new(
+      r#"
+      fn on_sensor(reading) {
+        if reading.sensor_type == "Pressure" && reading.data[0] > 200 {
+          [#{ device: 1, scalars: [#{ index: 0, scalar: 1, actuator: "Vibrate" }] }]
+        }
+      }
+      "#,
+      CommandScriptLimits::default(),
+    )
+    .expect("Test, assuming infallible");
+    let pressure = |value| SensorReading::new(0, 0, SensorType::Pressure, vec![value]);
+    assert_eq!(
+      script
+        .on_sensor(&pressure(250))
+        .expect("Test, assuming infallible"),
+      vec![vibrate(1, 1.0)]
+    );
+    assert!(script
+      .on_sensor(&pressure(100))
+      .expect("Test, assuming infallible")
+      .is_empty());
+    // Commands pass through scripts without on_command.
+    assert_eq!(
+      script
+        .on_command(&vibrate(0, 0.5))
+        .expect("Test, assuming infallible"),
+      None
+    );
+  }
+
+  #[test]
+  fn test_command_script_errors() {
+    let limits = CommandScriptLimits::default();
+    assert!(matches!(
+      CommandScript::new("fn on_command(cmd) {", limits),
+      Err(ButtplugDeviceError::CommandScriptError(_))
+    ));
+    assert!(matches!(
+      CommandScript::new("fn something_else(cmd) { }", limits),
+      Err(ButtplugDeviceError::CommandScriptError(_))
+    ));
+    let bad_return =
+      CommandScript::new("fn on_command(cmd) { 5 }", limits).expect("Test, assuming infallible");
+    assert!(bad_return.on_command(&vibrate(0, 0.5)).is_err());
+    let bad_actuator = CommandScript::new(
+      r#"fn on_command(cmd) { cmd.scalars[0].actuator = "Explode"; [cmd] }"#,
+      limits,
+    )
+    .expect("Test, assuming infallible");
+    assert!(bad_actuator.on_command(&vibrate(0, 0.5)).is_err());
+  }
+
+  #[test]
+  fn test_command_script_limits() {
+    let script = CommandScript::new(
+      "fn on_command(cmd) { loop { } }",
+      CommandScriptLimits::new(u64::MAX, Duration::from_millis(10), 16, 1024, 256),
+    )
+    .expect("Test, assuming infallible");
+    // Stopped by the time limit, as the operation limit is effectively off.
+    assert!(script.on_command(&vibrate(0, 0.5)).is_err());
+    let script = CommandScript::new(
+      "fn on_command(cmd) { loop { } }",
+      CommandScriptLimits::new(1000, Duration::from_secs(60), 16, 1024, 256),
+    )
+    .expect("Test, assuming infallible");
+    assert!(script.on_command(&vibrate(0, 0.5)).is_err());
+    let script = CommandScript::new(
+      "fn on_command(cmd) { let a = []; loop { a.push(1); } }",
+      CommandScriptLimits::new(u64::MAX, Duration::from_secs(60), 16, 1024, 256),
+    )
+    .expect("Test, assuming infallible");
+    assert!(script.on_command(&vibrate(0, 0.5)).is_err());
+  }
+}
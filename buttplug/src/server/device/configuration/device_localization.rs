@@ -0,0 +1,76 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use getset::{Getters, Setters};
+use serde::{Deserialize, Serialize};
+
+/// Display name and description of a device in one language, for clients that aren't showing
+/// English.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, Getters, Setters)]
+#[getset(get = "pub", set = "pub")]
+pub struct DeviceLocalization {
+  #[serde(
+    rename = "display-name",
+    default,
+    skip_serializing_if = "Option::is_none"
+  )]
+  display_name: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  description: Option<String>,
+}
+
+impl DeviceLocalization {
+  pub fn new(display_name: Option<String>, description: Option<String>) -> Self {
+    Self {
+      display_name,
+      description,
+    }
+  }
+
+  /// Fills in anything this localization is missing from `fallback`.
+  pub(super) fn or(&self, fallback: &DeviceLocalization) -> Self {
+    Self {
+      display_name: self
+        .display_name
+        .clone()
+        .or_else(|| fallback.display_name.clone()),
+      description: self
+        .description
+        .clone()
+        .or_else(|| fallback.description.clone()),
+    }
+  }
+}
+
+/// Language tags to look for when a client asks for `locale`, from most to least specific, e.g.
+/// "zh-hant-tw", "zh-hant", "zh". Tags are compared without case, so they're all lowercased.
+pub(super) fn language_tag_fallbacks(locale: &str) -> Vec<String> {
+  let locale = locale.trim().replace('_', "-").to_lowercase();
+  let mut tags = vec![];
+  let mut tag = locale.as_str();
+  while !tag.is_empty() {
+    tags.push(tag.to_owned());
+    tag = tag.rfind('-').map_or("", |index| &tag[..index]);
+  }
+  tags
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_language_tag_fallbacks() {
+    assert_eq!(
+      language_tag_fallbacks("zh-Hant-TW"),
+      vec!["zh-hant-tw", "zh-hant", "zh"]
+    );
+    assert_eq!(language_tag_fallbacks("pt_BR"), vec!["pt-br", "pt"]);
+    assert_eq!(language_tag_fallbacks("de"), vec!["de"]);
+    assert!(language_tag_fallbacks("").is_empty());
+  }
+}
@@ -0,0 +1,185 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Protocol attributes that are parsed on first use
+//!
+//! Most sessions only ever see devices from a handful of protocols, so parsing the names and
+//! message attributes of every device the library knows about when the server starts is mostly
+//! wasted work (and memory, on phones and other constrained targets). Protocols can instead be
+//! given a loader that is run the first time a device using the protocol needs its attributes.
+//! Specifiers are still needed up front for matching hardware, so they aren't loaded this way.
+
+use super::{
+  build_attribute_tree,
+  AttributeTree,
+  ProtocolAttributesIdentifier,
+  ProtocolAttributesType,
+  ProtocolDeviceAttributes,
+};
+use crate::core::errors::ButtplugDeviceError;
+use once_cell::sync::OnceCell;
+use std::{collections::HashMap, sync::Arc};
+
+/// Builds the attributes of a single protocol, keyed by the device identifier they apply to.
+pub type ProtocolAttributesLoader = Arc<
+  dyn Fn() -> Result<HashMap<ProtocolAttributesType, ProtocolDeviceAttributes>, ButtplugDeviceError>
+    + Send
+    + Sync,
+>;
+
+/// Attributes of a single protocol that haven't been needed yet.
+pub(super) struct LazyProtocolAttributes {
+  protocol: String,
+  loader: ProtocolAttributesLoader,
+  /// Attributes added to the DCM builder for this protocol (including user configurations), which
+  /// are applied over the loaded attributes.
+  overrides: HashMap<ProtocolAttributesIdentifier, ProtocolDeviceAttributes>,
+  /// Loaded attributes, or None if loading failed.
+  tree: OnceCell<Option<AttributeTree>>,
+}
+
+impl LazyProtocolAttributes {
+  pub fn new(
+    protocol: &str,
+    loader: ProtocolAttributesLoader,
+    overrides: HashMap<ProtocolAttributesIdentifier, ProtocolDeviceAttributes>,
+  ) -> Self {
+    Self {
+      protocol: protocol.to_owned(),
+      loader,
+      overrides,
+      tree: OnceCell::new(),
+    }
+  }
+
+  /// Returns a copy with `overrides` applied over the ones already given, which loads the attributes
+  /// again when they're next needed.
+  pub fn with_overrides(
+    &self,
+    overrides: HashMap<ProtocolAttributesIdentifier, ProtocolDeviceAttributes>,
+  ) -> Self {
+    let mut combined_overrides = self.overrides.clone();
+    combined_overrides.extend(overrides);
+    Self::new(&self.protocol, self.loader.clone(), combined_overrides)
+  }
+
+  /// Returns the attributes of the protocol, loading them if this is the first time they're
+  /// needed. Returns None if they can't be loaded, in which case devices using the protocol can't be
+  /// configured.
+  pub fn attributes(&self) -> Option<&AttributeTree> {
+    self
+      .tree
+      .get_or_init(|| match self.load() {
+        Ok(tree) => Some(tree),
+        Err(err) => {
+          error!(
+            "Cannot load attributes for protocol {}: {:?}",
+            self.protocol, err
+          );
+          None
+        }
+      })
+      .as_ref()
+  }
+
+  /// Loads the attributes now if anything was configured over them, so mistakes in configurations
+  /// show up when the DCM is built instead of when a device connects.
+  pub fn load_if_overridden(&self) -> Result<(), ButtplugDeviceError> {
+    if self.overrides.is_empty() {
+      return Ok(());
+    }
+    let tree = self.load()?;
+    let _ = self.tree.set(Some(tree));
+    Ok(())
+  }
+
+  fn load(&self) -> Result<AttributeTree, ButtplugDeviceError> {
+    debug!("Loading attributes for protocol {}", self.protocol);
+    let mut attributes: HashMap<_, _> = (self.loader)()?
+      .into_iter()
+      .map(|(attributes_identifier, attrs)| {
+        (
+          ProtocolAttributesIdentifier::new(&self.protocol, &attributes_identifier, &None),
+          attrs,
+        )
+      })
+      .collect();
+    attributes.extend(self.overrides.clone());
+    build_attribute_tree(&attributes)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::server::device::configuration::ServerDeviceMessageAttributes;
+  use std::sync::atomic::{AtomicU32, Ordering};
+
+  fn attributes(identifier: ProtocolAttributesType, name: &str) -> ProtocolDeviceAttributes {
+    ProtocolDeviceAttributes::new(
+      identifier,
+      Some(name.to_owned()),
+      None,
+      ServerDeviceMessageAttributes::default(),
+      None,
+    )
+  }
+
+  #[test]
+  fn test_lazy_attributes_load_once_with_overrides() {
+    let loads = Arc::new(AtomicU32::new(0));
+    let loads_clone = loads.clone();
+    let loader: ProtocolAttributesLoader = Arc::new(move || {
+      loads_clone.fetch_add(1, Ordering::SeqCst);
+      Ok(HashMap::from([
+        (
+          ProtocolAttributesType::Default,
+          attributes(ProtocolAttributesType::Default, "Test Device"),
+        ),
+        (
+          ProtocolAttributesType::Identifier("A".to_owned()),
+          attributes(ProtocolAttributesType::Identifier("A".to_owned()), "Test A"),
+        ),
+      ]))
+    });
+    let override_ident = ProtocolAttributesIdentifier::new(
+      "test",
+      &ProtocolAttributesType::Identifier("A".to_owned()),
+      &None,
+    );
+    let lazy = LazyProtocolAttributes::new(
+      "test",
+      loader,
+      HashMap::from([(
+        override_ident.clone(),
+        attributes(ProtocolAttributesType::Identifier("A".to_owned()), "Test A2"),
+      )]),
+    );
+    assert_eq!(loads.load(Ordering::SeqCst), 0);
+    lazy
+      .load_if_overridden()
+      .expect("Test, assuming infallible.");
+    assert_eq!(loads.load(Ordering::SeqCst), 1);
+    let tree = lazy.attributes().expect("Test, assuming infallible.");
+    assert_eq!(tree.len(), 2);
+    assert_eq!(tree[&override_ident].name(), "Test A2");
+    assert!(lazy.attributes().is_some());
+    assert_eq!(loads.load(Ordering::SeqCst), 1);
+  }
+
+  #[test]
+  fn test_lazy_attributes_load_failure() {
+    let loader: ProtocolAttributesLoader = Arc::new(|| {
+      Err(ButtplugDeviceError::DeviceConfigurationError(
+        "Broken".to_owned(),
+      ))
+    });
+    let lazy = LazyProtocolAttributes::new("test", loader, HashMap::new());
+    assert!(lazy.load_if_overridden().is_ok());
+    assert!(lazy.attributes().is_none());
+  }
+}
@@ -137,9 +137,19 @@
 //! ### User Configurations
 //!
 
+mod device_localization;
+mod lazy_attributes;
+mod protocol_suggestion;
 mod server_device_message_attributes;
 pub mod specifier;
+mod specifier_index;
+use device_localization::language_tag_fallbacks;
+pub use device_localization::DeviceLocalization;
+use lazy_attributes::LazyProtocolAttributes;
+pub use lazy_attributes::ProtocolAttributesLoader;
+pub use protocol_suggestion::ProtocolSuggestion;
 pub use specifier::*;
+use specifier_index::SpecifierIndex;
 
 pub use server_device_message_attributes::{
   ServerDeviceMessageAttributes,
@@ -151,20 +161,35 @@ use super::protocol::{get_default_protocol_map, ProtocolIdentifierFactory, Proto
 use crate::{
   core::{
     errors::ButtplugDeviceError,
-    message::{ButtplugDeviceMessageType, Endpoint},
+    message::{ButtplugDeviceMessageType, Endpoint, SensorDeviceMessageAttributes, SensorType},
   },
-  server::device::ServerDeviceIdentifier,
+  server::{
+    device::{
+      hardware::{BluetoothLEConnectionParameters, BluetoothLEWriteCoalescing},
+      EstimInterlock,
+      OveruseProtection,
+      RampPolicy,
+      SensorCalibration,
+      SensorRule,
+      ServerDeviceIdentifier,
+    },
+    persistence::{ButtplugPersistence, PersistedDeviceConfiguration, PersistenceError},
+  },
+  util::address_privacy::display_address,
 };
-use dashmap::DashMap;
+use dashmap::{DashMap, DashSet};
 use derivative::Derivative;
 use getset::{Getters, MutGetters, Setters};
 use serde::{Deserialize, Serialize};
 use std::{
-  collections::HashMap,
+  collections::{HashMap, HashSet},
+  ops::RangeInclusive,
   sync::{
     atomic::{AtomicU32, Ordering},
     Arc,
+    RwLock,
   },
+  time::Duration,
 };
 
 /// Denotes what set of protocols attributes should be used: Default (generic) or device class
@@ -260,6 +285,10 @@ pub struct ProtocolDeviceAttributes {
   display_name: Option<String>,
   /// Message attributes for this device instance.
   pub(super) message_attributes: ServerDeviceMessageAttributes,
+  /// If true, flips rotation direction relative to the parent of this instance.
+  invert_rotation: bool,
+  /// Names and descriptions of the device in other languages, keyed by lowercased language tag.
+  localizations: HashMap<String, DeviceLocalization>,
 }
 
 impl ProtocolDeviceAttributes {
@@ -277,6 +306,8 @@ impl ProtocolDeviceAttributes {
       display_name,
       message_attributes,
       parent,
+      invert_rotation: false,
+      localizations: HashMap::new(),
     }
   }
 
@@ -292,6 +323,8 @@ impl ProtocolDeviceAttributes {
       name: Some(self.name().to_owned()),
       display_name: self.display_name(),
       message_attributes: self.message_attributes(),
+      invert_rotation: self.rotation_inverted(),
+      localizations: self.localizations(),
     }
   }
 
@@ -330,6 +363,97 @@ impl ProtocolDeviceAttributes {
     }
   }
 
+  /// Set the user configured display name for this instance. None falls back to the parent's.
+  pub fn set_display_name(&mut self, display_name: Option<String>) {
+    self.display_name = display_name;
+  }
+
+  /// Set the step range of a ScalarCmd, RotateCmd or LinearCmd feature of this instance. Fails if
+  /// the instance doesn't have the feature, or the range is empty.
+  pub fn set_step_range(
+    &mut self,
+    message_type: ButtplugDeviceMessageType,
+    feature_index: u32,
+    step_range: RangeInclusive<u32>,
+  ) -> Result<(), ButtplugDeviceError> {
+    let mut message_attributes = self.message_attributes();
+    let features = match message_type {
+      ButtplugDeviceMessageType::ScalarCmd => message_attributes.scalar_cmd_mut(),
+      ButtplugDeviceMessageType::RotateCmd => message_attributes.rotate_cmd_mut(),
+      ButtplugDeviceMessageType::LinearCmd => message_attributes.linear_cmd_mut(),
+      _ => return Err(ButtplugDeviceError::MessageNotSupported(message_type)),
+    }
+    .as_mut()
+    .ok_or(ButtplugDeviceError::MessageNotSupported(message_type))?;
+    let feature_count = features.len() as u32;
+    let feature = features
+      .get_mut(feature_index as usize)
+      .ok_or(ButtplugDeviceError::DeviceFeatureIndexError(
+        feature_count,
+        feature_index,
+      ))?;
+    feature.set_step_range(step_range);
+    feature.is_valid(&message_type)?;
+    self.message_attributes = message_attributes;
+    Ok(())
+  }
+
+  /// Return true if the device turns the opposite way from what RotateCmd's clockwise flag
+  /// describes, and commands need their direction flipped. Each level of the attributes tree that
+  /// sets inversion flips the result of the level above it.
+  pub fn rotation_inverted(&self) -> bool {
+    let parent_inverted = self
+      .parent
+      .as_ref()
+      .is_some_and(|parent| parent.rotation_inverted());
+    self.invert_rotation != parent_inverted
+  }
+
+  /// Set whether this instance flips rotation direction relative to its parent.
+  pub fn set_invert_rotation(&mut self, invert_rotation: bool) {
+    self.invert_rotation = invert_rotation;
+  }
+
+  /// Set the names and descriptions of the device in other languages, keyed by language tag.
+  pub fn set_localizations(&mut self, localizations: HashMap<String, DeviceLocalization>) {
+    self.localizations = localizations
+      .into_iter()
+      .map(|(tag, localization)| (tag.to_lowercase(), localization))
+      .collect();
+  }
+
+  /// Return the names and descriptions of the device in other languages, keyed by lowercased
+  /// language tag. Anything this instance doesn't set for a language comes from its parent.
+  pub fn localizations(&self) -> HashMap<String, DeviceLocalization> {
+    let mut localizations = if let Some(parent) = &self.parent {
+      parent.localizations()
+    } else {
+      HashMap::new()
+    };
+    for (tag, localization) in &self.localizations {
+      let merged = if let Some(parent_localization) = localizations.get(tag) {
+        localization.or(parent_localization)
+      } else {
+        localization.clone()
+      };
+      localizations.insert(tag.clone(), merged);
+    }
+    localizations
+  }
+
+  /// Return the name and description of the device for a client asking for `locale`. Falls back
+  /// to less specific language tags (e.g. "de" for "de-AT") for anything missing, and leaves
+  /// anything no tag has unset.
+  pub fn localization(&self, locale: &str) -> DeviceLocalization {
+    let localizations = self.localizations();
+    language_tag_fallbacks(locale)
+      .iter()
+      .filter_map(|tag| localizations.get(tag))
+      .fold(DeviceLocalization::default(), |found, localization| {
+        found.or(localization)
+      })
+  }
+
   /// Check to make sure the message attributes of an instance are valid.
   fn is_valid(&self) -> Result<(), ButtplugDeviceError> {
     if let Some(attrs) = self.message_attributes.scalar_cmd() {
@@ -369,14 +493,25 @@ impl ProtocolDeviceAttributes {
   pub fn add_raw_messages(&mut self, endpoints: &[Endpoint]) {
     self.message_attributes.add_raw_messages(endpoints);
   }
+
+  /// Add a sensor provided by the server to the attributes of this instance. Returns the indexes of
+  /// the sensor for SensorReadCmd and SensorSubscribeCmd.
+  pub fn add_server_sensor(&mut self, sensor: SensorDeviceMessageAttributes) -> (u32, u32) {
+    self.message_attributes.add_server_sensor(sensor)
+  }
 }
 
 #[derive(Default, Clone)]
 pub struct DeviceConfigurationManagerBuilder {
   skip_default_protocols: bool,
   allow_raw_messages: bool,
+  /// Devices given raw message support, for when it isn't allowed for all devices.
+  raw_message_devices: Vec<ServerDeviceIdentifier>,
   communication_specifiers: HashMap<String, Vec<ProtocolCommunicationSpecifier>>,
   protocol_attributes: HashMap<ProtocolAttributesIdentifier, ProtocolDeviceAttributes>,
+  /// Loaders for protocols whose attributes are parsed the first time they're needed, keyed by
+  /// protocol name.
+  lazy_protocol_attributes: HashMap<String, ProtocolAttributesLoader>,
   /// Map of protocol names to their respective protocol instance factories
   protocols: Vec<(String, Arc<dyn ProtocolIdentifierFactory>)>,
   /// Addresses of devices that we will only connect to, if this list is not empty. As these are
@@ -388,12 +523,53 @@ pub struct DeviceConfigurationManagerBuilder {
   /// [ServerDeviceIdentifier].
   denied_addresses: Vec<String>,
   reserved_indexes: Vec<(ServerDeviceIdentifier, u32)>,
+  /// Devices that should have their battery level polled by the server, and how often.
+  battery_poll_intervals: Vec<(ServerDeviceIdentifier, Duration)>,
+  /// Devices that expose their signal strength as an RSSI sensor, and how often subscriptions to it
+  /// are sampled.
+  rssi_sample_intervals: Vec<(ServerDeviceIdentifier, Duration)>,
+  /// Default timeouts for commands sent to devices using a protocol, keyed by protocol name.
+  protocol_command_timeouts: HashMap<String, Duration>,
+  /// Command timeouts for specific devices, overriding the default for their protocol.
+  command_timeouts: Vec<(ServerDeviceIdentifier, Duration)>,
+  /// Default output update rates, in Hz, for devices using a protocol, keyed by protocol name.
+  protocol_max_update_rates: HashMap<String, u32>,
+  /// Output update rates for specific devices, overriding the default for their protocol.
+  max_update_rates: Vec<(ServerDeviceIdentifier, u32)>,
+  /// Connection parameters to request from bluetooth LE devices using a protocol, keyed by protocol
+  /// name.
+  protocol_btle_connection_parameters: HashMap<String, BluetoothLEConnectionParameters>,
+  /// Write coalescing for bluetooth LE devices using a protocol, keyed by protocol name.
+  protocol_btle_write_coalescing: HashMap<String, BluetoothLEWriteCoalescing>,
+  /// Overuse protection for devices using a protocol, keyed by protocol name.
+  protocol_overuse_protections: HashMap<String, OveruseProtection>,
+  /// E-stim interlocks for devices using a protocol, keyed by protocol name.
+  protocol_estim_interlocks: HashMap<String, EstimInterlock>,
+  /// Devices that cap their output for a while after connecting, and for how long.
+  warm_ups: Vec<(ServerDeviceIdentifier, Duration)>,
+  /// Devices that step their output down when stopped, and how long that takes.
+  cool_downs: Vec<(ServerDeviceIdentifier, Duration)>,
+  /// Calibrations for the sensors of specific devices, by sensor index and type.
+  sensor_calibrations: Vec<(ServerDeviceIdentifier, u32, SensorType, SensorCalibration)>,
+  /// Groups of devices presented to clients as a single device, by name.
+  merged_devices: Vec<(String, Vec<ServerDeviceIdentifier>)>,
+  /// Rules driving actuators from sensor readings, run by the server.
+  sensor_rules: Vec<SensorRule>,
+  /// Devices whose output features are presented to clients as devices of their own.
+  split_devices: Vec<ServerDeviceIdentifier>,
+  /// Version of the device configuration file these configurations were loaded from, if any.
+  version: Option<String>,
+  /// Storage for reserved indexes, allow/deny lists and calibrations that outlive the server.
+  persistence: Option<Arc<dyn ButtplugPersistence>>,
 }
 
 impl DeviceConfigurationManagerBuilder {
   pub fn merge(&mut self, other: &DeviceConfigurationManagerBuilder) -> &mut Self {
     self.skip_default_protocols = self.skip_default_protocols || other.skip_default_protocols;
     self.allow_raw_messages = self.allow_raw_messages || other.allow_raw_messages;
+    self
+      .raw_message_devices
+      .extend(other.raw_message_devices.iter().cloned());
     self.communication_specifiers.extend(
       other
         .communication_specifiers
@@ -406,6 +582,9 @@ impl DeviceConfigurationManagerBuilder {
         .iter()
         .map(|(k, v)| (k.clone(), v.clone())),
     );
+    self
+      .lazy_protocol_attributes
+      .extend(other.lazy_protocol_attributes.clone());
     self
       .protocols
       .extend(other.protocols.iter().map(|v| (v.clone())));
@@ -419,6 +598,54 @@ impl DeviceConfigurationManagerBuilder {
       .reserved_indexes
       .extend(other.reserved_indexes.iter().map(|v| (v.clone())));
     self
+      .battery_poll_intervals
+      .extend(other.battery_poll_intervals.iter().cloned());
+    self
+      .rssi_sample_intervals
+      .extend(other.rssi_sample_intervals.iter().cloned());
+    self
+      .protocol_command_timeouts
+      .extend(other.protocol_command_timeouts.clone());
+    self
+      .command_timeouts
+      .extend(other.command_timeouts.iter().cloned());
+    self
+      .protocol_max_update_rates
+      .extend(other.protocol_max_update_rates.clone());
+    self
+      .max_update_rates
+      .extend(other.max_update_rates.iter().cloned());
+    self
+      .protocol_btle_connection_parameters
+      .extend(other.protocol_btle_connection_parameters.clone());
+    self
+      .protocol_btle_write_coalescing
+      .extend(other.protocol_btle_write_coalescing.clone());
+    self
+      .protocol_overuse_protections
+      .extend(other.protocol_overuse_protections.clone());
+    self
+      .protocol_estim_interlocks
+      .extend(other.protocol_estim_interlocks.clone());
+    self.warm_ups.extend(other.warm_ups.iter().cloned());
+    self.cool_downs.extend(other.cool_downs.iter().cloned());
+    self
+      .sensor_calibrations
+      .extend(other.sensor_calibrations.iter().cloned());
+    self
+      .merged_devices
+      .extend(other.merged_devices.iter().cloned());
+    self.sensor_rules.extend(other.sensor_rules.iter().cloned());
+    self
+      .split_devices
+      .extend(other.split_devices.iter().cloned());
+    if other.version.is_some() {
+      self.version = other.version.clone();
+    }
+    if other.persistence.is_some() {
+      self.persistence = other.persistence.clone();
+    }
+    self
   }
 
   pub fn communication_specifier(
@@ -443,6 +670,21 @@ impl DeviceConfigurationManagerBuilder {
     self
   }
 
+  /// Load the attributes of the named protocol with `loader` the first time a device using the
+  /// protocol needs them, instead of when the DCM is built. Attributes added with
+  /// [protocol_attributes](Self::protocol_attributes) for the protocol are applied over whatever the
+  /// loader returns.
+  pub fn lazy_protocol_attributes(
+    &mut self,
+    protocol_name: &str,
+    loader: ProtocolAttributesLoader,
+  ) -> &mut Self {
+    self
+      .lazy_protocol_attributes
+      .insert(protocol_name.to_owned(), loader);
+    self
+  }
+
   /// Add a protocol instance factory for a [ButtplugProtocol]
   pub fn protocol_factory<T>(&mut self, factory: T) -> &mut Self
   where
@@ -464,6 +706,13 @@ impl DeviceConfigurationManagerBuilder {
     self
   }
 
+  /// Add raw message support to the device with the given identifier only, leaving other devices
+  /// without it unless [Self::allow_raw_messages] is also set.
+  pub fn allow_raw_messages_for(&mut self, identifier: &ServerDeviceIdentifier) -> &mut Self {
+    self.raw_message_devices.push(identifier.clone());
+    self
+  }
+
   pub fn allowed_address(&mut self, address: &str) -> &mut Self {
     self.allowed_addresses.push(address.to_owned());
     self
@@ -479,6 +728,190 @@ impl DeviceConfigurationManagerBuilder {
     self
   }
 
+  /// Load reserved indexes, allow/deny lists and sensor calibrations from `persistence` when
+  /// finishing, and save indexes there as they're given to devices. See
+  /// [PersistedDeviceConfiguration].
+  pub fn persistence(&mut self, persistence: Arc<dyn ButtplugPersistence>) -> &mut Self {
+    self.persistence = Some(persistence);
+    self
+  }
+
+  /// Poll the battery level of the device with the given identifier every `interval`, emitting
+  /// updated readings as events.
+  pub fn battery_poll_interval(
+    &mut self,
+    identifier: &ServerDeviceIdentifier,
+    interval: Duration,
+  ) -> &mut Self {
+    self
+      .battery_poll_intervals
+      .push((identifier.clone(), interval));
+    self
+  }
+
+  /// Expose the signal strength of the device with the given identifier as an RSSI sensor, with
+  /// subscriptions to it reading the signal strength every `interval`.
+  pub fn rssi_sample_interval(
+    &mut self,
+    identifier: &ServerDeviceIdentifier,
+    interval: Duration,
+  ) -> &mut Self {
+    self
+      .rssi_sample_intervals
+      .push((identifier.clone(), interval));
+    self
+  }
+
+  /// Fail commands sent to devices using the named protocol if they take longer than `timeout`.
+  pub fn protocol_command_timeout(&mut self, protocol_name: &str, timeout: Duration) -> &mut Self {
+    self
+      .protocol_command_timeouts
+      .insert(protocol_name.to_owned(), timeout);
+    self
+  }
+
+  /// Fail commands sent to the device with the given identifier if they take longer than `timeout`.
+  /// Takes precedence over any timeout set for the protocol.
+  pub fn command_timeout(
+    &mut self,
+    identifier: &ServerDeviceIdentifier,
+    timeout: Duration,
+  ) -> &mut Self {
+    self.command_timeouts.push((identifier.clone(), timeout));
+    self
+  }
+
+  /// Send output to devices using the named protocol at most `rate` times a second, merging
+  /// anything sent in between.
+  pub fn protocol_max_update_rate(&mut self, protocol_name: &str, rate: u32) -> &mut Self {
+    self
+      .protocol_max_update_rates
+      .insert(protocol_name.to_owned(), rate);
+    self
+  }
+
+  /// Send output to the device with the given identifier at most `rate` times a second, merging
+  /// anything sent in between. Takes precedence over any rate set for the protocol.
+  pub fn max_update_rate(&mut self, identifier: &ServerDeviceIdentifier, rate: u32) -> &mut Self {
+    self.max_update_rates.push((identifier.clone(), rate));
+    self
+  }
+
+  /// Request `parameters` from bluetooth LE devices using the named protocol once they connect, on
+  /// platforms that allow it.
+  pub fn protocol_btle_connection_parameters(
+    &mut self,
+    protocol_name: &str,
+    parameters: BluetoothLEConnectionParameters,
+  ) -> &mut Self {
+    self
+      .protocol_btle_connection_parameters
+      .insert(protocol_name.to_owned(), parameters);
+    self
+  }
+
+  /// Merge rapid writes to bluetooth LE devices using the named protocol, as described by
+  /// `coalescing`.
+  pub fn protocol_btle_write_coalescing(
+    &mut self,
+    protocol_name: &str,
+    coalescing: BluetoothLEWriteCoalescing,
+  ) -> &mut Self {
+    self
+      .protocol_btle_write_coalescing
+      .insert(protocol_name.to_owned(), coalescing);
+    self
+  }
+
+  /// Step devices using the named protocol down once they've run hard for longer than `protection`
+  /// allows.
+  pub fn protocol_overuse_protection(
+    &mut self,
+    protocol_name: &str,
+    protection: OveruseProtection,
+  ) -> &mut Self {
+    self
+      .protocol_overuse_protections
+      .insert(protocol_name.to_owned(), protection);
+    self
+  }
+
+  /// Treat devices using the named protocol as e-stim, putting their output through `interlock`.
+  /// They refuse output until armed, ramp up from nothing once armed, and are limited in how fast
+  /// their output can rise.
+  pub fn protocol_estim_interlock(
+    &mut self,
+    protocol_name: &str,
+    interlock: EstimInterlock,
+  ) -> &mut Self {
+    self
+      .protocol_estim_interlocks
+      .insert(protocol_name.to_owned(), interlock);
+    self
+  }
+
+  /// Cap the output of the device with the given identifier for `duration` after it connects, with
+  /// the cap rising from nothing to full output over that time.
+  pub fn warm_up(&mut self, identifier: &ServerDeviceIdentifier, duration: Duration) -> &mut Self {
+    self.warm_ups.push((identifier.clone(), duration));
+    self
+  }
+
+  /// Step the output of the device with the given identifier down to zero over `duration` when it
+  /// is stopped, instead of stopping it at once.
+  pub fn cool_down(
+    &mut self,
+    identifier: &ServerDeviceIdentifier,
+    duration: Duration,
+  ) -> &mut Self {
+    self.cool_downs.push((identifier.clone(), duration));
+    self
+  }
+
+  /// Calibrate readings from a sensor of the device with the given identifier before they reach
+  /// clients.
+  pub fn sensor_calibration(
+    &mut self,
+    identifier: &ServerDeviceIdentifier,
+    sensor_index: u32,
+    sensor_type: SensorType,
+    calibration: SensorCalibration,
+  ) -> &mut Self {
+    self
+      .sensor_calibrations
+      .push((identifier.clone(), sensor_index, sensor_type, calibration));
+    self
+  }
+
+  /// Present the devices with the given identifiers to clients as a single device with the given
+  /// name, whenever all of them are connected.
+  pub fn merged_device(&mut self, name: &str, members: &[ServerDeviceIdentifier]) -> &mut Self {
+    self
+      .merged_devices
+      .push((name.to_owned(), members.to_vec()));
+    self
+  }
+
+  /// Have the server run a rule driving an actuator from readings of a sensor, whether or not a
+  /// client is connected. See [SensorRule] for details.
+  pub fn sensor_rule(&mut self, rule: SensorRule) -> &mut Self {
+    self.sensor_rules.push(rule);
+    self
+  }
+
+  /// Present each output feature of the device with the given identifier to clients as a device of
+  /// its own, alongside the device itself.
+  pub fn split_device(&mut self, identifier: &ServerDeviceIdentifier) -> &mut Self {
+    self.split_devices.push(identifier.clone());
+    self
+  }
+
+  /// Set the version of the device configuration file this builder was loaded from.
+  pub fn version(&mut self, version: &str) -> &mut Self {
+    self.version = Some(version.to_owned());
+    self
+  }
+
   pub fn finish(&mut self) -> Result<DeviceConfigurationManager, ButtplugDeviceError> {
     // Map of protocol names to their respective protocol instance factories
     let mut protocol_map = if !self.skip_default_protocols {
@@ -494,112 +927,277 @@ impl DeviceConfigurationManagerBuilder {
       protocol_map.insert(name.clone(), protocol.clone());
     }
 
-    // Build and validate the protocol attributes tree.
-    let mut attribute_tree_map = HashMap::new();
-
-    // Add all the defaults first, they won't have parent attributes.
-    for (ident, attr) in self.protocol_attributes.iter().filter(|(ident, _)| {
-      ident.attributes_identifier == ProtocolAttributesType::Default && ident.address.is_none()
-    }) {
-      // If we don't have a protocol loaded for this configuration block, just drop it. We can't do
-      // anything with it anyways.
-      if !protocol_map.contains_key(&ident.protocol) {
-        continue;
-      }
-      attribute_tree_map.insert(ident.clone(), Arc::new(attr.clone()));
-    }
-
-    // Then add in everything that has a identifier but not an address, and possibly set their parents.
-    for (ident, attr) in self.protocol_attributes.iter().filter(|(ident, _)| {
-      matches!(
-        ident.attributes_identifier,
-        ProtocolAttributesType::Identifier(_)
-      ) && ident.address.is_none()
-    }) {
-      // If we don't have a protocol loaded for this configuration block, just drop it. We can't do
-      // anything with it anyways.
-      if !protocol_map.contains_key(&ident.protocol) {
-        continue;
-      }
-      if let Some(parent) = attribute_tree_map.get(&ProtocolAttributesIdentifier {
-        address: None,
-        protocol: ident.protocol.clone(),
-        attributes_identifier: ProtocolAttributesType::Default,
-      }) {
-        let attr_with_parent = attr.new_with_parent(parent.clone());
-        attribute_tree_map.insert(ident.clone(), Arc::new(attr_with_parent));
-      } else {
-        attribute_tree_map.insert(ident.clone(), Arc::new(attr.clone()));
-      }
-    }
-
-    // Finally, add in user configurations, which will have an address.
+    // Attributes for protocols we have no implementation for get dropped, we can't do anything with
+    // them anyways. Attributes for lazily loaded protocols are held back and layered over the
+    // loaded attributes once the protocol is needed.
+    let mut eager_attributes = HashMap::new();
+    let mut deferred_attributes: HashMap<String, HashMap<_, _>> = HashMap::new();
     for (ident, attr) in self
       .protocol_attributes
       .iter()
-      .filter(|(ident, _)| ident.address.is_some())
+      .filter(|(ident, _)| protocol_map.contains_key(&ident.protocol))
     {
-      // If we don't have a protocol loaded for this configuration block, just drop it. We can't do
-      // anything with it anyways.
-      if !protocol_map.contains_key(&ident.protocol) {
-        continue;
-      }
-
-      // The protocol and attribute identifier of a user config will be its parent. If that doesn't exist, error.
-      if let Some(parent) = attribute_tree_map.get(&ProtocolAttributesIdentifier {
-        address: None,
-        protocol: ident.protocol.clone(),
-        attributes_identifier: ident.attributes_identifier.clone(),
-      }) {
-        let attr_with_parent = attr.new_with_parent(parent.clone());
-        attribute_tree_map.insert(ident.clone(), Arc::new(attr_with_parent));
-      } else if let Some(parent) = attribute_tree_map.get(&ProtocolAttributesIdentifier {
-        address: None,
-        protocol: ident.protocol.clone(),
-        attributes_identifier: ProtocolAttributesType::Default,
-      }) {
-        // There are some cases where protocols will hand back identifiers even though we don't have
-        // any in the config (i.e. new devices we haven't added specializations for yet). In that
-        // case, fall back to the default.
-        let attr_with_parent = attr.new_with_parent(parent.clone());
-        attribute_tree_map.insert(ident.clone(), Arc::new(attr_with_parent));
+      if self.lazy_protocol_attributes.contains_key(&ident.protocol) {
+        deferred_attributes
+          .entry(ident.protocol.clone())
+          .or_default()
+          .insert(ident.clone(), attr.clone());
       } else {
-        return Err(ButtplugDeviceError::DeviceConfigurationError(format!("User configuration {:?} does not have a parent type, cannot create configuration. Please remove this user configuration, or make sure it has a parent.", ident)));
+        eager_attributes.insert(ident.clone(), attr.clone());
       }
     }
+    let attribute_tree_map = build_attribute_tree(&eager_attributes)?;
+    let lazy_protocol_attributes = self
+      .lazy_protocol_attributes
+      .iter()
+      .filter(|(name, _)| protocol_map.contains_key(*name))
+      .map(|(name, loader)| {
+        (
+          name.clone(),
+          Arc::new(LazyProtocolAttributes::new(
+            name,
+            loader.clone(),
+            deferred_attributes.remove(name).unwrap_or_default(),
+          )),
+        )
+      })
+      .collect::<HashMap<_, _>>();
+    for lazy_attributes in lazy_protocol_attributes.values() {
+      lazy_attributes.load_if_overridden()?;
+    }
 
     // Align the implementation, communication specifier, and attribute maps so we only keep what we
     // can actually use.
 
+    let persisted_configuration = self
+      .persistence
+      .clone()
+      .map(PersistedDeviceConfiguration::new);
+    let mut allowed_addresses = self.allowed_addresses.clone();
+    let mut denied_addresses = self.denied_addresses.clone();
+    let mut persisted_indexes = vec![];
+    let mut persisted_display_names = vec![];
+    let mut calibration_list = self.sensor_calibrations.clone();
+    if let Some(persisted) = &persisted_configuration {
+      let persistence_error = |err: PersistenceError| {
+        ButtplugDeviceError::DeviceConfigurationError(format!(
+          "Cannot load persisted device configuration: {}",
+          err
+        ))
+      };
+      allowed_addresses.extend(persisted.allowed_addresses().map_err(persistence_error)?);
+      denied_addresses.extend(persisted.denied_addresses().map_err(persistence_error)?);
+      persisted_indexes = persisted.reserved_indexes().map_err(persistence_error)?;
+      calibration_list.extend(persisted.sensor_calibrations().map_err(persistence_error)?);
+      persisted_display_names = persisted.display_names().map_err(persistence_error)?;
+    }
+
+    if let Some(address) = allowed_addresses
+      .iter()
+      .find(|address| denied_addresses.contains(address))
+    {
+      return Err(ButtplugDeviceError::DeviceConfigurationError(format!(
+        "Device address {} is in both the allowed and denied address lists.",
+        address
+      )));
+    }
+
     let reserved_indexes = DashMap::new();
     for (identifier, index) in &self.reserved_indexes {
-      if reserved_indexes.contains_key(identifier) {
-        // TODO Fill in error
+      if let Some(existing_index) = reserved_indexes.get(identifier) {
+        if *existing_index != *index {
+          return Err(ButtplugDeviceError::DeviceConfigurationError(format!(
+            "Device {:?} has conflicting reserved indexes {} and {}.",
+            identifier, *existing_index, index
+          )));
+        }
+        continue;
       }
       if reserved_indexes.iter().any(|pair| *pair == *index) {
-        // TODO Fill in error
+        return Err(ButtplugDeviceError::DeviceConfigurationError(format!(
+          "Device index {} is reserved for more than one device.",
+          index
+        )));
       }
       reserved_indexes.insert(identifier.clone(), *index);
     }
+    // Persisted indexes were handed out by earlier servers, so they give way to anything configured
+    // since.
+    for (identifier, index) in persisted_indexes {
+      if reserved_indexes.contains_key(&identifier)
+        || reserved_indexes.iter().any(|pair| *pair == index)
+      {
+        info!(
+          "Persisted index {} for device {:?} conflicts with configuration, ignoring.",
+          index, identifier
+        );
+        continue;
+      }
+      reserved_indexes.insert(identifier, index);
+    }
+
+    for rule in &self.sensor_rules {
+      if !(0.0..=1.0).contains(&rule.output().scalar()) {
+        return Err(ButtplugDeviceError::DeviceConfigurationError(format!(
+          "Sensor rule output {} is outside the range 0.0 to 1.0.",
+          rule.output().scalar()
+        )));
+      }
+      if rule.hysteresis() < 0 {
+        return Err(ButtplugDeviceError::DeviceConfigurationError(format!(
+          "Sensor rule hysteresis {} is negative.",
+          rule.hysteresis()
+        )));
+      }
+    }
 
-    // Make sure it's all valid.
-    for attrs in attribute_tree_map.values() {
-      attrs.is_valid()?;
+    // Later calibrations for the same sensor replace earlier ones, same as the other per-device
+    // settings.
+    let mut sensor_calibrations: HashMap<ServerDeviceIdentifier, Vec<_>> = HashMap::new();
+    for (identifier, sensor_index, sensor_type, calibration) in &calibration_list {
+      let calibrations = sensor_calibrations.entry(identifier.clone()).or_default();
+      calibrations.retain(|(index, sensor, _)| (index, sensor) != (sensor_index, sensor_type));
+      calibrations.push((*sensor_index, *sensor_type, calibration.clone()));
     }
 
     Ok(DeviceConfigurationManager {
       allow_raw_messages: self.allow_raw_messages,
-      communication_specifiers: self.communication_specifiers.clone(),
-      protocol_attributes: attribute_tree_map,
+      raw_message_devices: self.raw_message_devices.iter().cloned().collect(),
+      protocol_configurations: RwLock::new(Arc::new(ProtocolConfigurations {
+        communication_specifiers: self.communication_specifiers.clone(),
+        specifier_index: SpecifierIndex::new(&self.communication_specifiers),
+        attribute_sources: eager_attributes,
+        protocol_attributes: attribute_tree_map,
+        lazy_protocol_attributes,
+      })),
       protocol_map,
-      allowed_addresses: self.allowed_addresses.clone(),
-      denied_addresses: self.denied_addresses.clone(),
+      allowed_addresses: allowed_addresses.into_iter().collect(),
+      denied_addresses: denied_addresses.into_iter().collect(),
       reserved_indexes,
+      display_names: persisted_display_names.into_iter().collect(),
+      battery_poll_intervals: self.battery_poll_intervals.iter().cloned().collect(),
+      rssi_sample_intervals: self.rssi_sample_intervals.iter().cloned().collect(),
+      protocol_command_timeouts: self.protocol_command_timeouts.clone(),
+      command_timeouts: self.command_timeouts.iter().cloned().collect(),
+      protocol_max_update_rates: self.protocol_max_update_rates.clone(),
+      max_update_rates: self.max_update_rates.iter().cloned().collect(),
+      protocol_btle_connection_parameters: self.protocol_btle_connection_parameters.clone(),
+      protocol_btle_write_coalescing: self.protocol_btle_write_coalescing.clone(),
+      protocol_overuse_protections: self.protocol_overuse_protections.clone(),
+      protocol_estim_interlocks: self.protocol_estim_interlocks.clone(),
+      warm_ups: self.warm_ups.iter().cloned().collect(),
+      cool_downs: self.cool_downs.iter().cloned().collect(),
+      sensor_calibrations,
+      merged_devices: self.merged_devices.clone(),
+      sensor_rules: self.sensor_rules.clone(),
+      split_devices: self.split_devices.iter().cloned().collect(),
+      version: self.version.clone(),
       current_index: AtomicU32::new(0),
+      persisted_configuration,
     })
   }
 }
 
+fn persistence_error(err: PersistenceError) -> ButtplugDeviceError {
+  ButtplugDeviceError::DeviceConfigurationError(format!(
+    "Cannot update persisted device configuration: {}",
+    err
+  ))
+}
+
+/// Adds `address` to a list of addresses if `listed`, otherwise removes it.
+fn update_address_list(addresses: &mut Vec<String>, address: &str, listed: bool) {
+  addresses.retain(|listed_address| listed_address != address);
+  if listed {
+    addresses.push(address.to_owned());
+  }
+}
+
+/// Protocol attributes with their parents linked in, ready for lookups.
+type AttributeTree = HashMap<ProtocolAttributesIdentifier, Arc<ProtocolDeviceAttributes>>;
+
+/// Links protocol attributes to their parents (identifier configurations to the protocol default,
+/// user configurations to what they were configured over) and makes sure the results are valid.
+fn build_attribute_tree(
+  attributes: &HashMap<ProtocolAttributesIdentifier, ProtocolDeviceAttributes>,
+) -> Result<AttributeTree, ButtplugDeviceError> {
+  let mut attribute_tree_map = HashMap::new();
+
+  // Add all the defaults first, they won't have parent attributes.
+  for (ident, attr) in attributes.iter().filter(|(ident, _)| {
+    ident.attributes_identifier == ProtocolAttributesType::Default && ident.address.is_none()
+  }) {
+    attribute_tree_map.insert(ident.clone(), Arc::new(attr.clone()));
+  }
+
+  // Then add in everything that has a identifier but not an address, and possibly set their parents.
+  for (ident, attr) in attributes.iter().filter(|(ident, _)| {
+    matches!(
+      ident.attributes_identifier,
+      ProtocolAttributesType::Identifier(_)
+    ) && ident.address.is_none()
+  }) {
+    if let Some(parent) = attribute_tree_map.get(&ProtocolAttributesIdentifier {
+      address: None,
+      protocol: ident.protocol.clone(),
+      attributes_identifier: ProtocolAttributesType::Default,
+    }) {
+      let attr_with_parent = attr.new_with_parent(parent.clone());
+      attribute_tree_map.insert(ident.clone(), Arc::new(attr_with_parent));
+    } else {
+      attribute_tree_map.insert(ident.clone(), Arc::new(attr.clone()));
+    }
+  }
+
+  // Finally, add in user configurations, which will have an address.
+  for (ident, attr) in attributes
+    .iter()
+    .filter(|(ident, _)| ident.address.is_some())
+  {
+    // The protocol and attribute identifier of a user config will be its parent. If that doesn't exist, error.
+    if let Some(parent) = attribute_tree_map.get(&ProtocolAttributesIdentifier {
+      address: None,
+      protocol: ident.protocol.clone(),
+      attributes_identifier: ident.attributes_identifier.clone(),
+    }) {
+      let attr_with_parent = attr.new_with_parent(parent.clone());
+      attribute_tree_map.insert(ident.clone(), Arc::new(attr_with_parent));
+    } else if let Some(parent) = attribute_tree_map.get(&ProtocolAttributesIdentifier {
+      address: None,
+      protocol: ident.protocol.clone(),
+      attributes_identifier: ProtocolAttributesType::Default,
+    }) {
+      // There are some cases where protocols will hand back identifiers even though we don't have
+      // any in the config (i.e. new devices we haven't added specializations for yet). In that
+      // case, fall back to the default.
+      let attr_with_parent = attr.new_with_parent(parent.clone());
+      attribute_tree_map.insert(ident.clone(), Arc::new(attr_with_parent));
+    } else {
+      return Err(ButtplugDeviceError::DeviceConfigurationError(format!("User configuration {:?} does not have a parent type, cannot create configuration. Please remove this user configuration, or make sure it has a parent.", ident)));
+    }
+  }
+
+  // Make sure it's all valid.
+  for attrs in attribute_tree_map.values() {
+    attrs.is_valid()?;
+  }
+  Ok(attribute_tree_map)
+}
+
+/// Communication specifiers and attributes of the protocols a [DeviceConfigurationManager] knows
+/// about. Replaced as a whole when configurations are added while the server is running, so lookups
+/// never see specifiers without the attributes that go with them.
+struct ProtocolConfigurations {
+  communication_specifiers: HashMap<String, Vec<ProtocolCommunicationSpecifier>>,
+  /// Lookup tables over communication_specifiers, so matching a device doesn't scan all of them
+  specifier_index: SpecifierIndex,
+  /// Attributes protocol_attributes was built from, kept so it can be rebuilt with more of them
+  attribute_sources: HashMap<ProtocolAttributesIdentifier, ProtocolDeviceAttributes>,
+  protocol_attributes: AttributeTree,
+  /// Attributes for protocols that are only parsed once a device using them shows up
+  lazy_protocol_attributes: HashMap<String, Arc<LazyProtocolAttributes>>,
+}
+
 /// Correlates information about protocols and which devices they support.
 ///
 /// The [DeviceConfigurationManager] handles stores information about which device protocols the
@@ -614,14 +1212,37 @@ impl DeviceConfigurationManagerBuilder {
 pub struct DeviceConfigurationManager {
   /// If true, add raw message support to connected devices
   allow_raw_messages: bool,
-  communication_specifiers: HashMap<String, Vec<ProtocolCommunicationSpecifier>>,
-  protocol_attributes: HashMap<ProtocolAttributesIdentifier, Arc<ProtocolDeviceAttributes>>,
+  /// Devices to add raw message support to even if it isn't allowed for all of them
+  raw_message_devices: HashSet<ServerDeviceIdentifier>,
+  protocol_configurations: RwLock<Arc<ProtocolConfigurations>>,
   /// Map of protocol names to their respective protocol instance factories
   protocol_map: HashMap<String, Arc<dyn ProtocolIdentifierFactory>>,
-  allowed_addresses: Vec<String>,
-  denied_addresses: Vec<String>,
+  allowed_addresses: DashSet<String>,
+  denied_addresses: DashSet<String>,
   reserved_indexes: DashMap<ServerDeviceIdentifier, u32>,
+  /// Display names given to devices while servers were running, which outlive disconnections.
+  display_names: DashMap<ServerDeviceIdentifier, String>,
+  battery_poll_intervals: HashMap<ServerDeviceIdentifier, Duration>,
+  rssi_sample_intervals: HashMap<ServerDeviceIdentifier, Duration>,
+  protocol_command_timeouts: HashMap<String, Duration>,
+  command_timeouts: HashMap<ServerDeviceIdentifier, Duration>,
+  protocol_max_update_rates: HashMap<String, u32>,
+  max_update_rates: HashMap<ServerDeviceIdentifier, u32>,
+  protocol_btle_connection_parameters: HashMap<String, BluetoothLEConnectionParameters>,
+  protocol_btle_write_coalescing: HashMap<String, BluetoothLEWriteCoalescing>,
+  protocol_overuse_protections: HashMap<String, OveruseProtection>,
+  protocol_estim_interlocks: HashMap<String, EstimInterlock>,
+  warm_ups: HashMap<ServerDeviceIdentifier, Duration>,
+  cool_downs: HashMap<ServerDeviceIdentifier, Duration>,
+  sensor_calibrations: HashMap<ServerDeviceIdentifier, Vec<(u32, SensorType, SensorCalibration)>>,
+  merged_devices: Vec<(String, Vec<ServerDeviceIdentifier>)>,
+  sensor_rules: Vec<SensorRule>,
+  split_devices: HashSet<ServerDeviceIdentifier>,
+  version: Option<String>,
   current_index: AtomicU32,
+  /// Where indexes given to devices, display names and allow/deny list changes are saved, if
+  /// anywhere.
+  persisted_configuration: Option<PersistedDeviceConfiguration>,
 }
 
 impl Default for DeviceConfigurationManager {
@@ -637,20 +1258,19 @@ impl Default for DeviceConfigurationManager {
 
 impl DeviceConfigurationManager {
   pub fn address_allowed(&self, address: &str) -> bool {
-    let address = address.to_owned();
     // Make sure the device isn't on the deny list
-    if self.denied_addresses.contains(&address) {
+    if self.denied_addresses.contains(address) {
       // If device is outright denied, deny
       info!(
         "Device {} denied by configuration, not connecting.",
-        address
+        display_address(address)
       );
       false
-    } else if !self.allowed_addresses.is_empty() && !self.allowed_addresses.contains(&address) {
+    } else if !self.allowed_addresses.is_empty() && !self.allowed_addresses.contains(address) {
       // If device is not on allow list and allow list isn't empty, deny
       info!(
         "Device {} not on allow list and allow list not empty, not connecting.",
-        address
+        display_address(address)
       );
       false
     } else {
@@ -658,6 +1278,99 @@ impl DeviceConfigurationManager {
     }
   }
 
+  /// Adds an address to the allow list, or removes it if `allowed` is false. Fails if the address
+  /// is on the deny list. Takes effect the next time the device is found.
+  ///
+  /// Changes are saved if the configuration is persisted, so they're loaded by the next server.
+  /// Addresses given to the builder can be removed until then, but will be back in the next server.
+  pub fn set_address_allowed(
+    &self,
+    address: &str,
+    allowed: bool,
+  ) -> Result<(), ButtplugDeviceError> {
+    if allowed && self.denied_addresses.contains(address) {
+      return Err(ButtplugDeviceError::DeviceConfigurationError(format!(
+        "Device address {} is in the denied address list.",
+        display_address(address)
+      )));
+    }
+    if let Some(persisted) = &self.persisted_configuration {
+      let mut addresses = persisted.allowed_addresses().map_err(persistence_error)?;
+      update_address_list(&mut addresses, address, allowed);
+      persisted
+        .set_allowed_addresses(&addresses)
+        .map_err(persistence_error)?;
+    }
+    if allowed {
+      self.allowed_addresses.insert(address.to_owned());
+    } else {
+      self.allowed_addresses.remove(address);
+    }
+    Ok(())
+  }
+
+  /// Adds an address to the deny list, or removes it if `denied` is false. Fails if the address is
+  /// on the allow list. Saved the same way as [Self::set_address_allowed].
+  pub fn set_address_denied(&self, address: &str, denied: bool) -> Result<(), ButtplugDeviceError> {
+    if denied && self.allowed_addresses.contains(address) {
+      return Err(ButtplugDeviceError::DeviceConfigurationError(format!(
+        "Device address {} is in the allowed address list.",
+        display_address(address)
+      )));
+    }
+    if let Some(persisted) = &self.persisted_configuration {
+      let mut addresses = persisted.denied_addresses().map_err(persistence_error)?;
+      update_address_list(&mut addresses, address, denied);
+      persisted
+        .set_denied_addresses(&addresses)
+        .map_err(persistence_error)?;
+    }
+    if denied {
+      self.denied_addresses.insert(address.to_owned());
+    } else {
+      self.denied_addresses.remove(address);
+    }
+    Ok(())
+  }
+
+  /// Returns the display name given to a device through [Self::set_display_name], if any.
+  pub fn display_name(&self, identifier: &ServerDeviceIdentifier) -> Option<String> {
+    self
+      .display_names
+      .get(identifier)
+      .map(|display_name| display_name.value().clone())
+  }
+
+  /// Gives a device a display name that's used over any from the user config, or removes it with
+  /// None. The name is given to the device whenever it connects, and saved if the configuration is
+  /// persisted.
+  pub fn set_display_name(
+    &self,
+    identifier: &ServerDeviceIdentifier,
+    display_name: Option<String>,
+  ) -> Result<(), ButtplugDeviceError> {
+    if let Some(persisted) = &self.persisted_configuration {
+      let mut display_names: Vec<_> = self
+        .display_names
+        .iter()
+        .filter(|pair| pair.key() != identifier)
+        .map(|pair| (pair.key().clone(), pair.value().clone()))
+        .collect();
+      if let Some(display_name) = &display_name {
+        display_names.push((identifier.clone(), display_name.clone()));
+      }
+      persisted
+        .set_display_names(&display_names)
+        .map_err(persistence_error)?;
+    }
+    if let Some(display_name) = display_name {
+      self.display_names.insert(identifier.clone(), display_name);
+    } else {
+      self.display_names.remove(identifier);
+    }
+    Ok(())
+  }
+
   pub fn device_index(&self, identifier: &ServerDeviceIdentifier) -> u32 {
     // See if we have a reserved or reusable device index here.
     if let Some(id) = self.reserved_indexes.get(identifier) {
@@ -673,17 +1386,235 @@ impl DeviceConfigurationManager {
       self
         .reserved_indexes
         .insert(identifier.clone(), generated_device_index);
+      if let Some(persisted) = &self.persisted_configuration {
+        let indexes: Vec<_> = self
+          .reserved_indexes
+          .iter()
+          .map(|pair| (pair.key().clone(), *pair.value()))
+          .collect();
+        if let Err(err) = persisted.set_reserved_indexes(&indexes) {
+          warn!("Cannot persist index of device {:?}: {}", identifier, err);
+        }
+      }
       generated_device_index
     }
   }
 
+  /// Returns the identifier of the device that the index is reserved for (or was handed out to), if
+  /// any.
+  pub fn device_identifier(&self, index: u32) -> Option<ServerDeviceIdentifier> {
+    self
+      .reserved_indexes
+      .iter()
+      .find(|pair| *pair.value() == index)
+      .map(|pair| pair.key().clone())
+  }
+
+  /// Returns the version of the device configuration file loaded into this instance, if it was
+  /// loaded from a file.
+  pub fn version(&self) -> Option<String> {
+    self.version.clone()
+  }
+
+  /// Returns how often the server should poll the battery level of a device, if polling has been
+  /// configured for it.
+  pub fn battery_poll_interval(&self, identifier: &ServerDeviceIdentifier) -> Option<Duration> {
+    self.battery_poll_intervals.get(identifier).cloned()
+  }
+
+  /// Returns how often subscriptions to the RSSI sensor of a device sample its signal strength, if
+  /// the device has been configured to expose one.
+  pub fn rssi_sample_interval(&self, identifier: &ServerDeviceIdentifier) -> Option<Duration> {
+    self.rssi_sample_intervals.get(identifier).cloned()
+  }
+
+  /// Returns how long the server should wait on a command to a device before failing it, if a
+  /// timeout has been configured for the device or its protocol.
+  pub fn command_timeout(&self, identifier: &ServerDeviceIdentifier) -> Option<Duration> {
+    self
+      .command_timeouts
+      .get(identifier)
+      .or_else(|| self.protocol_command_timeouts.get(identifier.protocol()))
+      .cloned()
+  }
+
+  /// Returns how many times a second output can be sent to a device, if a rate has been configured
+  /// for the device or its protocol.
+  pub fn max_update_rate(&self, identifier: &ServerDeviceIdentifier) -> Option<u32> {
+    self
+      .max_update_rates
+      .get(identifier)
+      .or_else(|| self.protocol_max_update_rates.get(identifier.protocol()))
+      .cloned()
+  }
+
+  /// Returns the connection parameters to request from a bluetooth LE device after connecting, if
+  /// any have been configured for its protocol.
+  pub fn btle_connection_parameters(
+    &self,
+    identifier: &ServerDeviceIdentifier,
+  ) -> Option<BluetoothLEConnectionParameters> {
+    self
+      .protocol_btle_connection_parameters
+      .get(identifier.protocol())
+      .cloned()
+  }
+
+  /// Returns the write coalescing to set up on a bluetooth LE device after connecting, if any has
+  /// been configured for its protocol.
+  pub fn btle_write_coalescing(
+    &self,
+    identifier: &ServerDeviceIdentifier,
+  ) -> Option<BluetoothLEWriteCoalescing> {
+    self
+      .protocol_btle_write_coalescing
+      .get(identifier.protocol())
+      .cloned()
+  }
+
+  /// Returns the overuse protection for a device, if any has been configured for its protocol.
+  pub fn overuse_protection(
+    &self,
+    identifier: &ServerDeviceIdentifier,
+  ) -> Option<OveruseProtection> {
+    self
+      .protocol_overuse_protections
+      .get(identifier.protocol())
+      .cloned()
+  }
+
+  /// Returns the e-stim interlock for a device, if its protocol is configured as e-stim.
+  pub fn estim_interlock(&self, identifier: &ServerDeviceIdentifier) -> Option<EstimInterlock> {
+    self
+      .protocol_estim_interlocks
+      .get(identifier.protocol())
+      .cloned()
+  }
+
+  /// Returns the warm up and cool down settings for a device. Both are off unless configured.
+  pub fn ramp_policy(&self, identifier: &ServerDeviceIdentifier) -> RampPolicy {
+    RampPolicy::new(
+      self.warm_ups.get(identifier).cloned(),
+      self.cool_downs.get(identifier).cloned(),
+    )
+  }
+
+  /// Returns the calibrations configured for the sensors of a device, as sensor index, sensor type
+  /// and calibration.
+  pub fn sensor_calibrations(
+    &self,
+    identifier: &ServerDeviceIdentifier,
+  ) -> Vec<(u32, SensorType, SensorCalibration)> {
+    self
+      .sensor_calibrations
+      .get(identifier)
+      .cloned()
+      .unwrap_or_default()
+  }
+
+  /// Returns the configured groups of devices to present to clients as single devices, as name and
+  /// member identifiers.
+  pub fn merged_devices(&self) -> &[(String, Vec<ServerDeviceIdentifier>)] {
+    &self.merged_devices
+  }
+
+  /// Returns the rules the server runs on sensor readings.
+  pub fn sensor_rules(&self) -> &[SensorRule] {
+    &self.sensor_rules
+  }
+
+  /// Returns whether the output features of a device should be presented to clients as devices of
+  /// their own.
+  pub fn splits_device(&self, identifier: &ServerDeviceIdentifier) -> bool {
+    self.split_devices.contains(identifier)
+  }
+
   /// Provides read-only access to the internal protocol/identifier map. Mainly
   /// used for WebBluetooth filter construction, but could also be handy for
   /// listing capabilities in UI, etc.
   pub fn protocol_device_configurations(
     &self,
   ) -> HashMap<String, Vec<ProtocolCommunicationSpecifier>> {
-    self.communication_specifiers.clone()
+    self
+      .protocol_configurations()
+      .communication_specifiers
+      .clone()
+  }
+
+  fn protocol_configurations(&self) -> Arc<ProtocolConfigurations> {
+    self
+      .protocol_configurations
+      .read()
+      .expect("Protocol configurations lock should never be poisoned.")
+      .clone()
+  }
+
+  /// Adds the communication specifiers and protocol attributes of `builder` to the ones already
+  /// known, while the server is running. Specifiers are added alongside the ones already there, and
+  /// attributes replace any with the same identifier. Only devices found after this are affected.
+  ///
+  /// Nothing else in `builder` is used. Fails without changing anything if the builder has
+  /// configurations for protocols without an implementation, or attributes that aren't valid.
+  pub fn add_protocol_configurations(
+    &self,
+    builder: &DeviceConfigurationManagerBuilder,
+  ) -> Result<(), ButtplugDeviceError> {
+    if let Some(protocol) = builder
+      .communication_specifiers
+      .keys()
+      .chain(
+        builder
+          .protocol_attributes
+          .keys()
+          .map(|ident| &ident.protocol),
+      )
+      .find(|protocol| !self.protocol_map.contains_key(*protocol))
+    {
+      return Err(ButtplugDeviceError::ProtocolNotImplemented(
+        protocol.clone(),
+      ));
+    }
+
+    // Held for the whole update, so concurrent additions don't lose each other's changes.
+    let mut current = self
+      .protocol_configurations
+      .write()
+      .expect("Protocol configurations lock should never be poisoned.");
+    let mut communication_specifiers = current.communication_specifiers.clone();
+    for (protocol, specifiers) in &builder.communication_specifiers {
+      communication_specifiers
+        .entry(protocol.clone())
+        .or_default()
+        .extend(specifiers.iter().cloned());
+    }
+    let mut attribute_sources = current.attribute_sources.clone();
+    let mut lazy_protocol_attributes = current.lazy_protocol_attributes.clone();
+    let mut lazy_overrides: HashMap<String, HashMap<_, _>> = HashMap::new();
+    for (ident, attr) in &builder.protocol_attributes {
+      if lazy_protocol_attributes.contains_key(&ident.protocol) {
+        lazy_overrides
+          .entry(ident.protocol.clone())
+          .or_default()
+          .insert(ident.clone(), attr.clone());
+      } else {
+        attribute_sources.insert(ident.clone(), attr.clone());
+      }
+    }
+    let protocol_attributes = build_attribute_tree(&attribute_sources)?;
+    for (protocol, overrides) in lazy_overrides {
+      let lazy_attributes = Arc::new(lazy_protocol_attributes[&protocol].with_overrides(overrides));
+      lazy_attributes.load_if_overridden()?;
+      lazy_protocol_attributes.insert(protocol, lazy_attributes);
+    }
+
+    *current = Arc::new(ProtocolConfigurations {
+      specifier_index: SpecifierIndex::new(&communication_specifiers),
+      communication_specifiers,
+      attribute_sources,
+      protocol_attributes,
+      lazy_protocol_attributes,
+    });
+    Ok(())
   }
 
   pub fn protocol_specializers(
@@ -694,8 +1625,10 @@ impl DeviceConfigurationManager {
       "Looking for protocol that matches specifier: {:?}",
       specifier
     );
+    let configurations = self.protocol_configurations();
     let mut specializers = vec![];
-    for (name, specifiers) in self.communication_specifiers.iter() {
+    for name in configurations.specifier_index.candidates(specifier) {
+      let specifiers = &configurations.communication_specifiers[name];
       if specifiers.contains(specifier) {
         info!("Found protocol {:?} for specifier {:?}.", name, specifier);
 
@@ -719,15 +1652,47 @@ impl DeviceConfigurationManager {
     specializers
   }
 
+  /// Ranks protocols by how close the specifier came to matching them, for hardware that
+  /// [protocol_specializers](Self::protocol_specializers) found no protocol for. Returns at most
+  /// `count` suggestions, best first.
+  pub fn protocol_suggestions(
+    &self,
+    specifier: &ProtocolCommunicationSpecifier,
+    count: usize,
+  ) -> Vec<ProtocolSuggestion> {
+    protocol_suggestion::suggest_protocols(
+      specifier,
+      &self.protocol_configurations().communication_specifiers,
+      count,
+    )
+  }
+
+  /// Looks up attributes by identifier, loading the attributes of the protocol first if they're
+  /// lazily loaded.
+  fn attributes(
+    &self,
+    identifier: &ProtocolAttributesIdentifier,
+  ) -> Option<Arc<ProtocolDeviceAttributes>> {
+    let configurations = self.protocol_configurations();
+    if let Some(lazy_attributes) = configurations
+      .lazy_protocol_attributes
+      .get(&identifier.protocol)
+    {
+      lazy_attributes.attributes()?.get(identifier).cloned()
+    } else {
+      configurations.protocol_attributes.get(identifier).cloned()
+    }
+  }
+
   pub fn protocol_device_attributes(
     &self,
     identifier: &ServerDeviceIdentifier,
     raw_endpoints: &[Endpoint],
   ) -> Option<ProtocolDeviceAttributes> {
-    let mut flat_attrs = if let Some(attrs) = self.protocol_attributes.get(&identifier.into()) {
+    let mut flat_attrs = if let Some(attrs) = self.attributes(&identifier.into()) {
       debug!("User device config found for {:?}", identifier);
       attrs.flatten()
-    } else if let Some(attrs) = self.protocol_attributes.get(&ProtocolAttributesIdentifier {
+    } else if let Some(attrs) = self.attributes(&ProtocolAttributesIdentifier {
       address: None,
       attributes_identifier: identifier.attributes_identifier().clone(),
       protocol: identifier.protocol().clone(),
@@ -737,7 +1702,7 @@ impl DeviceConfigurationManager {
         identifier
       );
       attrs.flatten()
-    } else if let Some(attrs) = self.protocol_attributes.get(&ProtocolAttributesIdentifier {
+    } else if let Some(attrs) = self.attributes(&ProtocolAttributesIdentifier {
       address: None,
       attributes_identifier: ProtocolAttributesType::Default,
       protocol: identifier.protocol().clone(),
@@ -748,7 +1713,7 @@ impl DeviceConfigurationManager {
       return None;
     };
 
-    if self.allow_raw_messages {
+    if self.allow_raw_messages || self.raw_message_devices.contains(identifier) {
       flat_attrs.add_raw_messages(raw_endpoints);
     }
 
@@ -765,16 +1730,22 @@ mod test {
     },
     *,
   };
+  use crate::server::persistence::MemoryPersistence;
   use std::{
     collections::{HashMap, HashSet},
     ops::RangeInclusive,
   };
 
   fn create_unit_test_dcm(allow_raw_messages: bool) -> DeviceConfigurationManager {
-    let mut builder = DeviceConfigurationManagerBuilder::default();
+    let mut builder = create_unit_test_dcm_builder();
     if allow_raw_messages {
       builder.allow_raw_messages();
     }
+    builder.finish().unwrap()
+  }
+
+  fn create_unit_test_dcm_builder() -> DeviceConfigurationManagerBuilder {
+    let mut builder = DeviceConfigurationManagerBuilder::default();
     let specifiers = ProtocolCommunicationSpecifier::BluetoothLE(BluetoothLESpecifier::new(
       HashSet::from(["LVS-*".to_owned(), "LovenseDummyTestName".to_owned()]),
       vec![],
@@ -809,7 +1780,7 @@ mod test {
         None,
       ),
     );
-    builder.finish().unwrap()
+    builder
   }
 
   #[test]
@@ -925,6 +1896,111 @@ mod test {
     assert!(config.message_attributes().raw_unsubscribe_cmd().is_none());
   }
 
+  #[test]
+  fn test_per_device_raw_device_config_creation() {
+    let identifier = |address: &str| {
+      ServerDeviceIdentifier::new(
+        address,
+        "lovense",
+        &ProtocolAttributesType::Identifier("P".to_owned()),
+      )
+    };
+    let dcm = create_unit_test_dcm_builder()
+      .allow_raw_messages_for(&identifier("DebugAddress"))
+      .finish()
+      .expect("Test, assuming infallible.");
+    let debug_config = dcm
+      .protocol_device_attributes(&identifier("DebugAddress"), &[Endpoint::Tx])
+      .expect("Should be found");
+    assert!(debug_config.message_attributes().raw_write_cmd().is_some());
+    let other_config = dcm
+      .protocol_device_attributes(&identifier("OtherAddress"), &[Endpoint::Tx])
+      .expect("Should be found");
+    assert!(other_config.message_attributes().raw_read_cmd().is_none());
+    assert!(other_config.message_attributes().raw_write_cmd().is_none());
+  }
+
+  #[test]
+  fn test_add_protocol_configurations() {
+    let dcm = create_unit_test_dcm(false);
+    let device_spec = |name: &str| {
+      ProtocolCommunicationSpecifier::BluetoothLE(BluetoothLESpecifier::new_from_device(
+        name,
+        &HashMap::new(),
+        &[],
+      ))
+    };
+    assert!(dcm
+      .protocol_specializers(&device_spec("NewLovenseToy"))
+      .is_empty());
+
+    let mut builder = DeviceConfigurationManagerBuilder::default();
+    builder.communication_specifier(
+      "lovense",
+      ProtocolCommunicationSpecifier::BluetoothLE(BluetoothLESpecifier::new(
+        HashSet::from(["NewLovenseToy".to_owned()]),
+        vec![],
+        HashSet::new(),
+        HashMap::new(),
+      )),
+    );
+    builder.protocol_attributes(
+      ProtocolAttributesIdentifier::new(
+        "lovense",
+        &ProtocolAttributesType::Identifier("Q".to_owned()),
+        &None,
+      ),
+      ProtocolDeviceAttributes::new(
+        ProtocolAttributesType::Identifier("Q".to_owned()),
+        Some("Lovense New Toy".to_owned()),
+        None,
+        ServerDeviceMessageAttributesBuilder::default()
+          .scalar_cmd(&[ServerGenericDeviceMessageAttributes::new(
+            "New Toy Vibrator",
+            &RangeInclusive::new(0, 20),
+            crate::core::message::ActuatorType::Vibrate,
+          )])
+          .finish(),
+        None,
+      ),
+    );
+    dcm
+      .add_protocol_configurations(&builder)
+      .expect("Test, assuming infallible.");
+
+    // New configurations are picked up, and the ones already there are kept.
+    assert!(!dcm
+      .protocol_specializers(&device_spec("NewLovenseToy"))
+      .is_empty());
+    assert!(!dcm
+      .protocol_specializers(&device_spec("LVS-Whatever"))
+      .is_empty());
+    let attributes = |identifier: &str| {
+      dcm
+        .protocol_device_attributes(
+          &ServerDeviceIdentifier::new(
+            "Whatever",
+            "lovense",
+            &ProtocolAttributesType::Identifier(identifier.to_owned()),
+          ),
+          &[],
+        )
+        .expect("Should be found")
+    };
+    assert_eq!(attributes("Q").name(), "Lovense New Toy");
+    assert_eq!(attributes("P").name(), "Lovense Edge");
+
+    let mut unimplemented_builder = DeviceConfigurationManagerBuilder::default();
+    unimplemented_builder.communication_specifier("not-a-protocol", device_spec("NotAProtocolToy"));
+    assert!(matches!(
+      dcm.add_protocol_configurations(&unimplemented_builder),
+      Err(ButtplugDeviceError::ProtocolNotImplemented(_))
+    ));
+    assert!(dcm
+      .protocol_specializers(&device_spec("NotAProtocolToy"))
+      .is_empty());
+  }
+
   /*
       #[test]
       fn test_user_config_loading() {
@@ -987,6 +2063,83 @@ mod test {
           .any(|x| x.port == "COM1"));
       }
   */
+
+  #[test]
+  fn test_persisted_device_configuration() {
+    let persistence = Arc::new(MemoryPersistence::default());
+    let persisted = PersistedDeviceConfiguration::new(persistence.clone());
+    let identifier = |address: &str| {
+      ServerDeviceIdentifier::new(address, "lovense", &ProtocolAttributesType::Default)
+    };
+    persisted
+      .set_denied_addresses(&["DeniedAddress".to_owned()])
+      .expect("Test, assuming infallible.");
+    // Takes the index reserved by the builder below, so it's ignored.
+    persisted
+      .set_reserved_indexes(&[(identifier("ConflictingAddress"), 1)])
+      .expect("Test, assuming infallible.");
+    let mut builder = DeviceConfigurationManagerBuilder::default();
+    builder
+      .reserved_index(&identifier("ConfiguredAddress"), 1)
+      .persistence(persistence);
+    let config = builder.finish().expect("Test, assuming infallible.");
+    assert!(!config.address_allowed("DeniedAddress"));
+    assert!(config.address_allowed("OtherAddress"));
+    assert_eq!(config.device_index(&identifier("ConfiguredAddress")), 1);
+    assert_eq!(config.device_index(&identifier("ConflictingAddress")), 0);
+    assert_eq!(config.device_index(&identifier("NewAddress")), 2);
+
+    // Indexes handed out are remembered by the next manager.
+    let config = builder.finish().expect("Test, assuming infallible.");
+    assert_eq!(config.device_index(&identifier("NewAddress")), 2);
+    assert_eq!(config.device_index(&identifier("ConflictingAddress")), 0);
+    assert_eq!(config.device_index(&identifier("OtherAddress")), 3);
+  }
+
+  #[test]
+  fn test_persisted_runtime_changes() {
+    let persistence = Arc::new(MemoryPersistence::default());
+    let identifier =
+      ServerDeviceIdentifier::new("NamedAddress", "lovense", &ProtocolAttributesType::Default);
+    let mut builder = DeviceConfigurationManagerBuilder::default();
+    builder
+      .allowed_address("ConfiguredAddress")
+      .persistence(persistence);
+    let config = builder.finish().expect("Test, assuming infallible.");
+    config
+      .set_display_name(&identifier, Some("Bedside".to_owned()))
+      .expect("Test, assuming infallible.");
+    config
+      .set_address_allowed("AllowedAddress", true)
+      .expect("Test, assuming infallible.");
+    config
+      .set_address_denied("DeniedAddress", true)
+      .expect("Test, assuming infallible.");
+    assert!(config.set_address_denied("AllowedAddress", true).is_err());
+    assert!(config.address_allowed("AllowedAddress"));
+    assert!(!config.address_allowed("DeniedAddress"));
+
+    // Changes made to one manager are loaded by the next.
+    let config = builder.finish().expect("Test, assuming infallible.");
+    assert_eq!(config.display_name(&identifier), Some("Bedside".to_owned()));
+    assert!(config.address_allowed("AllowedAddress"));
+    assert!(config.address_allowed("ConfiguredAddress"));
+    assert!(!config.address_allowed("DeniedAddress"));
+    config
+      .set_display_name(&identifier, None)
+      .expect("Test, assuming infallible.");
+    config
+      .set_address_denied("DeniedAddress", false)
+      .expect("Test, assuming infallible.");
+    let config = builder.finish().expect("Test, assuming infallible.");
+    assert_eq!(config.display_name(&identifier), None);
+    assert!(!config.address_allowed("DeniedAddress"));
+    config
+      .set_address_allowed("DeniedAddress", true)
+      .expect("Test, assuming infallible.");
+    assert!(config.address_allowed("DeniedAddress"));
+  }
+
   // TODO Test invalid config load (not json)
 
   // TODO Test calculation/change of Step Count via Step Range
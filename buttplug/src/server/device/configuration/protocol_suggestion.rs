@@ -0,0 +1,205 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Near-miss matching of unsupported hardware against known protocols
+//!
+//! When hardware shows up that no protocol claims, it's usually either a device we really don't
+//! support, or a new revision of one we do that advertises a slightly different name. Scoring the
+//! hardware against every known specifier lets us tell users (and whoever triages their support
+//! request) which protocol it most likely belongs to.
+
+use super::{BluetoothLESpecifier, ProtocolCommunicationSpecifier};
+use getset::{CopyGetters, Getters};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+/// Suggestions scoring lower than this are more likely noise than a near miss.
+const MINIMUM_SUGGESTION_SCORE: u8 = 50;
+
+/// A protocol that hardware came close to matching.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Getters, CopyGetters)]
+pub struct ProtocolSuggestion {
+  /// Name of the protocol in the device configuration.
+  #[getset(get = "pub")]
+  protocol: String,
+  /// Configured name closest to the name the hardware advertised, if names were compared.
+  #[getset(get = "pub")]
+  closest_name: Option<String>,
+  /// Services the hardware advertised that the protocol also lists.
+  #[getset(get = "pub")]
+  shared_services: Vec<Uuid>,
+  /// How close the match is, from 0 (nothing in common) to 100.
+  #[getset(get_copy = "pub")]
+  score: u8,
+}
+
+/// Scores `specifier` against every protocol's specifiers, returning at most `count` suggestions,
+/// best first. Only bluetooth hardware carries enough information to compare, so anything else gets
+/// no suggestions.
+pub(super) fn suggest_protocols(
+  specifier: &ProtocolCommunicationSpecifier,
+  communication_specifiers: &HashMap<String, Vec<ProtocolCommunicationSpecifier>>,
+  count: usize,
+) -> Vec<ProtocolSuggestion> {
+  let device = if let ProtocolCommunicationSpecifier::BluetoothLE(device) = specifier {
+    device
+  } else {
+    return vec![];
+  };
+  let mut suggestions: Vec<ProtocolSuggestion> = communication_specifiers
+    .iter()
+    .filter_map(|(protocol, specifiers)| {
+      specifiers
+        .iter()
+        .filter_map(|specifier| {
+          if let ProtocolCommunicationSpecifier::BluetoothLE(known) = specifier {
+            Some(score_btle(protocol, device, known))
+          } else {
+            None
+          }
+        })
+        .max_by_key(|suggestion| suggestion.score)
+    })
+    .filter(|suggestion| suggestion.score >= MINIMUM_SUGGESTION_SCORE)
+    .collect();
+  suggestions.sort_by(|a, b| {
+    b.score
+      .cmp(&a.score)
+      .then_with(|| a.protocol.cmp(&b.protocol))
+  });
+  suggestions.truncate(count);
+  suggestions
+}
+
+fn score_btle(
+  protocol: &str,
+  device: &BluetoothLESpecifier,
+  known: &BluetoothLESpecifier,
+) -> ProtocolSuggestion {
+  let mut closest_name = None;
+  let mut name_score = 0;
+  for device_name in device.names() {
+    for known_name in known.names() {
+      let score = name_similarity(device_name, known_name);
+      if score > name_score {
+        name_score = score;
+        closest_name = Some(known_name.clone());
+      }
+    }
+  }
+
+  // Devices tend to advertise their main service, which configs may list either as an advertised
+  // service or as one of the services we'll talk to after connecting.
+  let known_services: HashSet<&Uuid> = known
+    .advertised_services()
+    .iter()
+    .chain(known.services().keys())
+    .collect();
+  let mut shared_services: Vec<Uuid> = device
+    .advertised_services()
+    .iter()
+    .filter(|service| known_services.contains(service))
+    .copied()
+    .collect();
+  shared_services.sort();
+  let service_score = if shared_services.is_empty() {
+    0
+  } else {
+    (shared_services.len() * 100 / device.advertised_services().len()) as u8
+  };
+
+  ProtocolSuggestion {
+    protocol: protocol.to_owned(),
+    closest_name,
+    shared_services,
+    score: name_score.max(service_score),
+  }
+}
+
+/// Case insensitive similarity of two names, from 0 to 100. Wildcard names (ending in `*`) are
+/// compared against the same length prefix of the advertised name.
+fn name_similarity(advertised: &str, known: &str) -> u8 {
+  let advertised: Vec<char> = advertised.to_lowercase().chars().collect();
+  let mut known: Vec<char> = known.to_lowercase().chars().collect();
+  let advertised = if known.last() == Some(&'*') {
+    known.pop();
+    &advertised[..known.len().min(advertised.len())]
+  } else {
+    &advertised[..]
+  };
+  let longest = advertised.len().max(known.len());
+  if longest == 0 {
+    return 0;
+  }
+  let distance = edit_distance(advertised, &known);
+  ((longest - distance) * 100 / longest) as u8
+}
+
+/// Levenshtein distance, keeping a single row of the table.
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+  let mut row: Vec<usize> = (0..=b.len()).collect();
+  for (i, a_char) in a.iter().enumerate() {
+    let mut diagonal = row[0];
+    row[0] = i + 1;
+    for (j, b_char) in b.iter().enumerate() {
+      let substitution = diagonal + usize::from(a_char != b_char);
+      diagonal = row[j + 1];
+      row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+    }
+  }
+  row[b.len()]
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn btle(names: &[&str], services: &[Uuid]) -> ProtocolCommunicationSpecifier {
+    ProtocolCommunicationSpecifier::BluetoothLE(BluetoothLESpecifier::new(
+      names.iter().map(|name| name.to_string()).collect(),
+      vec![],
+      services.iter().copied().collect(),
+      HashMap::new(),
+    ))
+  }
+
+  #[test]
+  fn test_name_similarity() {
+    assert_eq!(name_similarity("Flamingo", "flamingo"), 100);
+    assert_eq!(name_similarity("Flamingo2", "Flamingo"), 88);
+    assert_eq!(name_similarity("LVS-Z36D", "LVS-*"), 100);
+    assert_eq!(name_similarity("LVX-Z36D", "LVS-*"), 75);
+    assert_eq!(name_similarity("Boost", "VorzePiston"), 27);
+    assert_eq!(name_similarity("", ""), 0);
+  }
+
+  #[test]
+  fn test_suggestions_ranked_by_score() {
+    let service = Uuid::from_u128(0x1234);
+    let mut known = HashMap::new();
+    known.insert("flamingo".to_owned(), vec![btle(&["Flamingo"], &[])]);
+    known.insert("flamingo-ish".to_owned(), vec![btle(&["Flamingos"], &[])]);
+    known.insert(
+      "by-service".to_owned(),
+      vec![btle(&["Unrelated"], &[service])],
+    );
+    known.insert("vorze".to_owned(), vec![btle(&["VorzePiston"], &[])]);
+
+    let suggestions = suggest_protocols(&btle(&["Flamingo2"], &[service]), &known, 2);
+    assert_eq!(suggestions.len(), 2);
+    assert_eq!(suggestions[0].protocol(), "by-service");
+    assert_eq!(*suggestions[0].shared_services(), vec![service]);
+    assert_eq!(suggestions[0].score(), 100);
+    assert_eq!(suggestions[1].protocol(), "flamingo");
+    assert_eq!(suggestions[1].closest_name().as_deref(), Some("Flamingo"));
+
+    let suggestions = suggest_protocols(&btle(&["Flamingo2"], &[]), &known, 5);
+    let protocols: Vec<&str> = suggestions.iter().map(|s| s.protocol().as_str()).collect();
+    assert_eq!(protocols, vec!["flamingo", "flamingo-ish"]);
+  }
+}
@@ -12,6 +12,7 @@ use crate::core::{
     ClientDeviceMessageAttributesBuilder,
     ClientGenericDeviceMessageAttributes,
     Endpoint,
+    FeaturePlacement,
     NullDeviceMessageAttributes,
     RawDeviceMessageAttributes,
     SensorDeviceMessageAttributes,
@@ -22,7 +23,7 @@ use crate::core::{
 // Unlike other message components, MessageAttributes is always turned on for
 // serialization, because it's used by device configuration files also.
 #[derive(
-  Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Getters, MutGetters, Setters,
+  Clone, Debug, Default, PartialEq, Serialize, Deserialize, Getters, MutGetters, Setters,
 )]
 pub struct ServerDeviceMessageAttributes {
   // Generic commands
@@ -186,6 +187,19 @@ impl ServerDeviceMessageAttributes {
     self.raw_write_cmd = Some(raw_attrs.clone());
     self.raw_subscribe_cmd = Some(raw_attrs);
   }
+
+  /// Add a sensor that the server provides itself instead of the device, so it can be both read and
+  /// subscribed to. Returns the indexes of the sensor for SensorReadCmd and SensorSubscribeCmd.
+  pub fn add_server_sensor(&mut self, sensor: SensorDeviceMessageAttributes) -> (u32, u32) {
+    let read_sensors = self.sensor_read_cmd.get_or_insert_with(Vec::new);
+    read_sensors.push(sensor.clone());
+    let subscribe_sensors = self.sensor_subscribe_cmd.get_or_insert_with(Vec::new);
+    subscribe_sensors.push(sensor);
+    (
+      read_sensors.len() as u32 - 1,
+      subscribe_sensors.len() as u32 - 1,
+    )
+  }
 }
 
 impl From<ServerDeviceMessageAttributes> for ClientDeviceMessageAttributes {
@@ -280,7 +294,7 @@ fn unspecified_feature() -> String {
   "N/A".to_string()
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Getters, Setters)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Getters, Setters)]
 pub struct ServerGenericDeviceMessageAttributes {
   #[getset(get = "pub")]
   #[serde(rename = "FeatureDescriptor")]
@@ -293,15 +307,34 @@ pub struct ServerGenericDeviceMessageAttributes {
   #[serde(skip_serializing)]
   #[getset(get = "pub", set = "pub")]
   step_range: RangeInclusive<u32>,
+  #[getset(get = "pub", set = "pub")]
+  #[serde(rename = "Placement")]
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  placement: Option<FeaturePlacement>,
+  /// Lowest output, from 0.0 to 1.0, at which the actuator actually does anything. Many motors
+  /// don't move below ~20% duty, so nonzero commands are remapped onto [minimum, 1.0] to make the
+  /// whole command range useful. 0.0 still turns the actuator off.
+  #[getset(get = "pub", set = "pub")]
+  #[serde(rename = "MinimumEffectiveValue")]
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  minimum_effective_value: Option<f64>,
+  /// Output the feature drives, for protocols that address outputs by name instead of by feature
+  /// order (like the axes of T-code devices). Protocols pick one if it isn't set.
+  #[getset(get = "pub", set = "pub")]
+  #[serde(rename = "Axis")]
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  axis: Option<String>,
 }
 
 impl From<ServerGenericDeviceMessageAttributes> for ClientGenericDeviceMessageAttributes {
   fn from(attrs: ServerGenericDeviceMessageAttributes) -> Self {
-    ClientGenericDeviceMessageAttributes::new(
+    let mut client_attrs = ClientGenericDeviceMessageAttributes::new(
       &attrs.feature_descriptor,
       attrs.step_count(),
       attrs.actuator_type,
-    )
+    );
+    client_attrs.set_placement(attrs.placement);
+    client_attrs
   }
 }
 
@@ -315,6 +348,9 @@ impl ServerGenericDeviceMessageAttributes {
       feature_descriptor: feature_descriptor.to_owned(),
       actuator_type,
       step_range: step_range.clone(),
+      placement: None,
+      minimum_effective_value: None,
+      axis: None,
     }
   }
 
@@ -331,6 +367,31 @@ impl ServerGenericDeviceMessageAttributes {
         "Step range out of order for {}, must be start <= x <= end.",
         message_type
       )))
+    } else if self
+      .minimum_effective_value
+      .is_some_and(|minimum| !(0.0..1.0).contains(&minimum))
+    {
+      Err(ButtplugDeviceError::DeviceConfigurationError(format!(
+        "Minimum effective value for {} must be at least 0.0 and less than 1.0.",
+        message_type
+      )))
+    } else if self.minimum_effective_value.is_some()
+      && matches!(
+        self.actuator_type,
+        ActuatorType::Position | ActuatorType::RotatePosition
+      )
+    {
+      Err(ButtplugDeviceError::DeviceConfigurationError(format!(
+        "Position actuators can't have a minimum effective value, used with {}.",
+        message_type
+      )))
+    } else if self.actuator_type == ActuatorType::RotatePosition
+      && *message_type != ButtplugDeviceMessageType::LinearCmd
+    {
+      Err(ButtplugDeviceError::DeviceConfigurationError(format!(
+        "RotatePosition actuators can only be used with LinearCmd, not {}.",
+        message_type
+      )))
     } else {
       Ok(())
     }
@@ -340,6 +401,7 @@ impl ServerGenericDeviceMessageAttributes {
 #[cfg(test)]
 mod test {
   use super::*;
+  use crate::core::message::FeaturePosition;
 
   #[test]
   pub fn test_step_count_calculation() {
@@ -352,4 +414,20 @@ mod test {
     vibrate_attributes.set_step_range(RangeInclusive::new(3u32, 7));
     assert_eq!(vibrate_attributes.step_count(), 4);
   }
+
+  #[test]
+  pub fn test_placement_passed_to_client() {
+    let attributes: ServerGenericDeviceMessageAttributes = serde_json::from_str(
+      r#"{
+        "StepRange": [0, 20],
+        "ActuatorType": "Vibrate",
+        "Placement": { "Part": "Arm", "Position": "External" }
+      }"#,
+    )
+    .expect("Test, assuming infallible");
+    let placement = FeaturePlacement::new("Arm", Some(FeaturePosition::External));
+    assert_eq!(*attributes.placement(), Some(placement.clone()));
+    let client_attributes: ClientGenericDeviceMessageAttributes = attributes.into();
+    assert_eq!(*client_attributes.placement(), Some(placement));
+  }
 }
@@ -0,0 +1,309 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Lookup tables for which protocols a discovered device could belong to
+//!
+//! Finding the protocol for an advertisement used to mean comparing it against every specifier of
+//! every protocol. In busy places we can see dozens of advertisements a second, almost all from
+//! hardware we don't support, so that adds up. The index narrows the search down to protocols that
+//! share a name, name prefix, service, manufacturer or id with the device, and only those get the
+//! full specifier comparison.
+
+use super::{BluetoothLESpecifier, ProtocolCommunicationSpecifier};
+use std::{
+  collections::{BTreeSet, HashMap},
+  hash::Hash,
+};
+use uuid::Uuid;
+
+fn add_protocol<K: Eq + Hash>(map: &mut HashMap<K, Vec<String>>, key: K, protocol: &str) {
+  add_to_list(map.entry(key).or_default(), protocol);
+}
+
+fn add_to_list(list: &mut Vec<String>, protocol: &str) {
+  if !list.iter().any(|name| name == protocol) {
+    list.push(protocol.to_owned());
+  }
+}
+
+/// Trie of wildcard name prefixes (config names ending in `*`), keyed by character.
+#[derive(Default)]
+struct NamePrefixTrie {
+  children: HashMap<char, NamePrefixTrie>,
+  protocols: Vec<String>,
+}
+
+impl NamePrefixTrie {
+  fn insert(&mut self, prefix: &str, protocol: &str) {
+    let mut node = self;
+    for c in prefix.chars() {
+      node = node.children.entry(c).or_default();
+    }
+    add_to_list(&mut node.protocols, protocol);
+  }
+
+  /// Adds every protocol with a prefix of `name` to `found`.
+  fn find<'a>(&'a self, name: &str, found: &mut BTreeSet<&'a str>) {
+    let mut node = self;
+    found.extend(node.protocols.iter().map(String::as_str));
+    for c in name.chars() {
+      node = if let Some(child) = node.children.get(&c) {
+        child
+      } else {
+        return;
+      };
+      found.extend(node.protocols.iter().map(String::as_str));
+    }
+  }
+}
+
+#[derive(Default)]
+pub(super) struct SpecifierIndex {
+  btle_names: HashMap<String, Vec<String>>,
+  btle_name_prefixes: NamePrefixTrie,
+  btle_services: HashMap<Uuid, Vec<String>>,
+  btle_companies: HashMap<u16, Vec<String>>,
+  /// Every protocol with a bluetooth specifier, for the rare specifier we can't look up.
+  btle_protocols: Vec<String>,
+  hid_ids: HashMap<(u16, u16), Vec<String>>,
+  usb_ids: HashMap<(u16, u16), Vec<String>>,
+  serial_ports: HashMap<String, Vec<String>>,
+  websocket_names: HashMap<String, Vec<String>>,
+  // XInput and Lovense Connect specifiers match any other specifier of their type.
+  xinput_protocols: Vec<String>,
+  lovense_connect_service_protocols: Vec<String>,
+}
+
+impl SpecifierIndex {
+  pub fn new(
+    communication_specifiers: &HashMap<String, Vec<ProtocolCommunicationSpecifier>>,
+  ) -> Self {
+    let mut index = Self::default();
+    for (protocol, specifiers) in communication_specifiers {
+      for specifier in specifiers {
+        index.add(protocol, specifier);
+      }
+    }
+    index
+  }
+
+  fn add(&mut self, protocol: &str, specifier: &ProtocolCommunicationSpecifier) {
+    match specifier {
+      ProtocolCommunicationSpecifier::BluetoothLE(btle) => {
+        for name in btle.names() {
+          if let Some(prefix) = name.strip_suffix('*') {
+            self.btle_name_prefixes.insert(prefix, protocol);
+          } else {
+            add_protocol(&mut self.btle_names, name.clone(), protocol);
+          }
+        }
+        for service in btle.advertised_services() {
+          add_protocol(&mut self.btle_services, *service, protocol);
+        }
+        for data in btle.manufacturer_data() {
+          add_protocol(&mut self.btle_companies, *data.company(), protocol);
+        }
+        add_to_list(&mut self.btle_protocols, protocol);
+      }
+      ProtocolCommunicationSpecifier::HID(hid) => {
+        add_protocol(
+          &mut self.hid_ids,
+          (*hid.vendor_id(), *hid.product_id()),
+          protocol,
+        );
+      }
+      ProtocolCommunicationSpecifier::USB(usb) => {
+        add_protocol(
+          &mut self.usb_ids,
+          (*usb.vendor_id(), *usb.product_id()),
+          protocol,
+        );
+      }
+      ProtocolCommunicationSpecifier::Serial(serial) => {
+        add_protocol(&mut self.serial_ports, serial.port().clone(), protocol);
+      }
+      ProtocolCommunicationSpecifier::Websocket(websocket) => {
+        for name in websocket.names() {
+          add_protocol(&mut self.websocket_names, name.clone(), protocol);
+        }
+      }
+      ProtocolCommunicationSpecifier::XInput(_) => {
+        add_to_list(&mut self.xinput_protocols, protocol);
+      }
+      ProtocolCommunicationSpecifier::LovenseConnectService(_) => {
+        add_to_list(&mut self.lovense_connect_service_protocols, protocol);
+      }
+    }
+  }
+
+  /// Protocols that might have a specifier equal to `specifier`. Everything that does is in here,
+  /// but not everything in here does, so callers still need to compare specifiers.
+  pub fn candidates(&self, specifier: &ProtocolCommunicationSpecifier) -> BTreeSet<&str> {
+    fn lookup<'a, K: Eq + Hash>(
+      map: &'a HashMap<K, Vec<String>>,
+      key: &K,
+      found: &mut BTreeSet<&'a str>,
+    ) {
+      if let Some(protocols) = map.get(key) {
+        found.extend(protocols.iter().map(String::as_str));
+      }
+    }
+
+    let mut found = BTreeSet::new();
+    match specifier {
+      ProtocolCommunicationSpecifier::BluetoothLE(btle) => self.btle_candidates(btle, &mut found),
+      ProtocolCommunicationSpecifier::HID(hid) => lookup(
+        &self.hid_ids,
+        &(*hid.vendor_id(), *hid.product_id()),
+        &mut found,
+      ),
+      ProtocolCommunicationSpecifier::USB(usb) => lookup(
+        &self.usb_ids,
+        &(*usb.vendor_id(), *usb.product_id()),
+        &mut found,
+      ),
+      ProtocolCommunicationSpecifier::Serial(serial) => {
+        lookup(&self.serial_ports, serial.port(), &mut found)
+      }
+      ProtocolCommunicationSpecifier::Websocket(websocket) => {
+        for name in websocket.names() {
+          lookup(&self.websocket_names, name, &mut found);
+        }
+      }
+      ProtocolCommunicationSpecifier::XInput(_) => {
+        found.extend(self.xinput_protocols.iter().map(String::as_str))
+      }
+      ProtocolCommunicationSpecifier::LovenseConnectService(_) => found.extend(
+        self
+          .lovense_connect_service_protocols
+          .iter()
+          .map(String::as_str),
+      ),
+    }
+    found
+  }
+
+  fn btle_candidates<'a>(&'a self, btle: &BluetoothLESpecifier, found: &mut BTreeSet<&'a str>) {
+    for name in btle.names() {
+      if name.ends_with('*') {
+        // Wildcards only show up in configs, not advertisements, so there's no point indexing for
+        // them. Just check everything.
+        found.extend(self.btle_protocols.iter().map(String::as_str));
+        return;
+      }
+      if let Some(protocols) = self.btle_names.get(name) {
+        found.extend(protocols.iter().map(String::as_str));
+      }
+      self.btle_name_prefixes.find(name, found);
+    }
+    for service in btle.advertised_services() {
+      if let Some(protocols) = self.btle_services.get(service) {
+        found.extend(protocols.iter().map(String::as_str));
+      }
+    }
+    for data in btle.manufacturer_data() {
+      if let Some(protocols) = self.btle_companies.get(data.company()) {
+        found.extend(protocols.iter().map(String::as_str));
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::{
+    server::device::configuration::{BluetoothLEManufacturerData, HIDSpecifier},
+    util::device_configuration::load_protocol_configs,
+  };
+  use std::collections::HashSet;
+
+  fn btle(names: &[&str], services: &[Uuid], companies: &[u16]) -> ProtocolCommunicationSpecifier {
+    ProtocolCommunicationSpecifier::BluetoothLE(BluetoothLESpecifier::new(
+      names.iter().map(|name| name.to_string()).collect(),
+      companies
+        .iter()
+        .map(|company| BluetoothLEManufacturerData::new(*company, &None))
+        .collect(),
+      services.iter().copied().collect(),
+      HashMap::new(),
+    ))
+  }
+
+  #[test]
+  fn test_candidates() {
+    let service = Uuid::from_u128(0x1234);
+    let mut specifiers = HashMap::new();
+    specifiers.insert("exact".to_owned(), vec![btle(&["Toy"], &[], &[])]);
+    specifiers.insert("prefix".to_owned(), vec![btle(&["To*"], &[], &[])]);
+    specifiers.insert("everything".to_owned(), vec![btle(&["*"], &[], &[])]);
+    specifiers.insert(
+      "service".to_owned(),
+      vec![btle(&["Other"], &[service], &[])],
+    );
+    specifiers.insert("company".to_owned(), vec![btle(&["Other"], &[], &[0x1a2b])]);
+    specifiers.insert(
+      "hid".to_owned(),
+      vec![ProtocolCommunicationSpecifier::HID(HIDSpecifier::new(1, 2))],
+    );
+    let index = SpecifierIndex::new(&specifiers);
+
+    let found = index.candidates(&btle(&["Toy"], &[], &[]));
+    assert_eq!(found, BTreeSet::from(["everything", "exact", "prefix"]));
+    let found = index.candidates(&btle(&["Tall"], &[service], &[0x1a2b]));
+    assert_eq!(found, BTreeSet::from(["company", "everything", "service"]));
+    let found = index.candidates(&btle(&["T*"], &[], &[]));
+    assert_eq!(found.len(), 5);
+    let found = index.candidates(&ProtocolCommunicationSpecifier::HID(HIDSpecifier::new(
+      1, 2,
+    )));
+    assert_eq!(found, BTreeSet::from(["hid"]));
+    assert!(index
+      .candidates(&ProtocolCommunicationSpecifier::HID(HIDSpecifier::new(
+        1, 3
+      )))
+      .is_empty());
+  }
+
+  #[test]
+  fn test_candidates_cover_default_config() {
+    // Every protocol a specifier from the shipped config matches has to be a candidate, or the
+    // index would hide supported devices.
+    let builder = load_protocol_configs(None, None, false).expect("Default config is valid");
+    let specifiers = &builder.communication_specifiers;
+    let index = SpecifierIndex::new(specifiers);
+    for specifier in specifiers.values().flatten() {
+      // Stand in for an advertisement, which won't have wildcards.
+      let specifier = if let ProtocolCommunicationSpecifier::BluetoothLE(btle) = specifier {
+        let names: HashSet<String> = btle
+          .names()
+          .iter()
+          .map(|name| name.trim_end_matches('*').to_owned() + "1")
+          .collect();
+        ProtocolCommunicationSpecifier::BluetoothLE(BluetoothLESpecifier::new(
+          names,
+          btle.manufacturer_data().clone(),
+          btle.advertised_services().clone(),
+          HashMap::new(),
+        ))
+      } else {
+        specifier.clone()
+      };
+      let candidates = index.candidates(&specifier);
+      for (protocol, protocol_specifiers) in specifiers {
+        if protocol_specifiers.contains(&specifier) {
+          assert!(
+            candidates.contains(protocol.as_str()),
+            "{} should be a candidate for {:?}",
+            protocol,
+            specifier
+          );
+        }
+      }
+    }
+  }
+}
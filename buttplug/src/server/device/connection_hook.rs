@@ -0,0 +1,83 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Hooks for applications hosting the server to look at devices before clients hear about them.
+//!
+//! Once a device has connected and its protocol has been set up, but before it's given an index and
+//! DeviceAdded is sent, the server asks the [DeviceConnectionHook] given to its builder what to do
+//! with it. Hosts can turn devices away (say, until the user confirms them), or give them a display
+//! name (say, from a database of the user's devices). Rejected devices are disconnected, and will
+//! be connected and asked about again if they're found on a later scan, so hosts that remember
+//! answers should do so themselves. Devices that reconnect are asked about again too.
+//!
+//! The hook is asked about each device from the task connecting it, so a hook waiting on the user
+//! doesn't hold up anything else. The device stays connected while it waits.
+
+use super::ServerDeviceIdentifier;
+use futures::future::BoxFuture;
+use getset::Getters;
+
+/// Device that has connected and is waiting on a [DeviceConnectionHook].
+#[derive(Debug, Clone, PartialEq, Eq, Getters)]
+#[getset(get = "pub")]
+pub struct ConnectingDevice {
+  /// Name of the device, as it will be sent to clients.
+  name: String,
+  identifier: ServerDeviceIdentifier,
+  /// Display name from the device configuration, if the user has given the device one.
+  display_name: Option<String>,
+}
+
+impl ConnectingDevice {
+  pub(super) fn new(
+    name: &str,
+    identifier: &ServerDeviceIdentifier,
+    display_name: Option<String>,
+  ) -> Self {
+    Self {
+      name: name.to_owned(),
+      identifier: identifier.clone(),
+      display_name,
+    }
+  }
+}
+
+/// What to do with a device a [DeviceConnectionHook] was asked about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceConnectionDecision {
+  /// Add the device as it is.
+  Accept,
+  /// Add the device, with the given display name in place of any it already had. The name is kept
+  /// until the device disconnects, and isn't saved to the device configuration.
+  AcceptWithDisplayName(String),
+  /// Disconnect the device without telling clients about it.
+  Reject,
+}
+
+/// Asked about every device that connects, before clients are told about it. See the
+/// [module documentation](self) for details.
+///
+/// Implemented for closures taking a [ConnectingDevice] and returning a boxed future of the
+/// decision, for hosts that don't need a type of their own.
+pub trait DeviceConnectionHook: Send + Sync {
+  fn device_connecting(
+    &self,
+    device: ConnectingDevice,
+  ) -> BoxFuture<'static, DeviceConnectionDecision>;
+}
+
+impl<F> DeviceConnectionHook for F
+where
+  F: Fn(ConnectingDevice) -> BoxFuture<'static, DeviceConnectionDecision> + Send + Sync,
+{
+  fn device_connecting(
+    &self,
+    device: ConnectingDevice,
+  ) -> BoxFuture<'static, DeviceConnectionDecision> {
+    self(device)
+  }
+}
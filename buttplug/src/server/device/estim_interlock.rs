@@ -0,0 +1,382 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Safety interlock for e-stim devices.
+//!
+//! Protocols mark their devices as e-stim by giving them an interlock in the device config (user
+//! configs can add one to any protocol). Every output those devices are sent then goes through the
+//! interlock, whatever protocol they use:
+//!
+//! - Output is refused until a client arms the device with [EstimArmCmd].
+//! - Once armed, output is capped by a ramp rising from nothing to full over the ramp up time.
+//! - Intensities can rise by at most a set step each step interval. Commands asking for more are
+//!   capped, and the rest of the rise waits for the next command. Intensities can always drop.
+//! - Stopping all devices, which the server does when its client disconnects or misses a ping,
+//!   zeroes and disarms the device. It has to be armed again, and ramps up from zero again.
+//!
+//! As with warm up, positions are left alone.
+//!
+//! [EstimArmCmd]: crate::core::message::EstimArmCmd
+
+use crate::core::{
+  errors::ButtplugDeviceError,
+  message::{
+    ActuatorType,
+    ButtplugDeviceCommandMessageUnion,
+    ButtplugDeviceMessage,
+    ButtplugMessage,
+    RotateCmd,
+    RotationSubcommand,
+    ScalarCmd,
+    ScalarSubcommand,
+  },
+};
+use getset::CopyGetters;
+use std::{
+  collections::HashMap,
+  sync::Mutex,
+  time::{Duration, Instant},
+};
+
+/// E-stim interlock settings for the devices of a protocol.
+#[derive(Debug, Clone, Copy, PartialEq, CopyGetters)]
+#[getset(get_copy = "pub")]
+pub struct EstimInterlock {
+  /// How long output takes to ramp from nothing to full once the device is armed.
+  ramp_up: Duration,
+  /// Most an intensity, from 0.0 to 1.0, can rise in a step interval.
+  max_step: f64,
+  /// Time over which an intensity can rise by `max_step`.
+  step_interval: Duration,
+}
+
+impl EstimInterlock {
+  pub fn new(ramp_up: Duration, max_step: f64, step_interval: Duration) -> Self {
+    Self {
+      ramp_up,
+      max_step,
+      step_interval,
+    }
+  }
+
+  /// Highest output level allowed `elapsed` after the device was armed.
+  pub fn ramp_limit(&self, elapsed: Duration) -> f64 {
+    if elapsed >= self.ramp_up {
+      1.0
+    } else {
+      elapsed.as_secs_f64() / self.ramp_up.as_secs_f64()
+    }
+  }
+
+  /// Most an intensity can rise `elapsed` after output was last sent. Rises are spread over step
+  /// intervals, so commands sent faster than that share a step between them.
+  pub fn step_limit(&self, elapsed: Duration) -> f64 {
+    if elapsed >= self.step_interval {
+      self.max_step
+    } else {
+      self.max_step * elapsed.as_secs_f64() / self.step_interval.as_secs_f64()
+    }
+  }
+}
+
+/// An output intensity, keyed the way commands address it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum OutputKey {
+  Scalar(u32),
+  Rotation(u32),
+}
+
+#[derive(Debug)]
+struct InterlockState {
+  /// When the device was armed, or None if it isn't.
+  armed_at: Option<Instant>,
+  /// When output was last let through.
+  last_output: Instant,
+  /// Intensities last let through. Anything missing is at zero.
+  levels: HashMap<OutputKey, f64>,
+}
+
+/// Holds the arming state and current output of an e-stim device, and caps its output.
+#[derive(Debug)]
+pub(super) struct EstimInterlockGuard {
+  interlock: EstimInterlock,
+  state: Mutex<InterlockState>,
+}
+
+impl EstimInterlockGuard {
+  pub fn new(interlock: EstimInterlock) -> Self {
+    Self {
+      interlock,
+      state: Mutex::new(InterlockState {
+        armed_at: None,
+        last_output: Instant::now(),
+        levels: HashMap::new(),
+      }),
+    }
+  }
+
+  fn state(&self) -> std::sync::MutexGuard<'_, InterlockState> {
+    self
+      .state
+      .lock()
+      .expect("Interlock state lock should never be poisoned.")
+  }
+
+  pub fn armed(&self) -> bool {
+    self.state().armed_at.is_some()
+  }
+
+  /// Arms the device, starting its ramp up. Arming a device that's already armed does nothing.
+  pub fn arm(&self) {
+    self.arm_at(Instant::now());
+  }
+
+  fn arm_at(&self, now: Instant) {
+    let mut state = self.state();
+    if state.armed_at.is_none() {
+      state.armed_at = Some(now);
+    }
+  }
+
+  /// Disarms the device. Output is held at zero until it's armed again, and rises from zero after
+  /// that.
+  pub fn disarm(&self) {
+    let mut state = self.state();
+    state.armed_at = None;
+    state.levels.clear();
+  }
+
+  /// Fails if the command asks for output from a device that isn't armed. Stops, and anything else
+  /// asking for nothing, are always let through.
+  pub fn check_armed(
+    &self,
+    command_message: &ButtplugDeviceCommandMessageUnion,
+  ) -> Result<(), ButtplugDeviceError> {
+    if self.armed()
+      || intensities(command_message)
+        .iter()
+        .all(|(_, level)| *level <= 0.0)
+    {
+      Ok(())
+    } else {
+      Err(ButtplugDeviceError::ProtocolRequirementError(
+        "E-stim devices must be armed with EstimArmCmd before they can output.".to_owned(),
+      ))
+    }
+  }
+
+  /// Caps the intensities in a command by the ramp up and step limits, and remembers them as the
+  /// device's current output. Capping a command again right away leaves it as is, so commands can
+  /// go through here more than once on their way to the device.
+  pub fn limit(
+    &self,
+    command_message: ButtplugDeviceCommandMessageUnion,
+  ) -> ButtplugDeviceCommandMessageUnion {
+    self.limit_at(command_message, Instant::now())
+  }
+
+  fn limit_at(
+    &self,
+    command_message: ButtplugDeviceCommandMessageUnion,
+    now: Instant,
+  ) -> ButtplugDeviceCommandMessageUnion {
+    let requested = intensities(&command_message);
+    if requested.is_empty() {
+      return command_message;
+    }
+    let mut state = self.state();
+    let ramp_limit = state.armed_at.map_or(0.0, |armed_at| {
+      self
+        .interlock
+        .ramp_limit(now.saturating_duration_since(armed_at))
+    });
+    let step_limit = self
+      .interlock
+      .step_limit(now.saturating_duration_since(state.last_output));
+    let mut levels = HashMap::new();
+    for (key, level) in requested {
+      let current = state.levels.get(&key).copied().unwrap_or(0.0);
+      let level = level.min(ramp_limit).min(current + step_limit).max(0.0);
+      state.levels.insert(key, level);
+      levels.insert(key, level);
+    }
+    state.last_output = now;
+    with_intensities(command_message, &levels)
+  }
+}
+
+/// Intensities a command asks for. Positions aren't intensities, so they're skipped.
+fn intensities(command_message: &ButtplugDeviceCommandMessageUnion) -> Vec<(OutputKey, f64)> {
+  match command_message {
+    ButtplugDeviceCommandMessageUnion::ScalarCmd(msg) => msg
+      .scalars()
+      .iter()
+      .filter(|cmd| cmd.actuator_type() != ActuatorType::Position)
+      .map(|cmd| (OutputKey::Scalar(cmd.index()), cmd.scalar()))
+      .collect(),
+    ButtplugDeviceCommandMessageUnion::RotateCmd(msg) => msg
+      .rotations()
+      .iter()
+      .map(|cmd| (OutputKey::Rotation(cmd.index()), cmd.speed()))
+      .collect(),
+    _ => vec![],
+  }
+}
+
+/// Swaps the intensities in a command for the ones in `levels`.
+fn with_intensities(
+  command_message: ButtplugDeviceCommandMessageUnion,
+  levels: &HashMap<OutputKey, f64>,
+) -> ButtplugDeviceCommandMessageUnion {
+  match command_message {
+    ButtplugDeviceCommandMessageUnion::ScalarCmd(msg) => {
+      let scalars = msg
+        .scalars()
+        .iter()
+        .map(|cmd| {
+          let scalar = levels
+            .get(&OutputKey::Scalar(cmd.index()))
+            .copied()
+            .unwrap_or(cmd.scalar());
+          ScalarSubcommand::new(cmd.index(), scalar, cmd.actuator_type())
+        })
+        .collect();
+      let mut limited = ScalarCmd::new(msg.device_index(), scalars);
+      limited.set_id(msg.id());
+      limited.into()
+    }
+    ButtplugDeviceCommandMessageUnion::RotateCmd(msg) => {
+      let rotations = msg
+        .rotations()
+        .iter()
+        .map(|cmd| {
+          let speed = levels
+            .get(&OutputKey::Rotation(cmd.index()))
+            .copied()
+            .unwrap_or(cmd.speed());
+          RotationSubcommand::new(cmd.index(), speed, cmd.clockwise())
+        })
+        .collect();
+      let mut limited = RotateCmd::new(msg.device_index(), rotations);
+      limited.set_id(msg.id());
+      limited.into()
+    }
+    msg => msg,
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn interlock() -> EstimInterlock {
+    EstimInterlock::new(Duration::from_secs(10), 0.2, Duration::from_millis(100))
+  }
+
+  fn vibrate(level: f64) -> ButtplugDeviceCommandMessageUnion {
+    ScalarCmd::new(
+      0,
+      vec![
+        ScalarSubcommand::new(0, level, ActuatorType::Vibrate),
+        ScalarSubcommand::new(1, level, ActuatorType::Position),
+      ],
+    )
+    .into()
+  }
+
+  // Levels in a limited command, rounded to get rid of float noise from adding up steps.
+  fn levels(command_message: &ButtplugDeviceCommandMessageUnion) -> Vec<f64> {
+    if let ButtplugDeviceCommandMessageUnion::ScalarCmd(msg) = command_message {
+      msg
+        .scalars()
+        .iter()
+        .map(|cmd| (cmd.scalar() * 1000.0).round() / 1000.0)
+        .collect()
+    } else {
+      panic!("Limiting should not change the message type");
+    }
+  }
+
+  #[test]
+  fn test_ramp_limit() {
+    let interlock = interlock();
+    assert_eq!(interlock.ramp_limit(Duration::ZERO), 0.0);
+    assert_eq!(interlock.ramp_limit(Duration::from_secs(5)), 0.5);
+    assert_eq!(interlock.ramp_limit(Duration::from_secs(20)), 1.0);
+    let no_ramp = EstimInterlock::new(Duration::ZERO, 0.2, Duration::from_millis(100));
+    assert_eq!(no_ramp.ramp_limit(Duration::ZERO), 1.0);
+  }
+
+  #[test]
+  fn test_step_limit() {
+    let interlock = interlock();
+    assert_eq!(interlock.step_limit(Duration::ZERO), 0.0);
+    assert!((interlock.step_limit(Duration::from_millis(50)) - 0.1).abs() < f64::EPSILON);
+    assert_eq!(interlock.step_limit(Duration::from_secs(10)), 0.2);
+  }
+
+  #[test]
+  fn test_check_armed() {
+    let guard = EstimInterlockGuard::new(interlock());
+    assert!(guard.check_armed(&vibrate(0.5)).is_err());
+    // Stops always get through.
+    assert!(guard.check_armed(&vibrate(0.0)).is_ok());
+    guard.arm();
+    assert!(guard.check_armed(&vibrate(0.5)).is_ok());
+    guard.disarm();
+    assert!(guard.check_armed(&vibrate(0.5)).is_err());
+  }
+
+  #[test]
+  fn test_output_held_at_zero_while_disarmed() {
+    let guard = EstimInterlockGuard::new(interlock());
+    let later = Instant::now() + Duration::from_secs(60);
+    assert_eq!(levels(&guard.limit_at(vibrate(1.0), later)), vec![0.0, 1.0]);
+  }
+
+  #[test]
+  fn test_ramp_from_zero() {
+    let guard = EstimInterlockGuard::new(interlock());
+    let armed_at = Instant::now();
+    guard.arm_at(armed_at);
+    // Without the ramp, this would be a full step.
+    assert_eq!(
+      levels(&guard.limit_at(vibrate(1.0), armed_at + Duration::from_millis(500))),
+      vec![0.05, 1.0]
+    );
+    // Once the ramp is done, only the step limit applies.
+    assert_eq!(
+      levels(&guard.limit_at(vibrate(1.0), armed_at + Duration::from_secs(10))),
+      vec![0.25, 1.0]
+    );
+  }
+
+  #[test]
+  fn test_step_limit_caps_rises_only() {
+    let guard = EstimInterlockGuard::new(EstimInterlock::new(
+      Duration::ZERO,
+      0.2,
+      Duration::from_millis(100),
+    ));
+    let start = Instant::now();
+    guard.arm_at(start);
+    let mut now = start + Duration::from_secs(1);
+    let mut steps = vec![];
+    for _ in 0..3 {
+      steps.push(levels(&guard.limit_at(vibrate(0.5), now))[0]);
+      now += Duration::from_millis(100);
+    }
+    assert_eq!(steps, vec![0.2, 0.4, 0.5]);
+    // Limiting the same command again right away leaves it where it is.
+    assert_eq!(levels(&guard.limit_at(vibrate(0.5), now))[0], 0.5);
+    // Drops happen at once.
+    assert_eq!(
+      levels(&guard.limit_at(vibrate(0.0), now + Duration::from_millis(1)))[0],
+      0.0
+    );
+  }
+}
@@ -0,0 +1,122 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Timestamps of traffic to and from a [Hardware](super::Hardware)
+//!
+//! Reports of devices that stopped responding usually can't say whether the hardware went quiet or
+//! the server stopped sending to it. Tracking when commands last went out, when the hardware was
+//! last heard from, and whether the protocol is keeping the connection alive on its own tells the
+//! two apart.
+
+use crate::core::errors::ButtplugDeviceError;
+use futures::future::{BoxFuture, FutureExt};
+use getset::CopyGetters;
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+use std::{
+  sync::{Arc, Mutex},
+  time::SystemTime,
+};
+
+/// When a device was last sent commands and heard from. See
+/// [Hardware::activity](super::Hardware::activity).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, CopyGetters)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[getset(get_copy = "pub")]
+pub struct HardwareActivity {
+  /// Last write to the device that succeeded, not counting keepalives.
+  last_command_sent: Option<SystemTime>,
+  /// Last notification the device sent from any endpoint.
+  last_notification_received: Option<SystemTime>,
+  /// When the protocol started keeping the device alive, if it's still doing so.
+  keepalive_active_since: Option<SystemTime>,
+  /// Last keepalive write that succeeded.
+  last_keepalive_sent: Option<SystemTime>,
+}
+
+#[derive(Default)]
+pub(super) struct ActivityTracker {
+  activity: Mutex<HardwareActivity>,
+}
+
+impl ActivityTracker {
+  pub fn activity(&self) -> HardwareActivity {
+    *self
+      .activity
+      .lock()
+      .expect("Activity lock should never be poisoned.")
+  }
+
+  fn update<F>(&self, update: F)
+  where
+    F: FnOnce(&mut HardwareActivity),
+  {
+    update(
+      &mut self
+        .activity
+        .lock()
+        .expect("Activity lock should never be poisoned."),
+    );
+  }
+
+  pub fn record_notification(&self) {
+    self.update(|activity| activity.last_notification_received = Some(SystemTime::now()));
+  }
+
+  pub fn record_command(&self) {
+    self.update(|activity| activity.last_command_sent = Some(SystemTime::now()));
+  }
+
+  /// Runs `write`, recording it as a command if it succeeds.
+  pub fn track_command(
+    self: &Arc<Self>,
+    write: BoxFuture<'static, Result<(), ButtplugDeviceError>>,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    let tracker = self.clone();
+    async move {
+      write.await?;
+      tracker.record_command();
+      Ok(())
+    }
+    .boxed()
+  }
+
+  /// Runs `write`, recording it as a keepalive if it succeeds.
+  pub fn track_keepalive(
+    self: &Arc<Self>,
+    write: BoxFuture<'static, Result<(), ButtplugDeviceError>>,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    let tracker = self.clone();
+    async move {
+      write.await?;
+      tracker.update(|activity| activity.last_keepalive_sent = Some(SystemTime::now()));
+      Ok(())
+    }
+    .boxed()
+  }
+
+  pub fn start_keepalive(self: &Arc<Self>) -> KeepaliveGuard {
+    self.update(|activity| activity.keepalive_active_since = Some(SystemTime::now()));
+    KeepaliveGuard {
+      tracker: self.clone(),
+    }
+  }
+}
+
+/// Marks the keepalive of a device as running until dropped. See
+/// [Hardware::start_keepalive](super::Hardware::start_keepalive).
+pub struct KeepaliveGuard {
+  tracker: Arc<ActivityTracker>,
+}
+
+impl Drop for KeepaliveGuard {
+  fn drop(&mut self) {
+    self
+      .tracker
+      .update(|activity| activity.keepalive_active_since = None);
+  }
+}
@@ -0,0 +1,94 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Stable surface for adding hardware support from outside this crate.
+//!
+//! Crates that implement their own communication managers (for buses or platforms we don't cover)
+//! should import from here instead of from [hardware](super) or its
+//! [communication](super::communication) module. Everything re-exported here follows semver:
+//! breaking changes to these traits and types only happen in major releases. Anything else under
+//! [hardware](super) is an implementation detail that may change whenever we need it to.
+//!
+//! Adding hardware support takes four pieces:
+//!
+//! - A [HardwareCommunicationManagerBuilder], which the server uses to create a
+//!   [HardwareCommunicationManager] when it starts up.
+//! - The [HardwareCommunicationManager] (or a [TimedRetryCommunicationManagerImpl], for buses
+//!   that need to be polled), which sends a
+//!   [DeviceFound](HardwareCommunicationManagerEvent::DeviceFound) event with a
+//!   [HardwareConnector] for each device it finds while scanning.
+//! - The [HardwareConnector], which describes the device with a [ProtocolCommunicationSpecifier]
+//!   so it can be matched against the device configuration, then connects to it and hands back a
+//!   [HardwareSpecializer].
+//! - The [HardwareSpecializer], which builds the [Hardware] for the protocol that matched, using a
+//!   [HardwareInternal] implementation to do the actual communication. Use
+//!   [GenericHardwareSpecializer] if the bus has nothing to specialize.
+//!
+//! Devices are shared between the server's tasks, so all of the extension traits require `Send +
+//! Sync` (only `Send` for [HardwareCommunicationManagerBuilder], which is consumed on the task that
+//! creates the manager), and every future they return must be `Send + 'static`.
+
+pub use super::{
+  communication::{
+    HardwareCommunicationManager,
+    HardwareCommunicationManagerBuilder,
+    HardwareCommunicationManagerConfig,
+    HardwareCommunicationManagerEvent,
+    HardwareCommunicationManagerStatus,
+    HardwarePermission,
+    HardwarePermissionError,
+    TimedRetryCommunicationManager,
+    TimedRetryCommunicationManagerImpl,
+  },
+  BluetoothLEConnectionParameters,
+  BluetoothLEWriteCoalescing,
+  GenericHardwareSpecializer,
+  Hardware,
+  HardwareCommand,
+  HardwareConnector,
+  HardwareEvent,
+  HardwareInternal,
+  HardwareReadCmd,
+  HardwareReading,
+  HardwareSpecializer,
+  HardwareSubscribeCmd,
+  HardwareUnsubscribeCmd,
+  HardwareWriteCmd,
+};
+pub use crate::{
+  core::{errors::ButtplugDeviceError, message::Endpoint},
+  server::device::configuration::{
+    BluetoothLESpecifier,
+    HIDSpecifier,
+    LovenseConnectServiceSpecifier,
+    ProtocolCommunicationSpecifier,
+    SerialSpecifier,
+    USBSpecifier,
+    WebsocketSpecifier,
+    XInputSpecifier,
+  },
+};
+
+// Fails to build if anything on the extension surface stops being safe to share between tasks, so
+// bounds can't be loosened by accident in a refactor.
+#[allow(dead_code)]
+fn assert_thread_safety() {
+  fn send_sync<T: Send + Sync + ?Sized>() {}
+  fn send<T: Send + ?Sized>() {}
+  send_sync::<Hardware>();
+  send_sync::<HardwareEvent>();
+  send_sync::<HardwareCommand>();
+  send_sync::<HardwareReading>();
+  send_sync::<HardwareCommunicationManagerEvent>();
+  send_sync::<HardwareCommunicationManagerStatus>();
+  send_sync::<ProtocolCommunicationSpecifier>();
+  send_sync::<dyn HardwareInternal>();
+  send_sync::<dyn HardwareConnector>();
+  send_sync::<dyn HardwareSpecializer>();
+  send_sync::<dyn HardwareCommunicationManager>();
+  send::<dyn HardwareCommunicationManagerBuilder>();
+}
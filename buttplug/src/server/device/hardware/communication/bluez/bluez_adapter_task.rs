@@ -0,0 +1,342 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::{
+  bluez_comm_manager::{BluezAgentConfig, BluezCommunicationManagerConfig},
+  bluez_hardware::BluezHardwareConnector,
+};
+use crate::{
+  server::device::hardware::communication::{
+    btle_common::BtleAdvertisement,
+    HardwareCommunicationManagerEvent,
+  },
+  util::{address_privacy::display_address, async_manager},
+};
+use bluer::{
+  agent::{Agent, RequestPasskeyFn, RequestPinCodeFn},
+  Adapter,
+  AdapterEvent,
+  Address,
+  Device,
+  DiscoveryFilter,
+  DiscoveryTransport,
+  Session,
+};
+use futures::{future::FutureExt, StreamExt};
+use std::{
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
+  time::Duration,
+};
+use tokio::{
+  sync::mpsc::{unbounded_channel, Receiver, Sender, UnboundedSender},
+  time::sleep,
+};
+use tokio_util::sync::{CancellationToken, DropGuard};
+
+#[derive(Debug, Clone)]
+pub enum BluezAdapterCommand {
+  /// Start scanning, for the device with the given address only if one is given.
+  StartScanning(Option<String>),
+  StopScanning,
+}
+
+fn pairing_agent(config: &BluezAgentConfig) -> Agent {
+  let request_pin_code = config.pin_code.clone().map(|pin_code| {
+    let request: RequestPinCodeFn = Box::new(move |request| {
+      info!("Sending PIN code for pairing with {}", request.device);
+      let pin_code = pin_code.clone();
+      async move { Ok(pin_code) }.boxed()
+    });
+    request
+  });
+  let request_passkey = config.passkey.map(|passkey| {
+    let request: RequestPasskeyFn = Box::new(move |request| {
+      info!("Sending passkey for pairing with {}", request.device);
+      async move { Ok(passkey) }.boxed()
+    });
+    request
+  });
+  Agent {
+    request_default: config.request_default,
+    request_pin_code,
+    request_passkey,
+    request_confirmation: Some(Box::new(|request| {
+      info!(
+        "Confirming pairing with {} (passkey {:06})",
+        request.device, request.passkey
+      );
+      async { Ok(()) }.boxed()
+    })),
+    ..Default::default()
+  }
+}
+
+async fn advertisement_for_device(device: &Device) -> bluer::Result<BtleAdvertisement> {
+  // BlueZ hands services back as a set, sort them so repeated lookups compare equal.
+  let mut services: Vec<_> = device
+    .uuids()
+    .await?
+    .unwrap_or_default()
+    .into_iter()
+    .collect();
+  services.sort();
+  Ok(BtleAdvertisement {
+    name: device.name().await?.unwrap_or_default(),
+    address: device.address().to_string(),
+    manufacturer_data: device.manufacturer_data().await?.unwrap_or_default(),
+    services,
+    rssi: device.rssi().await?,
+  })
+}
+
+pub struct BluezAdapterTask {
+  event_sender: Sender<HardwareCommunicationManagerEvent>,
+  command_receiver: Receiver<BluezAdapterCommand>,
+  adapter_connected: Arc<AtomicBool>,
+  config: BluezCommunicationManagerConfig,
+}
+
+impl BluezAdapterTask {
+  pub fn new(
+    event_sender: Sender<HardwareCommunicationManagerEvent>,
+    command_receiver: Receiver<BluezAdapterCommand>,
+    adapter_connected: Arc<AtomicBool>,
+    config: BluezCommunicationManagerConfig,
+  ) -> Self {
+    Self {
+      event_sender,
+      command_receiver,
+      adapter_connected,
+      config,
+    }
+  }
+
+  async fn open_adapter(&self, session: &Session) -> Adapter {
+    // Start by assuming we'll find the adapter on the first try. If not, we'll print an error
+    // message then loop while trying to find it.
+    let mut adapter_found = true;
+    loop {
+      let adapter = match &self.config.adapter_name {
+        Some(name) => session.adapter(name),
+        None => session.default_adapter().await,
+      };
+      let error = match adapter {
+        Ok(adapter) => match adapter.set_powered(true).await {
+          Ok(()) => {
+            info!("BlueZ adapter {} found.", adapter.name());
+            self.adapter_connected.store(true, Ordering::SeqCst);
+            return adapter;
+          }
+          Err(err) => err,
+        },
+        Err(err) => err,
+      };
+      if adapter_found {
+        adapter_found = false;
+        warn!("Bluetooth LE adapter not found ({}), will not be using bluetooth scanning until found. Buttplug will continue polling for the adapter, but no more warning messages will be posted.", error);
+      }
+      sleep(Duration::from_secs(1)).await;
+    }
+  }
+
+  async fn start_discovery(
+    &self,
+    adapter: &Adapter,
+    event_sender: UnboundedSender<AdapterEvent>,
+    target: Option<&str>,
+  ) -> bluer::Result<DropGuard> {
+    // BlueZ matches patterns against the start of addresses as well as names, so a full address
+    // narrows discovery down to the device we're looking for.
+    let pattern = target
+      .map(|address| address.to_owned())
+      .or_else(|| self.config.name_pattern.clone());
+    let filter = DiscoveryFilter {
+      uuids: self.config.scan_services.iter().cloned().collect(),
+      rssi: self.config.rssi_threshold,
+      pathloss: self.config.pathloss_threshold,
+      transport: DiscoveryTransport::Le,
+      pattern,
+      ..Default::default()
+    };
+    adapter.set_discovery_filter(filter).await?;
+    let discovery = adapter.discover_devices_with_changes().await?;
+    // Discovery runs for as long as the stream is alive, so it gets its own task that we can cancel
+    // on stop.
+    let token = CancellationToken::new();
+    let child_token = token.child_token();
+    async_manager::spawn(async move {
+      let mut discovery = Box::pin(discovery);
+      loop {
+        select! {
+          _ = child_token.cancelled().fuse() => break,
+          event = discovery.next().fuse() => {
+            match event {
+              Some(event) => {
+                if event_sender.send(event).is_err() {
+                  break;
+                }
+              }
+              None => break,
+            }
+          }
+        }
+      }
+      debug!("BlueZ discovery stopped.");
+    });
+    Ok(token.drop_guard())
+  }
+
+  async fn maybe_add_device(
+    &self,
+    adapter: &Adapter,
+    address: Address,
+    tried_addresses: &mut Vec<BtleAdvertisement>,
+  ) {
+    let device = match adapter.device(address) {
+      Ok(device) => device,
+      Err(err) => {
+        error!(
+          "Device with address {} not found: {}",
+          display_address(&address.to_string()),
+          err
+        );
+        return;
+      }
+    };
+    let advertisement = match advertisement_for_device(&device).await {
+      Ok(advertisement) => advertisement,
+      Err(err) => {
+        error!(
+          "Cannot retreive device properties for {}: {}",
+          display_address(&address.to_string()),
+          err
+        );
+        return;
+      }
+    };
+    if advertisement.is_identifiable() && !tried_addresses.contains(&advertisement) {
+      let span = info_span!(
+        "bluez enumeration",
+        address = tracing::field::display(display_address(&advertisement.address)),
+        name = tracing::field::display(&advertisement.name)
+      );
+      let _enter = span.enter();
+
+      debug!(
+        "Found new bluetooth device advertisement: {:?}",
+        advertisement
+      );
+      tried_addresses.push(advertisement.clone());
+      let name = advertisement.name.clone();
+      let address = advertisement.address.clone();
+      let device_creator = Box::new(BluezHardwareConnector::new(
+        advertisement,
+        device,
+        self.config.pair_devices,
+      ));
+      if self
+        .event_sender
+        .send(HardwareCommunicationManagerEvent::DeviceFound {
+          name,
+          address,
+          creator: device_creator,
+        })
+        .await
+        .is_err()
+      {
+        error!("Device manager receiver dropped, cannot send device found message.");
+      }
+    } else {
+      trace!(
+        "Device {} found, no advertised name, ignoring.",
+        display_address(&advertisement.address)
+      );
+    }
+  }
+
+  pub async fn run(&mut self) {
+    let session = match Session::new().await {
+      Ok(session) => session,
+      Err(err) => {
+        error!("Error connecting to BlueZ: {}", err);
+        return;
+      }
+    };
+
+    // The agent stays registered as long as we hold its handle.
+    let _agent_handle = if let Some(agent_config) = &self.config.agent {
+      match session.register_agent(pairing_agent(agent_config)).await {
+        Ok(handle) => Some(handle),
+        Err(err) => {
+          error!("Cannot register BlueZ pairing agent: {}", err);
+          None
+        }
+      }
+    } else {
+      None
+    };
+
+    let adapter = self.open_adapter(&session).await;
+
+    let (discovery_sender, mut discovery_receiver) = unbounded_channel();
+    let mut discovery_guard: Option<DropGuard> = None;
+    let mut tried_addresses = vec![];
+
+    loop {
+      select! {
+        event = discovery_receiver.recv().fuse() => {
+          match event {
+            Some(AdapterEvent::DeviceAdded(address)) => {
+              self.maybe_add_device(&adapter, address, &mut tried_addresses).await;
+            }
+            Some(AdapterEvent::DeviceRemoved(address)) => {
+              let address = address.to_string();
+              debug!("BlueZ device removed: {}", display_address(&address));
+              tried_addresses.retain(|info| info.address != address);
+            }
+            Some(event) => {
+              trace!("Unhandled BlueZ adapter event: {:?}", event);
+            }
+            // We always hold a sender, so this never closes.
+            None => unreachable!(),
+          }
+        },
+        command = self.command_receiver.recv().fuse() => {
+          if let Some(cmd) = command {
+            match cmd {
+              BluezAdapterCommand::StartScanning(target) => {
+                tried_addresses.clear();
+                // Drop any running discovery before starting a new one.
+                discovery_guard.take();
+                // BlueZ keeps devices it has seen before around, so a device we're looking for
+                // may be connectable without waiting for it to advertise again.
+                if let Some(address) = target.as_ref().and_then(|target| target.parse::<Address>().ok()) {
+                  if adapter.device_addresses().await.map_or(false, |known| known.contains(&address)) {
+                    self.maybe_add_device(&adapter, address, &mut tried_addresses).await;
+                  }
+                }
+                match self.start_discovery(&adapter, discovery_sender.clone(), target.as_deref()).await {
+                  Ok(guard) => discovery_guard = Some(guard),
+                  Err(err) => error!("Start scanning request failed: {}", err),
+                }
+              }
+              BluezAdapterCommand::StopScanning => {
+                discovery_guard.take();
+              }
+            }
+          } else {
+            debug!("Command stream closed. Exiting BlueZ adapter loop.");
+            return;
+          }
+        }
+      }
+    }
+  }
+}
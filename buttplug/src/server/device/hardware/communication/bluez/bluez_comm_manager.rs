@@ -0,0 +1,186 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::bluez_adapter_task::{BluezAdapterCommand, BluezAdapterTask};
+use crate::{
+  core::{errors::ButtplugDeviceError, ButtplugResultFuture},
+  server::device::hardware::communication::{
+    HardwareCommunicationManager,
+    HardwareCommunicationManagerBuilder,
+    HardwareCommunicationManagerConfig,
+    HardwareCommunicationManagerEvent,
+    HardwareCommunicationManagerStatus,
+  },
+  util::async_manager,
+};
+use futures::future::FutureExt;
+use std::sync::{
+  atomic::{AtomicBool, Ordering},
+  Arc,
+};
+use tokio::sync::mpsc::{channel, Sender};
+use uuid::Uuid;
+
+/// Pairing agent settings. BlueZ asks the agent for credentials whenever a device needs to bond.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct BluezAgentConfig {
+  /// PIN code to answer legacy pairing requests with.
+  pub pin_code: Option<String>,
+  /// Passkey to answer pairing requests with.
+  pub passkey: Option<u32>,
+  /// Ask BlueZ to make this the default agent for the system, so it also handles pairing requests
+  /// not started by us.
+  pub request_default: bool,
+}
+
+/// Runtime settings for BlueZ bluetooth LE scanning and connection.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct BluezCommunicationManagerConfig {
+  /// Name of the adapter to use (i.e. `hci0`). Defaults to the BlueZ default adapter.
+  pub adapter_name: Option<String>,
+  /// If not empty, only devices advertising at least one of these services will be reported while
+  /// scanning.
+  pub scan_services: Vec<Uuid>,
+  /// Only report devices with a received signal strength above this value.
+  pub rssi_threshold: Option<i16>,
+  /// Only report devices with a computed pathloss below this value.
+  pub pathloss_threshold: Option<u16>,
+  /// Only report devices whose name or address starts with this pattern.
+  pub name_pattern: Option<String>,
+  /// Pair (bond) with devices before connecting, if they aren't paired already.
+  pub pair_devices: bool,
+  /// If set, register a pairing agent with BlueZ while the comm manager is alive.
+  pub agent: Option<BluezAgentConfig>,
+}
+
+impl HardwareCommunicationManagerConfig for BluezCommunicationManagerConfig {
+  type Builder = BluezCommunicationManagerBuilder;
+
+  fn into_builder(self) -> Self::Builder {
+    BluezCommunicationManagerBuilder::default().config(self)
+  }
+}
+
+#[derive(Default, Clone)]
+pub struct BluezCommunicationManagerBuilder {
+  config: BluezCommunicationManagerConfig,
+}
+
+impl BluezCommunicationManagerBuilder {
+  pub fn config(mut self, config: BluezCommunicationManagerConfig) -> Self {
+    self.config = config;
+    self
+  }
+}
+
+impl HardwareCommunicationManagerBuilder for BluezCommunicationManagerBuilder {
+  fn finish(
+    &mut self,
+    sender: Sender<HardwareCommunicationManagerEvent>,
+  ) -> Box<dyn HardwareCommunicationManager> {
+    Box::new(BluezCommunicationManager::new(sender, self.config.clone()))
+  }
+}
+
+pub(crate) struct BluezCommunicationManager {
+  adapter_event_sender: Sender<BluezAdapterCommand>,
+  scanning_status: Arc<AtomicBool>,
+  adapter_connected: Arc<AtomicBool>,
+}
+
+impl BluezCommunicationManager {
+  pub fn new(
+    event_sender: Sender<HardwareCommunicationManagerEvent>,
+    config: BluezCommunicationManagerConfig,
+  ) -> Self {
+    let (sender, receiver) = channel(256);
+    let adapter_connected = Arc::new(AtomicBool::new(false));
+    let adapter_connected_clone = adapter_connected.clone();
+    async_manager::spawn(async move {
+      let mut task = BluezAdapterTask::new(event_sender, receiver, adapter_connected_clone, config);
+      task.run().await;
+    });
+    Self {
+      adapter_event_sender: sender,
+      scanning_status: Arc::new(AtomicBool::new(false)),
+      adapter_connected,
+    }
+  }
+}
+
+impl BluezCommunicationManager {
+  fn send_start_scanning(&mut self, target: Option<String>) -> ButtplugResultFuture {
+    let adapter_event_sender = self.adapter_event_sender.clone();
+    let scanning_status = self.scanning_status.clone();
+    // Set to true just to make sure we don't call ScanningFinished too early.
+    scanning_status.store(true, Ordering::SeqCst);
+    async move {
+      if adapter_event_sender
+        .send(BluezAdapterCommand::StartScanning(target))
+        .await
+        .is_err()
+      {
+        error!("Error starting scan, cannot send to BlueZ event loop.");
+        scanning_status.store(false, Ordering::SeqCst);
+        Err(
+          ButtplugDeviceError::DeviceConnectionError(
+            "Cannot send start scanning request to event loop.".to_owned(),
+          )
+          .into(),
+        )
+      } else {
+        Ok(())
+      }
+    }
+    .boxed()
+  }
+}
+
+impl HardwareCommunicationManager for BluezCommunicationManager {
+  fn name(&self) -> &'static str {
+    "BluezCommunicationManager"
+  }
+
+  fn start_scanning(&mut self) -> ButtplugResultFuture {
+    self.send_start_scanning(None)
+  }
+
+  fn start_targeted_scanning(&mut self, address: &str) -> ButtplugResultFuture {
+    self.send_start_scanning(Some(address.to_owned()))
+  }
+
+  fn stop_scanning(&mut self) -> ButtplugResultFuture {
+    let adapter_event_sender = self.adapter_event_sender.clone();
+    // Just assume any outcome of this means we're done scanning.
+    self.scanning_status.store(false, Ordering::SeqCst);
+    async move {
+      if adapter_event_sender
+        .send(BluezAdapterCommand::StopScanning)
+        .await
+        .is_err()
+      {
+        error!("Error stopping scan, cannot send to BlueZ event loop.");
+        Err(
+          ButtplugDeviceError::DeviceConnectionError(
+            "Cannot send stop scanning request to event loop.".to_owned(),
+          )
+          .into(),
+        )
+      } else {
+        Ok(())
+      }
+    }
+    .boxed()
+  }
+
+  fn status(&self) -> HardwareCommunicationManagerStatus {
+    HardwareCommunicationManagerStatus::from_availability(
+      self.adapter_connected.load(Ordering::SeqCst),
+      self.scanning_status.load(Ordering::SeqCst),
+    )
+  }
+}
@@ -0,0 +1,392 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use crate::{
+  core::{errors::ButtplugDeviceError, message::Endpoint},
+  server::device::{
+    configuration::ProtocolCommunicationSpecifier,
+    hardware::{
+      communication::{
+        btle_common::{map_btle_endpoints, resolve_write_with_response, BtleAdvertisement},
+        HardwareSpecificError,
+      },
+      Hardware,
+      HardwareConnector,
+      HardwareEvent,
+      HardwareInternal,
+      HardwareReadCmd,
+      HardwareReading,
+      HardwareSpecializer,
+      HardwareSubscribeCmd,
+      HardwareUnsubscribeCmd,
+      HardwareWriteCmd,
+    },
+  },
+  util::{address_privacy::display_address, async_manager},
+};
+use async_trait::async_trait;
+use bluer::{
+  gatt::{
+    remote::{Characteristic, CharacteristicWriteRequest},
+    WriteOp,
+  },
+  Device,
+  DeviceEvent,
+  DeviceProperty,
+};
+use dashmap::DashMap;
+use futures::{
+  future::{self, BoxFuture, FutureExt},
+  StreamExt,
+};
+use std::{
+  collections::HashMap,
+  fmt::{self, Debug},
+  sync::Arc,
+};
+use tokio::sync::broadcast;
+use tokio_util::sync::{CancellationToken, DropGuard};
+use uuid::Uuid;
+
+fn bluez_error(err: bluer::Error) -> ButtplugDeviceError {
+  ButtplugDeviceError::from(HardwareSpecificError::BluezError(err.to_string()))
+}
+
+pub(super) struct BluezHardwareConnector {
+  advertisement: BtleAdvertisement,
+  device: Device,
+  pair_device: bool,
+}
+
+impl BluezHardwareConnector {
+  pub fn new(advertisement: BtleAdvertisement, device: Device, pair_device: bool) -> Self {
+    Self {
+      advertisement,
+      device,
+      pair_device,
+    }
+  }
+}
+
+impl Debug for BluezHardwareConnector {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("BluezHardwareConnector")
+      .field("name", &self.advertisement.name)
+      .field("address", &display_address(&self.advertisement.address))
+      .finish()
+  }
+}
+
+#[async_trait]
+impl HardwareConnector for BluezHardwareConnector {
+  fn specifier(&self) -> ProtocolCommunicationSpecifier {
+    self.advertisement.specifier()
+  }
+
+  fn rssi(&self) -> Option<i16> {
+    self.advertisement.rssi
+  }
+
+  async fn connect(&mut self) -> Result<Box<dyn HardwareSpecializer>, ButtplugDeviceError> {
+    if self.pair_device && !self.device.is_paired().await.map_err(bluez_error)? {
+      info!(
+        "Pairing with {}",
+        display_address(&self.advertisement.address)
+      );
+      self.device.pair().await.map_err(bluez_error)?;
+      // Trusted devices can reconnect without going through the agent again.
+      if let Err(err) = self.device.set_trusted(true).await {
+        warn!(
+          "Cannot mark {} as trusted: {}",
+          display_address(&self.advertisement.address),
+          err
+        );
+      }
+    }
+    if !self.device.is_connected().await.map_err(bluez_error)? {
+      self.device.connect().await.map_err(bluez_error)?;
+    }
+    // Waits for BlueZ to finish resolving services before returning.
+    let mut services = vec![];
+    for service in self.device.services().await.map_err(bluez_error)? {
+      let mut characteristics = vec![];
+      for chr in service.characteristics().await.map_err(bluez_error)? {
+        characteristics.push((chr.uuid().await.map_err(bluez_error)?, chr));
+      }
+      services.push((service.uuid().await.map_err(bluez_error)?, characteristics));
+    }
+    Ok(Box::new(BluezHardwareSpecializer {
+      name: self.advertisement.name.clone(),
+      address: self.advertisement.address.clone(),
+      device: self.device.clone(),
+      services,
+    }))
+  }
+}
+
+pub struct BluezHardwareSpecializer {
+  name: String,
+  address: String,
+  device: Device,
+  services: Vec<(Uuid, Vec<(Uuid, Characteristic)>)>,
+}
+
+#[async_trait]
+impl HardwareSpecializer for BluezHardwareSpecializer {
+  async fn specialize(
+    &mut self,
+    specifiers: &[ProtocolCommunicationSpecifier],
+  ) -> Result<Hardware, ButtplugDeviceError> {
+    let endpoints = map_btle_endpoints(&self.name, &self.address, specifiers, &self.services)?;
+    let device_internal_impl = BluezHardware::new(
+      self.device.clone(),
+      &self.name,
+      &self.address,
+      endpoints.clone(),
+    );
+    Ok(Hardware::new(
+      &self.name,
+      &self.address,
+      &endpoints.keys().cloned().collect::<Vec<Endpoint>>(),
+      Box::new(device_internal_impl),
+    ))
+  }
+}
+
+pub struct BluezHardware {
+  device: Device,
+  address: String,
+  event_stream: broadcast::Sender<HardwareEvent>,
+  endpoints: HashMap<Endpoint, Characteristic>,
+  // Dropping a guard ends the notification task for that endpoint.
+  subscribed_endpoints: Arc<DashMap<Endpoint, DropGuard>>,
+}
+
+impl BluezHardware {
+  pub fn new(
+    device: Device,
+    name: &str,
+    address: &str,
+    endpoints: HashMap<Endpoint, Characteristic>,
+  ) -> Self {
+    let (event_stream, _) = broadcast::channel(256);
+    let event_stream_clone = event_stream.clone();
+    let device_clone = device.clone();
+    let address_clone = address.to_owned();
+    let name_clone = name.to_owned();
+    async_manager::spawn(async move {
+      let events = match device_clone.events().await {
+        Ok(events) => events,
+        Err(err) => {
+          error!(
+            "Cannot watch BlueZ device events for {}: {}",
+            name_clone, err
+          );
+          return;
+        }
+      };
+      let mut events = Box::pin(events);
+      while let Some(event) = events.next().await {
+        if let DeviceEvent::PropertyChanged(DeviceProperty::Connected(false)) = event {
+          info!("Device {:?} disconnected", name_clone);
+          if event_stream_clone.receiver_count() != 0 {
+            if let Err(err) =
+              event_stream_clone.send(HardwareEvent::Disconnected(address_clone.clone()))
+            {
+              error!(
+                "Cannot send notification, device object disappeared: {:?}",
+                err
+              );
+            }
+          }
+          // At this point, we have nothing left to do because we can't reconnect a device
+          // that's been connected. Exit.
+          break;
+        }
+      }
+      info!(
+        "Exiting BlueZ event loop for device {}",
+        display_address(&address_clone)
+      )
+    });
+    Self {
+      device,
+      address: address.to_owned(),
+      event_stream,
+      endpoints,
+      subscribed_endpoints: Arc::new(DashMap::new()),
+    }
+  }
+}
+
+impl HardwareInternal for BluezHardware {
+  fn event_stream(&self) -> broadcast::Receiver<HardwareEvent> {
+    self.event_stream.subscribe()
+  }
+
+  fn disconnect(&self) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    self.subscribed_endpoints.clear();
+    let device = self.device.clone();
+    async move { device.disconnect().await.map_err(bluez_error) }.boxed()
+  }
+
+  fn write_value(
+    &self,
+    msg: &HardwareWriteCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    let characteristic = match self.endpoints.get(&msg.endpoint) {
+      Some(chr) => chr.clone(),
+      None => {
+        return future::ready(Err(ButtplugDeviceError::InvalidEndpoint(msg.endpoint))).boxed();
+      }
+    };
+    let write_with_response = msg.write_with_response;
+    let data = msg.data.clone();
+    async move {
+      let flags = characteristic.flags().await.map_err(bluez_error)?;
+      let op_type = if resolve_write_with_response(
+        write_with_response,
+        flags.write,
+        flags.write_without_response,
+      ) {
+        WriteOp::Request
+      } else {
+        WriteOp::Command
+      };
+      let request = CharacteristicWriteRequest {
+        op_type,
+        ..Default::default()
+      };
+      characteristic
+        .write_ext(&data, &request)
+        .await
+        .map_err(|err| {
+          error!("BlueZ device write error: {}", err);
+          bluez_error(err)
+        })
+    }
+    .boxed()
+  }
+
+  fn read_value(
+    &self,
+    msg: &HardwareReadCmd,
+  ) -> BoxFuture<'static, Result<HardwareReading, ButtplugDeviceError>> {
+    let characteristic = match self.endpoints.get(&msg.endpoint) {
+      Some(chr) => chr.clone(),
+      None => {
+        return future::ready(Err(ButtplugDeviceError::InvalidEndpoint(msg.endpoint))).boxed();
+      }
+    };
+    let endpoint = msg.endpoint;
+    async move {
+      let data = characteristic.read().await.map_err(|err| {
+        error!("BlueZ device read error: {}", err);
+        bluez_error(err)
+      })?;
+      trace!("Got reading: {:?}", data);
+      Ok(HardwareReading::new(endpoint, &data))
+    }
+    .boxed()
+  }
+
+  fn read_rssi(&self) -> BoxFuture<'static, Result<i16, ButtplugDeviceError>> {
+    let device = self.device.clone();
+    async move {
+      // BlueZ only updates RSSI while discovery is running, so there may not be one to give.
+      device.rssi().await.map_err(bluez_error)?.ok_or_else(|| {
+        ButtplugDeviceError::DeviceCommunicationError("BlueZ has no RSSI for device".to_owned())
+      })
+    }
+    .boxed()
+  }
+
+  fn subscribe(
+    &self,
+    msg: &HardwareSubscribeCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    let endpoint = msg.endpoint;
+    if self.subscribed_endpoints.contains_key(&endpoint) {
+      debug!(
+        "Endpoint {} already subscribed, ignoring and returning Ok.",
+        endpoint
+      );
+      return future::ready(Ok(())).boxed();
+    }
+    let characteristic = match self.endpoints.get(&endpoint) {
+      Some(chr) => chr.clone(),
+      None => {
+        return future::ready(Err(ButtplugDeviceError::InvalidEndpoint(msg.endpoint))).boxed();
+      }
+    };
+    let event_stream = self.event_stream.clone();
+    let address = self.address.clone();
+    let endpoints = self.subscribed_endpoints.clone();
+    async move {
+      let notifications = characteristic.notify().await.map_err(bluez_error)?;
+      let token = CancellationToken::new();
+      let child_token = token.child_token();
+      async_manager::spawn(async move {
+        let mut notifications = Box::pin(notifications);
+        loop {
+          select! {
+            _ = child_token.cancelled().fuse() => break,
+            data = notifications.next().fuse() => {
+              let data = if let Some(data) = data {
+                data
+              } else {
+                break;
+              };
+              if event_stream.receiver_count() == 0 {
+                continue;
+              }
+              if let Err(err) =
+                event_stream.send(HardwareEvent::Notification(address.clone(), endpoint, data))
+              {
+                error!(
+                  "Cannot send notification, device object disappeared: {:?}",
+                  err
+                );
+                break;
+              }
+            }
+          }
+        }
+      });
+      endpoints.insert(endpoint, token.drop_guard());
+      Ok(())
+    }
+    .boxed()
+  }
+
+  fn unsubscribe(
+    &self,
+    msg: &HardwareUnsubscribeCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    let endpoint = msg.endpoint;
+    // BlueZ stops notifying once the notification stream is dropped, which happens when the
+    // subscription task exits.
+    if self.subscribed_endpoints.remove(&endpoint).is_none() {
+      debug!(
+        "Endpoint {} already unsubscribed, ignoring and returning Ok.",
+        endpoint
+      );
+    }
+    future::ready(Ok(())).boxed()
+  }
+}
+
+impl Drop for BluezHardware {
+  fn drop(&mut self) {
+    let disconnect_fut = self.disconnect();
+    async_manager::spawn(async move {
+      if let Err(e) = disconnect_fut.await {
+        error!("Error disconnecting BlueZ device: {:?}", e);
+      }
+    });
+  }
+}
@@ -0,0 +1,22 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Linux native bluetooth LE support, talking to BlueZ over D-Bus via bluer.
+//!
+//! This is an alternative to the btleplug comm manager for setups that need a pairing agent for
+//! bonded devices, or discovery filters (RSSI/pathloss thresholds, name patterns) btleplug can't
+//! express. Use one or the other, not both, otherwise every device will be found twice.
+
+mod bluez_adapter_task;
+pub mod bluez_comm_manager;
+mod bluez_hardware;
+
+pub use bluez_comm_manager::{
+  BluezAgentConfig,
+  BluezCommunicationManagerBuilder,
+  BluezCommunicationManagerConfig,
+};
@@ -0,0 +1,410 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Bluetooth LE glue shared by the btleplug, WinRT and BlueZ comm managers.
+//!
+//! All backends see the same advertisements and GATT tables, they just get to them through
+//! different platform APIs. Anything that decides what a device *is* (specifier matching, endpoint
+//! mapping, write type selection) lives here so device configs behave the same no matter which
+//! backend is in use, along with write coalescing, which doesn't depend on the platform API.
+
+use crate::{
+  core::{errors::ButtplugDeviceError, message::Endpoint},
+  server::device::{
+    configuration::{BluetoothLESpecifier, ProtocolCommunicationSpecifier},
+    hardware::BluetoothLEWriteCoalescing,
+  },
+  util::{address_privacy::display_address, async_manager, sleep},
+};
+use futures::future::{BoxFuture, FutureExt};
+use std::{
+  collections::HashMap,
+  fmt::{self, Debug},
+  sync::{Arc, Mutex},
+  time::Instant,
+};
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+/// Advertisement information needed to decide whether a device is worth connecting to.
+#[derive(Clone)]
+pub(crate) struct BtleAdvertisement {
+  pub name: String,
+  pub address: String,
+  pub manufacturer_data: HashMap<u16, Vec<u8>>,
+  pub services: Vec<Uuid>,
+  /// Signal strength when the advertisement was seen, if the backend reports it.
+  pub rssi: Option<i16>,
+}
+
+// Signal strength changes with every advertisement, so it's left out when checking whether we've
+// already seen a device.
+impl PartialEq for BtleAdvertisement {
+  fn eq(&self, other: &Self) -> bool {
+    self.name == other.name
+      && self.address == other.address
+      && self.manufacturer_data == other.manufacturer_data
+      && self.services == other.services
+  }
+}
+
+impl Eq for BtleAdvertisement {}
+
+impl Debug for BtleAdvertisement {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("BtleAdvertisement")
+      .field("name", &self.name)
+      .field("address", &display_address(&self.address))
+      .field("manufacturer_data", &self.manufacturer_data)
+      .field("services", &self.services)
+      .field("rssi", &self.rssi)
+      .finish()
+  }
+}
+
+impl BtleAdvertisement {
+  /// If a device has no discernable name or services, there's nothing to match it against.
+  pub fn is_identifiable(&self) -> bool {
+    !self.name.is_empty() || !self.services.is_empty()
+  }
+
+  pub fn specifier(&self) -> ProtocolCommunicationSpecifier {
+    ProtocolCommunicationSpecifier::BluetoothLE(BluetoothLESpecifier::new_from_device(
+      &self.name,
+      &self.manufacturer_data,
+      &self.services,
+    ))
+  }
+}
+
+/// Match the services and characteristics a device exposes against the bluetooth specifier from
+/// the device configuration. `services` is a list of (service uuid, [(characteristic uuid,
+/// characteristic handle)]), where the handle is whatever the backend needs to talk to the
+/// characteristic later. Returns the characteristic handles keyed by the endpoints the device's
+/// protocol expects.
+pub(crate) fn map_btle_endpoints<C: Clone>(
+  name: &str,
+  address: &str,
+  specifiers: &[ProtocolCommunicationSpecifier],
+  services: &[(Uuid, Vec<(Uuid, C)>)],
+) -> Result<HashMap<Endpoint, C>, ButtplugDeviceError> {
+  let btle = if let Some(ProtocolCommunicationSpecifier::BluetoothLE(btle)) = specifiers
+    .iter()
+    .find(|x| matches!(x, ProtocolCommunicationSpecifier::BluetoothLE(_)))
+  {
+    btle
+  } else {
+    error!(
+      "Can't find btle protocol specifier mapping for device {} {}",
+      name,
+      display_address(address)
+    );
+    return Err(ButtplugDeviceError::DeviceConnectionError(format!(
+      "Can't find btle protocol specifier mapping for device {} {}",
+      name,
+      display_address(address)
+    )));
+  };
+  let mut endpoints = HashMap::new();
+  for (proto_uuid, proto_service) in btle.services() {
+    for (service_uuid, characteristics) in services {
+      if service_uuid != proto_uuid {
+        continue;
+      }
+      debug!("Found required service {}", service_uuid);
+      for (chr_name, chr_uuid) in proto_service.iter() {
+        if let Some((_, chr)) = characteristics.iter().find(|(uuid, _)| uuid == chr_uuid) {
+          debug!(
+            "Found characteristic {} for endpoint {}",
+            chr_uuid, *chr_name
+          );
+          endpoints.insert(*chr_name, chr.clone());
+        } else {
+          error!(
+            "Characteristic {} ({}) not found, may cause issues in connection.",
+            chr_name, chr_uuid
+          );
+        }
+      }
+    }
+  }
+  Ok(endpoints)
+}
+
+/// Decide whether a write should go out with or without response, given what was asked for and
+/// what the characteristic claims to support. Falls back to the other write type if the requested
+/// one isn't available. Returns true for write-with-response.
+pub(crate) fn resolve_write_with_response(
+  with_response: bool,
+  supports_write: bool,
+  supports_write_without_response: bool,
+) -> bool {
+  if !with_response && !supports_write_without_response && supports_write {
+    warn!("Bluetooth device doesn't support write-without-response! Falling back to write-with-response!");
+    true
+  } else if with_response && !supports_write && supports_write_without_response {
+    warn!("Bluetooth device doesn't support write-with-response! Falling back to write-without-response!");
+    false
+  } else {
+    if (with_response && !supports_write) || (!with_response && !supports_write_without_response) {
+      error!(
+        "Bluetooth device doesn't support {}! No fallback available!",
+        if with_response {
+          "write-with-response"
+        } else {
+          "write-without-response"
+        }
+      );
+    }
+    with_response
+  }
+}
+
+/// Writes a value to an endpoint, for [BtleWriteCoalescer] to call once it's decided what to send.
+pub(crate) type BtleWrite =
+  Arc<dyn Fn(Vec<u8>) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> + Send + Sync>;
+
+/// Caller waiting on a coalesced write.
+type WriteWaiter = oneshot::Sender<Result<(), ButtplugDeviceError>>;
+
+#[derive(Default)]
+struct CoalescedEndpoint {
+  /// When the endpoint was last written to.
+  last_write: Option<Instant>,
+  /// Value waiting for the window to close, and everyone waiting on it being written, including
+  /// callers whose values it replaced.
+  pending: Option<(Vec<u8>, Vec<WriteWaiter>)>,
+}
+
+/// Merges rapid writes to endpoints, as set up by [BluetoothLEWriteCoalescing].
+///
+/// The first write to an endpoint goes straight out. Writes landing within the window after it
+/// replace the value waiting to be sent, which is written once the window closes. Callers whose
+/// values were replaced get the result of the write that replaced them.
+pub(crate) struct BtleWriteCoalescer {
+  coalescing: BluetoothLEWriteCoalescing,
+  endpoints: Arc<Mutex<HashMap<Endpoint, CoalescedEndpoint>>>,
+}
+
+impl BtleWriteCoalescer {
+  pub fn new(coalescing: BluetoothLEWriteCoalescing) -> Self {
+    Self {
+      coalescing,
+      endpoints: Arc::new(Mutex::new(HashMap::new())),
+    }
+  }
+
+  /// True if writes to `endpoint` should go through [write](Self::write).
+  pub fn coalesces(&self, endpoint: Endpoint) -> bool {
+    self.coalescing.endpoints().contains(&endpoint)
+  }
+
+  /// Writes `data` to `endpoint` using `write`, now or once the window closes.
+  pub fn write(
+    &self,
+    endpoint: Endpoint,
+    data: Vec<u8>,
+    write: BtleWrite,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    let (sender, receiver) = oneshot::channel();
+    let mut endpoints = self
+      .endpoints
+      .lock()
+      .expect("Coalesced endpoint lock should never be poisoned.");
+    let state = endpoints.entry(endpoint).or_default();
+    let now = Instant::now();
+    if let Some((pending_data, waiters)) = state.pending.as_mut() {
+      *pending_data = data;
+      waiters.push(sender);
+    } else {
+      let since_write = match state.last_write {
+        Some(last_write) if now - last_write < self.coalescing.window() => now - last_write,
+        _ => {
+          state.last_write = Some(now);
+          return write(data);
+        }
+      };
+      state.pending = Some((data, vec![sender]));
+      let endpoints_clone = self.endpoints.clone();
+      let delay = self.coalescing.window() - since_write;
+      async_manager::spawn(async move {
+        sleep(delay).await;
+        let pending = {
+          let mut endpoints = endpoints_clone
+            .lock()
+            .expect("Coalesced endpoint lock should never be poisoned.");
+          let state = endpoints.entry(endpoint).or_default();
+          state.last_write = Some(Instant::now());
+          state.pending.take()
+        };
+        if let Some((data, waiters)) = pending {
+          let result = write(data).await;
+          for waiter in waiters {
+            let _ = waiter.send(result.clone());
+          }
+        }
+      });
+    }
+    async move {
+      receiver.await.unwrap_or_else(|_| {
+        Err(ButtplugDeviceError::DeviceCommunicationError(
+          "Coalesced write was dropped before being sent.".to_owned(),
+        ))
+      })
+    }
+    .boxed()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use std::time::Duration;
+
+  fn specifier() -> Vec<ProtocolCommunicationSpecifier> {
+    let specifier: BluetoothLESpecifier = serde_json::from_str(
+      r#"{
+        "names": ["TestDevice"],
+        "services": {
+          "0000fff0-0000-1000-8000-00805f9b34fb": {
+            "tx": "0000fff1-0000-1000-8000-00805f9b34fb",
+            "rx": "0000fff2-0000-1000-8000-00805f9b34fb"
+          }
+        }
+      }"#,
+    )
+    .expect("Test specifier is valid");
+    vec![ProtocolCommunicationSpecifier::BluetoothLE(specifier)]
+  }
+
+  fn uuid(s: &str) -> Uuid {
+    Uuid::parse_str(s).expect("Test uuid is valid")
+  }
+
+  #[test]
+  fn test_map_btle_endpoints() {
+    let services = vec![
+      (
+        uuid("0000180a-0000-1000-8000-00805f9b34fb"),
+        vec![(uuid("00002a29-0000-1000-8000-00805f9b34fb"), 1)],
+      ),
+      (
+        uuid("0000fff0-0000-1000-8000-00805f9b34fb"),
+        vec![
+          (uuid("0000fff1-0000-1000-8000-00805f9b34fb"), 2),
+          (uuid("0000fff2-0000-1000-8000-00805f9b34fb"), 3),
+        ],
+      ),
+    ];
+    let endpoints =
+      map_btle_endpoints("TestDevice", "test", &specifier(), &services).expect("Specifier exists");
+    assert_eq!(endpoints.len(), 2);
+    assert_eq!(endpoints[&Endpoint::Tx], 2);
+    assert_eq!(endpoints[&Endpoint::Rx], 3);
+  }
+
+  #[test]
+  fn test_map_btle_endpoints_missing_characteristic() {
+    let services = vec![(
+      uuid("0000fff0-0000-1000-8000-00805f9b34fb"),
+      vec![(uuid("0000fff1-0000-1000-8000-00805f9b34fb"), 2)],
+    )];
+    let endpoints =
+      map_btle_endpoints("TestDevice", "test", &specifier(), &services).expect("Specifier exists");
+    assert_eq!(endpoints.len(), 1);
+    assert!(!endpoints.contains_key(&Endpoint::Rx));
+  }
+
+  #[test]
+  fn test_map_btle_endpoints_no_specifier() {
+    let services: Vec<(Uuid, Vec<(Uuid, u32)>)> = vec![];
+    assert!(map_btle_endpoints("TestDevice", "test", &[], &services).is_err());
+  }
+
+  #[test]
+  fn test_btle_advertisement_identifiable() {
+    let mut advertisement = BtleAdvertisement {
+      name: String::new(),
+      address: "test".to_owned(),
+      manufacturer_data: HashMap::new(),
+      services: vec![],
+      rssi: None,
+    };
+    assert!(!advertisement.is_identifiable());
+    advertisement.name = "TestDevice".to_owned();
+    assert!(advertisement.is_identifiable());
+    assert_eq!(advertisement.specifier(), specifier()[0]);
+  }
+
+  #[test]
+  fn test_btle_advertisement_equality_ignores_rssi() {
+    let advertisement = BtleAdvertisement {
+      name: "TestDevice".to_owned(),
+      address: "test".to_owned(),
+      manufacturer_data: HashMap::new(),
+      services: vec![],
+      rssi: Some(-40),
+    };
+    let mut readvertisement = advertisement.clone();
+    readvertisement.rssi = Some(-70);
+    assert!(advertisement == readvertisement);
+    readvertisement.name = "OtherDevice".to_owned();
+    assert!(advertisement != readvertisement);
+  }
+
+  #[test]
+  fn test_resolve_write_with_response() {
+    assert!(resolve_write_with_response(true, true, true));
+    assert!(!resolve_write_with_response(false, true, true));
+    // Fallbacks
+    assert!(resolve_write_with_response(false, true, false));
+    assert!(!resolve_write_with_response(true, false, true));
+    // Nothing to fall back to, keep what was asked for.
+    assert!(resolve_write_with_response(true, false, false));
+  }
+
+  #[tokio::test]
+  async fn test_btle_write_coalescer() {
+    let coalescer = BtleWriteCoalescer::new(BluetoothLEWriteCoalescing::new(
+      Duration::from_millis(50),
+      [Endpoint::Tx].into_iter().collect(),
+    ));
+    assert!(coalescer.coalesces(Endpoint::Tx));
+    assert!(!coalescer.coalesces(Endpoint::TxMode));
+    let written = Arc::new(Mutex::new(vec![]));
+    let written_clone = written.clone();
+    let write: BtleWrite = Arc::new(move |data| {
+      written_clone
+        .lock()
+        .expect("Test, assuming infallible.")
+        .push(data);
+      futures::future::ready(Ok(())).boxed()
+    });
+    // First write goes straight out, the rest of the window only keeps the latest value.
+    let writes: Vec<_> = (0..4u8)
+      .map(|value| coalescer.write(Endpoint::Tx, vec![value], write.clone()))
+      .collect();
+    assert_eq!(
+      *written.lock().expect("Test, assuming infallible."),
+      vec![vec![0]]
+    );
+    for result in futures::future::join_all(writes).await {
+      assert!(result.is_ok());
+    }
+    assert_eq!(
+      *written.lock().expect("Test, assuming infallible."),
+      vec![vec![0], vec![3]]
+    );
+    // Once the window after the last write is over, writes go straight out again.
+    sleep(Duration::from_millis(60)).await;
+    coalescer
+      .write(Endpoint::Tx, vec![4], write.clone())
+      .await
+      .expect("Test, assuming infallible.");
+    assert_eq!(written.lock().expect("Test, assuming infallible.").len(), 3);
+  }
+}
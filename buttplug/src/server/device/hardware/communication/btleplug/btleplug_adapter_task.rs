@@ -5,18 +5,30 @@
 // Licensed under the BSD 3-Clause license. See LICENSE file in the project root
 // for full license information.
 
-use super::btleplug_hardware::BtleplugHardwareConnector;
-use crate::server::device::hardware::communication::HardwareCommunicationManagerEvent;
+use super::{
+  btleplug_comm_manager::BtlePlugCommunicationManagerConfig,
+  btleplug_hardware::BtleplugHardwareConnector,
+};
+use crate::{
+  server::device::hardware::communication::{
+    btle_common::BtleAdvertisement,
+    HardwareCommunicationManagerEvent,
+    HardwareCommunicationManagerStatus,
+    HardwarePermission,
+    HardwarePermissionError,
+  },
+  util::address_privacy::display_address,
+};
 use btleplug::{
   api::{Central, CentralEvent, Manager as _, Peripheral, ScanFilter},
   platform::{Adapter, Manager, PeripheralId},
 };
 use futures::{future::FutureExt, StreamExt};
 use std::{
-  collections::HashMap,
   sync::{
     atomic::{AtomicBool, Ordering},
     Arc,
+    Mutex,
   },
   time::Duration,
 };
@@ -25,24 +37,35 @@ use tokio::{
   time::sleep,
 };
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub enum BtleplugAdapterCommand {
-  StartScanning,
+  /// Start scanning, for the device with the given address only if one is given.
+  StartScanning(Option<String>),
   StopScanning,
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
-struct PeripheralInfo {
-  name: Option<String>,
-  peripheral_id: PeripheralId,
-  manufacturer_data: HashMap<u16, Vec<u8>>,
-  services: Vec<uuid::Uuid>,
+/// Error for the platform refusing us bluetooth, with where users can go to allow it.
+fn bluetooth_permission_error() -> HardwarePermissionError {
+  let guidance = if cfg!(target_os = "macos") {
+    "Allow bluetooth for this app in System Settings, under Privacy & Security > Bluetooth."
+  } else if cfg!(target_os = "ios") {
+    "Allow bluetooth for this app in Settings, under Privacy & Security > Bluetooth."
+  } else if cfg!(target_os = "android") {
+    "Grant this app the Nearby devices permission (Location on Android 11 and older) in its app \
+     settings."
+  } else {
+    "Allow this app to use bluetooth in the system privacy settings."
+  };
+  HardwarePermissionError::new(HardwarePermission::Bluetooth, guidance)
 }
 
 pub struct BtleplugAdapterTask {
   event_sender: Sender<HardwareCommunicationManagerEvent>,
   command_receiver: Receiver<BtleplugAdapterCommand>,
   adapter_connected: Arc<AtomicBool>,
+  /// Set while the platform is refusing us bluetooth.
+  permission_error: Arc<Mutex<Option<HardwarePermissionError>>>,
+  config: BtlePlugCommunicationManagerConfig,
 }
 
 impl BtleplugAdapterTask {
@@ -50,11 +73,63 @@ impl BtleplugAdapterTask {
     event_sender: Sender<HardwareCommunicationManagerEvent>,
     command_receiver: Receiver<BtleplugAdapterCommand>,
     adapter_connected: Arc<AtomicBool>,
+    permission_error: Arc<Mutex<Option<HardwarePermissionError>>>,
+    config: BtlePlugCommunicationManagerConfig,
   ) -> Self {
     Self {
       event_sender,
       command_receiver,
       adapter_connected,
+      permission_error,
+      config,
+    }
+  }
+
+  /// Keeps track of whether the platform let us do what we last asked, letting the device manager
+  /// know when that changes.
+  async fn set_permission_denied(&self, denied: bool) {
+    let status = {
+      let mut permission_error = self
+        .permission_error
+        .lock()
+        .expect("Lock only held for assignment");
+      if permission_error.is_some() == denied {
+        return;
+      }
+      if denied {
+        let error = bluetooth_permission_error();
+        warn!("Bluetooth permission denied: {}", error.guidance());
+        *permission_error = Some(error.clone());
+        HardwareCommunicationManagerStatus::NeedsPermission(error)
+      } else {
+        info!("Bluetooth permission granted.");
+        *permission_error = None;
+        HardwareCommunicationManagerStatus::from_availability(
+          self.adapter_connected.load(Ordering::SeqCst),
+          false,
+        )
+      }
+    };
+    if self
+      .event_sender
+      .send(HardwareCommunicationManagerEvent::StatusChanged(status))
+      .await
+      .is_err()
+    {
+      debug!("Device manager disappeared, cannot send permission status.");
+    }
+  }
+
+  /// Lets the device manager know the adapter came or went.
+  async fn send_adapter_status(&self, connected: bool) {
+    let status = HardwareCommunicationManagerStatus::from_availability(connected, false);
+    if self
+      .event_sender
+      .send(HardwareCommunicationManagerEvent::StatusChanged(status))
+      .await
+      .is_err()
+    {
+      debug!("Device manager disappeared, cannot send adapter status.");
     }
   }
 
@@ -62,12 +137,15 @@ impl BtleplugAdapterTask {
     &self,
     peripheral_id: &PeripheralId,
     adapter: &Adapter,
-    tried_addresses: &mut Vec<PeripheralInfo>,
+    tried_addresses: &mut Vec<BtleAdvertisement>,
   ) {
     let peripheral = if let Ok(peripheral) = adapter.peripheral(peripheral_id).await {
       peripheral
     } else {
-      error!("Peripheral with address {:?} not found.", peripheral_id);
+      error!(
+        "Peripheral with address {} not found.",
+        display_address(&format!("{:?}", peripheral_id))
+      );
       return;
     };
     // If a device has no discernable name, we can't do anything with it, just ignore it.
@@ -75,52 +153,45 @@ impl BtleplugAdapterTask {
       properties
     } else {
       error!(
-        "Cannot retreive peripheral properties for {:?}.",
-        peripheral_id
+        "Cannot retreive peripheral properties for {}.",
+        display_address(&format!("{:?}", peripheral_id))
       );
       return;
     };
 
-    let device_name = if let Some(name) = &properties.local_name {
-      name.clone()
-    } else {
-      String::new()
-    };
-
-    let peripheral_info = PeripheralInfo {
-      name: properties.local_name.clone(),
-      peripheral_id: peripheral_id.clone(),
+    let advertisement = BtleAdvertisement {
+      name: properties.local_name.clone().unwrap_or_default(),
+      address: format!("{:?}", peripheral_id),
       manufacturer_data: properties.manufacturer_data.clone(),
       services: properties.services.clone(),
+      rssi: properties.rssi,
     };
 
-    if (!device_name.is_empty() || !properties.services.is_empty())
-      && !tried_addresses.contains(&peripheral_info)
-    {
+    if advertisement.is_identifiable() && !tried_addresses.contains(&advertisement) {
       let span = info_span!(
         "btleplug enumeration",
-        address = tracing::field::display(format!("{:?}", peripheral_id)),
-        name = tracing::field::display(&device_name)
+        address = tracing::field::display(display_address(&advertisement.address)),
+        name = tracing::field::display(&advertisement.name)
       );
       let _enter = span.enter();
 
       debug!(
         "Found new bluetooth device advertisement: {:?}",
-        peripheral_info
+        advertisement
       );
-      tried_addresses.push(peripheral_info.clone());
+      tried_addresses.push(advertisement.clone());
+      let name = advertisement.name.clone();
+      let address = advertisement.address.clone();
       let device_creator = Box::new(BtleplugHardwareConnector::new(
-        &device_name,
-        &properties.manufacturer_data,
-        &properties.services,
+        advertisement,
         peripheral.clone(),
         adapter.clone(),
       ));
       if self
         .event_sender
         .send(HardwareCommunicationManagerEvent::DeviceFound {
-          name: device_name,
-          address: format!("{:?}", peripheral_id),
+          name,
+          address,
           creator: device_creator,
         })
         .await
@@ -131,7 +202,7 @@ impl BtleplugAdapterTask {
     } else {
       trace!(
         "Device {} found, no advertised name, ignoring.",
-        properties.address
+        display_address(&properties.address.to_string())
       );
     }
   }
@@ -139,6 +210,10 @@ impl BtleplugAdapterTask {
   pub async fn run(&mut self) {
     let manager = match Manager::new().await {
       Ok(mgr) => mgr,
+      Err(btleplug::Error::PermissionDenied) => {
+        self.set_permission_denied(true).await;
+        return;
+      }
       Err(e) => {
         error!("Error creating btleplug manager: {:?}", e);
         return;
@@ -158,7 +233,7 @@ impl BtleplugAdapterTask {
       }
       adapter = match manager.adapters().await {
         Ok(adapters) => {
-          if let Some(adapter) = adapters.into_iter().next() {
+          if let Some(adapter) = adapters.into_iter().nth(self.config.adapter_index) {
             info!("Bluetooth LE adapter found.");
             // Bluetooth dongle identification for Windows
             #[cfg(target_os = "windows")]
@@ -197,19 +272,31 @@ impl BtleplugAdapterTask {
           } else {
             if adapter_found {
               self.adapter_connected.store(false, Ordering::SeqCst);
+              self.send_adapter_status(false).await;
               warn!("Bluetooth LE adapter not found, will not be using bluetooth scanning until found. Buttplug will continue polling for the adapter, but no more warning messages will be posted.");
             }
             continue;
           }
         }
+        Err(btleplug::Error::PermissionDenied) => {
+          self.adapter_connected.store(false, Ordering::SeqCst);
+          self.set_permission_denied(true).await;
+          continue;
+        }
         Err(e) => {
           if adapter_found {
             self.adapter_connected.store(false, Ordering::SeqCst);
+            self.send_adapter_status(false).await;
             error!("Error retreiving BTLE adapters: {:?}", e);
           }
           continue;
         }
       };
+      if !adapter_found {
+        self.adapter_connected.store(true, Ordering::SeqCst);
+        self.send_adapter_status(true).await;
+      }
+      self.set_permission_denied(false).await;
       break;
     }
 
@@ -219,6 +306,8 @@ impl BtleplugAdapterTask {
       .expect("Should always be able to retreive stream.");
 
     let mut tried_addresses = vec![];
+    // Address of the only device we're scanning for, if this is a targeted scan.
+    let mut scan_target: Option<String> = None;
 
     loop {
       let event_fut = events.next();
@@ -228,11 +317,17 @@ impl BtleplugAdapterTask {
             if let Some(event) = event {
               match event {
                 CentralEvent::DeviceDiscovered(peripheral_id) | CentralEvent::DeviceUpdated(peripheral_id) => {
-                  self.maybe_add_peripheral(&peripheral_id, &adapter, &mut tried_addresses).await;
+                  // Targeted scans only care about one device, so don't bother looking up anything
+                  // else.
+                  let address = format!("{:?}", peripheral_id);
+                  if scan_target.as_ref().map_or(true, |target| *target == address) {
+                    self.maybe_add_peripheral(&peripheral_id, &adapter, &mut tried_addresses).await;
+                  }
                 }
                 CentralEvent::DeviceDisconnected(peripheral_id) => {
-                  debug!("BTLEPlug Device disconnected: {:?}", peripheral_id);
-                  tried_addresses.retain(|info| info.peripheral_id != peripheral_id);
+                  let address = format!("{:?}", peripheral_id);
+                  debug!("BTLEPlug Device disconnected: {}", display_address(&address));
+                  tried_addresses.retain(|info| info.address != address);
                 }
                 event => {
                   trace!("Unhandled btleplug central event: {:?}", event)
@@ -246,13 +341,31 @@ impl BtleplugAdapterTask {
         command = self.command_receiver.recv().fuse() => {
           if let Some(cmd) = command {
             match cmd {
-              BtleplugAdapterCommand::StartScanning => {
+              BtleplugAdapterCommand::StartScanning(target) => {
                 tried_addresses.clear();
-                if let Err(err) = adapter.start_scan(ScanFilter::default()).await {
-                  error!("Start scanning request failed: {}", err);
+                scan_target = target;
+                if let Some(address) = &scan_target {
+                  // The platform may still know the device from when it was last connected, in
+                  // which case we can try it without waiting for it to advertise again.
+                  if let Ok(peripherals) = adapter.peripherals().await {
+                    for peripheral in peripherals {
+                      if format!("{:?}", peripheral.id()) == *address {
+                        self.maybe_add_peripheral(&peripheral.id(), &adapter, &mut tried_addresses).await;
+                      }
+                    }
+                  }
+                }
+                let filter = ScanFilter {
+                  services: self.config.scan_services.clone(),
+                };
+                match adapter.start_scan(filter).await {
+                  Ok(()) => self.set_permission_denied(false).await,
+                  Err(btleplug::Error::PermissionDenied) => self.set_permission_denied(true).await,
+                  Err(err) => error!("Start scanning request failed: {}", err),
                 }
               }
               BtleplugAdapterCommand::StopScanning => {
+                scan_target = None;
                 if let Err(err) = adapter.stop_scan().await {
                   error!("Stop scanning request failed: {}", err);
                 }
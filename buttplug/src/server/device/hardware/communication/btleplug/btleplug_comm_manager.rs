@@ -11,7 +11,10 @@ use crate::{
   server::device::hardware::communication::{
     HardwareCommunicationManager,
     HardwareCommunicationManagerBuilder,
+    HardwareCommunicationManagerConfig,
     HardwareCommunicationManagerEvent,
+    HardwareCommunicationManagerStatus,
+    HardwarePermissionError,
   },
   util::async_manager,
 };
@@ -19,57 +22,99 @@ use futures::future::FutureExt;
 use std::sync::{
   atomic::{AtomicBool, Ordering},
   Arc,
+  Mutex,
 };
 use tokio::sync::mpsc::{channel, Sender};
+use uuid::Uuid;
+
+/// Runtime settings for bluetooth LE scanning.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct BtlePlugCommunicationManagerConfig {
+  /// Index of the bluetooth adapter to use, in the order the platform reports them. Defaults to
+  /// the first adapter.
+  pub adapter_index: usize,
+  /// If not empty, only devices advertising at least one of these services will be reported while
+  /// scanning.
+  pub scan_services: Vec<Uuid>,
+}
+
+impl HardwareCommunicationManagerConfig for BtlePlugCommunicationManagerConfig {
+  type Builder = BtlePlugCommunicationManagerBuilder;
+
+  fn into_builder(self) -> Self::Builder {
+    BtlePlugCommunicationManagerBuilder::default().config(self)
+  }
+}
 
 #[derive(Default, Clone)]
-pub struct BtlePlugCommunicationManagerBuilder {}
+pub struct BtlePlugCommunicationManagerBuilder {
+  config: BtlePlugCommunicationManagerConfig,
+}
+
+impl BtlePlugCommunicationManagerBuilder {
+  pub fn config(mut self, config: BtlePlugCommunicationManagerConfig) -> Self {
+    self.config = config;
+    self
+  }
+}
 
 impl HardwareCommunicationManagerBuilder for BtlePlugCommunicationManagerBuilder {
   fn finish(
     &mut self,
     sender: Sender<HardwareCommunicationManagerEvent>,
   ) -> Box<dyn HardwareCommunicationManager> {
-    Box::new(BtlePlugCommunicationManager::new(sender))
+    Box::new(BtlePlugCommunicationManager::new(
+      sender,
+      self.config.clone(),
+    ))
   }
 }
 
-pub struct BtlePlugCommunicationManager {
+pub(crate) struct BtlePlugCommunicationManager {
   adapter_event_sender: Sender<BtleplugAdapterCommand>,
   scanning_status: Arc<AtomicBool>,
   adapter_connected: Arc<AtomicBool>,
+  permission_error: Arc<Mutex<Option<HardwarePermissionError>>>,
 }
 
 impl BtlePlugCommunicationManager {
-  pub fn new(event_sender: Sender<HardwareCommunicationManagerEvent>) -> Self {
+  pub fn new(
+    event_sender: Sender<HardwareCommunicationManagerEvent>,
+    config: BtlePlugCommunicationManagerConfig,
+  ) -> Self {
     let (sender, receiver) = channel(256);
     let adapter_connected = Arc::new(AtomicBool::new(false));
     let adapter_connected_clone = adapter_connected.clone();
+    let permission_error = Arc::new(Mutex::new(None));
+    let permission_error_clone = permission_error.clone();
     async_manager::spawn(async move {
-      let mut task = BtleplugAdapterTask::new(event_sender, receiver, adapter_connected_clone);
+      let mut task = BtleplugAdapterTask::new(
+        event_sender,
+        receiver,
+        adapter_connected_clone,
+        permission_error_clone,
+        config,
+      );
       task.run().await;
     });
     Self {
       adapter_event_sender: sender,
       scanning_status: Arc::new(AtomicBool::new(false)),
       adapter_connected,
+      permission_error,
     }
   }
 }
 
-impl HardwareCommunicationManager for BtlePlugCommunicationManager {
-  fn name(&self) -> &'static str {
-    "BtlePlugCommunicationManager"
-  }
-
-  fn start_scanning(&mut self) -> ButtplugResultFuture {
+impl BtlePlugCommunicationManager {
+  fn send_start_scanning(&mut self, target: Option<String>) -> ButtplugResultFuture {
     let adapter_event_sender = self.adapter_event_sender.clone();
     let scanning_status = self.scanning_status.clone();
     // Set to true just to make sure we don't call ScanningFinished too early.
     scanning_status.store(true, Ordering::SeqCst);
     async move {
       if adapter_event_sender
-        .send(BtleplugAdapterCommand::StartScanning)
+        .send(BtleplugAdapterCommand::StartScanning(target))
         .await
         .is_err()
       {
@@ -87,6 +132,20 @@ impl HardwareCommunicationManager for BtlePlugCommunicationManager {
     }
     .boxed()
   }
+}
+
+impl HardwareCommunicationManager for BtlePlugCommunicationManager {
+  fn name(&self) -> &'static str {
+    "BtlePlugCommunicationManager"
+  }
+
+  fn start_scanning(&mut self) -> ButtplugResultFuture {
+    self.send_start_scanning(None)
+  }
+
+  fn start_targeted_scanning(&mut self, address: &str) -> ButtplugResultFuture {
+    self.send_start_scanning(Some(address.to_owned()))
+  }
 
   fn stop_scanning(&mut self) -> ButtplugResultFuture {
     let adapter_event_sender = self.adapter_event_sender.clone();
@@ -112,12 +171,23 @@ impl HardwareCommunicationManager for BtlePlugCommunicationManager {
     .boxed()
   }
 
-  fn scanning_status(&self) -> bool {
-    self.scanning_status.load(Ordering::SeqCst)
+  fn status(&self) -> HardwareCommunicationManagerStatus {
+    HardwareCommunicationManagerStatus::from_availability(
+      self.adapter_connected.load(Ordering::SeqCst),
+      self.scanning_status.load(Ordering::SeqCst),
+    )
   }
 
-  fn can_scan(&self) -> bool {
-    self.adapter_connected.load(Ordering::SeqCst)
+  // The platform only tells us when we try to use bluetooth, so this is whatever it said last.
+  fn check_permissions(&self) -> Result<(), HardwarePermissionError> {
+    match &*self
+      .permission_error
+      .lock()
+      .expect("Lock only held for assignment")
+    {
+      Some(error) => Err(error.clone()),
+      None => Ok(()),
+    }
   }
 }
 /*
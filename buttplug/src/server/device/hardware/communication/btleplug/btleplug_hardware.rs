@@ -7,10 +7,20 @@
 
 use crate::{
   core::{errors::ButtplugDeviceError, message::Endpoint},
-  server::device::hardware::communication::HardwareSpecificError,
+  server::device::hardware::communication::{
+    btle_common::{
+      map_btle_endpoints,
+      resolve_write_with_response,
+      BtleAdvertisement,
+      BtleWrite,
+      BtleWriteCoalescer,
+    },
+    HardwareSpecificError,
+  },
   server::device::{
-    configuration::{BluetoothLESpecifier, ProtocolCommunicationSpecifier},
+    configuration::ProtocolCommunicationSpecifier,
     hardware::{
+      BluetoothLEWriteCoalescing,
       Hardware,
       HardwareConnector,
       HardwareEvent,
@@ -23,7 +33,7 @@ use crate::{
       HardwareWriteCmd,
     },
   },
-  util::async_manager,
+  util::{address_privacy::display_address, async_manager},
 };
 use async_trait::async_trait;
 use btleplug::api::CharPropFlags;
@@ -41,34 +51,22 @@ use std::{
   collections::HashMap,
   fmt::{self, Debug},
   pin::Pin,
-  sync::Arc,
+  sync::{Arc, RwLock},
 };
 use tokio::sync::broadcast;
 use uuid::Uuid;
 
 pub(super) struct BtleplugHardwareConnector<T: Peripheral + 'static> {
   // Passed in and stored as a member because otherwise it's annoying to get (properties require await)
-  name: String,
-  // Passed in and stored as a member because otherwise it's annoying to get (properties require await)
-  manufacturer_data: HashMap<u16, Vec<u8>>,
-  // Passed in and stored as a member because otherwise it's annoying to get (properties require await)
-  services: Vec<Uuid>,
+  advertisement: BtleAdvertisement,
   device: T,
   adapter: Adapter,
 }
 
 impl<T: Peripheral> BtleplugHardwareConnector<T> {
-  pub fn new(
-    name: &str,
-    manufacturer_data: &HashMap<u16, Vec<u8>>,
-    services: &[Uuid],
-    device: T,
-    adapter: Adapter,
-  ) -> Self {
+  pub fn new(advertisement: BtleAdvertisement, device: T, adapter: Adapter) -> Self {
     Self {
-      name: name.to_owned(),
-      manufacturer_data: manufacturer_data.clone(),
-      services: services.to_vec(),
+      advertisement,
       device,
       adapter,
     }
@@ -78,8 +76,11 @@ impl<T: Peripheral> BtleplugHardwareConnector<T> {
 impl<T: Peripheral> Debug for BtleplugHardwareConnector<T> {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     f.debug_struct("BtleplugHardwareCreator")
-      .field("name", &self.name)
-      .field("address", &self.device.id())
+      .field("name", &self.advertisement.name)
+      .field(
+        "address",
+        &display_address(&format!("{:?}", self.device.id())),
+      )
       .finish()
   }
 }
@@ -87,11 +88,11 @@ impl<T: Peripheral> Debug for BtleplugHardwareConnector<T> {
 #[async_trait]
 impl<T: Peripheral> HardwareConnector for BtleplugHardwareConnector<T> {
   fn specifier(&self) -> ProtocolCommunicationSpecifier {
-    ProtocolCommunicationSpecifier::BluetoothLE(BluetoothLESpecifier::new_from_device(
-      &self.name,
-      &self.manufacturer_data,
-      &self.services,
-    ))
+    self.advertisement.specifier()
+  }
+
+  fn rssi(&self) -> Option<i16> {
+    self.advertisement.rssi
   }
 
   async fn connect(&mut self) -> Result<Box<dyn HardwareSpecializer>, ButtplugDeviceError> {
@@ -102,9 +103,8 @@ impl<T: Peripheral> HardwareConnector for BtleplugHardwareConnector<T> {
       .expect("If we crash here it's Bluez's fault. Use something else please.")
     {
       if let Err(err) = self.device.connect().await {
-        let return_err = ButtplugDeviceError::DeviceSpecificError(
-          HardwareSpecificError::BtleplugError(format!("{:?}", err)),
-        );
+        let return_err =
+          ButtplugDeviceError::from(HardwareSpecificError::BtleplugError(format!("{:?}", err)));
         return Err(return_err);
       }
       if let Err(err) = self.device.discover_services().await {
@@ -116,7 +116,7 @@ impl<T: Peripheral> HardwareConnector for BtleplugHardwareConnector<T> {
       }
     }
     Ok(Box::new(BtleplugHardwareSpecializer::new(
-      &self.name,
+      &self.advertisement.name,
       self.device.clone(),
       self.adapter.clone(),
     )))
@@ -145,49 +145,29 @@ impl<T: Peripheral> HardwareSpecializer for BtleplugHardwareSpecializer<T> {
     &mut self,
     specifiers: &[ProtocolCommunicationSpecifier],
   ) -> Result<Hardware, ButtplugDeviceError> {
-    // Map UUIDs to endpoints
-    let mut uuid_map = HashMap::<Uuid, Endpoint>::new();
-    let mut endpoints = HashMap::<Endpoint, Characteristic>::new();
     let address = self.device.id();
-
-    if let Some(ProtocolCommunicationSpecifier::BluetoothLE(btle)) = specifiers
+    let services: Vec<(Uuid, Vec<(Uuid, Characteristic)>)> = self
+      .device
+      .services()
+      .into_iter()
+      .map(|service| {
+        (
+          service.uuid,
+          service
+            .characteristics
+            .into_iter()
+            .map(|chr| (chr.uuid, chr))
+            .collect(),
+        )
+      })
+      .collect();
+    let endpoints =
+      map_btle_endpoints(&self.name, &format!("{:?}", address), specifiers, &services)?;
+    // Notifications only carry the characteristic uuid, so keep a way back to the endpoint.
+    let uuid_map: HashMap<Uuid, Endpoint> = endpoints
       .iter()
-      .find(|x| matches!(x, ProtocolCommunicationSpecifier::BluetoothLE(_)))
-    {
-      for (proto_uuid, proto_service) in btle.services() {
-        for service in self.device.services() {
-          if service.uuid != *proto_uuid {
-            continue;
-          }
-
-          debug!("Found required service {} {:?}", service.uuid, service);
-          for (chr_name, chr_uuid) in proto_service.iter() {
-            if let Some(chr) = service.characteristics.iter().find(|c| c.uuid == *chr_uuid) {
-              debug!(
-                "Found characteristic {} for endpoint {}",
-                chr.uuid, *chr_name
-              );
-              endpoints.insert(*chr_name, chr.clone());
-              uuid_map.insert(*chr_uuid, *chr_name);
-            } else {
-              error!(
-                "Characteristic {} ({}) not found, may cause issues in connection.",
-                chr_name, chr_uuid
-              );
-            }
-          }
-        }
-      }
-    } else {
-      error!(
-        "Can't find btle protocol specifier mapping for device {} {:?}",
-        self.name, address
-      );
-      return Err(ButtplugDeviceError::DeviceConnectionError(format!(
-        "Can't find btle protocol specifier mapping for device {} {:?}",
-        self.name, address
-      )));
-    }
+      .map(|(endpoint, chr)| (chr.uuid, *endpoint))
+      .collect();
     let notification_stream = self
       .device
       .notifications()
@@ -221,6 +201,7 @@ pub struct BtlePlugHardware<T: Peripheral + 'static> {
   event_stream: broadcast::Sender<HardwareEvent>,
   endpoints: HashMap<Endpoint, Characteristic>,
   subscribed_endpoints: Arc<DashSet<Endpoint>>,
+  write_coalescer: RwLock<Option<BtleWriteCoalescer>>,
 }
 
 impl<T: Peripheral + 'static> BtlePlugHardware<T> {
@@ -298,8 +279,8 @@ impl<T: Peripheral + 'static> BtlePlugHardware<T> {
         }
       }
       info!(
-        "Exiting btleplug notification/event loop for device {:?}",
-        address
+        "Exiting btleplug notification/event loop for device {}",
+        display_address(&format!("{:?}", address))
       )
     });
     Self {
@@ -307,6 +288,7 @@ impl<T: Peripheral + 'static> BtlePlugHardware<T> {
       endpoints,
       event_stream,
       subscribed_endpoints: Arc::new(DashSet::new()),
+      write_coalescer: RwLock::new(None),
     }
   }
 }
@@ -316,6 +298,11 @@ impl<T: Peripheral + 'static> HardwareInternal for BtlePlugHardware<T> {
     self.event_stream.subscribe()
   }
 
+  #[cfg(feature = "unstable-btleplug-peripheral")]
+  fn btleplug_peripheral(&self) -> Option<Box<dyn std::any::Any + Send>> {
+    Some(Box::new(self.device.clone()))
+  }
+
   fn disconnect(&self) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
     let device = self.device.clone();
     async move {
@@ -337,54 +324,60 @@ impl<T: Peripheral + 'static> HardwareInternal for BtlePlugHardware<T> {
     };
 
     let device = self.device.clone();
-    let mut write_type = if msg.write_with_response {
+    let write_type = if resolve_write_with_response(
+      msg.write_with_response,
+      characteristic.properties.contains(CharPropFlags::WRITE),
+      characteristic
+        .properties
+        .contains(CharPropFlags::WRITE_WITHOUT_RESPONSE),
+    ) {
       WriteType::WithResponse
     } else {
       WriteType::WithoutResponse
     };
 
-    if (write_type == WriteType::WithoutResponse
-      && (characteristic.properties & CharPropFlags::WRITE_WITHOUT_RESPONSE)
-        != CharPropFlags::WRITE_WITHOUT_RESPONSE)
-      || (write_type == WriteType::WithResponse
-        && (characteristic.properties & CharPropFlags::WRITE) != CharPropFlags::WRITE)
+    let write: BtleWrite = Arc::new(move |data: Vec<u8>| {
+      let device = device.clone();
+      let characteristic = characteristic.clone();
+      async move {
+        match device.write(&characteristic, &data, write_type).await {
+          Ok(()) => Ok(()),
+          Err(err) => {
+            error!("BTLEPlug device write error: {:?}", err);
+            Err(ButtplugDeviceError::from(
+              HardwareSpecificError::BtleplugError(format!("{:?}", err)),
+            ))
+          }
+        }
+      }
+      .boxed()
+    });
+    // Only writes without response are merged, anything asking for a response gets one.
+    match self
+      .write_coalescer
+      .read()
+      .expect("Write coalescer lock should never be poisoned.")
+      .as_ref()
     {
-      if write_type == WriteType::WithoutResponse
-        && (characteristic.properties & CharPropFlags::WRITE) == CharPropFlags::WRITE
+      Some(coalescer)
+        if write_type == WriteType::WithoutResponse && coalescer.coalesces(msg.endpoint) =>
       {
-        warn!("BTLEPlug device doesn't support write-without-response! Falling back to write-with-response!");
-        write_type = WriteType::WithResponse
-      } else if write_type == WriteType::WithResponse
-        && (characteristic.properties & CharPropFlags::WRITE_WITHOUT_RESPONSE)
-          == CharPropFlags::WRITE_WITHOUT_RESPONSE
-      {
-        warn!("BTLEPlug device doesn't support write-with-response! Falling back to write-without-response!");
-        write_type = WriteType::WithoutResponse
-      } else {
-        error!(
-          "BTLEPlug device doesn't support {}! No fallback available!",
-          if write_type == WriteType::WithoutResponse {
-            "write-without-response"
-          } else {
-            "write-with-response"
-          }
-        );
+        coalescer.write(msg.endpoint, msg.data.clone(), write)
       }
+      _ => write(msg.data.clone()),
     }
+  }
 
-    let data = msg.data.clone();
-    async move {
-      match device.write(&characteristic, &data, write_type).await {
-        Ok(()) => Ok(()),
-        Err(err) => {
-          error!("BTLEPlug device write error: {:?}", err);
-          Err(ButtplugDeviceError::DeviceSpecificError(
-            HardwareSpecificError::BtleplugError(format!("{:?}", err)),
-          ))
-        }
-      }
-    }
-    .boxed()
+  fn set_write_coalescing(
+    &self,
+    coalescing: &BluetoothLEWriteCoalescing,
+  ) -> Result<(), ButtplugDeviceError> {
+    *self
+      .write_coalescer
+      .write()
+      .expect("Write coalescer lock should never be poisoned.") =
+      Some(BtleWriteCoalescer::new(coalescing.clone()));
+    Ok(())
   }
 
   fn read_value(
@@ -409,7 +402,7 @@ impl<T: Peripheral + 'static> HardwareInternal for BtlePlugHardware<T> {
         }
         Err(err) => {
           error!("BTLEPlug device read error: {:?}", err);
-          Err(ButtplugDeviceError::DeviceSpecificError(
+          Err(ButtplugDeviceError::from(
             HardwareSpecificError::BtleplugError(format!("{:?}", err)),
           ))
         }
@@ -418,6 +411,19 @@ impl<T: Peripheral + 'static> HardwareInternal for BtlePlugHardware<T> {
     .boxed()
   }
 
+  fn read_rssi(&self) -> BoxFuture<'static, Result<i16, ButtplugDeviceError>> {
+    let device = self.device.clone();
+    async move {
+      let properties = device.properties().await.map_err(|err| {
+        ButtplugDeviceError::from(HardwareSpecificError::BtleplugError(format!("{:?}", err)))
+      })?;
+      properties.and_then(|props| props.rssi).ok_or_else(|| {
+        ButtplugDeviceError::DeviceCommunicationError("Device has no RSSI reading".to_owned())
+      })
+    }
+    .boxed()
+  }
+
   fn subscribe(
     &self,
     msg: &HardwareSubscribeCmd,
@@ -440,10 +446,7 @@ impl<T: Peripheral + 'static> HardwareInternal for BtlePlugHardware<T> {
     let device = self.device.clone();
     async move {
       device.subscribe(&characteristic).await.map_err(|e| {
-        ButtplugDeviceError::DeviceSpecificError(HardwareSpecificError::BtleplugError(format!(
-          "{:?}",
-          e
-        )))
+        ButtplugDeviceError::from(HardwareSpecificError::BtleplugError(format!("{:?}", e)))
       })?;
       endpoints.insert(endpoint);
       Ok(())
@@ -473,10 +476,7 @@ impl<T: Peripheral + 'static> HardwareInternal for BtlePlugHardware<T> {
     let device = self.device.clone();
     async move {
       device.unsubscribe(&characteristic).await.map_err(|e| {
-        ButtplugDeviceError::DeviceSpecificError(HardwareSpecificError::BtleplugError(format!(
-          "{:?}",
-          e
-        )))
+        ButtplugDeviceError::from(HardwareSpecificError::BtleplugError(format!("{:?}", e)))
       })?;
       endpoints.remove(&endpoint);
       Ok(())
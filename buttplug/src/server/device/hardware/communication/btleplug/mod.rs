@@ -6,6 +6,9 @@
 // for full license information.
 
 pub mod btleplug_comm_manager;
-pub use btleplug_comm_manager::BtlePlugCommunicationManagerBuilder;
+pub use btleplug_comm_manager::{
+  BtlePlugCommunicationManagerBuilder,
+  BtlePlugCommunicationManagerConfig,
+};
 mod btleplug_adapter_task;
 pub mod btleplug_hardware;
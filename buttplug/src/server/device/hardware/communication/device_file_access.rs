@@ -0,0 +1,177 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Checks for whether the current user can open device files (`/dev/ttyUSB0`, `/dev/hidraw3`,
+//! etc) on linux, without actually opening them. Opening a serial port can reset whatever is on
+//! the other end, so permissions are worked out from file modes and the groups we're in instead.
+
+use std::{fs, os::unix::fs::MetadataExt, path::Path};
+
+/// User and groups the process runs as.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct ProcessIds {
+  uid: u32,
+  gid: u32,
+  groups: Vec<u32>,
+}
+
+impl ProcessIds {
+  /// Reads the effective user and groups of the process out of `/proc/self/status`.
+  fn current() -> Option<Self> {
+    Self::parse(&fs::read_to_string("/proc/self/status").ok()?)
+  }
+
+  fn parse(status: &str) -> Option<Self> {
+    let mut ids = Self::default();
+    for (key, values) in status.lines().filter_map(|line| line.split_once(':')) {
+      let mut values = values.split_whitespace().map(str::parse::<u32>);
+      match key {
+        // Real, effective, saved and filesystem ids, in that order.
+        "Uid" => ids.uid = values.nth(1)?.ok()?,
+        "Gid" => ids.gid = values.nth(1)?.ok()?,
+        "Groups" => ids.groups = values.collect::<Result<_, _>>().ok()?,
+        _ => {}
+      }
+    }
+    Some(ids)
+  }
+
+  fn can_read_write(&self, mode: u32, file_uid: u32, file_gid: u32) -> bool {
+    const READ_WRITE: u32 = 0o6;
+    if self.uid == 0 {
+      return true;
+    }
+    let permissions = if self.uid == file_uid {
+      mode >> 6
+    } else if self.gid == file_gid || self.groups.contains(&file_gid) {
+      mode >> 3
+    } else {
+      mode
+    };
+    permissions & READ_WRITE == READ_WRITE
+  }
+
+  fn inaccessible_device_file<'a, I>(&self, paths: I) -> Option<InaccessibleDeviceFile>
+  where
+    I: IntoIterator<Item = &'a str>,
+  {
+    let mut inaccessible = None;
+    for path in paths {
+      let metadata = if let Ok(metadata) = fs::metadata(Path::new(path)) {
+        metadata
+      } else {
+        continue;
+      };
+      if self.can_read_write(metadata.mode(), metadata.uid(), metadata.gid()) {
+        return None;
+      }
+      if inaccessible.is_none() {
+        let group_can_read_write = (metadata.mode() >> 3) & 0o6 == 0o6;
+        inaccessible = Some(InaccessibleDeviceFile {
+          path: path.to_owned(),
+          group: group_can_read_write
+            .then(|| group_name(metadata.gid()))
+            .flatten(),
+        });
+      }
+    }
+    inaccessible
+  }
+}
+
+/// Name of the group with the given id, from `/etc/group`.
+fn group_name(gid: u32) -> Option<String> {
+  fs::read_to_string("/etc/group").ok()?.lines().find_map(|line| {
+    let mut fields = line.split(':');
+    let name = fields.next()?;
+    (fields.nth(1)?.parse::<u32>().ok()? == gid).then(|| name.to_owned())
+  })
+}
+
+/// A device file the current user can't read from and write to.
+#[derive(Debug)]
+pub(super) struct InaccessibleDeviceFile {
+  pub path: String,
+  /// Group that owns the file, if members of the group can open it.
+  pub group: Option<String>,
+}
+
+/// Checks the given device files, returning one the current user can't open if none of them can be
+/// opened. Files that can't be looked up are skipped, as are all checks if we can't tell who we
+/// are.
+///
+/// Users can have access to some devices and not others (i.e. through udev rules for specific
+/// hardware), so as long as one file can be opened, permissions are assumed to be set up.
+pub(super) fn inaccessible_device_file<'a, I>(paths: I) -> Option<InaccessibleDeviceFile>
+where
+  I: IntoIterator<Item = &'a str>,
+{
+  ProcessIds::current()?.inaccessible_device_file(paths)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use std::os::unix::fs::PermissionsExt;
+
+  #[test]
+  fn test_process_ids_parse() {
+    let status = "Uid:\t1000\t1001\t1000\t1000\nGid:\t100\t101\t100\t100\nGroups:\t20 27 \n";
+    assert_eq!(
+      ProcessIds::parse(status),
+      Some(ProcessIds {
+        uid: 1001,
+        gid: 101,
+        groups: vec![20, 27],
+      })
+    );
+  }
+
+  #[test]
+  fn test_can_read_write() {
+    let ids = ProcessIds {
+      uid: 1000,
+      gid: 1000,
+      groups: vec![20],
+    };
+    // crw-rw---- root:dialout
+    assert!(ids.can_read_write(0o660, 0, 20));
+    // crw-rw---- root:root
+    assert!(!ids.can_read_write(0o660, 0, 0));
+    // crw------- user:root
+    assert!(ids.can_read_write(0o600, 1000, 0));
+    // crw-rw-r-- root:root, others can only read.
+    assert!(!ids.can_read_write(0o664, 0, 0));
+    assert!(ProcessIds::default().can_read_write(0o000, 1000, 1000));
+  }
+
+  #[test]
+  fn test_inaccessible_device_file() {
+    let path = std::env::temp_dir().join(format!(
+      "buttplug-test-device-file-{}",
+      std::process::id()
+    ));
+    fs::write(&path, b"").expect("Test, assuming infallible.");
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600))
+      .expect("Test, assuming infallible.");
+    let path_str = path.to_str().expect("Test, assuming infallible.");
+    let missing_path = "/dev/buttplug-test-missing";
+    // Files that aren't there are skipped, and we can always open a file we just made.
+    assert!(inaccessible_device_file([missing_path, path_str]).is_none());
+    let stranger = ProcessIds {
+      uid: u32::MAX - 1,
+      gid: u32::MAX - 1,
+      groups: vec![],
+    };
+    let file = stranger
+      .inaccessible_device_file([missing_path, path_str])
+      .expect("Test, assuming infallible.");
+    assert_eq!(file.path, path_str);
+    assert!(file.group.is_none());
+    fs::remove_file(&path).expect("Test, assuming infallible.");
+  }
+}
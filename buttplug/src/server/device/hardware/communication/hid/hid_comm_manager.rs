@@ -3,20 +3,54 @@ use crate::{
   server::device::hardware::communication::{
     HardwareCommunicationManager,
     HardwareCommunicationManagerBuilder,
+    HardwareCommunicationManagerConfig,
     HardwareCommunicationManagerEvent,
+    HardwarePermissionError,
     TimedRetryCommunicationManager,
     TimedRetryCommunicationManagerImpl,
   },
 };
 use async_trait::async_trait;
 use hidapi::HidApi;
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 use tokio::sync::mpsc::Sender;
 
 use super::hid_device_impl::HidHardwareConnector;
 
+/// Runtime settings for HID device scanning.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HidCommunicationManagerConfig {
+  /// Time to wait between HID device enumerations while scanning.
+  pub rescan_wait_duration: Duration,
+}
+
+impl Default for HidCommunicationManagerConfig {
+  fn default() -> Self {
+    Self {
+      rescan_wait_duration: Duration::from_secs(1),
+    }
+  }
+}
+
+impl HardwareCommunicationManagerConfig for HidCommunicationManagerConfig {
+  type Builder = HidCommunicationManagerBuilder;
+
+  fn into_builder(self) -> Self::Builder {
+    HidCommunicationManagerBuilder::default().config(self)
+  }
+}
+
 #[derive(Default)]
-pub struct HidCommunicationManagerBuilder {}
+pub struct HidCommunicationManagerBuilder {
+  config: HidCommunicationManagerConfig,
+}
+
+impl HidCommunicationManagerBuilder {
+  pub fn config(mut self, config: HidCommunicationManagerConfig) -> Self {
+    self.config = config;
+    self
+  }
+}
 
 impl HardwareCommunicationManagerBuilder for HidCommunicationManagerBuilder {
   fn finish(
@@ -24,21 +58,26 @@ impl HardwareCommunicationManagerBuilder for HidCommunicationManagerBuilder {
     sender: Sender<HardwareCommunicationManagerEvent>,
   ) -> Box<dyn HardwareCommunicationManager> {
     Box::new(TimedRetryCommunicationManager::new(
-      HidCommunicationManager::new(sender),
+      HidCommunicationManager::new(sender, self.config.clone()),
     ))
   }
 }
 
-pub struct HidCommunicationManager {
+pub(crate) struct HidCommunicationManager {
   sender: Sender<HardwareCommunicationManagerEvent>,
   hidapi: Arc<HidApi>,
+  config: HidCommunicationManagerConfig,
 }
 
 impl HidCommunicationManager {
-  fn new(sender: Sender<HardwareCommunicationManagerEvent>) -> Self {
+  fn new(
+    sender: Sender<HardwareCommunicationManagerEvent>,
+    config: HidCommunicationManagerConfig,
+  ) -> Self {
     Self {
       sender,
       hidapi: Arc::new(HidApi::new().unwrap()),
+      config,
     }
   }
 }
@@ -49,6 +88,39 @@ impl TimedRetryCommunicationManagerImpl for HidCommunicationManager {
     "HIDCommunicationManager"
   }
 
+  fn rescan_wait_duration(&self) -> Duration {
+    self.config.rescan_wait_duration
+  }
+
+  fn check_permissions(&self) -> Result<(), HardwarePermissionError> {
+    // hidraw devices on linux are only readable by root unless a udev rule says otherwise.
+    #[cfg(target_os = "linux")]
+    {
+      use crate::server::device::hardware::communication::{
+        device_file_access::inaccessible_device_file,
+        HardwarePermission,
+      };
+      // Only devices we'd offer for connection matter, same as in scan.
+      let paths = self
+        .hidapi
+        .device_list()
+        .filter(|device| device.serial_number().is_some())
+        .filter_map(|device| device.path().to_str().ok());
+      if let Some(file) = inaccessible_device_file(paths) {
+        return Err(HardwarePermissionError::new(
+          HardwarePermission::Hid,
+          &format!(
+            "Cannot open {}. Add a udev rule giving your user access to the device (i.e. \
+             `KERNEL==\"hidraw*\", TAG+=\"uaccess\"` in a file under /etc/udev/rules.d/), then \
+             unplug and replug it.",
+            file.path
+          ),
+        ));
+      }
+    }
+    Ok(())
+  }
+
   async fn scan(&self) -> Result<(), ButtplugDeviceError> {
     // TODO Does this block? Should it run in one of our threads?
     let device_sender = self.sender.clone();
@@ -80,8 +152,4 @@ impl TimedRetryCommunicationManagerImpl for HidCommunicationManager {
     }
     Ok(())
   }
-
-  fn can_scan(&self) -> bool {
-    true
-  }
 }
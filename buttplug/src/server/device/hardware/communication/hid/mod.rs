@@ -2,4 +2,7 @@ pub mod hid_comm_manager;
 pub mod hid_device_impl;
 mod hidapi_async;
 
-pub use hid_comm_manager::{HidCommunicationManager, HidCommunicationManagerBuilder};
+pub use hid_comm_manager::{
+  HidCommunicationManagerBuilder,
+  HidCommunicationManagerConfig,
+};
@@ -11,6 +11,7 @@ use crate::{
   server::device::hardware::communication::{
     HardwareCommunicationManager,
     HardwareCommunicationManagerBuilder,
+    HardwareCommunicationManagerConfig,
     HardwareCommunicationManagerEvent,
     TimedRetryCommunicationManager,
     TimedRetryCommunicationManagerImpl,
@@ -110,8 +111,40 @@ pub(super) struct LovenseServiceLocalInfo {
 
 type LovenseServiceInfo = HashMap<String, LovenseServiceHostInfo>;
 
+/// Runtime settings for Lovense Connect polling.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LovenseConnectServiceCommunicationManagerConfig {
+  /// Time to wait between Lovense Connect API queries while scanning.
+  pub rescan_wait_duration: Duration,
+}
+
+impl Default for LovenseConnectServiceCommunicationManagerConfig {
+  fn default() -> Self {
+    Self {
+      rescan_wait_duration: Duration::from_secs(10),
+    }
+  }
+}
+
+impl HardwareCommunicationManagerConfig for LovenseConnectServiceCommunicationManagerConfig {
+  type Builder = LovenseConnectServiceCommunicationManagerBuilder;
+
+  fn into_builder(self) -> Self::Builder {
+    LovenseConnectServiceCommunicationManagerBuilder::default().config(self)
+  }
+}
+
 #[derive(Default, Clone)]
-pub struct LovenseConnectServiceCommunicationManagerBuilder {}
+pub struct LovenseConnectServiceCommunicationManagerBuilder {
+  config: LovenseConnectServiceCommunicationManagerConfig,
+}
+
+impl LovenseConnectServiceCommunicationManagerBuilder {
+  pub fn config(mut self, config: LovenseConnectServiceCommunicationManagerConfig) -> Self {
+    self.config = config;
+    self
+  }
+}
 
 impl HardwareCommunicationManagerBuilder for LovenseConnectServiceCommunicationManagerBuilder {
   fn finish(
@@ -119,14 +152,15 @@ impl HardwareCommunicationManagerBuilder for LovenseConnectServiceCommunicationM
     sender: Sender<HardwareCommunicationManagerEvent>,
   ) -> Box<dyn HardwareCommunicationManager> {
     Box::new(TimedRetryCommunicationManager::new(
-      LovenseConnectServiceCommunicationManager::new(sender),
+      LovenseConnectServiceCommunicationManager::new(sender, self.config.clone()),
     ))
   }
 }
 
-pub struct LovenseConnectServiceCommunicationManager {
+pub(crate) struct LovenseConnectServiceCommunicationManager {
   sender: mpsc::Sender<HardwareCommunicationManagerEvent>,
   known_hosts: DashSet<String>,
+  config: LovenseConnectServiceCommunicationManagerConfig,
 }
 
 pub(super) async fn get_local_info(host: &str) -> Option<LovenseServiceLocalInfo> {
@@ -161,10 +195,14 @@ pub(super) async fn get_local_info(host: &str) -> Option<LovenseServiceLocalInfo
 }
 
 impl LovenseConnectServiceCommunicationManager {
-  fn new(sender: mpsc::Sender<HardwareCommunicationManagerEvent>) -> Self {
+  fn new(
+    sender: mpsc::Sender<HardwareCommunicationManagerEvent>,
+    config: LovenseConnectServiceCommunicationManagerConfig,
+  ) -> Self {
     Self {
       sender,
       known_hosts: DashSet::new(),
+      config,
     }
   }
 
@@ -211,7 +249,7 @@ impl TimedRetryCommunicationManagerImpl for LovenseConnectServiceCommunicationMa
   }
 
   fn rescan_wait_duration(&self) -> Duration {
-    Duration::from_secs(10)
+    self.config.rescan_wait_duration
   }
 
   async fn scan(&self) -> Result<(), ButtplugDeviceError> {
@@ -261,10 +299,4 @@ impl TimedRetryCommunicationManagerImpl for LovenseConnectServiceCommunicationMa
     }
     Ok(())
   }
-
-  // Assume we've already got network access. A bad assumption, but we'll need to figure out how to
-  // make this work better later.
-  fn can_scan(&self) -> bool {
-    true
-  }
 }
@@ -8,7 +8,7 @@
 mod lovense_connect_service_comm_manager;
 mod lovense_connect_service_hardware;
 pub use lovense_connect_service_comm_manager::{
-  LovenseConnectServiceCommunicationManager,
   LovenseConnectServiceCommunicationManagerBuilder,
+  LovenseConnectServiceCommunicationManagerConfig,
 };
 pub use lovense_connect_service_hardware::LovenseServiceHardware;
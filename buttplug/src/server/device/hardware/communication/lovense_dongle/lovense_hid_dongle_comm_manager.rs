@@ -18,7 +18,9 @@ use crate::{
   server::device::hardware::communication::{
     HardwareCommunicationManager,
     HardwareCommunicationManagerBuilder,
+    HardwareCommunicationManagerConfig,
     HardwareCommunicationManagerEvent,
+    HardwareCommunicationManagerStatus,
   },
   util::async_manager,
 };
@@ -157,29 +159,71 @@ fn hid_read_thread(
   trace!("Leaving HID dongle read thread");
 }
 
+/// Runtime settings for finding the Lovense HID dongle.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LovenseHIDDongleCommunicationManagerConfig {
+  /// USB Vendor ID of the dongle.
+  pub vendor_id: u16,
+  /// USB Product ID of the dongle.
+  pub product_id: u16,
+}
+
+impl Default for LovenseHIDDongleCommunicationManagerConfig {
+  fn default() -> Self {
+    Self {
+      vendor_id: 0x1915,
+      product_id: 0x520a,
+    }
+  }
+}
+
+impl HardwareCommunicationManagerConfig for LovenseHIDDongleCommunicationManagerConfig {
+  type Builder = LovenseHIDDongleCommunicationManagerBuilder;
+
+  fn into_builder(self) -> Self::Builder {
+    LovenseHIDDongleCommunicationManagerBuilder::default().config(self)
+  }
+}
+
 #[derive(Default, Clone)]
-pub struct LovenseHIDDongleCommunicationManagerBuilder {}
+pub struct LovenseHIDDongleCommunicationManagerBuilder {
+  config: LovenseHIDDongleCommunicationManagerConfig,
+}
+
+impl LovenseHIDDongleCommunicationManagerBuilder {
+  pub fn config(mut self, config: LovenseHIDDongleCommunicationManagerConfig) -> Self {
+    self.config = config;
+    self
+  }
+}
 
 impl HardwareCommunicationManagerBuilder for LovenseHIDDongleCommunicationManagerBuilder {
   fn finish(
     &mut self,
     sender: Sender<HardwareCommunicationManagerEvent>,
   ) -> Box<dyn HardwareCommunicationManager> {
-    Box::new(LovenseHIDDongleCommunicationManager::new(sender))
+    Box::new(LovenseHIDDongleCommunicationManager::new(
+      sender,
+      self.config.clone(),
+    ))
   }
 }
 
-pub struct LovenseHIDDongleCommunicationManager {
+pub(crate) struct LovenseHIDDongleCommunicationManager {
   machine_sender: Sender<LovenseDeviceCommand>,
   read_thread: Arc<Mutex<Option<thread::JoinHandle<()>>>>,
   write_thread: Arc<Mutex<Option<thread::JoinHandle<()>>>>,
   is_scanning: Arc<AtomicBool>,
   thread_cancellation_token: CancellationToken,
   dongle_available: Arc<AtomicBool>,
+  config: LovenseHIDDongleCommunicationManagerConfig,
 }
 
 impl LovenseHIDDongleCommunicationManager {
-  fn new(event_sender: Sender<HardwareCommunicationManagerEvent>) -> Self {
+  fn new(
+    event_sender: Sender<HardwareCommunicationManagerEvent>,
+    config: LovenseHIDDongleCommunicationManagerConfig,
+  ) -> Self {
     trace!("Lovense dongle HID Manager created");
     let (machine_sender, machine_receiver) = channel(256);
     let dongle_available = Arc::new(AtomicBool::new(false));
@@ -190,6 +234,7 @@ impl LovenseHIDDongleCommunicationManager {
       is_scanning: Arc::new(AtomicBool::new(false)),
       thread_cancellation_token: CancellationToken::new(),
       dongle_available,
+      config,
     };
     let dongle_fut = mgr.find_dongle();
     async_manager::spawn(
@@ -222,6 +267,8 @@ impl LovenseHIDDongleCommunicationManager {
     let read_token = self.thread_cancellation_token.child_token();
     let write_token = self.thread_cancellation_token.child_token();
     let dongle_available = self.dongle_available.clone();
+    let vendor_id = self.config.vendor_id;
+    let product_id = self.config.product_id;
     async move {
       let (writer_sender, writer_receiver) = channel(256);
       let (reader_sender, reader_receiver) = channel(256);
@@ -233,11 +280,11 @@ impl LovenseHIDDongleCommunicationManager {
 
       // We can't clone HIDDevices, so instead we just open 2 instances of the same one to pass to
       // the different threads. Ugh.
-      let dongle1 = api.open(0x1915, 0x520a).map_err(|_| {
+      let dongle1 = api.open(vendor_id, product_id).map_err(|_| {
         warn!("Cannot find lovense HID dongle.");
         ButtplugDeviceError::DeviceConnectionError("Cannot find lovense HID Dongle.".to_owned())
       })?;
-      let dongle2 = api.open(0x1915, 0x520a).map_err(|_| {
+      let dongle2 = api.open(vendor_id, product_id).map_err(|_| {
         warn!("Cannot find lovense HID dongle.");
         ButtplugDeviceError::DeviceConnectionError("Cannot find lovense HID Dongle.".to_owned())
       })?;
@@ -309,12 +356,11 @@ impl HardwareCommunicationManager for LovenseHIDDongleCommunicationManager {
     .boxed()
   }
 
-  fn scanning_status(&self) -> bool {
-    self.is_scanning.load(Ordering::SeqCst)
-  }
-
-  fn can_scan(&self) -> bool {
-    self.dongle_available.load(Ordering::SeqCst)
+  fn status(&self) -> HardwareCommunicationManagerStatus {
+    HardwareCommunicationManagerStatus::from_availability(
+      self.dongle_available.load(Ordering::SeqCst),
+      self.is_scanning.load(Ordering::SeqCst),
+    )
   }
 }
 
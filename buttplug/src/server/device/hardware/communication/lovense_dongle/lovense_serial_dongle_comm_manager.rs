@@ -18,7 +18,9 @@ use crate::{
   server::device::hardware::communication::{
     HardwareCommunicationManager,
     HardwareCommunicationManagerBuilder,
+    HardwareCommunicationManagerConfig,
     HardwareCommunicationManagerEvent,
+    HardwareCommunicationManagerStatus,
   },
   util::async_manager,
 };
@@ -143,19 +145,54 @@ fn serial_read_thread(
   debug!("Exiting lovense dongle read thread.");
 }
 
+/// Runtime settings for finding the Lovense serial dongle.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LovenseSerialDongleCommunicationManagerConfig {
+  /// USB (Vendor ID, Product ID) pairs of serial ports that will be treated as dongles.
+  pub usb_ids: Vec<(u16, u16)>,
+}
+
+impl Default for LovenseSerialDongleCommunicationManagerConfig {
+  fn default() -> Self {
+    Self {
+      usb_ids: vec![(0x1a86, 0x7523)],
+    }
+  }
+}
+
+impl HardwareCommunicationManagerConfig for LovenseSerialDongleCommunicationManagerConfig {
+  type Builder = LovenseSerialDongleCommunicationManagerBuilder;
+
+  fn into_builder(self) -> Self::Builder {
+    LovenseSerialDongleCommunicationManagerBuilder::default().config(self)
+  }
+}
+
 #[derive(Default, Clone)]
-pub struct LovenseSerialDongleCommunicationManagerBuilder {}
+pub struct LovenseSerialDongleCommunicationManagerBuilder {
+  config: LovenseSerialDongleCommunicationManagerConfig,
+}
+
+impl LovenseSerialDongleCommunicationManagerBuilder {
+  pub fn config(mut self, config: LovenseSerialDongleCommunicationManagerConfig) -> Self {
+    self.config = config;
+    self
+  }
+}
 
 impl HardwareCommunicationManagerBuilder for LovenseSerialDongleCommunicationManagerBuilder {
   fn finish(
     &mut self,
     sender: Sender<HardwareCommunicationManagerEvent>,
   ) -> Box<dyn HardwareCommunicationManager> {
-    Box::new(LovenseSerialDongleCommunicationManager::new(sender))
+    Box::new(LovenseSerialDongleCommunicationManager::new(
+      sender,
+      self.config.clone(),
+    ))
   }
 }
 
-pub struct LovenseSerialDongleCommunicationManager {
+pub(crate) struct LovenseSerialDongleCommunicationManager {
   machine_sender: Sender<LovenseDeviceCommand>,
   //port: Arc<Mutex<Option<Box<dyn SerialPort>>>>,
   read_thread: Arc<Mutex<Option<thread::JoinHandle<()>>>>,
@@ -163,10 +200,14 @@ pub struct LovenseSerialDongleCommunicationManager {
   is_scanning: Arc<AtomicBool>,
   thread_cancellation_token: CancellationToken,
   dongle_available: Arc<AtomicBool>,
+  config: LovenseSerialDongleCommunicationManagerConfig,
 }
 
 impl LovenseSerialDongleCommunicationManager {
-  fn new(event_sender: Sender<HardwareCommunicationManagerEvent>) -> Self {
+  fn new(
+    event_sender: Sender<HardwareCommunicationManagerEvent>,
+    config: LovenseSerialDongleCommunicationManagerConfig,
+  ) -> Self {
     trace!("Lovense dongle serial port created");
     let (machine_sender, machine_receiver) = channel(256);
     let dongle_available = Arc::new(AtomicBool::new(false));
@@ -177,6 +218,7 @@ impl LovenseSerialDongleCommunicationManager {
       is_scanning: Arc::new(AtomicBool::new(false)),
       thread_cancellation_token: CancellationToken::new(),
       dongle_available,
+      config,
     };
     let dongle_fut = mgr.find_dongle();
     // TODO If we don't find a dongle before scanning, what happens?
@@ -211,6 +253,7 @@ impl LovenseSerialDongleCommunicationManager {
     let held_write_thread = self.write_thread.clone();
     let token = self.thread_cancellation_token.child_token();
     let dongle_available = self.dongle_available.clone();
+    let usb_ids = self.config.usb_ids.clone();
     async move {
       // TODO Does this block? Should it run in one of our threads?
       let found_dongle = false;
@@ -219,9 +262,9 @@ impl LovenseSerialDongleCommunicationManager {
           debug!("Got {} serial ports back", ports.len());
           for p in ports {
             if let SerialPortType::UsbPort(usb_info) = p.port_type {
-              // Hardcode the dongle VID/PID for now. We can't really do protocol
+              // Match on the configured dongle VID/PIDs. We can't really do protocol
               // detection here because this is a comm bus to us, not a device.
-              if usb_info.vid == 0x1a86 && usb_info.pid == 0x7523 {
+              if usb_ids.contains(&(usb_info.vid, usb_info.pid)) {
                 // We've found a dongle.
                 info!("Found lovense dongle, connecting");
                 let serial_port =
@@ -311,12 +354,11 @@ impl HardwareCommunicationManager for LovenseSerialDongleCommunicationManager {
     .boxed()
   }
 
-  fn scanning_status(&self) -> bool {
-    self.is_scanning.load(Ordering::SeqCst)
-  }
-
-  fn can_scan(&self) -> bool {
-    self.dongle_available.load(Ordering::SeqCst)
+  fn status(&self) -> HardwareCommunicationManagerStatus {
+    HardwareCommunicationManagerStatus::from_availability(
+      self.dongle_available.load(Ordering::SeqCst),
+      self.is_scanning.load(Ordering::SeqCst),
+    )
   }
 }
 
@@ -13,10 +13,10 @@ pub mod lovense_serial_dongle_comm_manager;
 
 pub use lovense_dongle_hardware::{LovenseDongleHardware, LovenseDongleHardwareConnector};
 pub use lovense_hid_dongle_comm_manager::{
-  LovenseHIDDongleCommunicationManager,
   LovenseHIDDongleCommunicationManagerBuilder,
+  LovenseHIDDongleCommunicationManagerConfig,
 };
 pub use lovense_serial_dongle_comm_manager::{
-  LovenseSerialDongleCommunicationManager,
   LovenseSerialDongleCommunicationManagerBuilder,
+  LovenseSerialDongleCommunicationManagerConfig,
 };
@@ -11,6 +11,14 @@ pub mod lovense_connect_service;
 #[cfg(feature = "websocket-server-manager")]
 pub mod websocket_server;
 
+// Virtual devices work anywhere, since there's no hardware involved
+#[cfg(feature = "virtual-device-manager")]
+pub mod virtual_device;
+
+// Test devices are driven by the application, for testing without hardware
+#[cfg(feature = "test-device-manager")]
+pub mod test_device;
+
 // BTLEPlug works on anything not WASM
 #[cfg(all(
   feature = "btleplug-manager",
@@ -24,6 +32,39 @@ pub mod websocket_server;
 ))]
 pub mod btleplug;
 
+// Bluetooth LE glue shared between btleplug and the native WinRT/BlueZ backends
+#[cfg(any(
+  all(
+    feature = "btleplug-manager",
+    any(
+      target_os = "windows",
+      target_os = "macos",
+      target_os = "linux",
+      target_os = "ios",
+      target_os = "android"
+    )
+  ),
+  all(feature = "winrt-ble-manager", target_os = "windows"),
+  all(feature = "bluez-manager", target_os = "linux"),
+  test
+))]
+mod btle_common;
+
+// Native WinRT bluetooth, for when btleplug's windows backend doesn't cut it
+#[cfg(all(feature = "winrt-ble-manager", target_os = "windows"))]
+pub mod winrt_ble;
+
+// Checks for whether device files can be opened, for the managers that go through them on linux
+#[cfg(all(
+  target_os = "linux",
+  any(feature = "serial-manager", feature = "hid-manager", test)
+))]
+mod device_file_access;
+
+// Native BlueZ, for bonding agents and discovery filters btleplug doesn't expose
+#[cfg(all(feature = "bluez-manager", target_os = "linux"))]
+pub mod bluez;
+
 // Lovense Dongles and Serial Ports work on all desktop platforms
 #[cfg(all(
   feature = "lovense-dongle-manager",
@@ -53,8 +94,12 @@ use crate::{
 };
 use async_trait::async_trait;
 use futures::future::{self, FutureExt};
+use getset::{CopyGetters, Getters};
 use serde::{Deserialize, Serialize};
-use std::{sync::Arc, time::Duration};
+use std::{
+  sync::{Arc, Mutex},
+  time::Duration,
+};
 use thiserror::Error;
 use tokio::sync::mpsc::Sender;
 use tokio_util::sync::CancellationToken;
@@ -69,6 +114,77 @@ pub enum HardwareCommunicationManagerEvent {
     creator: Box<dyn HardwareConnector>,
   },
   ScanningFinished,
+  /// The status of the manager changed without being asked to (adapter unplugged or turned off,
+  /// permissions revoked, scanning failed, etc).
+  StatusChanged(HardwareCommunicationManagerStatus),
+}
+
+/// What a [HardwareCommunicationManager] is up to, and if it isn't able to scan, why.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HardwareCommunicationManagerStatus {
+  /// Able to scan, but not currently scanning.
+  Ready,
+  /// The OS or user hasn't given us permission to use the hardware.
+  NeedsPermission(HardwarePermissionError),
+  /// The hardware the manager uses (bluetooth adapter, dongle, etc) isn't there or is turned off.
+  AdapterMissing,
+  /// Currently scanning for devices.
+  Scanning,
+  /// Something went wrong that leaves the manager unable to scan.
+  Error(String),
+}
+
+impl HardwareCommunicationManagerStatus {
+  /// Status for managers that only keep track of whether their hardware is available and whether
+  /// they're scanning.
+  pub fn from_availability(available: bool, scanning: bool) -> Self {
+    if !available {
+      Self::AdapterMissing
+    } else if scanning {
+      Self::Scanning
+    } else {
+      Self::Ready
+    }
+  }
+
+  /// True if asking the manager to scan might work. Errors may be transient, so managers that hit
+  /// one are still asked to scan.
+  pub fn can_scan(&self) -> bool {
+    !matches!(self, Self::NeedsPermission(_) | Self::AdapterMissing)
+  }
+
+  pub fn is_scanning(&self) -> bool {
+    *self == Self::Scanning
+  }
+}
+
+/// OS level permissions communication managers may need to use their hardware.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HardwarePermission {
+  Bluetooth,
+  SerialPort,
+  Hid,
+}
+
+/// A permission a communication manager is missing, along with what the user can do to grant it,
+/// so hosts can show something more useful than a scan that never finds anything.
+#[derive(Error, Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Getters, CopyGetters)]
+#[error("{permission:?} permission needed: {guidance}")]
+pub struct HardwarePermissionError {
+  #[getset(get_copy = "pub")]
+  permission: HardwarePermission,
+  /// Steps the user can take to grant the permission, in a form that can be shown to them.
+  #[getset(get = "pub")]
+  guidance: String,
+}
+
+impl HardwarePermissionError {
+  pub fn new(permission: HardwarePermission, guidance: &str) -> Self {
+    Self {
+      permission,
+      guidance: guidance.to_owned(),
+    }
+  }
 }
 
 pub trait HardwareCommunicationManagerBuilder: Send {
@@ -78,19 +194,54 @@ pub trait HardwareCommunicationManagerBuilder: Send {
   ) -> Box<dyn HardwareCommunicationManager>;
 }
 
+/// Typed runtime configuration for a comm manager. Lets hosts pass user settings (scan parameters,
+/// adapter selection, port filters, etc) to `ServerDeviceManagerBuilder::comm_manager_config`
+/// without having to construct the matching builder themselves.
+pub trait HardwareCommunicationManagerConfig {
+  type Builder: HardwareCommunicationManagerBuilder + 'static;
+
+  fn into_builder(self) -> Self::Builder;
+}
+
 pub trait HardwareCommunicationManager: Send + Sync {
   fn name(&self) -> &'static str;
   fn start_scanning(&mut self) -> ButtplugResultFuture;
   fn stop_scanning(&mut self) -> ButtplugResultFuture;
-  fn scanning_status(&self) -> bool {
-    false
+  /// Scan for a single, previously connected device by its address. Managers that can narrow their
+  /// search (address filters for bluetooth, a single port for serial, etc) should override this so
+  /// reconnecting is faster and uses less power. By default this is a full scan, and the device
+  /// manager ignores anything found that isn't the device asked for.
+  fn start_targeted_scanning(&mut self, _address: &str) -> ButtplugResultFuture {
+    self.start_scanning()
+  }
+  /// Current status of the manager. Changes that don't come from starting or stopping scanning
+  /// should also be sent as [HardwareCommunicationManagerEvent::StatusChanged].
+  fn status(&self) -> HardwareCommunicationManagerStatus;
+  /// Checks that the OS lets us use the hardware. Run by the device manager whenever scanning
+  /// starts or statuses are updated, so it should be quick. Managers that fail it are reported as
+  /// [NeedsPermission](HardwareCommunicationManagerStatus::NeedsPermission) and not asked to scan.
+  fn check_permissions(&self) -> Result<(), HardwarePermissionError> {
+    Ok(())
   }
-  fn can_scan(&self) -> bool;
   // Events happen via channel senders passed to the comm manager.
 }
 
-#[derive(Error, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
-pub enum HardwareSpecificError {
+/// Status of a manager, or [NeedsPermission](HardwareCommunicationManagerStatus::NeedsPermission)
+/// if it fails its [permission check](HardwareCommunicationManager::check_permissions).
+pub(crate) fn checked_status(
+  manager: &dyn HardwareCommunicationManager,
+) -> HardwareCommunicationManagerStatus {
+  match manager.check_permissions() {
+    Ok(()) => manager.status(),
+    Err(err) => HardwareCommunicationManagerStatus::NeedsPermission(err),
+  }
+}
+
+/// Errors from the libraries behind the built in communication managers. Which variants exist
+/// depends on the features and platform the crate is built for, so they reach users as the message
+/// of a [ButtplugDeviceError::DeviceSpecificError] instead.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub(crate) enum HardwareSpecificError {
   // XInput library doesn't derive error on its error enum. :(
   #[cfg(all(feature = "xinput-manager", target_os = "windows"))]
   #[error("XInput usage error: {0}")]
@@ -108,6 +259,12 @@ pub enum HardwareSpecificError {
   ))]
   #[error("Btleplug error: {0}")]
   BtleplugError(String),
+  #[cfg(all(feature = "winrt-ble-manager", target_os = "windows"))]
+  #[error("WinRT bluetooth error: {0}")]
+  WinRtBleError(String),
+  #[cfg(all(feature = "bluez-manager", target_os = "linux"))]
+  #[error("BlueZ error: {0}")]
+  BluezError(String),
   #[cfg(all(
     feature = "serial-manager",
     any(target_os = "windows", target_os = "macos", target_os = "linux")
@@ -116,19 +273,40 @@ pub enum HardwareSpecificError {
   SerialError(String),
 }
 
+impl From<HardwareSpecificError> for ButtplugDeviceError {
+  fn from(err: HardwareSpecificError) -> Self {
+    ButtplugDeviceError::DeviceSpecificError(err.to_string())
+  }
+}
+
 #[async_trait]
 pub trait TimedRetryCommunicationManagerImpl: Sync + Send {
   fn name(&self) -> &'static str;
-  fn can_scan(&self) -> bool;
+  /// Whether the manager is able to scan. [TimedRetryCommunicationManager] reports
+  /// [Scanning](HardwareCommunicationManagerStatus::Scanning) itself while it's running scans.
+  fn status(&self) -> HardwareCommunicationManagerStatus {
+    HardwareCommunicationManagerStatus::Ready
+  }
   fn rescan_wait_duration(&self) -> Duration {
     Duration::from_secs(1)
   }
+  /// See [HardwareCommunicationManager::check_permissions].
+  fn check_permissions(&self) -> Result<(), HardwarePermissionError> {
+    Ok(())
+  }
   async fn scan(&self) -> Result<(), ButtplugDeviceError>;
+  /// Scan for a single device by address. See
+  /// [HardwareCommunicationManager::start_targeted_scanning], defaults to a full scan.
+  async fn scan_for(&self, _address: &str) -> Result<(), ButtplugDeviceError> {
+    self.scan().await
+  }
 }
 
 pub struct TimedRetryCommunicationManager<T: TimedRetryCommunicationManagerImpl + 'static> {
   comm_manager: Arc<T>,
   cancellation_token: Option<CancellationToken>,
+  /// Error that stopped the last scan loop, if it stopped on its own.
+  scan_error: Arc<Mutex<Option<String>>>,
 }
 
 impl<T: TimedRetryCommunicationManagerImpl> TimedRetryCommunicationManager<T> {
@@ -136,19 +314,19 @@ impl<T: TimedRetryCommunicationManagerImpl> TimedRetryCommunicationManager<T> {
     Self {
       comm_manager: Arc::new(comm_manager),
       cancellation_token: None,
+      scan_error: Arc::new(Mutex::new(None)),
     }
   }
-}
-
-impl<T: TimedRetryCommunicationManagerImpl> HardwareCommunicationManager
-  for TimedRetryCommunicationManager<T>
-{
-  fn name(&self) -> &'static str {
-    self.comm_manager.name()
-  }
 
-  fn start_scanning(&mut self) -> ButtplugResultFuture {
-    if self.cancellation_token.is_some() {
+  /// Starts a loop running scans until stopped, for the given address or for all devices.
+  fn start_scan_loop(&mut self, address: Option<String>) -> ButtplugResultFuture {
+    // A scan loop that stopped on an error leaves its token behind, start a new loop over it.
+    let scan_failed = self
+      .scan_error
+      .lock()
+      .expect("Lock only held for assignment")
+      .is_some();
+    if self.cancellation_token.is_some() && !scan_failed {
       return future::ready(Ok(())).boxed();
     }
     let comm_manager = self.comm_manager.clone();
@@ -156,11 +334,19 @@ impl<T: TimedRetryCommunicationManagerImpl> HardwareCommunicationManager
     let child_token = token.child_token();
     self.cancellation_token = Some(token);
     let duration = self.comm_manager.rescan_wait_duration();
+    let scan_error = self.scan_error.clone();
+    *scan_error.lock().expect("Lock only held for assignment") = None;
     async move {
       async_manager::spawn(async move {
         loop {
-          if let Err(err) = comm_manager.scan().await {
+          let result = if let Some(address) = &address {
+            comm_manager.scan_for(address).await
+          } else {
+            comm_manager.scan().await
+          };
+          if let Err(err) = result {
             error!("Timed Device Communication Manager Failure: {}", err);
+            *scan_error.lock().expect("Lock only held for assignment") = Some(err.to_string());
             break;
           }
           tokio::select! {
@@ -173,6 +359,26 @@ impl<T: TimedRetryCommunicationManagerImpl> HardwareCommunicationManager
     }
     .boxed()
   }
+}
+
+impl<T: TimedRetryCommunicationManagerImpl> HardwareCommunicationManager
+  for TimedRetryCommunicationManager<T>
+{
+  fn name(&self) -> &'static str {
+    self.comm_manager.name()
+  }
+
+  fn start_scanning(&mut self) -> ButtplugResultFuture {
+    self.start_scan_loop(None)
+  }
+
+  fn start_targeted_scanning(&mut self, address: &str) -> ButtplugResultFuture {
+    self.start_scan_loop(Some(address.to_owned()))
+  }
+
+  fn check_permissions(&self) -> Result<(), HardwarePermissionError> {
+    self.comm_manager.check_permissions()
+  }
 
   fn stop_scanning(&mut self) -> ButtplugResultFuture {
     if self.cancellation_token.is_none() {
@@ -182,11 +388,21 @@ impl<T: TimedRetryCommunicationManagerImpl> HardwareCommunicationManager
     future::ready(Ok(())).boxed()
   }
 
-  fn scanning_status(&self) -> bool {
-    self.cancellation_token.is_some()
-  }
-  fn can_scan(&self) -> bool {
-    self.comm_manager.can_scan()
+  fn status(&self) -> HardwareCommunicationManagerStatus {
+    if let Some(err) = self
+      .scan_error
+      .lock()
+      .expect("Lock only held for assignment")
+      .clone()
+    {
+      return HardwareCommunicationManagerStatus::Error(err);
+    }
+    match self.comm_manager.status() {
+      HardwareCommunicationManagerStatus::Ready if self.cancellation_token.is_some() => {
+        HardwareCommunicationManagerStatus::Scanning
+      }
+      status => status,
+    }
   }
 }
 
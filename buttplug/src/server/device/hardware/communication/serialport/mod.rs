@@ -9,7 +9,7 @@ mod serialport_comm_manager;
 mod serialport_hardware;
 
 pub use serialport_comm_manager::{
-  SerialPortCommunicationManager,
   SerialPortCommunicationManagerBuilder,
+  SerialPortCommunicationManagerConfig,
 };
 pub use serialport_hardware::{SerialPortHardware, SerialPortHardwareConnector};
@@ -10,20 +10,117 @@ use std::time::Duration;
 use super::SerialPortHardwareConnector;
 use crate::{
   core::errors::ButtplugDeviceError,
-  server::device::hardware::communication::{
-    HardwareCommunicationManager,
-    HardwareCommunicationManagerBuilder,
-    HardwareCommunicationManagerEvent,
-    TimedRetryCommunicationManager,
-    TimedRetryCommunicationManagerImpl,
+  server::device::{
+    configuration::{DeviceConfigurationManager, ProtocolCommunicationSpecifier},
+    hardware::communication::{
+      HardwareCommunicationManager,
+      HardwareCommunicationManagerBuilder,
+      HardwareCommunicationManagerConfig,
+      HardwareCommunicationManagerEvent,
+      HardwarePermissionError,
+      TimedRetryCommunicationManager,
+      TimedRetryCommunicationManagerImpl,
+    },
   },
 };
 use async_trait::async_trait;
-use serialport::available_ports;
+use serialport::{available_ports, SerialPortInfo, SerialPortType};
 use tokio::sync::mpsc::Sender;
 
+/// Runtime settings for serial port scanning.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SerialPortCommunicationManagerConfig {
+  /// If this or `allowed_usb_ids` isn't empty, only ports with these names (i.e. `COM3`,
+  /// `/dev/ttyUSB0`) or USB IDs will be offered for device connection.
+  pub allowed_ports: Vec<String>,
+  /// If this or `allowed_ports` isn't empty, only USB serial adapters with these vendor and product
+  /// IDs, or ports with those names, will be offered for device connection.
+  pub allowed_usb_ids: Vec<(u16, u16)>,
+  /// Port names that will never be offered for device connection. Takes precedence over
+  /// `allowed_ports` and `allowed_usb_ids`.
+  pub denied_ports: Vec<String>,
+  /// Time to wait between port enumerations while scanning.
+  pub rescan_wait_duration: Duration,
+}
+
+impl Default for SerialPortCommunicationManagerConfig {
+  fn default() -> Self {
+    Self {
+      allowed_ports: vec![],
+      allowed_usb_ids: vec![],
+      denied_ports: vec![],
+      rescan_wait_duration: Duration::from_secs(5),
+    }
+  }
+}
+
+impl SerialPortCommunicationManagerConfig {
+  fn port_allowed(&self, port: &SerialPortInfo) -> bool {
+    if self.denied_ports.contains(&port.port_name) {
+      return false;
+    }
+    if self.allowed_ports.is_empty() && self.allowed_usb_ids.is_empty() {
+      return true;
+    }
+    let usb_allowed = match &port.port_type {
+      SerialPortType::UsbPort(usb) => self.allowed_usb_ids.contains(&(usb.vid, usb.pid)),
+      _ => false,
+    };
+    usb_allowed || self.allowed_ports.contains(&port.port_name)
+  }
+}
+
+impl HardwareCommunicationManagerConfig for SerialPortCommunicationManagerConfig {
+  type Builder = SerialPortCommunicationManagerBuilder;
+
+  fn into_builder(self) -> Self::Builder {
+    SerialPortCommunicationManagerBuilder::default().config(self)
+  }
+}
+
 #[derive(Default, Clone)]
-pub struct SerialPortCommunicationManagerBuilder {}
+pub struct SerialPortCommunicationManagerBuilder {
+  config: SerialPortCommunicationManagerConfig,
+}
+
+impl SerialPortCommunicationManagerBuilder {
+  pub fn config(mut self, config: SerialPortCommunicationManagerConfig) -> Self {
+    self.config = config;
+    self
+  }
+
+  /// Offer the port with the given name for device connection. Once any ports or USB IDs are
+  /// allowed, ports that aren't are skipped when scanning.
+  pub fn allowed_port(mut self, port_name: &str) -> Self {
+    self.config.allowed_ports.push(port_name.to_owned());
+    self
+  }
+
+  /// Offer USB serial adapters with the given vendor and product ID for device connection. Once any
+  /// ports or USB IDs are allowed, ports that aren't are skipped when scanning.
+  pub fn allowed_usb_id(mut self, vendor_id: u16, product_id: u16) -> Self {
+    self.config.allowed_usb_ids.push((vendor_id, product_id));
+    self
+  }
+
+  /// Offer every port that has a serial configuration (baud rate, etc) in the device configuration
+  /// for device connection. Once any ports or USB IDs are allowed, ports that aren't are skipped
+  /// when scanning.
+  pub fn configured_ports(mut self, device_config_manager: &DeviceConfigurationManager) -> Self {
+    for specifier in device_config_manager
+      .protocol_device_configurations()
+      .values()
+      .flatten()
+    {
+      if let ProtocolCommunicationSpecifier::Serial(serial) = specifier {
+        if !self.config.allowed_ports.contains(serial.port()) {
+          self.config.allowed_ports.push(serial.port().clone());
+        }
+      }
+    }
+    self
+  }
+}
 
 impl HardwareCommunicationManagerBuilder for SerialPortCommunicationManagerBuilder {
   fn finish(
@@ -31,38 +128,39 @@ impl HardwareCommunicationManagerBuilder for SerialPortCommunicationManagerBuild
     sender: Sender<HardwareCommunicationManagerEvent>,
   ) -> Box<dyn HardwareCommunicationManager> {
     Box::new(TimedRetryCommunicationManager::new(
-      SerialPortCommunicationManager::new(sender),
+      SerialPortCommunicationManager::new(sender, self.config.clone()),
     ))
   }
 }
 
-pub struct SerialPortCommunicationManager {
+pub(crate) struct SerialPortCommunicationManager {
   sender: Sender<HardwareCommunicationManagerEvent>,
+  config: SerialPortCommunicationManagerConfig,
 }
 
 impl SerialPortCommunicationManager {
-  fn new(sender: Sender<HardwareCommunicationManagerEvent>) -> Self {
+  fn new(
+    sender: Sender<HardwareCommunicationManagerEvent>,
+    config: SerialPortCommunicationManagerConfig,
+  ) -> Self {
     trace!("Serial port created.");
-    Self { sender }
-  }
-}
-
-#[async_trait]
-impl TimedRetryCommunicationManagerImpl for SerialPortCommunicationManager {
-  fn name(&self) -> &'static str {
-    "SerialPortCommunicationManager"
-  }
-
-  fn rescan_wait_duration(&self) -> Duration {
-    Duration::from_secs(5)
+    Self { sender, config }
   }
 
-  async fn scan(&self) -> Result<(), ButtplugDeviceError> {
+  /// Offers allowed ports for device connection, or only the port named `address` if given.
+  async fn scan_ports(&self, address: Option<&str>) -> Result<(), ButtplugDeviceError> {
     trace!("Serial port manager scanning for devices.");
     match available_ports() {
       Ok(ports) => {
         debug!("Got {} serial ports back", ports.len());
         for p in ports {
+          if !self.config.port_allowed(&p) {
+            trace!("Skipping filtered serial port {}", p.port_name);
+            continue;
+          }
+          if matches!(address, Some(address) if address != p.port_name) {
+            continue;
+          }
           trace!(
             "Sending serial port {:?} for possible device connection.",
             p
@@ -88,9 +186,172 @@ impl TimedRetryCommunicationManagerImpl for SerialPortCommunicationManager {
     }
     Ok(())
   }
+}
+
+#[async_trait]
+impl TimedRetryCommunicationManagerImpl for SerialPortCommunicationManager {
+  fn name(&self) -> &'static str {
+    "SerialPortCommunicationManager"
+  }
+
+  fn rescan_wait_duration(&self) -> Duration {
+    self.config.rescan_wait_duration
+  }
+
+  fn check_permissions(&self) -> Result<(), HardwarePermissionError> {
+    // Serial ports on linux usually belong to a group (dialout, uucp, etc) the user has to be in.
+    #[cfg(target_os = "linux")]
+    {
+      use crate::server::device::hardware::communication::{
+        device_file_access::inaccessible_device_file,
+        HardwarePermission,
+      };
+      let ports = available_ports().unwrap_or_default();
+      let port_names = ports
+        .iter()
+        .filter(|port| self.config.port_allowed(port))
+        .map(|port| port.port_name.as_str());
+      if let Some(file) = inaccessible_device_file(port_names) {
+        let guidance = match file.group {
+          Some(group) => format!(
+            "Cannot open {}. Add your user to the {} group (i.e. `sudo usermod -aG {} $USER`), then \
+             log out and back in.",
+            file.path, group, group
+          ),
+          None => format!(
+            "Cannot open {}. Add a udev rule giving your user access to the port.",
+            file.path
+          ),
+        };
+        return Err(HardwarePermissionError::new(
+          HardwarePermission::SerialPort,
+          &guidance,
+        ));
+      }
+    }
+    Ok(())
+  }
+
+  async fn scan(&self) -> Result<(), ButtplugDeviceError> {
+    self.scan_ports(None).await
+  }
+
+  async fn scan_for(&self, address: &str) -> Result<(), ButtplugDeviceError> {
+    self.scan_ports(Some(address)).await
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::server::device::configuration::{
+    DeviceConfigurationManagerBuilder,
+    SerialSpecifier,
+    WebsocketSpecifier,
+  };
+  use serialport::UsbPortInfo;
+
+  fn usb_port(port_name: &str, vid: u16, pid: u16) -> SerialPortInfo {
+    SerialPortInfo {
+      port_name: port_name.to_owned(),
+      port_type: SerialPortType::UsbPort(UsbPortInfo {
+        vid,
+        pid,
+        serial_number: None,
+        manufacturer: None,
+        product: None,
+      }),
+    }
+  }
+
+  fn native_port(port_name: &str) -> SerialPortInfo {
+    SerialPortInfo {
+      port_name: port_name.to_owned(),
+      port_type: SerialPortType::Unknown,
+    }
+  }
+
+  fn built_config(
+    builder: SerialPortCommunicationManagerBuilder,
+  ) -> SerialPortCommunicationManagerConfig {
+    builder.config
+  }
+
+  #[test]
+  fn test_serial_port_filter_empty_allows_all() {
+    let config = SerialPortCommunicationManagerConfig::default();
+    assert!(config.port_allowed(&native_port("COM1")));
+    assert!(config.port_allowed(&usb_port("/dev/ttyUSB0", 0x1a86, 0x7523)));
+  }
+
+  #[test]
+  fn test_serial_port_filter_deny_beats_allow() {
+    let config = built_config(
+      SerialPortCommunicationManagerBuilder::default()
+        .allowed_port("COM3")
+        .allowed_usb_id(0x1a86, 0x7523),
+    );
+    let mut config = SerialPortCommunicationManagerConfig {
+      denied_ports: vec!["COM3".to_owned(), "COM4".to_owned()],
+      ..config
+    };
+    assert!(!config.port_allowed(&native_port("COM3")));
+    // Matching USB IDs don't get a denied port through either.
+    assert!(!config.port_allowed(&usb_port("COM4", 0x1a86, 0x7523)));
+    assert!(config.port_allowed(&usb_port("COM5", 0x1a86, 0x7523)));
+    // Denying works without any allow lists too.
+    config.allowed_ports.clear();
+    config.allowed_usb_ids.clear();
+    assert!(!config.port_allowed(&native_port("COM3")));
+    assert!(config.port_allowed(&native_port("COM1")));
+  }
+
+  #[test]
+  fn test_serial_port_filter_usb_ids() {
+    let config = built_config(
+      SerialPortCommunicationManagerBuilder::default()
+        .allowed_usb_id(0x1a86, 0x7523)
+        .allowed_port("/dev/ttyS0"),
+    );
+    assert!(config.port_allowed(&usb_port("/dev/ttyUSB0", 0x1a86, 0x7523)));
+    assert!(!config.port_allowed(&usb_port("/dev/ttyUSB1", 0x0403, 0x6001)));
+    // Ports that aren't USB have no IDs to match, so only their name can allow them.
+    assert!(config.port_allowed(&native_port("/dev/ttyS0")));
+    assert!(!config.port_allowed(&native_port("/dev/ttyS1")));
+  }
 
-  // We should always be able to at least look at serial ports.
-  fn can_scan(&self) -> bool {
-    true
+  #[test]
+  fn test_serial_port_filter_configured_ports() {
+    let dcm = DeviceConfigurationManagerBuilder::default()
+      .communication_specifier(
+        "nobra",
+        ProtocolCommunicationSpecifier::Serial(SerialSpecifier::new_from_name("COM7")),
+      )
+      .communication_specifier(
+        "tcode-v03",
+        ProtocolCommunicationSpecifier::Serial(SerialSpecifier::new_from_name("COM8")),
+      )
+      .communication_specifier(
+        "tcode-v03",
+        ProtocolCommunicationSpecifier::Serial(SerialSpecifier::new_from_name("COM7")),
+      )
+      .communication_specifier(
+        "websocket",
+        ProtocolCommunicationSpecifier::Websocket(WebsocketSpecifier::new(
+          &vec!["COM9".to_owned()],
+        )),
+      )
+      .finish()
+      .expect("Test, assuming infallible.");
+    let mut config = built_config(
+      SerialPortCommunicationManagerBuilder::default()
+        .allowed_port("COM7")
+        .configured_ports(&dcm),
+    );
+    config.allowed_ports.sort();
+    // Each port once, and nothing from specifiers that aren't serial.
+    assert_eq!(config.allowed_ports, vec!["COM7", "COM8"]);
+    assert!(config.port_allowed(&native_port("COM8")));
+    assert!(!config.port_allowed(&native_port("COM9")));
   }
 }
@@ -225,9 +225,7 @@ impl SerialPortHardware {
       .recv()
       .await
       .expect("This will always be a Some value, we're just blocking for bringup")
-      .map_err(|e| {
-        ButtplugDeviceError::DeviceSpecificError(HardwareSpecificError::SerialError(e.to_string()))
-      })?;
+      .map_err(|e| ButtplugDeviceError::from(HardwareSpecificError::SerialError(e.to_string())))?;
     debug!("Serial port received from thread.");
     let (writer_sender, writer_receiver) = mpsc::channel(256);
     let (reader_sender, reader_receiver) = mpsc::channel(256);
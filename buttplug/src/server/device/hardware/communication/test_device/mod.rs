@@ -0,0 +1,69 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Test devices, for integration testing applications without hardware.
+//!
+//! Unlike [virtual devices](super::virtual_device), which act on their own, test devices are driven
+//! step by step by the test. Each device added to the [TestDeviceCommunicationManagerBuilder] comes
+//! with a [TestDeviceChannelHost], which injects notifications, read results, write failures and
+//! disconnections into the device, and hands back every write, subscribe and unsubscribe the server
+//! performs on it, so tests can check the exact bytes a protocol sends. Scans finish as soon as the
+//! queued devices are found, unless the manager is set to
+//! [scan until stopped](TestDeviceCommunicationManagerBuilder::scan_until_stopped), in which case
+//! devices added mid-scan are found as they're added.
+//!
+//! ```no_run
+//! # async fn run() {
+//! use buttplug::{
+//!   core::message::Endpoint,
+//!   server::{
+//!     device::hardware::{
+//!       communication::test_device::{
+//!         TestDeviceCommunicationManagerBuilder,
+//!         TestDeviceIdentifier,
+//!       },
+//!       HardwareCommand,
+//!       HardwareWriteCmd,
+//!     },
+//!     ButtplugServerBuilder,
+//!   },
+//! };
+//!
+//! let mut devices = TestDeviceCommunicationManagerBuilder::default();
+//! let mut device = devices.add_test_device(&TestDeviceIdentifier::new("Massage Demo", None));
+//! let mut server_builder = ButtplugServerBuilder::default();
+//! server_builder.comm_manager(devices);
+//! let server = server_builder.finish().unwrap();
+//! // ... connect a client to the server, scan and send a vibrate command ...
+//! assert_eq!(
+//!   device.next_command().await,
+//!   Some(HardwareCommand::Write(HardwareWriteCmd::new(
+//!     Endpoint::Tx,
+//!     vec![0xF1, 64],
+//!     false
+//!   )))
+//! );
+//! device.notify(Endpoint::Rx, &[0x01]).await.unwrap();
+//! # }
+//! ```
+
+mod test_device_comm_manager;
+mod test_hardware;
+
+pub use test_device_comm_manager::{
+  TestDeviceCommunicationManagerBuilder,
+  TestDeviceIdentifier,
+};
+pub use test_hardware::{
+  new_device_channel,
+  TestDevice,
+  TestDeviceChannelDevice,
+  TestDeviceChannelHost,
+  TestHardwareConnector,
+  TestHardwareEvent,
+  TestHardwareNotification,
+};
@@ -0,0 +1,276 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::test_hardware::{
+  new_device_channel,
+  TestDevice,
+  TestDeviceChannelDevice,
+  TestDeviceChannelHost,
+  TestHardwareConnector,
+};
+use crate::{
+  core::{message::Endpoint, ButtplugResultFuture},
+  server::device::{
+    configuration::{BluetoothLESpecifier, ProtocolCommunicationSpecifier},
+    hardware::communication::{
+      HardwareCommunicationManager,
+      HardwareCommunicationManagerBuilder,
+      HardwareCommunicationManagerEvent,
+      HardwareCommunicationManagerStatus,
+    },
+  },
+  util::async_manager,
+};
+use futures::future::{self, FutureExt};
+use serde::{Deserialize, Serialize};
+use std::{
+  collections::HashMap,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+    Mutex,
+  },
+  time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::{mpsc::Sender, Notify};
+
+fn generate_address() -> String {
+  info!("Generating random address for test device");
+  // Vaguely, not really random number. Works well enough to be an address that
+  // doesn't collide.
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .expect("System time should always be after the unix epoch.")
+    .subsec_nanos()
+    .to_string()
+}
+
+/// Describes a test device to be found on scanning.
+///
+/// Test devices advertise as bluetooth LE devices called `name`, so they're matched to protocols
+/// through the bluetooth names in the device configuration (including user configurations, for
+/// protocols or endpoints that don't exist in the built in configuration). The endpoints the
+/// matched configuration lists are added to the device, along with any given here.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TestDeviceIdentifier {
+  name: String,
+  #[serde(default = "generate_address")]
+  address: String,
+  #[serde(default)]
+  endpoints: Vec<Endpoint>,
+}
+
+impl TestDeviceIdentifier {
+  /// Creates an identifier for a device called `name`. If no address is given, a unique one is
+  /// made up.
+  pub fn new(name: &str, address: Option<String>) -> Self {
+    let address = address.unwrap_or_else(generate_address);
+    Self {
+      name: name.to_owned(),
+      address,
+      endpoints: vec![],
+    }
+  }
+
+  /// Adds endpoints to the device on top of the ones from its device configuration.
+  pub fn endpoints(mut self, endpoints: &[Endpoint]) -> Self {
+    self.endpoints.extend_from_slice(endpoints);
+    self
+  }
+}
+
+/// Devices that will be found on the next scan.
+type TestDeviceQueue = Arc<Mutex<Vec<(TestDeviceIdentifier, TestDeviceChannelDevice)>>>;
+
+/// Clones share the same device queue, so a clone kept by a test can add devices (or add them back
+/// after they disconnect) once the manager is running.
+#[derive(Default, Clone)]
+pub struct TestDeviceCommunicationManagerBuilder {
+  devices: TestDeviceQueue,
+  /// Woken when a device is queued, so scans that run until stopped can find it.
+  device_added: Arc<Notify>,
+  scan_until_stopped: bool,
+}
+
+impl TestDeviceCommunicationManagerBuilder {
+  /// Queues a device to be found on the next scan, returning the channel used to drive it and watch
+  /// what the server does with it. If a scan that runs until stopped is going, the device is found
+  /// right away.
+  pub fn add_test_device(&mut self, device: &TestDeviceIdentifier) -> TestDeviceChannelHost {
+    let (host_channel, device_channel) = new_device_channel();
+    self
+      .devices
+      .lock()
+      .expect("Test device queue lock should never be poisoned.")
+      .push((device.clone(), device_channel));
+    self.device_added.notify_one();
+    host_channel
+  }
+
+  /// If true, scans keep going until they're stopped, finding devices as they're added, like a
+  /// bluetooth scan picking up devices as they're turned on. Otherwise (the default) scans finish as
+  /// soon as the queued devices have been found.
+  pub fn scan_until_stopped(&mut self, scan_until_stopped: bool) -> &mut Self {
+    self.scan_until_stopped = scan_until_stopped;
+    self
+  }
+}
+
+impl HardwareCommunicationManagerBuilder for TestDeviceCommunicationManagerBuilder {
+  fn finish(
+    &mut self,
+    sender: Sender<HardwareCommunicationManagerEvent>,
+  ) -> Box<dyn HardwareCommunicationManager> {
+    Box::new(TestDeviceCommunicationManager::new(sender, self.clone()))
+  }
+}
+
+fn new_uninitialized_ble_test_device(
+  identifier: &TestDeviceIdentifier,
+  device_channel: TestDeviceChannelDevice,
+) -> TestHardwareConnector {
+  let address = identifier.address.clone();
+  let specifier = ProtocolCommunicationSpecifier::BluetoothLE(
+    BluetoothLESpecifier::new_from_device(&identifier.name, &HashMap::new(), &[]),
+  );
+  let mut hardware = TestDevice::new(&identifier.name, &address, device_channel);
+  for endpoint in &identifier.endpoints {
+    hardware.add_endpoint(endpoint);
+  }
+  TestHardwareConnector::new(specifier, hardware)
+}
+
+/// Takes everything off the queue, as found device events.
+fn take_found_devices(devices: &TestDeviceQueue) -> Vec<HardwareCommunicationManagerEvent> {
+  let mut devices = devices
+    .lock()
+    .expect("Test device queue lock should never be poisoned.");
+  let mut events = vec![];
+  while let Some((device, test_channel)) = devices.pop() {
+    let device_creator = new_uninitialized_ble_test_device(&device, test_channel);
+
+    events.push(HardwareCommunicationManagerEvent::DeviceFound {
+      name: device.name.clone(),
+      address: device.address,
+      creator: Box::new(device_creator),
+    });
+  }
+  events
+}
+
+pub(crate) struct TestDeviceCommunicationManager {
+  device_sender: Sender<HardwareCommunicationManagerEvent>,
+  devices: TestDeviceQueue,
+  device_added: Arc<Notify>,
+  scan_until_stopped: bool,
+  stop_scan: Arc<Notify>,
+  is_scanning: Arc<AtomicBool>,
+}
+
+impl TestDeviceCommunicationManager {
+  fn new(
+    device_sender: Sender<HardwareCommunicationManagerEvent>,
+    builder: TestDeviceCommunicationManagerBuilder,
+  ) -> Self {
+    Self {
+      device_sender,
+      devices: builder.devices,
+      device_added: builder.device_added,
+      scan_until_stopped: builder.scan_until_stopped,
+      stop_scan: Arc::new(Notify::new()),
+      is_scanning: Arc::new(AtomicBool::new(false)),
+    }
+  }
+
+  /// Finds whatever is queued, then keeps finding devices as they're added until the scan is
+  /// stopped.
+  fn scan_until_stopped(&self) -> ButtplugResultFuture {
+    if self.is_scanning.swap(true, Ordering::SeqCst) {
+      return future::ready(Ok(())).boxed();
+    }
+    let devices = self.devices.clone();
+    let device_added = self.device_added.clone();
+    let stop_scan = self.stop_scan.clone();
+    let device_sender = self.device_sender.clone();
+    let is_scanning = self.is_scanning.clone();
+    async_manager::spawn(async move {
+      loop {
+        for event in take_found_devices(&devices) {
+          if device_sender.send(event).await.is_err() {
+            error!("Device channel no longer open.");
+          }
+        }
+        // Devices added just before the scan was stopped are still found.
+        tokio::select! {
+          biased;
+          _ = device_added.notified() => continue,
+          _ = stop_scan.notified() => break,
+        }
+      }
+      is_scanning.store(false, Ordering::SeqCst);
+      if device_sender
+        .send(HardwareCommunicationManagerEvent::ScanningFinished)
+        .await
+        .is_err()
+      {
+        error!("Error sending scanning finished. Scanning may not register as finished now!");
+      }
+    });
+    future::ready(Ok(())).boxed()
+  }
+}
+
+impl HardwareCommunicationManager for TestDeviceCommunicationManager {
+  fn name(&self) -> &'static str {
+    "TestDeviceCommunicationManager"
+  }
+
+  fn start_scanning(&mut self) -> ButtplugResultFuture {
+    if self.scan_until_stopped {
+      return self.scan_until_stopped();
+    }
+    let events = take_found_devices(&self.devices);
+    if events.is_empty() {
+      warn!("No devices for test device comm manager to emit, did you mean to do this?");
+    }
+    let device_sender = self.device_sender.clone();
+    let is_scanning = self.is_scanning.clone();
+    async move {
+      is_scanning.store(true, Ordering::SeqCst);
+      for event in events {
+        if device_sender.send(event).await.is_err() {
+          error!("Device channel no longer open.");
+        }
+      }
+      is_scanning.store(false, Ordering::SeqCst);
+      if device_sender
+        .send(HardwareCommunicationManagerEvent::ScanningFinished)
+        .await
+        .is_err()
+      {
+        error!("Error sending scanning finished. Scanning may not register as finished now!");
+      }
+      Ok(())
+    }
+    .boxed()
+  }
+
+  fn stop_scanning(&mut self) -> ButtplugResultFuture {
+    if self.is_scanning.load(Ordering::SeqCst) {
+      self.stop_scan.notify_one();
+    }
+    future::ready(Ok(())).boxed()
+  }
+
+  // Test devices are always available, since there's no hardware behind them.
+  fn status(&self) -> HardwareCommunicationManagerStatus {
+    HardwareCommunicationManagerStatus::from_availability(
+      true,
+      self.is_scanning.load(Ordering::SeqCst),
+    )
+  }
+}
@@ -5,7 +5,7 @@
 // Licensed under the BSD 3-Clause license. See LICENSE file in the project root
 // for full license information.
 
-use buttplug::{
+use crate::{
   core::{errors::ButtplugDeviceError, message::Endpoint},
   server::device::{
     configuration::ProtocolCommunicationSpecifier,
@@ -23,9 +23,8 @@ use buttplug::{
       HardwareWriteCmd,
     },
   },
-  util::async_manager,
+  util::{async_manager, sleep, stream::recv_now},
 };
-
 use async_trait::async_trait;
 use dashmap::DashSet;
 use futures::future::{self, BoxFuture, FutureExt};
@@ -33,22 +32,46 @@ use serde::{Deserialize, Serialize};
 use std::{
   collections::{HashSet, VecDeque},
   fmt::{self, Debug},
-  sync::Arc,
+  sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc,
+  },
+  time::Duration,
 };
 use tokio::sync::{broadcast, mpsc, Mutex};
 
+/// How many times a read waits for a value to be queued before giving up.
+const READ_RETRIES: u32 = 5;
+const READ_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Data a test device hands back on an endpoint, either as a notification or as the result of a
+/// read.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TestHardwareNotification {
   endpoint: Endpoint,
   data: Vec<u8>,
 }
 
+impl TestHardwareNotification {
+  pub fn new(endpoint: Endpoint, data: &[u8]) -> Self {
+    Self {
+      endpoint,
+      data: data.to_vec(),
+    }
+  }
+}
+
+/// Things a test can make a test device do, sent over [TestDeviceChannelHost::sender].
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum TestHardwareEvent {
   // Values to be emitted from subscriptions
   Notifications(Vec<TestHardwareNotification>),
   // Values to be emitted when calls to ReadValue happen
   Reads(Vec<TestHardwareNotification>),
+  // Number of upcoming writes that should fail
+  FailWrites(u32),
+  // Signal strength to report from RSSI reads, in dBm
+  Rssi(i16),
   Disconnect,
 }
 
@@ -58,7 +81,6 @@ pub struct TestHardwareConnector {
 }
 
 impl TestHardwareConnector {
-  #[allow(dead_code)]
   pub fn new(specifier: ProtocolCommunicationSpecifier, hardware: TestDevice) -> Self {
     Self {
       specifier,
@@ -82,9 +104,10 @@ impl HardwareConnector for TestHardwareConnector {
   }
 
   async fn connect(&mut self) -> Result<Box<dyn HardwareSpecializer>, ButtplugDeviceError> {
-    Ok(Box::new(TestHardwareSpecializer::new(
-      self.hardware.take().expect("Test"),
-    )))
+    let hardware = self.hardware.take().ok_or_else(|| {
+      ButtplugDeviceError::DeviceConnectionError("Test device already connected".to_owned())
+    })?;
+    Ok(Box::new(TestHardwareSpecializer::new(hardware)))
   }
 }
 
@@ -106,8 +129,9 @@ impl HardwareSpecializer for TestHardwareSpecializer {
     &mut self,
     specifiers: &[ProtocolCommunicationSpecifier],
   ) -> Result<Hardware, ButtplugDeviceError> {
-    let mut device = self.hardware.take().expect("Test");
-    let mut endpoints = vec![];
+    let mut device = self.hardware.take().ok_or_else(|| {
+      ButtplugDeviceError::DeviceConnectionError("Test device already specialized".to_owned())
+    })?;
     if let Some(ProtocolCommunicationSpecifier::BluetoothLE(btle)) = specifiers
       .iter()
       .find(|x| matches!(x, ProtocolCommunicationSpecifier::BluetoothLE(_)))
@@ -115,10 +139,10 @@ impl HardwareSpecializer for TestHardwareSpecializer {
       for endpoint_map in btle.services().values() {
         for endpoint in endpoint_map.keys() {
           device.add_endpoint(endpoint);
-          endpoints.push(*endpoint);
         }
       }
     }
+    let endpoints: Vec<Endpoint> = device.endpoints.iter().copied().collect();
     let hardware = Hardware::new(
       &device.name(),
       &device.address(),
@@ -129,11 +153,60 @@ impl HardwareSpecializer for TestHardwareSpecializer {
   }
 }
 
+/// The test's end of a test device: events sent here are acted out by the device, and every
+/// command the server has the device perform comes back out.
 pub struct TestDeviceChannelHost {
   pub sender: mpsc::Sender<TestHardwareEvent>,
   pub receiver: mpsc::Receiver<HardwareCommand>,
 }
 
+impl TestDeviceChannelHost {
+  async fn send_event(&self, event: TestHardwareEvent) -> Result<(), ButtplugDeviceError> {
+    self.sender.send(event).await.map_err(|_| {
+      ButtplugDeviceError::DeviceNotConnected("Test device no longer exists".to_owned())
+    })
+  }
+
+  /// Emits `data` from `endpoint`, if the server is subscribed to it.
+  pub async fn notify(&self, endpoint: Endpoint, data: &[u8]) -> Result<(), ButtplugDeviceError> {
+    self
+      .send_event(TestHardwareEvent::Notifications(vec![
+        TestHardwareNotification::new(endpoint, data),
+      ]))
+      .await
+  }
+
+  /// Queues `data` as the result of the next read of `endpoint`.
+  pub async fn queue_read(
+    &self,
+    endpoint: Endpoint,
+    data: &[u8],
+  ) -> Result<(), ButtplugDeviceError> {
+    self
+      .send_event(TestHardwareEvent::Reads(vec![TestHardwareNotification::new(
+        endpoint, data,
+      )]))
+      .await
+  }
+
+  /// Makes the device act as if it lost its connection.
+  pub async fn disconnect(&self) -> Result<(), ButtplugDeviceError> {
+    self.send_event(TestHardwareEvent::Disconnect).await
+  }
+
+  /// Waits for the next write, subscribe or unsubscribe the server performs on the device. Returns
+  /// None once the device is gone.
+  pub async fn next_command(&mut self) -> Option<HardwareCommand> {
+    self.receiver.recv().await
+  }
+
+  /// Returns the next command the server performed on the device, if it's already happened.
+  pub fn try_next_command(&mut self) -> Option<HardwareCommand> {
+    recv_now(&mut self.receiver).flatten()
+  }
+}
+
+/// The device's end of a test device, handed to [TestDevice::new].
 pub struct TestDeviceChannelDevice {
   pub sender: mpsc::Sender<HardwareCommand>,
   pub receiver: mpsc::Receiver<TestHardwareEvent>,
@@ -154,6 +227,7 @@ pub fn new_device_channel() -> (TestDeviceChannelHost, TestDeviceChannelDevice)
   )
 }
 
+/// Fake hardware, controlled through a [TestDeviceChannelHost].
 pub struct TestDevice {
   name: String,
   address: String,
@@ -162,10 +236,11 @@ pub struct TestDevice {
   event_sender: broadcast::Sender<HardwareEvent>,
   subscribed_endpoints: Arc<DashSet<Endpoint>>,
   read_data: Arc<Mutex<VecDeque<HardwareReading>>>,
+  failing_writes: Arc<AtomicU32>,
+  rssi: Arc<Mutex<Option<i16>>>,
 }
 
 impl TestDevice {
-  #[allow(dead_code)]
   pub fn new(name: &str, address: &str, test_device_channel: TestDeviceChannelDevice) -> Self {
     let (event_sender, _) = broadcast::channel(256);
 
@@ -176,27 +251,34 @@ impl TestDevice {
     let subscribed_endpoints_clone = subscribed_endpoints.clone();
     let read_data = Arc::new(Mutex::new(VecDeque::new()));
     let read_data_clone = read_data.clone();
+    let failing_writes = Arc::new(AtomicU32::new(0));
+    let failing_writes_clone = failing_writes.clone();
+    let rssi = Arc::new(Mutex::new(None));
+    let rssi_clone = rssi.clone();
     async_manager::spawn(async move {
       while let Some(event) = receiver.recv().await {
         match event {
           TestHardwareEvent::Disconnect => {
-            event_sender_clone
-              .send(HardwareEvent::Disconnected(address_clone.clone()))
-              .expect("Test");
+            // Nothing may be listening yet, which is fine.
+            let _ = event_sender_clone.send(HardwareEvent::Disconnected(address_clone.clone()));
           }
           TestHardwareEvent::Notifications(notifications) => {
             for notification in notifications {
               if subscribed_endpoints_clone.contains(&notification.endpoint) {
-                event_sender_clone
-                  .send(HardwareEvent::Notification(
-                    address_clone.clone(),
-                    notification.endpoint,
-                    notification.data.clone(),
-                  ))
-                  .expect("Test");
+                let _ = event_sender_clone.send(HardwareEvent::Notification(
+                  address_clone.clone(),
+                  notification.endpoint,
+                  notification.data.clone(),
+                ));
               }
             }
           }
+          TestHardwareEvent::FailWrites(count) => {
+            failing_writes_clone.store(count, Ordering::SeqCst);
+          }
+          TestHardwareEvent::Rssi(level) => {
+            *rssi_clone.lock().await = Some(level);
+          }
           TestHardwareEvent::Reads(events) => {
             let mut guard = read_data_clone.lock().await;
             for read in events {
@@ -215,9 +297,12 @@ impl TestDevice {
       event_sender,
       subscribed_endpoints,
       read_data,
+      failing_writes,
+      rssi,
     }
   }
 
+  /// Gives the device an endpoint, on top of the ones its device configuration lists.
   pub fn add_endpoint(&mut self, endpoint: &Endpoint) {
     self.endpoints.insert(*endpoint);
   }
@@ -236,7 +321,8 @@ impl TestDevice {
   ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
     let sender = self.test_device_channel.clone();
     async move {
-      sender.send(data_command).await.expect("Test");
+      // If the test dropped its end of the channel, it's not watching commands anymore.
+      let _ = sender.send(data_command).await;
       Ok(())
     }
     .boxed()
@@ -252,9 +338,7 @@ impl HardwareInternal for TestDevice {
     let sender = self.event_sender.clone();
     let address = self.address.clone();
     async move {
-      sender
-        .send(HardwareEvent::Disconnected(address))
-        .expect("Test");
+      let _ = sender.send(HardwareEvent::Disconnected(address));
       Ok(())
     }
     .boxed()
@@ -267,20 +351,20 @@ impl HardwareInternal for TestDevice {
     let reads = self.read_data.clone();
     let msg = *msg;
     async move {
-      let mut count = 0;
-      loop {
-        if count == 5 {
-          panic!("Not getting expected read in time!");
-        }
-        {
-          if reads.lock().await.len() > 0 {
-            break;
-          }
+      let mut read_msg = None;
+      for _ in 0..READ_RETRIES {
+        read_msg = reads.lock().await.pop_back();
+        if read_msg.is_some() {
+          break;
         }
-        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
-        count += 1;
+        sleep(READ_RETRY_INTERVAL).await;
       }
-      let read_msg = reads.lock().await.pop_back().unwrap();
+      let read_msg = read_msg.ok_or_else(|| {
+        ButtplugDeviceError::DeviceCommunicationError(format!(
+          "No read queued for test device on endpoint {}",
+          msg.endpoint()
+        ))
+      })?;
       if *read_msg.endpoint() != msg.endpoint() {
         Err(ButtplugDeviceError::DeviceCommunicationError(format!(
           "Read endpoint {} while expecting endpoint {}",
@@ -294,6 +378,16 @@ impl HardwareInternal for TestDevice {
     .boxed()
   }
 
+  fn read_rssi(&self) -> BoxFuture<'static, Result<i16, ButtplugDeviceError>> {
+    let rssi = self.rssi.clone();
+    async move {
+      rssi.lock().await.ok_or_else(|| {
+        ButtplugDeviceError::DeviceCommunicationError("Test device has no RSSI set".to_owned())
+      })
+    }
+    .boxed()
+  }
+
   fn write_value(
     &self,
     msg: &HardwareWriteCmd,
@@ -301,6 +395,18 @@ impl HardwareInternal for TestDevice {
     if !self.endpoints.contains(&msg.endpoint()) {
       return future::ready(Err(ButtplugDeviceError::InvalidEndpoint(msg.endpoint()))).boxed();
     }
+    if self
+      .failing_writes
+      .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+        count.checked_sub(1)
+      })
+      .is_ok()
+    {
+      return future::ready(Err(ButtplugDeviceError::DeviceCommunicationError(
+        "Test write failure".to_owned(),
+      )))
+      .boxed();
+    }
     self.send_command(msg.clone().into())
   }
 
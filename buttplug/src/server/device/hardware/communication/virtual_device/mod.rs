@@ -0,0 +1,56 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Virtual devices, for developing client applications without hardware.
+//!
+//! The virtual device manager emits a configurable set of fake devices that go through the same
+//! protocol and configuration system as real hardware. Writes are accepted and logged, battery reads
+//! return a slowly draining level, and pressure sensors produce a synthetic wave while subscribed.
+//! Devices can also be given [VirtualDeviceFaults] (command latency, dropped notifications,
+//! malformed responses, mid-session disconnects) for testing how servers and applications deal with
+//! unreliable hardware.
+//!
+//! Paired with a [ButtplugRemoteServer](crate::server::ButtplugRemoteServer), this gives client
+//! developers a server to connect to over websockets without any toys on hand:
+//!
+//! ```no_run
+//! # #[cfg(all(feature = "websockets", feature = "serialize-json"))]
+//! # async fn run() {
+//! use buttplug::{
+//!   core::connector::new_json_ws_server_connector,
+//!   server::{
+//!     device::hardware::communication::virtual_device::{
+//!       VirtualDeviceCommunicationManagerBuilder,
+//!       VirtualDeviceType,
+//!     },
+//!     ButtplugRemoteServer,
+//!     ButtplugServerBuilder,
+//!   },
+//! };
+//!
+//! let mut devices = VirtualDeviceCommunicationManagerBuilder::default();
+//! devices
+//!   .add_devices(VirtualDeviceType::Vibrator, 2)
+//!   .add_device(VirtualDeviceType::Stroker)
+//!   .add_device(VirtualDeviceType::PressureSensor);
+//! let mut server_builder = ButtplugServerBuilder::default();
+//! server_builder.comm_manager(devices);
+//! let server = ButtplugRemoteServer::new(server_builder.finish().unwrap());
+//! server
+//!   .start(new_json_ws_server_connector(12345, false))
+//!   .await
+//!   .unwrap();
+//! # }
+//! ```
+
+mod virtual_device_comm_manager;
+mod virtual_faults;
+mod virtual_hardware;
+
+pub use virtual_device_comm_manager::VirtualDeviceCommunicationManagerBuilder;
+pub use virtual_faults::VirtualDeviceFaults;
+pub use virtual_hardware::VirtualDeviceType;
@@ -0,0 +1,132 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::{
+  virtual_faults::VirtualDeviceFaults,
+  virtual_hardware::{VirtualDeviceType, VirtualHardwareConnector},
+};
+use crate::{
+  core::ButtplugResultFuture,
+  server::device::hardware::communication::{
+    HardwareCommunicationManager,
+    HardwareCommunicationManagerBuilder,
+    HardwareCommunicationManagerEvent,
+    HardwareCommunicationManagerStatus,
+  },
+};
+use futures::future::{self, FutureExt};
+use tokio::sync::mpsc::Sender;
+
+#[derive(Default, Clone)]
+pub struct VirtualDeviceCommunicationManagerBuilder {
+  devices: Vec<(VirtualDeviceType, VirtualDeviceFaults)>,
+}
+
+impl VirtualDeviceCommunicationManagerBuilder {
+  pub fn add_device(&mut self, device_type: VirtualDeviceType) -> &mut Self {
+    self.add_device_with_faults(device_type, VirtualDeviceFaults::default())
+  }
+
+  pub fn add_devices(&mut self, device_type: VirtualDeviceType, count: usize) -> &mut Self {
+    for _ in 0..count {
+      self.add_device(device_type);
+    }
+    self
+  }
+
+  /// Adds a device that misbehaves as described by `faults`.
+  pub fn add_device_with_faults(
+    &mut self,
+    device_type: VirtualDeviceType,
+    faults: VirtualDeviceFaults,
+  ) -> &mut Self {
+    self.devices.push((device_type, faults));
+    self
+  }
+}
+
+impl HardwareCommunicationManagerBuilder for VirtualDeviceCommunicationManagerBuilder {
+  fn finish(
+    &mut self,
+    sender: Sender<HardwareCommunicationManagerEvent>,
+  ) -> Box<dyn HardwareCommunicationManager> {
+    Box::new(VirtualDeviceCommunicationManager::new(
+      sender,
+      self.devices.clone(),
+    ))
+  }
+}
+
+/// Emits the configured virtual devices on the first scan. Since device addresses are stable,
+/// later scans will only re-announce devices that have been disconnected.
+pub(crate) struct VirtualDeviceCommunicationManager {
+  sender: Sender<HardwareCommunicationManagerEvent>,
+  devices: Vec<(VirtualDeviceType, VirtualDeviceFaults)>,
+}
+
+impl VirtualDeviceCommunicationManager {
+  fn new(
+    sender: Sender<HardwareCommunicationManagerEvent>,
+    devices: Vec<(VirtualDeviceType, VirtualDeviceFaults)>,
+  ) -> Self {
+    Self { sender, devices }
+  }
+}
+
+impl HardwareCommunicationManager for VirtualDeviceCommunicationManager {
+  fn name(&self) -> &'static str {
+    "VirtualDeviceCommunicationManager"
+  }
+
+  fn start_scanning(&mut self) -> ButtplugResultFuture {
+    let sender = self.sender.clone();
+    let devices = self.devices.clone();
+    async move {
+      for (index, (device_type, faults)) in devices.into_iter().enumerate() {
+        let address = format!("virtual-{}", index);
+        debug!(
+          "Virtual device manager emitting {} at {}",
+          device_type, address
+        );
+        if sender
+          .send(HardwareCommunicationManagerEvent::DeviceFound {
+            name: device_type.advertised_name().to_owned(),
+            address: address.clone(),
+            creator: Box::new(VirtualHardwareConnector::new(
+              device_type,
+              faults,
+              index as u64,
+              &address,
+            )),
+          })
+          .await
+          .is_err()
+        {
+          error!("Device manager disappeared, exiting virtual device scan.");
+          return Ok(());
+        }
+      }
+      if sender
+        .send(HardwareCommunicationManagerEvent::ScanningFinished)
+        .await
+        .is_err()
+      {
+        error!("Error sending scanning finished. Scanning may not register as finished now!");
+      }
+      Ok(())
+    }
+    .boxed()
+  }
+
+  fn stop_scanning(&mut self) -> ButtplugResultFuture {
+    future::ready(Ok(())).boxed()
+  }
+
+  fn status(&self) -> HardwareCommunicationManagerStatus {
+    HardwareCommunicationManagerStatus::Ready
+  }
+}
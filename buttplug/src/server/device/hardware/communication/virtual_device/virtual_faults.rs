@@ -0,0 +1,143 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use serde::{Deserialize, Serialize};
+use std::{
+  sync::Mutex,
+  time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Faults to inject into a virtual device, for testing how the server and client applications cope
+/// with misbehaving hardware. The default injects nothing.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VirtualDeviceFaults {
+  /// Shortest delay added to every hardware command.
+  pub min_latency: Duration,
+  /// Longest delay added to every hardware command. Each command waits a random time between the
+  /// minimum and this.
+  pub max_latency: Duration,
+  /// Chance (0.0-1.0) that a notification is silently dropped.
+  pub notification_drop_rate: f64,
+  /// Chance (0.0-1.0) that a read or notification comes back truncated and full of garbage.
+  pub malformed_response_rate: f64,
+  /// Disconnect the device this long after it connects.
+  pub disconnect_after: Option<Duration>,
+  /// Seed for fault decisions, so runs can be reproduced. Picked from the clock if not set.
+  pub seed: Option<u64>,
+}
+
+/// Makes the random decisions for a [VirtualDeviceFaults] configuration.
+pub(super) struct FaultInjector {
+  faults: VirtualDeviceFaults,
+  // xorshift64* state. Good enough for picking faults, and saves us a dependency.
+  state: Mutex<u64>,
+}
+
+impl FaultInjector {
+  /// `stream` separates devices that share a configured seed, so they don't fail in lockstep.
+  pub fn new(faults: VirtualDeviceFaults, stream: u64) -> Self {
+    let seed = faults.seed.unwrap_or_else(|| {
+      SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_nanos() as u64)
+        .unwrap_or_default()
+    });
+    // Zero is a fixed point for xorshift, so make sure we never start there.
+    let state = (seed ^ stream.wrapping_mul(0x9E37_79B9_7F4A_7C15)) | 1;
+    Self {
+      faults,
+      state: Mutex::new(state),
+    }
+  }
+
+  pub fn disconnect_after(&self) -> Option<Duration> {
+    self.faults.disconnect_after
+  }
+
+  fn next_u64(&self) -> u64 {
+    let mut state = self
+      .state
+      .lock()
+      .expect("Fault state lock should never be poisoned.");
+    *state ^= *state >> 12;
+    *state ^= *state << 25;
+    *state ^= *state >> 27;
+    state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+  }
+
+  fn next_f64(&self) -> f64 {
+    (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+  }
+
+  fn roll(&self, rate: f64) -> bool {
+    rate > 0.0 && self.next_f64() < rate
+  }
+
+  pub fn latency(&self) -> Duration {
+    let min = self.faults.min_latency;
+    let max = self.faults.max_latency;
+    if max <= min {
+      return min;
+    }
+    min + (max - min).mul_f64(self.next_f64())
+  }
+
+  pub fn drop_notification(&self) -> bool {
+    self.roll(self.faults.notification_drop_rate)
+  }
+
+  /// Returns the data untouched, or a shorter run of random bytes if this response is picked to
+  /// be malformed.
+  pub fn maybe_malform(&self, data: Vec<u8>) -> Vec<u8> {
+    if !self.roll(self.faults.malformed_response_rate) {
+      return data;
+    }
+    let len = if data.is_empty() {
+      0
+    } else {
+      (self.next_u64() % data.len() as u64) as usize
+    };
+    (0..len).map(|_| self.next_u64() as u8).collect()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_default_faults_inject_nothing() {
+    let injector = FaultInjector::new(VirtualDeviceFaults::default(), 0);
+    for _ in 0..100 {
+      assert_eq!(injector.latency(), Duration::ZERO);
+      assert!(!injector.drop_notification());
+      assert_eq!(injector.maybe_malform(vec![1, 2, 3]), vec![1, 2, 3]);
+    }
+  }
+
+  #[test]
+  fn test_faults_are_reproducible_and_bounded() {
+    let faults = VirtualDeviceFaults {
+      min_latency: Duration::from_millis(10),
+      max_latency: Duration::from_millis(20),
+      malformed_response_rate: 1.0,
+      seed: Some(1234),
+      ..Default::default()
+    };
+    let first = FaultInjector::new(faults.clone(), 1);
+    let second = FaultInjector::new(faults, 1);
+    for _ in 0..100 {
+      let latency = first.latency();
+      assert_eq!(latency, second.latency());
+      assert!(latency >= Duration::from_millis(10) && latency <= Duration::from_millis(20));
+      let malformed = first.maybe_malform(vec![0; 7]);
+      assert_eq!(malformed, second.maybe_malform(vec![0; 7]));
+      assert!(malformed.len() < 7);
+    }
+  }
+}
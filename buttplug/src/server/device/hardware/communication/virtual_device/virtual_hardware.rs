@@ -0,0 +1,365 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::virtual_faults::{FaultInjector, VirtualDeviceFaults};
+use crate::{
+  core::{errors::ButtplugDeviceError, message::Endpoint},
+  server::device::{
+    configuration::{BluetoothLESpecifier, ProtocolCommunicationSpecifier},
+    hardware::{
+      Hardware,
+      HardwareConnector,
+      HardwareEvent,
+      HardwareInternal,
+      HardwareReadCmd,
+      HardwareReading,
+      HardwareSpecializer,
+      HardwareSubscribeCmd,
+      HardwareUnsubscribeCmd,
+      HardwareWriteCmd,
+    },
+  },
+  util::{async_manager, sleep},
+};
+use async_trait::async_trait;
+use dashmap::DashSet;
+use futures::future::{self, BoxFuture, FutureExt};
+use serde::{Deserialize, Serialize};
+use std::{
+  collections::{HashMap, HashSet},
+  f64::consts::PI,
+  fmt::{self, Debug},
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
+  time::{Duration, Instant},
+};
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+
+// How often pressure sensors emit a reading while subscribed.
+const PRESSURE_NOTIFICATION_INTERVAL: Duration = Duration::from_millis(100);
+// Length of one full synthetic pressure wave.
+const PRESSURE_WAVE_PERIOD: Duration = Duration::from_secs(4);
+// Virtual batteries lose 1% per this duration of connection time.
+const BATTERY_DRAIN_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Kinds of devices the virtual device manager can emulate.
+///
+/// Each kind is backed by a real device definition from the device configuration, so the messages
+/// and attributes a client sees are the same as they would be for the physical toy.
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum VirtualDeviceType {
+  /// Single vibrator with a battery (MagicMotion Flamingo)
+  Vibrator,
+  /// Linear stroker (Vorze Piston)
+  Stroker,
+  /// Rotating device (Vorze UFO SA)
+  Rotator,
+  /// Pressure sensor with a battery (KGoal Boost)
+  PressureSensor,
+}
+
+impl VirtualDeviceType {
+  /// Bluetooth name the device advertises, which is what the device configuration matches on.
+  pub fn advertised_name(&self) -> &'static str {
+    match self {
+      VirtualDeviceType::Vibrator => "Flamingo",
+      VirtualDeviceType::Stroker => "VorzePiston",
+      VirtualDeviceType::Rotator => "UFOSA",
+      VirtualDeviceType::PressureSensor => "Boost",
+    }
+  }
+}
+
+pub(super) struct VirtualHardwareConnector {
+  specifier: ProtocolCommunicationSpecifier,
+  name: String,
+  address: String,
+  faults: VirtualDeviceFaults,
+  index: u64,
+}
+
+impl VirtualHardwareConnector {
+  pub fn new(
+    device_type: VirtualDeviceType,
+    faults: VirtualDeviceFaults,
+    index: u64,
+    address: &str,
+  ) -> Self {
+    let name = device_type.advertised_name();
+    Self {
+      specifier: ProtocolCommunicationSpecifier::BluetoothLE(
+        BluetoothLESpecifier::new_from_device(name, &HashMap::new(), &[]),
+      ),
+      name: name.to_owned(),
+      address: address.to_owned(),
+      faults,
+      index,
+    }
+  }
+}
+
+impl Debug for VirtualHardwareConnector {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("VirtualHardwareConnector")
+      .field("name", &self.name)
+      .field("address", &self.address)
+      .field("faults", &self.faults)
+      .finish()
+  }
+}
+
+#[async_trait]
+impl HardwareConnector for VirtualHardwareConnector {
+  fn specifier(&self) -> ProtocolCommunicationSpecifier {
+    self.specifier.clone()
+  }
+
+  async fn connect(&mut self) -> Result<Box<dyn HardwareSpecializer>, ButtplugDeviceError> {
+    Ok(Box::new(VirtualHardwareSpecializer {
+      name: self.name.clone(),
+      address: self.address.clone(),
+      faults: self.faults.clone(),
+      index: self.index,
+    }))
+  }
+}
+
+struct VirtualHardwareSpecializer {
+  name: String,
+  address: String,
+  faults: VirtualDeviceFaults,
+  index: u64,
+}
+
+#[async_trait]
+impl HardwareSpecializer for VirtualHardwareSpecializer {
+  async fn specialize(
+    &mut self,
+    specifiers: &[ProtocolCommunicationSpecifier],
+  ) -> Result<Hardware, ButtplugDeviceError> {
+    // Expose whatever endpoints the matched BLE definition expects, same as a real device that
+    // had all of the services we went looking for.
+    let mut endpoints = vec![];
+    if let Some(ProtocolCommunicationSpecifier::BluetoothLE(btle)) = specifiers
+      .iter()
+      .find(|x| matches!(x, ProtocolCommunicationSpecifier::BluetoothLE(_)))
+    {
+      for endpoint_map in btle.services().values() {
+        endpoints.extend(endpoint_map.keys().copied());
+      }
+    }
+    let faults = FaultInjector::new(self.faults.clone(), self.index);
+    let device = VirtualHardware::new(&self.address, &endpoints, faults);
+    Ok(Hardware::new(
+      &self.name,
+      &self.address,
+      &endpoints,
+      Box::new(device),
+    ))
+  }
+}
+
+struct VirtualHardware {
+  address: String,
+  endpoints: HashSet<Endpoint>,
+  event_sender: broadcast::Sender<HardwareEvent>,
+  subscribed_endpoints: Arc<DashSet<Endpoint>>,
+  connected_at: Instant,
+  connected: Arc<AtomicBool>,
+  faults: Arc<FaultInjector>,
+  cancellation_token: CancellationToken,
+}
+
+impl VirtualHardware {
+  fn new(address: &str, endpoints: &[Endpoint], faults: FaultInjector) -> Self {
+    let (event_sender, _) = broadcast::channel(256);
+    let device = Self {
+      address: address.to_owned(),
+      endpoints: endpoints.iter().copied().collect(),
+      event_sender,
+      subscribed_endpoints: Arc::new(DashSet::new()),
+      connected_at: Instant::now(),
+      connected: Arc::new(AtomicBool::new(true)),
+      faults: Arc::new(faults),
+      cancellation_token: CancellationToken::new(),
+    };
+    if let Some(disconnect_after) = device.faults.disconnect_after() {
+      device.schedule_disconnect(disconnect_after);
+    }
+    device
+  }
+
+  fn battery_level(&self) -> u8 {
+    let drained = self.connected_at.elapsed().as_secs() / BATTERY_DRAIN_INTERVAL.as_secs();
+    100u64.saturating_sub(drained) as u8
+  }
+
+  fn check_command(&self, endpoint: Endpoint) -> Result<(), ButtplugDeviceError> {
+    if !self.connected.load(Ordering::SeqCst) {
+      Err(ButtplugDeviceError::DeviceNotConnected(
+        self.address.clone(),
+      ))
+    } else if self.endpoints.contains(&endpoint) {
+      Ok(())
+    } else {
+      Err(ButtplugDeviceError::InvalidEndpoint(endpoint))
+    }
+  }
+
+  /// Resolves to the command result after any injected latency.
+  fn respond<T: Send + 'static>(
+    &self,
+    result: Result<T, ButtplugDeviceError>,
+  ) -> BoxFuture<'static, Result<T, ButtplugDeviceError>> {
+    let latency = self.faults.latency();
+    if latency.is_zero() {
+      return future::ready(result).boxed();
+    }
+    async move {
+      sleep(latency).await;
+      result
+    }
+    .boxed()
+  }
+
+  fn schedule_disconnect(&self, disconnect_after: Duration) {
+    let address = self.address.clone();
+    let sender = self.event_sender.clone();
+    let connected = self.connected.clone();
+    let token = self.cancellation_token.clone();
+    async_manager::spawn(async move {
+      select! {
+        _ = token.cancelled().fuse() => return,
+        _ = sleep(disconnect_after).fuse() => {}
+      }
+      info!(
+        "Virtual device {} disconnecting due to injected fault.",
+        address
+      );
+      connected.store(false, Ordering::SeqCst);
+      token.cancel();
+      let _ = sender.send(HardwareEvent::Disconnected(address));
+    });
+  }
+
+  fn start_pressure_notifications(&self) {
+    let address = self.address.clone();
+    let sender = self.event_sender.clone();
+    let subscribed_endpoints = self.subscribed_endpoints.clone();
+    let faults = self.faults.clone();
+    let token = self.cancellation_token.child_token();
+    let start = Instant::now();
+    async_manager::spawn(async move {
+      while subscribed_endpoints.contains(&Endpoint::RxPressure) {
+        // Matches the KGoal Boost packet layout: 3 header bytes, then normalized and raw u16
+        // readings, both big endian.
+        let phase = start.elapsed().as_secs_f64() / PRESSURE_WAVE_PERIOD.as_secs_f64();
+        let normalized = ((1.0 - (phase * 2.0 * PI).cos()) / 2.0 * 1000.0) as u16;
+        // Raw readings sit on top of a resting baseline, like an uncalibrated sensor would.
+        let raw = 200 + normalized * 4 / 5;
+        let mut data = vec![0x00, 0x01, 0x04];
+        data.extend_from_slice(&normalized.to_be_bytes());
+        data.extend_from_slice(&raw.to_be_bytes());
+        if !faults.drop_notification() {
+          // No receivers just means nobody is listening yet, keep going until unsubscribed.
+          let _ = sender.send(HardwareEvent::Notification(
+            address.clone(),
+            Endpoint::RxPressure,
+            faults.maybe_malform(data),
+          ));
+        }
+        select! {
+          _ = token.cancelled().fuse() => return,
+          _ = sleep(PRESSURE_NOTIFICATION_INTERVAL).fuse() => {}
+        }
+      }
+    });
+  }
+}
+
+impl Drop for VirtualHardware {
+  fn drop(&mut self) {
+    self.cancellation_token.cancel();
+  }
+}
+
+impl HardwareInternal for VirtualHardware {
+  fn event_stream(&self) -> broadcast::Receiver<HardwareEvent> {
+    self.event_sender.subscribe()
+  }
+
+  fn disconnect(&self) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    self.connected.store(false, Ordering::SeqCst);
+    self.cancellation_token.cancel();
+    let sender = self.event_sender.clone();
+    let address = self.address.clone();
+    async move {
+      let _ = sender.send(HardwareEvent::Disconnected(address));
+      Ok(())
+    }
+    .boxed()
+  }
+
+  fn read_value(
+    &self,
+    msg: &HardwareReadCmd,
+  ) -> BoxFuture<'static, Result<HardwareReading, ButtplugDeviceError>> {
+    let result = self.check_command(msg.endpoint()).map(|_| {
+      let data = if msg.endpoint() == Endpoint::RxBLEBattery {
+        vec![self.battery_level()]
+      } else {
+        vec![0; msg.length() as usize]
+      };
+      HardwareReading::new(msg.endpoint(), &self.faults.maybe_malform(data))
+    });
+    self.respond(result)
+  }
+
+  fn write_value(
+    &self,
+    msg: &HardwareWriteCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    let result = self.check_command(msg.endpoint());
+    if result.is_ok() {
+      debug!(
+        "Virtual device {} write to {}: {:?}",
+        self.address,
+        msg.endpoint(),
+        msg.data()
+      );
+    }
+    self.respond(result)
+  }
+
+  fn subscribe(
+    &self,
+    msg: &HardwareSubscribeCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    let result = self.check_command(msg.endpoint());
+    if result.is_ok()
+      && self.subscribed_endpoints.insert(msg.endpoint())
+      && msg.endpoint() == Endpoint::RxPressure
+    {
+      self.start_pressure_notifications();
+    }
+    self.respond(result)
+  }
+
+  fn unsubscribe(
+    &self,
+    msg: &HardwareUnsubscribeCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    let result = self.check_command(msg.endpoint());
+    if result.is_ok() {
+      self.subscribed_endpoints.remove(&msg.endpoint());
+    }
+    self.respond(result)
+  }
+}
@@ -11,7 +11,9 @@ use crate::{
   server::device::hardware::communication::{
     HardwareCommunicationManager,
     HardwareCommunicationManagerBuilder,
+    HardwareCommunicationManagerConfig,
     HardwareCommunicationManagerEvent,
+    HardwareCommunicationManagerStatus,
   },
   util::async_manager,
 };
@@ -32,13 +34,16 @@ pub struct WebsocketServerDeviceCommManagerInitInfo {
   version: u32,
 }
 
-#[derive(Clone)]
-pub struct WebsocketServerDeviceCommunicationManagerBuilder {
-  listen_on_all_interfaces: bool,
-  server_port: u16,
+/// Runtime settings for the websocket device server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WebsocketServerDeviceCommunicationManagerConfig {
+  /// Listen on 0.0.0.0 instead of only on localhost.
+  pub listen_on_all_interfaces: bool,
+  /// Port the device server listens on.
+  pub server_port: u16,
 }
 
-impl Default for WebsocketServerDeviceCommunicationManagerBuilder {
+impl Default for WebsocketServerDeviceCommunicationManagerConfig {
   fn default() -> Self {
     Self {
       listen_on_all_interfaces: false,
@@ -47,14 +52,32 @@ impl Default for WebsocketServerDeviceCommunicationManagerBuilder {
   }
 }
 
+impl HardwareCommunicationManagerConfig for WebsocketServerDeviceCommunicationManagerConfig {
+  type Builder = WebsocketServerDeviceCommunicationManagerBuilder;
+
+  fn into_builder(self) -> Self::Builder {
+    WebsocketServerDeviceCommunicationManagerBuilder::default().config(self)
+  }
+}
+
+#[derive(Default, Clone)]
+pub struct WebsocketServerDeviceCommunicationManagerBuilder {
+  config: WebsocketServerDeviceCommunicationManagerConfig,
+}
+
 impl WebsocketServerDeviceCommunicationManagerBuilder {
+  pub fn config(mut self, config: WebsocketServerDeviceCommunicationManagerConfig) -> Self {
+    self.config = config;
+    self
+  }
+
   pub fn listen_on_all_interfaces(mut self, should_listen: bool) -> Self {
-    self.listen_on_all_interfaces = should_listen;
+    self.config.listen_on_all_interfaces = should_listen;
     self
   }
 
   pub fn server_port(mut self, port: u16) -> Self {
-    self.server_port = port;
+    self.config.server_port = port;
     self
   }
 }
@@ -66,13 +89,13 @@ impl HardwareCommunicationManagerBuilder for WebsocketServerDeviceCommunicationM
   ) -> Box<dyn HardwareCommunicationManager> {
     Box::new(WebsocketServerDeviceCommunicationManager::new(
       sender,
-      self.server_port,
-      self.listen_on_all_interfaces,
+      self.config.server_port,
+      self.config.listen_on_all_interfaces,
     ))
   }
 }
 
-pub struct WebsocketServerDeviceCommunicationManager {
+pub(crate) struct WebsocketServerDeviceCommunicationManager {
   server_cancellation_token: CancellationToken,
 }
 
@@ -190,8 +213,8 @@ impl HardwareCommunicationManager for WebsocketServerDeviceCommunicationManager
   }
 
   // No restrictions since this is network not hardware.
-  fn can_scan(&self) -> bool {
-    true
+  fn status(&self) -> HardwareCommunicationManagerStatus {
+    HardwareCommunicationManagerStatus::Ready
   }
 }
 
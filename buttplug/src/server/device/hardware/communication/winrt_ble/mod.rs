@@ -0,0 +1,20 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Windows native bluetooth LE support, via the WinRT BluetoothLE APIs.
+//!
+//! This is an alternative to the btleplug comm manager for Windows users that run into btleplug
+//! limitations. Use one or the other, not both, otherwise every device will be found twice.
+
+mod winrt_ble_adapter_task;
+pub mod winrt_ble_comm_manager;
+mod winrt_ble_hardware;
+
+pub use winrt_ble_comm_manager::{
+  WinRtBleCommunicationManagerBuilder,
+  WinRtBleCommunicationManagerConfig,
+};
@@ -0,0 +1,278 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::{
+  winrt_ble_comm_manager::WinRtBleCommunicationManagerConfig,
+  winrt_ble_hardware::{read_buffer, WinRtBleHardwareConnector},
+};
+use crate::{
+  server::device::hardware::communication::{
+    btle_common::BtleAdvertisement,
+    HardwareCommunicationManagerEvent,
+  },
+  util::address_privacy::display_address,
+};
+use futures::future::FutureExt;
+use std::{
+  collections::HashMap,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
+  time::Duration,
+};
+use tokio::{
+  sync::mpsc::{unbounded_channel, Receiver, Sender},
+  time::sleep,
+};
+use uuid::Uuid;
+use windows::{
+  Devices::Bluetooth::{
+    Advertisement::{
+      BluetoothLEAdvertisementReceivedEventArgs,
+      BluetoothLEAdvertisementWatcher,
+      BluetoothLEScanningMode,
+    },
+    BluetoothAdapter,
+  },
+  Foundation::TypedEventHandler,
+};
+
+#[derive(Debug, Clone, Copy)]
+pub enum WinRtBleAdapterCommand {
+  StartScanning,
+  StopScanning,
+}
+
+/// Format a WinRT bluetooth address the same way other platforms display them.
+fn format_address(address: u64) -> String {
+  address.to_be_bytes()[2..]
+    .iter()
+    .map(|b| format!("{:02X}", b))
+    .collect::<Vec<String>>()
+    .join(":")
+}
+
+fn advertisement_from_args(
+  args: &BluetoothLEAdvertisementReceivedEventArgs,
+) -> windows::core::Result<(u64, BtleAdvertisement)> {
+  let bluetooth_address = args.BluetoothAddress()?;
+  let advertisement = args.Advertisement()?;
+  let mut manufacturer_data = HashMap::new();
+  for data in advertisement.ManufacturerData()? {
+    manufacturer_data.insert(data.CompanyId()?, read_buffer(&data.Data()?)?);
+  }
+  let services = advertisement
+    .ServiceUuids()?
+    .into_iter()
+    .map(|guid| Uuid::from_u128(guid.to_u128()))
+    .collect();
+  Ok((
+    bluetooth_address,
+    BtleAdvertisement {
+      name: advertisement.LocalName()?.to_string_lossy(),
+      address: format_address(bluetooth_address),
+      manufacturer_data,
+      services,
+      rssi: Some(args.RawSignalStrengthInDBm()?),
+    },
+  ))
+}
+
+pub struct WinRtBleAdapterTask {
+  event_sender: Sender<HardwareCommunicationManagerEvent>,
+  command_receiver: Receiver<WinRtBleAdapterCommand>,
+  adapter_connected: Arc<AtomicBool>,
+  config: WinRtBleCommunicationManagerConfig,
+}
+
+impl WinRtBleAdapterTask {
+  pub fn new(
+    event_sender: Sender<HardwareCommunicationManagerEvent>,
+    command_receiver: Receiver<WinRtBleAdapterCommand>,
+    adapter_connected: Arc<AtomicBool>,
+    config: WinRtBleCommunicationManagerConfig,
+  ) -> Self {
+    Self {
+      event_sender,
+      command_receiver,
+      adapter_connected,
+      config,
+    }
+  }
+
+  async fn wait_for_adapter(&self) {
+    // Start by assuming we'll find the adapter on the first try. If not, we'll print an error
+    // message then loop while trying to find it.
+    let mut adapter_found = true;
+    loop {
+      let adapter = match BluetoothAdapter::GetDefaultAsync() {
+        Ok(op) => op.await,
+        Err(e) => Err(e),
+      };
+      match adapter {
+        Ok(adapter) if adapter.IsLowEnergySupported().unwrap_or(false) => {
+          info!("WinRT Bluetooth LE adapter found.");
+          self.adapter_connected.store(true, Ordering::SeqCst);
+          return;
+        }
+        _ => {
+          if adapter_found {
+            adapter_found = false;
+            warn!("Bluetooth LE adapter not found, will not be using bluetooth scanning until found. Buttplug will continue polling for the adapter, but no more warning messages will be posted.");
+          }
+        }
+      }
+      sleep(Duration::from_secs(1)).await;
+    }
+  }
+
+  async fn maybe_add_device(
+    &self,
+    bluetooth_address: u64,
+    advertisement: BtleAdvertisement,
+    tried_addresses: &mut Vec<BtleAdvertisement>,
+  ) {
+    if !self.config.scan_services.is_empty()
+      && !advertisement
+        .services
+        .iter()
+        .any(|service| self.config.scan_services.contains(service))
+    {
+      return;
+    }
+    if advertisement.is_identifiable() && !tried_addresses.contains(&advertisement) {
+      let span = info_span!(
+        "winrt bluetooth enumeration",
+        address = tracing::field::display(display_address(&advertisement.address)),
+        name = tracing::field::display(&advertisement.name)
+      );
+      let _enter = span.enter();
+
+      debug!(
+        "Found new bluetooth device advertisement: {:?}",
+        advertisement
+      );
+      tried_addresses.push(advertisement.clone());
+      let name = advertisement.name.clone();
+      let address = advertisement.address.clone();
+      let device_creator = Box::new(WinRtBleHardwareConnector::new(
+        advertisement,
+        bluetooth_address,
+      ));
+      if self
+        .event_sender
+        .send(HardwareCommunicationManagerEvent::DeviceFound {
+          name,
+          address,
+          creator: device_creator,
+        })
+        .await
+        .is_err()
+      {
+        error!("Device manager receiver dropped, cannot send device found message.");
+      }
+    } else {
+      trace!(
+        "Device {} found, no advertised name, ignoring.",
+        display_address(&advertisement.address)
+      );
+    }
+  }
+
+  pub async fn run(&mut self) {
+    self.wait_for_adapter().await;
+
+    let watcher = match BluetoothLEAdvertisementWatcher::new() {
+      Ok(watcher) => watcher,
+      Err(e) => {
+        error!("Error creating WinRT advertisement watcher: {:?}", e);
+        return;
+      }
+    };
+    let scanning_mode = if self.config.passive_scanning {
+      BluetoothLEScanningMode::Passive
+    } else {
+      BluetoothLEScanningMode::Active
+    };
+    if let Err(e) = watcher.SetScanningMode(scanning_mode) {
+      warn!("Cannot set WinRT scanning mode: {:?}", e);
+    }
+
+    // The watcher calls us back on a WinRT thread, so bounce advertisements over to our loop.
+    let (advertisement_sender, mut advertisement_receiver) = unbounded_channel();
+    if let Err(e) = watcher.Received(&TypedEventHandler::new(
+      move |_, args: &Option<BluetoothLEAdvertisementReceivedEventArgs>| {
+        if let Some(args) = args {
+          match advertisement_from_args(args) {
+            Ok(advertisement) => {
+              let _ = advertisement_sender.send(advertisement);
+            }
+            Err(e) => trace!("Cannot parse WinRT advertisement: {:?}", e),
+          }
+        }
+        Ok(())
+      },
+    )) {
+      error!("Cannot register WinRT advertisement handler: {:?}", e);
+      return;
+    }
+
+    // Active scans deliver names and services in separate scan response packets, so merge
+    // everything we've heard from an address before deciding whether it's identifiable.
+    let mut seen_advertisements = HashMap::<u64, BtleAdvertisement>::new();
+    let mut tried_addresses = vec![];
+
+    loop {
+      select! {
+        advertisement = advertisement_receiver.recv().fuse() => {
+          if let Some((bluetooth_address, advertisement)) = advertisement {
+            let merged = seen_advertisements
+              .entry(bluetooth_address)
+              .or_insert_with(|| advertisement.clone());
+            if !advertisement.name.is_empty() {
+              merged.name = advertisement.name;
+            }
+            for service in advertisement.services {
+              if !merged.services.contains(&service) {
+                merged.services.push(service);
+              }
+            }
+            merged.manufacturer_data.extend(advertisement.manufacturer_data);
+            let merged = merged.clone();
+            self.maybe_add_device(bluetooth_address, merged, &mut tried_addresses).await;
+          } else {
+            error!("Advertisement stream closed. Exiting loop.");
+            return;
+          }
+        },
+        command = self.command_receiver.recv().fuse() => {
+          if let Some(cmd) = command {
+            match cmd {
+              WinRtBleAdapterCommand::StartScanning => {
+                tried_addresses.clear();
+                seen_advertisements.clear();
+                if let Err(err) = watcher.Start() {
+                  error!("Start scanning request failed: {:?}", err);
+                }
+              }
+              WinRtBleAdapterCommand::StopScanning => {
+                if let Err(err) = watcher.Stop() {
+                  error!("Stop scanning request failed: {:?}", err);
+                }
+              }
+            }
+          } else {
+            debug!("Command stream closed. Exiting WinRT bluetooth adapter loop.");
+            let _ = watcher.Stop();
+            return;
+          }
+        }
+      }
+    }
+  }
+}
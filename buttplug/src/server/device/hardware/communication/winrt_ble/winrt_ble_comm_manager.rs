@@ -0,0 +1,159 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::winrt_ble_adapter_task::{WinRtBleAdapterCommand, WinRtBleAdapterTask};
+use crate::{
+  core::{errors::ButtplugDeviceError, ButtplugResultFuture},
+  server::device::hardware::communication::{
+    HardwareCommunicationManager,
+    HardwareCommunicationManagerBuilder,
+    HardwareCommunicationManagerConfig,
+    HardwareCommunicationManagerEvent,
+    HardwareCommunicationManagerStatus,
+  },
+  util::async_manager,
+};
+use futures::future::FutureExt;
+use std::sync::{
+  atomic::{AtomicBool, Ordering},
+  Arc,
+};
+use tokio::sync::mpsc::{channel, Sender};
+use uuid::Uuid;
+
+/// Runtime settings for WinRT bluetooth LE scanning.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct WinRtBleCommunicationManagerConfig {
+  /// If not empty, only devices advertising at least one of these services will be reported while
+  /// scanning.
+  pub scan_services: Vec<Uuid>,
+  /// Only listen for advertisements instead of sending scan requests. Uses less power, but devices
+  /// that only send their name in scan responses won't be identified.
+  pub passive_scanning: bool,
+}
+
+impl HardwareCommunicationManagerConfig for WinRtBleCommunicationManagerConfig {
+  type Builder = WinRtBleCommunicationManagerBuilder;
+
+  fn into_builder(self) -> Self::Builder {
+    WinRtBleCommunicationManagerBuilder::default().config(self)
+  }
+}
+
+#[derive(Default, Clone)]
+pub struct WinRtBleCommunicationManagerBuilder {
+  config: WinRtBleCommunicationManagerConfig,
+}
+
+impl WinRtBleCommunicationManagerBuilder {
+  pub fn config(mut self, config: WinRtBleCommunicationManagerConfig) -> Self {
+    self.config = config;
+    self
+  }
+}
+
+impl HardwareCommunicationManagerBuilder for WinRtBleCommunicationManagerBuilder {
+  fn finish(
+    &mut self,
+    sender: Sender<HardwareCommunicationManagerEvent>,
+  ) -> Box<dyn HardwareCommunicationManager> {
+    Box::new(WinRtBleCommunicationManager::new(
+      sender,
+      self.config.clone(),
+    ))
+  }
+}
+
+pub(crate) struct WinRtBleCommunicationManager {
+  adapter_event_sender: Sender<WinRtBleAdapterCommand>,
+  scanning_status: Arc<AtomicBool>,
+  adapter_connected: Arc<AtomicBool>,
+}
+
+impl WinRtBleCommunicationManager {
+  pub fn new(
+    event_sender: Sender<HardwareCommunicationManagerEvent>,
+    config: WinRtBleCommunicationManagerConfig,
+  ) -> Self {
+    let (sender, receiver) = channel(256);
+    let adapter_connected = Arc::new(AtomicBool::new(false));
+    let adapter_connected_clone = adapter_connected.clone();
+    async_manager::spawn(async move {
+      let mut task =
+        WinRtBleAdapterTask::new(event_sender, receiver, adapter_connected_clone, config);
+      task.run().await;
+    });
+    Self {
+      adapter_event_sender: sender,
+      scanning_status: Arc::new(AtomicBool::new(false)),
+      adapter_connected,
+    }
+  }
+}
+
+impl HardwareCommunicationManager for WinRtBleCommunicationManager {
+  fn name(&self) -> &'static str {
+    "WinRtBleCommunicationManager"
+  }
+
+  fn start_scanning(&mut self) -> ButtplugResultFuture {
+    let adapter_event_sender = self.adapter_event_sender.clone();
+    let scanning_status = self.scanning_status.clone();
+    // Set to true just to make sure we don't call ScanningFinished too early.
+    scanning_status.store(true, Ordering::SeqCst);
+    async move {
+      if adapter_event_sender
+        .send(WinRtBleAdapterCommand::StartScanning)
+        .await
+        .is_err()
+      {
+        error!("Error starting scan, cannot send to WinRT bluetooth event loop.");
+        scanning_status.store(false, Ordering::SeqCst);
+        Err(
+          ButtplugDeviceError::DeviceConnectionError(
+            "Cannot send start scanning request to event loop.".to_owned(),
+          )
+          .into(),
+        )
+      } else {
+        Ok(())
+      }
+    }
+    .boxed()
+  }
+
+  fn stop_scanning(&mut self) -> ButtplugResultFuture {
+    let adapter_event_sender = self.adapter_event_sender.clone();
+    // Just assume any outcome of this means we're done scanning.
+    self.scanning_status.store(false, Ordering::SeqCst);
+    async move {
+      if adapter_event_sender
+        .send(WinRtBleAdapterCommand::StopScanning)
+        .await
+        .is_err()
+      {
+        error!("Error stopping scan, cannot send to WinRT bluetooth event loop.");
+        Err(
+          ButtplugDeviceError::DeviceConnectionError(
+            "Cannot send stop scanning request to event loop.".to_owned(),
+          )
+          .into(),
+        )
+      } else {
+        Ok(())
+      }
+    }
+    .boxed()
+  }
+
+  fn status(&self) -> HardwareCommunicationManagerStatus {
+    HardwareCommunicationManagerStatus::from_availability(
+      self.adapter_connected.load(Ordering::SeqCst),
+      self.scanning_status.load(Ordering::SeqCst),
+    )
+  }
+}
@@ -0,0 +1,508 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use crate::{
+  core::{errors::ButtplugDeviceError, message::Endpoint},
+  server::device::{
+    configuration::ProtocolCommunicationSpecifier,
+    hardware::{
+      communication::{
+        btle_common::{map_btle_endpoints, resolve_write_with_response, BtleAdvertisement},
+        HardwareSpecificError,
+      },
+      BluetoothLEConnectionParameters,
+      Hardware,
+      HardwareConnector,
+      HardwareEvent,
+      HardwareInternal,
+      HardwareReadCmd,
+      HardwareReading,
+      HardwareSpecializer,
+      HardwareSubscribeCmd,
+      HardwareUnsubscribeCmd,
+      HardwareWriteCmd,
+    },
+  },
+  util::address_privacy::display_address,
+};
+use async_trait::async_trait;
+use dashmap::DashMap;
+use futures::future::{self, BoxFuture, FutureExt};
+use std::{
+  collections::HashMap,
+  fmt::{self, Debug},
+  sync::{Arc, Mutex},
+  time::Duration,
+};
+use tokio::sync::broadcast;
+use uuid::Uuid;
+use windows::{
+  Devices::Bluetooth::{
+    BluetoothCacheMode,
+    BluetoothConnectionStatus,
+    BluetoothLEDevice,
+    BluetoothLEPreferredConnectionParameters,
+    BluetoothLEPreferredConnectionParametersRequest,
+    BluetoothLEPreferredConnectionParametersRequestStatus,
+    GenericAttributeProfile::{
+      GattCharacteristic,
+      GattCharacteristicProperties,
+      GattClientCharacteristicConfigurationDescriptorValue,
+      GattCommunicationStatus,
+      GattDeviceService,
+      GattValueChangedEventArgs,
+      GattWriteOption,
+    },
+  },
+  Foundation::{EventRegistrationToken, TypedEventHandler},
+  Storage::Streams::{DataReader, DataWriter, IBuffer},
+};
+
+// WinRT only lets us pick from a few connection parameter presets. Requests that fit inside the
+// throughput preset's interval get it, requests that start at the power preset's interval get that,
+// and everything else gets the balanced preset.
+const THROUGHPUT_OPTIMIZED_MAX_INTERVAL: Duration = Duration::from_millis(15);
+const POWER_OPTIMIZED_MIN_INTERVAL: Duration = Duration::from_millis(90);
+
+fn winrt_error<T: Debug>(err: T) -> ButtplugDeviceError {
+  ButtplugDeviceError::from(HardwareSpecificError::WinRtBleError(format!("{:?}", err)))
+}
+
+pub(super) fn read_buffer(buffer: &IBuffer) -> windows::core::Result<Vec<u8>> {
+  let reader = DataReader::FromBuffer(buffer)?;
+  let mut data = vec![0u8; reader.UnconsumedBufferLength()? as usize];
+  reader.ReadBytes(&mut data)?;
+  Ok(data)
+}
+
+fn write_buffer(data: &[u8]) -> windows::core::Result<IBuffer> {
+  let writer = DataWriter::new()?;
+  writer.WriteBytes(data)?;
+  writer.DetachBuffer()
+}
+
+fn check_status(status: GattCommunicationStatus) -> Result<(), ButtplugDeviceError> {
+  if status == GattCommunicationStatus::Success {
+    Ok(())
+  } else {
+    Err(winrt_error(status))
+  }
+}
+
+pub(super) struct WinRtBleHardwareConnector {
+  advertisement: BtleAdvertisement,
+  bluetooth_address: u64,
+}
+
+impl WinRtBleHardwareConnector {
+  pub fn new(advertisement: BtleAdvertisement, bluetooth_address: u64) -> Self {
+    Self {
+      advertisement,
+      bluetooth_address,
+    }
+  }
+}
+
+impl Debug for WinRtBleHardwareConnector {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("WinRtBleHardwareConnector")
+      .field("name", &self.advertisement.name)
+      .field("address", &display_address(&self.advertisement.address))
+      .finish()
+  }
+}
+
+#[async_trait]
+impl HardwareConnector for WinRtBleHardwareConnector {
+  fn specifier(&self) -> ProtocolCommunicationSpecifier {
+    self.advertisement.specifier()
+  }
+
+  fn rssi(&self) -> Option<i16> {
+    self.advertisement.rssi
+  }
+
+  async fn connect(&mut self) -> Result<Box<dyn HardwareSpecializer>, ButtplugDeviceError> {
+    // WinRT connects implicitly the first time we touch the GATT table.
+    let device = BluetoothLEDevice::FromBluetoothAddressAsync(self.bluetooth_address)
+      .map_err(winrt_error)?
+      .await
+      .map_err(winrt_error)?;
+    let services_result = device
+      .GetGattServicesWithCacheModeAsync(BluetoothCacheMode::Uncached)
+      .map_err(winrt_error)?
+      .await
+      .map_err(winrt_error)?;
+    check_status(services_result.Status().map_err(winrt_error)?)?;
+    let gatt_services: Vec<GattDeviceService> = services_result
+      .Services()
+      .map_err(winrt_error)?
+      .into_iter()
+      .collect();
+    let mut services = vec![];
+    for service in gatt_services {
+      let characteristics_result = service
+        .GetCharacteristicsWithCacheModeAsync(BluetoothCacheMode::Uncached)
+        .map_err(winrt_error)?
+        .await
+        .map_err(winrt_error)?;
+      if let Err(err) = check_status(characteristics_result.Status().map_err(winrt_error)?) {
+        // Some services (HID, etc) are locked by the OS, skip them instead of failing.
+        debug!(
+          "Cannot read characteristics for service, skipping: {:?}",
+          err
+        );
+        continue;
+      }
+      let mut characteristics = vec![];
+      for chr in characteristics_result
+        .Characteristics()
+        .map_err(winrt_error)?
+      {
+        characteristics.push((
+          Uuid::from_u128(chr.Uuid().map_err(winrt_error)?.to_u128()),
+          chr,
+        ));
+      }
+      services.push((
+        Uuid::from_u128(service.Uuid().map_err(winrt_error)?.to_u128()),
+        characteristics,
+      ));
+    }
+    Ok(Box::new(WinRtBleHardwareSpecializer {
+      name: self.advertisement.name.clone(),
+      address: self.advertisement.address.clone(),
+      device,
+      services,
+    }))
+  }
+}
+
+pub struct WinRtBleHardwareSpecializer {
+  name: String,
+  address: String,
+  device: BluetoothLEDevice,
+  services: Vec<(Uuid, Vec<(Uuid, GattCharacteristic)>)>,
+}
+
+#[async_trait]
+impl HardwareSpecializer for WinRtBleHardwareSpecializer {
+  async fn specialize(
+    &mut self,
+    specifiers: &[ProtocolCommunicationSpecifier],
+  ) -> Result<Hardware, ButtplugDeviceError> {
+    let endpoints = map_btle_endpoints(&self.name, &self.address, specifiers, &self.services)?;
+    let device_internal_impl = WinRtBleHardware::new(
+      self.device.clone(),
+      &self.name,
+      &self.address,
+      endpoints.clone(),
+    )?;
+    Ok(Hardware::new(
+      &self.name,
+      &self.address,
+      &endpoints.keys().cloned().collect::<Vec<Endpoint>>(),
+      Box::new(device_internal_impl),
+    ))
+  }
+}
+
+pub struct WinRtBleHardware {
+  device: BluetoothLEDevice,
+  address: String,
+  event_stream: broadcast::Sender<HardwareEvent>,
+  endpoints: HashMap<Endpoint, GattCharacteristic>,
+  subscribed_endpoints: Arc<DashMap<Endpoint, EventRegistrationToken>>,
+  connection_status_token: EventRegistrationToken,
+  /// WinRT only keeps preferred connection parameters while the request that set them is open.
+  connection_parameters_request: Mutex<Option<BluetoothLEPreferredConnectionParametersRequest>>,
+}
+
+impl WinRtBleHardware {
+  pub fn new(
+    device: BluetoothLEDevice,
+    name: &str,
+    address: &str,
+    endpoints: HashMap<Endpoint, GattCharacteristic>,
+  ) -> Result<Self, ButtplugDeviceError> {
+    let (event_stream, _) = broadcast::channel(256);
+    let event_stream_clone = event_stream.clone();
+    let address_clone = address.to_owned();
+    let name_clone = name.to_owned();
+    let connection_status_token = device
+      .ConnectionStatusChanged(&TypedEventHandler::new(
+        move |device: &Option<BluetoothLEDevice>, _| {
+          if let Some(device) = device {
+            if device.ConnectionStatus()? == BluetoothConnectionStatus::Disconnected {
+              info!("Device {:?} disconnected", name_clone);
+              if event_stream_clone.receiver_count() != 0 {
+                if let Err(err) =
+                  event_stream_clone.send(HardwareEvent::Disconnected(address_clone.clone()))
+                {
+                  error!(
+                    "Cannot send notification, device object disappeared: {:?}",
+                    err
+                  );
+                }
+              }
+            }
+          }
+          Ok(())
+        },
+      ))
+      .map_err(winrt_error)?;
+    Ok(Self {
+      device,
+      address: address.to_owned(),
+      event_stream,
+      endpoints,
+      subscribed_endpoints: Arc::new(DashMap::new()),
+      connection_status_token,
+      connection_parameters_request: Mutex::new(None),
+    })
+  }
+}
+
+impl HardwareInternal for WinRtBleHardware {
+  fn event_stream(&self) -> broadcast::Receiver<HardwareEvent> {
+    self.event_stream.subscribe()
+  }
+
+  fn disconnect(&self) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    for (endpoint, chr) in &self.endpoints {
+      if let Some((_, token)) = self.subscribed_endpoints.remove(endpoint) {
+        let _ = chr.RemoveValueChanged(token);
+      }
+    }
+    let _ = self
+      .device
+      .RemoveConnectionStatusChanged(self.connection_status_token);
+    if let Some(request) = self
+      .connection_parameters_request
+      .lock()
+      .expect("Connection parameter lock should never be poisoned.")
+      .take()
+    {
+      let _ = request.Close();
+    }
+    // WinRT drops the connection once nothing holds the device open.
+    future::ready(self.device.Close().map_err(winrt_error)).boxed()
+  }
+
+  fn request_connection_parameters(
+    &self,
+    parameters: &BluetoothLEConnectionParameters,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    let preset = if parameters.max_interval() <= THROUGHPUT_OPTIMIZED_MAX_INTERVAL {
+      BluetoothLEPreferredConnectionParameters::ThroughputOptimized()
+    } else if parameters.min_interval() >= POWER_OPTIMIZED_MIN_INTERVAL {
+      BluetoothLEPreferredConnectionParameters::PowerOptimized()
+    } else {
+      BluetoothLEPreferredConnectionParameters::Balanced()
+    };
+    // Fails on Windows versions older than 11, which don't have preferred connection parameters.
+    let result = preset
+      .and_then(|preset| self.device.RequestPreferredConnectionParameters(&preset))
+      .map_err(winrt_error)
+      .and_then(|request| {
+        let status = request.Status().map_err(winrt_error)?;
+        if status != BluetoothLEPreferredConnectionParametersRequestStatus::Success {
+          let _ = request.Close();
+          return Err(ButtplugDeviceError::DeviceCommunicationError(format!(
+            "Connection parameter request failed with status {}",
+            status.0
+          )));
+        }
+        // Close any earlier request, so only the newest parameters stay in effect.
+        if let Some(previous) = self
+          .connection_parameters_request
+          .lock()
+          .expect("Connection parameter lock should never be poisoned.")
+          .replace(request)
+        {
+          let _ = previous.Close();
+        }
+        Ok(())
+      });
+    future::ready(result).boxed()
+  }
+
+  fn write_value(
+    &self,
+    msg: &HardwareWriteCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    let characteristic = match self.endpoints.get(&msg.endpoint) {
+      Some(chr) => chr.clone(),
+      None => {
+        return future::ready(Err(ButtplugDeviceError::InvalidEndpoint(msg.endpoint))).boxed();
+      }
+    };
+    let properties = characteristic
+      .CharacteristicProperties()
+      .unwrap_or(GattCharacteristicProperties::None);
+    let write_option = if resolve_write_with_response(
+      msg.write_with_response,
+      properties.contains(GattCharacteristicProperties::Write),
+      properties.contains(GattCharacteristicProperties::WriteWithoutResponse),
+    ) {
+      GattWriteOption::WriteWithResponse
+    } else {
+      GattWriteOption::WriteWithoutResponse
+    };
+    let data = msg.data.clone();
+    async move {
+      let write_op = {
+        let buffer = write_buffer(&data).map_err(winrt_error)?;
+        characteristic
+          .WriteValueWithOptionAsync(&buffer, write_option)
+          .map_err(winrt_error)?
+      };
+      let status = write_op.await.map_err(winrt_error)?;
+      check_status(status).map_err(|err| {
+        error!("WinRT bluetooth device write error: {:?}", err);
+        err
+      })
+    }
+    .boxed()
+  }
+
+  fn read_value(
+    &self,
+    msg: &HardwareReadCmd,
+  ) -> BoxFuture<'static, Result<HardwareReading, ButtplugDeviceError>> {
+    let characteristic = match self.endpoints.get(&msg.endpoint) {
+      Some(chr) => chr.clone(),
+      None => {
+        return future::ready(Err(ButtplugDeviceError::InvalidEndpoint(msg.endpoint))).boxed();
+      }
+    };
+    let endpoint = msg.endpoint;
+    async move {
+      let result = characteristic
+        .ReadValueWithCacheModeAsync(BluetoothCacheMode::Uncached)
+        .map_err(winrt_error)?
+        .await
+        .map_err(winrt_error)?;
+      check_status(result.Status().map_err(winrt_error)?)?;
+      let data = read_buffer(&result.Value().map_err(winrt_error)?).map_err(winrt_error)?;
+      trace!("Got reading: {:?}", data);
+      Ok(HardwareReading::new(endpoint, &data))
+    }
+    .boxed()
+  }
+
+  fn subscribe(
+    &self,
+    msg: &HardwareSubscribeCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    let endpoint = msg.endpoint;
+    if self.subscribed_endpoints.contains_key(&endpoint) {
+      debug!(
+        "Endpoint {} already subscribed, ignoring and returning Ok.",
+        endpoint
+      );
+      return future::ready(Ok(())).boxed();
+    }
+    let characteristic = match self.endpoints.get(&endpoint) {
+      Some(chr) => chr.clone(),
+      None => {
+        return future::ready(Err(ButtplugDeviceError::InvalidEndpoint(msg.endpoint))).boxed();
+      }
+    };
+    let event_stream = self.event_stream.clone();
+    let address = self.address.clone();
+    let endpoints = self.subscribed_endpoints.clone();
+    async move {
+      let token = characteristic
+        .ValueChanged(&TypedEventHandler::new(
+          move |_, args: &Option<GattValueChangedEventArgs>| {
+            if let Some(args) = args {
+              let data = read_buffer(&args.CharacteristicValue()?)?;
+              if event_stream.receiver_count() != 0 {
+                if let Err(err) =
+                  event_stream.send(HardwareEvent::Notification(address.clone(), endpoint, data))
+                {
+                  error!(
+                    "Cannot send notification, device object disappeared: {:?}",
+                    err
+                  );
+                }
+              }
+            }
+            Ok(())
+          },
+        ))
+        .map_err(winrt_error)?;
+      let properties = characteristic
+        .CharacteristicProperties()
+        .map_err(winrt_error)?;
+      let descriptor_value = if properties.contains(GattCharacteristicProperties::Notify) {
+        GattClientCharacteristicConfigurationDescriptorValue::Notify
+      } else {
+        GattClientCharacteristicConfigurationDescriptorValue::Indicate
+      };
+      let status = characteristic
+        .WriteClientCharacteristicConfigurationDescriptorAsync(descriptor_value)
+        .map_err(winrt_error)?
+        .await
+        .map_err(winrt_error)?;
+      if let Err(err) = check_status(status) {
+        let _ = characteristic.RemoveValueChanged(token);
+        return Err(err);
+      }
+      endpoints.insert(endpoint, token);
+      Ok(())
+    }
+    .boxed()
+  }
+
+  fn unsubscribe(
+    &self,
+    msg: &HardwareUnsubscribeCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    let endpoint = msg.endpoint;
+    if !self.subscribed_endpoints.contains_key(&endpoint) {
+      debug!(
+        "Endpoint {} already unsubscribed, ignoring and returning Ok.",
+        endpoint
+      );
+      return future::ready(Ok(())).boxed();
+    }
+    let characteristic = match self.endpoints.get(&endpoint) {
+      Some(chr) => chr.clone(),
+      None => {
+        return future::ready(Err(ButtplugDeviceError::InvalidEndpoint(msg.endpoint))).boxed();
+      }
+    };
+    let endpoints = self.subscribed_endpoints.clone();
+    async move {
+      let status = characteristic
+        .WriteClientCharacteristicConfigurationDescriptorAsync(
+          GattClientCharacteristicConfigurationDescriptorValue::None,
+        )
+        .map_err(winrt_error)?
+        .await
+        .map_err(winrt_error)?;
+      check_status(status)?;
+      if let Some((_, token)) = endpoints.remove(&endpoint) {
+        characteristic
+          .RemoveValueChanged(token)
+          .map_err(winrt_error)?;
+      }
+      Ok(())
+    }
+    .boxed()
+  }
+}
+
+impl Drop for WinRtBleHardware {
+  fn drop(&mut self) {
+    if let Err(e) = self.disconnect().now_or_never().unwrap_or(Ok(())) {
+      error!("Error disconnecting WinRT bluetooth device: {:?}", e);
+    }
+  }
+}
@@ -8,7 +8,4 @@
 mod xinput_device_comm_manager;
 mod xinput_hardware;
 
-pub use xinput_device_comm_manager::{
-  XInputDeviceCommunicationManager,
-  XInputDeviceCommunicationManagerBuilder,
-};
+pub use xinput_device_comm_manager::XInputDeviceCommunicationManagerBuilder;
@@ -15,11 +15,17 @@ use crate::{
     TimedRetryCommunicationManager,
     TimedRetryCommunicationManagerImpl,
   },
+  util::async_manager,
 };
 use async_trait::async_trait;
 use rusty_xinput::XInputHandle;
-use std::string::ToString;
+use std::{
+  string::ToString,
+  sync::atomic::{AtomicBool, Ordering},
+  time::Duration,
+};
 use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
 
 // 1-index this because we use it elsewhere for showing which controller is which.
 #[derive(Debug, Display, Clone, Copy)]
@@ -31,6 +37,65 @@ pub enum XInputControllerIndex {
   XInputController4 = 3,
 }
 
+const XINPUT_CONTROLLERS: [XInputControllerIndex; 4] = [
+  XInputControllerIndex::XInputController1,
+  XInputControllerIndex::XInputController2,
+  XInputControllerIndex::XInputController3,
+  XInputControllerIndex::XInputController4,
+];
+
+/// Tells the device manager about a controller. Returns false if the device manager has gone away.
+async fn send_device_found(
+  sender: &mpsc::Sender<HardwareCommunicationManagerEvent>,
+  index: XInputControllerIndex,
+) -> bool {
+  let device_creator = Box::new(XInputHardwareConnector::new(index));
+  if sender
+    .send(HardwareCommunicationManagerEvent::DeviceFound {
+      name: index.to_string(),
+      address: index.to_string(),
+      creator: device_creator,
+    })
+    .await
+    .is_err()
+  {
+    error!("Error sending device found message from Xinput.");
+    return false;
+  }
+  true
+}
+
+/// Reports controllers as they're plugged in, so ones connected after scanning started are found
+/// without another scan. Controllers already plugged in when this starts are left to the scan that
+/// started it.
+async fn watch_for_gamepads(
+  sender: mpsc::Sender<HardwareCommunicationManagerEvent>,
+  cancellation_token: CancellationToken,
+) {
+  let handle = rusty_xinput::XInputHandle::load_default()
+    .expect("Always loads in windows, this shouldn't run elsewhere.");
+  let mut connected: Vec<bool> = XINPUT_CONTROLLERS
+    .iter()
+    .map(|index| handle.get_state(*index as u32).is_ok())
+    .collect();
+  loop {
+    tokio::select! {
+      _ = cancellation_token.cancelled() => return,
+      _ = tokio::time::sleep(Duration::from_millis(500)) => {}
+    }
+    for (index, was_connected) in XINPUT_CONTROLLERS.iter().zip(connected.iter_mut()) {
+      let is_connected = handle.get_state(*index as u32).is_ok();
+      if is_connected && !*was_connected {
+        info!("XInput gamepad {} has been plugged in.", index);
+        if !send_device_found(&sender, *index).await {
+          return;
+        }
+      }
+      *was_connected = is_connected;
+    }
+  }
+}
+
 #[derive(Default, Clone)]
 pub struct XInputDeviceCommunicationManagerBuilder {}
 
@@ -45,9 +110,13 @@ impl HardwareCommunicationManagerBuilder for XInputDeviceCommunicationManagerBui
   }
 }
 
-pub struct XInputDeviceCommunicationManager {
+pub(crate) struct XInputDeviceCommunicationManager {
   sender: mpsc::Sender<HardwareCommunicationManagerEvent>,
   handle: XInputHandle,
+  /// Set once the first scan has started the hotplug watcher.
+  watching: AtomicBool,
+  /// Stops the hotplug watcher.
+  watcher_token: CancellationToken,
 }
 
 impl XInputDeviceCommunicationManager {
@@ -56,10 +125,18 @@ impl XInputDeviceCommunicationManager {
       sender,
       handle: rusty_xinput::XInputHandle::load_default()
         .expect("Always loads in windows, this shouldn't run elsewhere."),
+      watching: AtomicBool::new(false),
+      watcher_token: CancellationToken::new(),
     }
   }
 }
 
+impl Drop for XInputDeviceCommunicationManager {
+  fn drop(&mut self) {
+    self.watcher_token.cancel();
+  }
+}
+
 #[async_trait]
 impl TimedRetryCommunicationManagerImpl for XInputDeviceCommunicationManager {
   fn name(&self) -> &'static str {
@@ -68,29 +145,20 @@ impl TimedRetryCommunicationManagerImpl for XInputDeviceCommunicationManager {
 
   async fn scan(&self) -> Result<(), ButtplugDeviceError> {
     trace!("XInput manager scanning for devices");
-    for i in &[
-      XInputControllerIndex::XInputController1,
-      XInputControllerIndex::XInputController2,
-      XInputControllerIndex::XInputController3,
-      XInputControllerIndex::XInputController4,
-    ] {
+    if !self.watching.swap(true, Ordering::Relaxed) {
+      debug!("XInput manager watching for gamepads being plugged in.");
+      let sender = self.sender.clone();
+      let token = self.watcher_token.child_token();
+      async_manager::spawn(async move {
+        watch_for_gamepads(sender, token).await;
+      });
+    }
+    for i in &XINPUT_CONTROLLERS {
       match self.handle.get_state(*i as u32) {
         Ok(_) => {
           let index = *i as u32;
           debug!("XInput manager found device {}", index);
-          let device_creator = Box::new(XInputHardwareConnector::new(*i));
-
-          if self
-            .sender
-            .send(HardwareCommunicationManagerEvent::DeviceFound {
-              name: i.to_string(),
-              address: i.to_string(),
-              creator: device_creator,
-            })
-            .await
-            .is_err()
-          {
-            error!("Error sending device found message from Xinput.");
+          if !send_device_found(&self.sender, *i).await {
             break;
           }
         }
@@ -101,9 +169,4 @@ impl TimedRetryCommunicationManagerImpl for XInputDeviceCommunicationManager {
     }
     Ok(())
   }
-
-  // We should always be able to at least look at xinput if we're up on windows.
-  fn can_scan(&self) -> bool {
-    true
-  }
 }
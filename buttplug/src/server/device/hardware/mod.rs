@@ -1,6 +1,17 @@
+mod activity;
+pub mod api;
 pub mod communication;
+mod register_cache;
 
-use std::fmt::Debug;
+use std::{
+  collections::HashSet,
+  fmt::Debug,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
+  time::Duration,
+};
 
 use crate::{
   core::{
@@ -9,11 +20,18 @@ use crate::{
   },
   server::device::configuration::ProtocolCommunicationSpecifier,
 };
+use activity::ActivityTracker;
+pub use activity::{HardwareActivity, KeepaliveGuard};
 use async_trait::async_trait;
-use futures::future::BoxFuture;
+use futures::future::{self, BoxFuture, FutureExt};
 use getset::{CopyGetters, Getters};
+use register_cache::RegisterCache;
 use serde::{Deserialize, Serialize};
-use tokio::sync::broadcast;
+use tokio::sync::{broadcast, OwnedRwLockWriteGuard, RwLock};
+
+// How many times read-modify-write will start over because the endpoint was written behind its
+// back, before giving up.
+const READ_MODIFY_WRITE_ATTEMPTS: usize = 3;
 
 /// Parameters for reading data from a [Hardware](crate::device::Hardware) endpoint
 ///
@@ -226,6 +244,55 @@ pub enum HardwareEvent {
   Disconnected(String),
 }
 
+/// Connection parameters to request from bluetooth LE hardware after connecting.
+///
+/// Shorter connection intervals get commands to the device sooner, at the cost of power on both
+/// ends. These are only requests: the OS and the device both get a say in what the connection ends
+/// up using, and some platforms only offer a few presets to pick from.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, CopyGetters)]
+#[getset(get_copy = "pub")]
+pub struct BluetoothLEConnectionParameters {
+  /// Shortest connection interval to ask for.
+  min_interval: Duration,
+  /// Longest connection interval to ask for.
+  max_interval: Duration,
+  /// Number of connection events the device may skip when it has nothing to send.
+  latency: u16,
+}
+
+impl BluetoothLEConnectionParameters {
+  pub fn new(min_interval: Duration, max_interval: Duration, latency: u16) -> Self {
+    Self {
+      min_interval,
+      max_interval,
+      latency,
+    }
+  }
+}
+
+/// Merging of rapid write-without-response writes for bluetooth LE hardware.
+///
+/// Some adapters only queue a handful of writes, so a stream of output updates backs up behind the
+/// connection interval and everything sent after it stalls. With coalescing on, writes to the
+/// listed endpoints that land within `window` of the last one only replace the value waiting to go
+/// out, and the latest value is written once the window is over. Only endpoints where every write
+/// carries the device's whole output state should be listed, as older writes are dropped.
+#[derive(PartialEq, Eq, Debug, Clone, Getters, CopyGetters)]
+pub struct BluetoothLEWriteCoalescing {
+  /// How long after a write further writes to the same endpoint are merged.
+  #[getset(get_copy = "pub")]
+  window: Duration,
+  /// Endpoints to merge writes to.
+  #[getset(get = "pub")]
+  endpoints: HashSet<Endpoint>,
+}
+
+impl BluetoothLEWriteCoalescing {
+  pub fn new(window: Duration, endpoints: HashSet<Endpoint>) -> Self {
+    Self { window, endpoints }
+  }
+}
+
 /// Hardware implementation and communication portion of a
 /// [ButtplugDevice](crate::device::ButtplugDevice) instance. The Hardware contains a
 /// HardwareInternal, which handles all of the actual hardware communication. However, the struct
@@ -239,7 +306,16 @@ pub struct Hardware {
   /// Communication endpoints
   endpoints: Vec<Endpoint>,
   /// Internal implementation details
-  internal_impl: Box<dyn HardwareInternal>,
+  internal_impl: Arc<dyn HardwareInternal>,
+  /// Last known endpoint values, for read-modify-write
+  register_cache: Arc<RegisterCache>,
+  /// Held shared by regular traffic, and exclusively by [lock_exclusive](Self::lock_exclusive)
+  access: Arc<RwLock<()>>,
+  /// When the device was last written to and heard from
+  activity: Arc<ActivityTracker>,
+  /// True while the device is taking a firmware update, see
+  /// [set_firmware_mode](Self::set_firmware_mode)
+  firmware_mode: Arc<AtomicBool>,
 }
 
 impl Hardware {
@@ -253,7 +329,11 @@ impl Hardware {
       name: name.to_owned(),
       address: address.to_owned(),
       endpoints: endpoints.into(),
-      internal_impl,
+      internal_impl: internal_impl.into(),
+      register_cache: Arc::new(RegisterCache::default()),
+      access: Arc::new(RwLock::new(())),
+      activity: Arc::new(ActivityTracker::default()),
+      firmware_mode: Arc::new(AtomicBool::new(false)),
     }
   }
 
@@ -280,11 +360,84 @@ impl Hardware {
     self.internal_impl.event_stream()
   }
 
+  /// Returns the btleplug peripheral handle for bluetooth hardware, or None for any other type of
+  /// hardware.
+  ///
+  /// This is an escape hatch for embedders that need operations the hardware abstraction doesn't
+  /// cover (pairing prompts, GATT descriptor changes, etc). Anything done through the peripheral
+  /// happens behind the back of the protocol handling the device, so it's on the caller to not
+  /// break it. **Unstable**: this may change or be removed in any release, including patch
+  /// releases.
+  #[cfg(feature = "unstable-btleplug-peripheral")]
+  pub fn btleplug_peripheral(&self) -> Option<btleplug::platform::Peripheral> {
+    self
+      .internal_impl
+      .btleplug_peripheral()
+      .and_then(|peripheral| peripheral.downcast::<btleplug::platform::Peripheral>().ok())
+      .map(|peripheral| *peripheral)
+  }
+
+  /// When the device was last sent commands and heard from.
+  pub fn activity(&self) -> HardwareActivity {
+    self.activity.activity()
+  }
+
+  /// Note that the device sent a notification. Hardware doesn't watch its own event stream, so this
+  /// is left to whoever does.
+  pub(crate) fn record_notification(&self) {
+    self.activity.record_notification();
+  }
+
+  /// Mark the protocol as keeping the device alive until the returned guard is dropped. Protocols
+  /// that resend the last command on a timer, on top of sending commands as they come in, should
+  /// hold this in their resend loop and resend with [write_keepalive](Self::write_keepalive), so
+  /// resends can be told apart from new commands. Protocols that only send from a loop should
+  /// keep using [write_value](Self::write_value), as their loop is what sends commands.
+  pub fn start_keepalive(&self) -> KeepaliveGuard {
+    self.activity.start_keepalive()
+  }
+
+  /// Put the device in or out of firmware mode. While in firmware mode,
+  /// [write_keepalive](Self::write_keepalive) doesn't write anything, so keepalives don't land in
+  /// the middle of a firmware update sent through raw writes.
+  pub fn set_firmware_mode(&self, enabled: bool) {
+    self.firmware_mode.store(enabled, Ordering::Relaxed);
+  }
+
+  /// Returns true if the device is in firmware mode.
+  pub fn firmware_mode(&self) -> bool {
+    self.firmware_mode.load(Ordering::Relaxed)
+  }
+
   /// Disconnect from the device (if it is connected)
   pub fn disconnect(&self) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
     self.internal_impl.disconnect()
   }
 
+  /// Ask the device to switch to new connection parameters. Fails with
+  /// [ButtplugDeviceError::UnhandledCommand] if the hardware or platform can't make the request.
+  pub fn request_connection_parameters(
+    &self,
+    parameters: &BluetoothLEConnectionParameters,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    self.internal_impl.request_connection_parameters(parameters)
+  }
+
+  /// Merge rapid writes to the endpoints in `coalescing`. Fails with
+  /// [ButtplugDeviceError::UnhandledCommand] if the hardware writes everything as it comes.
+  pub fn set_write_coalescing(
+    &self,
+    coalescing: &BluetoothLEWriteCoalescing,
+  ) -> Result<(), ButtplugDeviceError> {
+    self.internal_impl.set_write_coalescing(coalescing)
+  }
+
+  /// Read the current signal strength of the connection to the device, in dBm. Fails with
+  /// [ButtplugDeviceError::UnhandledCommand] if the hardware has no way to measure it.
+  pub fn read_rssi(&self) -> BoxFuture<'static, Result<i16, ButtplugDeviceError>> {
+    self.internal_impl.read_rssi()
+  }
+
   pub fn parse_message(
     &self,
     command: &HardwareCommand,
@@ -296,6 +449,207 @@ impl Hardware {
     }
   }
 
+  /// Wait until the device is free of [exclusive access](Self::lock_exclusive), then run the
+  /// command built by `command`.
+  fn with_shared_access<T, F>(
+    &self,
+    command: F,
+  ) -> BoxFuture<'static, Result<T, ButtplugDeviceError>>
+  where
+    T: Send + 'static,
+    F: FnOnce(&dyn HardwareInternal) -> BoxFuture<'static, Result<T, ButtplugDeviceError>>
+      + Send
+      + 'static,
+  {
+    let access = self.access.clone();
+    let internal_impl = self.internal_impl.clone();
+    async move {
+      let _guard = access.read().await;
+      command(internal_impl.as_ref()).await
+    }
+    .boxed()
+  }
+
+  /// Wait for the commands already sent to the device to finish, then hold off reads, writes and
+  /// subscriptions from everything else (keepalives, user commands, other init steps) until the
+  /// returned [ExclusiveHardwareAccess] is dropped. Meant for protocols with init or handshake
+  /// sequences that break if other traffic lands in the middle of them.
+  ///
+  /// While holding the lock, talk to the device through the [ExclusiveHardwareAccess] only. Going
+  /// through this [Hardware] waits on the lock, so it'll never finish if the same task is the one
+  /// holding it. Disconnecting, reading RSSI and changing connection parameters aren't blocked.
+  pub fn lock_exclusive(&self) -> BoxFuture<'static, ExclusiveHardwareAccess> {
+    let access = self.access.clone();
+    let internal_impl = self.internal_impl.clone();
+    let register_cache = self.register_cache.clone();
+    let activity = self.activity.clone();
+    async move {
+      ExclusiveHardwareAccess {
+        _guard: access.write_owned().await,
+        internal_impl,
+        register_cache,
+        activity,
+      }
+    }
+    .boxed()
+  }
+
+  /// Read a value from the device
+  pub fn read_value(
+    &self,
+    msg: &HardwareReadCmd,
+  ) -> BoxFuture<'static, Result<HardwareReading, ButtplugDeviceError>> {
+    let msg = *msg;
+    self.with_shared_access(move |internal_impl| internal_impl.read_value(&msg))
+  }
+
+  /// Write a value to the device
+  pub fn write_value(
+    &self,
+    msg: &HardwareWriteCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    self.register_cache.invalidate(msg.endpoint());
+    let msg = msg.clone();
+    let activity = self.activity.clone();
+    self.with_shared_access(move |internal_impl| {
+      activity.track_command(internal_impl.write_value(&msg))
+    })
+  }
+
+  /// Write a value to the device as part of a keepalive. Same as [write_value](Self::write_value),
+  /// but counted as a keepalive instead of a command in [activity](Self::activity). Skipped while
+  /// the device is in [firmware mode](Self::set_firmware_mode).
+  pub fn write_keepalive(
+    &self,
+    msg: &HardwareWriteCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    if self.firmware_mode() {
+      trace!("{} is in firmware mode, skipping keepalive.", self.name);
+      return future::ready(Ok(())).boxed();
+    }
+    self.register_cache.invalidate(msg.endpoint());
+    let msg = msg.clone();
+    let activity = self.activity.clone();
+    self.with_shared_access(move |internal_impl| {
+      activity.track_keepalive(internal_impl.write_value(&msg))
+    })
+  }
+
+  /// Write values to multiple endpoints at once, for devices that expect related writes to
+  /// different characteristics (e.g. a command and its amplitude) to land together.
+  ///
+  /// Writes to the same endpoint go out in the order given, while writes to different endpoints
+  /// are issued concurrently. Resolves with the first error, if any.
+  pub fn write_values(
+    &self,
+    msgs: &[HardwareWriteCmd],
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    for msg in msgs {
+      self.register_cache.invalidate(msg.endpoint());
+    }
+    let msgs = msgs.to_vec();
+    let activity = self.activity.clone();
+    self.with_shared_access(move |internal_impl| {
+      activity.track_command(internal_impl.write_values(&msgs))
+    })
+  }
+
+  /// Read the value of an endpoint, change it with `modify`, and write the result back, returning
+  /// the value written. Meant for devices that keep several settings packed into one register.
+  ///
+  /// Read-modify-writes on the same endpoint run one at a time. The last value read or written is
+  /// cached, so only the first call on an endpoint reads from the device, and nothing is written if
+  /// `modify` leaves the value unchanged. Any other write to the endpoint through this hardware
+  /// drops the cached value. If one lands while the endpoint is being read, the read is stale and
+  /// the whole operation starts over, up to a few times before failing with
+  /// [ButtplugDeviceError::DeviceCommunicationError].
+  ///
+  /// The cache assumes only we change the endpoint. For devices that change it themselves, call
+  /// [invalidate_register_cache](Self::invalidate_register_cache) when they do.
+  pub fn read_modify_write<F>(
+    &self,
+    msg: &HardwareReadCmd,
+    write_with_response: bool,
+    modify: F,
+  ) -> BoxFuture<'static, Result<Vec<u8>, ButtplugDeviceError>>
+  where
+    F: Fn(&[u8]) -> Vec<u8> + Send + 'static,
+  {
+    let internal_impl = self.internal_impl.clone();
+    let register_cache = self.register_cache.clone();
+    let access = self.access.clone();
+    let activity = self.activity.clone();
+    let msg = *msg;
+    async move {
+      let _access = access.read().await;
+      let endpoint = msg.endpoint();
+      let lock = register_cache.lock(endpoint);
+      let _guard = lock.lock().await;
+      for _ in 0..READ_MODIFY_WRITE_ATTEMPTS {
+        let (generation, cached) = register_cache.get(endpoint);
+        let current = match cached {
+          Some(value) => value,
+          None => internal_impl.read_value(&msg).await?.data().clone(),
+        };
+        if register_cache.generation(endpoint) != generation {
+          continue;
+        }
+        let value = modify(&current);
+        if value != current {
+          let write = HardwareWriteCmd::new(endpoint, value.clone(), write_with_response);
+          if let Err(err) = internal_impl.write_value(&write).await {
+            // No telling what state the register was left in.
+            register_cache.invalidate(endpoint);
+            return Err(err);
+          }
+          activity.record_command();
+        }
+        register_cache.store(endpoint, generation, value.clone());
+        return Ok(value);
+      }
+      Err(ButtplugDeviceError::DeviceCommunicationError(format!(
+        "Endpoint {} kept changing during read-modify-write, gave up after {} attempts.",
+        endpoint, READ_MODIFY_WRITE_ATTEMPTS
+      )))
+    }
+    .boxed()
+  }
+
+  /// Forget the cached value of an endpoint, so the next
+  /// [read_modify_write](Self::read_modify_write) reads it from the device.
+  pub fn invalidate_register_cache(&self, endpoint: Endpoint) {
+    self.register_cache.invalidate(endpoint);
+  }
+
+  /// Subscribe to a device endpoint, if it exists
+  pub fn subscribe(
+    &self,
+    msg: &HardwareSubscribeCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    let msg = *msg;
+    self.with_shared_access(move |internal_impl| internal_impl.subscribe(&msg))
+  }
+
+  /// Unsubscribe from a device endpoint, if it exists
+  pub fn unsubscribe(
+    &self,
+    msg: &HardwareUnsubscribeCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    let msg = *msg;
+    self.with_shared_access(move |internal_impl| internal_impl.unsubscribe(&msg))
+  }
+}
+
+/// Sole access to a [Hardware], from [Hardware::lock_exclusive]. Everything else sending to the
+/// device waits until this is dropped.
+pub struct ExclusiveHardwareAccess {
+  _guard: OwnedRwLockWriteGuard<()>,
+  internal_impl: Arc<dyn HardwareInternal>,
+  register_cache: Arc<RegisterCache>,
+  activity: Arc<ActivityTracker>,
+}
+
+impl ExclusiveHardwareAccess {
   /// Read a value from the device
   pub fn read_value(
     &self,
@@ -309,7 +663,27 @@ impl Hardware {
     &self,
     msg: &HardwareWriteCmd,
   ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
-    self.internal_impl.write_value(msg)
+    self.register_cache.invalidate(msg.endpoint());
+    self
+      .activity
+      .track_command(self.internal_impl.write_value(msg))
+  }
+
+  /// Write values to multiple endpoints at once, for devices that expect related writes to
+  /// different characteristics (e.g. a command and its amplitude) to land together.
+  ///
+  /// Writes to the same endpoint go out in the order given, while writes to different endpoints
+  /// are issued concurrently. Resolves with the first error, if any.
+  pub fn write_values(
+    &self,
+    msgs: &[HardwareWriteCmd],
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    for msg in msgs {
+      self.register_cache.invalidate(msg.endpoint());
+    }
+    self
+      .activity
+      .track_command(self.internal_impl.write_values(msgs))
   }
 
   /// Subscribe to a device endpoint, if it exists
@@ -360,13 +734,85 @@ pub trait HardwareInternal: Sync + Send {
     &self,
     msg: &HardwareUnsubscribeCmd,
   ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>>;
+  /// Ask the device to switch to new connection parameters. Only bluetooth LE hardware on platforms
+  /// that let centrals request parameter updates implements this.
+  fn request_connection_parameters(
+    &self,
+    _parameters: &BluetoothLEConnectionParameters,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    future::ready(Err(ButtplugDeviceError::UnhandledCommand(
+      "Hardware cannot request connection parameter updates".to_owned(),
+    )))
+    .boxed()
+  }
+  /// Merge rapid writes to the endpoints in `coalescing`. Only bluetooth LE hardware that writes
+  /// through btleplug implements this.
+  fn set_write_coalescing(
+    &self,
+    _coalescing: &BluetoothLEWriteCoalescing,
+  ) -> Result<(), ButtplugDeviceError> {
+    Err(ButtplugDeviceError::UnhandledCommand(
+      "Hardware cannot coalesce writes".to_owned(),
+    ))
+  }
+  /// Read the current signal strength of the connection to the device, in dBm. Only bluetooth
+  /// hardware implements this.
+  fn read_rssi(&self) -> BoxFuture<'static, Result<i16, ButtplugDeviceError>> {
+    future::ready(Err(ButtplugDeviceError::UnhandledCommand(
+      "Hardware cannot read signal strength".to_owned(),
+    )))
+    .boxed()
+  }
+  /// Returns a clone of the btleplug peripheral handle backing this hardware, boxed so this trait
+  /// doesn't need to know the peripheral type. Only bluetooth hardware returns anything.
+  #[cfg(feature = "unstable-btleplug-peripheral")]
+  fn btleplug_peripheral(&self) -> Option<Box<dyn std::any::Any + Send>> {
+    None
+  }
 }
 
+/// Batched writes on top of [HardwareInternal]. They're kept out of that trait so how they're issued
+/// can change without breaking implementations outside this crate.
+pub(crate) trait HardwareInternalExt: HardwareInternal {
+  /// Issues the writes for [Hardware::write_values], with writes to separate endpoints running
+  /// concurrently.
+  fn write_values(
+    &self,
+    msgs: &[HardwareWriteCmd],
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    let mut endpoint_writes: Vec<(Endpoint, Vec<_>)> = vec![];
+    for msg in msgs {
+      let write = self.write_value(msg);
+      match endpoint_writes
+        .iter_mut()
+        .find(|(endpoint, _)| *endpoint == msg.endpoint)
+      {
+        Some((_, writes)) => writes.push(write),
+        None => endpoint_writes.push((msg.endpoint, vec![write])),
+      }
+    }
+    future::try_join_all(endpoint_writes.into_iter().map(|(_, writes)| async move {
+      for write in writes {
+        write.await?;
+      }
+      Ok(())
+    }))
+    .map(|result| result.map(|_| ()))
+    .boxed()
+  }
+}
+
+impl<T: HardwareInternal + ?Sized> HardwareInternalExt for T {}
+
 #[async_trait]
 pub trait HardwareConnector: Sync + Send + Debug {
   /// Return the hardware identifier for the device. Depends on the communication bus type, so may
   /// be a bluetooth name, serial port name, etc...
   fn specifier(&self) -> ProtocolCommunicationSpecifier;
+  /// Signal strength of the device when it was found, for buses that have such a thing.
+  fn rssi(&self) -> Option<i16> {
+    None
+  }
   async fn connect(&mut self) -> Result<Box<dyn HardwareSpecializer>, ButtplugDeviceError>;
 }
 
@@ -406,3 +852,338 @@ impl HardwareSpecializer for GenericHardwareSpecializer {
     Ok(self.hardware.take().expect("This should only be run once"))
   }
 }
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::util::sleep;
+  use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Mutex,
+  };
+
+  // Logs the start and end of every write, taking long enough that concurrent writes overlap.
+  struct SlowHardware {
+    log: Arc<Mutex<Vec<(Endpoint, u8, bool)>>>,
+  }
+
+  impl HardwareInternal for SlowHardware {
+    fn disconnect(&self) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+      future::ready(Ok(())).boxed()
+    }
+
+    fn event_stream(&self) -> broadcast::Receiver<HardwareEvent> {
+      broadcast::channel(1).1
+    }
+
+    fn read_value(
+      &self,
+      _msg: &HardwareReadCmd,
+    ) -> BoxFuture<'static, Result<HardwareReading, ButtplugDeviceError>> {
+      unimplemented!("Test hardware only writes")
+    }
+
+    fn write_value(
+      &self,
+      msg: &HardwareWriteCmd,
+    ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+      let log = self.log.clone();
+      let endpoint = msg.endpoint();
+      let value = msg.data()[0];
+      async move {
+        if endpoint == Endpoint::Rx {
+          return Err(ButtplugDeviceError::InvalidEndpoint(endpoint));
+        }
+        log.lock().expect("Test").push((endpoint, value, false));
+        sleep(Duration::from_millis(50)).await;
+        log.lock().expect("Test").push((endpoint, value, true));
+        Ok(())
+      }
+      .boxed()
+    }
+
+    fn subscribe(
+      &self,
+      _msg: &HardwareSubscribeCmd,
+    ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+      unimplemented!("Test hardware only writes")
+    }
+
+    fn unsubscribe(
+      &self,
+      _msg: &HardwareUnsubscribeCmd,
+    ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+      unimplemented!("Test hardware only writes")
+    }
+  }
+
+  #[tokio::test]
+  async fn test_write_values_concurrent_across_endpoints() {
+    let log = Arc::new(Mutex::new(vec![]));
+    let hardware = Hardware::new(
+      "Test",
+      "test",
+      &[Endpoint::Tx, Endpoint::TxMode],
+      Box::new(SlowHardware { log: log.clone() }),
+    );
+    hardware
+      .write_values(&[
+        HardwareWriteCmd::new(Endpoint::Tx, vec![1], false),
+        HardwareWriteCmd::new(Endpoint::TxMode, vec![2], false),
+        HardwareWriteCmd::new(Endpoint::Tx, vec![3], false),
+      ])
+      .await
+      .expect("Test");
+    let log = log.lock().expect("Test").clone();
+    // Both endpoints start before either finishes its first write...
+    assert_eq!(
+      log[..2],
+      [(Endpoint::Tx, 1, false), (Endpoint::TxMode, 2, false)]
+    );
+    // ...but the second Tx write waits for the first.
+    let tx_log: Vec<_> = log
+      .iter()
+      .filter(|(endpoint, _, _)| *endpoint == Endpoint::Tx)
+      .collect();
+    assert_eq!(
+      tx_log,
+      [
+        &(Endpoint::Tx, 1, false),
+        &(Endpoint::Tx, 1, true),
+        &(Endpoint::Tx, 3, false),
+        &(Endpoint::Tx, 3, true)
+      ]
+    );
+
+    let result = hardware
+      .write_values(&[
+        HardwareWriteCmd::new(Endpoint::Tx, vec![4], false),
+        HardwareWriteCmd::new(Endpoint::Rx, vec![5], false),
+      ])
+      .await;
+    assert_eq!(
+      result,
+      Err(ButtplugDeviceError::InvalidEndpoint(Endpoint::Rx))
+    );
+  }
+
+  #[tokio::test]
+  async fn test_lock_exclusive() {
+    let log = Arc::new(Mutex::new(vec![]));
+    let hardware = Hardware::new(
+      "Test",
+      "test",
+      &[Endpoint::Tx],
+      Box::new(SlowHardware { log: log.clone() }),
+    );
+    let exclusive = hardware.lock_exclusive().await;
+    // Sent while the lock is held, so it has to wait for the whole init sequence.
+    let write = hardware.write_value(&HardwareWriteCmd::new(Endpoint::Tx, vec![1], false));
+    let init = async {
+      for value in [2, 3] {
+        exclusive
+          .write_value(&HardwareWriteCmd::new(Endpoint::Tx, vec![value], false))
+          .await
+          .expect("Test");
+      }
+      drop(exclusive);
+    };
+    let (write, _) = futures::join!(write, init);
+    write.expect("Test");
+    assert_eq!(
+      *log.lock().expect("Test"),
+      [
+        (Endpoint::Tx, 2, false),
+        (Endpoint::Tx, 2, true),
+        (Endpoint::Tx, 3, false),
+        (Endpoint::Tx, 3, true),
+        (Endpoint::Tx, 1, false),
+        (Endpoint::Tx, 1, true)
+      ]
+    );
+  }
+
+  #[tokio::test]
+  async fn test_activity() {
+    let hardware = Hardware::new(
+      "Test",
+      "test",
+      &[Endpoint::Tx],
+      Box::new(SlowHardware {
+        log: Arc::new(Mutex::new(vec![])),
+      }),
+    );
+    assert_eq!(hardware.activity(), HardwareActivity::default());
+
+    // Failed writes don't count.
+    assert!(hardware
+      .write_value(&HardwareWriteCmd::new(Endpoint::Rx, vec![1], false))
+      .await
+      .is_err());
+    assert!(hardware.activity().last_command_sent().is_none());
+
+    // Keepalives are kept apart from commands.
+    let keepalive = hardware.start_keepalive();
+    assert!(hardware.activity().keepalive_active_since().is_some());
+    hardware
+      .write_keepalive(&HardwareWriteCmd::new(Endpoint::Tx, vec![1], false))
+      .await
+      .expect("Test");
+    let activity = hardware.activity();
+    assert!(activity.last_keepalive_sent().is_some());
+    assert!(activity.last_command_sent().is_none());
+    drop(keepalive);
+    assert!(hardware.activity().keepalive_active_since().is_none());
+
+    hardware
+      .write_value(&HardwareWriteCmd::new(Endpoint::Tx, vec![2], false))
+      .await
+      .expect("Test");
+    assert!(hardware.activity().last_command_sent().is_some());
+    hardware.record_notification();
+    let activity = hardware.activity();
+    assert!(activity.last_notification_received().is_some());
+    // Stopping the keepalive doesn't forget when it last sent.
+    assert!(activity.last_keepalive_sent().is_some());
+  }
+
+  #[tokio::test]
+  async fn test_firmware_mode_skips_keepalives() {
+    let log = Arc::new(Mutex::new(vec![]));
+    let hardware = Hardware::new(
+      "Test",
+      "test",
+      &[Endpoint::Tx, Endpoint::Firmware],
+      Box::new(SlowHardware { log: log.clone() }),
+    );
+    let keepalive = HardwareWriteCmd::new(Endpoint::Tx, vec![1], false);
+    hardware.set_firmware_mode(true);
+    hardware.write_keepalive(&keepalive).await.expect("Test");
+    // Writes that aren't keepalives, like the update itself, still go through.
+    hardware
+      .write_value(&HardwareWriteCmd::new(Endpoint::Firmware, vec![2], false))
+      .await
+      .expect("Test");
+    hardware.set_firmware_mode(false);
+    hardware.write_keepalive(&keepalive).await.expect("Test");
+    assert_eq!(
+      *log.lock().expect("Test"),
+      vec![
+        (Endpoint::Firmware, 2, false),
+        (Endpoint::Firmware, 2, true),
+        (Endpoint::Tx, 1, false),
+        (Endpoint::Tx, 1, true)
+      ]
+    );
+  }
+
+  // Single register, counting how often it's read. Reads take a while so writes can race them.
+  struct RegisterHardware {
+    register: Arc<Mutex<Vec<u8>>>,
+    reads: Arc<AtomicUsize>,
+  }
+
+  impl HardwareInternal for RegisterHardware {
+    fn disconnect(&self) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+      future::ready(Ok(())).boxed()
+    }
+
+    fn event_stream(&self) -> broadcast::Receiver<HardwareEvent> {
+      broadcast::channel(1).1
+    }
+
+    fn read_value(
+      &self,
+      msg: &HardwareReadCmd,
+    ) -> BoxFuture<'static, Result<HardwareReading, ButtplugDeviceError>> {
+      let register = self.register.clone();
+      let endpoint = msg.endpoint();
+      self.reads.fetch_add(1, Ordering::SeqCst);
+      async move {
+        sleep(Duration::from_millis(50)).await;
+        let data = register.lock().expect("Test").clone();
+        Ok(HardwareReading::new(endpoint, &data))
+      }
+      .boxed()
+    }
+
+    fn write_value(
+      &self,
+      msg: &HardwareWriteCmd,
+    ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+      *self.register.lock().expect("Test") = msg.data().clone();
+      future::ready(Ok(())).boxed()
+    }
+
+    fn subscribe(
+      &self,
+      _msg: &HardwareSubscribeCmd,
+    ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+      unimplemented!("Test hardware only reads and writes")
+    }
+
+    fn unsubscribe(
+      &self,
+      _msg: &HardwareUnsubscribeCmd,
+    ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+      unimplemented!("Test hardware only reads and writes")
+    }
+  }
+
+  #[tokio::test]
+  async fn test_read_modify_write() {
+    let register = Arc::new(Mutex::new(vec![0b0001]));
+    let reads = Arc::new(AtomicUsize::new(0));
+    let hardware = Hardware::new(
+      "Test",
+      "test",
+      &[Endpoint::Tx],
+      Box::new(RegisterHardware {
+        register: register.clone(),
+        reads: reads.clone(),
+      }),
+    );
+    let read = HardwareReadCmd::new(Endpoint::Tx, 1, 0);
+    let set_bit = |bit: u8| move |value: &[u8]| vec![value[0] | bit];
+
+    // Only the first call reads, later ones work from the cache.
+    let value = hardware
+      .read_modify_write(&read, false, set_bit(0b0010))
+      .await
+      .expect("Test");
+    assert_eq!(value, vec![0b0011]);
+    let value = hardware
+      .read_modify_write(&read, false, set_bit(0b0100))
+      .await
+      .expect("Test");
+    assert_eq!(value, vec![0b0111]);
+    assert_eq!(*register.lock().expect("Test"), vec![0b0111]);
+    assert_eq!(reads.load(Ordering::SeqCst), 1);
+
+    // A plain write drops the cache.
+    hardware
+      .write_value(&HardwareWriteCmd::new(Endpoint::Tx, vec![0b1000], false))
+      .await
+      .expect("Test");
+    let value = hardware
+      .read_modify_write(&read, false, set_bit(0b0001))
+      .await
+      .expect("Test");
+    assert_eq!(value, vec![0b1001]);
+    assert_eq!(reads.load(Ordering::SeqCst), 2);
+
+    // A write landing mid-read makes the read stale, so it's retried.
+    hardware.invalidate_register_cache(Endpoint::Tx);
+    let rmw = hardware.read_modify_write(&read, false, set_bit(0b0010));
+    let write = async {
+      sleep(Duration::from_millis(10)).await;
+      hardware
+        .write_value(&HardwareWriteCmd::new(Endpoint::Tx, vec![0b0100], false))
+        .await
+    };
+    let (value, write) = futures::join!(rmw, write);
+    write.expect("Test");
+    assert_eq!(value.expect("Test"), vec![0b0110]);
+    assert_eq!(reads.load(Ordering::SeqCst), 4);
+  }
+}
@@ -0,0 +1,68 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Per-endpoint cache backing [Hardware::read_modify_write](super::Hardware::read_modify_write)
+//!
+//! Every endpoint tracks a generation, which is bumped by any write that doesn't go through
+//! read-modify-write. Read-modify-write notes the generation when it reads, and only writes back
+//! (and caches the result) if nothing else wrote in the meantime.
+
+use crate::core::message::Endpoint;
+use dashmap::DashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+#[derive(Default)]
+struct EndpointRegister {
+  /// Held for the whole of a read-modify-write, so they run one at a time per endpoint.
+  lock: Arc<Mutex<()>>,
+  generation: u64,
+  value: Option<Vec<u8>>,
+}
+
+#[derive(Default)]
+pub(super) struct RegisterCache {
+  registers: DashMap<Endpoint, EndpointRegister>,
+}
+
+impl RegisterCache {
+  pub fn lock(&self, endpoint: Endpoint) -> Arc<Mutex<()>> {
+    self.registers.entry(endpoint).or_default().lock.clone()
+  }
+
+  /// Current generation of the endpoint, along with its cached value if there is one.
+  pub fn get(&self, endpoint: Endpoint) -> (u64, Option<Vec<u8>>) {
+    let register = self.registers.entry(endpoint).or_default();
+    (register.generation, register.value.clone())
+  }
+
+  pub fn generation(&self, endpoint: Endpoint) -> u64 {
+    self
+      .registers
+      .get(&endpoint)
+      .map_or(0, |register| register.generation)
+  }
+
+  /// Caches a value read from or written to the device, unless the endpoint has been written
+  /// since `generation`. Returns whether the value was stored.
+  pub fn store(&self, endpoint: Endpoint, generation: u64, value: Vec<u8>) -> bool {
+    let mut register = self.registers.entry(endpoint).or_default();
+    if register.generation != generation {
+      return false;
+    }
+    register.value = Some(value);
+    true
+  }
+
+  /// Drops the cached value and marks the endpoint as changed by someone else.
+  pub fn invalidate(&self, endpoint: Endpoint) {
+    if let Some(mut register) = self.registers.get_mut(&endpoint) {
+      register.generation += 1;
+      register.value = None;
+    }
+  }
+}
@@ -0,0 +1,218 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Position estimates for open-loop strokers.
+//!
+//! Most linear devices take a position and a duration, and never report where they actually are.
+//! Assuming the device moves at a constant speed toward whatever it was last told to, we can work
+//! out where it should be at any point, and hand that to clients as a Position sensor. Apps can use
+//! that to draw the device, or to start a new move from where the device is instead of from where
+//! it was last sent.
+
+use super::configuration::ProtocolDeviceAttributes;
+use crate::core::message::{LinearCmd, SensorDeviceMessageAttributes, SensorType};
+use std::{
+  sync::Mutex,
+  time::{Duration, Instant},
+};
+
+/// Top of the range of estimated position readings, which go from 0 (position 0.0) to this
+/// (position 1.0).
+pub(super) const ESTIMATED_POSITION_MAX: u32 = 1000;
+
+/// How often subscriptions to an estimated position sensor check for a new estimate.
+pub(super) const ESTIMATED_POSITION_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A move from one position to another, at a constant speed.
+#[derive(Debug, Clone, Copy)]
+struct Trajectory {
+  from: f64,
+  to: f64,
+  started: Instant,
+  duration: Duration,
+}
+
+impl Trajectory {
+  fn position_at(&self, now: Instant) -> f64 {
+    let elapsed = now.saturating_duration_since(self.started);
+    if elapsed >= self.duration {
+      return self.to;
+    }
+    let progress = elapsed.as_secs_f64() / self.duration.as_secs_f64();
+    self.from + (self.to - self.from) * progress
+  }
+}
+
+/// Tracks the LinearCmd moves sent to a device, and estimates the position of each axis from them.
+#[derive(Debug)]
+pub(super) struct LinearPositionEstimator {
+  /// Last move sent to each linear axis, or None if the axis hasn't been moved yet.
+  axes: Mutex<Vec<Option<Trajectory>>>,
+  /// SensorReadCmd index of the sensor for each axis.
+  read_sensor_indexes: Vec<u32>,
+  /// SensorSubscribeCmd index of the sensor for each axis.
+  subscribe_sensor_indexes: Vec<u32>,
+}
+
+impl LinearPositionEstimator {
+  /// Adds an estimated Position sensor for each linear axis of a device, if the device takes
+  /// LinearCmd and doesn't already have position sensors of its own. Returns None if the device
+  /// doesn't need an estimator.
+  pub(super) fn for_device(attributes: &mut ProtocolDeviceAttributes) -> Option<Self> {
+    let message_attributes = attributes.message_attributes();
+    let axis_count = message_attributes.linear_cmd().as_ref()?.len();
+    if message_attributes
+      .sensor_read_cmd()
+      .iter()
+      .flatten()
+      .any(|sensor| *sensor.sensor_type() == SensorType::Position)
+    {
+      return None;
+    }
+    let mut read_sensor_indexes = vec![];
+    let mut subscribe_sensor_indexes = vec![];
+    for axis in 0..axis_count {
+      let (read_index, subscribe_index) =
+        attributes.add_server_sensor(SensorDeviceMessageAttributes::new(
+          &format!("Estimated Position (Axis {})", axis),
+          SensorType::Position,
+          vec![0..=ESTIMATED_POSITION_MAX],
+        ));
+      read_sensor_indexes.push(read_index);
+      subscribe_sensor_indexes.push(subscribe_index);
+    }
+    Some(Self {
+      axes: Mutex::new(vec![None; axis_count]),
+      read_sensor_indexes,
+      subscribe_sensor_indexes,
+    })
+  }
+
+  /// Linear axis that a SensorReadCmd sensor index estimates, if any.
+  pub(super) fn read_sensor_axis(&self, sensor_index: u32) -> Option<usize> {
+    self
+      .read_sensor_indexes
+      .iter()
+      .position(|index| *index == sensor_index)
+  }
+
+  /// Linear axis that a SensorSubscribeCmd sensor index estimates, if any.
+  pub(super) fn subscribe_sensor_axis(&self, sensor_index: u32) -> Option<usize> {
+    self
+      .subscribe_sensor_indexes
+      .iter()
+      .position(|index| *index == sensor_index)
+  }
+
+  /// Records the moves of a LinearCmd that was sent to the device.
+  pub(super) fn command(&self, message: &LinearCmd) {
+    self.command_at(message, Instant::now());
+  }
+
+  fn command_at(&self, message: &LinearCmd, now: Instant) {
+    let mut axes = self
+      .axes
+      .lock()
+      .expect("Estimator lock should never be poisoned.");
+    for vector in message.vectors() {
+      if let Some(axis) = axes.get_mut(vector.index() as usize) {
+        // New moves start from wherever the last one has gotten to, which may be partway there.
+        let from = axis
+          .map(|trajectory| trajectory.position_at(now))
+          .unwrap_or(0.0);
+        *axis = Some(Trajectory {
+          from,
+          to: vector.position().clamp(0.0, 1.0),
+          started: now,
+          duration: Duration::from_millis(vector.duration() as u64),
+        });
+      }
+    }
+  }
+
+  /// Estimated position of an axis, from 0 to [ESTIMATED_POSITION_MAX]. Axes are assumed to be at 0
+  /// until they're first moved, as there's no way to know where they start.
+  pub(super) fn position(&self, axis: usize) -> i32 {
+    self.position_at(axis, Instant::now())
+  }
+
+  fn position_at(&self, axis: usize, now: Instant) -> i32 {
+    let axes = self
+      .axes
+      .lock()
+      .expect("Estimator lock should never be poisoned.");
+    let position = axes
+      .get(axis)
+      .copied()
+      .flatten()
+      .map(|trajectory| trajectory.position_at(now))
+      .unwrap_or(0.0);
+    (position * ESTIMATED_POSITION_MAX as f64).round() as i32
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::core::message::VectorSubcommand;
+
+  fn estimator(axis_count: usize) -> LinearPositionEstimator {
+    LinearPositionEstimator {
+      axes: Mutex::new(vec![None; axis_count]),
+      read_sensor_indexes: (0..axis_count as u32).collect(),
+      subscribe_sensor_indexes: (0..axis_count as u32).collect(),
+    }
+  }
+
+  #[test]
+  fn test_position_follows_moves() {
+    let estimator = estimator(2);
+    let start = Instant::now();
+    assert_eq!(estimator.position_at(0, start), 0);
+
+    estimator.command_at(
+      &LinearCmd::new(0, vec![VectorSubcommand::new(0, 1000, 1.0)]),
+      start,
+    );
+    assert_eq!(estimator.position_at(0, start), 0);
+    assert_eq!(
+      estimator.position_at(0, start + Duration::from_millis(250)),
+      250
+    );
+    assert_eq!(
+      estimator.position_at(0, start + Duration::from_secs(2)),
+      1000
+    );
+    // Axes that weren't in the command don't move.
+    assert_eq!(estimator.position_at(1, start + Duration::from_secs(2)), 0);
+  }
+
+  #[test]
+  fn test_retargeting_starts_from_estimate() {
+    let estimator = estimator(1);
+    let start = Instant::now();
+    estimator.command_at(
+      &LinearCmd::new(0, vec![VectorSubcommand::new(0, 1000, 1.0)]),
+      start,
+    );
+    // Halfway up, turn around and head back to 0 over 500ms.
+    let retarget = start + Duration::from_millis(500);
+    estimator.command_at(
+      &LinearCmd::new(0, vec![VectorSubcommand::new(0, 500, 0.0)]),
+      retarget,
+    );
+    assert_eq!(estimator.position_at(0, retarget), 500);
+    assert_eq!(
+      estimator.position_at(0, retarget + Duration::from_millis(250)),
+      250
+    );
+    assert_eq!(
+      estimator.position_at(0, retarget + Duration::from_secs(1)),
+      0
+    );
+  }
+}
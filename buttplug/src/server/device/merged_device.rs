@@ -0,0 +1,395 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Two or more devices presented to clients as a single device.
+//!
+//! The output features of the members (ScalarCmd, RotateCmd and LinearCmd) are listed one after
+//! the other in member order, and commands sent to the merged device are split up into commands for
+//! each member. Sensors and raw commands stay with the members, which are still listed and usable
+//! on their own. A merged device is listed while all of its members are connected, under an index
+//! reserved for its name, so it keeps the same index across reconnects.
+
+use super::{
+  configuration::{
+    DeviceConfigurationManager,
+    ProtocolAttributesType,
+    ServerDeviceMessageAttributes,
+    ServerDeviceMessageAttributesBuilder,
+    ServerGenericDeviceMessageAttributes,
+  },
+  ServerDevice,
+  ServerDeviceIdentifier,
+};
+use crate::{
+  core::{
+    errors::{ButtplugDeviceError, ButtplugError},
+    message::{
+      self,
+      ButtplugDeviceCommandMessageUnion,
+      ButtplugDeviceMessage,
+      ButtplugServerMessage,
+      DeviceAdded,
+      DeviceMessageInfo,
+      DeviceRemoved,
+      LinearCmd,
+      RotateCmd,
+      RotationSubcommand,
+      ScalarCmd,
+      ScalarSubcommand,
+      StopDeviceCmd,
+      VectorSubcommand,
+    },
+  },
+  server::ButtplugServerResultFuture,
+};
+use dashmap::DashMap;
+use futures::{future, FutureExt};
+use std::{
+  collections::HashSet,
+  sync::{Arc, Mutex},
+};
+
+/// Protocol name for the identifiers merged devices reserve their indexes under.
+const MERGED_DEVICE_PROTOCOL: &str = "merged";
+
+/// Identifier a merged device reserves its index under.
+fn merged_device_identifier(name: &str) -> ServerDeviceIdentifier {
+  ServerDeviceIdentifier::new(name, MERGED_DEVICE_PROTOCOL, &ProtocolAttributesType::Default)
+}
+
+type FeatureAttributes = Option<Vec<ServerGenericDeviceMessageAttributes>>;
+
+/// Number of features each member has for a command.
+fn feature_counts(
+  members: &[Arc<ServerDevice>],
+  features: impl Fn(&ServerDeviceMessageAttributes) -> &FeatureAttributes,
+) -> Vec<usize> {
+  members
+    .iter()
+    .map(|member| features(&member.message_attributes()).as_ref().map_or(0, |f| f.len()))
+    .collect()
+}
+
+/// Sorts subcommands for the merged device into subcommands for each member, with their indexes
+/// changed to the index of the feature on that member.
+fn split_subcommands<T>(
+  counts: &[usize],
+  subcommands: &[T],
+  index: impl Fn(&T) -> u32,
+  reindex: impl Fn(&T, u32) -> T,
+) -> Result<Vec<Vec<T>>, ButtplugDeviceError> {
+  let total = counts.iter().sum::<usize>() as u32;
+  let mut split: Vec<Vec<T>> = counts.iter().map(|_| vec![]).collect();
+  for subcommand in subcommands {
+    let mut feature_index = index(subcommand);
+    let mut member = 0;
+    while member < counts.len() && feature_index as usize >= counts[member] {
+      feature_index -= counts[member] as u32;
+      member += 1;
+    }
+    if member == counts.len() {
+      return Err(ButtplugDeviceError::DeviceFeatureIndexError(
+        total,
+        index(subcommand),
+      ));
+    }
+    split[member].push(reindex(subcommand, feature_index));
+  }
+  Ok(split)
+}
+
+/// Message attributes of a merged device, made up of the output features of its members.
+fn merged_message_attributes(members: &[Arc<ServerDevice>]) -> ServerDeviceMessageAttributes {
+  let mut scalar_cmd = vec![];
+  let mut rotate_cmd = vec![];
+  let mut linear_cmd = vec![];
+  for member in members {
+    let attributes = member.message_attributes();
+    scalar_cmd.extend(attributes.scalar_cmd().clone().unwrap_or_default());
+    rotate_cmd.extend(attributes.rotate_cmd().clone().unwrap_or_default());
+    linear_cmd.extend(attributes.linear_cmd().clone().unwrap_or_default());
+  }
+  let mut builder = ServerDeviceMessageAttributesBuilder::default();
+  if !scalar_cmd.is_empty() {
+    builder.scalar_cmd(&scalar_cmd);
+  }
+  if !rotate_cmd.is_empty() {
+    builder.rotate_cmd(&rotate_cmd);
+  }
+  if !linear_cmd.is_empty() {
+    builder.linear_cmd(&linear_cmd);
+  }
+  builder.finish()
+}
+
+/// A merged device that is currently listed.
+struct MergedDevice {
+  name: String,
+  /// Indexes of the members, in member order.
+  members: Vec<u32>,
+}
+
+impl MergedDevice {
+  fn members(
+    &self,
+    devices: &DashMap<u32, Arc<ServerDevice>>,
+  ) -> Result<Vec<Arc<ServerDevice>>, ButtplugDeviceError> {
+    self
+      .members
+      .iter()
+      .map(|index| {
+        devices
+          .get(index)
+          .map(|device| device.value().clone())
+          .ok_or(ButtplugDeviceError::DeviceNotAvailable(*index))
+      })
+      .collect()
+  }
+
+  fn device_message_info(
+    &self,
+    device_index: u32,
+    devices: &DashMap<u32, Arc<ServerDevice>>,
+  ) -> Option<DeviceMessageInfo> {
+    let members = self.members(devices).ok()?;
+    Some(DeviceMessageInfo::new(
+      device_index,
+      &self.name,
+      &None,
+      &None,
+      merged_message_attributes(&members).into(),
+    ))
+  }
+
+  /// Splits a command for the merged device into commands for its members.
+  fn member_commands(
+    &self,
+    members: &[Arc<ServerDevice>],
+    command: ButtplugDeviceCommandMessageUnion,
+  ) -> Result<Vec<(usize, ButtplugDeviceCommandMessageUnion)>, ButtplugDeviceError> {
+    let commands: Vec<(usize, ButtplugDeviceCommandMessageUnion)> = match command {
+      ButtplugDeviceCommandMessageUnion::ScalarCmd(msg) => split_subcommands(
+        &feature_counts(members, |attrs| attrs.scalar_cmd()),
+        msg.scalars(),
+        |scalar| scalar.index(),
+        |scalar, index| ScalarSubcommand::new(index, scalar.scalar(), scalar.actuator_type()),
+      )?
+      .into_iter()
+      .enumerate()
+      .filter(|(_, scalars)| !scalars.is_empty())
+      .map(|(member, scalars)| (member, ScalarCmd::new(self.members[member], scalars).into()))
+      .collect(),
+      ButtplugDeviceCommandMessageUnion::VibrateCmd(msg) => {
+        return self.member_commands(members, ScalarCmd::from(msg).into())
+      }
+      ButtplugDeviceCommandMessageUnion::RotateCmd(msg) => split_subcommands(
+        &feature_counts(members, |attrs| attrs.rotate_cmd()),
+        msg.rotations(),
+        |rotation| rotation.index(),
+        |rotation, index| RotationSubcommand::new(index, rotation.speed(), rotation.clockwise()),
+      )?
+      .into_iter()
+      .enumerate()
+      .filter(|(_, rotations)| !rotations.is_empty())
+      .map(|(member, rotations)| (member, RotateCmd::new(self.members[member], rotations).into()))
+      .collect(),
+      ButtplugDeviceCommandMessageUnion::LinearCmd(msg) => split_subcommands(
+        &feature_counts(members, |attrs| attrs.linear_cmd()),
+        msg.vectors(),
+        |vector| vector.index(),
+        |vector, index| VectorSubcommand::new(index, vector.duration(), vector.position()),
+      )?
+      .into_iter()
+      .enumerate()
+      .filter(|(_, vectors)| !vectors.is_empty())
+      .map(|(member, vectors)| (member, LinearCmd::new(self.members[member], vectors).into()))
+      .collect(),
+      ButtplugDeviceCommandMessageUnion::StopDeviceCmd(_) => self
+        .members
+        .iter()
+        .enumerate()
+        .map(|(member, index)| (member, StopDeviceCmd::new(*index).into()))
+        .collect(),
+      _ => {
+        return Err(ButtplugDeviceError::UnhandledCommand(
+          "Merged devices only take ScalarCmd, RotateCmd, LinearCmd and StopDeviceCmd".to_owned(),
+        ))
+      }
+    };
+    Ok(commands)
+  }
+}
+
+/// Merged device definitions, and which of them are currently listed.
+#[derive(Default)]
+pub(super) struct MergedDevices {
+  /// Member identifiers of each merged device, by name.
+  definitions: DashMap<String, Vec<ServerDeviceIdentifier>>,
+  /// Merged devices currently listed, by index.
+  listed: DashMap<u32, MergedDevice>,
+  /// Held while working out which merged devices should be listed, so that the device manager and
+  /// its event loop can't both list (or unlist) the same device.
+  update_lock: Mutex<()>,
+}
+
+impl MergedDevices {
+  pub fn new(definitions: &[(String, Vec<ServerDeviceIdentifier>)]) -> Self {
+    let merged_devices = Self::default();
+    for (name, members) in definitions {
+      if let Err(err) = merged_devices.add(name, members) {
+        warn!("Ignoring merged device configuration {}: {}", name, err);
+      }
+    }
+    merged_devices
+  }
+
+  /// Adds a merged device. Fails if the name is already taken, or if there aren't at least two
+  /// distinct members.
+  pub fn add(
+    &self,
+    name: &str,
+    members: &[ServerDeviceIdentifier],
+  ) -> Result<(), ButtplugDeviceError> {
+    if members.iter().collect::<HashSet<_>>().len() != members.len() || members.len() < 2 {
+      return Err(ButtplugDeviceError::DeviceConfigurationError(format!(
+        "Merged device {} needs at least two different devices",
+        name
+      )));
+    }
+    if self.definitions.contains_key(name) {
+      return Err(ButtplugDeviceError::DeviceConfigurationError(format!(
+        "Merged device {} already exists",
+        name
+      )));
+    }
+    self
+      .definitions
+      .insert(name.to_owned(), members.to_vec());
+    Ok(())
+  }
+
+  /// Removes a merged device, returning false if there isn't one with the given name.
+  pub fn remove(&self, name: &str) -> bool {
+    self.definitions.remove(name).is_some()
+  }
+
+  /// Lists merged devices that have all their members connected, and unlists ones that lost a
+  /// member or were removed. Returns DeviceAdded and DeviceRemoved messages for the changes.
+  pub fn update(
+    &self,
+    devices: &DashMap<u32, Arc<ServerDevice>>,
+    device_config_manager: &DeviceConfigurationManager,
+  ) -> Vec<ButtplugServerMessage> {
+    let _guard = self
+      .update_lock
+      .lock()
+      .expect("Merged device lock should never be poisoned.");
+    let member_index = |identifier: &ServerDeviceIdentifier| {
+      devices
+        .iter()
+        .find(|device| device.value().identifier() == identifier)
+        .map(|device| *device.key())
+    };
+    let mut messages = vec![];
+
+    self.listed.retain(|index, merged| {
+      let members = self
+        .definitions
+        .get(&merged.name)
+        .map(|definition| definition.iter().map(member_index).collect::<Option<Vec<_>>>());
+      if members == Some(Some(merged.members.clone())) {
+        return true;
+      }
+      info!("Merged device {} no longer available.", merged.name);
+      messages.push(DeviceRemoved::new(*index).into());
+      false
+    });
+
+    for definition in self.definitions.iter() {
+      let name = definition.key();
+      if self.listed.iter().any(|merged| merged.name == *name) {
+        continue;
+      }
+      let member_indexes: Option<Vec<u32>> = definition.value().iter().map(member_index).collect();
+      if let Some(member_indexes) = member_indexes {
+        let device_index = device_config_manager.device_index(&merged_device_identifier(name));
+        let merged = MergedDevice {
+          name: name.clone(),
+          members: member_indexes,
+        };
+        if let Some(info) = merged.device_message_info(device_index, devices) {
+          info!("Merged device {} available at index {}.", name, device_index);
+          messages.push(
+            DeviceAdded::new(
+              device_index,
+              info.device_name(),
+              &None,
+              &None,
+              info.device_messages(),
+            )
+            .into(),
+          );
+          self.listed.insert(device_index, merged);
+        }
+      }
+    }
+    messages
+  }
+
+  /// Entries for all listed merged devices, for device lists.
+  pub fn device_message_infos(
+    &self,
+    devices: &DashMap<u32, Arc<ServerDevice>>,
+  ) -> Vec<DeviceMessageInfo> {
+    self
+      .listed
+      .iter()
+      .filter_map(|merged| merged.device_message_info(*merged.key(), devices))
+      .collect()
+  }
+
+  /// Entry for a listed merged device, if there is one at the index.
+  pub fn device_message_info(
+    &self,
+    device_index: u32,
+    devices: &DashMap<u32, Arc<ServerDevice>>,
+  ) -> Option<DeviceMessageInfo> {
+    self
+      .listed
+      .get(&device_index)
+      .and_then(|merged| merged.device_message_info(device_index, devices))
+  }
+
+  /// Sends a command for a merged device to its members. Returns None if there's no merged device
+  /// at the index the command is for.
+  pub fn parse_message(
+    &self,
+    devices: &DashMap<u32, Arc<ServerDevice>>,
+    command: ButtplugDeviceCommandMessageUnion,
+  ) -> Option<ButtplugServerResultFuture> {
+    let merged = self.listed.get(&command.device_index())?;
+    let commands = merged
+      .members(devices)
+      .and_then(|members| Ok((merged.member_commands(&members, command)?, members)));
+    let futures: Vec<_> = match commands {
+      Ok((commands, members)) => commands
+        .into_iter()
+        .map(|(member, command)| members[member].parse_message(command))
+        .collect(),
+      Err(err) => return Some(future::ready(Err(ButtplugError::from(err))).boxed()),
+    };
+    Some(
+      async move {
+        for result in future::join_all(futures).await {
+          result?;
+        }
+        Ok(message::Ok::default().into())
+      }
+      .boxed(),
+    )
+  }
+}
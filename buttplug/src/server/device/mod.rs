@@ -95,12 +95,46 @@
 //!
 //!
 
+mod battery_saver;
+mod comm_manager_metrics;
+mod command_coalescer;
+#[cfg(feature = "scripting")]
+mod command_script;
 pub mod configuration;
+mod connection_hook;
+mod estim_interlock;
 pub mod hardware;
+mod linear_position_estimator;
+mod merged_device;
+mod output_queue;
+mod output_ramp;
+mod overuse_protection;
+mod pattern_playback;
 pub mod protocol;
+mod rssi_sensor;
+mod sensor_calibration;
+mod sensor_notifier;
+mod sensor_rules;
 pub mod server_device;
 mod server_device_manager;
 mod server_device_manager_event_loop;
+mod server_device_manager_event_queue;
+mod split_device;
 
+pub use battery_saver::{BatterySaverSettings, DutyCycle};
+pub use comm_manager_metrics::CommManagerMetrics;
+#[cfg(feature = "scripting")]
+pub use command_script::{CommandScript, CommandScriptLimits};
+pub use connection_hook::{ConnectingDevice, DeviceConnectionDecision, DeviceConnectionHook};
+pub use estim_interlock::EstimInterlock;
+pub use output_ramp::RampPolicy;
+pub use overuse_protection::{OveruseProtection, OveruseWarning};
+pub use sensor_calibration::SensorCalibration;
+pub use sensor_rules::{SensorCondition, SensorRule};
 pub use server_device::{ServerDevice, ServerDeviceEvent, ServerDeviceIdentifier};
-pub use server_device_manager::{ServerDeviceManager, ServerDeviceManagerBuilder};
+pub use server_device_manager::{
+  ServerDeviceManager,
+  ServerDeviceManagerBuilder,
+  UnsupportedDeviceInfo,
+};
+pub use server_device_manager_event_queue::QueueMetrics;
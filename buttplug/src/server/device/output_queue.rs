@@ -0,0 +1,184 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Shedding superseded output when a device falls behind.
+//!
+//! A client sending ScalarCmd or RotateCmd faster than the device takes writes would otherwise
+//! stack up writes waiting on the hardware, each one already out of date, with the device lagging
+//! further and further behind what the client asked for. While output is being sent to a device,
+//! newer output commands are merged instead of sent, the latest value for each feature replacing
+//! older ones, and the merged output goes out as soon as the device is free again. So however fast
+//! a client sends, at most one output write is waiting behind the one in flight, and what the
+//! device gets next is always the latest thing asked for.
+//!
+//! Merged commands are answered right away. Stop commands are never merged, they throw away any
+//! output still waiting.
+
+use super::battery_saver::RequestedOutput;
+use crate::core::message::ButtplugDeviceCommandMessageUnion;
+use std::sync::Mutex;
+
+/// What to do with an output command handed to the output queue.
+pub(super) enum Queued {
+  /// Nothing is being sent, so send the command now, then call
+  /// [finish_sending](OutputQueue::finish_sending) once it's sent.
+  Send(ButtplugDeviceCommandMessageUnion),
+  /// Output is already being sent, so the command was merged into the output sent after it.
+  Merged,
+}
+
+#[derive(Default)]
+struct OutputQueueState {
+  /// True while output is being sent to the device.
+  sending: bool,
+  /// Output merged while sending, waiting for the device to be free.
+  pending: Option<RequestedOutput>,
+}
+
+#[derive(Default)]
+pub(super) struct OutputQueue {
+  state: Mutex<OutputQueueState>,
+  /// Held while sending merged output, so stops can wait for it instead of racing it.
+  draining: tokio::sync::Mutex<()>,
+}
+
+impl OutputQueue {
+  /// Decides whether an output command goes out now or waits for the output being sent. Only scalar
+  /// and rotation output is merged, everything else is always sent.
+  pub fn queue(&self, command: ButtplugDeviceCommandMessageUnion) -> Queued {
+    if !matches!(
+      command,
+      ButtplugDeviceCommandMessageUnion::ScalarCmd(_)
+        | ButtplugDeviceCommandMessageUnion::RotateCmd(_)
+    ) {
+      return Queued::Send(command);
+    }
+    let mut state = self
+      .state
+      .lock()
+      .expect("Output queue state lock should never be poisoned.");
+    if !state.sending {
+      state.sending = true;
+      return Queued::Send(command);
+    }
+    let pending = state.pending.get_or_insert_with(RequestedOutput::default);
+    match &command {
+      ButtplugDeviceCommandMessageUnion::ScalarCmd(msg) => {
+        pending.record_scalars(msg.scalars().iter())
+      }
+      ButtplugDeviceCommandMessageUnion::RotateCmd(msg) => {
+        pending.record_rotations(msg.rotations().iter())
+      }
+      _ => unreachable!("Only output commands are merged."),
+    }
+    Queued::Merged
+  }
+
+  /// Called once output is sent. Returns true if output was merged in the meantime, which then
+  /// needs taking with [take_pending](Self::take_pending) and sending. Otherwise the device is free
+  /// for the next output command.
+  pub fn finish_sending(&self) -> bool {
+    let mut state = self
+      .state
+      .lock()
+      .expect("Output queue state lock should never be poisoned.");
+    state.sending = state.pending.is_some();
+    state.sending
+  }
+
+  /// Takes the merged output waiting to be sent. If there's none, the device is free for the next
+  /// output command. Take it while holding [draining](Self::draining), so stops clearing the queue
+  /// either win or wait for it to be sent.
+  pub fn take_pending(&self) -> Option<Vec<ButtplugDeviceCommandMessageUnion>> {
+    let mut state = self
+      .state
+      .lock()
+      .expect("Output queue state lock should never be poisoned.");
+    let pending = state.pending.take().map(|pending| pending.commands());
+    state.sending = pending.is_some();
+    pending
+  }
+
+  /// Throws away the output waiting to be sent, for when a stop replaces it.
+  pub fn clear(&self) {
+    self
+      .state
+      .lock()
+      .expect("Output queue state lock should never be poisoned.")
+      .pending = None;
+  }
+
+  /// Held while sending merged output.
+  pub async fn draining(&self) -> tokio::sync::MutexGuard<'_, ()> {
+    self.draining.lock().await
+  }
+
+  /// Waits for merged output that's being sent to finish.
+  pub async fn wait_for_drain(&self) {
+    let _draining = self.draining.lock().await;
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::core::message::{
+    ActuatorType,
+    RotateCmd,
+    RotationSubcommand,
+    ScalarCmd,
+    ScalarSubcommand,
+    StopDeviceCmd,
+  };
+
+  fn vibrate(index: u32, scalar: f64) -> ButtplugDeviceCommandMessageUnion {
+    ScalarCmd::new(
+      0,
+      vec![ScalarSubcommand::new(index, scalar, ActuatorType::Vibrate)],
+    )
+    .into()
+  }
+
+  #[test]
+  fn test_output_queue_merges_while_sending() {
+    let queue = OutputQueue::default();
+    assert!(matches!(queue.queue(vibrate(0, 0.1)), Queued::Send(_)));
+    assert!(matches!(queue.queue(vibrate(0, 0.2)), Queued::Merged));
+    assert!(matches!(queue.queue(vibrate(1, 0.3)), Queued::Merged));
+    assert!(matches!(queue.queue(vibrate(0, 0.4)), Queued::Merged));
+    assert!(matches!(
+      queue.queue(RotateCmd::new(0, vec![RotationSubcommand::new(0, 0.5, true)]).into()),
+      Queued::Merged
+    ));
+    // Anything that isn't output never waits.
+    assert!(matches!(
+      queue.queue(StopDeviceCmd::new(0).into()),
+      Queued::Send(_)
+    ));
+    assert!(queue.finish_sending());
+    // Latest value per feature wins.
+    assert_eq!(
+      queue.take_pending(),
+      Some(vec![
+        ButtplugDeviceCommandMessageUnion::from(ScalarCmd::new(
+          0,
+          vec![
+            ScalarSubcommand::new(0, 0.4, ActuatorType::Vibrate),
+            ScalarSubcommand::new(1, 0.3, ActuatorType::Vibrate)
+          ]
+        )),
+        RotateCmd::new(0, vec![RotationSubcommand::new(0, 0.5, true)]).into()
+      ])
+    );
+    // Still sending the merged output, so this waits for it.
+    assert!(matches!(queue.queue(vibrate(0, 0.6)), Queued::Merged));
+    queue.clear();
+    assert_eq!(queue.take_pending(), None);
+    // Nothing waiting, so the device is free again.
+    assert!(matches!(queue.queue(vibrate(0, 0.7)), Queued::Send(_)));
+  }
+}
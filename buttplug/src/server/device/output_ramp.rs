@@ -0,0 +1,151 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Gradual output changes for devices that shouldn't jump between intensities.
+//!
+//! A device can be set to warm up, which caps how strong its output can be for a while after it
+//! connects, and to cool down, which steps its output down over time when it is stopped instead of
+//! cutting it off at once. Only intensities are ramped (scalar actuators other than Position, and
+//! rotation speeds). Positions are left alone, as scaling a position just moves it somewhere else.
+
+use crate::core::message::{
+  ActuatorType,
+  ButtplugDeviceCommandMessageUnion,
+  ButtplugDeviceMessage,
+  ButtplugMessage,
+  RotateCmd,
+  RotationSubcommand,
+  ScalarCmd,
+  ScalarSubcommand,
+};
+use getset::CopyGetters;
+use std::time::Duration;
+
+/// Time between the output steps of a cool down.
+pub(super) const COOL_DOWN_STEP_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Warm up and cool down settings for a device. Both are off by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, CopyGetters)]
+#[getset(get_copy = "pub")]
+pub struct RampPolicy {
+  /// How long after connection output is capped. The cap rises linearly from nothing to full
+  /// output over this time. Commands sent during warm up stay capped until the next command.
+  warm_up: Option<Duration>,
+  /// How long StopDeviceCmd takes to step output down to zero.
+  cool_down: Option<Duration>,
+}
+
+impl RampPolicy {
+  pub fn new(warm_up: Option<Duration>, cool_down: Option<Duration>) -> Self {
+    Self { warm_up, cool_down }
+  }
+
+  /// Highest output level allowed `elapsed` after the device connected, or None if output isn't
+  /// capped.
+  pub fn warm_up_limit(&self, elapsed: Duration) -> Option<f64> {
+    let warm_up = self.warm_up?;
+    if elapsed >= warm_up {
+      None
+    } else {
+      Some(elapsed.as_secs_f64() / warm_up.as_secs_f64())
+    }
+  }
+
+  /// Fractions of the current output to step through while cooling down, one per
+  /// [COOL_DOWN_STEP_INTERVAL], not including the final stop.
+  pub fn cool_down_levels(&self) -> Vec<f64> {
+    let cool_down = if let Some(cool_down) = self.cool_down {
+      cool_down
+    } else {
+      return vec![];
+    };
+    let steps = (cool_down.as_millis() / COOL_DOWN_STEP_INTERVAL.as_millis()).max(1) as u32;
+    (1..steps)
+      .map(|step| 1.0 - step as f64 / steps as f64)
+      .collect()
+  }
+}
+
+/// Run every intensity in a command through `scale`. Anything that isn't a ScalarCmd or RotateCmd
+/// is returned as is.
+pub(super) fn scale_output(
+  message: ButtplugDeviceCommandMessageUnion,
+  scale: impl Fn(f64) -> f64,
+) -> ButtplugDeviceCommandMessageUnion {
+  match message {
+    ButtplugDeviceCommandMessageUnion::ScalarCmd(msg) => {
+      let scalars = msg
+        .scalars()
+        .iter()
+        .map(|cmd| {
+          let scalar = if cmd.actuator_type() == ActuatorType::Position {
+            cmd.scalar()
+          } else {
+            scale(cmd.scalar())
+          };
+          ScalarSubcommand::new(cmd.index(), scalar, cmd.actuator_type())
+        })
+        .collect();
+      let mut scaled = ScalarCmd::new(msg.device_index(), scalars);
+      scaled.set_id(msg.id());
+      scaled.into()
+    }
+    ButtplugDeviceCommandMessageUnion::RotateCmd(msg) => {
+      let rotations = msg
+        .rotations()
+        .iter()
+        .map(|cmd| RotationSubcommand::new(cmd.index(), scale(cmd.speed()), cmd.clockwise()))
+        .collect();
+      let mut scaled = RotateCmd::new(msg.device_index(), rotations);
+      scaled.set_id(msg.id());
+      scaled.into()
+    }
+    msg => msg,
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_warm_up_limit() {
+    let policy = RampPolicy::new(Some(Duration::from_secs(10)), None);
+    assert_eq!(policy.warm_up_limit(Duration::ZERO), Some(0.0));
+    assert_eq!(policy.warm_up_limit(Duration::from_secs(5)), Some(0.5));
+    assert_eq!(policy.warm_up_limit(Duration::from_secs(10)), None);
+    assert_eq!(RampPolicy::default().warm_up_limit(Duration::ZERO), None);
+  }
+
+  #[test]
+  fn test_cool_down_levels() {
+    let policy = RampPolicy::new(None, Some(Duration::from_millis(400)));
+    assert_eq!(policy.cool_down_levels(), vec![0.75, 0.5, 0.25]);
+    // Cool downs shorter than a step go straight to the stop.
+    let policy = RampPolicy::new(None, Some(Duration::from_millis(50)));
+    assert!(policy.cool_down_levels().is_empty());
+    assert!(RampPolicy::default().cool_down_levels().is_empty());
+  }
+
+  #[test]
+  fn test_scale_output_skips_positions() {
+    let msg = ScalarCmd::new(
+      0,
+      vec![
+        ScalarSubcommand::new(0, 1.0, ActuatorType::Vibrate),
+        ScalarSubcommand::new(1, 1.0, ActuatorType::Position),
+      ],
+    );
+    let scaled = scale_output(msg.into(), |scalar| scalar * 0.5);
+    if let ButtplugDeviceCommandMessageUnion::ScalarCmd(scaled) = scaled {
+      assert_eq!(scaled.scalars()[0].scalar(), 0.5);
+      assert_eq!(scaled.scalars()[1].scalar(), 1.0);
+    } else {
+      panic!("Scaling should not change the message type");
+    }
+  }
+}
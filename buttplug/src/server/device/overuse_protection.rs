@@ -0,0 +1,205 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Overuse protection, for devices whose motors overheat when run hard for too long.
+//!
+//! Protocols can declare in the device config how long their devices may run at or above a high
+//! intensity. Once a device has run that long without a break, its intensities are capped at a
+//! lower level, and a [OveruseWarning] is sent to applications hosting the server. The cap is lifted
+//! once the output asked for drops below the high intensity, which includes the device being
+//! stopped or resting in a battery saver duty cycle. As with warm up, positions aren't counted or
+//! capped.
+
+use super::ServerDeviceIdentifier;
+use crate::{
+  core::message::{ActuatorType, ButtplugDeviceCommandMessageUnion},
+  util::{async_manager, sleep},
+};
+use getset::{CopyGetters, Getters};
+use std::{
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+    Mutex,
+  },
+  time::Duration,
+};
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+
+/// Overuse protection settings for the devices of a protocol.
+#[derive(Debug, Clone, Copy, PartialEq, CopyGetters)]
+#[getset(get_copy = "pub")]
+pub struct OveruseProtection {
+  /// Intensity, from 0.0 to 1.0, at or above which a device counts as running hard.
+  threshold: f64,
+  /// How long a device can run hard before it's stepped down.
+  max_runtime: Duration,
+  /// Intensity, from 0.0 to 1.0, that output is capped at once a device is stepped down.
+  step_down_level: f64,
+}
+
+impl OveruseProtection {
+  pub fn new(threshold: f64, max_runtime: Duration, step_down_level: f64) -> Self {
+    Self {
+      threshold,
+      max_runtime,
+      step_down_level,
+    }
+  }
+}
+
+/// Sent when a device has run hard for longer than its protocol allows, and has been stepped down.
+#[derive(Debug, Clone, PartialEq, Getters, CopyGetters)]
+pub struct OveruseWarning {
+  #[getset(get_copy = "pub")]
+  device_index: u32,
+  #[getset(get = "pub")]
+  identifier: ServerDeviceIdentifier,
+  /// The protection that kicked in, which has how long the device ran and what it's capped at.
+  #[getset(get_copy = "pub")]
+  protection: OveruseProtection,
+}
+
+impl OveruseWarning {
+  pub(super) fn new(
+    device_index: u32,
+    identifier: ServerDeviceIdentifier,
+    protection: OveruseProtection,
+  ) -> Self {
+    Self {
+      device_index,
+      identifier,
+      protection,
+    }
+  }
+}
+
+/// Highest intensity in a set of commands.
+pub(super) fn output_intensity(commands: &[ButtplugDeviceCommandMessageUnion]) -> f64 {
+  commands
+    .iter()
+    .flat_map(|command| match command {
+      ButtplugDeviceCommandMessageUnion::ScalarCmd(msg) => msg
+        .scalars()
+        .iter()
+        .filter(|scalar| scalar.actuator_type() != ActuatorType::Position)
+        .map(|scalar| scalar.scalar())
+        .collect(),
+      ButtplugDeviceCommandMessageUnion::RotateCmd(msg) => msg
+        .rotations()
+        .iter()
+        .map(|rotation| rotation.speed())
+        .collect(),
+      _ => vec![],
+    })
+    .fold(0.0, f64::max)
+}
+
+/// Tracks how long a device has been running hard.
+pub(super) struct OveruseMonitor {
+  protection: OveruseProtection,
+  /// Set once the device has run hard for too long, until it stops running hard.
+  stepped_down: Arc<AtomicBool>,
+  /// Cancels the timer started when the device began running hard, if it's running hard.
+  timer_token: Mutex<Option<CancellationToken>>,
+  /// Told about each step down, so the device manager can send the device's output again under the
+  /// cap.
+  step_down_sender: broadcast::Sender<OveruseProtection>,
+}
+
+impl OveruseMonitor {
+  pub fn new(
+    protection: OveruseProtection,
+    step_down_sender: broadcast::Sender<OveruseProtection>,
+  ) -> Self {
+    Self {
+      protection,
+      stepped_down: Arc::new(AtomicBool::new(false)),
+      timer_token: Mutex::new(None),
+      step_down_sender,
+    }
+  }
+
+  /// Highest intensity allowed right now, or None if output isn't capped.
+  pub fn limit(&self) -> Option<f64> {
+    self
+      .stepped_down
+      .load(Ordering::SeqCst)
+      .then_some(self.protection.step_down_level)
+  }
+
+  /// Takes the intensity the device is being asked for, starting the clock if the device just
+  /// started running hard, or resetting it (and lifting any cap) if it stopped.
+  pub fn update(&self, intensity: f64) {
+    let mut timer_token = self
+      .timer_token
+      .lock()
+      .expect("Overuse timer lock should never be poisoned.");
+    if intensity < self.protection.threshold {
+      if let Some(token) = timer_token.take() {
+        token.cancel();
+      }
+      self.stepped_down.store(false, Ordering::SeqCst);
+      return;
+    }
+    if timer_token.is_some() {
+      return;
+    }
+    let token = CancellationToken::new();
+    let child_token = token.child_token();
+    let protection = self.protection;
+    let stepped_down = self.stepped_down.clone();
+    let step_down_sender = self.step_down_sender.clone();
+    async_manager::spawn(async move {
+      tokio::select! {
+        _ = sleep(protection.max_runtime) => {},
+        _ = child_token.cancelled() => return,
+      }
+      stepped_down.store(true, Ordering::SeqCst);
+      // Send only fails if there are no listeners, in which case there's nobody to tell.
+      let _ = step_down_sender.send(protection);
+    });
+    *timer_token = Some(token);
+  }
+}
+
+impl Drop for OveruseMonitor {
+  fn drop(&mut self) {
+    if let Some(token) = self
+      .timer_token
+      .lock()
+      .expect("Overuse timer lock should never be poisoned.")
+      .take()
+    {
+      token.cancel();
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::core::message::{RotateCmd, RotationSubcommand, ScalarCmd, ScalarSubcommand};
+
+  #[test]
+  fn test_output_intensity_skips_positions() {
+    let commands = vec![
+      ScalarCmd::new(
+        0,
+        vec![
+          ScalarSubcommand::new(0, 0.25, ActuatorType::Vibrate),
+          ScalarSubcommand::new(1, 1.0, ActuatorType::Position),
+        ],
+      )
+      .into(),
+      RotateCmd::new(0, vec![RotationSubcommand::new(0, 0.5, true)]).into(),
+    ];
+    assert_eq!(output_intensity(&commands), 0.5);
+    assert_eq!(output_intensity(&[]), 0.0);
+  }
+}
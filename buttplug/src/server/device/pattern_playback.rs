@@ -0,0 +1,135 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Server side playback of [PatternCmd] keyframes.
+//!
+//! Each keyframe is sent to the device as the regular commands for its subcommands (ScalarCmd,
+//! LinearCmd and RotateCmd) once its time comes up, so patterns go through the same checks, ramps
+//! and timeouts as commands sent by clients. A device plays one pattern at a time.
+
+use super::ServerDevice;
+use crate::{
+  core::{
+    errors::{ButtplugDeviceError, ButtplugError},
+    message::{
+      ButtplugDeviceCommandMessageUnion,
+      ButtplugDeviceMessage,
+      LinearCmd,
+      PatternCmd,
+      PatternKeyframe,
+      RotateCmd,
+      ScalarCmd,
+    },
+  },
+  util::{async_manager, sleep},
+};
+use dashmap::DashMap;
+use std::{
+  sync::Arc,
+  time::{Duration, Instant},
+};
+use tokio_util::sync::CancellationToken;
+
+/// Commands to send to a device for a keyframe.
+fn keyframe_commands(
+  device_index: u32,
+  keyframe: &PatternKeyframe,
+) -> Vec<ButtplugDeviceCommandMessageUnion> {
+  let mut commands = vec![];
+  if !keyframe.scalars().is_empty() {
+    commands.push(ScalarCmd::new(device_index, keyframe.scalars().clone()).into());
+  }
+  if !keyframe.vectors().is_empty() {
+    commands.push(LinearCmd::new(device_index, keyframe.vectors().clone()).into());
+  }
+  if !keyframe.rotations().is_empty() {
+    commands.push(RotateCmd::new(device_index, keyframe.rotations().clone()).into());
+  }
+  commands
+}
+
+/// Patterns currently playing, by device index.
+#[derive(Default)]
+pub(super) struct PatternPlayback {
+  playing: Arc<DashMap<u32, CancellationToken>>,
+}
+
+impl PatternPlayback {
+  /// Starts playing a pattern on a device, ending whatever pattern was already playing on it.
+  /// Fails without touching the current pattern if the device doesn't take the commands the
+  /// keyframes need.
+  pub fn play(&self, device: Arc<ServerDevice>, pattern: PatternCmd) -> Result<(), ButtplugError> {
+    let device_index = pattern.device_index();
+    for keyframe in pattern.keyframes() {
+      for command in keyframe_commands(device_index, keyframe) {
+        device.supports_message(&command)?;
+      }
+    }
+    let token = CancellationToken::new();
+    if let Some(previous) = self.playing.insert(device_index, token.clone()) {
+      previous.cancel();
+    }
+    let playing = self.playing.clone();
+    async_manager::spawn(async move {
+      tokio::select! {
+        _ = token.cancelled() => {},
+        _ = play_keyframes(&device, &pattern) => {
+          // Replaced patterns are taken out of the map before being cancelled, so a cancelled token
+          // left in there is this one.
+          token.cancel();
+          playing.remove_if(&device_index, |_, playing_token| playing_token.is_cancelled());
+        }
+      }
+    });
+    Ok(())
+  }
+
+  /// Ends the pattern playing on a device, if any. Output is left where the last keyframe put it.
+  pub fn stop(&self, device_index: u32) {
+    if let Some((_, token)) = self.playing.remove(&device_index) {
+      token.cancel();
+    }
+  }
+
+  /// Ends all patterns.
+  pub fn stop_all(&self) {
+    self.playing.retain(|_, token| {
+      token.cancel();
+      false
+    });
+  }
+}
+
+async fn play_keyframes(device: &ServerDevice, pattern: &PatternCmd) {
+  let device_index = pattern.device_index();
+  loop {
+    let start = Instant::now();
+    for keyframe in pattern.keyframes() {
+      let keyframe_time = start + Duration::from_millis(keyframe.time().into());
+      sleep(keyframe_time.saturating_duration_since(Instant::now())).await;
+      for command in keyframe_commands(device_index, keyframe) {
+        match device.parse_message(command).await {
+          Ok(_) => {}
+          Err(ButtplugError::ButtplugDeviceError(ButtplugDeviceError::DeviceDisconnected(_))) => {
+            debug!("Device {} disconnected, ending pattern.", device_index);
+            return;
+          }
+          // Keep going, a single dropped command shouldn't end the whole pattern.
+          Err(err) => warn!(
+            "Pattern command for device {} at {}ms failed: {}",
+            device_index,
+            keyframe.time(),
+            err
+          ),
+        }
+      }
+    }
+    if !pattern.looped() {
+      return;
+    }
+  }
+}
@@ -26,11 +26,27 @@ use std::{
   sync::atomic::{AtomicBool, AtomicU32, Ordering::SeqCst},
 };
 
+/// Remaps a nonzero 0.0-1.0 level onto [minimum, 1.0], for actuators that don't do anything below
+/// some level. 0.0 stays off.
+fn apply_minimum_effective_value(level: f64, minimum: f64) -> f64 {
+  if level <= 0.0 {
+    0.0
+  } else {
+    minimum + level * (1.0 - minimum)
+  }
+}
+
+/// Undoes [apply_minimum_effective_value], giving the level that was asked for.
+fn remove_minimum_effective_value(level: f64, minimum: f64) -> f64 {
+  ((level - minimum) / (1.0 - minimum)).max(0.0)
+}
+
 #[derive(Getters)]
 #[getset(get = "pub")]
 struct ScalarGenericCommand {
   actuator: ActuatorType,
   step_range: RangeInclusive<u32>,
+  minimum_effective_value: f64,
   value: AtomicU32,
 }
 
@@ -39,6 +55,7 @@ impl ScalarGenericCommand {
     Self {
       actuator: *attributes.actuator_type(),
       step_range: attributes.step_range().clone(),
+      minimum_effective_value: attributes.minimum_effective_value().unwrap_or(0.0),
       value: AtomicU32::new(0),
     }
   }
@@ -59,6 +76,10 @@ pub struct GenericCommandManager {
   scalars: Vec<ScalarGenericCommand>,
   rotations: Vec<(AtomicU32, AtomicBool)>,
   rotation_step_ranges: Vec<RangeInclusive<u32>>,
+  rotation_minimum_effective_values: Vec<f64>,
+  /// Device turns the opposite way from what RotateCmd describes, so flip directions before
+  /// handing them to the protocol.
+  invert_rotation: bool,
   _linears: Vec<(u32, u32)>,
   _linear_step_counts: Vec<u32>,
   stop_commands: Vec<ButtplugDeviceCommandMessageUnion>,
@@ -69,6 +90,7 @@ impl GenericCommandManager {
     let mut scalars = vec![];
     let mut rotations = vec![];
     let mut rotation_step_ranges = vec![];
+    let mut rotation_minimum_effective_values = vec![];
     let mut linears = vec![];
     let mut linear_step_counts = vec![];
 
@@ -91,6 +113,7 @@ impl GenericCommandManager {
       rotations.resize_with(attrs.len(), || (AtomicU32::new(0), AtomicBool::new(false)));
       for attr in attrs {
         rotation_step_ranges.push(attr.step_range().clone());
+        rotation_minimum_effective_values.push(attr.minimum_effective_value().unwrap_or(0.0));
       }
 
       // TODO Can we assume clockwise is false here? We might send extra
@@ -118,6 +141,8 @@ impl GenericCommandManager {
       rotations,
       _linears: linears,
       rotation_step_ranges,
+      rotation_minimum_effective_values,
+      invert_rotation: attributes.rotation_inverted(),
       _linear_step_counts: linear_step_counts,
       stop_commands,
     }
@@ -146,6 +171,9 @@ impl GenericCommandManager {
     // old values. Otherwise, we should always send whatever command we're
     // going to send.
     let mut result: Vec<Option<(ActuatorType, u32)>> = vec![None; self.scalars.len()];
+    // Checked once for the whole message, so that every subcommand in the first message after
+    // creation (or after forget_sent_values) gets sent.
+    let sent_scalar = self.sent_scalar.load(SeqCst);
 
     for scalar_command in msg.scalars() {
       let index = scalar_command.index() as usize;
@@ -164,7 +192,11 @@ impl GenericCommandManager {
 
       let range_start = self.scalars[index].step_range().start();
       let range = self.scalars[index].step_range().end() - range_start;
-      let scalar_modifier = scalar_command.scalar() * range as f64;
+      let level = apply_minimum_effective_value(
+        scalar_command.scalar(),
+        *self.scalars[index].minimum_effective_value(),
+      );
+      let scalar_modifier = level * range as f64;
       let scalar = if scalar_modifier < 0.0001 {
         0
       } else {
@@ -184,15 +216,14 @@ impl GenericCommandManager {
       // because some of our communication busses are REALLY slow. Make sure
       // these values get None in our return vector.
       let current_scalar = self.scalars[index].value().load(SeqCst);
-      let sent_scalar = self.sent_scalar.load(SeqCst);
       if !sent_scalar || scalar != current_scalar {
         self.scalars[index].value().store(scalar, SeqCst);
         result[index] = Some((*self.scalars[index].actuator(), scalar));
       }
+    }
 
-      if !sent_scalar {
-        self.sent_scalar.store(true, SeqCst);
-      }
+    if !sent_scalar {
+      self.sent_scalar.store(true, SeqCst);
     }
 
     // If we have no changes to the device, just send back an empty command array. We have nothing
@@ -246,6 +277,7 @@ impl GenericCommandManager {
     // old values. Otherwise, we should always send whatever command we're
     // going to send.
     let mut result: Vec<Option<(u32, bool)>> = vec![None; self.rotations.len()];
+    let sent_rotation = self.sent_rotation.load(SeqCst);
     for rotate_command in msg.rotations() {
       let index = rotate_command.index() as usize;
       // Since we're going to iterate here anyways, we do our index check
@@ -265,7 +297,11 @@ impl GenericCommandManager {
       // things in buttplug-js and buttplug-csharp, so it's more for history
       // than anything, but it's what users will expect.
       let range = self.rotation_step_ranges[index].end() - self.rotation_step_ranges[index].start();
-      let speed_modifier = rotate_command.speed() * range as f64;
+      let level = apply_minimum_effective_value(
+        rotate_command.speed(),
+        self.rotation_minimum_effective_values[index],
+      );
+      let speed_modifier = level * range as f64;
       let speed = if speed_modifier < 0.0001 {
         0
       } else {
@@ -274,11 +310,12 @@ impl GenericCommandManager {
         // than anything, but it's what users will expect.
         (speed_modifier + *self.rotation_step_ranges[index].start() as f64).ceil() as u32
       };
-      let clockwise = rotate_command.clockwise();
+      // Stored and returned directions are in the device's own terms, so protocols never need to
+      // know about inversion.
+      let clockwise = rotate_command.clockwise() != self.invert_rotation;
       // If we've already sent commands, we don't want to send them again,
       // because some of our communication busses are REALLY slow. Make sure
       // these values get None in our return vector.
-      let sent_rotation = self.sent_rotation.load(SeqCst);
       if !sent_rotation
         || speed != self.rotations[index].0.load(SeqCst)
         || clockwise != self.rotations[index].1.load(SeqCst)
@@ -287,9 +324,9 @@ impl GenericCommandManager {
         self.rotations[index].1.store(clockwise, SeqCst);
         result[index] = Some((speed, clockwise));
       }
-      if !sent_rotation {
-        self.sent_rotation.store(true, SeqCst);
-      }
+    }
+    if !sent_rotation {
+      self.sent_rotation.store(true, SeqCst);
     }
 
     // If we're in a match all situation, set up the array with all prior
@@ -328,6 +365,74 @@ impl GenericCommandManager {
   pub fn stop_commands(&self) -> Vec<ButtplugDeviceCommandMessageUnion> {
     self.stop_commands.clone()
   }
+
+  /// Commands that would set the device to the output it's currently running at, in generic
+  /// 0.0-1.0 terms (before any minimum effective value is applied). Position actuators and outputs
+  /// that are already off are left out. Used to step output down from where it is when a device
+  /// cools down.
+  pub fn current_output_commands(&self) -> Vec<ButtplugDeviceCommandMessageUnion> {
+    let to_level = |value: u32, step_range: &RangeInclusive<u32>| {
+      let range = step_range.end() - step_range.start();
+      if range == 0 {
+        1.0
+      } else {
+        (value.saturating_sub(*step_range.start()) as f64 / range as f64).min(1.0)
+      }
+    };
+    let mut commands = vec![];
+    let scalars: Vec<ScalarSubcommand> = self
+      .scalars
+      .iter()
+      .enumerate()
+      .filter(|(_, cmd)| *cmd.actuator() != ActuatorType::Position)
+      .filter_map(|(index, cmd)| {
+        let value = cmd.value().load(SeqCst);
+        (value != 0).then(|| {
+          ScalarSubcommand::new(
+            index as u32,
+            remove_minimum_effective_value(
+              to_level(value, cmd.step_range()),
+              *cmd.minimum_effective_value(),
+            ),
+            *cmd.actuator(),
+          )
+        })
+      })
+      .collect();
+    if !scalars.is_empty() {
+      commands.push(ScalarCmd::new(0, scalars).into());
+    }
+    let rotations: Vec<RotationSubcommand> = self
+      .rotations
+      .iter()
+      .enumerate()
+      .filter_map(|(index, (speed, clockwise))| {
+        let speed = speed.load(SeqCst);
+        // Stored directions are in device terms, so flip them back for the message.
+        (speed != 0).then(|| {
+          RotationSubcommand::new(
+            index as u32,
+            remove_minimum_effective_value(
+              to_level(speed, &self.rotation_step_ranges[index]),
+              self.rotation_minimum_effective_values[index],
+            ),
+            clockwise.load(SeqCst) != self.invert_rotation,
+          )
+        })
+      })
+      .collect();
+    if !rotations.is_empty() {
+      commands.push(RotateCmd::new(0, rotations).into());
+    }
+    commands
+  }
+
+  /// Forget what we think the device was last sent, so the next update sends every value again.
+  /// Used when a command fails, since we then don't know what state the device is in.
+  pub fn forget_sent_values(&self) {
+    self.sent_scalar.store(false, SeqCst);
+    self.sent_rotation.store(false, SeqCst);
+  }
 }
 
 #[cfg(test)]
@@ -335,14 +440,21 @@ mod test {
 
   use super::{GenericCommandManager, ProtocolDeviceAttributes};
   use crate::{
-    core::message::{ActuatorType, RotateCmd, RotationSubcommand, ScalarCmd, ScalarSubcommand},
+    core::message::{
+      ActuatorType,
+      ButtplugDeviceCommandMessageUnion,
+      RotateCmd,
+      RotationSubcommand,
+      ScalarCmd,
+      ScalarSubcommand,
+    },
     server::device::configuration::{
       ProtocolAttributesType,
       ServerDeviceMessageAttributesBuilder,
       ServerGenericDeviceMessageAttributes,
     },
   };
-  use std::ops::RangeInclusive;
+  use std::{ops::RangeInclusive, sync::Arc};
 
   #[test]
   pub fn test_command_generator_vibration() {
@@ -410,6 +522,18 @@ mod test {
         Some((ActuatorType::Vibrate, 15))
       ]
     );
+
+    // Once sent values are forgotten, everything gets sent again even if it hasn't changed.
+    mgr.forget_sent_values();
+    assert_eq!(
+      mgr
+        .update_scalar(&vibrate_msg_2, false)
+        .expect("Test, assuming infallible"),
+      vec![
+        Some((ActuatorType::Vibrate, 10)),
+        Some((ActuatorType::Vibrate, 15))
+      ]
+    );
   }
 
   #[test]
@@ -559,6 +683,52 @@ mod test {
     );
   }
 
+  #[test]
+  pub fn test_command_generator_minimum_effective_value() {
+    let mut vibrate_attrs = ServerGenericDeviceMessageAttributes::new(
+      "Test",
+      &RangeInclusive::new(0, 20),
+      ActuatorType::Vibrate,
+    );
+    vibrate_attrs.set_minimum_effective_value(Some(0.25));
+    let vibrate_attributes = ServerDeviceMessageAttributesBuilder::default()
+      .scalar_cmd(&[vibrate_attrs])
+      .finish();
+    let device_attributes = ProtocolDeviceAttributes::new(
+      ProtocolAttributesType::Default,
+      None,
+      None,
+      vibrate_attributes,
+      None,
+    );
+    let mgr = GenericCommandManager::new(&device_attributes);
+    let vibrate = |level| {
+      mgr
+        .update_scalar(
+          &ScalarCmd::new(
+            0,
+            vec![ScalarSubcommand::new(0, level, ActuatorType::Vibrate)],
+          ),
+          false,
+        )
+        .expect("Test, assuming infallible")
+    };
+    // 0.5 is halfway between the minimum (step 5) and full output (step 20), rounded up.
+    assert_eq!(vibrate(0.5), vec![Some((ActuatorType::Vibrate, 13))]);
+    assert_eq!(vibrate(0.05), vec![Some((ActuatorType::Vibrate, 6))]);
+    assert_eq!(vibrate(1.0), vec![Some((ActuatorType::Vibrate, 20))]);
+    // Off is still off.
+    assert_eq!(vibrate(0.0), vec![Some((ActuatorType::Vibrate, 0))]);
+
+    // Current output is reported in the levels that were asked for, give or take a step.
+    vibrate(0.5);
+    if let ButtplugDeviceCommandMessageUnion::ScalarCmd(msg) = &mgr.current_output_commands()[0] {
+      assert!((msg.scalars()[0].scalar() - 0.5).abs() < 0.05);
+    } else {
+      panic!("Expected a ScalarCmd");
+    }
+  }
+
   #[test]
   pub fn test_command_generator_rotation() {
     let rotate_attrs = ServerGenericDeviceMessageAttributes::new(
@@ -627,5 +797,59 @@ mod test {
     let rotate_msg_invalid = RotateCmd::new(0, vec![RotationSubcommand::new(2, 0.5, true)]);
     assert!(mgr.update_rotation(&rotate_msg_invalid, false).is_err());
   }
+
+  #[test]
+  pub fn test_command_generator_rotation_inverted() {
+    let rotate_attrs = ServerGenericDeviceMessageAttributes::new(
+      "Test",
+      &RangeInclusive::new(0, 20),
+      ActuatorType::Rotate,
+    );
+
+    let rotate_attributes = ServerDeviceMessageAttributesBuilder::default()
+      .rotate_cmd(&[rotate_attrs])
+      .finish();
+    let mut default_attributes = ProtocolDeviceAttributes::new(
+      ProtocolAttributesType::Default,
+      None,
+      None,
+      rotate_attributes,
+      None,
+    );
+    default_attributes.set_invert_rotation(true);
+    let mgr = GenericCommandManager::new(&default_attributes);
+
+    let rotate_msg = RotateCmd::new(0, vec![RotationSubcommand::new(0, 0.5, true)]);
+    assert_eq!(
+      mgr
+        .update_rotation(&rotate_msg, false)
+        .expect("Test, assuming infallible"),
+      vec![Some((10, false))]
+    );
+    let rotate_msg_2 = RotateCmd::new(0, vec![RotationSubcommand::new(0, 0.5, false)]);
+    assert_eq!(
+      mgr
+        .update_rotation(&rotate_msg_2, false)
+        .expect("Test, assuming infallible"),
+      vec![Some((10, true))]
+    );
+
+    // A child that also inverts flips the direction back.
+    let mut device_attributes = ProtocolDeviceAttributes::new(
+      ProtocolAttributesType::Identifier("Test".to_owned()),
+      None,
+      None,
+      ServerDeviceMessageAttributesBuilder::default().finish(),
+      Some(Arc::new(default_attributes)),
+    );
+    device_attributes.set_invert_rotation(true);
+    let mgr = GenericCommandManager::new(&device_attributes.flatten());
+    assert_eq!(
+      mgr
+        .update_rotation(&rotate_msg, false)
+        .expect("Test, assuming infallible"),
+      vec![Some((10, true))]
+    );
+  }
   // TODO Write test for vibration stop generator
 }
@@ -0,0 +1,110 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! White-label G-spot vibrators sold as GVibe and under a pile of other brands.
+//!
+//! Every firmware variant advertises the same BLE name, but they don't all have the same motors, so
+//! the name can't tell us which attributes to use. Instead we read the model number characteristic
+//! during identification, and use that to pick the configuration. Early units don't have a usable
+//! model number, and get the protocol defaults.
+
+use crate::server::device::configuration::ProtocolDeviceAttributes;
+use crate::{
+  core::{
+    errors::ButtplugDeviceError,
+    message::{ActuatorType, Endpoint},
+  },
+  server::device::{
+    configuration::ProtocolAttributesType,
+    hardware::{Hardware, HardwareCommand, HardwareReadCmd, HardwareWriteCmd},
+    protocol::{ProtocolHandler, ProtocolIdentifier, ProtocolInitializer},
+    ServerDeviceIdentifier,
+  },
+};
+use async_trait::async_trait;
+use std::sync::Arc;
+
+pub mod setup {
+  use crate::server::device::protocol::{ProtocolIdentifier, ProtocolIdentifierFactory};
+  #[derive(Default)]
+  pub struct GVibeIdentifierFactory {}
+
+  impl ProtocolIdentifierFactory for GVibeIdentifierFactory {
+    fn identifier(&self) -> &str {
+      "gvibe"
+    }
+
+    fn create(&self) -> Box<dyn ProtocolIdentifier> {
+      Box::new(super::GVibeIdentifier::default())
+    }
+  }
+}
+
+#[derive(Default)]
+pub struct GVibeIdentifier {}
+
+#[async_trait]
+impl ProtocolIdentifier for GVibeIdentifier {
+  async fn identify(
+    &mut self,
+    hardware: Arc<Hardware>,
+  ) -> Result<(ServerDeviceIdentifier, Box<dyn ProtocolInitializer>), ButtplugDeviceError> {
+    let model = hardware
+      .read_value(&HardwareReadCmd::new(Endpoint::RxBLEModel, 32, 500))
+      .await
+      .ok()
+      .and_then(|reading| String::from_utf8(reading.data().to_vec()).ok())
+      .map(|model| model.trim_end_matches('\0').trim().to_owned())
+      .filter(|model| !model.is_empty());
+    let attributes_identifier = if let Some(model) = model {
+      info!("GVibe model number: {}", model);
+      ProtocolAttributesType::Identifier(model)
+    } else {
+      info!("GVibe device has no model number, using default attributes.");
+      ProtocolAttributesType::Default
+    };
+    Ok((
+      ServerDeviceIdentifier::new(hardware.address(), "gvibe", &attributes_identifier),
+      Box::new(GVibeInitializer::default()),
+    ))
+  }
+}
+
+#[derive(Default)]
+pub struct GVibeInitializer {}
+
+#[async_trait]
+impl ProtocolInitializer for GVibeInitializer {
+  async fn initialize(
+    &mut self,
+    _: Arc<Hardware>,
+    _: &ProtocolDeviceAttributes,
+  ) -> Result<Arc<dyn ProtocolHandler>, ButtplugDeviceError> {
+    Ok(Arc::new(GVibe::default()))
+  }
+}
+
+#[derive(Default)]
+pub struct GVibe {}
+
+impl ProtocolHandler for GVibe {
+  fn needs_full_command_set(&self) -> bool {
+    true
+  }
+
+  fn handle_scalar_cmd(
+    &self,
+    cmds: &[Option<(ActuatorType, u32)>],
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    // Header, motor count, then one speed per motor.
+    let mut data = vec![0xaa, cmds.len() as u8];
+    for cmd in cmds {
+      data.push(cmd.unwrap_or((ActuatorType::Vibrate, 0)).1 as u8);
+    }
+    Ok(vec![HardwareWriteCmd::new(Endpoint::Tx, data, false).into()])
+  }
+}
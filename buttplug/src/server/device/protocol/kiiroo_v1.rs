@@ -0,0 +1,100 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Kiiroo v1, used by the original Onyx and Pearl.
+//!
+//! Both take a single number followed by a comma and a newline: one of the Onyx's five stroke positions (0-4), or
+//! the Pearl's vibration level (0-4). The Onyx heads straight for whatever position it's sent, so
+//! LinearCmd moves are written out by a [PositionInterpolator].
+
+use crate::{
+  core::{
+    errors::ButtplugDeviceError,
+    message::{self, Endpoint},
+  },
+  server::device::{
+    configuration::{ProtocolAttributesType, ProtocolDeviceAttributes},
+    hardware::{Hardware, HardwareCommand, HardwareWriteCmd},
+    protocol::{
+      generic_protocol_initializer_setup,
+      position_interpolator::{Easing, PositionInterpolator, DEFAULT_STEP_INTERVAL},
+      ProtocolHandler,
+      ProtocolIdentifier,
+      ProtocolInitializer,
+    },
+    ServerDeviceIdentifier,
+  },
+};
+use async_trait::async_trait;
+use std::sync::{
+  atomic::{AtomicU32, Ordering},
+  Arc,
+};
+
+generic_protocol_initializer_setup!(KiirooV1, "kiiroo-v1");
+
+/// Highest stroke position of the Onyx.
+const MAX_POSITION: f64 = 4.0;
+
+fn kiiroo_command(value: u32) -> HardwareCommand {
+  HardwareWriteCmd::new(Endpoint::Tx, format!("{},\n", value).into_bytes(), false).into()
+}
+
+#[derive(Default)]
+pub struct KiirooV1Initializer {}
+
+#[async_trait]
+impl ProtocolInitializer for KiirooV1Initializer {
+  async fn initialize(
+    &mut self,
+    hardware: Arc<Hardware>,
+    _: &ProtocolDeviceAttributes,
+  ) -> Result<Arc<dyn ProtocolHandler>, ButtplugDeviceError> {
+    // Steps are far closer together than the Onyx's positions, so only write the ones that land on
+    // a new position.
+    let last_position = Arc::new(AtomicU32::new(u32::MAX));
+    let linear_interpolator = PositionInterpolator::new(
+      hardware,
+      1,
+      0.0,
+      DEFAULT_STEP_INTERVAL,
+      Easing::Linear,
+      Arc::new(move |_, position| {
+        let position = (position * MAX_POSITION).round() as u32;
+        if last_position.swap(position, Ordering::SeqCst) == position {
+          return Ok(vec![]);
+        }
+        Ok(vec![kiiroo_command(position)])
+      }),
+    );
+    Ok(Arc::new(KiirooV1 {
+      linear_interpolator,
+    }))
+  }
+}
+
+pub struct KiirooV1 {
+  /// Writes out moves of the Onyx's stroker.
+  linear_interpolator: PositionInterpolator,
+}
+
+impl ProtocolHandler for KiirooV1 {
+  fn handle_linear_cmd(
+    &self,
+    message: message::LinearCmd,
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    self.linear_interpolator.handle_linear_cmd(&message)
+  }
+
+  fn handle_scalar_vibrate_cmd(
+    &self,
+    _index: u32,
+    scalar: u32,
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    Ok(vec![kiiroo_command(scalar)])
+  }
+}
@@ -98,11 +98,12 @@ fn form_commands(data: Arc<Vec<AtomicU8>>, force: Option<Vec<bool>>) -> Vec<Vec<
 }
 
 async fn send_longlosttouch_updates(device: Arc<Hardware>, data: Arc<Vec<AtomicU8>>) {
+  let _keepalive = device.start_keepalive();
   loop {
     let cmds = form_commands(data.clone(), None);
     for cmd in cmds {
       if let Err(e) = device
-        .write_value(&HardwareWriteCmd::new(Endpoint::Tx, cmd, true).into())
+        .write_keepalive(&HardwareWriteCmd::new(Endpoint::Tx, cmd, true).into())
         .await
       {
         error!(
@@ -20,7 +20,7 @@ use crate::{
   util::sleep,
 };
 use async_trait::async_trait;
-use futures::{future::BoxFuture, FutureExt};
+use futures::FutureExt;
 use std::{
   sync::{
     atomic::{AtomicBool, Ordering},
@@ -127,10 +127,10 @@ pub struct LovenseInitializer {}
 impl ProtocolInitializer for LovenseInitializer {
   async fn initialize(
     &mut self,
-    _: Arc<Hardware>,
+    hardware: Arc<Hardware>,
     attributes: &ProtocolDeviceAttributes,
   ) -> Result<Arc<dyn ProtocolHandler>, ButtplugDeviceError> {
-    let mut protocol = Lovense::default();
+    let mut protocol = Lovense::new(hardware);
 
     if let Some(scalars) = attributes.message_attributes.scalar_cmd() {
       protocol.vibrator_count = scalars
@@ -150,13 +150,25 @@ impl ProtocolInitializer for LovenseInitializer {
   }
 }
 
-#[derive(Default)]
 pub struct Lovense {
+  hardware: Arc<Hardware>,
   rotation_direction: Arc<AtomicBool>,
   vibrator_count: usize,
   use_mply: bool,
 }
 
+impl Lovense {
+  fn new(hardware: Arc<Hardware>) -> Self {
+    Self {
+      hardware,
+      rotation_direction: Default::default(),
+      vibrator_count: 0,
+      use_mply: false,
+    }
+  }
+}
+
+#[async_trait]
 impl ProtocolHandler for Lovense {
   fn handle_scalar_cmd(
     &self,
@@ -284,60 +296,58 @@ impl ProtocolHandler for Lovense {
     Ok(hardware_cmds)
   }
 
-  fn handle_battery_level_cmd(
+  async fn handle_battery_level_cmd(
     &self,
-    device: Arc<Hardware>,
     message: message::SensorReadCmd,
-  ) -> BoxFuture<Result<ButtplugServerMessage, ButtplugDeviceError>> {
-    let mut device_notification_receiver = device.event_stream();
-    async move {
-      let write_fut = device.write_value(&HardwareWriteCmd::new(
+  ) -> Result<ButtplugServerMessage, ButtplugDeviceError> {
+    let mut device_notification_receiver = self.hardware.event_stream();
+    self
+      .hardware
+      .write_value(&HardwareWriteCmd::new(
         Endpoint::Tx,
         b"Battery;".to_vec(),
         false,
-      ));
-      write_fut.await?;
-      while let Ok(event) = device_notification_receiver.recv().await {
-        match event {
-          HardwareEvent::Notification(_, _, data) => {
-            if let Ok(data_str) = std::str::from_utf8(&data) {
-              debug!("Lovense event received: {}", data_str);
-              let len = data_str.len();
-              // Depending on the state of the toy, we may get an initial
-              // character of some kind, i.e. if the toy is currently vibrating
-              // then battery level comes up as "s89;" versus just "89;". We'll
-              // need to chop the semicolon and make sure we only read the
-              // numbers in the string.
-              //
-              // Contains() is casting a wider net than we need here, but it'll
-              // do for now.
-              let start_pos = usize::from(data_str.contains('s'));
-              if let Ok(level) = data_str[start_pos..(len - 1)].parse::<u8>() {
-                return Ok(
-                  message::SensorReading::new(
-                    message.device_index(),
-                    0,
-                    message::SensorType::Battery,
-                    vec![level as i32],
-                  )
-                  .into(),
-                );
-              }
+      ))
+      .await?;
+    while let Ok(event) = device_notification_receiver.recv().await {
+      match event {
+        HardwareEvent::Notification(_, _, data) => {
+          if let Ok(data_str) = std::str::from_utf8(&data) {
+            debug!("Lovense event received: {}", data_str);
+            let len = data_str.len();
+            // Depending on the state of the toy, we may get an initial
+            // character of some kind, i.e. if the toy is currently vibrating
+            // then battery level comes up as "s89;" versus just "89;". We'll
+            // need to chop the semicolon and make sure we only read the
+            // numbers in the string.
+            //
+            // Contains() is casting a wider net than we need here, but it'll
+            // do for now.
+            let start_pos = usize::from(data_str.contains('s'));
+            if let Ok(level) = data_str[start_pos..(len - 1)].parse::<u8>() {
+              return Ok(
+                message::SensorReading::new(
+                  message.device_index(),
+                  0,
+                  message::SensorType::Battery,
+                  vec![level as i32],
+                )
+                .into(),
+              );
             }
           }
-          HardwareEvent::Disconnected(_) => {
-            return Err(ButtplugDeviceError::ProtocolSpecificError(
-              "Lovense".to_owned(),
-              "Lovense Device disconnected while getting Battery info.".to_owned(),
-            ))
-          }
+        }
+        HardwareEvent::Disconnected(_) => {
+          return Err(ButtplugDeviceError::ProtocolSpecificError(
+            "Lovense".to_owned(),
+            "Lovense Device disconnected while getting Battery info.".to_owned(),
+          ))
         }
       }
-      Err(ButtplugDeviceError::ProtocolSpecificError(
-        "Lovense".to_owned(),
-        "Lovense Device disconnected while getting Battery info.".to_owned(),
-      ))
     }
-    .boxed()
+    Err(ButtplugDeviceError::ProtocolSpecificError(
+      "Lovense".to_owned(),
+      "Lovense Device disconnected while getting Battery info.".to_owned(),
+    ))
   }
 }
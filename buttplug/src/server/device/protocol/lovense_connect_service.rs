@@ -24,7 +24,6 @@ use crate::{
   },
 };
 use async_trait::async_trait;
-use futures::future::{BoxFuture, FutureExt};
 use std::sync::{
   atomic::{AtomicBool, Ordering},
   Arc,
@@ -42,7 +41,7 @@ impl ProtocolInitializer for LovenseConnectServiceInitializer {
     hardware: Arc<Hardware>,
     attributes: &ProtocolDeviceAttributes,
   ) -> Result<Arc<dyn ProtocolHandler>, ButtplugDeviceError> {
-    let mut protocol = LovenseConnectService::new(hardware.address());
+    let mut protocol = LovenseConnectService::new(hardware);
 
     if let Some(scalars) = attributes.message_attributes.scalar_cmd() {
       protocol.vibrator_count = scalars
@@ -69,8 +68,8 @@ impl ProtocolInitializer for LovenseConnectServiceInitializer {
   }
 }
 
-#[derive(Default)]
 pub struct LovenseConnectService {
+  hardware: Arc<Hardware>,
   address: String,
   rotation_direction: Arc<AtomicBool>,
   vibrator_count: usize,
@@ -78,14 +77,18 @@ pub struct LovenseConnectService {
 }
 
 impl LovenseConnectService {
-  pub fn new(address: &str) -> Self {
+  pub fn new(hardware: Arc<Hardware>) -> Self {
     Self {
-      address: address.to_owned(),
-      ..Default::default()
+      address: hardware.address().to_owned(),
+      hardware,
+      rotation_direction: Default::default(),
+      vibrator_count: 0,
+      thusting_count: 0,
     }
   }
 }
 
+#[async_trait]
 impl ProtocolHandler for LovenseConnectService {
   fn handle_scalar_cmd(
     &self,
@@ -279,28 +282,25 @@ impl ProtocolHandler for LovenseConnectService {
     Ok(hardware_cmds)
   }
 
-  fn handle_battery_level_cmd(
+  async fn handle_battery_level_cmd(
     &self,
-    device: Arc<Hardware>,
     msg: message::SensorReadCmd,
-  ) -> BoxFuture<Result<ButtplugServerMessage, ButtplugDeviceError>> {
-    async move {
-      // This is a dummy read. We just store the battery level in the device
-      // implementation and it's the only thing read will return.
-      let reading = device
-        .read_value(&HardwareReadCmd::new(Endpoint::Rx, 0, 0))
-        .await?;
-      debug!("Battery level: {}", reading.data()[0]);
-      Ok(
-        message::SensorReading::new(
-          msg.device_index(),
-          *msg.sensor_index(),
-          *msg.sensor_type(),
-          vec![reading.data()[0] as i32],
-        )
-        .into(),
+  ) -> Result<ButtplugServerMessage, ButtplugDeviceError> {
+    // This is a dummy read. We just store the battery level in the device
+    // implementation and it's the only thing read will return.
+    let reading = self
+      .hardware
+      .read_value(&HardwareReadCmd::new(Endpoint::Rx, 0, 0))
+      .await?;
+    debug!("Battery level: {}", reading.data()[0]);
+    Ok(
+      message::SensorReading::new(
+        msg.device_index(),
+        *msg.sensor_index(),
+        *msg.sensor_type(),
+        vec![reading.data()[0] as i32],
       )
-    }
-    .boxed()
+      .into(),
+    )
   }
 }
@@ -11,27 +11,34 @@ pub mod generic_command_manager;
 
 // Utility mods
 pub mod fleshlight_launch_helper;
+pub mod position_interpolator;
 
 // Since users can pick and choose protocols, we need all of these to be public.
 pub mod adrienlastic;
 pub mod aneros;
 pub mod ankni;
+#[cfg(feature = "passthrough-protocols")]
 pub mod buttplug_passthru;
 pub mod cachito;
 pub mod cowgirl;
 pub mod foreo;
 pub mod fox;
+#[cfg(feature = "stroker-protocols")]
 pub mod fredorch;
 pub mod fredorch_rotary;
 pub mod galaku_pump;
+pub mod gvibe;
 pub mod hgod;
 pub mod hismith;
 pub mod hismith_mini;
 pub mod htk_bm;
 pub mod jejoue;
 pub mod kgoal_boost;
+pub mod kiiroo_v1;
+#[cfg(feature = "stroker-protocols")]
 pub mod kiiroo_v2;
 pub mod kiiroo_v21;
+#[cfg(feature = "stroker-protocols")]
 pub mod kiiroo_v21_initialized;
 pub mod kiiroo_v2_vibrator;
 pub mod kizuna;
@@ -60,12 +67,14 @@ pub mod mizzzee_v2;
 pub mod motorbunny;
 pub mod mysteryvibe;
 pub mod mysteryvibe_v2;
+#[cfg(feature = "gamepad-protocols")]
 pub mod nintendo_joycon;
 pub mod nobra;
 pub mod patoo;
 pub mod picobong;
 pub mod pink_punch;
 pub mod prettylove;
+#[cfg(feature = "passthrough-protocols")]
 pub mod raw_protocol;
 pub mod realov;
 pub mod sakuraneko;
@@ -83,7 +92,9 @@ pub mod svakom_v2;
 pub mod svakom_v3;
 pub mod svakom_v4;
 pub mod synchro;
+#[cfg(feature = "stroker-protocols")]
 pub mod tcode_v03;
+#[cfg(feature = "stroker-protocols")]
 pub mod thehandy;
 pub mod tryfun;
 pub mod vibratissimo;
@@ -93,6 +104,7 @@ pub mod wevibe;
 pub mod wevibe8bit;
 pub mod wevibe_chorus;
 pub mod xibao;
+#[cfg(feature = "gamepad-protocols")]
 pub mod xinput;
 pub mod xiuxiuda;
 pub mod youcups;
@@ -120,10 +132,7 @@ use crate::{
   },
 };
 use async_trait::async_trait;
-use futures::{
-  future::{self, BoxFuture, FutureExt},
-  StreamExt,
-};
+use futures::StreamExt;
 use std::pin::Pin;
 use std::{collections::HashMap, sync::Arc};
 
@@ -149,6 +158,7 @@ pub fn get_default_protocol_map() -> HashMap<String, Arc<dyn ProtocolIdentifierF
     adrienlastic::setup::AdrienLasticIdentifierFactory::default(),
   );
   add_to_protocol_map(&mut map, aneros::setup::AnerosIdentifierFactory::default());
+  #[cfg(feature = "passthrough-protocols")]
   add_to_protocol_map(
     &mut map,
     buttplug_passthru::setup::ButtplugPassthruIdentifierFactory::default(),
@@ -174,6 +184,7 @@ pub fn get_default_protocol_map() -> HashMap<String, Arc<dyn ProtocolIdentifierF
     hismith_mini::setup::HismithMiniIdentifierFactory::default(),
   );
   add_to_protocol_map(&mut map, htk_bm::setup::HtkBmIdentifierFactory::default());
+  #[cfg(feature = "stroker-protocols")]
   add_to_protocol_map(
     &mut map,
     thehandy::setup::TheHandyIdentifierFactory::default(),
@@ -182,6 +193,7 @@ pub fn get_default_protocol_map() -> HashMap<String, Arc<dyn ProtocolIdentifierF
   add_to_protocol_map(&mut map, ankni::setup::AnkniIdentifierFactory::default());
   add_to_protocol_map(&mut map, foreo::setup::ForeoIdentifierFactory::default());
   add_to_protocol_map(&mut map, fox::setup::FoxIdentifierFactory::default());
+  #[cfg(feature = "stroker-protocols")]
   add_to_protocol_map(
     &mut map,
     fredorch::setup::FredorchIdentifierFactory::default(),
@@ -197,8 +209,14 @@ pub fn get_default_protocol_map() -> HashMap<String, Arc<dyn ProtocolIdentifierF
     &mut map,
     galaku_pump::setup::GalakuPumpIdentifierFactory::default(),
   );
+  add_to_protocol_map(&mut map, gvibe::setup::GVibeIdentifierFactory::default());
 
   add_to_protocol_map(&mut map, jejoue::setup::JeJoueIdentifierFactory::default());
+  add_to_protocol_map(
+    &mut map,
+    kiiroo_v1::setup::KiirooV1IdentifierFactory::default(),
+  );
+  #[cfg(feature = "stroker-protocols")]
   add_to_protocol_map(
     &mut map,
     kiiroo_v2::setup::KiirooV2IdentifierFactory::default(),
@@ -211,6 +229,7 @@ pub fn get_default_protocol_map() -> HashMap<String, Arc<dyn ProtocolIdentifierF
     &mut map,
     kiiroo_v21::setup::KiirooV21IdentifierFactory::default(),
   );
+  #[cfg(feature = "stroker-protocols")]
   add_to_protocol_map(
     &mut map,
     kiiroo_v21_initialized::setup::KiirooV21InitializedIdentifierFactory::default(),
@@ -304,6 +323,7 @@ pub fn get_default_protocol_map() -> HashMap<String, Arc<dyn ProtocolIdentifierF
     &mut map,
     mysteryvibe_v2::setup::MysteryVibeV2IdentifierFactory::default(),
   );
+  #[cfg(feature = "gamepad-protocols")]
   add_to_protocol_map(
     &mut map,
     nintendo_joycon::setup::NintendoJoyconIdentifierFactory::default(),
@@ -322,6 +342,7 @@ pub fn get_default_protocol_map() -> HashMap<String, Arc<dyn ProtocolIdentifierF
     &mut map,
     prettylove::setup::PrettyLoveIdentifierFactory::default(),
   );
+  #[cfg(feature = "passthrough-protocols")]
   add_to_protocol_map(
     &mut map,
     raw_protocol::setup::RawProtocolIdentifierFactory::default(),
@@ -382,6 +403,7 @@ pub fn get_default_protocol_map() -> HashMap<String, Arc<dyn ProtocolIdentifierF
     synchro::setup::SynchroIdentifierFactory::default(),
   );
   add_to_protocol_map(&mut map, tryfun::setup::TryFunIdentifierFactory::default());
+  #[cfg(feature = "stroker-protocols")]
   add_to_protocol_map(
     &mut map,
     tcode_v03::setup::TCodeV03IdentifierFactory::default(),
@@ -405,6 +427,7 @@ pub fn get_default_protocol_map() -> HashMap<String, Arc<dyn ProtocolIdentifierF
     wevibe_chorus::setup::WeVibeChorusIdentifierFactory::default(),
   );
   add_to_protocol_map(&mut map, xibao::setup::XibaoIdentifierFactory::default());
+  #[cfg(feature = "gamepad-protocols")]
   add_to_protocol_map(&mut map, xinput::setup::XInputIdentifierFactory::default());
   add_to_protocol_map(
     &mut map,
@@ -423,6 +446,32 @@ pub fn get_default_protocol_map() -> HashMap<String, Arc<dyn ProtocolIdentifierF
   map
 }
 
+/// Names of the protocols whose family was left out of this build. Their entries in the built in
+/// device configuration are skipped, since no device could use them.
+///
+/// A family only covers protocols used by nothing but that kind of hardware. Protocols shared with
+/// other hardware, like kiiroo-v21 (Kiiroo Titan and vibrators) or vorze-sa (Vorze Piston and
+/// rotators), are always built.
+pub(crate) fn compiled_out_protocols() -> Vec<&'static str> {
+  let mut protocols = vec![];
+  if cfg!(not(feature = "gamepad-protocols")) {
+    protocols.extend(["xinput", "nintendo-joycon"]);
+  }
+  if cfg!(not(feature = "stroker-protocols")) {
+    protocols.extend([
+      "thehandy",
+      "tcode-v03",
+      "kiiroo-v2",
+      "kiiroo-v21-initialized",
+      "fredorch",
+    ]);
+  }
+  if cfg!(not(feature = "passthrough-protocols")) {
+    protocols.extend(["buttplug-passthru", "raw"]);
+  }
+  protocols
+}
+
 fn print_type_of<T>(_: &T) -> &'static str {
   std::any::type_name::<T>()
 }
@@ -460,6 +509,11 @@ pub trait ProtocolIdentifier: Sync + Send {
   ) -> Result<(ServerDeviceIdentifier, Box<dyn ProtocolInitializer>), ButtplugDeviceError>;
 }
 
+/// Sets up a device once its protocol is known, returning the handler for it.
+///
+/// Init sequences that can't have other traffic land in the middle of them (keepalives started by
+/// earlier steps, reads from another task, etc) should run under
+/// [Hardware::lock_exclusive] instead of spacing things out with sleeps.
 #[async_trait]
 pub trait ProtocolInitializer: Sync + Send {
   async fn initialize(
@@ -469,15 +523,19 @@ pub trait ProtocolInitializer: Sync + Send {
   ) -> Result<Arc<dyn ProtocolHandler>, ButtplugDeviceError>;
 }
 
+/// Builds a protocol handler for hardware once it's connected.
+pub type ProtocolHandlerBuilder =
+  Box<dyn FnOnce(Arc<Hardware>) -> Arc<dyn ProtocolHandler> + Send + Sync>;
+
 pub struct GenericProtocolIdentifier {
-  handler: Option<Arc<dyn ProtocolHandler>>,
+  handler_builder: Option<ProtocolHandlerBuilder>,
   protocol_identifier: String,
 }
 
 impl GenericProtocolIdentifier {
-  pub fn new(handler: Arc<dyn ProtocolHandler>, protocol_identifier: &str) -> Self {
+  pub fn new(handler_builder: ProtocolHandlerBuilder, protocol_identifier: &str) -> Self {
     Self {
-      handler: Some(handler),
+      handler_builder: Some(handler_builder),
       protocol_identifier: protocol_identifier.to_owned(),
     }
   }
@@ -497,20 +555,20 @@ impl ProtocolIdentifier for GenericProtocolIdentifier {
     Ok((
       device_identifier,
       Box::new(GenericProtocolInitializer::new(
-        self.handler.take().unwrap(),
+        self.handler_builder.take().unwrap(),
       )),
     ))
   }
 }
 
 pub struct GenericProtocolInitializer {
-  handler: Option<Arc<dyn ProtocolHandler>>,
+  handler_builder: Option<ProtocolHandlerBuilder>,
 }
 
 impl GenericProtocolInitializer {
-  pub fn new(handler: Arc<dyn ProtocolHandler>) -> Self {
+  pub fn new(handler_builder: ProtocolHandlerBuilder) -> Self {
     Self {
-      handler: Some(handler),
+      handler_builder: Some(handler_builder),
     }
   }
 }
@@ -519,13 +577,66 @@ impl GenericProtocolInitializer {
 impl ProtocolInitializer for GenericProtocolInitializer {
   async fn initialize(
     &mut self,
-    _: Arc<Hardware>,
+    hardware: Arc<Hardware>,
     _: &ProtocolDeviceAttributes,
   ) -> Result<Arc<dyn ProtocolHandler>, ButtplugDeviceError> {
-    Ok(self.handler.take().unwrap())
+    Ok((self.handler_builder.take().unwrap())(hardware))
+  }
+}
+
+/// Reads the battery level from the standard BLE battery service. The
+/// [ServerDevice](crate::server::device::ServerDevice) falls back to this for battery reads the
+/// protocol handler doesn't handle itself, since it's the same for every protocol.
+pub(crate) async fn read_standard_battery_level(
+  hardware: &Hardware,
+  message: message::SensorReadCmd,
+) -> Result<ButtplugServerMessage, ButtplugDeviceError> {
+  if !hardware.endpoints().contains(&Endpoint::RxBLEBattery) {
+    return Err(ButtplugDeviceError::UnhandledCommand(
+      "Command not implemented for this protocol: SensorReadCmd".to_string(),
+    ));
   }
+  debug!("Trying to get battery reading.");
+  let hw_msg = hardware
+    .read_value(&HardwareReadCmd::new(Endpoint::RxBLEBattery, 1, 0))
+    .await?;
+  let battery_level = if let Some(level) = hw_msg.data().first() {
+    *level as i32
+  } else {
+    return Err(ButtplugDeviceError::DeviceCommunicationError(
+      "Battery read returned no data".to_owned(),
+    ));
+  };
+  debug!("Got battery reading: {}", battery_level);
+  Ok(
+    message::SensorReading::new(
+      message.device_index(),
+      *message.sensor_index(),
+      *message.sensor_type(),
+      vec![battery_level],
+    )
+    .into(),
+  )
 }
 
+/// Turns Buttplug device messages into hardware commands for a specific protocol.
+///
+/// Most commands only need to build [HardwareCommand]s, which the
+/// [ServerDevice](crate::server::device::ServerDevice) then sends to the hardware, so those methods
+/// are synchronous. Commands that need to wait on the hardware (sensor reads and subscriptions) are
+/// async.
+///
+/// Handlers are built once their hardware is connected, and own whatever they need to talk to it.
+/// Handlers set up with [generic_protocol_setup] that need the hardware are built with `new`,
+/// others with `Default`. Handlers built by a [ProtocolInitializer] get the hardware in
+/// [initialize](ProtocolInitializer::initialize).
+///
+/// Implementations only need `#[async_trait]` on their impl block if they override one of the
+/// async methods.
+///
+/// Handlers that need to send a multi-step sequence without keepalives or other commands getting
+/// in between can hold [Hardware::lock_exclusive] while they send it.
+#[async_trait]
 pub trait ProtocolHandler: Sync + Send {
   fn needs_full_command_set(&self) -> bool {
     false
@@ -573,6 +684,9 @@ pub trait ProtocolHandler: Sync + Send {
           ActuatorType::Rotate => self.handle_scalar_rotate_cmd(index as u32, *scalar)?,
           ActuatorType::Vibrate => self.handle_scalar_vibrate_cmd(index as u32, *scalar)?,
           ActuatorType::Position => self.handle_scalar_position_cmd(index as u32, *scalar)?,
+          ActuatorType::RotatePosition => Err(ButtplugDeviceError::UnhandledCommand(
+            "RotatePosition actuators are controlled with LinearCmd.".to_owned(),
+          ))?,
           ActuatorType::Unknown => Err(ButtplugDeviceError::UnhandledCommand(
             "Unknown actuator types are not controllable.".to_owned(),
           ))?,
@@ -665,83 +779,54 @@ pub trait ProtocolHandler: Sync + Send {
     self.command_unimplemented(print_type_of(&message))
   }
 
-  fn handle_sensor_subscribe_cmd(
+  async fn handle_sensor_subscribe_cmd(
     &self,
-    _device: Arc<Hardware>,
     _message: message::SensorSubscribeCmd,
-  ) -> BoxFuture<Result<ButtplugServerMessage, ButtplugDeviceError>> {
-    future::ready(Err(ButtplugDeviceError::UnhandledCommand(
-      "Command not implemented for this protocol: BatteryCmd".to_string(),
-    )))
-    .boxed()
+  ) -> Result<ButtplugServerMessage, ButtplugDeviceError> {
+    Err(ButtplugDeviceError::UnhandledCommand(
+      "Command not implemented for this protocol: SensorSubscribeCmd".to_string(),
+    ))
   }
 
-  fn handle_sensor_unsubscribe_cmd(
+  async fn handle_sensor_unsubscribe_cmd(
     &self,
-    _device: Arc<Hardware>,
     _message: message::SensorUnsubscribeCmd,
-  ) -> BoxFuture<Result<ButtplugServerMessage, ButtplugDeviceError>> {
-    future::ready(Err(ButtplugDeviceError::UnhandledCommand(
-      "Command not implemented for this protocol: BatteryCmd".to_string(),
-    )))
-    .boxed()
+  ) -> Result<ButtplugServerMessage, ButtplugDeviceError> {
+    Err(ButtplugDeviceError::UnhandledCommand(
+      "Command not implemented for this protocol: SensorUnsubscribeCmd".to_string(),
+    ))
   }
 
-  fn handle_sensor_read_cmd(
+  async fn handle_sensor_read_cmd(
     &self,
-    device: Arc<Hardware>,
     message: message::SensorReadCmd,
-  ) -> BoxFuture<Result<ButtplugServerMessage, ButtplugDeviceError>> {
+  ) -> Result<ButtplugServerMessage, ButtplugDeviceError> {
     match message.sensor_type() {
-      SensorType::Battery => self.handle_battery_level_cmd(device, message),
-      _ => future::ready(Err(ButtplugDeviceError::UnhandledCommand(
+      SensorType::Battery => self.handle_battery_level_cmd(message).await,
+      _ => Err(ButtplugDeviceError::UnhandledCommand(
         "Command not implemented for this protocol: SensorReadCmd".to_string(),
-      )))
-      .boxed(),
+      )),
     }
   }
 
-  fn handle_battery_level_cmd(
+  /// Reads the battery level of hardware with its own battery command. Hardware with the standard
+  /// BLE battery service is read without going through the handler.
+  async fn handle_battery_level_cmd(
     &self,
-    device: Arc<Hardware>,
-    message: message::SensorReadCmd,
-  ) -> BoxFuture<Result<ButtplugServerMessage, ButtplugDeviceError>> {
-    // If we have a standardized BLE Battery endpoint, handle that above the
-    // protocol, as it'll always be the same.
-    if device.endpoints().contains(&Endpoint::RxBLEBattery) {
-      debug!("Trying to get battery reading.");
-      let msg = HardwareReadCmd::new(Endpoint::RxBLEBattery, 1, 0);
-      let fut = device.read_value(&msg);
-      async move {
-        let hw_msg = fut.await?;
-        let battery_level = hw_msg.data()[0] as i32;
-        let battery_reading = message::SensorReading::new(
-          message.device_index(),
-          *message.sensor_index(),
-          *message.sensor_type(),
-          vec![battery_level],
-        );
-        debug!("Got battery reading: {}", battery_level);
-        Ok(battery_reading.into())
-      }
-      .boxed()
-    } else {
-      future::ready(Err(ButtplugDeviceError::UnhandledCommand(
-        "Command not implemented for this protocol: SensorReadCmd".to_string(),
-      )))
-      .boxed()
-    }
+    _message: message::SensorReadCmd,
+  ) -> Result<ButtplugServerMessage, ButtplugDeviceError> {
+    Err(ButtplugDeviceError::UnhandledCommand(
+      "Command not implemented for this protocol: SensorReadCmd".to_string(),
+    ))
   }
 
-  fn handle_rssi_level_cmd(
+  async fn handle_rssi_level_cmd(
     &self,
-    _device: Arc<Hardware>,
     _message: message::RSSILevelCmd,
-  ) -> BoxFuture<Result<ButtplugServerMessage, ButtplugDeviceError>> {
-    future::ready(Err(ButtplugDeviceError::UnhandledCommand(
-      "Command not implemented for this protocol: SensorReadCmd".to_string(),
-    )))
-    .boxed()
+  ) -> Result<ButtplugServerMessage, ButtplugDeviceError> {
+    Err(ButtplugDeviceError::UnhandledCommand(
+      "Command not implemented for this protocol: RSSILevelCmd".to_string(),
+    ))
   }
 
   fn event_stream(
@@ -770,7 +855,33 @@ macro_rules! generic_protocol_setup {
 
           fn create(&self) -> Box<dyn ProtocolIdentifier> {
             Box::new(GenericProtocolIdentifier::new(
-              Arc::new(super::$protocol_name::default()),
+              Box::new(|_| Arc::new(super::$protocol_name::default())),
+              self.identifier(),
+            ))
+          }
+        }
+      }
+    }
+  };
+  // Handlers that talk to the hardware themselves are built with it, using `new(hardware)`.
+  ( $protocol_name:ident, $protocol_identifier:tt, with_hardware) => {
+    paste::paste! {
+      pub mod setup {
+        use std::sync::Arc;
+        use $crate::server::device::protocol::{
+          GenericProtocolIdentifier, ProtocolIdentifier, ProtocolIdentifierFactory,
+        };
+        #[derive(Default)]
+        pub struct [< $protocol_name IdentifierFactory >] {}
+
+        impl ProtocolIdentifierFactory for  [< $protocol_name IdentifierFactory >] {
+          fn identifier(&self) -> &str {
+            $protocol_identifier
+          }
+
+          fn create(&self) -> Box<dyn ProtocolIdentifier> {
+            Box::new(GenericProtocolIdentifier::new(
+              Box::new(|hardware| Arc::new(super::$protocol_name::new(hardware))),
               self.identifier(),
             ))
           }
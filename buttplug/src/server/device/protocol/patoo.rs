@@ -57,7 +57,7 @@ impl ProtocolIdentifier for PatooIdentifier {
     Ok((
       ServerDeviceIdentifier::new(
         hardware.address(),
-        "Patoo",
+        "patoo",
         &ProtocolAttributesType::Identifier(name),
       ),
       Box::new(PatooInitializer::default()),
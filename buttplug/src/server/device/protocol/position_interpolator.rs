@@ -0,0 +1,465 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Position interpolation for linear hardware that only takes positions.
+//!
+//! LinearCmd asks for a position to be reached over a duration. Hardware that takes a position and
+//! a duration or speed (T-Code, Fleshlight Launch style commands) does the movement itself, but
+//! some hardware, like the Kiiroo v1 Onyx, only takes a position and heads straight for it.
+//! Protocols for that hardware can hand their moves to a [PositionInterpolator], which breaks each
+//! move up into small steps and writes them out over the duration of the move, following an
+//! [Easing] curve.
+
+use crate::{
+  core::{errors::ButtplugDeviceError, message::LinearCmd},
+  server::device::hardware::{Hardware, HardwareCommand},
+  util::{async_manager, sleep},
+};
+#[cfg(feature = "wasm")]
+use std::time::Instant;
+use std::{
+  sync::{Arc, Mutex},
+  time::Duration,
+};
+#[cfg(not(feature = "wasm"))]
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+/// Time between position writes, unless a protocol needs something else.
+pub const DEFAULT_STEP_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Shape of a move from one position to another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Easing {
+  /// Constant speed the whole way.
+  #[default]
+  Linear,
+  /// Starts slow and speeds up.
+  EaseIn,
+  /// Starts fast and slows down.
+  EaseOut,
+  /// Starts slow, speeds up, then slows down again before stopping.
+  EaseInOut,
+}
+
+impl Easing {
+  /// Maps how far through a move we are in time (0.0 to 1.0) to how far through the distance of the
+  /// move the position should be.
+  pub fn apply(&self, progress: f64) -> f64 {
+    let t = progress.clamp(0.0, 1.0);
+    match self {
+      Easing::Linear => t,
+      Easing::EaseIn => t * t,
+      Easing::EaseOut => t * (2.0 - t),
+      Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+    }
+  }
+}
+
+/// A move of an axis from one position to another.
+#[derive(Debug, Clone, Copy)]
+struct Move {
+  from: f64,
+  to: f64,
+  duration: Duration,
+  easing: Easing,
+}
+
+impl Move {
+  fn position_at(&self, elapsed: Duration) -> f64 {
+    if elapsed >= self.duration {
+      return self.to;
+    }
+    let progress = elapsed.as_secs_f64() / self.duration.as_secs_f64();
+    self.from + (self.to - self.from) * self.easing.apply(progress)
+  }
+
+  /// Positions to write for the move, as (time since the start of the move, position), `step`
+  /// apart. The last one is always the target, at the end of the move.
+  fn steps(&self, step: Duration) -> Vec<(Duration, f64)> {
+    let mut steps = vec![];
+    let mut elapsed = step;
+    while elapsed < self.duration {
+      steps.push((elapsed, self.position_at(elapsed)));
+      elapsed += step;
+    }
+    steps.push((self.duration, self.to));
+    steps
+  }
+}
+
+#[derive(Default)]
+struct AxisState {
+  /// Latest move of the axis and when it started, or None if it hasn't been moved yet.
+  last_move: Option<(Move, Instant)>,
+  /// Cancels the task writing out the latest move.
+  token: Option<CancellationToken>,
+}
+
+/// Builds the hardware commands that send an axis to a position (0.0 to 1.0).
+pub type PositionWriter =
+  Arc<dyn Fn(u32, f64) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> + Send + Sync>;
+
+/// Turns LinearCmd moves into a stream of timed position writes for each axis of a device.
+///
+/// Each move starts from wherever the axis is on the way to its last target, so a new move cuts
+/// short the one before it instead of waiting for it. Position-only hardware can't say where it is,
+/// so the first move of an axis starts from the `start` position the protocol gives, and still
+/// takes the duration asked for.
+pub struct PositionInterpolator {
+  hardware: Arc<Hardware>,
+  writer: PositionWriter,
+  start: f64,
+  step: Duration,
+  easing: Easing,
+  axes: Mutex<Vec<AxisState>>,
+}
+
+impl PositionInterpolator {
+  pub fn new(
+    hardware: Arc<Hardware>,
+    axis_count: usize,
+    start: f64,
+    step: Duration,
+    easing: Easing,
+    writer: PositionWriter,
+  ) -> Self {
+    Self {
+      hardware,
+      writer,
+      start: start.clamp(0.0, 1.0),
+      // Steps of nothing would never get anywhere.
+      step: step.max(Duration::from_millis(1)),
+      easing,
+      axes: Mutex::new((0..axis_count).map(|_| AxisState::default()).collect()),
+    }
+  }
+
+  /// Starts moving `axis` to `position` over `duration` milliseconds.
+  pub fn move_to(
+    &self,
+    axis: u32,
+    position: f64,
+    duration: u32,
+  ) -> Result<(), ButtplugDeviceError> {
+    let mut axes = self
+      .axes
+      .lock()
+      .expect("Interpolator lock should never be poisoned.");
+    let axis_count = axes.len() as u32;
+    let state = axes
+      .get_mut(axis as usize)
+      .ok_or(ButtplugDeviceError::DeviceFeatureIndexError(
+        axis_count, axis,
+      ))?;
+    if let Some(token) = state.token.take() {
+      token.cancel();
+    }
+    let now = Instant::now();
+    let from = match state.last_move {
+      Some((last_move, started)) => last_move.position_at(now.saturating_duration_since(started)),
+      None => self.start,
+    };
+    let next_move = Move {
+      from,
+      to: position,
+      duration: Duration::from_millis(duration as u64),
+      easing: self.easing,
+    };
+    let token = CancellationToken::new();
+    async_manager::spawn(write_steps(
+      self.hardware.clone(),
+      self.writer.clone(),
+      axis,
+      next_move.steps(self.step),
+      now,
+      token.child_token(),
+    ));
+    state.last_move = Some((next_move, now));
+    state.token = Some(token);
+    Ok(())
+  }
+
+  /// Starts the moves in a LinearCmd. The positions are written in the background, so there are
+  /// never any commands to hand back, but this returns the same type as
+  /// [ProtocolHandler::handle_linear_cmd](super::ProtocolHandler::handle_linear_cmd) so handlers can
+  /// pass straight through to it.
+  pub fn handle_linear_cmd(
+    &self,
+    message: &LinearCmd,
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    for vector in message.vectors() {
+      self.move_to(vector.index(), vector.position(), vector.duration())?;
+    }
+    Ok(vec![])
+  }
+
+  /// Stops every axis wherever it's got to.
+  pub fn stop(&self) {
+    let now = Instant::now();
+    for state in self
+      .axes
+      .lock()
+      .expect("Interpolator lock should never be poisoned.")
+      .iter_mut()
+    {
+      if let Some(token) = state.token.take() {
+        token.cancel();
+      }
+      if let Some((last_move, started)) = state.last_move.as_mut() {
+        let position = last_move.position_at(now.saturating_duration_since(*started));
+        *last_move = Move {
+          from: position,
+          to: position,
+          duration: Duration::ZERO,
+          easing: last_move.easing,
+        };
+      }
+    }
+  }
+}
+
+impl Drop for PositionInterpolator {
+  fn drop(&mut self) {
+    self.stop();
+  }
+}
+
+async fn write_steps(
+  hardware: Arc<Hardware>,
+  writer: PositionWriter,
+  axis: u32,
+  steps: Vec<(Duration, f64)>,
+  started: Instant,
+  token: CancellationToken,
+) {
+  for (elapsed, position) in steps {
+    let wait = (started + elapsed).saturating_duration_since(Instant::now());
+    tokio::select! {
+      biased;
+      _ = token.cancelled() => return,
+      _ = sleep(wait) => {},
+    }
+    let commands = match writer(axis, position) {
+      Ok(commands) => commands,
+      Err(err) => {
+        warn!(
+          "Could not build position write for axis {}: {:?}",
+          axis, err
+        );
+        return;
+      }
+    };
+    for command in commands {
+      if let Err(err) = hardware.parse_message(&command).await {
+        warn!("Could not write position for axis {}: {:?}", axis, err);
+        return;
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::{
+    core::message::{Endpoint, VectorSubcommand},
+    server::device::hardware::{
+      HardwareEvent,
+      HardwareInternal,
+      HardwareReadCmd,
+      HardwareReading,
+      HardwareSubscribeCmd,
+      HardwareUnsubscribeCmd,
+      HardwareWriteCmd,
+    },
+  };
+  use futures::future::{self, BoxFuture, FutureExt};
+  use tokio::sync::broadcast;
+
+  // Records the first byte of every write.
+  struct RecordingHardware {
+    writes: Arc<Mutex<Vec<u8>>>,
+  }
+
+  impl HardwareInternal for RecordingHardware {
+    fn disconnect(&self) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+      future::ready(Ok(())).boxed()
+    }
+
+    fn event_stream(&self) -> broadcast::Receiver<HardwareEvent> {
+      broadcast::channel(1).1
+    }
+
+    fn read_value(
+      &self,
+      _msg: &HardwareReadCmd,
+    ) -> BoxFuture<'static, Result<HardwareReading, ButtplugDeviceError>> {
+      unimplemented!("Test hardware only writes")
+    }
+
+    fn write_value(
+      &self,
+      msg: &HardwareWriteCmd,
+    ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+      self
+        .writes
+        .lock()
+        .expect("Test, assuming infallible.")
+        .push(msg.data()[0]);
+      future::ready(Ok(())).boxed()
+    }
+
+    fn subscribe(
+      &self,
+      _msg: &HardwareSubscribeCmd,
+    ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+      unimplemented!("Test hardware only writes")
+    }
+
+    fn unsubscribe(
+      &self,
+      _msg: &HardwareUnsubscribeCmd,
+    ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+      unimplemented!("Test hardware only writes")
+    }
+  }
+
+  fn percent_steps(steps: Vec<(Duration, f64)>) -> Vec<(u128, i32)> {
+    steps
+      .into_iter()
+      .map(|(elapsed, position)| (elapsed.as_millis(), (position * 100.0).round() as i32))
+      .collect()
+  }
+
+  #[test]
+  fn test_easing() {
+    for easing in [
+      Easing::Linear,
+      Easing::EaseIn,
+      Easing::EaseOut,
+      Easing::EaseInOut,
+    ] {
+      assert_eq!(easing.apply(0.0), 0.0);
+      assert_eq!(easing.apply(1.0), 1.0);
+      assert_eq!(easing.apply(2.0), 1.0);
+    }
+    assert_eq!(Easing::Linear.apply(0.5), 0.5);
+    assert_eq!(Easing::EaseIn.apply(0.5), 0.25);
+    assert_eq!(Easing::EaseOut.apply(0.5), 0.75);
+    assert_eq!(Easing::EaseInOut.apply(0.5), 0.5);
+    assert!(Easing::EaseInOut.apply(0.25) < 0.25);
+    assert!(Easing::EaseInOut.apply(0.75) > 0.75);
+  }
+
+  #[test]
+  fn test_move_steps() {
+    let mut linear_move = Move {
+      from: 0.2,
+      to: 1.0,
+      duration: Duration::from_millis(100),
+      easing: Easing::Linear,
+    };
+    assert_eq!(
+      percent_steps(linear_move.steps(Duration::from_millis(30))),
+      vec![(30, 44), (60, 68), (90, 92), (100, 100)]
+    );
+    linear_move.easing = Easing::EaseIn;
+    assert_eq!(
+      percent_steps(linear_move.steps(Duration::from_millis(50))),
+      vec![(50, 40), (100, 100)]
+    );
+    // Moves shorter than a step go straight to the target.
+    linear_move.duration = Duration::from_millis(10);
+    assert_eq!(
+      percent_steps(linear_move.steps(Duration::from_millis(20))),
+      vec![(10, 100)]
+    );
+  }
+
+  // Lets time pass, then lets the write tasks catch up with it.
+  async fn advance(millis: u64) {
+    tokio::time::advance(Duration::from_millis(millis)).await;
+    for _ in 0..10 {
+      tokio::task::yield_now().await;
+    }
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn test_interpolated_moves() {
+    let writes = Arc::new(Mutex::new(vec![]));
+    let hardware = Arc::new(Hardware::new(
+      "Test",
+      "Test",
+      &[Endpoint::Tx],
+      Box::new(RecordingHardware {
+        writes: writes.clone(),
+      }),
+    ));
+    let interpolator = PositionInterpolator::new(
+      hardware,
+      1,
+      0.0,
+      Duration::from_millis(20),
+      Easing::Linear,
+      Arc::new(|_, position| {
+        Ok(vec![HardwareWriteCmd::new(
+          Endpoint::Tx,
+          vec![(position * 100.0).round() as u8],
+          false,
+        )
+        .into()])
+      }),
+    );
+    let take_writes = || std::mem::take(&mut *writes.lock().expect("Test, assuming infallible."));
+
+    // The first move starts from the start position, and takes as long as it was asked to. Each
+    // step is written when it's due, and not before.
+    assert!(interpolator
+      .handle_linear_cmd(&LinearCmd::new(0, vec![VectorSubcommand::new(0, 100, 1.0)]))
+      .expect("Test, assuming infallible.")
+      .is_empty());
+    advance(19).await;
+    assert!(take_writes().is_empty());
+    advance(1).await;
+    assert_eq!(take_writes(), vec![20]);
+    for _ in 0..4 {
+      advance(20).await;
+    }
+    assert_eq!(take_writes(), vec![40, 60, 80, 100]);
+    advance(100).await;
+    assert!(take_writes().is_empty());
+    assert!(interpolator.move_to(1, 0.5, 100).is_err());
+
+    // A new move cuts the last one short, starting from wherever it got to.
+    interpolator
+      .move_to(0, 0.0, 1000)
+      .expect("Test, assuming infallible.");
+    for _ in 0..5 {
+      advance(20).await;
+    }
+    assert_eq!(take_writes(), vec![98, 96, 94, 92, 90]);
+    interpolator
+      .move_to(0, 1.0, 100)
+      .expect("Test, assuming infallible.");
+    for _ in 0..5 {
+      advance(20).await;
+    }
+    assert_eq!(take_writes(), vec![92, 94, 96, 98, 100]);
+
+    // Stopped moves don't write anything else.
+    interpolator
+      .move_to(0, 0.0, 1000)
+      .expect("Test, assuming infallible.");
+    advance(20).await;
+    advance(20).await;
+    interpolator.stop();
+    assert_eq!(take_writes(), vec![98, 96]);
+    advance(1000).await;
+    assert!(take_writes().is_empty());
+  }
+}
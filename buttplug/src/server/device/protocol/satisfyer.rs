@@ -118,10 +118,11 @@ async fn send_satisfyer_updates(
   feature_count: usize,
   data: Arc<Vec<AtomicU8>>,
 ) {
+  let _keepalive = device.start_keepalive();
   loop {
     let command = form_command(feature_count, data.clone());
     if let Err(e) = device
-      .write_value(&HardwareWriteCmd::new(Endpoint::Tx, command, false))
+      .write_keepalive(&HardwareWriteCmd::new(Endpoint::Tx, command, false))
       .await
     {
       error!(
@@ -5,21 +5,185 @@
 // Licensed under the BSD 3-Clause license. See LICENSE file in the project root
 // for full license information.
 
+//! T-code v0.3, used by open source strokers and multi-axis devices like the OSR-2 and SR-6.
+//!
+//! T-code addresses outputs by axis: L0-L2 are linear axes (stroke, surge, sway), R0-R2 are
+//! rotation axes (twist, roll, pitch), V0-V9 are vibration channels and A0-A9 are auxiliary
+//! channels. Values are the digits after a decimal point, so both "L05" and "L050" send L0 to the
+//! middle of its range.
+//!
+//! The device configuration only describes a single linear axis, since that's all most devices
+//! have. Others can be described with a user configuration. Features drive the axis given by their
+//! `Axis` attribute, or otherwise the lowest numbered free axis of their kind, in config order:
+//!
+//! - LinearCmd features with a RotatePosition actuator take R axes, other LinearCmd features take L
+//!   axes.
+//! - RotateCmd features take R axes. Speed and direction are sent as an offset from the middle of
+//!   the axis, which is how firmware for continuously rotating twist axes expects them.
+//! - ScalarCmd features with a Vibrate actuator take V axes, others take A axes.
+
 use crate::{
   core::{
     errors::ButtplugDeviceError,
-    message::{self, Endpoint},
+    message::{self, ActuatorType, Endpoint},
   },
   server::device::{
-    hardware::{HardwareCommand, HardwareWriteCmd},
-    protocol::{generic_protocol_setup, ProtocolHandler},
+    configuration::{
+      ProtocolAttributesType,
+      ProtocolDeviceAttributes,
+      ServerGenericDeviceMessageAttributes,
+    },
+    hardware::{Hardware, HardwareCommand, HardwareWriteCmd},
+    protocol::{
+      generic_protocol_initializer_setup,
+      ProtocolHandler,
+      ProtocolIdentifier,
+      ProtocolInitializer,
+    },
+    ServerDeviceIdentifier,
   },
 };
+use async_trait::async_trait;
+use std::{collections::HashSet, sync::Arc};
+
+generic_protocol_initializer_setup!(TCodeV03, "tcode-v03");
 
-generic_protocol_setup!(TCodeV03, "tcode-v03");
+/// Checks the axes features were configured with, returning the set of them.
+fn configured_axes<'a>(
+  features: impl Iterator<Item = &'a ServerGenericDeviceMessageAttributes>,
+) -> Result<HashSet<String>, ButtplugDeviceError> {
+  let mut axes = HashSet::new();
+  for axis in features.filter_map(|feature| feature.axis().as_ref()) {
+    let mut chars = axis.chars();
+    if !matches!(chars.next(), Some('L' | 'R' | 'V' | 'A'))
+      || !chars.next().is_some_and(|number| number.is_ascii_digit())
+      || chars.next().is_some()
+    {
+      return Err(ButtplugDeviceError::DeviceConfigurationError(format!(
+        "{} is not a T-code axis. Axes are L, R, V or A followed by a single digit.",
+        axis
+      )));
+    }
+    if !axes.insert(axis.clone()) {
+      return Err(ButtplugDeviceError::DeviceConfigurationError(format!(
+        "T-code axis {} is driven by more than one feature.",
+        axis
+      )));
+    }
+  }
+  Ok(axes)
+}
+
+/// Returns the axis a feature drives: the one it was configured with, or otherwise the lowest
+/// numbered axis of the given kind that isn't in `used_axes` yet.
+fn feature_axis(
+  feature: &ServerGenericDeviceMessageAttributes,
+  kind: char,
+  used_axes: &mut HashSet<String>,
+) -> Result<String, ButtplugDeviceError> {
+  if let Some(axis) = feature.axis() {
+    return Ok(axis.clone());
+  }
+  let axis = (0..10)
+    .map(|number| format!("{}{}", kind, number))
+    .find(|axis| !used_axes.contains(axis))
+    .ok_or_else(|| {
+      ButtplugDeviceError::DeviceConfigurationError(format!(
+        "Device has more features than there are T-code {} axes.",
+        kind
+      ))
+    })?;
+  used_axes.insert(axis.clone());
+  Ok(axis)
+}
+
+/// Formats a value from 0.0 to 1.0 for an axis, at the two digit precision the protocol has always
+/// used.
+fn axis_value(value: f64) -> u32 {
+  (value.clamp(0.0, 1.0) * 99f64) as u32
+}
+
+fn tcode_command(command: String) -> HardwareCommand {
+  HardwareWriteCmd::new(Endpoint::Tx, command.into_bytes(), false).into()
+}
 
 #[derive(Default)]
-pub struct TCodeV03 {}
+pub struct TCodeV03Initializer {}
+
+#[async_trait]
+impl ProtocolInitializer for TCodeV03Initializer {
+  async fn initialize(
+    &mut self,
+    _: Arc<Hardware>,
+    attrs: &ProtocolDeviceAttributes,
+  ) -> Result<Arc<dyn ProtocolHandler>, ButtplugDeviceError> {
+    let linear_features = attrs
+      .message_attributes()
+      .linear_cmd()
+      .clone()
+      .unwrap_or_default();
+    let rotate_features = attrs
+      .message_attributes()
+      .rotate_cmd()
+      .clone()
+      .unwrap_or_default();
+    let scalar_features = attrs
+      .message_attributes()
+      .scalar_cmd()
+      .clone()
+      .unwrap_or_default();
+    // Configured axes are claimed first, so features without one don't take them.
+    let mut used_axes = configured_axes(
+      linear_features
+        .iter()
+        .chain(&rotate_features)
+        .chain(&scalar_features),
+    )?;
+
+    let mut linear_axes = vec![];
+    for feature in &linear_features {
+      let kind = if *feature.actuator_type() == ActuatorType::RotatePosition {
+        'R'
+      } else {
+        'L'
+      };
+      linear_axes.push(feature_axis(feature, kind, &mut used_axes)?);
+    }
+    let mut rotation_axes = vec![];
+    for feature in &rotate_features {
+      rotation_axes.push((
+        feature_axis(feature, 'R', &mut used_axes)?,
+        feature.step_count(),
+      ));
+    }
+    let mut scalar_axes = vec![];
+    for feature in &scalar_features {
+      let kind = if *feature.actuator_type() == ActuatorType::Vibrate {
+        'V'
+      } else {
+        'A'
+      };
+      scalar_axes.push((
+        feature_axis(feature, kind, &mut used_axes)?,
+        feature.step_count(),
+      ));
+    }
+    Ok(Arc::new(TCodeV03 {
+      linear_axes,
+      rotation_axes,
+      scalar_axes,
+    }))
+  }
+}
+
+#[derive(Default)]
+pub struct TCodeV03 {
+  linear_axes: Vec<String>,
+  /// Axes of RotateCmd features, with their step counts.
+  rotation_axes: Vec<(String, u32)>,
+  /// Axes of ScalarCmd features, with their step counts.
+  scalar_axes: Vec<(String, u32)>,
+}
 
 impl ProtocolHandler for TCodeV03 {
   fn handle_linear_cmd(
@@ -28,10 +192,48 @@ impl ProtocolHandler for TCodeV03 {
   ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
     let mut msg_vec = vec![];
     for v in msg.vectors() {
-      let position = (v.position() * 99f64) as u32;
+      let axis = self.linear_axes.get(v.index() as usize).ok_or_else(|| {
+        ButtplugDeviceError::DeviceFeatureIndexError(self.linear_axes.len() as u32, v.index())
+      })?;
+      msg_vec.push(tcode_command(format!(
+        "{}{:02}I{}\n",
+        axis,
+        axis_value(v.position()),
+        v.duration()
+      )));
+    }
+    Ok(msg_vec)
+  }
 
-      let command = format!("L{}{:02}I{}\n", v.index(), position, v.duration());
-      msg_vec.push(HardwareWriteCmd::new(Endpoint::Tx, command.as_bytes().to_vec(), false).into());
+  fn handle_rotate_cmd(
+    &self,
+    commands: &[Option<(u32, bool)>],
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    let mut msg_vec = vec![];
+    for (index, command) in commands.iter().enumerate() {
+      if let Some((speed, clockwise)) = command {
+        let (axis, step_count) =
+          self
+            .rotation_axes
+            .get(index)
+            .ok_or(ButtplugDeviceError::DeviceFeatureIndexError(
+              self.rotation_axes.len() as u32,
+              index as u32,
+            ))?;
+        let offset = *speed as f64 / (*step_count).max(1) as f64 / 2f64;
+        let position = if *clockwise {
+          0.5 + offset
+        } else {
+          0.5 - offset
+        };
+        // Rounded instead of truncated, so stopping lands on the middle of the axis instead of
+        // leaving the device turning slowly.
+        msg_vec.push(tcode_command(format!(
+          "{}{:02}\n",
+          axis,
+          (position * 99f64).round() as u32
+        )));
+      }
     }
     Ok(msg_vec)
   }
@@ -41,11 +243,18 @@ impl ProtocolHandler for TCodeV03 {
     index: u32,
     scalar: u32,
   ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
-    Ok(vec![HardwareWriteCmd::new(
-      Endpoint::Tx,
-      format!("V{}{:02}\n", index, scalar).as_bytes().to_vec(),
-      false,
-    )
-    .into()])
+    let (axis, step_count) =
+      self
+        .scalar_axes
+        .get(index as usize)
+        .ok_or(ButtplugDeviceError::DeviceFeatureIndexError(
+          self.scalar_axes.len() as u32,
+          index,
+        ))?;
+    Ok(vec![tcode_command(format!(
+      "{}{:02}\n",
+      axis,
+      axis_value(scalar as f64 / (*step_count).max(1) as f64)
+    ))])
   }
 }
@@ -17,15 +17,23 @@ use crate::{
     protocol::{generic_protocol_setup, ProtocolHandler},
   },
 };
+use async_trait::async_trait;
 use byteorder::WriteBytesExt;
-use futures::future::{BoxFuture, FutureExt};
 use std::sync::Arc;
 
-generic_protocol_setup!(XInput, "xinput");
+generic_protocol_setup!(XInput, "xinput", with_hardware);
 
-#[derive(Default)]
-pub struct XInput {}
+pub struct XInput {
+  hardware: Arc<Hardware>,
+}
+
+impl XInput {
+  fn new(hardware: Arc<Hardware>) -> Self {
+    Self { hardware }
+  }
+}
 
+#[async_trait]
 impl ProtocolHandler for XInput {
   fn needs_full_command_set(&self) -> bool {
     true
@@ -63,36 +71,33 @@ impl ProtocolHandler for XInput {
     Ok(vec![HardwareWriteCmd::new(Endpoint::Tx, cmd, false).into()])
   }
 
-  fn handle_battery_level_cmd(
+  async fn handle_battery_level_cmd(
     &self,
-    device: Arc<Hardware>,
     msg: message::SensorReadCmd,
-  ) -> BoxFuture<Result<ButtplugServerMessage, ButtplugDeviceError>> {
-    async move {
-      let reading = device
-        .read_value(&HardwareReadCmd::new(Endpoint::Rx, 0, 0))
-        .await?;
-      let battery = match reading.data()[0] {
-        0 => 0i32,
-        1 => 33,
-        2 => 66,
-        3 => 100,
-        _ => {
-          return Err(ButtplugDeviceError::DeviceCommunicationError(
-            "something went wrong".to_string(),
-          ))
-        }
-      };
-      Ok(
-        message::SensorReading::new(
-          msg.device_index(),
-          *msg.sensor_index(),
-          *msg.sensor_type(),
-          vec![battery],
-        )
-        .into(),
+  ) -> Result<ButtplugServerMessage, ButtplugDeviceError> {
+    let reading = self
+      .hardware
+      .read_value(&HardwareReadCmd::new(Endpoint::Rx, 0, 0))
+      .await?;
+    let battery = match reading.data()[0] {
+      0 => 0i32,
+      1 => 33,
+      2 => 66,
+      3 => 100,
+      _ => {
+        return Err(ButtplugDeviceError::DeviceCommunicationError(
+          "something went wrong".to_string(),
+        ))
+      }
+    };
+    Ok(
+      message::SensorReading::new(
+        msg.device_index(),
+        *msg.sensor_index(),
+        *msg.sensor_type(),
+        vec![battery],
       )
-    }
-    .boxed()
+      .into(),
+    )
   }
 }
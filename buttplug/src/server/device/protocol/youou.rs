@@ -49,7 +49,7 @@ impl ProtocolIdentifier for YououIdentifier {
     Ok((
       ServerDeviceIdentifier::new(
         hardware.address(),
-        "Youou",
+        "youou",
         &ProtocolAttributesType::Identifier("VX001_".to_owned()),
       ),
       Box::new(YououInitializer::default()),
@@ -0,0 +1,95 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Signal strength sensor for bluetooth devices.
+//!
+//! Devices configured with an RSSI sample interval get an RSSI sensor, which clients can read or
+//! subscribe to. Subscriptions sample the signal strength of the connection at that interval, so
+//! apps can react to how close the device is (e.g. turning intensity up as the device gets closer).
+//!
+//! Sensor ranges can't go negative, so instead of dBm, readings are a strength from 0 (at or below
+//! [RSSI_FLOOR_DBM]) to 100 (0 dBm). Higher means closer.
+
+use super::configuration::ProtocolDeviceAttributes;
+use crate::core::message::{SensorDeviceMessageAttributes, SensorType};
+use std::time::Duration;
+
+/// Signal strength, in dBm, that reads as 0. Anything weaker is about to drop the connection.
+pub(super) const RSSI_FLOOR_DBM: i32 = -100;
+
+/// Sensor indexes and sampling rate of a device's RSSI sensor.
+#[derive(Debug)]
+pub(super) struct RssiSensor {
+  /// SensorReadCmd index of the sensor.
+  read_sensor_index: u32,
+  /// SensorSubscribeCmd index of the sensor.
+  subscribe_sensor_index: u32,
+  /// How often subscriptions read the signal strength.
+  sample_interval: Duration,
+}
+
+impl RssiSensor {
+  /// Adds an RSSI sensor to a device, sampled every `sample_interval` when subscribed to. Returns
+  /// None if the device already has an RSSI sensor of its own.
+  pub(super) fn for_device(
+    attributes: &mut ProtocolDeviceAttributes,
+    sample_interval: Duration,
+  ) -> Option<Self> {
+    if attributes
+      .message_attributes()
+      .sensor_read_cmd()
+      .iter()
+      .flatten()
+      .any(|sensor| *sensor.sensor_type() == SensorType::RSSI)
+    {
+      return None;
+    }
+    let (read_sensor_index, subscribe_sensor_index) =
+      attributes.add_server_sensor(SensorDeviceMessageAttributes::new(
+        "Signal Strength",
+        SensorType::RSSI,
+        vec![0..=(-RSSI_FLOOR_DBM as u32)],
+      ));
+    Some(Self {
+      read_sensor_index,
+      subscribe_sensor_index,
+      sample_interval,
+    })
+  }
+
+  pub(super) fn read_sensor_index(&self) -> u32 {
+    self.read_sensor_index
+  }
+
+  pub(super) fn subscribe_sensor_index(&self) -> u32 {
+    self.subscribe_sensor_index
+  }
+
+  pub(super) fn sample_interval(&self) -> Duration {
+    self.sample_interval
+  }
+
+  /// Converts an RSSI in dBm to a sensor reading.
+  pub(super) fn strength(rssi: i16) -> i32 {
+    (rssi as i32 - RSSI_FLOOR_DBM).clamp(0, -RSSI_FLOOR_DBM)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_strength_from_rssi() {
+    assert_eq!(RssiSensor::strength(0), 100);
+    assert_eq!(RssiSensor::strength(-40), 60);
+    assert_eq!(RssiSensor::strength(-100), 0);
+    assert_eq!(RssiSensor::strength(-127), 0);
+    // Some stacks report small positive values right next to the radio.
+    assert_eq!(RssiSensor::strength(4), 100);
+  }
+}
@@ -0,0 +1,119 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Per-unit sensor calibration.
+//!
+//! Sensors on two units of the same toy rarely read the same, e.g. one pressure sensor might rest
+//! at 200 while another rests at 260. A calibration removes that difference before readings reach
+//! clients, by shifting readings down by a zero offset and then scaling them.
+
+use getset::{CopyGetters, Getters};
+use serde::{Deserialize, Serialize};
+use std::ops::RangeInclusive;
+
+fn default_scale() -> f64 {
+  1.0
+}
+
+/// Zero offset and scaling for one sensor of a device.
+///
+/// Each value of a reading has its offset subtracted, is multiplied by the scale, and is then
+/// clamped back into the range the sensor reports.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Getters, CopyGetters)]
+pub struct SensorCalibration {
+  /// Subtracted from the values of a reading, one offset per value. Values without an offset are
+  /// left where they are.
+  #[getset(get = "pub")]
+  #[serde(default)]
+  offset: Vec<i32>,
+  /// Multiplier applied after the offset.
+  #[getset(get_copy = "pub")]
+  #[serde(default = "default_scale")]
+  scale: f64,
+}
+
+impl Default for SensorCalibration {
+  fn default() -> Self {
+    Self {
+      offset: vec![],
+      scale: default_scale(),
+    }
+  }
+}
+
+impl SensorCalibration {
+  pub fn new(offset: Vec<i32>, scale: f64) -> Self {
+    Self { offset, scale }
+  }
+
+  /// Copy of this calibration with its offset replaced.
+  pub fn with_offset(&self, offset: Vec<i32>) -> Self {
+    Self {
+      offset,
+      scale: self.scale,
+    }
+  }
+
+  /// Calibrates the values of a reading in place. `ranges` are the sensor's ranges, one per value.
+  pub fn apply(&self, data: &mut [i32], ranges: &[RangeInclusive<u32>]) {
+    for (index, value) in data.iter_mut().enumerate() {
+      let offset = self.offset.get(index).copied().unwrap_or(0);
+      let calibrated = ((*value as f64 - offset as f64) * self.scale).round();
+      *value = if let Some(range) = ranges.get(index) {
+        calibrated.clamp(*range.start() as f64, *range.end() as f64) as i32
+      } else {
+        calibrated as i32
+      };
+    }
+  }
+
+  /// Zero offset that makes the average of `samples` read as zero, per value.
+  pub(super) fn baseline(samples: &[Vec<i32>]) -> Vec<i32> {
+    let values = samples.iter().map(|sample| sample.len()).max().unwrap_or(0);
+    (0..values)
+      .map(|index| {
+        let values: Vec<i64> = samples
+          .iter()
+          .filter_map(|sample| sample.get(index).map(|value| *value as i64))
+          .collect();
+        (values.iter().sum::<i64>() as f64 / values.len() as f64).round() as i32
+      })
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_apply_calibration() {
+    let calibration = SensorCalibration::new(vec![200, 10], 1.25);
+    let mut data = vec![600, 20, 7];
+    calibration.apply(&mut data, &[0..=1000, 0..=5]);
+    // Third value has no offset or range, so it's only scaled.
+    assert_eq!(data, vec![500, 5, 9]);
+
+    // Readings under the offset don't go below the range.
+    let mut data = vec![150];
+    calibration.apply(&mut data, &[0..=1000]);
+    assert_eq!(data, vec![0]);
+
+    let mut data = vec![42];
+    SensorCalibration::default().apply(&mut data, &[0..=100]);
+    assert_eq!(data, vec![42]);
+  }
+
+  #[test]
+  fn test_baseline() {
+    assert_eq!(
+      SensorCalibration::baseline(&[vec![200, 1], vec![203, 2], vec![204]]),
+      vec![202, 2]
+    );
+    assert!(SensorCalibration::baseline(&[]).is_empty());
+  }
+}
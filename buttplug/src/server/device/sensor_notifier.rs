@@ -0,0 +1,149 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Delivery of subscribed sensor and raw readings to device event stream subscribers.
+//!
+//! Broadcast channels drop the oldest readings once a receiver lags, and the receiver only finds
+//! out through an error most streams treat as the end of the channel. Readings from subscriptions
+//! instead go through a bounded queue per subscriber. When a subscriber's queue is full, new
+//! readings for it are dropped and counted, and the count is handed to the subscriber before its
+//! next reading, so it can tell the client readings went missing.
+
+use crate::core::message::ButtplugServerDeviceMessage;
+use async_stream::stream;
+use futures::Stream;
+use std::sync::{
+  atomic::{AtomicU32, Ordering},
+  Arc,
+  Mutex,
+};
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+/// Readings each subscriber can have waiting before new ones are dropped.
+pub(super) const SENSOR_NOTIFICATION_QUEUE_SIZE: usize = 256;
+
+/// What a subscriber receives from a [SensorNotifier].
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum SensorNotification {
+  Reading(ButtplugServerDeviceMessage),
+  /// Number of readings dropped since the last notification, as the subscriber fell behind.
+  Dropped(u32),
+}
+
+struct SensorSubscriber {
+  sender: mpsc::Sender<ButtplugServerDeviceMessage>,
+  dropped: Arc<AtomicU32>,
+}
+
+/// Sends readings to every subscriber, each through its own bounded queue.
+#[derive(Clone)]
+pub(super) struct SensorNotifier {
+  subscribers: Arc<Mutex<Vec<SensorSubscriber>>>,
+  queue_size: usize,
+}
+
+impl Default for SensorNotifier {
+  fn default() -> Self {
+    Self::new(SENSOR_NOTIFICATION_QUEUE_SIZE)
+  }
+}
+
+impl SensorNotifier {
+  pub fn new(queue_size: usize) -> Self {
+    Self {
+      subscribers: Arc::new(Mutex::new(vec![])),
+      queue_size,
+    }
+  }
+
+  /// Adds a subscriber, which receives readings sent from now on.
+  pub fn subscribe(&self) -> impl Stream<Item = SensorNotification> {
+    let (sender, mut receiver) = mpsc::channel(self.queue_size);
+    let dropped = Arc::new(AtomicU32::new(0));
+    self
+      .subscribers
+      .lock()
+      .expect("Sensor subscriber lock should never be poisoned.")
+      .push(SensorSubscriber {
+        sender,
+        dropped: dropped.clone(),
+      });
+    stream! {
+      while let Some(reading) = receiver.recv().await {
+        let count = dropped.swap(0, Ordering::Relaxed);
+        if count > 0 {
+          yield SensorNotification::Dropped(count);
+        }
+        yield SensorNotification::Reading(reading);
+      }
+    }
+  }
+
+  /// Queues a reading for every subscriber. Never waits, so a slow subscriber can't hold up the
+  /// others or whatever produced the reading.
+  pub fn send(&self, reading: ButtplugServerDeviceMessage) {
+    self
+      .subscribers
+      .lock()
+      .expect("Sensor subscriber lock should never be poisoned.")
+      .retain(
+        |subscriber| match subscriber.sender.try_send(reading.clone()) {
+          Ok(()) => true,
+          Err(TrySendError::Full(_)) => {
+            subscriber.dropped.fetch_add(1, Ordering::Relaxed);
+            true
+          }
+          // Stream was dropped, stop sending to it.
+          Err(TrySendError::Closed(_)) => false,
+        },
+      );
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::core::message::{SensorReading, SensorType};
+  use futures::{pin_mut, FutureExt, StreamExt};
+
+  fn reading(value: i32) -> ButtplugServerDeviceMessage {
+    SensorReading::new(0, 0, SensorType::Pressure, vec![value]).into()
+  }
+
+  #[tokio::test]
+  async fn test_sensor_notifier_reports_overflow() {
+    let notifier = SensorNotifier::new(2);
+    let slow = notifier.subscribe();
+    let fast = notifier.subscribe();
+    pin_mut!(slow);
+    pin_mut!(fast);
+    notifier.send(reading(1));
+    assert_eq!(
+      fast.next().await,
+      Some(SensorNotification::Reading(reading(1)))
+    );
+    for value in 2..6 {
+      notifier.send(reading(value));
+      // Keeping up means never losing anything.
+      assert_eq!(
+        fast.next().await,
+        Some(SensorNotification::Reading(reading(value)))
+      );
+    }
+    // Slow subscriber only had room for the first two readings.
+    assert_eq!(slow.next().await, Some(SensorNotification::Dropped(3)));
+    assert_eq!(
+      slow.next().await,
+      Some(SensorNotification::Reading(reading(1)))
+    );
+    assert_eq!(
+      slow.next().await,
+      Some(SensorNotification::Reading(reading(2)))
+    );
+    assert!(slow.next().now_or_never().is_none());
+  }
+}
@@ -0,0 +1,276 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Rules that drive actuators from sensor readings on the server, without a client.
+//!
+//! Each [SensorRule] watches one sensor of a device, and turns on an actuator (of the same device,
+//! or another one) when readings cross a threshold, e.g. vibrating while a pressure sensor reads
+//! above 600. The actuator is turned off again once readings come back past the threshold by the
+//! rule's hysteresis, so readings hovering around the threshold don't flip the output back and
+//! forth, and the rule can't turn on again until its cooldown has passed.
+//!
+//! The server subscribes to the sensors rules watch as devices connect, so rules work without a
+//! client subscribing to them. Clients unsubscribing from those sensors stops the rules watching
+//! them until the device reconnects. Only the first value of each reading is checked.
+
+use super::ServerDeviceIdentifier;
+use crate::core::message::{ScalarSubcommand, SensorReading, SensorType};
+use getset::{CopyGetters, Getters, Setters};
+use std::{
+  sync::Mutex,
+  time::{Duration, Instant},
+};
+
+/// When a [SensorRule] turns on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SensorCondition {
+  /// Readings above the value.
+  Above(i32),
+  /// Readings below the value.
+  Below(i32),
+}
+
+/// Turns on an actuator while readings from a sensor meet a condition. See the
+/// [module documentation](self) for details.
+#[derive(Debug, Clone, PartialEq, Getters, CopyGetters, Setters)]
+pub struct SensorRule {
+  #[getset(get = "pub")]
+  sensor_device: ServerDeviceIdentifier,
+  #[getset(get_copy = "pub")]
+  sensor_index: u32,
+  #[getset(get_copy = "pub")]
+  sensor_type: SensorType,
+  #[getset(get_copy = "pub")]
+  condition: SensorCondition,
+  /// How far readings have to come back past the threshold before the rule turns off.
+  #[getset(get_copy = "pub", set = "pub")]
+  hysteresis: i32,
+  /// Time after the rule turns off before it can turn on again.
+  #[getset(get_copy = "pub", set = "pub")]
+  cooldown: Duration,
+  #[getset(get = "pub")]
+  actuator_device: ServerDeviceIdentifier,
+  /// Sent to the actuator device when the rule turns on. The same actuator is set to 0 when the
+  /// rule turns off.
+  #[getset(get = "pub")]
+  output: ScalarSubcommand,
+}
+
+impl SensorRule {
+  pub fn new(
+    sensor_device: &ServerDeviceIdentifier,
+    sensor_index: u32,
+    sensor_type: SensorType,
+    condition: SensorCondition,
+    actuator_device: &ServerDeviceIdentifier,
+    output: ScalarSubcommand,
+  ) -> Self {
+    Self {
+      sensor_device: sensor_device.clone(),
+      sensor_index,
+      sensor_type,
+      condition,
+      hysteresis: 0,
+      cooldown: Duration::ZERO,
+      actuator_device: actuator_device.clone(),
+      output,
+    }
+  }
+
+  fn turns_on(&self, value: i32) -> bool {
+    match self.condition {
+      SensorCondition::Above(threshold) => value > threshold,
+      SensorCondition::Below(threshold) => value < threshold,
+    }
+  }
+
+  fn turns_off(&self, value: i32) -> bool {
+    match self.condition {
+      SensorCondition::Above(threshold) => value < threshold.saturating_sub(self.hysteresis),
+      SensorCondition::Below(threshold) => value > threshold.saturating_add(self.hysteresis),
+    }
+  }
+
+  fn off_output(&self) -> ScalarSubcommand {
+    ScalarSubcommand::new(self.output.index(), 0.0, self.output.actuator_type())
+  }
+}
+
+#[derive(Default)]
+struct SensorRuleState {
+  on: bool,
+  last_turned_off: Option<Instant>,
+}
+
+/// Sensor rules, and whether each of them is on.
+pub(super) struct SensorRules {
+  rules: Vec<SensorRule>,
+  /// State of each rule, in the same order as the rules.
+  states: Mutex<Vec<SensorRuleState>>,
+}
+
+impl SensorRules {
+  pub fn new(rules: &[SensorRule]) -> Self {
+    Self {
+      rules: rules.to_vec(),
+      states: Mutex::new(rules.iter().map(|_| SensorRuleState::default()).collect()),
+    }
+  }
+
+  /// Returns the sensors of a device that rules watch, as sensor index and type.
+  pub fn watched_sensors(&self, identifier: &ServerDeviceIdentifier) -> Vec<(u32, SensorType)> {
+    let mut sensors = vec![];
+    for rule in self
+      .rules
+      .iter()
+      .filter(|rule| rule.sensor_device == *identifier)
+    {
+      let sensor = (rule.sensor_index, rule.sensor_type);
+      if !sensors.contains(&sensor) {
+        sensors.push(sensor);
+      }
+    }
+    sensors
+  }
+
+  /// Runs a reading from a device through the rules, returning the outputs to send for rules that
+  /// turned on or off, along with the devices to send them to.
+  pub fn on_reading(
+    &self,
+    identifier: &ServerDeviceIdentifier,
+    reading: &SensorReading,
+    now: Instant,
+  ) -> Vec<(ServerDeviceIdentifier, ScalarSubcommand)> {
+    let value = match reading.data().first() {
+      Some(value) => *value,
+      None => return vec![],
+    };
+    let mut states = self
+      .states
+      .lock()
+      .expect("Sensor rule state lock should never be poisoned.");
+    let mut outputs = vec![];
+    for (rule, state) in self.rules.iter().zip(states.iter_mut()) {
+      if rule.sensor_device != *identifier
+        || rule.sensor_index != reading.sensor_index()
+        || rule.sensor_type != reading.sensor_type()
+      {
+        continue;
+      }
+      if state.on {
+        if rule.turns_off(value) {
+          state.on = false;
+          state.last_turned_off = Some(now);
+          outputs.push((rule.actuator_device.clone(), rule.off_output()));
+        }
+      } else if rule.turns_on(value)
+        && state
+          .last_turned_off
+          .is_none_or(|turned_off| now >= turned_off + rule.cooldown)
+      {
+        state.on = true;
+        outputs.push((rule.actuator_device.clone(), rule.output.clone()));
+      }
+    }
+    outputs
+  }
+
+  /// Turns off rules involving a device that disconnected, returning the outputs to send to the
+  /// actuators of rules whose sensor went away.
+  pub fn on_device_removed(
+    &self,
+    identifier: &ServerDeviceIdentifier,
+    now: Instant,
+  ) -> Vec<(ServerDeviceIdentifier, ScalarSubcommand)> {
+    let mut states = self
+      .states
+      .lock()
+      .expect("Sensor rule state lock should never be poisoned.");
+    let mut outputs = vec![];
+    for (rule, state) in self.rules.iter().zip(states.iter_mut()) {
+      if !state.on || (rule.sensor_device != *identifier && rule.actuator_device != *identifier) {
+        continue;
+      }
+      state.on = false;
+      state.last_turned_off = Some(now);
+      if rule.actuator_device != *identifier {
+        outputs.push((rule.actuator_device.clone(), rule.off_output()));
+      }
+    }
+    outputs
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::{core::message::ActuatorType, server::device::configuration::ProtocolAttributesType};
+
+  fn identifier(address: &str) -> ServerDeviceIdentifier {
+    ServerDeviceIdentifier::new(address, "kgoal-boost", &ProtocolAttributesType::Default)
+  }
+
+  fn pressure(value: i32) -> SensorReading {
+    SensorReading::new(0, 0, SensorType::Pressure, vec![value])
+  }
+
+  #[test]
+  fn test_sensor_rule_hysteresis_and_cooldown() {
+    let output = ScalarSubcommand::new(0, 0.8, ActuatorType::Vibrate);
+    let mut rule = SensorRule::new(
+      &identifier("Sensor"),
+      0,
+      SensorType::Pressure,
+      SensorCondition::Above(600),
+      &identifier("Actuator"),
+      output.clone(),
+    );
+    rule.set_hysteresis(50).set_cooldown(Duration::from_secs(2));
+    let rules = SensorRules::new(&[rule]);
+    let start = Instant::now();
+    let on_reading = |value, seconds| {
+      rules.on_reading(
+        &identifier("Sensor"),
+        &pressure(value),
+        start + Duration::from_secs(seconds),
+      )
+    };
+    let on = vec![(identifier("Actuator"), output)];
+    let off = vec![(
+      identifier("Actuator"),
+      ScalarSubcommand::new(0, 0.0, ActuatorType::Vibrate),
+    )];
+
+    assert!(on_reading(600, 0).is_empty());
+    assert_eq!(on_reading(601, 0), on);
+    // Already on, and not far enough back below the threshold to turn off.
+    assert!(on_reading(700, 0).is_empty());
+    assert!(on_reading(560, 0).is_empty());
+    assert_eq!(on_reading(549, 1), off);
+    // Cooling down.
+    assert!(on_reading(700, 2).is_empty());
+    assert_eq!(on_reading(700, 3), on);
+    // Readings from other devices and sensors are ignored.
+    assert!(rules
+      .on_reading(&identifier("Actuator"), &pressure(0), start)
+      .is_empty());
+    assert!(rules
+      .on_reading(
+        &identifier("Sensor"),
+        &SensorReading::new(0, 1, SensorType::Pressure, vec![0]),
+        start
+      )
+      .is_empty());
+    // Losing the sensor turns the actuator off.
+    assert_eq!(rules.on_device_removed(&identifier("Sensor"), start), off);
+    assert_eq!(
+      rules.watched_sensors(&identifier("Sensor")),
+      vec![(0, SensorType::Pressure)]
+    );
+    assert!(rules.watched_sensors(&identifier("Actuator")).is_empty());
+  }
+}
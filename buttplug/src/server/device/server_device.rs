@@ -7,7 +7,16 @@
 
 use std::{
   fmt::{self, Debug},
-  sync::Arc,
+  ops::RangeInclusive,
+  str::FromStr,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+    Mutex,
+    RwLock,
+    RwLockReadGuard,
+  },
+  time::{Duration, Instant},
 };
 
 use crate::{
@@ -27,34 +36,55 @@ use crate::{
       RSSILevelReading,
       RawReading,
       RawSubscribeCmd,
+      RotateCmd,
       ScalarCmd,
       ScalarSubcommand,
       SensorDeviceMessageAttributes,
       SensorReadCmd,
+      SensorReading,
+      SensorSubscribeCmd,
       SensorType,
     },
     ButtplugResultFuture,
   },
   server::{
     device::{
-      configuration::{DeviceConfigurationManager, ProtocolAttributesType},
-      hardware::{Hardware, HardwareCommand, HardwareConnector, HardwareEvent},
-      protocol::ProtocolHandler,
+      configuration::{DeviceConfigurationManager, DeviceLocalization, ProtocolAttributesType},
+      hardware::{Hardware, HardwareActivity, HardwareCommand, HardwareConnector, HardwareEvent},
+      protocol::{read_standard_battery_level, ProtocolHandler},
     },
     ButtplugServerResultFuture,
+    ProgressReporter,
+  },
+  util::{
+    address_privacy::display_address,
+    async_manager,
+    sleep,
+    stream::convert_broadcast_receiver_to_stream,
   },
-  util::stream::convert_broadcast_receiver_to_stream,
 };
 use core::hash::{Hash, Hasher};
-use dashmap::DashSet;
-use futures::future::{self, FutureExt};
+use dashmap::{DashMap, DashSet};
+use futures::future::{self, BoxFuture, FutureExt, Shared};
 use getset::{Getters, MutGetters, Setters};
 use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, error::RecvError};
 use tokio_stream::StreamExt;
+use tokio_util::sync::CancellationToken;
 
 use super::{
+  battery_saver::{BatterySaver, RequestedOutput},
+  command_coalescer::{Coalesced, CommandCoalescer},
   configuration::{ProtocolDeviceAttributes, ServerDeviceMessageAttributes},
+  estim_interlock::{EstimInterlock, EstimInterlockGuard},
+  linear_position_estimator::{LinearPositionEstimator, ESTIMATED_POSITION_INTERVAL},
+  output_queue::{OutputQueue, Queued},
+  output_ramp::{scale_output, RampPolicy, COOL_DOWN_STEP_INTERVAL},
+  overuse_protection::{output_intensity, OveruseMonitor, OveruseProtection},
   protocol::{generic_command_manager::GenericCommandManager, ProtocolSpecializer},
+  rssi_sensor::RssiSensor,
+  sensor_notifier::{SensorNotification, SensorNotifier},
+  SensorCalibration,
 };
 
 #[derive(Debug)]
@@ -62,14 +92,21 @@ pub enum ServerDeviceEvent {
   Connected(Arc<ServerDevice>),
   Notification(ServerDeviceIdentifier, ButtplugServerDeviceMessage),
   Disconnected(ServerDeviceIdentifier),
+  /// The device ran hard for longer than its overuse protection allows, and has been stepped down.
+  OveruseStepDown(ServerDeviceIdentifier, OveruseProtection),
+  /// Number of subscribed readings dropped because the event stream fell behind.
+  NotificationsDropped(ServerDeviceIdentifier, u32),
 }
 
 /// Identifying information for a connected devices
 ///
 /// Contains the 3 fields needed to uniquely identify a device in the system.
-#[derive(
-  Debug, Eq, PartialEq, Hash, Clone, Getters, Setters, MutGetters, Serialize, Deserialize,
-)]
+///
+/// When persisted (reserved indexes, user configs, etc...), identifiers are written in a versioned
+/// string format, see the [Display](fmt::Display) and [FromStr] implementations. Deserialization
+/// also accepts the formats used before that existed, so older stored identifiers keep working.
+#[derive(Eq, PartialEq, Hash, Clone, Getters, Setters, MutGetters, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "SerializedServerDeviceIdentifier")]
 #[getset(get = "pub(crate)", get_mut = "pub(crate)")]
 pub struct ServerDeviceIdentifier {
   /// Address, as possibly serialized by whatever the managing library for the Device Communication Manager is.
@@ -91,10 +128,188 @@ impl ServerDeviceIdentifier {
   }
 }
 
+/// Matches the derived [Debug] output, except the address is hidden if address hashing is on.
+impl Debug for ServerDeviceIdentifier {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("ServerDeviceIdentifier")
+      .field("address", &display_address(&self.address))
+      .field("protocol", &self.protocol)
+      .field("attributes_identifier", &self.attributes_identifier)
+      .finish()
+  }
+}
+
+/// Version of the identifier string format, written as the first field.
+const SERVER_DEVICE_IDENTIFIER_VERSION: &str = "1";
+const SERVER_DEVICE_IDENTIFIER_SEPARATOR: char = ';';
+
+fn escape_identifier_field(field: &str) -> String {
+  field.replace('%', "%25").replace(';', "%3B")
+}
+
+fn unescape_identifier_field(field: &str) -> String {
+  field.replace("%3B", ";").replace("%25", "%")
+}
+
+/// Formats the identifier as `1;<protocol>;<attributes>;<address>`, where attributes is empty for
+/// [ProtocolAttributesType::Default] and `=<identifier>` otherwise. `%` and `;` in fields are
+/// percent-escaped.
+impl fmt::Display for ServerDeviceIdentifier {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let attributes = match &self.attributes_identifier {
+      ProtocolAttributesType::Default => String::new(),
+      ProtocolAttributesType::Identifier(ident) => format!("={}", escape_identifier_field(ident)),
+    };
+    write!(
+      f,
+      "{version}{sep}{}{sep}{}{sep}{}",
+      escape_identifier_field(&self.protocol),
+      attributes,
+      escape_identifier_field(&self.address),
+      version = SERVER_DEVICE_IDENTIFIER_VERSION,
+      sep = SERVER_DEVICE_IDENTIFIER_SEPARATOR
+    )
+  }
+}
+
+impl FromStr for ServerDeviceIdentifier {
+  type Err = ButtplugDeviceError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    if s.starts_with("ServerDeviceIdentifier {") {
+      return parse_legacy_debug_identifier(s);
+    }
+    let invalid = || {
+      ButtplugDeviceError::DeviceConfigurationError(format!(
+        "Invalid device identifier string: {}",
+        s
+      ))
+    };
+    let fields: Vec<&str> = s.split(SERVER_DEVICE_IDENTIFIER_SEPARATOR).collect();
+    let [version, protocol, attributes, address] = fields[..] else {
+      return Err(invalid());
+    };
+    if version != SERVER_DEVICE_IDENTIFIER_VERSION {
+      return Err(ButtplugDeviceError::DeviceConfigurationError(format!(
+        "Unknown device identifier version {} in {}",
+        version, s
+      )));
+    }
+    let attributes_identifier = if attributes.is_empty() {
+      ProtocolAttributesType::Default
+    } else if let Some(ident) = attributes.strip_prefix('=') {
+      ProtocolAttributesType::Identifier(unescape_identifier_field(ident))
+    } else {
+      return Err(invalid());
+    };
+    Ok(Self {
+      address: unescape_identifier_field(address),
+      protocol: unescape_identifier_field(protocol),
+      attributes_identifier,
+    })
+  }
+}
+
+/// Parses identifiers that were stored using their [Debug] output, which is how they were persisted
+/// before the versioned format existed.
+fn parse_legacy_debug_identifier(s: &str) -> Result<ServerDeviceIdentifier, ButtplugDeviceError> {
+  let invalid = || {
+    ButtplugDeviceError::DeviceConfigurationError(format!(
+      "Invalid legacy device identifier string: {}",
+      s
+    ))
+  };
+  // Reads a debug formatted string starting at the opening quote, returning the unescaped string
+  // and whatever follows the closing quote.
+  fn debug_string(input: &str) -> Option<(String, &str)> {
+    let mut chars = input.strip_prefix('"')?.char_indices();
+    let mut out = String::new();
+    while let Some((i, c)) = chars.next() {
+      match c {
+        '"' => return Some((out, &input[i + 2..])),
+        '\\' => match chars.next()?.1 {
+          'n' => out.push('\n'),
+          'r' => out.push('\r'),
+          't' => out.push('\t'),
+          '0' => out.push('\0'),
+          'u' => {
+            let escape = chars.as_str().strip_prefix('{')?.split_once('}')?.0;
+            out.push(char::from_u32(u32::from_str_radix(escape, 16).ok()?)?);
+            // Skip the braces along with the hex digits.
+            chars.nth(escape.len() + 1)?;
+          }
+          c => out.push(c),
+        },
+        c => out.push(c),
+      }
+    }
+    None
+  }
+  let rest = s
+    .strip_prefix("ServerDeviceIdentifier { address: ")
+    .ok_or_else(invalid)?;
+  let (address, rest) = debug_string(rest).ok_or_else(invalid)?;
+  let rest = rest.strip_prefix(", protocol: ").ok_or_else(invalid)?;
+  let (protocol, rest) = debug_string(rest).ok_or_else(invalid)?;
+  let rest = rest
+    .strip_prefix(", attributes_identifier: ")
+    .ok_or_else(invalid)?;
+  let attributes_identifier = if rest == "Default }" {
+    ProtocolAttributesType::Default
+  } else {
+    let rest = rest.strip_prefix("Identifier(").ok_or_else(invalid)?;
+    let (ident, rest) = debug_string(rest).ok_or_else(invalid)?;
+    if rest != ") }" {
+      return Err(invalid());
+    }
+    ProtocolAttributesType::Identifier(ident)
+  };
+  Ok(ServerDeviceIdentifier {
+    address,
+    protocol,
+    attributes_identifier,
+  })
+}
+
+impl From<ServerDeviceIdentifier> for String {
+  fn from(identifier: ServerDeviceIdentifier) -> Self {
+    identifier.to_string()
+  }
+}
+
+/// Everything a [ServerDeviceIdentifier] may have been serialized as: the current versioned string
+/// (or a legacy debug string), or the struct layout serde derived before the versioned format.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SerializedServerDeviceIdentifier {
+  Versioned(String),
+  Legacy {
+    address: String,
+    protocol: String,
+    attributes_identifier: ProtocolAttributesType,
+  },
+}
+
+impl TryFrom<SerializedServerDeviceIdentifier> for ServerDeviceIdentifier {
+  type Error = ButtplugDeviceError;
+
+  fn try_from(identifier: SerializedServerDeviceIdentifier) -> Result<Self, Self::Error> {
+    match identifier {
+      SerializedServerDeviceIdentifier::Versioned(identifier) => identifier.parse(),
+      SerializedServerDeviceIdentifier::Legacy {
+        address,
+        protocol,
+        attributes_identifier,
+      } => Ok(Self::new(&address, &protocol, &attributes_identifier)),
+    }
+  }
+}
+
 pub(super) async fn build_server_device(
   device_config_manager: Arc<DeviceConfigurationManager>,
   mut hardware_connector: Box<dyn HardwareConnector>,
   protocol_specializers: Vec<ProtocolSpecializer>,
+  battery_saver: Arc<BatterySaver>,
 ) -> Result<ServerDevice, ButtplugDeviceError> {
   // We've already checked to make sure we have specializers in the server device manager event
   // loop. That check used to be here for sake of continuity in building devices in this method, but
@@ -135,6 +350,25 @@ pub(super) async fn build_server_device(
   // connection failure, as identify may have already run commands on the device, and therefore
   // put it in an unknown state if anything fails.
 
+  // Connection parameters are only a nice to have, so if the platform or device won't go along
+  // with them, carry on with whatever the connection is using.
+  if let Some(parameters) = device_config_manager.btle_connection_parameters(&identifier) {
+    if let Err(err) = hardware.request_connection_parameters(&parameters).await {
+      info!(
+        "Could not request connection parameters {:?} for {:?}: {:?}",
+        parameters, identifier, err
+      );
+    }
+  }
+  if let Some(coalescing) = device_config_manager.btle_write_coalescing(&identifier) {
+    if let Err(err) = hardware.set_write_coalescing(&coalescing) {
+      info!(
+        "Could not set up write coalescing {:?} for {:?}: {:?}",
+        coalescing, identifier, err
+      );
+    }
+  }
+
   // Check in the DeviceConfigurationManager to make sure we have attributes
   // for this device.
   let attrs = if let Some(attrs) =
@@ -157,18 +391,341 @@ pub(super) async fn build_server_device(
     .initialize(hardware.clone(), &attrs)
     .await?;
 
+  let battery_poll_interval = device_config_manager.battery_poll_interval(&identifier);
+  let rssi_sample_interval = device_config_manager.rssi_sample_interval(&identifier);
+  let command_timeout = device_config_manager.command_timeout(&identifier);
+  let max_update_rate = device_config_manager.max_update_rate(&identifier);
+  let ramp_policy = device_config_manager.ramp_policy(&identifier);
+  let overuse_protection = device_config_manager.overuse_protection(&identifier);
+  let estim_interlock = device_config_manager.estim_interlock(&identifier);
+
+  let sensor_calibrations = device_config_manager.sensor_calibrations(&identifier);
+
   // We now have fully initialized hardware, return a server device.
-  Ok(ServerDevice::new(identifier, handler, hardware, &attrs))
+  let device = ServerDevice::new(
+    identifier,
+    handler,
+    hardware,
+    &attrs,
+    battery_poll_interval,
+    rssi_sample_interval,
+    command_timeout,
+    max_update_rate,
+    ramp_policy,
+    battery_saver,
+    overuse_protection,
+    estim_interlock,
+  );
+  if let Some(display_name) = device_config_manager.display_name(device.identifier()) {
+    device.set_display_name(Some(display_name));
+  }
+  for (sensor_index, sensor_type, calibration) in sensor_calibrations {
+    if let Err(err) = device.set_sensor_calibration(sensor_index, sensor_type, Some(calibration)) {
+      warn!(
+        "Ignoring sensor calibration for {:?}: {}",
+        device.identifier(),
+        err
+      );
+    }
+  }
+  Ok(device)
+}
+
+/// Battery reads for a device, shared between client requests and the battery poller.
+#[derive(Clone, Default)]
+struct BatteryState {
+  /// Battery read currently waiting on the hardware, if any.
+  pending_read: Arc<Mutex<Option<Shared<ButtplugServerResultFuture>>>>,
+  /// Last level read from the battery sensor, if it has been read.
+  last_level: Arc<Mutex<Option<i32>>>,
+}
+
+/// Sampling state for sensor subscriptions that asked for fewer readings, keyed by sensor index and
+/// type.
+type SensorSamplers = Arc<DashMap<(u32, SensorType), SensorSampler>>;
+
+/// Calibrations for a device's sensors along with the ranges of the sensors, keyed by sensor index
+/// and type.
+type SensorCalibrations =
+  Arc<DashMap<(u32, SensorType), (SensorCalibration, Vec<RangeInclusive<u32>>)>>;
+
+/// Applies the calibration for the sensor a reading came from, if it has one.
+fn calibrate_reading(calibrations: &SensorCalibrations, reading: &mut SensorReading) {
+  if let Some(entry) = calibrations.get(&(reading.sensor_index(), reading.sensor_type())) {
+    let (calibration, ranges) = entry.value();
+    calibration.apply(reading.data_mut(), ranges);
+  }
+}
+
+/// Drops readings for a sensor subscription based on the MinInterval/Decimation options of the
+/// [SensorSubscribeCmd] that set it up.
+#[derive(Debug)]
+struct SensorSampler {
+  min_interval: Duration,
+  decimation: u64,
+  readings_seen: u64,
+  last_sent: Option<Instant>,
+}
+
+impl SensorSampler {
+  /// Returns None if the subscription didn't ask for any sampling, in which case every reading is
+  /// sent.
+  fn new(message: &SensorSubscribeCmd) -> Option<Self> {
+    if message.min_interval().is_none() && message.decimation().is_none() {
+      return None;
+    }
+    Some(Self {
+      min_interval: Duration::from_millis(message.min_interval().unwrap_or(0) as u64),
+      decimation: message.decimation().unwrap_or(1).max(1) as u64,
+      readings_seen: 0,
+      last_sent: None,
+    })
+  }
+
+  fn should_send(&mut self) -> bool {
+    let skipped = !self.readings_seen.is_multiple_of(self.decimation);
+    self.readings_seen += 1;
+    if skipped {
+      return false;
+    }
+    let now = Instant::now();
+    if let Some(last_sent) = self.last_sent {
+      if now.duration_since(last_sent) < self.min_interval {
+        return false;
+      }
+    }
+    self.last_sent = Some(now);
+    true
+  }
+}
+
+/// Reads a sensor through the protocol handler. Battery reads the handler doesn't handle itself go
+/// to the standard BLE battery service instead.
+async fn read_sensor(
+  handler: &Arc<dyn ProtocolHandler>,
+  hardware: &Hardware,
+  message: SensorReadCmd,
+) -> Result<ButtplugServerMessage, ButtplugDeviceError> {
+  let is_battery = *message.sensor_type() == SensorType::Battery;
+  match handler.handle_sensor_read_cmd(message.clone()).await {
+    Err(ButtplugDeviceError::UnhandledCommand(_)) if is_battery => {
+      read_standard_battery_level(hardware, message).await
+    }
+    result => result,
+  }
+}
+
+/// Read the battery sensor of a device, joining any battery read that is already waiting on the
+/// hardware instead of issuing a new one.
+///
+/// Battery reads are slow on most hardware (usually a BLE characteristic read), and can be
+/// requested by clients and the battery poller at the same time, so there's no reason to do more
+/// than one at once.
+fn coalesced_battery_read(
+  battery_state: &BatteryState,
+  handler: &Arc<dyn ProtocolHandler>,
+  hardware: &Arc<Hardware>,
+  sensor_calibrations: &SensorCalibrations,
+  message: SensorReadCmd,
+) -> ButtplugServerResultFuture {
+  let device_index = message.device_index();
+  let read_fut = {
+    let mut pending_read = battery_state
+      .pending_read
+      .lock()
+      .expect("Battery read lock should never be poisoned.");
+    match pending_read.as_ref() {
+      Some(fut) if fut.peek().is_none() => fut.clone(),
+      _ => {
+        let handler = handler.clone();
+        let hardware = hardware.clone();
+        let last_level = battery_state.last_level.clone();
+        let sensor_calibrations = sensor_calibrations.clone();
+        let fut = async move {
+          let mut reading = read_sensor(&handler, &hardware, message).await?;
+          if let ButtplugServerMessage::SensorReading(msg) = &mut reading {
+            calibrate_reading(&sensor_calibrations, msg);
+            *last_level
+              .lock()
+              .expect("Battery level lock should never be poisoned.") = msg.data().first().cloned();
+          }
+          Ok(reading)
+        }
+        .boxed()
+        .shared();
+        *pending_read = Some(fut.clone());
+        fut
+      }
+    }
+  };
+  async move {
+    // Whoever started the read may have used a different device index, so make sure the reading
+    // matches what we were asked for.
+    let mut reading = read_fut.await?;
+    if let ButtplugServerMessage::SensorReading(msg) = &mut reading {
+      msg.set_device_index(device_index);
+    }
+    Ok(reading)
+  }
+  .boxed()
+}
+
+fn send_hardware_commands(
+  hardware: Arc<Hardware>,
+  commands: Vec<HardwareCommand>,
+) -> ButtplugServerResultFuture {
+  async move {
+    // Run commands in order, otherwise we may end up sending out of order. This may take a while,
+    // but it's what 99% of protocols expect. If they want something else, they can implement it
+    // themselves.
+    //
+    // If anything errors out, just bail on the command series. This most likely means the device
+    // disconnected.
+    for command in commands {
+      hardware.parse_message(&command).await?;
+    }
+    Ok(message::Ok::default().into())
+  }
+  .boxed()
+}
+
+fn generic_command_result(
+  hardware: Arc<Hardware>,
+  command_result: Result<Vec<HardwareCommand>, ButtplugDeviceError>,
+) -> ButtplugServerResultFuture {
+  match command_result {
+    Ok(commands) => send_hardware_commands(hardware, commands),
+    Err(err) => future::ready(Err(err.into())).boxed(),
+  }
+}
+
+/// Send scalar or rotation output through the generic command manager and protocol handler. This
+/// doesn't need the device, so merged output can be sent once its update interval is over.
+fn send_generic_output(
+  handler: &Arc<dyn ProtocolHandler>,
+  hardware: &Arc<Hardware>,
+  command_manager: &GenericCommandManager,
+  command_message: ButtplugDeviceCommandMessageUnion,
+) -> ButtplugServerResultFuture {
+  match command_message {
+    ButtplugDeviceCommandMessageUnion::ScalarCmd(msg) => {
+      let commands = match command_manager.update_scalar(&msg, handler.needs_full_command_set()) {
+        Ok(values) => values,
+        Err(err) => return future::ready(Err(err)).boxed(),
+      };
+
+      if commands.is_empty() {
+        trace!("No commands generated for incoming device packet, skipping and returning success.");
+        return future::ready(Ok(message::Ok::default().into())).boxed();
+      }
+
+      generic_command_result(hardware.clone(), handler.handle_scalar_cmd(&commands))
+    }
+    ButtplugDeviceCommandMessageUnion::RotateCmd(msg) => {
+      let commands = match command_manager.update_rotation(&msg, handler.needs_full_command_set()) {
+        Ok(values) => values,
+        Err(err) => return future::ready(Err(err)).boxed(),
+      };
+      generic_command_result(hardware.clone(), handler.handle_rotate_cmd(&commands))
+    }
+    _ => unreachable!("Only scalar and rotation output goes through the generic command manager."),
+  }
+}
+
+/// Output being sent through the output queue. Once it's done (or given up on), whatever was
+/// merged in the meantime is sent on, until the queue runs dry.
+struct OutputSending {
+  output_queue: Arc<OutputQueue>,
+  handler: Arc<dyn ProtocolHandler>,
+  hardware: Arc<Hardware>,
+  command_manager: Arc<GenericCommandManager>,
+  disconnect_token: CancellationToken,
+}
+
+impl Drop for OutputSending {
+  fn drop(&mut self) {
+    if !self.output_queue.finish_sending() {
+      return;
+    }
+    let output_queue = self.output_queue.clone();
+    let handler = self.handler.clone();
+    let hardware = self.hardware.clone();
+    let command_manager = self.command_manager.clone();
+    let token = self.disconnect_token.clone();
+    async_manager::spawn(async move {
+      loop {
+        let _draining = output_queue.draining().await;
+        let Some(commands) = output_queue.take_pending() else {
+          break;
+        };
+        for command_message in commands {
+          let send = send_generic_output(&handler, &hardware, &command_manager, command_message);
+          tokio::select! {
+            result = send => if let Err(err) = result {
+              warn!("Sending merged output failed: {}", err);
+            },
+            _ = token.cancelled() => return,
+          }
+        }
+      }
+    });
+  }
 }
 
 pub struct ServerDevice {
   hardware: Arc<Hardware>,
   handler: Arc<dyn ProtocolHandler>,
-  attributes: ProtocolDeviceAttributes,
-  generic_command_manager: GenericCommandManager,
+  /// Attributes as of now, which can change while the device is connected.
+  attributes: RwLock<ProtocolDeviceAttributes>,
+  /// Message attributes the device connected with, which limit how far attributes can be changed.
+  connected_message_attributes: ServerDeviceMessageAttributes,
+  /// Replaced when step ranges change, as it's built around them.
+  generic_command_manager: RwLock<Arc<GenericCommandManager>>,
   /// Unique identifier for the device
   identifier: ServerDeviceIdentifier,
   raw_subscribed_endpoints: Arc<DashSet<Endpoint>>,
+  /// Cancelled when the hardware disconnects, so that in-flight commands can be drained instead of
+  /// waiting on hardware that will never answer.
+  disconnect_token: CancellationToken,
+  /// Set when something on our side asked for the disconnect, as opposed to the hardware dropping.
+  disconnect_requested: AtomicBool,
+  /// Battery read in progress and last battery level read, if any.
+  battery_state: BatteryState,
+  /// Readings from subscriptions, polled batteries and raw endpoints, sent to each event stream
+  /// through its own bounded queue.
+  sensor_notifier: SensorNotifier,
+  /// Sampling for sensor subscriptions that limit how many readings they receive.
+  sensor_samplers: SensorSamplers,
+  /// Calibrations applied to sensor readings before they're sent out.
+  sensor_calibrations: SensorCalibrations,
+  /// How long a command can take before we fail it, if the device or its protocol sets a limit.
+  command_timeout: Option<Duration>,
+  /// Merges output sent faster than the device or its protocol allows.
+  command_coalescer: Option<Arc<CommandCoalescer>>,
+  /// Merges output sent while the device is still busy with earlier output.
+  output_queue: Arc<OutputQueue>,
+  /// Warm up and cool down settings for the device's output.
+  ramp_policy: RampPolicy,
+  /// When the device was created, which is when warm up starts.
+  connected_at: Instant,
+  /// Battery saver settings, shared by every device.
+  battery_saver: Arc<BatterySaver>,
+  /// Output clients last asked for, before the battery saver scaled it.
+  requested_output: Mutex<RequestedOutput>,
+  /// Tracks how long the device has run hard, if its protocol has overuse protection.
+  overuse_monitor: Option<OveruseMonitor>,
+  /// Step downs from the overuse monitor, sent out as device events.
+  overuse_sender: broadcast::Sender<OveruseProtection>,
+  /// Arming and output limits, if the device is e-stim.
+  estim_interlock: Option<EstimInterlockGuard>,
+  /// Position estimates for linear devices that can't report their own position.
+  position_estimator: Option<Arc<LinearPositionEstimator>>,
+  /// Running subscriptions to estimated position sensors, keyed by sensor index.
+  position_subscriptions: Arc<DashMap<u32, CancellationToken>>,
+  /// Signal strength sensor, for devices configured to expose one.
+  rssi_sensor: Option<Arc<RssiSensor>>,
+  /// Running subscription to the signal strength sensor, if any.
+  rssi_subscription: Arc<Mutex<Option<CancellationToken>>>,
 }
 impl Debug for ServerDevice {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -194,23 +751,220 @@ impl PartialEq for ServerDevice {
   }
 }
 
+impl Drop for ServerDevice {
+  fn drop(&mut self) {
+    // Stops the hardware disconnect watcher task.
+    self.disconnect_token.cancel();
+  }
+}
+
 impl ServerDevice {
   /// Given a protocol and a device impl, create a new ButtplugDevice instance
+  #[allow(clippy::too_many_arguments)]
   fn new(
     identifier: ServerDeviceIdentifier,
     handler: Arc<dyn ProtocolHandler>,
     hardware: Arc<Hardware>,
     attributes: &ProtocolDeviceAttributes,
+    battery_poll_interval: Option<Duration>,
+    rssi_sample_interval: Option<Duration>,
+    command_timeout: Option<Duration>,
+    max_update_rate: Option<u32>,
+    ramp_policy: RampPolicy,
+    battery_saver: Arc<BatterySaver>,
+    overuse_protection: Option<OveruseProtection>,
+    estim_interlock: Option<EstimInterlock>,
   ) -> Self {
-    // Hook up our stream mapper now.
+    // Watch for hardware disconnection, so we can fail any commands still waiting on the device.
+    // Notifications are noted on the way past, for the device's activity, and passed on to event
+    // streams if they're from a raw subscribed endpoint. The hardware is only held weakly, so this
+    // doesn't keep it alive.
+    let disconnect_token = CancellationToken::new();
+    let sensor_notifier = SensorNotifier::default();
+    let raw_subscribed_endpoints = Arc::new(DashSet::new());
+    let token = disconnect_token.clone();
+    let mut hardware_events = hardware.event_stream();
+    let watched_hardware = Arc::downgrade(&hardware);
+    let notifier = sensor_notifier.clone();
+    let raw_endpoints = raw_subscribed_endpoints.clone();
+    async_manager::spawn(async move {
+      loop {
+        tokio::select! {
+          event = hardware_events.recv() => match event {
+            Ok(HardwareEvent::Disconnected(_)) | Err(RecvError::Closed) => break,
+            Ok(HardwareEvent::Notification(_address, endpoint, data)) => {
+              if let Some(hardware) = watched_hardware.upgrade() {
+                hardware.record_notification();
+              }
+              if raw_endpoints.contains(&endpoint) {
+                notifier.send(RawReading::new(0, endpoint, data).into());
+              }
+            }
+            Err(RecvError::Lagged(skipped)) => {
+              warn!("Hardware event watcher fell behind, skipped {} events.", skipped);
+            }
+          },
+          _ = token.cancelled() => return,
+        }
+      }
+      token.cancel();
+    });
+
+    // Estimated position sensors are added here rather than in the device config, as whether a
+    // device needs them depends on what sensors the config gives it.
+    let mut attributes = attributes.clone();
+    let position_estimator = LinearPositionEstimator::for_device(&mut attributes).map(Arc::new);
+    let rssi_sensor = rssi_sample_interval
+      .and_then(|interval| RssiSensor::for_device(&mut attributes, interval))
+      .map(Arc::new);
+
+    // Readings from the protocol handler are pulled as they arrive, so its channel never lags.
+    // Sampling and calibration happen here, so every event stream gets the same readings.
+    let battery_state = BatteryState::default();
+    let sensor_calibrations = SensorCalibrations::default();
+    let sensor_samplers = SensorSamplers::default();
+    let mut handler_events = handler.event_stream();
+    let token = disconnect_token.clone();
+    let notifier = sensor_notifier.clone();
+    let samplers = sensor_samplers.clone();
+    let calibrations = sensor_calibrations.clone();
+    async_manager::spawn(async move {
+      loop {
+        let mut message = tokio::select! {
+          message = handler_events.next() => match message {
+            Some(message) => message,
+            None => break,
+          },
+          _ = token.cancelled() => break,
+        };
+        if let ButtplugServerDeviceMessage::SensorReading(reading) = &mut message {
+          if let Some(mut sampler) =
+            samplers.get_mut(&(reading.sensor_index(), reading.sensor_type()))
+          {
+            if !sampler.should_send() {
+              continue;
+            }
+          }
+          calibrate_reading(&calibrations, reading);
+        }
+        notifier.send(message);
+      }
+    });
+    let (overuse_sender, _) = broadcast::channel(16);
+    let overuse_monitor =
+      overuse_protection.map(|protection| OveruseMonitor::new(protection, overuse_sender.clone()));
+    // Merged output is sent through the generic command manager, which handlers with their own
+    // message handling don't use.
+    let command_coalescer = max_update_rate
+      .filter(|_| !handler.has_handle_message())
+      .and_then(CommandCoalescer::new)
+      .map(Arc::new);
+
+    if let Some(interval) = battery_poll_interval {
+      let battery_sensor_index = attributes
+        .message_attributes()
+        .sensor_read_cmd()
+        .as_ref()
+        .and_then(|sensors| {
+          sensors
+            .iter()
+            .position(|sensor| *sensor.sensor_type() == SensorType::Battery)
+        });
+      if let Some(sensor_index) = battery_sensor_index {
+        info!(
+          "Polling battery for {:?} every {:?}",
+          identifier, interval
+        );
+        async_manager::spawn(Self::poll_battery(
+          interval,
+          sensor_index as u32,
+          disconnect_token.clone(),
+          battery_state.clone(),
+          handler.clone(),
+          hardware.clone(),
+          sensor_calibrations.clone(),
+          sensor_notifier.clone(),
+        ));
+      } else {
+        warn!(
+          "Battery polling configured for {:?}, but device has no battery sensor. Ignoring.",
+          identifier
+        );
+      }
+    }
 
     Self {
       identifier,
-      generic_command_manager: GenericCommandManager::new(attributes),
+      generic_command_manager: RwLock::new(Arc::new(GenericCommandManager::new(&attributes))),
       handler,
       hardware,
-      attributes: attributes.clone(),
-      raw_subscribed_endpoints: Arc::new(DashSet::new()),
+      connected_message_attributes: attributes.message_attributes(),
+      attributes: RwLock::new(attributes),
+      raw_subscribed_endpoints,
+      disconnect_token,
+      disconnect_requested: AtomicBool::new(false),
+      battery_state,
+      sensor_notifier,
+      sensor_samplers,
+      sensor_calibrations,
+      command_timeout,
+      command_coalescer,
+      output_queue: Arc::new(OutputQueue::default()),
+      ramp_policy,
+      connected_at: Instant::now(),
+      battery_saver,
+      requested_output: Mutex::new(RequestedOutput::default()),
+      overuse_monitor,
+      overuse_sender,
+      estim_interlock: estim_interlock.map(EstimInterlockGuard::new),
+      position_estimator,
+      position_subscriptions: Arc::new(DashMap::new()),
+      rssi_sensor,
+      rssi_subscription: Arc::new(Mutex::new(None)),
+    }
+  }
+
+  /// Periodically read the battery level of the device until it disconnects, sending a
+  /// notification whenever the reading changes.
+  #[allow(clippy::too_many_arguments)]
+  async fn poll_battery(
+    interval: Duration,
+    sensor_index: u32,
+    disconnect_token: CancellationToken,
+    battery_state: BatteryState,
+    handler: Arc<dyn ProtocolHandler>,
+    hardware: Arc<Hardware>,
+    sensor_calibrations: SensorCalibrations,
+    sensor_notifier: SensorNotifier,
+  ) {
+    let mut last_reading = None;
+    loop {
+      tokio::select! {
+        _ = sleep(interval) => {},
+        _ = disconnect_token.cancelled() => break,
+      }
+      let read = coalesced_battery_read(
+        &battery_state,
+        &handler,
+        &hardware,
+        &sensor_calibrations,
+        SensorReadCmd::new(0, sensor_index, SensorType::Battery),
+      );
+      let result = tokio::select! {
+        result = read => result,
+        _ = disconnect_token.cancelled() => break,
+      };
+      match result {
+        Ok(ButtplugServerMessage::SensorReading(reading)) => {
+          if last_reading.as_ref() == Some(reading.data()) {
+            continue;
+          }
+          last_reading = Some(reading.data().clone());
+          sensor_notifier.send(reading.into());
+        }
+        Ok(msg) => warn!("Unexpected battery poll reply: {:?}", msg),
+        Err(err) => debug!("Battery poll failed: {}", err),
+      }
     }
   }
 
@@ -219,9 +973,83 @@ impl ServerDevice {
     &self.identifier
   }
 
+  fn attributes(&self) -> RwLockReadGuard<'_, ProtocolDeviceAttributes> {
+    self
+      .attributes
+      .read()
+      .expect("Attributes lock should never be poisoned.")
+  }
+
+  fn generic_command_manager(&self) -> Arc<GenericCommandManager> {
+    self
+      .generic_command_manager
+      .read()
+      .expect("Command manager lock should never be poisoned.")
+      .clone()
+  }
+
   /// Get the user created display name for a device, if one exists.
   pub fn display_name(&self) -> Option<String> {
-    self.attributes.display_name()
+    self.attributes().display_name()
+  }
+
+  /// Change the display name of the device, or remove it with None, until the device disconnects.
+  pub fn set_display_name(&self, display_name: Option<String>) {
+    self
+      .attributes
+      .write()
+      .expect("Attributes lock should never be poisoned.")
+      .set_display_name(display_name);
+  }
+
+  /// Limit the step range of a ScalarCmd, RotateCmd or LinearCmd feature until the device
+  /// disconnects. The range has to fit inside the one the device connected with, so limits can be
+  /// tightened and loosened again, but never past what the device config allows.
+  ///
+  /// Output the device had before the change is forgotten, so the next command for the device sends
+  /// every feature again.
+  pub fn set_step_limit(
+    &self,
+    message_type: ButtplugDeviceMessageType,
+    feature_index: u32,
+    step_range: RangeInclusive<u32>,
+  ) -> Result<(), ButtplugDeviceError> {
+    let connected_features = match message_type {
+      ButtplugDeviceMessageType::ScalarCmd => self.connected_message_attributes.scalar_cmd(),
+      ButtplugDeviceMessageType::RotateCmd => self.connected_message_attributes.rotate_cmd(),
+      ButtplugDeviceMessageType::LinearCmd => self.connected_message_attributes.linear_cmd(),
+      _ => &None,
+    };
+    if let Some(connected_range) = connected_features
+      .as_ref()
+      .and_then(|features| features.get(feature_index as usize))
+      .map(|feature| feature.step_range())
+    {
+      if step_range.start() < connected_range.start() || step_range.end() > connected_range.end() {
+        return Err(ButtplugDeviceError::DeviceConfigurationError(format!(
+          "Step range {:?} for {} feature {} is outside of the device's range of {:?}.",
+          step_range, message_type, feature_index, connected_range
+        )));
+      }
+    }
+    let mut attributes = self
+      .attributes
+      .write()
+      .expect("Attributes lock should never be poisoned.");
+    attributes.set_step_range(message_type, feature_index, step_range)?;
+    *self
+      .generic_command_manager
+      .write()
+      .expect("Command manager lock should never be poisoned.") =
+      Arc::new(GenericCommandManager::new(&attributes));
+    Ok(())
+  }
+
+  /// Returns the btleplug peripheral handle if this is a bluetooth device. **Unstable**, see
+  /// [Hardware::btleplug_peripheral].
+  #[cfg(feature = "unstable-btleplug-peripheral")]
+  pub fn btleplug_peripheral(&self) -> Option<btleplug::platform::Peripheral> {
+    self.hardware.btleplug_peripheral()
   }
 
   /// Get the name of the device as set in the Device Configuration File.
@@ -241,55 +1069,218 @@ impl ServerDevice {
       ))
       .is_ok()
     {
-      format!("{} (Raw Messages Allowed)", self.attributes.name())
+      format!("{} (Raw Messages Allowed)", self.attributes().name())
     } else {
-      self.attributes.name().to_owned()
+      self.attributes().name().to_owned()
     }
   }
 
+  /// Returns true if the hardware has not reported a disconnection.
+  pub fn connected(&self) -> bool {
+    !self.disconnect_token.is_cancelled()
+  }
+
+  /// Returns true if [ServerDevice::disconnect] has been called, meaning any disconnection was
+  /// asked for rather than the hardware going away on its own.
+  pub fn disconnect_requested(&self) -> bool {
+    self.disconnect_requested.load(Ordering::SeqCst)
+  }
+
   /// Disconnect from the device, if it's connected.
   pub fn disconnect(&self) -> ButtplugResultFuture {
+    self.disconnect_requested.store(true, Ordering::SeqCst);
+    // Any commands still in flight will never complete once we've disconnected, so drain them now.
+    self.disconnect_token.cancel();
     let fut = self.hardware.disconnect();
     async move { fut.await.map_err(|err| err.into()) }.boxed()
   }
 
+  /// Returns the display name and description of the device for a client asking for `locale`. A
+  /// display name from the user config is used for every language, over any from the device
+  /// config.
+  pub fn localization(&self, locale: Option<&str>) -> DeviceLocalization {
+    let mut localization = locale
+      .map(|locale| self.attributes().localization(locale))
+      .unwrap_or_default();
+    if let Some(display_name) = self.display_name() {
+      localization.set_display_name(Some(display_name));
+    }
+    localization
+  }
+
+  /// Returns the last level read from the battery sensor of the device, in the sensor's range, if
+  /// the battery has been read since the device connected.
+  pub fn battery_level(&self) -> Option<i32> {
+    *self
+      .battery_state
+      .last_level
+      .lock()
+      .expect("Battery level lock should never be poisoned.")
+  }
+
+  /// Returns when the device was last sent commands and heard from, for telling silent hardware
+  /// apart from a stalled server.
+  pub fn activity(&self) -> HardwareActivity {
+    self.hardware.activity()
+  }
+
+  /// Returns the calibration applied to readings from a sensor, if it has one.
+  pub fn sensor_calibration(
+    &self,
+    sensor_index: u32,
+    sensor_type: SensorType,
+  ) -> Option<SensorCalibration> {
+    self
+      .sensor_calibrations
+      .get(&(sensor_index, sensor_type))
+      .map(|entry| entry.value().0.clone())
+  }
+
+  /// Sets the calibration applied to readings from a sensor, or removes it if `calibration` is
+  /// None. Fails if the device doesn't have a sensor of that type at that index.
+  pub fn set_sensor_calibration(
+    &self,
+    sensor_index: u32,
+    sensor_type: SensorType,
+    calibration: Option<SensorCalibration>,
+  ) -> Result<(), ButtplugDeviceError> {
+    let ranges = self.sensor_ranges(sensor_index, sensor_type)?;
+    if let Some(calibration) = calibration {
+      self
+        .sensor_calibrations
+        .insert((sensor_index, sensor_type), (calibration, ranges));
+    } else {
+      self
+        .sensor_calibrations
+        .remove(&(sensor_index, sensor_type));
+    }
+    Ok(())
+  }
+
+  fn sensor_ranges(
+    &self,
+    sensor_index: u32,
+    sensor_type: SensorType,
+  ) -> Result<Vec<RangeInclusive<u32>>, ButtplugDeviceError> {
+    let attributes = self.message_attributes();
+    // Readable and subscribable sensors are listed separately, but a sensor that can do both has
+    // the same index and range in each.
+    attributes
+      .sensor_read_cmd()
+      .iter()
+      .chain(attributes.sensor_subscribe_cmd().iter())
+      .find_map(|sensors| {
+        sensors
+          .get(sensor_index as usize)
+          .filter(|sensor| *sensor.sensor_type() == sensor_type)
+      })
+      .map(|sensor| sensor.sensor_range().clone())
+      .ok_or(ButtplugDeviceError::DeviceConfigurationError(format!(
+        "Device has no {} sensor at index {}",
+        sensor_type, sensor_index
+      )))
+  }
+
+  /// Reads a sensor `samples` times while the device is at rest, and installs a calibration that
+  /// zeroes the average of those readings, keeping any scale the sensor was already calibrated
+  /// with. Returns the new calibration, which can be saved to the user config so it's used the next
+  /// time the device connects.
+  ///
+  /// Only works for sensors that can be read. Readings taken here aren't calibrated, and aren't
+  /// sent to clients. Progress is reported to `progress` after each sample.
+  pub fn capture_sensor_baseline(
+    &self,
+    sensor_index: u32,
+    sensor_type: SensorType,
+    samples: u32,
+    progress: ProgressReporter,
+  ) -> BoxFuture<'static, Result<SensorCalibration, ButtplugDeviceError>> {
+    let result = if let Some(sensors) = self.message_attributes().sensor_read_cmd() {
+      self
+        .check_sensor_command(sensors, &sensor_index, &sensor_type)
+        .and_then(|_| self.sensor_ranges(sensor_index, sensor_type))
+    } else {
+      Err(ButtplugDeviceError::MessageNotSupported(
+        ButtplugDeviceMessageType::SensorReadCmd,
+      ))
+    };
+    let handler = self.handler.clone();
+    let hardware = self.hardware.clone();
+    let sensor_calibrations = self.sensor_calibrations.clone();
+    let previous = self
+      .sensor_calibration(sensor_index, sensor_type)
+      .unwrap_or_default();
+    async move {
+      let ranges = result?;
+      if samples == 0 {
+        return Err(ButtplugDeviceError::DeviceConfigurationError(
+          "Capturing a sensor baseline needs at least one sample".to_owned(),
+        ));
+      }
+      let mut readings = vec![];
+      progress.report(0, samples);
+      for sample in 0..samples {
+        let reading = read_sensor(
+          &handler,
+          &hardware,
+          SensorReadCmd::new(0, sensor_index, sensor_type),
+        )
+        .await?;
+        if let ButtplugServerMessage::SensorReading(msg) = reading {
+          readings.push(msg.data().clone());
+          progress.report(sample + 1, samples);
+        } else {
+          return Err(ButtplugDeviceError::DeviceCommunicationError(format!(
+            "Unexpected sensor read reply: {:?}",
+            reading
+          )));
+        }
+      }
+      let calibration = previous.with_offset(SensorCalibration::baseline(&readings));
+      sensor_calibrations.insert((sensor_index, sensor_type), (calibration.clone(), ranges));
+      Ok(calibration)
+    }
+    .boxed()
+  }
+
   /// Retreive the message attributes for the device.
   pub fn message_attributes(&self) -> ServerDeviceMessageAttributes {
-    self.attributes.message_attributes()
+    self.attributes().message_attributes()
   }
 
   /// Retreive the event stream for the device.
   ///
   /// This will include connections, disconnections, and notification events from subscribed
-  /// endpoints.
+  /// endpoints. Notifications are queued separately for each stream, and a stream falling too far
+  /// behind gets a [ServerDeviceEvent::NotificationsDropped] instead of the readings it missed.
   pub fn event_stream(&self) -> impl futures::Stream<Item = ServerDeviceEvent> + Send {
     let identifier = self.identifier.clone();
-    let raw_endpoints = self.raw_subscribed_endpoints.clone();
     let hardware_stream = convert_broadcast_receiver_to_stream(self.hardware.event_stream())
-      .filter_map(move |hardware_event| {
-        let id = identifier.clone();
-        match hardware_event {
-          HardwareEvent::Disconnected(_) => Some(ServerDeviceEvent::Disconnected(id)),
-          HardwareEvent::Notification(_address, endpoint, data) => {
-            // TODO Figure out how we're going to parse raw data into something sendable to the client.
-            if raw_endpoints.contains(&endpoint) {
-              Some(ServerDeviceEvent::Notification(
-                id,
-                ButtplugServerDeviceMessage::RawReading(RawReading::new(0, endpoint, data)),
-              ))
-            } else {
-              None
-            }
-          }
-        }
+      .filter_map(move |hardware_event| match hardware_event {
+        HardwareEvent::Disconnected(_) => Some(ServerDeviceEvent::Disconnected(identifier.clone())),
+        // Raw readings are passed on by the hardware watcher, through the sensor notifier.
+        HardwareEvent::Notification(..) => None,
       });
 
     let identifier = self.identifier.clone();
-    let handler_mapped_stream = self.handler.event_stream().map(move |incoming_message| {
-      let id = identifier.clone();
-      ServerDeviceEvent::Notification(id, incoming_message)
-    });
-    hardware_stream.merge(handler_mapped_stream)
+    let notification_stream =
+      self
+        .sensor_notifier
+        .subscribe()
+        .map(move |notification| match notification {
+          SensorNotification::Reading(message) => {
+            ServerDeviceEvent::Notification(identifier.clone(), message)
+          }
+          SensorNotification::Dropped(count) => {
+            ServerDeviceEvent::NotificationsDropped(identifier.clone(), count)
+          }
+        });
+    let identifier = self.identifier.clone();
+    let overuse_stream = convert_broadcast_receiver_to_stream(self.overuse_sender.subscribe())
+      .map(move |protection| ServerDeviceEvent::OveruseStepDown(identifier.clone(), protection));
+    hardware_stream
+      .merge(notification_stream)
+      .merge(overuse_stream)
   }
 
   pub fn supports_message(
@@ -299,7 +1290,7 @@ impl ServerDevice {
     // TODO This should be generated by a macro, as should the types enum.
     let check_msg = |msg_type| {
       self
-        .attributes
+        .attributes()
         .allows_message(&msg_type)
         .then_some(())
         .ok_or(ButtplugDeviceError::MessageNotSupported(msg_type))
@@ -330,6 +1321,23 @@ impl ServerDevice {
       ButtplugDeviceCommandMessageUnion::RawWriteCmd(_) => {
         check_msg(ButtplugDeviceMessageType::RawWriteCmd)
       }
+      // Firmware updates are sent as raw writes, so firmware mode is only for devices allowed raw
+      // messages, and only useful for devices with somewhere to send the update.
+      ButtplugDeviceCommandMessageUnion::FirmwareModeCmd(_) => {
+        check_msg(ButtplugDeviceMessageType::RawWriteCmd).and_then(|_| {
+          self
+            .hardware
+            .endpoints()
+            .contains(&Endpoint::Firmware)
+            .then_some(())
+            .ok_or(ButtplugDeviceError::InvalidEndpoint(Endpoint::Firmware))
+        })
+      }
+      ButtplugDeviceCommandMessageUnion::EstimArmCmd(_) => self
+        .estim_interlock
+        .as_ref()
+        .map(|_| ())
+        .ok_or_else(|| self.not_estim_device()),
       ButtplugDeviceCommandMessageUnion::RotateCmd(_) => {
         check_msg(ButtplugDeviceMessageType::RotateCmd)
       }
@@ -368,13 +1376,318 @@ impl ServerDevice {
   // In order to not have to worry about id setting at the protocol level (this
   // should be taken care of in the server's device manager), we return server
   // messages but Buttplug errors.
+  //
+  // If the device disconnects while a command is in flight, the command resolves with a
+  // DeviceDisconnected error instead of waiting on the hardware. Commands sent after disconnection
+  // fail immediately. Either way, the error names the device the same way DeviceAdded did, so
+  // clients can match it up without being sent the device's address.
   pub fn parse_message(
     &self,
     command_message: ButtplugDeviceCommandMessageUnion,
+  ) -> ButtplugServerResultFuture {
+    if !self.connected() {
+      return ButtplugDeviceError::DeviceDisconnected(self.name()).into();
+    }
+    let fut = self.handle_command_message(command_message);
+    let token = self.disconnect_token.clone();
+    let name = self.name();
+    // All commands go through here, so timeouts are the same no matter what the device is connected
+    // over.
+    let command_timeout = self.command_timeout;
+    async move {
+      let timeout = async move {
+        match command_timeout {
+          Some(duration) => {
+            sleep(duration).await;
+            duration
+          }
+          None => future::pending().await,
+        }
+      };
+      tokio::select! {
+        biased;
+        result = fut => result,
+        _ = token.cancelled() => Err(ButtplugDeviceError::DeviceDisconnected(name).into()),
+        duration = timeout => {
+          Err(ButtplugDeviceError::DeviceCommandTimeout(duration.as_millis() as u32).into())
+        }
+      }
+    }
+    .boxed()
+  }
+
+  /// Sends the output clients last asked for again, scaled by the current battery saver settings
+  /// and capped if the device has been stepped down.
+  pub(super) fn refresh_output(&self) -> ButtplugServerResultFuture {
+    if !self.connected() {
+      return future::ready(Ok(message::Ok::default().into())).boxed();
+    }
+    // This is everything clients asked for, so it replaces any merged output still waiting.
+    if let Some(coalescer) = &self.command_coalescer {
+      coalescer.clear();
+    }
+    self.output_queue.clear();
+    let commands = self.output_commands();
+    self.update_overuse_monitor(&commands);
+    let fut_vec: Vec<_> = commands
+      .into_iter()
+      .map(|msg| self.send_command_message(msg))
+      .collect();
+    async move {
+      for fut in fut_vec {
+        fut.await?;
+      }
+      Ok(message::Ok::default().into())
+    }
+    .boxed()
+  }
+
+  fn handle_command_message(
+    &self,
+    command_message: ButtplugDeviceCommandMessageUnion,
   ) -> ButtplugServerResultFuture {
     if let Err(err) = self.supports_message(&command_message) {
       return future::ready(Err(err)).boxed();
     }
+    if let Some(interlock) = &self.estim_interlock {
+      if let Err(err) = interlock.check_armed(&command_message) {
+        return future::ready(Err(err.into())).boxed();
+      }
+    }
+    // The battery saver scales what clients ask for, which is kept so it can be sent again when
+    // the battery saver changes.
+    self.record_requested_output(&command_message);
+    if self.overuse_monitor.is_some() {
+      self.update_overuse_monitor(&self.output_commands());
+    }
+    let command_message = self.battery_saver.apply(command_message);
+    if let Some(coalescer) = &self.command_coalescer {
+      return self.coalesce_command_message(coalescer, command_message);
+    }
+    self.queue_output(command_message)
+  }
+
+  /// Sends output now if the device isn't busy with earlier output, otherwise merges it into the
+  /// output sent once the device is free. Merged output is checked and capped here, as it goes
+  /// straight to the protocol handler when it's sent.
+  fn queue_output(
+    &self,
+    command_message: ButtplugDeviceCommandMessageUnion,
+  ) -> ButtplugServerResultFuture {
+    if !matches!(
+      command_message,
+      ButtplugDeviceCommandMessageUnion::ScalarCmd(_)
+        | ButtplugDeviceCommandMessageUnion::RotateCmd(_)
+    ) {
+      return self.send_command_message(command_message);
+    }
+    let checked = match &command_message {
+      ButtplugDeviceCommandMessageUnion::ScalarCmd(msg) => self.check_scalar_cmd(msg),
+      ButtplugDeviceCommandMessageUnion::RotateCmd(msg) => self.check_rotate_cmd(msg),
+      _ => Ok(()),
+    };
+    if let Err(err) = checked {
+      return future::ready(Err(err)).boxed();
+    }
+    let command_message = match self.output_queue.queue(self.limit_output(command_message)) {
+      Queued::Send(command_message) => command_message,
+      Queued::Merged => return future::ready(Ok(message::Ok::default().into())).boxed(),
+    };
+    let fut = self.send_command_message(command_message);
+    let sending = OutputSending {
+      output_queue: self.output_queue.clone(),
+      handler: self.handler.clone(),
+      hardware: self.hardware.clone(),
+      command_manager: self.generic_command_manager(),
+      disconnect_token: self.disconnect_token.clone(),
+    };
+    async move {
+      let result = fut.await;
+      // Sends anything merged while this was going out, without holding up the reply.
+      drop(sending);
+      result
+    }
+    .boxed()
+  }
+
+  /// Sends output now if nothing has been sent this update interval, otherwise merges it into the
+  /// output sent at the end of the interval. Held output is checked and capped here, as it goes
+  /// straight to the protocol handler when it's sent.
+  fn coalesce_command_message(
+    &self,
+    coalescer: &Arc<CommandCoalescer>,
+    command_message: ButtplugDeviceCommandMessageUnion,
+  ) -> ButtplugServerResultFuture {
+    let checked = match &command_message {
+      ButtplugDeviceCommandMessageUnion::ScalarCmd(msg) => self.check_scalar_cmd(msg),
+      ButtplugDeviceCommandMessageUnion::RotateCmd(msg) => self.check_rotate_cmd(msg),
+      _ => Ok(()),
+    };
+    if let Err(err) = checked {
+      return future::ready(Err(err)).boxed();
+    }
+    match coalescer.coalesce(self.limit_output(command_message)) {
+      Coalesced::Send(command_message) => return self.queue_output(command_message),
+      Coalesced::Merged => {}
+      Coalesced::FlushAfter(delay) => {
+        let coalescer = coalescer.clone();
+        let handler = self.handler.clone();
+        let hardware = self.hardware.clone();
+        let command_manager = self.generic_command_manager();
+        let token = self.disconnect_token.clone();
+        async_manager::spawn(async move {
+          tokio::select! {
+            _ = sleep(delay) => {},
+            _ = token.cancelled() => return,
+          }
+          let _flushing = coalescer.flushing().await;
+          for command_message in coalescer.take_pending() {
+            let result =
+              send_generic_output(&handler, &hardware, &command_manager, command_message).await;
+            if let Err(err) = result {
+              warn!("Sending merged output failed: {}", err);
+            }
+          }
+        });
+      }
+    }
+    future::ready(Ok(message::Ok::default().into())).boxed()
+  }
+
+  /// Commands for the output clients last asked for, run through the battery saver.
+  fn output_commands(&self) -> Vec<ButtplugDeviceCommandMessageUnion> {
+    self
+      .requested_output
+      .lock()
+      .expect("Requested output lock should never be poisoned.")
+      .commands()
+      .into_iter()
+      .map(|msg| self.battery_saver.apply(msg))
+      .collect()
+  }
+
+  /// Lets the overuse monitor know what the device is now running at. What matters is the output
+  /// the device is actually asked for, so resting duty cycle windows count as a break.
+  fn update_overuse_monitor(&self, commands: &[ButtplugDeviceCommandMessageUnion]) {
+    if let Some(monitor) = &self.overuse_monitor {
+      monitor.update(output_intensity(commands));
+    }
+  }
+
+  fn record_requested_output(&self, command_message: &ButtplugDeviceCommandMessageUnion) {
+    // Features that don't exist would fail every time the output is sent again, so leave them out.
+    // The commands still fail as usual when they're sent.
+    let rotation_count = self
+      .message_attributes()
+      .rotate_cmd()
+      .as_ref()
+      .map_or(0, |rotations| rotations.len());
+    let scalars_valid = match command_message {
+      ButtplugDeviceCommandMessageUnion::ScalarCmd(msg) => self.check_scalar_cmd(msg).is_ok(),
+      _ => false,
+    };
+    let mut requested_output = self
+      .requested_output
+      .lock()
+      .expect("Requested output lock should never be poisoned.");
+    match command_message {
+      ButtplugDeviceCommandMessageUnion::ScalarCmd(msg) if scalars_valid => {
+        requested_output.record_scalars(msg.scalars().iter())
+      }
+      ButtplugDeviceCommandMessageUnion::RotateCmd(msg) => requested_output.record_rotations(
+        msg
+          .rotations()
+          .iter()
+          .filter(|rotation| (rotation.index() as usize) < rotation_count),
+      ),
+      ButtplugDeviceCommandMessageUnion::StopDeviceCmd(_) => requested_output.clear(),
+      _ => {}
+    }
+  }
+
+  fn check_scalar_cmd(&self, msg: &ScalarCmd) -> Result<(), ButtplugError> {
+    let attributes = self.message_attributes();
+    let attrs = attributes
+      .scalar_cmd()
+      .as_ref()
+      .expect("Already checked existence");
+    for command in msg.scalars() {
+      if command.index() >= attrs.len() as u32 {
+        return Err(
+          ButtplugDeviceError::DeviceFeatureIndexError(attrs.len() as u32, command.index()).into(),
+        );
+      }
+      if *attrs[command.index() as usize].actuator_type() != command.actuator_type() {
+        return Err(
+          ButtplugDeviceError::DeviceActuatorTypeMismatch(
+            self.name(),
+            command.actuator_type(),
+            *attrs[command.index() as usize].actuator_type(),
+          )
+          .into(),
+        );
+      }
+    }
+    Ok(())
+  }
+
+  fn check_rotate_cmd(&self, msg: &RotateCmd) -> Result<(), ButtplugError> {
+    let rotation_count = self
+      .message_attributes()
+      .rotate_cmd()
+      .as_ref()
+      .map_or(0, |rotations| rotations.len()) as u32;
+    match msg
+      .rotations()
+      .iter()
+      .find(|rotation| rotation.index() >= rotation_count)
+    {
+      Some(rotation) => {
+        Err(ButtplugDeviceError::DeviceFeatureIndexError(rotation_count, rotation.index()).into())
+      }
+      None => Ok(()),
+    }
+  }
+
+  /// Caps output while the device warms up, or once it's been stepped down for overuse.
+  fn limit_output(
+    &self,
+    command_message: ButtplugDeviceCommandMessageUnion,
+  ) -> ButtplugDeviceCommandMessageUnion {
+    let command_message = match self.ramp_policy.warm_up_limit(self.connected_at.elapsed()) {
+      Some(limit) => scale_output(command_message, |level| level.min(limit)),
+      None => command_message,
+    };
+    let command_message = match self
+      .overuse_monitor
+      .as_ref()
+      .and_then(|monitor| monitor.limit())
+    {
+      Some(limit) => scale_output(command_message, |level| level.min(limit)),
+      None => command_message,
+    };
+    // Last, so the interlock sees what's actually going to the device.
+    match &self.estim_interlock {
+      Some(interlock) => interlock.limit(command_message),
+      None => command_message,
+    }
+  }
+
+  /// Disarms the device if it's e-stim, so it has to be armed again before it can output.
+  pub(super) fn disarm(&self) {
+    if let Some(interlock) = &self.estim_interlock {
+      interlock.disarm();
+    }
+  }
+
+  /// Sends a command on to the protocol handler, once it's been through the battery saver.
+  fn send_command_message(
+    &self,
+    command_message: ButtplugDeviceCommandMessageUnion,
+  ) -> ButtplugServerResultFuture {
+    // Every output command comes through here (including the ones other messages are converted
+    // to), so this is the one place output needs capping while the device warms up.
+    let command_message = self.limit_output(command_message);
 
     // If a handler implements handle message, bypass all of our parsing and let it do its own
     // thing. This should be a very rare thing.
@@ -391,6 +1704,8 @@ impl ServerDevice {
       ButtplugDeviceCommandMessageUnion::RawUnsubscribeCmd(msg) => {
         self.handle_raw_unsubscribe_cmd(msg)
       }
+      ButtplugDeviceCommandMessageUnion::FirmwareModeCmd(msg) => self.handle_firmware_mode_cmd(msg),
+      ButtplugDeviceCommandMessageUnion::EstimArmCmd(msg) => self.handle_estim_arm_cmd(msg),
       ButtplugDeviceCommandMessageUnion::StopDeviceCmd(_) => self.handle_stop_device_cmd(),
       ButtplugDeviceCommandMessageUnion::SingleMotorVibrateCmd(msg) => {
         self.handle_single_motor_vibrate_cmd(msg)
@@ -402,64 +1717,36 @@ impl ServerDevice {
       // use the generic command manager for, but still need protocol level translation.
       ButtplugDeviceCommandMessageUnion::ScalarCmd(msg) => {
         // TODO Add ability to turn off actuator matching
-        let attributes = self.attributes.message_attributes();
-        let attrs = attributes
-          .scalar_cmd()
-          .as_ref()
-          .expect("Already checked existence");
-        for command in msg.scalars() {
-          if command.index() > attrs.len() as u32 {
-            return future::ready(Err(
-              ButtplugDeviceError::DeviceFeatureIndexError(attrs.len() as u32, command.index())
-                .into(),
-            ))
-            .boxed();
-          }
-          if *attrs[command.index() as usize].actuator_type() != command.actuator_type() {
-            return future::ready(Err(
-              ButtplugDeviceError::DeviceActuatorTypeMismatch(
-                self.name(),
-                command.actuator_type(),
-                *attrs[command.index() as usize].actuator_type(),
-              )
-              .into(),
-            ))
-            .boxed();
-          }
-        }
-
-        let commands = match self
-          .generic_command_manager
-          .update_scalar(&msg, self.handler.needs_full_command_set())
-        {
-          Ok(values) => values,
-          Err(err) => return future::ready(Err(err)).boxed(),
-        };
-
-        if commands.is_empty() {
-          trace!(
-            "No commands generated for incoming device packet, skipping and returning success."
-          );
-          return future::ready(Ok(message::Ok::default().into())).boxed();
+        if let Err(err) = self.check_scalar_cmd(&msg) {
+          return future::ready(Err(err)).boxed();
         }
-
-        self.handle_generic_command_result(self.handler.handle_scalar_cmd(&commands))
-      }
-      ButtplugDeviceCommandMessageUnion::RotateCmd(msg) => {
-        let commands = match self
-          .generic_command_manager
-          .update_rotation(&msg, self.handler.needs_full_command_set())
-        {
-          Ok(values) => values,
-          Err(err) => return future::ready(Err(err)).boxed(),
-        };
-        self.handle_generic_command_result(self.handler.handle_rotate_cmd(&commands))
+        send_generic_output(
+          &self.handler,
+          &self.hardware,
+          &self.generic_command_manager(),
+          msg.into(),
+        )
       }
+      ButtplugDeviceCommandMessageUnion::RotateCmd(msg) => send_generic_output(
+        &self.handler,
+        &self.hardware,
+        &self.generic_command_manager(),
+        msg.into(),
+      ),
       ButtplugDeviceCommandMessageUnion::VibrateCmd(msg) => {
-        self.parse_message(ScalarCmd::from(msg).into())
+        self.handle_command_message(ScalarCmd::from(msg).into())
       }
       ButtplugDeviceCommandMessageUnion::LinearCmd(msg) => {
-        self.handle_generic_command_result(self.handler.handle_linear_cmd(msg))
+        let fut = self.handle_generic_command_result(self.handler.handle_linear_cmd(msg.clone()));
+        let position_estimator = self.position_estimator.clone();
+        async move {
+          let reply = fut.await?;
+          if let Some(estimator) = position_estimator {
+            estimator.command(&msg);
+          }
+          Ok(reply)
+        }
+        .boxed()
       }
       ButtplugDeviceCommandMessageUnion::FleshlightLaunchFW12Cmd(msg) => {
         self.handle_generic_command_result(self.handler.handle_fleshlight_launch_fw12_cmd(msg))
@@ -482,44 +1769,63 @@ impl ServerDevice {
     }
   }
 
-  fn handle_hardware_commands(&self, commands: Vec<HardwareCommand>) -> ButtplugServerResultFuture {
-    let hardware = self.hardware.clone();
-    async move {
-      // Run commands in order, otherwise we may end up sending out of order. This may take a while,
-      // but it's what 99% of protocols expect. If they want something else, they can implement it
-      // themselves.
-      //
-      // If anything errors out, just bail on the command series. This most likely means the device
-      // disconnected.
-      for command in commands {
-        hardware.parse_message(&command).await?;
-      }
-      Ok(message::Ok::default().into())
-    }
-    .boxed()
-  }
-
   fn handle_generic_command_result(
     &self,
     command_result: Result<Vec<HardwareCommand>, ButtplugDeviceError>,
   ) -> ButtplugServerResultFuture {
-    let hardware_commands = match command_result {
-      Ok(commands) => commands,
-      Err(err) => return future::ready(Err(err.into())).boxed(),
-    };
-
-    self.handle_hardware_commands(hardware_commands)
+    generic_command_result(self.hardware.clone(), command_result)
   }
 
   fn handle_stop_device_cmd(&self) -> ButtplugServerResultFuture {
-    let commands = self.generic_command_manager.stop_commands();
+    // If the device cools down, step its current output down before stopping it. Commands are
+    // built in order up front, so the command manager sees each step before the stop.
+    let mut cool_down_steps = vec![];
+    let command_manager = self.generic_command_manager();
+    let current_output = command_manager.current_output_commands();
+    if !current_output.is_empty() {
+      for level in self.ramp_policy.cool_down_levels() {
+        cool_down_steps.push(
+          current_output
+            .iter()
+            .map(|msg| self.send_command_message(scale_output(msg.clone(), |x| x * level)))
+            .collect::<Vec<_>>(),
+        );
+      }
+    }
+    let commands = command_manager.stop_commands();
     let mut fut_vec = vec![];
     commands
       .iter()
-      .for_each(|msg| fut_vec.push(self.parse_message(msg.clone())));
+      .for_each(|msg| fut_vec.push(self.send_command_message(msg.clone())));
+    let coalescer = self.command_coalescer.clone();
+    if let Some(coalescer) = &coalescer {
+      coalescer.clear();
+    }
+    let output_queue = self.output_queue.clone();
+    output_queue.clear();
     async move {
+      // Merged output may already be on its way out, so let it finish rather than land after the
+      // stop.
+      if let Some(coalescer) = coalescer {
+        coalescer.wait_for_flush().await;
+      }
+      output_queue.wait_for_drain().await;
+      for step in cool_down_steps {
+        for fut in step {
+          // Stopping matters more than a smooth ramp, so keep going if a step fails.
+          if let Err(err) = fut.await {
+            warn!("Device cool down step failed, continuing to stop: {}", err);
+          }
+        }
+        sleep(COOL_DOWN_STEP_INTERVAL).await;
+      }
       for fut in fut_vec {
-        fut.await?;
+        if let Err(err) = fut.await {
+          // The stop values are already stored as sent, so without this a retried stop would think
+          // there's nothing to do.
+          command_manager.forget_sent_values();
+          return Err(err);
+        }
       }
       Ok(message::Ok::default().into())
     }
@@ -560,14 +1866,58 @@ impl ServerDevice {
       message.sensor_index(),
       message.sensor_type(),
     );
+    if let Err(err) = result {
+      return future::ready(Err(err.into())).boxed();
+    }
+    if let Some(estimator) = &self.position_estimator {
+      if let Some(axis) = estimator.read_sensor_axis(*message.sensor_index()) {
+        let mut reading = SensorReading::new(
+          message.device_index(),
+          *message.sensor_index(),
+          SensorType::Position,
+          vec![estimator.position(axis)],
+        );
+        reading.set_id(message.id());
+        calibrate_reading(&self.sensor_calibrations, &mut reading);
+        return future::ready(Ok(reading.into())).boxed();
+      }
+    }
+    if let Some(rssi_sensor) = &self.rssi_sensor {
+      if rssi_sensor.read_sensor_index() == *message.sensor_index() {
+        let read = self.hardware.read_rssi();
+        let sensor_calibrations = self.sensor_calibrations.clone();
+        return async move {
+          let mut reading = SensorReading::new(
+            message.device_index(),
+            *message.sensor_index(),
+            SensorType::RSSI,
+            vec![RssiSensor::strength(read.await?)],
+          );
+          reading.set_id(message.id());
+          calibrate_reading(&sensor_calibrations, &mut reading);
+          Ok(reading.into())
+        }
+        .boxed();
+      }
+    }
+    if *message.sensor_type() == SensorType::Battery {
+      return coalesced_battery_read(
+        &self.battery_state,
+        &self.handler,
+        &self.hardware,
+        &self.sensor_calibrations,
+        message,
+      );
+    }
     let device = self.hardware.clone();
     let handler = self.handler.clone();
+    let sensor_calibrations = self.sensor_calibrations.clone();
     async move {
-      result?;
-      handler
-        .handle_sensor_read_cmd(device, message)
-        .await
-        .map_err(|e| e.into())
+      let mut reading = read_sensor(&handler, &device, message).await?;
+      if let ButtplugServerMessage::SensorReading(msg) = &mut reading {
+        calibrate_reading(&sensor_calibrations, msg);
+      }
+      Ok(reading)
     }
     .boxed()
   }
@@ -585,18 +1935,150 @@ impl ServerDevice {
       message.sensor_index(),
       message.sensor_type(),
     );
-    let device = self.hardware.clone();
     let handler = self.handler.clone();
+    let sensor_samplers = self.sensor_samplers.clone();
+    let sensor_key = (*message.sensor_index(), *message.sensor_type());
+    let sampler = SensorSampler::new(&message);
+    if let Some(estimator) = &self.position_estimator {
+      if let Some(axis) = estimator.subscribe_sensor_axis(*message.sensor_index()) {
+        if let Err(err) = result {
+          return future::ready(Err(err.into())).boxed();
+        }
+        self.subscribe_position_estimate(estimator.clone(), axis, &message, sampler);
+        return future::ready(Ok(message::Ok::new(message.id()).into())).boxed();
+      }
+    }
+    if let Some(rssi_sensor) = &self.rssi_sensor {
+      if rssi_sensor.subscribe_sensor_index() == *message.sensor_index() {
+        if let Err(err) = result {
+          return future::ready(Err(err.into())).boxed();
+        }
+        self.subscribe_rssi(rssi_sensor.sample_interval(), &message, sampler);
+        return future::ready(Ok(message::Ok::new(message.id()).into())).boxed();
+      }
+    }
     async move {
       result?;
-      handler
-        .handle_sensor_subscribe_cmd(device, message)
-        .await
-        .map_err(|e| e.into())
+      let reply = handler.handle_sensor_subscribe_cmd(message).await?;
+      // Subscribing again replaces the sampling options of an existing subscription.
+      if let Some(sampler) = sampler {
+        sensor_samplers.insert(sensor_key, sampler);
+      } else {
+        sensor_samplers.remove(&sensor_key);
+      }
+      Ok(reply)
     }
     .boxed()
   }
 
+  /// Sends a reading for an estimated position sensor whenever the estimate changes, until the
+  /// sensor is unsubscribed or the device disconnects. Subscribing again replaces the existing
+  /// subscription.
+  fn subscribe_position_estimate(
+    &self,
+    estimator: Arc<LinearPositionEstimator>,
+    axis: usize,
+    message: &SensorSubscribeCmd,
+    mut sampler: Option<SensorSampler>,
+  ) {
+    let sensor_index = *message.sensor_index();
+    let token = self.disconnect_token.child_token();
+    if let Some(old_token) = self
+      .position_subscriptions
+      .insert(sensor_index, token.clone())
+    {
+      old_token.cancel();
+    }
+    let device_index = message.device_index();
+    let sensor_calibrations = self.sensor_calibrations.clone();
+    let sensor_notifier = self.sensor_notifier.clone();
+    async_manager::spawn(async move {
+      let mut last_position = None;
+      loop {
+        tokio::select! {
+          _ = sleep(ESTIMATED_POSITION_INTERVAL) => {},
+          _ = token.cancelled() => break,
+        }
+        let position = estimator.position(axis);
+        if last_position == Some(position) {
+          continue;
+        }
+        last_position = Some(position);
+        if let Some(sampler) = &mut sampler {
+          if !sampler.should_send() {
+            continue;
+          }
+        }
+        let mut reading = SensorReading::new(
+          device_index,
+          sensor_index,
+          SensorType::Position,
+          vec![position],
+        );
+        calibrate_reading(&sensor_calibrations, &mut reading);
+        sensor_notifier.send(reading.into());
+      }
+    });
+  }
+
+  /// Sends a reading for the signal strength sensor whenever a sample differs from the last one,
+  /// until the sensor is unsubscribed or the device disconnects. Subscribing again replaces the
+  /// existing subscription.
+  fn subscribe_rssi(
+    &self,
+    sample_interval: Duration,
+    message: &SensorSubscribeCmd,
+    mut sampler: Option<SensorSampler>,
+  ) {
+    let sensor_index = *message.sensor_index();
+    let token = self.disconnect_token.child_token();
+    if let Some(old_token) = self
+      .rssi_subscription
+      .lock()
+      .expect("RSSI subscription lock should never be poisoned.")
+      .replace(token.clone())
+    {
+      old_token.cancel();
+    }
+    let device_index = message.device_index();
+    let hardware = self.hardware.clone();
+    let sensor_calibrations = self.sensor_calibrations.clone();
+    let sensor_notifier = self.sensor_notifier.clone();
+    async_manager::spawn(async move {
+      let mut last_strength = None;
+      loop {
+        tokio::select! {
+          _ = sleep(sample_interval) => {},
+          _ = token.cancelled() => break,
+        }
+        let rssi = tokio::select! {
+          rssi = hardware.read_rssi() => rssi,
+          _ = token.cancelled() => break,
+        };
+        let strength = match rssi {
+          Ok(rssi) => RssiSensor::strength(rssi),
+          Err(err) => {
+            debug!("RSSI sample failed: {}", err);
+            continue;
+          }
+        };
+        if last_strength == Some(strength) {
+          continue;
+        }
+        last_strength = Some(strength);
+        if let Some(sampler) = &mut sampler {
+          if !sampler.should_send() {
+            continue;
+          }
+        }
+        let mut reading =
+          SensorReading::new(device_index, sensor_index, SensorType::RSSI, vec![strength]);
+        calibrate_reading(&sensor_calibrations, &mut reading);
+        sensor_notifier.send(reading.into());
+      }
+    });
+  }
+
   fn handle_sensor_unsubscribe_cmd(
     &self,
     message: message::SensorUnsubscribeCmd,
@@ -610,14 +2092,44 @@ impl ServerDevice {
       message.sensor_index(),
       message.sensor_type(),
     );
-    let device = self.hardware.clone();
     let handler = self.handler.clone();
+    let sensor_samplers = self.sensor_samplers.clone();
+    let sensor_key = (*message.sensor_index(), *message.sensor_type());
+    if let Some(estimator) = &self.position_estimator {
+      if estimator
+        .subscribe_sensor_axis(*message.sensor_index())
+        .is_some()
+      {
+        if let Err(err) = result {
+          return future::ready(Err(err.into())).boxed();
+        }
+        if let Some((_, token)) = self.position_subscriptions.remove(message.sensor_index()) {
+          token.cancel();
+        }
+        return future::ready(Ok(message::Ok::new(message.id()).into())).boxed();
+      }
+    }
+    if let Some(rssi_sensor) = &self.rssi_sensor {
+      if rssi_sensor.subscribe_sensor_index() == *message.sensor_index() {
+        if let Err(err) = result {
+          return future::ready(Err(err.into())).boxed();
+        }
+        if let Some(token) = self
+          .rssi_subscription
+          .lock()
+          .expect("RSSI subscription lock should never be poisoned.")
+          .take()
+        {
+          token.cancel();
+        }
+        return future::ready(Ok(message::Ok::new(message.id()).into())).boxed();
+      }
+    }
     async move {
       result?;
-      handler
-        .handle_sensor_unsubscribe_cmd(device, message)
-        .await
-        .map_err(|e| e.into())
+      let reply = handler.handle_sensor_unsubscribe_cmd(message).await?;
+      sensor_samplers.remove(&sensor_key);
+      Ok(reply)
     }
     .boxed()
   }
@@ -626,7 +2138,7 @@ impl ServerDevice {
     &self,
     message: message::SingleMotorVibrateCmd,
   ) -> ButtplugServerResultFuture {
-    if let Some(attr) = self.attributes.message_attributes().scalar_cmd() {
+    if let Some(attr) = self.message_attributes().scalar_cmd() {
       let speed = message.speed();
       let cmds: Vec<ScalarSubcommand> = attr
         .iter()
@@ -643,7 +2155,7 @@ impl ServerDevice {
       } else {
         let mut vibrate_cmd = ScalarCmd::new(message.device_index(), cmds);
         vibrate_cmd.set_id(message.id());
-        self.parse_message(vibrate_cmd.into())
+        self.handle_command_message(vibrate_cmd.into())
       }
     } else {
       ButtplugDeviceError::ProtocolRequirementError(format!(
@@ -726,6 +2238,52 @@ impl ServerDevice {
     .boxed()
   }
 
+  fn handle_firmware_mode_cmd(
+    &self,
+    message: message::FirmwareModeCmd,
+  ) -> ButtplugServerResultFuture {
+    info!(
+      "{} firmware mode for {}.",
+      if message.enabled() {
+        "Entering"
+      } else {
+        "Leaving"
+      },
+      self.name()
+    );
+    self.hardware.set_firmware_mode(message.enabled());
+    future::ready(Ok(message::Ok::new(message.id()).into())).boxed()
+  }
+
+  fn not_estim_device(&self) -> ButtplugDeviceError {
+    ButtplugDeviceError::ProtocolRequirementError(format!(
+      "{} is not an e-stim device, and has nothing to arm.",
+      self.name()
+    ))
+  }
+
+  fn handle_estim_arm_cmd(&self, message: message::EstimArmCmd) -> ButtplugServerResultFuture {
+    let interlock = if let Some(interlock) = &self.estim_interlock {
+      interlock
+    } else {
+      return future::ready(Err(self.not_estim_device().into())).boxed();
+    };
+    if message.armed() {
+      info!("Arming {}.", self.name());
+      interlock.arm();
+      return future::ready(Ok(message::Ok::new(message.id()).into())).boxed();
+    }
+    // Disarming stops the device first, while the stop can still get through the interlock.
+    info!("Disarming {}.", self.name());
+    let stop = self.handle_stop_device_cmd();
+    interlock.disarm();
+    async move {
+      stop.await?;
+      Ok(message::Ok::new(message.id()).into())
+    }
+    .boxed()
+  }
+
   fn handle_battery_level_cmd(&self) -> ButtplugServerResultFuture {
     // See if we have a battery sensor.
     if let Some(sensor_attributes) = self.message_attributes().sensor_read_cmd() {
@@ -764,7 +2322,12 @@ impl ServerDevice {
   }
 
   fn handle_rssi_level_cmd(&self) -> ButtplugServerResultFuture {
-    // See if we have a battery sensor.
+    // RSSILevelReading is in dBm, so skip the conversion to strength our own sensor does.
+    if self.rssi_sensor.is_some() {
+      let read = self.hardware.read_rssi();
+      return async move { Ok(RSSILevelReading::new(0, read.await? as i32).into()) }.boxed();
+    }
+    // See if we have an RSSI sensor.
     if let Some(sensor_attributes) = self.message_attributes().sensor_read_cmd() {
       for (index, sensor) in sensor_attributes.iter().enumerate() {
         if *sensor.sensor_type() == SensorType::RSSI {
@@ -796,3 +2359,108 @@ impl ServerDevice {
     .boxed()
   }
 }
+
+#[cfg(test)]
+mod test {
+  use super::ServerDeviceIdentifier;
+  use crate::server::device::configuration::ProtocolAttributesType;
+
+  #[test]
+  fn test_identifier_string_roundtrip() {
+    let identifiers = [
+      ServerDeviceIdentifier::new(
+        "00:11:22:33:44:55",
+        "lovense",
+        &ProtocolAttributesType::Default,
+      ),
+      ServerDeviceIdentifier::new(
+        "COM3",
+        "kiiroo-v21",
+        &ProtocolAttributesType::Identifier("OhMiBod LUMEN".to_owned()),
+      ),
+      ServerDeviceIdentifier::new(
+        "odd;address%3B",
+        "protocol",
+        &ProtocolAttributesType::Identifier(String::new()),
+      ),
+    ];
+    for identifier in identifiers {
+      let serialized = identifier.to_string();
+      assert_eq!(
+        serialized.parse::<ServerDeviceIdentifier>().unwrap(),
+        identifier
+      );
+      let json = serde_json::to_string(&identifier).unwrap();
+      assert_eq!(
+        serde_json::from_str::<ServerDeviceIdentifier>(&json).unwrap(),
+        identifier
+      );
+    }
+  }
+
+  #[test]
+  fn test_identifier_string_format() {
+    let identifier = ServerDeviceIdentifier::new(
+      "00:11:22:33:44:55",
+      "lovense",
+      &ProtocolAttributesType::Identifier("P".to_owned()),
+    );
+    assert_eq!(identifier.to_string(), "1;lovense;=P;00:11:22:33:44:55");
+    let identifier =
+      ServerDeviceIdentifier::new("a;b", "lovense", &ProtocolAttributesType::Default);
+    assert_eq!(identifier.to_string(), "1;lovense;;a%3Bb");
+  }
+
+  #[test]
+  fn test_identifier_invalid_strings() {
+    for invalid in [
+      "",
+      "lovense",
+      "2;lovense;;address",
+      "1;lovense;P;address",
+      "1;lovense;;address;extra",
+    ] {
+      assert!(invalid.parse::<ServerDeviceIdentifier>().is_err());
+    }
+  }
+
+  #[test]
+  fn test_identifier_legacy_formats() {
+    let identifiers = [
+      ServerDeviceIdentifier::new(
+        "00:11:22:33:44:55",
+        "lovense",
+        &ProtocolAttributesType::Default,
+      ),
+      ServerDeviceIdentifier::new(
+        "quoted \"address\" \\ \u{1b}",
+        "lovense",
+        &ProtocolAttributesType::Identifier("P".to_owned()),
+      ),
+    ];
+    for identifier in identifiers {
+      let debug = format!("{:?}", identifier);
+      assert_eq!(debug.parse::<ServerDeviceIdentifier>().unwrap(), identifier);
+      let json = serde_json::to_string(&debug).unwrap();
+      assert_eq!(
+        serde_json::from_str::<ServerDeviceIdentifier>(&json).unwrap(),
+        identifier
+      );
+    }
+    let json =
+      r#"{"address":"addr","protocol":"lovense","attributes_identifier":{"Identifier":"P"}}"#;
+    assert_eq!(
+      serde_json::from_str::<ServerDeviceIdentifier>(json).unwrap(),
+      ServerDeviceIdentifier::new(
+        "addr",
+        "lovense",
+        &ProtocolAttributesType::Identifier("P".to_owned())
+      )
+    );
+    let json = r#"{"address":"addr","protocol":"lovense","attributes_identifier":"Default"}"#;
+    assert_eq!(
+      serde_json::from_str::<ServerDeviceIdentifier>(json).unwrap(),
+      ServerDeviceIdentifier::new("addr", "lovense", &ProtocolAttributesType::Default)
+    );
+  }
+}
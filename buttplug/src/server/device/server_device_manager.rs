@@ -8,55 +8,97 @@
 //! Buttplug Device Manager, manages Device Subtype (Platform/Communication bus
 //! specific) Managers
 
-use super::server_device_manager_event_loop::ServerDeviceManagerEventLoop;
+#[cfg(feature = "scripting")]
+use super::command_script::{send_script_commands, CommandScript};
+use super::{
+  battery_saver::{BatterySaver, BatterySaverSettings},
+  comm_manager_metrics::CommManagerMetrics,
+  connection_hook::DeviceConnectionHook,
+  merged_device::MergedDevices,
+  overuse_protection::OveruseWarning,
+  pattern_playback::PatternPlayback,
+  server_device_manager_event_loop::ServerDeviceManagerEventLoop,
+  server_device_manager_event_queue::{lane_queue, LaneQueueSender, QueueMetrics},
+  split_device::SplitDevices,
+};
 use crate::{
   core::{
-    errors::{ButtplugDeviceError, ButtplugMessageError, ButtplugUnknownError},
+    errors::{
+      ButtplugDeviceError,
+      ButtplugMessageError,
+      ButtplugUnknownError,
+      DeviceStopFailure,
+      DeviceStopFailures,
+    },
     message::{
       self,
       ButtplugClientMessage,
       ButtplugDeviceCommandMessageUnion,
       ButtplugDeviceManagerMessageUnion,
       ButtplugDeviceMessage,
+      ButtplugDeviceMessageType,
       ButtplugMessage,
       ButtplugServerMessage,
+      DeviceAttributes,
       DeviceList,
       DeviceMessageInfo,
+      SensorType,
     },
   },
   server::{
     device::{
       configuration::{
+        DeviceConfigurationManager,
         DeviceConfigurationManagerBuilder,
         ProtocolAttributesIdentifier,
         ProtocolCommunicationSpecifier,
         ProtocolDeviceAttributes,
+        ProtocolSuggestion,
       },
       hardware::communication::{
+        checked_status,
         HardwareCommunicationManager,
         HardwareCommunicationManagerBuilder,
+        HardwareCommunicationManagerConfig,
+        HardwareCommunicationManagerEvent,
       },
       protocol::ProtocolIdentifierFactory,
+      SensorCalibration,
+      SensorRule,
       ServerDevice,
       ServerDeviceIdentifier,
     },
+    persistence::ButtplugPersistence,
     ButtplugServerError,
     ButtplugServerResultFuture,
+    DeviceStateSnapshot,
+    ProgressReporter,
+  },
+  util::{
+    address_privacy::display_address,
+    async_manager,
+    device_configuration::load_protocol_configs,
+    sleep,
+    stream::{convert_broadcast_receiver_to_conflated_stream, convert_broadcast_receiver_to_stream},
   },
-  util::{async_manager, stream::convert_broadcast_receiver_to_stream},
 };
 use dashmap::DashMap;
 use futures::{
-  future::{self, FutureExt},
+  future::{self, BoxFuture, FutureExt},
   Stream,
 };
-use getset::Getters;
+use getset::{CopyGetters, Getters};
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
 use std::{
   convert::TryFrom,
+  ops::RangeInclusive,
   sync::{
     atomic::{AtomicBool, Ordering},
     Arc,
+    RwLock,
   },
+  time::Duration,
 };
 use tokio::sync::{broadcast, mpsc};
 use tokio_util::sync::CancellationToken;
@@ -64,9 +106,43 @@ use tokio_util::sync::CancellationToken;
 #[derive(Debug)]
 pub(super) enum DeviceManagerCommand {
   StartScanning,
+  /// Scan for a single, previously connected device.
+  StartScanningFor(ServerDeviceIdentifier),
   StopScanning,
 }
 
+/// Number of extra attempts StopAllDevices makes for devices that failed to stop. Stopping is the
+/// command users rely on when something goes wrong, so it's worth a couple more tries.
+const STOP_ALL_DEVICES_RETRIES: u32 = 2;
+/// Time to wait between StopAllDevices retries.
+const STOP_ALL_DEVICES_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Number of items the device manager event loop queues hold before senders have to wait for room.
+pub(super) const DEVICE_MANAGER_QUEUE_CAPACITY: usize = 256;
+
+/// Number of devices returned per page of a paged device list, unless otherwise specified via
+/// [ServerDeviceManagerBuilder::device_list_page_size].
+pub const DEFAULT_DEVICE_LIST_PAGE_SIZE: u32 = 64;
+
+/// Entry for a device in a [DeviceList], with its display name and description in the language
+/// the client asked for.
+fn device_message_info(
+  device_index: u32,
+  device: &ServerDevice,
+  locale: Option<&str>,
+) -> DeviceMessageInfo {
+  let localization = device.localization(locale);
+  let mut info = DeviceMessageInfo::new(
+    device_index,
+    &device.name(),
+    localization.display_name(),
+    &None,
+    device.message_attributes().into(),
+  );
+  info.set_device_description(localization.description().clone());
+  info
+}
+
 #[derive(Debug, Getters)]
 #[getset(get = "pub")]
 pub struct ServerDeviceInfo {
@@ -74,10 +150,60 @@ pub struct ServerDeviceInfo {
   display_name: Option<String>,
 }
 
+/// Hardware that showed up while scanning but didn't match any protocol in the device
+/// configuration. Useful for showing users devices that may be unsupported, and for filing
+/// support requests, since the specifier is what a device configuration entry would need to
+/// match.
+#[derive(Debug, Clone, PartialEq, Eq, Getters, CopyGetters)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct UnsupportedDeviceInfo {
+  /// Name the hardware advertised, or the name the comm manager gave it.
+  #[getset(get = "pub")]
+  name: String,
+  /// Hardware address, hashed if address hashing is on. See
+  /// [address_privacy](crate::util::address_privacy).
+  #[getset(get = "pub")]
+  address: String,
+  /// What the hardware looked like to its comm manager (advertised names and services for
+  /// bluetooth, port name for serial, etc).
+  #[getset(get = "pub")]
+  specifier: ProtocolCommunicationSpecifier,
+  /// Signal strength when the hardware was found, for buses that report it.
+  #[getset(get_copy = "pub")]
+  rssi: Option<i16>,
+  /// Protocols the hardware came closest to matching, best first. Empty if nothing came close, or
+  /// the hardware's bus doesn't carry enough information to compare.
+  #[getset(get = "pub")]
+  suggestions: Vec<ProtocolSuggestion>,
+}
+
+impl UnsupportedDeviceInfo {
+  pub(super) fn new(
+    name: &str,
+    address: &str,
+    specifier: ProtocolCommunicationSpecifier,
+    rssi: Option<i16>,
+    suggestions: Vec<ProtocolSuggestion>,
+  ) -> Self {
+    Self {
+      name: name.to_owned(),
+      address: display_address(address).to_string(),
+      specifier,
+      rssi,
+      suggestions,
+    }
+  }
+}
+
 #[derive(Default)]
 pub struct ServerDeviceManagerBuilder {
   configuration_manager_builder: DeviceConfigurationManagerBuilder,
   comm_managers: Vec<Box<dyn HardwareCommunicationManagerBuilder>>,
+  device_list_page_size: Option<u32>,
+  reconnect_window: Option<Duration>,
+  connection_hook: Option<Arc<dyn DeviceConnectionHook>>,
+  #[cfg(feature = "scripting")]
+  command_script: Option<Arc<CommandScript>>,
 }
 
 impl ServerDeviceManagerBuilder {
@@ -89,6 +215,14 @@ impl ServerDeviceManagerBuilder {
     self
   }
 
+  /// Add the comm manager matching a runtime configuration struct, set up with that configuration.
+  pub fn comm_manager_config<T>(&mut self, config: T) -> &mut Self
+  where
+    T: HardwareCommunicationManagerConfig,
+  {
+    self.comm_manager(config.into_builder())
+  }
+
   pub fn device_configuration_manager_builder(
     &mut self,
     dcm_builder: &DeviceConfigurationManagerBuilder,
@@ -114,6 +248,35 @@ impl ServerDeviceManagerBuilder {
     self
   }
 
+  /// Present the given devices to clients as a single device called `name`, once all of them are
+  /// connected.
+  pub fn merged_device(&mut self, name: &str, members: &[ServerDeviceIdentifier]) -> &mut Self {
+    self
+      .configuration_manager_builder
+      .merged_device(name, members);
+    self
+  }
+
+  /// Drive an actuator from readings of a sensor on the server. See [SensorRule] for details.
+  pub fn sensor_rule(&mut self, rule: SensorRule) -> &mut Self {
+    self.configuration_manager_builder.sensor_rule(rule);
+    self
+  }
+
+  /// Present each output feature of the device with the given identifier to clients as a device of
+  /// its own.
+  pub fn split_device(&mut self, identifier: &ServerDeviceIdentifier) -> &mut Self {
+    self
+      .configuration_manager_builder
+      .split_device(identifier);
+    self
+  }
+
+  pub fn persistence(&mut self, persistence: Arc<dyn ButtplugPersistence>) -> &mut Self {
+    self.configuration_manager_builder.persistence(persistence);
+    self
+  }
+
   pub fn protocol_factory<T>(&mut self, factory: T) -> &mut Self
   where
     T: ProtocolIdentifierFactory + 'static,
@@ -154,17 +317,67 @@ impl ServerDeviceManagerBuilder {
     self
   }
 
-  pub fn finish(&mut self) -> Result<ServerDeviceManager, ButtplugServerError> {
-    let config_mgr = self
+  /// Add raw message support to the device with the given identifier only. See
+  /// [DeviceConfigurationManagerBuilder::allow_raw_messages_for].
+  pub fn allow_raw_messages_for(&mut self, identifier: &ServerDeviceIdentifier) -> &mut Self {
+    self
       .configuration_manager_builder
-      .finish()
-      .map_err(ButtplugServerError::DeviceConfigurationManagerError)?;
+      .allow_raw_messages_for(identifier);
+    self
+  }
+
+  /// Set the maximum number of devices sent per page when clients request a paged device list.
+  /// Values below 1 are treated as 1.
+  pub fn device_list_page_size(&mut self, page_size: u32) -> &mut Self {
+    self.device_list_page_size = Some(page_size.max(1));
+    self
+  }
 
-    let (device_command_sender, device_command_receiver) = mpsc::channel(256);
+  /// Look for devices that disconnect without being asked to (out of range, powered off, etc) for
+  /// up to `window`, reconnecting any that come back. Reconnected devices get their old index.
+  /// While only reconnect candidates are being looked for, anything else found is ignored and
+  /// clients won't get a ScanningFinished for the scan.
+  pub fn reconnect_window(&mut self, window: Duration) -> &mut Self {
+    self.reconnect_window = Some(window);
+    self
+  }
+
+  /// Ask `hook` about every device that connects before clients are told about it, so it can be
+  /// turned away or given a display name. See [DeviceConnectionHook].
+  pub fn connection_hook<T>(&mut self, hook: T) -> &mut Self
+  where
+    T: DeviceConnectionHook + 'static,
+  {
+    self.connection_hook = Some(Arc::new(hook));
+    self
+  }
+
+  /// Run commands sent by clients and sensor readings sent by devices through a user script. See
+  /// [CommandScript] for what scripts can do.
+  #[cfg(feature = "scripting")]
+  pub fn command_script(&mut self, script: CommandScript) -> &mut Self {
+    self.command_script = Some(Arc::new(script));
+    self
+  }
+
+  pub fn finish(&mut self) -> Result<ServerDeviceManager, ButtplugServerError> {
+    let config_mgr = Arc::new(
+      self
+        .configuration_manager_builder
+        .finish()
+        .map_err(ButtplugServerError::DeviceConfigurationManagerError)?,
+    );
+
+    let (device_command_sender, device_command_receiver) =
+      lane_queue(DEVICE_MANAGER_QUEUE_CAPACITY);
     let (device_event_sender, device_event_receiver) = mpsc::channel(256);
     let mut comm_managers: Vec<Box<dyn HardwareCommunicationManager>> = Vec::new();
+    let mut comm_manager_metrics = vec![];
     for builder in &mut self.comm_managers {
-      let comm_mgr = builder.finish(device_event_sender.clone());
+      // Each manager gets its own channel, so events can be tagged with the metrics of the manager
+      // that sent them on the way to the event loop.
+      let (comm_mgr_sender, mut comm_mgr_receiver) = mpsc::channel(256);
+      let comm_mgr = builder.finish(comm_mgr_sender);
 
       if comm_managers
         .iter()
@@ -177,12 +390,33 @@ impl ServerDeviceManagerBuilder {
         );
       }
 
+      let metrics = Arc::new(CommManagerMetrics::new(
+        comm_mgr.name(),
+        checked_status(comm_mgr.as_ref()),
+      ));
+      let metrics_clone = metrics.clone();
+      let device_event_sender_clone = device_event_sender.clone();
+      async_manager::spawn(async move {
+        while let Some(event) = comm_mgr_receiver.recv().await {
+          if matches!(event, HardwareCommunicationManagerEvent::DeviceFound { .. }) {
+            metrics_clone.record_advertisement();
+          }
+          if device_event_sender_clone
+            .send((metrics_clone.clone(), event))
+            .await
+            .is_err()
+          {
+            break;
+          }
+        }
+      });
+      comm_manager_metrics.push(metrics);
       comm_managers.push(comm_mgr);
     }
 
     let mut colliding_dcms = vec![];
     for mgr in comm_managers.iter() {
-      info!("{}: {}", mgr.name(), mgr.can_scan());
+      info!("{}: {:?}", mgr.name(), mgr.status());
       // Hack: Lovense and Bluetooth dongles will fight with each other over devices, possibly
       // interrupting each other connecting and causing very weird issues for users. Print a
       // warning message to logs if more than one is active and available to scan.
@@ -193,7 +427,7 @@ impl ServerDeviceManagerBuilder {
       ]
       .iter()
       .any(|x| x == &mgr.name())
-        && mgr.can_scan()
+        && mgr.status().can_scan()
       {
         colliding_dcms.push(mgr.name().to_owned());
       }
@@ -208,50 +442,187 @@ impl ServerDeviceManagerBuilder {
     let output_sender = broadcast::channel(255).0;
 
     let mut event_loop = ServerDeviceManagerEventLoop::new(
-      comm_managers,
-      config_mgr,
+      comm_managers
+        .into_iter()
+        .zip(comm_manager_metrics.clone())
+        .collect(),
+      config_mgr.clone(),
       devices.clone(),
       loop_cancellation_token.child_token(),
       output_sender.clone(),
       device_event_receiver,
       device_command_receiver,
     );
+    let device_event_queue_metrics = event_loop.device_event_queue_metrics();
+    let scanning = event_loop.scanning_started();
+    let unsupported_device_sender = event_loop.unsupported_device_sender();
+    let unsupported_devices = event_loop.reported_unsupported_devices();
+    let overuse_warning_sender = event_loop.overuse_warning_sender();
+    let client_locale = event_loop.client_locale();
+    let battery_saver = event_loop.battery_saver();
+    let merged_devices = event_loop.merged_devices();
+    let split_devices = event_loop.split_devices();
+    if let Some(window) = self.reconnect_window {
+      event_loop.set_reconnect_window(window);
+    }
+    if let Some(hook) = &self.connection_hook {
+      event_loop.set_connection_hook(hook.clone());
+    }
+    #[cfg(feature = "scripting")]
+    if let Some(script) = &self.command_script {
+      event_loop.set_command_script(script.clone());
+    }
     async_manager::spawn(async move {
       event_loop.run().await;
     });
     Ok(ServerDeviceManager {
+      device_config_manager: config_mgr,
       devices,
+      device_list_page_size: self
+        .device_list_page_size
+        .unwrap_or(DEFAULT_DEVICE_LIST_PAGE_SIZE),
       device_command_sender,
+      device_event_queue_metrics,
+      comm_manager_metrics,
       loop_cancellation_token,
       running: Arc::new(AtomicBool::new(true)),
+      scanning,
       output_sender,
+      unsupported_device_sender,
+      unsupported_devices,
+      overuse_warning_sender,
+      client_locale,
+      battery_saver,
+      pattern_playback: PatternPlayback::default(),
+      merged_devices,
+      split_devices,
+      #[cfg(feature = "scripting")]
+      command_script: self.command_script.clone(),
     })
   }
 }
 
 pub struct ServerDeviceManager {
+  device_config_manager: Arc<DeviceConfigurationManager>,
   devices: Arc<DashMap<u32, Arc<ServerDevice>>>,
-  device_command_sender: mpsc::Sender<DeviceManagerCommand>,
+  device_list_page_size: u32,
+  device_command_sender: LaneQueueSender<DeviceManagerCommand>,
+  device_event_queue_metrics: Arc<QueueMetrics>,
+  /// Scan and connection counters for each communication manager, in the order they were added.
+  comm_manager_metrics: Vec<Arc<CommManagerMetrics>>,
   loop_cancellation_token: CancellationToken,
   running: Arc<AtomicBool>,
-  output_sender: broadcast::Sender<ButtplugServerMessage>,
+  /// True from when scanning starts until ScanningFinished is sent.
+  scanning: Arc<AtomicBool>,
+  output_sender: broadcast::Sender<Arc<ButtplugServerMessage>>,
+  unsupported_device_sender: broadcast::Sender<UnsupportedDeviceInfo>,
+  /// Hardware reported on [unsupported_device_sender](Self::unsupported_device_sender) since
+  /// scanning last started, keyed by address.
+  unsupported_devices: Arc<DashMap<String, UnsupportedDeviceInfo>>,
+  overuse_warning_sender: broadcast::Sender<OveruseWarning>,
+  /// Language tag the connected client asked for device names and descriptions in, if any.
+  client_locale: Arc<RwLock<Option<String>>>,
+  /// Battery saver settings, shared with every device.
+  battery_saver: Arc<BatterySaver>,
+  /// Patterns from PatternCmd messages, played here so they can be ended by stop commands.
+  pattern_playback: PatternPlayback,
+  /// Devices presented to clients as one. Listed and unlisted by the event loop as their members
+  /// come and go.
+  merged_devices: Arc<MergedDevices>,
+  /// Features of devices presented to clients as devices of their own. Listed and unlisted by the
+  /// event loop as the devices come and go.
+  split_devices: Arc<SplitDevices>,
+  #[cfg(feature = "scripting")]
+  command_script: Option<Arc<CommandScript>>,
 }
 
 impl ServerDeviceManager {
-  pub fn event_stream(&self) -> impl Stream<Item = ButtplugServerMessage> {
+  pub fn device_configuration_manager(&self) -> Arc<DeviceConfigurationManager> {
+    self.device_config_manager.clone()
+  }
+
+  /// Adds the protocol communication specifiers and attributes in a device configuration file to
+  /// the ones the server already knows about, so support for new devices can be added without
+  /// restarting. The file needs the same major version as the one built into the library. Devices
+  /// found after this use the new configurations; devices already connected aren't affected. See
+  /// [DeviceConfigurationManager::add_protocol_configurations].
+  pub fn reload_configuration(&self, json: &str) -> Result<(), ButtplugDeviceError> {
+    let builder = load_protocol_configs(Some(json.to_owned()), None, false)?;
+    self
+      .device_config_manager
+      .add_protocol_configurations(&builder)
+  }
+
+  /// Maximum number of devices returned in reply to a paged device list request.
+  pub fn device_list_page_size(&self) -> u32 {
+    self.device_list_page_size
+  }
+
+  /// Depth and backpressure counters for the queue carrying scanning commands to the device manager
+  /// event loop.
+  pub fn device_command_queue_metrics(&self) -> Arc<QueueMetrics> {
+    self.device_command_sender.metrics()
+  }
+
+  /// Depth and backpressure counters for the queue carrying device connections, disconnections and
+  /// notifications to the device manager event loop.
+  pub fn device_event_queue_metrics(&self) -> Arc<QueueMetrics> {
+    self.device_event_queue_metrics.clone()
+  }
+
+  /// Scan and connection counters for each communication manager, in the order they were added.
+  pub fn comm_manager_metrics(&self) -> Vec<Arc<CommManagerMetrics>> {
+    self.comm_manager_metrics.clone()
+  }
+
+  pub fn event_stream(&self) -> impl Stream<Item = Arc<ButtplugServerMessage>> {
     // Unlike the client API, we can expect anyone using the server to pin this
     // themselves.
-    convert_broadcast_receiver_to_stream(self.output_sender.subscribe())
+    //
+    // Sensor readings are conflated, so consumers that can't keep up with high rate sensors (i.e.
+    // clients on slow connections) get the latest reading for each sensor instead of a backlog.
+    convert_broadcast_receiver_to_conflated_stream(self.output_sender.subscribe(), |msg| {
+      if let ButtplugServerMessage::SensorReading(reading) = msg.as_ref() {
+        Some((
+          reading.device_index(),
+          reading.sensor_index(),
+          reading.sensor_type(),
+        ))
+      } else {
+        None
+      }
+    })
   }
 
-  fn start_scanning(&self) -> ButtplugServerResultFuture {
+  /// Stream of hardware found while scanning that no protocol matched. Each piece of hardware is
+  /// reported once per scan. Nothing is sent to clients, this is for applications hosting the
+  /// server.
+  pub fn unsupported_device_stream(&self) -> impl Stream<Item = UnsupportedDeviceInfo> {
+    convert_broadcast_receiver_to_stream(self.unsupported_device_sender.subscribe())
+  }
+
+  /// Stream of devices stepped down by overuse protection, after running hard for longer than their
+  /// protocol allows. Nothing is sent to clients, this is for applications hosting the server.
+  pub fn overuse_warning_stream(&self) -> impl Stream<Item = OveruseWarning> {
+    convert_broadcast_receiver_to_stream(self.overuse_warning_sender.subscribe())
+  }
+
+  /// Hardware reported on the [unsupported device stream](Self::unsupported_device_stream) since
+  /// scanning last started, sorted by address.
+  pub fn unsupported_devices(&self) -> Vec<UnsupportedDeviceInfo> {
+    let mut devices: Vec<UnsupportedDeviceInfo> = self
+      .unsupported_devices
+      .iter()
+      .map(|device| device.value().clone())
+      .collect();
+    devices.sort_by(|a, b| a.address().cmp(b.address()));
+    devices
+  }
+
+  fn send_scanning_command(&self, command: DeviceManagerCommand) -> ButtplugServerResultFuture {
     let command_sender = self.device_command_sender.clone();
     async move {
-      if command_sender
-        .send(DeviceManagerCommand::StartScanning)
-        .await
-        .is_err()
-      {
+      if command_sender.send(command).await.is_err() {
         // TODO Fill in error.
       }
       Ok(message::Ok::default().into())
@@ -259,33 +630,87 @@ impl ServerDeviceManager {
     .boxed()
   }
 
+  fn start_scanning(&self) -> ButtplugServerResultFuture {
+    self.send_scanning_command(DeviceManagerCommand::StartScanning)
+  }
+
   fn stop_scanning(&self) -> ButtplugServerResultFuture {
-    let command_sender = self.device_command_sender.clone();
+    self.send_scanning_command(DeviceManagerCommand::StopScanning)
+  }
+
+  /// Scans for a single device, letting comm managers narrow their search to it (address filters
+  /// for bluetooth, a single port for serial, etc). Anything else found while scanning is ignored.
+  /// Scanning stops once the device connects, otherwise it finishes like any other scan.
+  ///
+  /// Does nothing if a scan is already running.
+  pub fn start_targeted_scanning(
+    &self,
+    identifier: &ServerDeviceIdentifier,
+  ) -> ButtplugServerResultFuture {
+    self.send_scanning_command(DeviceManagerCommand::StartScanningFor(identifier.clone()))
+  }
+
+  /// Stops every connected device. Devices that fail to stop are retried a few times, and any that
+  /// are still failing after that are listed in a [ButtplugDeviceError::DevicesFailedToStop] error.
+  /// E-stim devices are disarmed once the stops are done, whether or not they worked.
+  pub(crate) fn stop_all_devices(&self) -> ButtplugServerResultFuture {
+    // Otherwise the next keyframe would start devices right back up.
+    self.pattern_playback.stop_all();
+    let device_map = self.devices.clone();
     async move {
-      if command_sender
-        .send(DeviceManagerCommand::StopScanning)
-        .await
-        .is_err()
-      {
-        // TODO Fill in error.
+      let indexes: Vec<u32> = device_map.iter().map(|dev| *dev.key()).collect();
+      let mut failures = stop_devices(&device_map, &indexes).await;
+      for attempt in 1..=STOP_ALL_DEVICES_RETRIES {
+        if failures.is_empty() {
+          break;
+        }
+        warn!(
+          "{} device(s) failed to stop, retrying (attempt {} of {}): {}",
+          failures.len(),
+          attempt,
+          STOP_ALL_DEVICES_RETRIES,
+          DeviceStopFailures(failures.clone())
+        );
+        sleep(STOP_ALL_DEVICES_RETRY_DELAY).await;
+        let indexes: Vec<u32> = failures.iter().map(|f| f.device_index()).collect();
+        failures = stop_devices(&device_map, &indexes).await;
+      }
+      // Disarming waits for the stops, as cool downs and stops have to get through the interlock.
+      for device in device_map.iter() {
+        device.value().disarm();
+      }
+      if failures.is_empty() {
+        Ok(message::Ok::default().into())
+      } else {
+        let failures = DeviceStopFailures(failures);
+        error!("Devices failed to stop: {}", failures);
+        Err(ButtplugDeviceError::DevicesFailedToStop(failures).into())
       }
-      Ok(message::Ok::default().into())
     }
     .boxed()
   }
 
-  pub(crate) fn stop_all_devices(&self) -> ButtplugServerResultFuture {
-    let device_map = self.devices.clone();
-    // TODO This could use some error reporting.
+  /// Stops and disconnects every connected device, releasing the hardware so other programs can use
+  /// it, while leaving the manager running. Scanning is stopped first, otherwise released devices
+  /// could be picked up again right away. Devices are removed (and DeviceRemoved sent) as their
+  /// disconnections come in, and can be found again by a later scan.
+  pub fn disconnect_all_devices(&self) -> ButtplugServerResultFuture {
+    let devices = self.devices.clone();
+    let stop_scanning = self.stop_scanning();
+    let stop_devices = self.stop_all_devices();
     async move {
-      let fut_vec: Vec<_> = device_map
+      let _ = stop_scanning.await;
+      // Devices that fail to stop are still disconnected, which is as stopped as they can get.
+      if let Err(err) = stop_devices.await {
+        warn!("Disconnecting devices that could not be stopped: {}", err);
+      }
+      let fut_vec: Vec<_> = devices
         .iter()
-        .map(|dev| {
-          let device = dev.value();
-          device.parse_message(message::StopDeviceCmd::new(1).into())
-        })
+        .map(|device| device.value().disconnect())
         .collect();
-      future::join_all(fut_vec).await;
+      for result in future::join_all(fut_vec).await {
+        result?;
+      }
       Ok(message::Ok::default().into())
     }
     .boxed()
@@ -295,13 +720,44 @@ impl ServerDeviceManager {
     &self,
     device_msg: ButtplugDeviceCommandMessageUnion,
   ) -> ButtplugServerResultFuture {
+    #[cfg(feature = "scripting")]
+    if let (Some(script), ButtplugDeviceCommandMessageUnion::ScalarCmd(msg)) =
+      (&self.command_script, &device_msg)
+    {
+      match script.on_command(msg) {
+        Ok(Some(commands)) => {
+          let fut = send_script_commands(&self.devices, commands);
+          return async move {
+            fut.await?;
+            Ok(message::Ok::default().into())
+          }
+          .boxed();
+        }
+        Ok(None) => {}
+        Err(err) => {
+          error!("Command script failed: {}", err);
+          return err.into();
+        }
+      }
+    }
+    if let ButtplugDeviceCommandMessageUnion::StopDeviceCmd(msg) = &device_msg {
+      self.pattern_playback.stop(msg.device_index());
+    }
     match self.devices.get(&device_msg.device_index()) {
       Some(device) => {
         let fut = device.parse_message(device_msg);
         // Create a future to run the message through the device, then handle adding the id to the result.
         async move { fut.await }.boxed()
       }
-      None => ButtplugDeviceError::DeviceNotAvailable(device_msg.device_index()).into(),
+      None => {
+        let device_index = device_msg.device_index();
+        if self.split_devices.is_listed(device_index) {
+          self.split_devices.parse_message(&self.devices, device_msg)
+        } else {
+          self.merged_devices.parse_message(&self.devices, device_msg)
+        }
+        .unwrap_or_else(|| ButtplugDeviceError::DeviceNotAvailable(device_index).into())
+      }
     }
   }
 
@@ -311,27 +767,89 @@ impl ServerDeviceManager {
   ) -> ButtplugServerResultFuture {
     match manager_msg {
       ButtplugDeviceManagerMessageUnion::RequestDeviceList(msg) => {
-        let devices = self
+        let locale = self.client_locale();
+        let mut devices: Vec<DeviceMessageInfo> = self
           .devices
           .iter()
-          .map(|device| {
-            let dev = device.value();
-            DeviceMessageInfo::new(
-              *device.key(),
-              &dev.name(),
-              &dev.display_name(),
-              &None,
-              dev.message_attributes().into(),
-            )
-          })
+          .map(|device| device_message_info(*device.key(), device.value(), locale.as_deref()))
+          .chain(self.merged_devices.device_message_infos(&self.devices))
+          .chain(self.split_devices.device_message_infos(&self.devices))
+          .filter(|device| device.device_index() >= msg.start_device_index().unwrap_or(0))
           .collect();
-        let mut device_list = DeviceList::new(devices);
+        let mut device_list = if msg.start_device_index().is_some() {
+          // Paged requests walk the list in index order, so the next page can pick up from the
+          // first device we didn't send, even if devices are added or removed in between.
+          devices.sort_by_key(|device| device.device_index());
+          let page_size = self.device_list_page_size as usize;
+          let next_device_index = devices.get(page_size).map(|device| device.device_index());
+          devices.truncate(page_size);
+          DeviceList::new_page(devices, next_device_index)
+        } else {
+          DeviceList::new(devices)
+        };
         device_list.set_id(msg.id());
         future::ready(Ok(device_list.into())).boxed()
       }
+      ButtplugDeviceManagerMessageUnion::RequestDeviceAttributes(msg) => {
+        let device_index = msg.device_index();
+        match self.devices.get(&device_index) {
+          Some(device) => {
+            let locale = self.client_locale();
+            let mut attributes = DeviceAttributes::new(device_message_info(
+              device_index,
+              device.value(),
+              locale.as_deref(),
+            ));
+            attributes.set_id(msg.id());
+            future::ready(Ok(attributes.into())).boxed()
+          }
+          None => match self
+            .merged_devices
+            .device_message_info(device_index, &self.devices)
+            .or_else(|| {
+              self
+                .split_devices
+                .device_message_info(device_index, &self.devices)
+            }) {
+            Some(info) => {
+              let mut attributes = DeviceAttributes::new(info);
+              attributes.set_id(msg.id());
+              future::ready(Ok(attributes.into())).boxed()
+            }
+            None => ButtplugDeviceError::DeviceNotAvailable(device_index).into(),
+          },
+        }
+      }
       ButtplugDeviceManagerMessageUnion::StopAllDevices(_) => self.stop_all_devices(),
+      ButtplugDeviceManagerMessageUnion::DisconnectAllDevices(_) => self.disconnect_all_devices(),
       ButtplugDeviceManagerMessageUnion::StartScanning(_) => self.start_scanning(),
+      ButtplugDeviceManagerMessageUnion::StartTargetedScanning(msg) => {
+        let device_index = msg.device_index();
+        if self.devices.contains_key(&device_index) {
+          // Already here, nothing to look for.
+          return future::ready(Ok(message::Ok::default().into())).boxed();
+        }
+        // Indexes stay reserved for the devices they were given to, so this finds whatever was
+        // last connected at the index.
+        match self.device_config_manager.device_identifier(device_index) {
+          Some(identifier) => self.start_targeted_scanning(&identifier),
+          None => ButtplugDeviceError::DeviceNotAvailable(device_index).into(),
+        }
+      }
       ButtplugDeviceManagerMessageUnion::StopScanning(_) => self.stop_scanning(),
+      ButtplugDeviceManagerMessageUnion::PatternCmd(msg) => {
+        let device_index = msg.device_index();
+        match self.devices.get(&device_index) {
+          Some(device) => {
+            let result = self
+              .pattern_playback
+              .play(device.value().clone(), msg)
+              .map(|_| message::Ok::default().into());
+            future::ready(result).boxed()
+          }
+          None => ButtplugDeviceError::DeviceNotAvailable(device_index).into(),
+        }
+      }
     }
   }
 
@@ -350,6 +868,55 @@ impl ServerDeviceManager {
     }
   }
 
+  /// Language tag the connected client asked for device names and descriptions in, if any.
+  pub fn client_locale(&self) -> Option<String> {
+    self
+      .client_locale
+      .read()
+      .expect("Locale lock should never be poisoned.")
+      .clone()
+  }
+
+  // Set on handshake by the ButtplugServer that owns this manager, as that's where clients say
+  // which language they want.
+  pub(crate) fn set_client_locale(&self, locale: Option<String>) {
+    *self
+      .client_locale
+      .write()
+      .expect("Locale lock should never be poisoned.") = locale;
+  }
+
+  /// Current battery saver settings, or None if the battery saver is off.
+  pub fn battery_saver(&self) -> Option<BatterySaverSettings> {
+    self.battery_saver.settings()
+  }
+
+  /// Turns on the battery saver for all devices, changes its settings, or turns it off with None.
+  /// Output devices are already running at is rescaled right away, and any duty cycle starts over
+  /// with a running window. See [BatterySaverSettings].
+  pub fn set_battery_saver(
+    &self,
+    settings: Option<BatterySaverSettings>,
+  ) -> Result<(), ButtplugDeviceError> {
+    self.battery_saver.set(settings, self.devices.clone())
+  }
+
+  /// Returns true if the device manager is currently scanning for devices.
+  pub fn scanning(&self) -> bool {
+    self.scanning.load(Ordering::SeqCst)
+  }
+
+  /// Returns the current state of all connected devices, sorted by device index.
+  pub fn device_state_snapshots(&self) -> Vec<DeviceStateSnapshot> {
+    let mut devices: Vec<DeviceStateSnapshot> = self
+      .devices
+      .iter()
+      .map(|device| DeviceStateSnapshot::new(*device.key(), device.value()))
+      .collect();
+    devices.sort_by_key(|device| device.device_index());
+    devices
+  }
+
   pub fn device_info(&self, index: u32) -> Option<ServerDeviceInfo> {
     self.devices.get(&index).map(|device| ServerDeviceInfo {
       identifier: device.value().identifier().clone(),
@@ -357,27 +924,134 @@ impl ServerDeviceManager {
     })
   }
 
+  /// Changes the display name of the device at the given index, or removes it with None, and
+  /// sends clients the new attributes. The name is kept for when the device reconnects, and saved
+  /// if the device configuration is persisted. See
+  /// [DeviceConfigurationManager::set_display_name].
+  pub fn set_device_display_name(
+    &self,
+    index: u32,
+    display_name: Option<String>,
+  ) -> Result<(), ButtplugDeviceError> {
+    let device = self
+      .devices
+      .get(&index)
+      .ok_or(ButtplugDeviceError::DeviceNotAvailable(index))?;
+    self
+      .device_config_manager
+      .set_display_name(device.value().identifier(), display_name.clone())?;
+    device.value().set_display_name(display_name);
+    self.send_device_attributes(index, device.value());
+    Ok(())
+  }
+
+  /// Limits the step range of a feature of the device at the given index, and sends clients the new
+  /// attributes. See [ServerDevice::set_step_limit].
+  pub fn set_device_step_limit(
+    &self,
+    index: u32,
+    message_type: ButtplugDeviceMessageType,
+    feature_index: u32,
+    step_range: RangeInclusive<u32>,
+  ) -> Result<(), ButtplugDeviceError> {
+    let device = self
+      .devices
+      .get(&index)
+      .ok_or(ButtplugDeviceError::DeviceNotAvailable(index))?;
+    device
+      .value()
+      .set_step_limit(message_type, feature_index, step_range)?;
+    self.send_device_attributes(index, device.value());
+    Ok(())
+  }
+
+  /// Presents the devices with the given identifiers to clients as a single device with the given
+  /// name, whenever all of them are connected. Commands for its output features are split up and
+  /// sent to the members, which stay listed on their own too. Merged devices can also be set up in
+  /// the user configuration.
+  pub fn merge_devices(
+    &self,
+    name: &str,
+    members: &[ServerDeviceIdentifier],
+  ) -> Result<(), ButtplugDeviceError> {
+    self.merged_devices.add(name, members)?;
+    self.update_merged_devices();
+    Ok(())
+  }
+
+  /// Stops presenting a merged device set up by [Self::merge_devices] or the user configuration.
+  pub fn unmerge_devices(&self, name: &str) -> Result<(), ButtplugDeviceError> {
+    if !self.merged_devices.remove(name) {
+      return Err(ButtplugDeviceError::DeviceConfigurationError(format!(
+        "No merged device named {}",
+        name
+      )));
+    }
+    self.update_merged_devices();
+    Ok(())
+  }
+
+  fn update_merged_devices(&self) {
+    for message in self
+      .merged_devices
+      .update(&self.devices, &self.device_config_manager)
+    {
+      // Send only fails if there are no listeners, in which case there's nobody to tell.
+      let _ = self.output_sender.send(Arc::new(message));
+    }
+  }
+
+  fn send_device_attributes(&self, index: u32, device: &ServerDevice) {
+    let locale = self.client_locale();
+    let info = device_message_info(index, device, locale.as_deref());
+    // Send only fails if there are no listeners, in which case there's nobody to tell.
+    let _ = self
+      .output_sender
+      .send(Arc::new(DeviceAttributes::new(info).into()));
+  }
+
+  /// Captures a zero offset for a sensor of the device at the given index. See
+  /// [ServerDevice::capture_sensor_baseline].
+  pub fn capture_sensor_baseline(
+    &self,
+    index: u32,
+    sensor_index: u32,
+    sensor_type: SensorType,
+    samples: u32,
+    progress: ProgressReporter,
+  ) -> BoxFuture<'static, Result<SensorCalibration, ButtplugDeviceError>> {
+    if let Some(device) = self.devices.get(&index) {
+      device
+        .value()
+        .capture_sensor_baseline(sensor_index, sensor_type, samples, progress)
+    } else {
+      future::ready(Err(ButtplugDeviceError::DeviceNotAvailable(index))).boxed()
+    }
+  }
+
+  /// Returns the btleplug peripheral handle for the device at the given index, if it exists and is
+  /// a bluetooth device. **Unstable**, see `Hardware::btleplug_peripheral`.
+  #[cfg(feature = "unstable-btleplug-peripheral")]
+  pub fn btleplug_peripheral(&self, index: u32) -> Option<btleplug::platform::Peripheral> {
+    self
+      .devices
+      .get(&index)
+      .and_then(|device| device.value().btleplug_peripheral())
+  }
+
   // Only a ButtplugServer should be able to call this. We don't want to expose this capability to
   // the outside world. Note that this could cause issues for lifetimes if someone holds this longer
   // than the lifetime of the server that originally created it. Ideally we should lock the Server
   // Device Manager lifetime to the owning ButtplugServer lifetime to ensure that doesn't happen,
   // but that's going to be complicated.
   pub(crate) fn shutdown(&self) -> ButtplugServerResultFuture {
-    let devices = self.devices.clone();
     // Make sure that, once our owning server shuts us down, no one outside can use this manager
     // again. Otherwise we can have all sorts of ownership weirdness.
     self.running.store(false, Ordering::SeqCst);
-    let stop_scanning = self.stop_scanning();
-    let stop_devices = self.stop_all_devices();
+    let disconnect_devices = self.disconnect_all_devices();
     let token = self.loop_cancellation_token.clone();
     async move {
-      // Force stop scanning, otherwise we can disconnect and instantly try to reconnect while
-      // cleaning up if we're still scanning.
-      let _ = stop_scanning.await;
-      let _ = stop_devices.await;
-      for device in devices.iter() {
-        device.value().disconnect().await?;
-      }
+      disconnect_devices.await?;
       token.cancel();
       Ok(message::Ok::default().into())
     }
@@ -385,6 +1059,35 @@ impl ServerDeviceManager {
   }
 }
 
+/// Sends a stop command to each device index given, returning the ones that failed. Devices that
+/// disconnected in the meantime are no longer running, so they aren't counted as failures.
+async fn stop_devices(
+  device_map: &DashMap<u32, Arc<ServerDevice>>,
+  indexes: &[u32],
+) -> Vec<DeviceStopFailure> {
+  let fut_vec: Vec<_> = indexes
+    .iter()
+    .filter_map(|index| {
+      device_map.get(index).map(|device| {
+        let fut = device
+          .value()
+          .parse_message(message::StopDeviceCmd::new(*index).into());
+        let index = *index;
+        async move { (index, fut.await) }
+      })
+    })
+    .collect();
+  future::join_all(fut_vec)
+    .await
+    .into_iter()
+    .filter_map(|(index, result)| {
+      result
+        .err()
+        .map(|err| DeviceStopFailure::new(index, &err.to_string()))
+    })
+    .collect()
+}
+
 impl Drop for ServerDeviceManager {
   fn drop(&mut self) {
     info!("Dropping device manager!");
@@ -6,106 +6,451 @@
 // for full license information.
 
 use crate::{
-  core::message::{ButtplugServerMessage, DeviceAdded, DeviceRemoved, ScanningFinished},
+  core::{
+    errors::{ButtplugDeviceError, ButtplugError},
+    message::{
+      self,
+      ButtplugDeviceMessage,
+      ButtplugServerDeviceMessage,
+      ButtplugServerMessage,
+      DeviceAdded,
+      DeviceRemoved,
+      ScalarCmd,
+      ScalarSubcommand,
+      ScanningFinished,
+      SensorSubscribeCmd,
+    },
+  },
   server::device::{
+    battery_saver::BatterySaver,
+    comm_manager_metrics::CommManagerMetrics,
     configuration::DeviceConfigurationManager,
-    hardware::communication::{HardwareCommunicationManager, HardwareCommunicationManagerEvent},
+    hardware::communication::{
+      checked_status,
+      HardwareCommunicationManager,
+      HardwareCommunicationManagerEvent,
+      HardwareCommunicationManagerStatus,
+    },
     server_device::build_server_device,
     ServerDevice,
     ServerDeviceEvent,
+    ServerDeviceIdentifier,
   },
-  util::async_manager,
+  util::{address_privacy::display_address, async_manager},
 };
 use dashmap::{DashMap, DashSet};
 use futures::{future, FutureExt, StreamExt};
-use std::sync::Arc;
+use std::{
+  collections::HashMap,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+    RwLock,
+  },
+  time::{Duration, Instant},
+};
 use tokio::sync::{broadcast, mpsc};
 use tokio_util::sync::CancellationToken;
 use tracing;
 use tracing_futures::Instrument;
 
-use super::server_device_manager::DeviceManagerCommand;
+#[cfg(feature = "scripting")]
+use super::command_script::{send_script_commands, CommandScript};
+#[cfg(feature = "scripting")]
+use crate::core::message::SensorReading;
+
+use super::{
+  connection_hook::{ConnectingDevice, DeviceConnectionDecision, DeviceConnectionHook},
+  merged_device::MergedDevices,
+  overuse_protection::OveruseWarning,
+  sensor_rules::SensorRules,
+  server_device_manager::{
+    DeviceManagerCommand,
+    UnsupportedDeviceInfo,
+    DEVICE_MANAGER_QUEUE_CAPACITY,
+  },
+  split_device::SplitDevices,
+  server_device_manager_event_queue::{
+    lane_queue,
+    LaneQueueReceiver,
+    LaneQueueSender,
+    QueueMetrics,
+  },
+};
+
+/// Number of near-miss protocols listed for hardware that matched no protocol.
+const UNSUPPORTED_DEVICE_SUGGESTION_COUNT: usize = 3;
+
+/// Hardware communication manager event, along with the metrics of the manager that sent it.
+pub(super) type TaggedCommManagerEvent =
+  (Arc<CommManagerMetrics>, HardwareCommunicationManagerEvent);
+
+/// Waits until `deadline`, or forever if there isn't one.
+async fn wait_until(deadline: Option<Instant>) {
+  match deadline {
+    Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+    None => future::pending().await,
+  }
+}
+
+/// Asks the host's hook what to do with a newly connected device, applying any display name it
+/// gives. Returns false, after disconnecting the device, if the hook turned it away.
+async fn apply_connection_hook(hook: &dyn DeviceConnectionHook, device: &ServerDevice) -> bool {
+  let connecting =
+    ConnectingDevice::new(&device.name(), device.identifier(), device.display_name());
+  match hook.device_connecting(connecting).await {
+    DeviceConnectionDecision::Accept => true,
+    DeviceConnectionDecision::AcceptWithDisplayName(display_name) => {
+      device.set_display_name(Some(display_name));
+      true
+    }
+    DeviceConnectionDecision::Reject => {
+      info!("Device rejected by connection hook, disconnecting.");
+      if let Err(err) = device.disconnect().await {
+        error!("Error disconnecting rejected device: {:?}", err);
+      }
+      false
+    }
+  }
+}
 
 pub(super) struct ServerDeviceManagerEventLoop {
   comm_managers: Vec<Box<dyn HardwareCommunicationManager>>,
+  /// Metrics for each comm manager, in the same order as comm_managers. Statuses are kept up to
+  /// date here so they can be read from outside the loop.
+  comm_manager_metrics: Vec<Arc<CommManagerMetrics>>,
   device_config_manager: Arc<DeviceConfigurationManager>,
-  device_command_receiver: mpsc::Receiver<DeviceManagerCommand>,
+  device_command_receiver: LaneQueueReceiver<DeviceManagerCommand>,
   /// Maps device index (exposed to the outside world) to actual device objects held by the server.
   device_map: Arc<DashMap<u32, Arc<ServerDevice>>>,
   /// Broadcaster that relays device events in the form of Buttplug Messages to
   /// whoever owns the Buttplug Server.
-  server_sender: broadcast::Sender<ButtplugServerMessage>,
+  server_sender: broadcast::Sender<Arc<ButtplugServerMessage>>,
+  /// Broadcaster for hardware that no protocol matched, for applications hosting the server.
+  unsupported_device_sender: broadcast::Sender<UnsupportedDeviceInfo>,
+  /// Hardware reported as unsupported since scanning last started, keyed by address, so repeated
+  /// advertisements or rescans don't report the same hardware over and over. Shared with the device
+  /// manager for state snapshots.
+  reported_unsupported_devices: Arc<DashMap<String, UnsupportedDeviceInfo>>,
+  /// Broadcaster for devices stepped down by overuse protection, for applications hosting the
+  /// server.
+  overuse_warning_sender: broadcast::Sender<OveruseWarning>,
   /// As the device manager owns the Device Communication Managers, it will have
-  /// a receiver that the comm managers all send thru.
-  device_comm_receiver: mpsc::Receiver<HardwareCommunicationManagerEvent>,
-  /// Sender for device events, passed to new devices when they are created.
-  device_event_sender: mpsc::Sender<ServerDeviceEvent>,
+  /// a receiver that the comm managers all send thru. Events are tagged with the metrics of the
+  /// comm manager that sent them.
+  device_comm_receiver: mpsc::Receiver<TaggedCommManagerEvent>,
+  /// Sender for device events, passed to new devices when they are created. Senders wait for room
+  /// when the queue is full, nothing is dropped.
+  device_event_sender: LaneQueueSender<ServerDeviceEvent>,
   /// Receiver for device events, which the event loops to handle events.
-  device_event_receiver: mpsc::Receiver<ServerDeviceEvent>,
+  device_event_receiver: LaneQueueReceiver<ServerDeviceEvent>,
   /// True if StartScanning has been called but no ScanningFinished has been
   /// emitted yet.
   scanning_bringup_in_progress: bool,
-  /// Denote whether scanning has been started since we last sent a ScanningFinished message.
-  scanning_started: bool,
+  /// Denote whether scanning has been started since we last sent a ScanningFinished message. Shared
+  /// with the device manager, so scanning status can be checked from outside the loop.
+  scanning_started: Arc<AtomicBool>,
+  /// Device the current scan is looking for, if it was started to find a single device. Anything
+  /// else found while it's set is ignored.
+  scan_target: Option<ServerDeviceIdentifier>,
+  /// How long to look for devices that disconnected without being asked to, if at all.
+  reconnect_window: Option<Duration>,
+  /// Devices being looked for to reconnect, along with when to give up on them.
+  reconnect_candidates: HashMap<ServerDeviceIdentifier, Instant>,
+  /// True if the current scan was started to reconnect devices rather than by a client. These scans
+  /// ignore anything that isn't being reconnected, and don't send ScanningFinished.
+  reconnect_scan: bool,
+  /// Host hook asked about each device before it's added, if any.
+  connection_hook: Option<Arc<dyn DeviceConnectionHook>>,
+  /// Devices presented to clients as one, listed and unlisted as their members come and go. Shared
+  /// with the device manager, which sends them commands.
+  merged_devices: Arc<MergedDevices>,
+  /// Features of devices presented to clients as devices of their own, listed and unlisted as the
+  /// devices come and go. Shared with the device manager, which sends them commands.
+  split_devices: Arc<SplitDevices>,
   /// Devices currently trying to connect.
   connecting_devices: Arc<DashSet<String>>,
+  /// Language tag the connected client asked for device names and descriptions in, if any. Shared
+  /// with the device manager, which sets it on handshake.
+  client_locale: Arc<RwLock<Option<String>>>,
+  /// Battery saver settings, given to every device as it's created. Shared with the device
+  /// manager, which changes them.
+  battery_saver: Arc<BatterySaver>,
+  /// User script to run sensor readings through, if any.
+  #[cfg(feature = "scripting")]
+  command_script: Option<Arc<CommandScript>>,
+  /// Rules driving actuators from sensor readings.
+  sensor_rules: SensorRules,
   /// Cancellation token for the event loop
   loop_cancellation_token: CancellationToken,
 }
 
 impl ServerDeviceManagerEventLoop {
+  /// Comm managers are passed along with their metrics.
   pub fn new(
-    comm_managers: Vec<Box<dyn HardwareCommunicationManager>>,
-    device_config_manager: DeviceConfigurationManager,
+    comm_managers: Vec<(Box<dyn HardwareCommunicationManager>, Arc<CommManagerMetrics>)>,
+    device_config_manager: Arc<DeviceConfigurationManager>,
     device_map: Arc<DashMap<u32, Arc<ServerDevice>>>,
     loop_cancellation_token: CancellationToken,
-    server_sender: broadcast::Sender<ButtplugServerMessage>,
-    device_comm_receiver: mpsc::Receiver<HardwareCommunicationManagerEvent>,
-    device_command_receiver: mpsc::Receiver<DeviceManagerCommand>,
+    server_sender: broadcast::Sender<Arc<ButtplugServerMessage>>,
+    device_comm_receiver: mpsc::Receiver<TaggedCommManagerEvent>,
+    device_command_receiver: LaneQueueReceiver<DeviceManagerCommand>,
   ) -> Self {
-    let (device_event_sender, device_event_receiver) = mpsc::channel(256);
+    let (device_event_sender, device_event_receiver) =
+      lane_queue(DEVICE_MANAGER_QUEUE_CAPACITY);
+    let (comm_managers, comm_manager_metrics) = comm_managers.into_iter().unzip();
+    let merged_devices = Arc::new(MergedDevices::new(device_config_manager.merged_devices()));
+    let sensor_rules = SensorRules::new(device_config_manager.sensor_rules());
     Self {
       comm_managers,
-      device_config_manager: Arc::new(device_config_manager),
+      comm_manager_metrics,
+      device_config_manager,
       server_sender,
+      unsupported_device_sender: broadcast::channel(255).0,
+      reported_unsupported_devices: Arc::new(DashMap::new()),
+      overuse_warning_sender: broadcast::channel(255).0,
       device_map,
       device_comm_receiver,
       device_event_sender,
       device_event_receiver,
       device_command_receiver,
       scanning_bringup_in_progress: false,
-      scanning_started: false,
+      scanning_started: Arc::new(AtomicBool::new(false)),
+      scan_target: None,
+      reconnect_window: None,
+      reconnect_candidates: HashMap::new(),
+      reconnect_scan: false,
+      connection_hook: None,
+      merged_devices,
+      split_devices: Arc::new(SplitDevices::default()),
       connecting_devices: Arc::new(DashSet::new()),
+      client_locale: Arc::new(RwLock::new(None)),
+      battery_saver: Arc::new(BatterySaver::default()),
+      #[cfg(feature = "scripting")]
+      command_script: None,
+      sensor_rules,
       loop_cancellation_token,
     }
   }
 
+  pub fn device_event_queue_metrics(&self) -> Arc<QueueMetrics> {
+    self.device_event_sender.metrics()
+  }
+
+  pub fn client_locale(&self) -> Arc<RwLock<Option<String>>> {
+    self.client_locale.clone()
+  }
+
+  pub fn battery_saver(&self) -> Arc<BatterySaver> {
+    self.battery_saver.clone()
+  }
+
+  /// Look for devices that disconnect without being asked to for up to `window`, reconnecting them
+  /// if they show up.
+  pub fn set_reconnect_window(&mut self, window: Duration) {
+    self.reconnect_window = Some(window);
+  }
+
+  pub fn set_connection_hook(&mut self, hook: Arc<dyn DeviceConnectionHook>) {
+    self.connection_hook = Some(hook);
+  }
+
+  #[cfg(feature = "scripting")]
+  pub fn set_command_script(&mut self, script: Arc<CommandScript>) {
+    self.command_script = Some(script);
+  }
+
+  // Commands from sensor scripts are sent without waiting, so a slow device can't hold up the
+  // event loop.
+  #[cfg(feature = "scripting")]
+  fn run_sensor_script(&self, reading: &SensorReading) {
+    if let Some(script) = &self.command_script {
+      match script.on_sensor(reading) {
+        Ok(commands) if commands.is_empty() => {}
+        Ok(commands) => {
+          let fut = send_script_commands(&self.device_map, commands);
+          async_manager::spawn(async move {
+            if let Err(err) = fut.await {
+              error!("Command from sensor script failed: {}", err);
+            }
+          });
+        }
+        Err(err) => error!("Sensor script failed: {}", err),
+      }
+    }
+  }
+
+  /// Subscribes to the sensors of a newly connected device that sensor rules watch.
+  fn subscribe_rule_sensors(&self, device_index: u32, device: &Arc<ServerDevice>) {
+    for (sensor_index, sensor_type) in self.sensor_rules.watched_sensors(device.identifier()) {
+      let fut = device
+        .parse_message(SensorSubscribeCmd::new(device_index, sensor_index, sensor_type).into());
+      async_manager::spawn(async move {
+        if let Err(err) = fut.await {
+          error!("Could not subscribe to sensor for sensor rule: {}", err);
+        }
+      });
+    }
+  }
+
+  // Like sensor script commands, rule outputs are sent without waiting on the devices.
+  fn send_rule_outputs(&self, outputs: Vec<(ServerDeviceIdentifier, ScalarSubcommand)>) {
+    for (identifier, output) in outputs {
+      let device_pair = self
+        .device_map
+        .iter()
+        .find(|device_pair| *device_pair.value().identifier() == identifier)
+        .map(|device_pair| (*device_pair.key(), device_pair.value().clone()));
+      // Rules whose actuator isn't connected have nothing to drive.
+      if let Some((device_index, device)) = device_pair {
+        let fut = device.parse_message(ScalarCmd::new(device_index, vec![output]).into());
+        async_manager::spawn(async move {
+          if let Err(err) = fut.await {
+            error!("Command from sensor rule failed: {}", err);
+          }
+        });
+      }
+    }
+  }
+
+  pub fn merged_devices(&self) -> Arc<MergedDevices> {
+    self.merged_devices.clone()
+  }
+
+  pub fn split_devices(&self) -> Arc<SplitDevices> {
+    self.split_devices.clone()
+  }
+
+  /// Flag that is true while the loop is scanning for devices.
+  pub fn scanning_started(&self) -> Arc<AtomicBool> {
+    self.scanning_started.clone()
+  }
+
+  pub fn unsupported_device_sender(&self) -> broadcast::Sender<UnsupportedDeviceInfo> {
+    self.unsupported_device_sender.clone()
+  }
+
+  pub fn reported_unsupported_devices(&self) -> Arc<DashMap<String, UnsupportedDeviceInfo>> {
+    self.reported_unsupported_devices.clone()
+  }
+
+  pub fn overuse_warning_sender(&self) -> broadcast::Sender<OveruseWarning> {
+    self.overuse_warning_sender.clone()
+  }
+
   fn scanning_status(&self) -> bool {
-    if self.comm_managers.iter().any(|x| x.scanning_status()) {
+    if self.comm_managers.iter().any(|x| x.status().is_scanning()) {
       debug!("At least one manager still scanning, continuing event loop.");
       return true;
     }
     false
   }
 
-  async fn handle_start_scanning(&mut self) {
+  fn update_comm_manager_statuses(&self) {
+    for (mgr, metrics) in self.comm_managers.iter().zip(&self.comm_manager_metrics) {
+      metrics.set_status(checked_status(mgr.as_ref()));
+    }
+  }
+
+  /// Sends ScanningFinished if a scan was started and no manager is still scanning.
+  fn check_scanning_finished(&mut self) {
+    if self.scanning_bringup_in_progress {
+      debug!("Hardware Comm Manager finished before scanning was fully started, continuing event loop.");
+      return;
+    }
+    if !self.scanning_status() && self.scanning_started.load(Ordering::SeqCst) {
+      self.scanning_started.store(false, Ordering::SeqCst);
+      if self.reconnect_scan {
+        // Nobody asked for this scan, so nobody needs to hear about it finishing.
+        debug!("All managers finished reconnect scan.");
+        return;
+      }
+      debug!("All managers finished, emitting ScanningFinished");
+      if self
+        .server_sender
+        .send(Arc::new(ScanningFinished::default().into()))
+        .is_err()
+      {
+        info!("Server disappeared, exiting loop.");
+      }
+    }
+  }
+
+  /// Forgets the device a targeted scan was looking for, or that a scan was only for reconnects,
+  /// once no manager is scanning anymore. Only done on events from the managers, as anything they
+  /// found while scanning comes in before them.
+  fn clear_finished_scan_target(&mut self) {
+    if self.scanning_status() {
+      return;
+    }
+    if self.scan_target.is_some() {
+      debug!("Targeted scan finished.");
+      self.scan_target = None;
+    }
+    self.reconnect_scan = false;
+  }
+
+  /// Scans asked for by clients take over any scan for reconnects, so clients get their
+  /// ScanningFinished and see everything found.
+  async fn handle_client_start_scanning(&mut self, target: Option<ServerDeviceIdentifier>) {
+    if self.reconnect_scan {
+      self.reconnect_scan = false;
+      if self.scanning_status() || self.scanning_bringup_in_progress {
+        debug!("System already scanning to reconnect devices, handing scan over to client.");
+        self.scan_target = target;
+        return;
+      }
+    }
+    self.handle_start_scanning(target).await;
+  }
+
+  /// Starts scanning on all managers that can, for all devices or only for `target` if given.
+  async fn handle_start_scanning(&mut self, target: Option<ServerDeviceIdentifier>) {
     if self.scanning_status() || self.scanning_bringup_in_progress {
-      debug!("System already scanning, ignoring new scanning request");
+      if target.is_none() && self.scan_target.take().is_some() {
+        // Managers may still be looking for the target alone, but at least don't throw away
+        // anything else they find.
+        debug!("System already scanning for a single device, accepting all devices from now on");
+      } else {
+        debug!("System already scanning, ignoring new scanning request");
+      }
       return;
     }
 
     info!("No scan currently in progress, starting new scan.");
     self.scanning_bringup_in_progress = true;
-    self.scanning_started = true;
+    self.reported_unsupported_devices.clear();
+    self.scanning_started.store(true, Ordering::SeqCst);
+    self.scan_target = target;
+    let target_address = self
+      .scan_target
+      .as_ref()
+      .map(|target| target.address().clone());
     let fut_vec: Vec<_> = self
       .comm_managers
       .iter_mut()
-      .map(|guard| guard.start_scanning())
+      .filter(|guard| {
+        let status = checked_status(guard.as_ref());
+        if let HardwareCommunicationManagerStatus::NeedsPermission(err) = &status {
+          warn!("{} can't scan: {}", guard.name(), err);
+        } else if !status.can_scan() {
+          info!("{} can't scan ({:?}), skipping.", guard.name(), status);
+        }
+        status.can_scan()
+      })
+      .map(|guard| match &target_address {
+        Some(address) => guard.start_targeted_scanning(address),
+        None => guard.start_scanning(),
+      })
       .collect();
     // TODO If start_scanning fails anywhere, this will ignore it. We should maybe at least log?
     future::join_all(fut_vec).await;
     debug!("Scanning started for all hardware comm managers.");
     self.scanning_bringup_in_progress = false;
+    self.update_comm_manager_statuses();
+    // Managers that couldn't scan (or finished before bringup was done) won't send
+    // ScanningFinished, so check here too.
+    self.check_scanning_finished();
   }
 
   async fn handle_stop_scanning(&mut self) {
@@ -116,28 +461,93 @@ impl ServerDeviceManagerEventLoop {
       .collect();
     // TODO If stop_scanning fails anywhere, this will ignore it. We should maybe at least log?
     future::join_all(fut_vec).await;
+    self.update_comm_manager_statuses();
+    self.scan_target = None;
   }
 
-  async fn handle_device_communication(&mut self, event: HardwareCommunicationManagerEvent) {
+  /// Starts looking for a device that disconnected without being asked to, if reconnecting is
+  /// turned on. Any scan already running will find it too, so only starts scanning if none is.
+  async fn start_reconnect(&mut self, identifier: ServerDeviceIdentifier) {
+    if let Some(window) = self.reconnect_window {
+      info!("Looking for {:?} to reconnect for {:?}.", identifier, window);
+      self
+        .reconnect_candidates
+        .insert(identifier, Instant::now() + window);
+      if !self.scanning_status() && !self.scanning_bringup_in_progress {
+        self.reconnect_scan = true;
+        self.handle_start_scanning(None).await;
+      }
+    }
+  }
+
+  /// Lists or unlists merged devices and split features after a device connects or disconnects.
+  fn update_virtual_devices(&self) {
+    let mut messages = self
+      .merged_devices
+      .update(&self.device_map, &self.device_config_manager);
+    messages.extend(
+      self
+        .split_devices
+        .update(&self.device_map, &self.device_config_manager),
+    );
+    for message in messages {
+      if self.server_sender.send(Arc::new(message)).is_err() {
+        debug!("Server not currently available, dropping virtual device event.");
+      }
+    }
+  }
+
+  fn is_reconnect_candidate(&self, address: &str) -> bool {
+    self
+      .reconnect_candidates
+      .keys()
+      .any(|identifier| identifier.address() == address)
+  }
+
+  /// Stops scanning if it was only being done for reconnects and there's nothing left to look for.
+  async fn stop_finished_reconnect_scan(&mut self) {
+    if self.reconnect_scan && self.reconnect_candidates.is_empty() {
+      debug!("No devices left to reconnect, stopping scan.");
+      self.handle_stop_scanning().await;
+      self.check_scanning_finished();
+    }
+  }
+
+  /// Gives up on any devices that haven't come back within the reconnect window.
+  async fn handle_reconnect_timeout(&mut self) {
+    let now = Instant::now();
+    self.reconnect_candidates.retain(|identifier, deadline| {
+      if *deadline > now {
+        return true;
+      }
+      info!("{:?} did not come back in time, no longer reconnecting.", identifier);
+      false
+    });
+    self.stop_finished_reconnect_scan().await;
+  }
+
+  async fn handle_device_communication(
+    &mut self,
+    metrics: Arc<CommManagerMetrics>,
+    event: HardwareCommunicationManagerEvent,
+  ) {
     match event {
       HardwareCommunicationManagerEvent::ScanningFinished => {
         debug!(
           "System signaled that scanning was finished, check to see if all managers are finished."
         );
-        if self.scanning_bringup_in_progress {
-          debug!("Hardware Comm Manager finished before scanning was fully started, continuing event loop.");
-          return;
-        }
-        if !self.scanning_status() && self.scanning_started {
-          debug!("All managers finished, emitting ScanningFinished");
-          self.scanning_started = false;
-          if self
-            .server_sender
-            .send(ScanningFinished::default().into())
-            .is_err()
-          {
-            info!("Server disappeared, exiting loop.");
-          }
+        self.update_comm_manager_statuses();
+        self.check_scanning_finished();
+        self.clear_finished_scan_target();
+      }
+      HardwareCommunicationManagerEvent::StatusChanged(status) => {
+        info!("{} status changed to {:?}", metrics.name(), status);
+        let scanning = status.is_scanning();
+        metrics.set_status(status);
+        // A manager losing its adapter mid-scan won't send ScanningFinished.
+        if !scanning {
+          self.check_scanning_finished();
+          self.clear_finished_scan_target();
         }
       }
       HardwareCommunicationManagerEvent::DeviceFound {
@@ -145,14 +555,31 @@ impl ServerDeviceManagerEventLoop {
         address,
         creator,
       } => {
-        info!("Device {} ({}) found.", name, address);
+        info!("Device {} ({}) found.", name, display_address(&address));
         // Make sure the device isn't on the deny list, or is on the allow list if anything is on it.
         if !self.device_config_manager.address_allowed(&address) {
           return;
         }
+        if let Some(target) = &self.scan_target {
+          if *target.address() != address && !self.is_reconnect_candidate(&address) {
+            debug!(
+              "Scanning for {} only, ignoring {}.",
+              display_address(target.address()),
+              display_address(&address)
+            );
+            return;
+          }
+        }
+        if self.reconnect_scan && !self.is_reconnect_candidate(&address) {
+          debug!(
+            "Scanning to reconnect devices only, ignoring {}.",
+            display_address(&address)
+          );
+          return;
+        }
         debug!(
           "Device {} allowed via configuration file, continuing.",
-          address
+          display_address(&address)
         );
 
         // Check to make sure the device isn't already connected. If it is, drop what we've been
@@ -164,7 +591,7 @@ impl ServerDeviceManagerEventLoop {
         {
           debug!(
             "Device {} already connected, ignoring new device event.",
-            address
+            display_address(&address)
           );
           return;
         }
@@ -189,8 +616,22 @@ impl ServerDeviceManagerEventLoop {
               creator.specifier()
             )
           );
+          if !self.reported_unsupported_devices.contains_key(&address) {
+            let specifier = creator.specifier();
+            let suggestions = self
+              .device_config_manager
+              .protocol_suggestions(&specifier, UNSUPPORTED_DEVICE_SUGGESTION_COUNT);
+            let info =
+              UnsupportedDeviceInfo::new(&name, &address, specifier, creator.rssi(), suggestions);
+            self
+              .reported_unsupported_devices
+              .insert(address.clone(), info.clone());
+            // No receivers just means nobody hosting the server cares, which is fine.
+            let _ = self.unsupported_device_sender.send(info);
+          }
           return;
         }
+        metrics.record_match();
 
         // Some device managers (like bluetooth) can send multiple DeviceFound events for the same
         // device, due to how things like advertisements work. We'll filter this at the
@@ -199,26 +640,46 @@ impl ServerDeviceManagerEventLoop {
         if self.connecting_devices.contains(&address) {
           info!(
             "Device {} currently trying to connect, ignoring new device event.",
-            address
+            display_address(&address)
           );
           return;
         }
 
         self.connecting_devices.insert(address.clone());
+        metrics.record_connect_attempt();
 
         let device_event_sender_clone = self.device_event_sender.clone();
 
         let device_config_manager = self.device_config_manager.clone();
+        let battery_saver = self.battery_saver.clone();
         let connecting_devices = self.connecting_devices.clone();
+        let connection_hook = self.connection_hook.clone();
         let span = info_span!(
           "device creation",
           name = tracing::field::display(name),
-          address = tracing::field::display(address.clone())
+          address = tracing::field::display(display_address(&address))
         );
 
         async_manager::spawn(async move {
-          match build_server_device(device_config_manager, creator, protocol_specializers).await {
+          let connect_start = Instant::now();
+          match build_server_device(
+            device_config_manager,
+            creator,
+            protocol_specializers,
+            battery_saver,
+          )
+          .await
+          {
             Ok(device) => {
+              metrics.record_connect_success(connect_start.elapsed());
+              // Devices stay in connecting_devices while the hook decides, so hosts aren't asked
+              // about the same device twice.
+              if let Some(hook) = connection_hook {
+                if !apply_connection_hook(hook.as_ref(), &device).await {
+                  connecting_devices.remove(&address);
+                  return;
+                }
+              }
               if device_event_sender_clone
                 .send(ServerDeviceEvent::Connected(Arc::new(device)))
                 .await
@@ -227,6 +688,7 @@ impl ServerDeviceManagerEventLoop {
               }
             },
             Err(e) => {
+              metrics.record_connect_failure();
               error!("Device errored while trying to connect: {}", e);
             }
           }
@@ -284,23 +746,48 @@ impl ServerDeviceManagerEventLoop {
         });
 
         info!("Assigning index {} to {}", device_index, device.name());
-        let device_added_message = DeviceAdded::new(
+        let localization = device.localization(
+          self
+            .client_locale
+            .read()
+            .expect("Locale lock should never be poisoned.")
+            .as_deref(),
+        );
+        let mut device_added_message = DeviceAdded::new(
           device_index,
           &device.name(),
-          &device.display_name(),
+          localization.display_name(),
           &None,
           &device.message_attributes().into(),
         );
+        device_added_message.set_device_description(localization.description().clone());
+        let found_scan_target = self.scan_target.as_ref() == Some(device.identifier());
+        let reconnected = self
+          .reconnect_candidates
+          .remove(device.identifier())
+          .is_some();
+        self.subscribe_rule_sensors(device_index, &device);
         self.device_map.insert(device_index, device);
         // After that, we can send out to the server's event listeners to let
         // them know a device has been added.
         if self
           .server_sender
-          .send(device_added_message.into())
+          .send(Arc::new(device_added_message.into()))
           .is_err()
         {
           debug!("Server not currently available, dropping Device Added event.");
         }
+        self.update_virtual_devices();
+        // Targeted scans are done once their device shows up, no need to keep the radio busy.
+        if found_scan_target {
+          info!("Device scanned for has connected, stopping scan.");
+          self.handle_stop_scanning().await;
+          self.check_scanning_finished();
+        }
+        if reconnected {
+          info!("Reconnected device at index {}.", device_index);
+          self.stop_finished_reconnect_scan().await;
+        }
       }
       ServerDeviceEvent::Disconnected(identifier) => {
         let mut device_index = None;
@@ -311,60 +798,141 @@ impl ServerDeviceManagerEventLoop {
           }
         }
         if let Some(device_index) = device_index {
-          self
+          let (_, device) = self
             .device_map
             .remove(&device_index)
             .expect("Remove will always work.");
           if self
             .server_sender
-            .send(DeviceRemoved::new(device_index).into())
+            .send(Arc::new(DeviceRemoved::new(device_index).into()))
             .is_err()
           {
             debug!("Server not currently available, dropping Device Removed event.");
           }
+          self.update_virtual_devices();
+          self.send_rule_outputs(
+            self
+              .sensor_rules
+              .on_device_removed(&identifier, Instant::now()),
+          );
+          if !device.disconnect_requested() {
+            self.start_reconnect(identifier).await;
+          }
         }
       }
-      ServerDeviceEvent::Notification(_, message) => {
-        if self.server_sender.send(message.into()).is_err() {
+      ServerDeviceEvent::Notification(identifier, mut message) => {
+        let mut rule_outputs = vec![];
+        // Devices don't know which index they've been assigned, so fill it in here for any
+        // notifications they generate on their own (like polled battery readings).
+        if let Some(device_pair) = self
+          .device_map
+          .iter()
+          .find(|device_pair| *device_pair.value().identifier() == identifier)
+        {
+          let device_index = *device_pair.key();
+          match &mut message {
+            ButtplugServerDeviceMessage::RawReading(msg) => msg.set_device_index(device_index),
+            ButtplugServerDeviceMessage::SensorReading(msg) => msg.set_device_index(device_index),
+          }
+          if let ButtplugServerDeviceMessage::SensorReading(msg) = &message {
+            #[cfg(feature = "scripting")]
+            self.run_sensor_script(msg);
+            rule_outputs = self
+              .sensor_rules
+              .on_reading(&identifier, msg, Instant::now());
+          }
+        }
+        // Sent once the device map is no longer borrowed, since outputs are looked up in it.
+        self.send_rule_outputs(rule_outputs);
+        if self.server_sender.send(Arc::new(message.into())).is_err() {
           debug!("Server not currently available, dropping Device Added event.");
         }
       }
+      ServerDeviceEvent::NotificationsDropped(identifier, count) => {
+        let device_index = match self
+          .device_map
+          .iter()
+          .find(|device_pair| *device_pair.value().identifier() == identifier)
+        {
+          Some(device_pair) => *device_pair.key(),
+          // The device disconnected before the count got here, so there's nothing to read anymore.
+          None => return,
+        };
+        warn!(
+          "Device {} dropped {} readings, event stream fell behind.",
+          device_index, count
+        );
+        let error = message::Error::from(ButtplugError::from(
+          ButtplugDeviceError::DeviceNotificationsDropped(device_index, count),
+        ));
+        if self.server_sender.send(Arc::new(error.into())).is_err() {
+          debug!("Server not currently available, dropping notification drop error.");
+        }
+      }
+      ServerDeviceEvent::OveruseStepDown(identifier, protection) => {
+        let device_pair = self
+          .device_map
+          .iter()
+          .find(|device_pair| *device_pair.value().identifier() == identifier)
+          .map(|device_pair| (*device_pair.key(), device_pair.value().clone()));
+        let (device_index, device) = match device_pair {
+          Some(device_pair) => device_pair,
+          // The device disconnected before the step down got here.
+          None => return,
+        };
+        warn!(
+          "Device {} ran at or above {} for {:?}, stepping down to {}.",
+          device_index,
+          protection.threshold(),
+          protection.max_runtime(),
+          protection.step_down_level()
+        );
+        // Send the device's output again, now that the monitor caps it.
+        async_manager::spawn(async move {
+          if let Err(err) = device.refresh_output().await {
+            warn!("Could not step down device output: {}", err);
+          }
+        });
+        // Send only fails if there are no listeners, in which case there's nobody to tell.
+        let _ = self.overuse_warning_sender.send(OveruseWarning::new(
+          device_index,
+          identifier,
+          protection,
+        ));
+      }
     }
   }
 
   pub async fn run(&mut self) {
     debug!("Starting Device Manager Loop");
     loop {
+      let reconnect_deadline = self.reconnect_candidates.values().min().copied();
       tokio::select! {
         device_comm_msg = self.device_comm_receiver.recv() => {
-          if let Some(msg) = device_comm_msg {
+          if let Some((metrics, msg)) = device_comm_msg {
             trace!("Got device communication message {:?}", msg);
-            self.handle_device_communication(msg).await;
+            self.handle_device_communication(metrics, msg).await;
           } else {
             break;
           }
         }
-        device_event_msg = self.device_event_receiver.recv() => {
-          if let Some(msg) = device_event_msg {
-            trace!("Got device event message {:?}", msg);
-            self.handle_device_event(msg).await;
-          } else {
-            error!("We shouldn't be able to get here since we also own the sender.");
-            break;
-          }
+        msg = self.device_event_receiver.recv() => {
+          trace!("Got device event message {:?}", msg);
+          self.handle_device_event(msg).await;
         },
-        device_command_msg = self.device_command_receiver.recv() => {
-          if let Some(msg) = device_command_msg {
-            trace!("Got device command message {:?}", msg);
-            match msg {
-              DeviceManagerCommand::StartScanning => self.handle_start_scanning().await,
-              DeviceManagerCommand::StopScanning => self.handle_stop_scanning().await,
+        // The command queue doesn't close when the Device Manager frontend is dropped, but dropping
+        // the frontend cancels our token, so we'll exit below.
+        msg = self.device_command_receiver.recv() => {
+          trace!("Got device command message {:?}", msg);
+          match msg {
+            DeviceManagerCommand::StartScanning => self.handle_client_start_scanning(None).await,
+            DeviceManagerCommand::StartScanningFor(identifier) => {
+              self.handle_client_start_scanning(Some(identifier)).await
             }
-          } else {
-            debug!("Channel to Device Manager frontend dropped, exiting event loop.");
-            break;
+            DeviceManagerCommand::StopScanning => self.handle_stop_scanning().await,
           }
         }
+        _ = wait_until(reconnect_deadline) => self.handle_reconnect_timeout().await,
         _ = self.loop_cancellation_token.cancelled().fuse() => {
           debug!("Device event loop cancelled, exiting.");
           break;
@@ -0,0 +1,337 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Bounded queues feeding the device manager event loop, served round robin per device.
+//!
+//! Nothing in these queues can be thrown away: connections, disconnections, readings clients
+//! subscribed to and scanning commands all have to arrive. Once a queue is full, senders wait for
+//! room instead, which pushes back on whatever is producing the items. For device readings, that
+//! fills the device's [sensor notification queue](super::sensor_notifier), which counts what it has
+//! to drop so clients are told about it.
+//!
+//! Items can also be sorted into lanes (one per device for device events). Lanes are served round
+//! robin, so a device flooding the queue with readings can't delay connects, disconnects or
+//! readings from quieter devices behind its backlog.
+//!
+//! Client device commands (stops, reads, output) don't go through here. They're handed straight to
+//! the device they're for, so they only ever wait behind that device's own traffic, and a busy
+//! device's output is merged rather than queued up (see [ServerDevice](super::ServerDevice)).
+
+use super::{server_device_manager::DeviceManagerCommand, ServerDeviceEvent};
+use std::{
+  collections::VecDeque,
+  sync::{
+    atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    Arc,
+    Mutex,
+  },
+};
+use tokio::sync::Notify;
+
+pub(super) trait QueueItem {
+  /// Lane the item is queued in. Items in the same lane are delivered in order, lanes take turns.
+  fn lane(&self) -> Option<&str> {
+    None
+  }
+}
+
+impl QueueItem for ServerDeviceEvent {
+  fn lane(&self) -> Option<&str> {
+    Some(match self {
+      ServerDeviceEvent::Connected(device) => device.identifier().address(),
+      ServerDeviceEvent::Notification(identifier, _) => identifier.address(),
+      ServerDeviceEvent::Disconnected(identifier) => identifier.address(),
+      ServerDeviceEvent::OveruseStepDown(identifier, _) => identifier.address(),
+      ServerDeviceEvent::NotificationsDropped(identifier, _) => identifier.address(),
+    })
+  }
+}
+
+impl QueueItem for DeviceManagerCommand {
+}
+
+/// Depth and backpressure counters for a device manager queue.
+#[derive(Debug, Default)]
+pub struct QueueMetrics {
+  depth: AtomicUsize,
+  high_water_mark: AtomicUsize,
+  full_waits: AtomicU64,
+}
+
+impl QueueMetrics {
+  /// Number of items currently waiting in the queue.
+  pub fn depth(&self) -> usize {
+    self.depth.load(Ordering::Relaxed)
+  }
+
+  /// Largest depth the queue has reached since it was created.
+  pub fn high_water_mark(&self) -> usize {
+    self.high_water_mark.load(Ordering::Relaxed)
+  }
+
+  /// Number of times a sender found the queue full and had to wait for room, since the queue was
+  /// created.
+  pub fn full_waits(&self) -> u64 {
+    self.full_waits.load(Ordering::Relaxed)
+  }
+}
+
+struct Lane<T> {
+  key: Option<String>,
+  items: VecDeque<T>,
+}
+
+/// Items split into lanes, with lanes in the order they'll be served.
+struct Lanes<T> {
+  lanes: VecDeque<Lane<T>>,
+  len: usize,
+}
+
+impl<T: QueueItem> Lanes<T> {
+  fn new() -> Self {
+    Self {
+      lanes: VecDeque::new(),
+      len: 0,
+    }
+  }
+
+  fn push(&mut self, item: T) {
+    let key = item.lane();
+    match self
+      .lanes
+      .iter_mut()
+      .find(|lane| lane.key.as_deref() == key)
+    {
+      Some(lane) => lane.items.push_back(item),
+      None => {
+        let key = key.map(|key| key.to_owned());
+        self.lanes.push_back(Lane {
+          key,
+          items: VecDeque::from([item]),
+        });
+      }
+    }
+    self.len += 1;
+  }
+
+  /// Take the next item from the lane at the front, then send that lane to the back.
+  fn pop(&mut self) -> Option<T> {
+    while let Some(mut lane) = self.lanes.pop_front() {
+      if let Some(item) = lane.items.pop_front() {
+        if !lane.items.is_empty() {
+          self.lanes.push_back(lane);
+        }
+        self.len -= 1;
+        return Some(item);
+      }
+    }
+    None
+  }
+}
+
+struct LaneQueueShared<T> {
+  queue: Mutex<Lanes<T>>,
+  capacity: usize,
+  /// Notified when an item is queued.
+  item_queued: Notify,
+  /// Notified when an item is taken, or the receiver goes away.
+  room_made: Notify,
+  receiver_dropped: AtomicBool,
+  metrics: Arc<QueueMetrics>,
+}
+
+pub(super) fn lane_queue<T: QueueItem>(capacity: usize) -> (LaneQueueSender<T>, LaneQueueReceiver<T>) {
+  let shared = Arc::new(LaneQueueShared {
+    queue: Mutex::new(Lanes::new()),
+    capacity: capacity.max(1),
+    item_queued: Notify::new(),
+    room_made: Notify::new(),
+    receiver_dropped: AtomicBool::new(false),
+    metrics: Arc::new(QueueMetrics::default()),
+  });
+  (
+    LaneQueueSender {
+      shared: shared.clone(),
+    },
+    LaneQueueReceiver { shared },
+  )
+}
+
+pub(super) struct LaneQueueSender<T> {
+  shared: Arc<LaneQueueShared<T>>,
+}
+
+impl<T> Clone for LaneQueueSender<T> {
+  fn clone(&self) -> Self {
+    Self {
+      shared: self.shared.clone(),
+    }
+  }
+}
+
+impl<T: QueueItem> LaneQueueSender<T> {
+  /// Queue an item, waiting for room if the queue is full. Returns the item if the receiver no
+  /// longer exists.
+  pub async fn send(&self, item: T) -> Result<(), T> {
+    let mut waited = false;
+    loop {
+      // Registered before checking for room, so room made in between isn't missed.
+      let room_made = self.shared.room_made.notified();
+      tokio::pin!(room_made);
+      room_made.as_mut().enable();
+      if self.shared.receiver_dropped.load(Ordering::SeqCst) {
+        return Err(item);
+      }
+      {
+        let metrics = &self.shared.metrics;
+        let mut queue = self
+          .shared
+          .queue
+          .lock()
+          .expect("Queue lock should never be poisoned");
+        if queue.len < self.shared.capacity {
+          queue.push(item);
+          metrics.depth.store(queue.len, Ordering::Relaxed);
+          metrics
+            .high_water_mark
+            .fetch_max(queue.len, Ordering::Relaxed);
+          break;
+        }
+        if !waited {
+          waited = true;
+          metrics.full_waits.fetch_add(1, Ordering::Relaxed);
+        }
+      }
+      room_made.await;
+    }
+    self.shared.item_queued.notify_one();
+    Ok(())
+  }
+
+  pub fn metrics(&self) -> Arc<QueueMetrics> {
+    self.shared.metrics.clone()
+  }
+}
+
+pub(super) struct LaneQueueReceiver<T> {
+  shared: Arc<LaneQueueShared<T>>,
+}
+
+impl<T: QueueItem> LaneQueueReceiver<T> {
+  fn try_recv(&self) -> Option<T> {
+    let mut queue = self
+      .shared
+      .queue
+      .lock()
+      .expect("Queue lock should never be poisoned");
+    let item = queue.pop();
+    self.shared.metrics.depth.store(queue.len, Ordering::Relaxed);
+    if item.is_some() {
+      self.shared.room_made.notify_waiters();
+    }
+    item
+  }
+
+  /// Wait for the next item. Senders never close the queue, so shutdown is handled by whoever owns
+  /// the receiver (i.e. via cancellation token).
+  pub async fn recv(&mut self) -> T {
+    loop {
+      if let Some(item) = self.try_recv() {
+        return item;
+      }
+      self.shared.item_queued.notified().await;
+    }
+  }
+}
+
+impl<T> Drop for LaneQueueReceiver<T> {
+  fn drop(&mut self) {
+    self.shared.receiver_dropped.store(true, Ordering::SeqCst);
+    // Senders waiting for room would otherwise wait forever.
+    self.shared.room_made.notify_waiters();
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use futures::FutureExt;
+
+  #[derive(Debug, PartialEq, Eq)]
+  enum TestItem {
+    Update(u32),
+    DeviceUpdate(&'static str, u32),
+    DeviceStop(&'static str),
+  }
+
+  impl QueueItem for TestItem {
+    fn lane(&self) -> Option<&str> {
+      match self {
+        TestItem::DeviceUpdate(device, _) | TestItem::DeviceStop(device) => Some(device),
+        _ => None,
+      }
+    }
+  }
+
+  #[tokio::test]
+  async fn test_lane_queue_waits_for_room() {
+    let (sender, mut receiver) = lane_queue(2);
+    for i in 0..2 {
+      sender.send(TestItem::Update(i)).await.expect("Receiver exists");
+    }
+    let metrics = sender.metrics();
+    assert_eq!(metrics.depth(), 2);
+    // Full, so the next send waits instead of dropping anything.
+    let waiting_sender = sender.clone();
+    let mut waiting = Box::pin(async move { waiting_sender.send(TestItem::Update(2)).await });
+    assert!((&mut waiting).now_or_never().is_none());
+    assert_eq!(metrics.full_waits(), 1);
+    assert_eq!(receiver.recv().await, TestItem::Update(0));
+    waiting.await.expect("Receiver exists");
+    assert_eq!(receiver.recv().await, TestItem::Update(1));
+    assert_eq!(receiver.recv().await, TestItem::Update(2));
+    assert_eq!(metrics.depth(), 0);
+    assert_eq!(metrics.high_water_mark(), 2);
+  }
+
+  #[tokio::test]
+  async fn test_lane_queue_lanes_take_turns() {
+    let (sender, mut receiver) = lane_queue(8);
+    for i in 0..4 {
+      sender
+        .send(TestItem::DeviceUpdate("chatty", i))
+        .await
+        .expect("Receiver exists");
+    }
+    sender
+      .send(TestItem::DeviceStop("quiet"))
+      .await
+      .expect("Receiver exists");
+    assert_eq!(receiver.recv().await, TestItem::DeviceUpdate("chatty", 0));
+    // The quiet device doesn't wait for the rest of the backlog.
+    assert_eq!(receiver.recv().await, TestItem::DeviceStop("quiet"));
+    for i in 1..4 {
+      assert_eq!(receiver.recv().await, TestItem::DeviceUpdate("chatty", i));
+    }
+  }
+
+  #[tokio::test]
+  async fn test_lane_queue_receiver_dropped() {
+    let (sender, receiver) = lane_queue(1);
+    sender.send(TestItem::Update(0)).await.expect("Receiver exists");
+    let waiting_sender = sender.clone();
+    let mut waiting = Box::pin(async move { waiting_sender.send(TestItem::Update(1)).await });
+    assert!((&mut waiting).now_or_never().is_none());
+    drop(receiver);
+    // Senders waiting for room give up once there's nobody to make it.
+    assert_eq!(waiting.await, Err(TestItem::Update(1)));
+    assert_eq!(
+      sender.send(TestItem::Update(2)).await,
+      Err(TestItem::Update(2))
+    );
+  }
+}
@@ -0,0 +1,380 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Output features of a device presented to clients as devices of their own.
+//!
+//! Meant for applications that only send one command to a device, like SingleMotorVibrateCmd, or
+//! only show one slider per device. Each ScalarCmd, RotateCmd and LinearCmd feature of a device
+//! configured to be split is listed as a device with just that feature, and commands sent to it are
+//! passed on to the device with their feature index changed. The device itself is still listed and
+//! usable as a whole. Each feature keeps the same index across reconnects, as it is reserved for
+//! the device and feature.
+
+use super::{
+  configuration::{
+    DeviceConfigurationManager,
+    ProtocolAttributesType,
+    ServerDeviceMessageAttributes,
+    ServerDeviceMessageAttributesBuilder,
+  },
+  ServerDevice,
+  ServerDeviceIdentifier,
+};
+use crate::{
+  core::{
+    errors::{ButtplugDeviceError, ButtplugError},
+    message::{
+      self,
+      ActuatorType,
+      ButtplugDeviceCommandMessageUnion,
+      ButtplugDeviceMessage,
+      ButtplugServerMessage,
+      DeviceAdded,
+      DeviceMessageInfo,
+      DeviceRemoved,
+      LinearCmd,
+      RotateCmd,
+      RotationSubcommand,
+      ScalarCmd,
+      ScalarSubcommand,
+      VectorSubcommand,
+    },
+  },
+  server::ButtplugServerResultFuture,
+};
+use dashmap::DashMap;
+use futures::{future, FutureExt};
+use std::sync::{Arc, Mutex};
+
+/// Protocol name for the identifiers split features reserve their indexes under.
+const SPLIT_DEVICE_PROTOCOL: &str = "split";
+
+/// Identifier the feature at `position` in the output features of a device reserves its index
+/// under.
+fn split_feature_identifier(
+  identifier: &ServerDeviceIdentifier,
+  position: usize,
+) -> ServerDeviceIdentifier {
+  ServerDeviceIdentifier::new(
+    &format!("{}/{}/{}", identifier.protocol(), identifier.address(), position),
+    SPLIT_DEVICE_PROTOCOL,
+    &ProtocolAttributesType::Default,
+  )
+}
+
+/// An output feature of a device, by command and index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SplitFeature {
+  Scalar(u32, ActuatorType),
+  Rotate(u32),
+  Linear(u32),
+}
+
+impl SplitFeature {
+  /// Output features of a device, in the order they're listed in.
+  fn all(attributes: &ServerDeviceMessageAttributes) -> Vec<Self> {
+    let mut features = vec![];
+    if let Some(scalars) = attributes.scalar_cmd() {
+      features.extend(
+        scalars
+          .iter()
+          .enumerate()
+          .map(|(index, attrs)| Self::Scalar(index as u32, *attrs.actuator_type())),
+      );
+    }
+    if let Some(rotations) = attributes.rotate_cmd() {
+      features.extend((0..rotations.len() as u32).map(Self::Rotate));
+    }
+    if let Some(vectors) = attributes.linear_cmd() {
+      features.extend((0..vectors.len() as u32).map(Self::Linear));
+    }
+    features
+  }
+
+  /// Message attributes with only this feature of the device in them.
+  fn message_attributes(
+    &self,
+    attributes: &ServerDeviceMessageAttributes,
+  ) -> Option<ServerDeviceMessageAttributes> {
+    let mut builder = ServerDeviceMessageAttributesBuilder::default();
+    match self {
+      Self::Scalar(index, _) => {
+        builder.scalar_cmd(&[attributes.scalar_cmd().as_ref()?.get(*index as usize)?.clone()])
+      }
+      Self::Rotate(index) => {
+        builder.rotate_cmd(&[attributes.rotate_cmd().as_ref()?.get(*index as usize)?.clone()])
+      }
+      Self::Linear(index) => {
+        builder.linear_cmd(&[attributes.linear_cmd().as_ref()?.get(*index as usize)?.clone()])
+      }
+    };
+    Some(builder.finish())
+  }
+}
+
+/// Checks a subcommand is for the only feature of a split device.
+fn check_feature_index(index: u32) -> Result<(), ButtplugDeviceError> {
+  if index == 0 {
+    Ok(())
+  } else {
+    Err(ButtplugDeviceError::DeviceFeatureIndexError(1, index))
+  }
+}
+
+/// A split feature that is currently listed.
+struct SplitDevice {
+  /// Device the feature belongs to, by index and identifier.
+  device_index: u32,
+  identifier: ServerDeviceIdentifier,
+  /// Position of the feature in the output features of the device, counting from 0.
+  position: usize,
+  feature: SplitFeature,
+}
+
+impl SplitDevice {
+  fn device(
+    &self,
+    devices: &DashMap<u32, Arc<ServerDevice>>,
+  ) -> Result<Arc<ServerDevice>, ButtplugDeviceError> {
+    devices
+      .get(&self.device_index)
+      .map(|device| device.value().clone())
+      .filter(|device| *device.identifier() == self.identifier)
+      .ok_or(ButtplugDeviceError::DeviceNotAvailable(self.device_index))
+  }
+
+  fn device_message_info(
+    &self,
+    device_index: u32,
+    devices: &DashMap<u32, Arc<ServerDevice>>,
+  ) -> Option<DeviceMessageInfo> {
+    let device = self.device(devices).ok()?;
+    let attributes = self.feature.message_attributes(&device.message_attributes())?;
+    Some(DeviceMessageInfo::new(
+      device_index,
+      &format!("{} {}", device.name(), self.position + 1),
+      &None,
+      &None,
+      attributes.into(),
+    ))
+  }
+
+  /// Turns a command for the split feature into one for its device. Returns None if there's
+  /// nothing to send, like when stopping a linear feature.
+  fn device_command(
+    &self,
+    command: ButtplugDeviceCommandMessageUnion,
+  ) -> Result<Option<ButtplugDeviceCommandMessageUnion>, ButtplugDeviceError> {
+    let device_index = self.device_index;
+    let command = match (command, self.feature) {
+      (ButtplugDeviceCommandMessageUnion::ScalarCmd(msg), SplitFeature::Scalar(index, _)) => {
+        let mut scalars = vec![];
+        for scalar in msg.scalars() {
+          check_feature_index(scalar.index())?;
+          scalars.push(ScalarSubcommand::new(
+            index,
+            scalar.scalar(),
+            scalar.actuator_type(),
+          ));
+        }
+        ScalarCmd::new(device_index, scalars).into()
+      }
+      (ButtplugDeviceCommandMessageUnion::VibrateCmd(msg), SplitFeature::Scalar(..)) => {
+        return self.device_command(ScalarCmd::from(msg).into())
+      }
+      (
+        ButtplugDeviceCommandMessageUnion::SingleMotorVibrateCmd(msg),
+        SplitFeature::Scalar(index, _),
+      ) => ScalarCmd::new(
+        device_index,
+        vec![ScalarSubcommand::new(
+          index,
+          msg.speed(),
+          ActuatorType::Vibrate,
+        )],
+      )
+      .into(),
+      (ButtplugDeviceCommandMessageUnion::RotateCmd(msg), SplitFeature::Rotate(index)) => {
+        let mut rotations = vec![];
+        for rotation in msg.rotations() {
+          check_feature_index(rotation.index())?;
+          rotations.push(RotationSubcommand::new(
+            index,
+            rotation.speed(),
+            rotation.clockwise(),
+          ));
+        }
+        RotateCmd::new(device_index, rotations).into()
+      }
+      (ButtplugDeviceCommandMessageUnion::LinearCmd(msg), SplitFeature::Linear(index)) => {
+        let mut vectors = vec![];
+        for vector in msg.vectors() {
+          check_feature_index(vector.index())?;
+          vectors.push(VectorSubcommand::new(
+            index,
+            vector.duration(),
+            vector.position(),
+          ));
+        }
+        LinearCmd::new(device_index, vectors).into()
+      }
+      (
+        ButtplugDeviceCommandMessageUnion::StopDeviceCmd(_),
+        SplitFeature::Scalar(index, actuator),
+      ) => ScalarCmd::new(device_index, vec![ScalarSubcommand::new(index, 0.0, actuator)]).into(),
+      (ButtplugDeviceCommandMessageUnion::StopDeviceCmd(_), SplitFeature::Rotate(index)) => {
+        RotateCmd::new(
+          device_index,
+          vec![RotationSubcommand::new(index, 0.0, false)],
+        )
+        .into()
+      }
+      // Linear movements end on their own.
+      (ButtplugDeviceCommandMessageUnion::StopDeviceCmd(_), SplitFeature::Linear(_)) => {
+        return Ok(None)
+      }
+      _ => {
+        return Err(ButtplugDeviceError::UnhandledCommand(
+          "Split device features only take commands for the kind of feature they are".to_owned(),
+        ))
+      }
+    };
+    Ok(Some(command))
+  }
+}
+
+/// Split features that are currently listed, by index.
+#[derive(Default)]
+pub(super) struct SplitDevices {
+  listed: DashMap<u32, SplitDevice>,
+  /// Held while working out which features should be listed, so that two updates can't both list
+  /// (or unlist) the same feature.
+  update_lock: Mutex<()>,
+}
+
+impl SplitDevices {
+  /// Lists the features of connected devices configured to be split, and unlists the features of
+  /// devices that disconnected. Returns DeviceAdded and DeviceRemoved messages for the changes.
+  pub fn update(
+    &self,
+    devices: &DashMap<u32, Arc<ServerDevice>>,
+    device_config_manager: &DeviceConfigurationManager,
+  ) -> Vec<ButtplugServerMessage> {
+    let _guard = self
+      .update_lock
+      .lock()
+      .expect("Split device lock should never be poisoned.");
+    let mut messages = vec![];
+
+    self.listed.retain(|index, split| {
+      if split.device(devices).is_ok() {
+        return true;
+      }
+      info!("Split device feature at index {} no longer available.", index);
+      messages.push(DeviceRemoved::new(*index).into());
+      false
+    });
+
+    for device in devices.iter() {
+      if !device_config_manager.splits_device(device.identifier())
+        || self
+          .listed
+          .iter()
+          .any(|split| split.device_index == *device.key())
+      {
+        continue;
+      }
+      for (position, feature) in SplitFeature::all(&device.message_attributes())
+        .into_iter()
+        .enumerate()
+      {
+        let device_index = device_config_manager
+          .device_index(&split_feature_identifier(device.identifier(), position));
+        let split = SplitDevice {
+          device_index: *device.key(),
+          identifier: device.identifier().clone(),
+          position,
+          feature,
+        };
+        if let Some(info) = split.device_message_info(device_index, devices) {
+          info!(
+            "Feature {} of device {} available at index {}.",
+            position,
+            device.key(),
+            device_index
+          );
+          messages.push(
+            DeviceAdded::new(
+              device_index,
+              info.device_name(),
+              &None,
+              &None,
+              info.device_messages(),
+            )
+            .into(),
+          );
+          self.listed.insert(device_index, split);
+        }
+      }
+    }
+    messages
+  }
+
+  /// Returns whether there's a split feature listed at the index.
+  pub fn is_listed(&self, device_index: u32) -> bool {
+    self.listed.contains_key(&device_index)
+  }
+
+  /// Entries for all listed split features, for device lists.
+  pub fn device_message_infos(
+    &self,
+    devices: &DashMap<u32, Arc<ServerDevice>>,
+  ) -> Vec<DeviceMessageInfo> {
+    self
+      .listed
+      .iter()
+      .filter_map(|split| split.device_message_info(*split.key(), devices))
+      .collect()
+  }
+
+  /// Entry for a listed split feature, if there is one at the index.
+  pub fn device_message_info(
+    &self,
+    device_index: u32,
+    devices: &DashMap<u32, Arc<ServerDevice>>,
+  ) -> Option<DeviceMessageInfo> {
+    self
+      .listed
+      .get(&device_index)
+      .and_then(|split| split.device_message_info(device_index, devices))
+  }
+
+  /// Sends a command for a split feature to its device. Returns None if there's no split feature
+  /// at the index the command is for.
+  pub fn parse_message(
+    &self,
+    devices: &DashMap<u32, Arc<ServerDevice>>,
+    command: ButtplugDeviceCommandMessageUnion,
+  ) -> Option<ButtplugServerResultFuture> {
+    let split = self.listed.get(&command.device_index())?;
+    let result = split
+      .device(devices)
+      .and_then(|device| Ok((device, split.device_command(command)?)));
+    Some(match result {
+      Ok((device, Some(command))) => {
+        let fut = device.parse_message(command);
+        async move {
+          fut.await?;
+          Ok(message::Ok::default().into())
+        }
+        .boxed()
+      }
+      Ok((_, None)) => future::ready(Ok(message::Ok::default().into())).boxed(),
+      Err(err) => future::ready(Err(ButtplugError::from(err))).boxed(),
+    })
+  }
+}
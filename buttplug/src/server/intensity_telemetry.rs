@@ -0,0 +1,438 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Exporting the intensities clients command, for analysing sessions afterwards.
+//!
+//! An [IntensityTelemetryWriter] attached through
+//! [ButtplugServerBuilder::intensity_telemetry](super::ButtplugServerBuilder::intensity_telemetry)
+//! writes a line to a file each time a command the server accepted changes the value of an
+//! actuator, one JSON object per line, stamped with the milliseconds since the writer was created.
+//! Commands repeating the value an actuator already has aren't written, and stopping a device
+//! writes a 0 for each of its actuators that weren't already at 0, apart from positions, which
+//! stay where they were.
+//!
+//! Telemetry is meant to leave the machine running the server, so it never holds hardware
+//! addresses. Devices are identified by a keyed hash of their address (see
+//! [hash_address](crate::util::address_privacy::hash_address)), which stays the same across
+//! sessions written with the same key, so files can be compared without telling who owns the
+//! device. Device indexes aren't stored either, as they change between sessions.
+//!
+//! ScalarCmd, VibrateCmd, RotateCmd and LinearCmd are recorded, along with StopDeviceCmd and
+//! StopAllDevices. Deprecated device specific commands and SingleMotorVibrateCmd aren't, and neither
+//! is anything the server plays itself, like patterns.
+//!
+//! An [IntensityTelemetryReader] loads the file back, for applications analysing sessions.
+
+use super::device::ServerDeviceManager;
+use crate::{
+  core::message::{ActuatorType, ButtplugClientMessage, ButtplugDeviceMessage},
+  util::address_privacy::hash_address,
+};
+use getset::{CopyGetters, Getters};
+use serde::{Deserialize, Serialize};
+use std::{
+  collections::HashMap,
+  fmt,
+  fs::File,
+  io::{self, BufRead, BufReader, BufWriter, Write},
+  path::Path,
+  sync::Mutex,
+  time::Instant,
+};
+use thiserror::Error;
+
+/// Errors that can happen while loading intensity telemetry.
+#[derive(Error, Debug)]
+pub enum IntensityTelemetryError {
+  /// Telemetry file could not be opened or read.
+  #[error("Could not read intensity telemetry: {0}")]
+  Io(#[from] io::Error),
+  /// A line of the telemetry is not a valid sample.
+  #[error("Intensity telemetry line {0} is not a valid sample: {1}")]
+  InvalidSample(usize, String),
+}
+
+/// Value of an actuator changing, as a single line of intensity telemetry.
+#[derive(Debug, Clone, PartialEq, Getters, CopyGetters, Serialize, Deserialize)]
+pub struct IntensitySample {
+  /// Milliseconds between the start of the telemetry and the command.
+  #[serde(rename = "Ms")]
+  #[getset(get_copy = "pub")]
+  elapsed_ms: u64,
+  /// Keyed hash of the device address.
+  #[serde(rename = "Device")]
+  #[getset(get = "pub")]
+  device: String,
+  /// Protocol the device uses, to tell what kind of device it is.
+  #[serde(rename = "Protocol")]
+  #[getset(get = "pub")]
+  protocol: String,
+  /// Index of the actuator, as used in the command.
+  #[serde(rename = "Index")]
+  #[getset(get_copy = "pub")]
+  index: u32,
+  #[serde(rename = "Actuator")]
+  #[getset(get_copy = "pub")]
+  actuator_type: ActuatorType,
+  /// Speed, position, or scalar value commanded, from 0 to 1.
+  #[serde(rename = "Value")]
+  #[getset(get_copy = "pub")]
+  value: f64,
+}
+
+impl IntensitySample {
+  pub fn new(
+    elapsed_ms: u64,
+    device: &str,
+    protocol: &str,
+    index: u32,
+    actuator_type: ActuatorType,
+    value: f64,
+  ) -> Self {
+    Self {
+      elapsed_ms,
+      device: device.to_owned(),
+      protocol: protocol.to_owned(),
+      index,
+      actuator_type,
+      value,
+    }
+  }
+}
+
+/// Device a command was sent to, with the address already hashed.
+#[derive(Debug, Clone)]
+pub(super) struct TelemetryDevice {
+  device: String,
+  protocol: String,
+}
+
+/// What a command will change if the server accepts it. Worked out before the command is sent,
+/// while the device index still points at the device the client meant.
+#[derive(Debug)]
+pub(super) enum IntensityChange {
+  Set(TelemetryDevice, Vec<(u32, ActuatorType, f64)>),
+  StopDevice(TelemetryDevice),
+  StopAll,
+}
+
+type ActuatorKey = (String, u32, ActuatorType);
+
+struct IntensityTelemetryState {
+  writer: Box<dyn Write + Send>,
+  /// Last value written for each actuator, along with the protocol of its device.
+  values: HashMap<ActuatorKey, (String, f64)>,
+}
+
+/// Writes the intensities clients command to a file. See the [module docs](self) for the format.
+pub struct IntensityTelemetryWriter {
+  hash_key: Vec<u8>,
+  start: Instant,
+  state: Mutex<IntensityTelemetryState>,
+}
+
+impl fmt::Debug for IntensityTelemetryWriter {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("IntensityTelemetryWriter")
+      .field("start", &self.start)
+      .finish()
+  }
+}
+
+impl IntensityTelemetryWriter {
+  /// Write to any writer, hashing device addresses with `hash_key`. The key should be random and
+  /// stored per install, like the one given to
+  /// [ButtplugServerBuilder::address_hash_key](super::ButtplugServerBuilder::address_hash_key).
+  /// Each sample is flushed as it is written.
+  pub fn new<T>(writer: T, hash_key: &str) -> Self
+  where
+    T: Write + Send + 'static,
+  {
+    Self {
+      hash_key: hash_key.as_bytes().to_vec(),
+      start: Instant::now(),
+      state: Mutex::new(IntensityTelemetryState {
+        writer: Box::new(writer),
+        values: HashMap::new(),
+      }),
+    }
+  }
+
+  /// Write to a file, creating or truncating it.
+  pub fn to_file<P: AsRef<Path>>(path: P, hash_key: &str) -> Result<Self, io::Error> {
+    Ok(Self::new(BufWriter::new(File::create(path)?), hash_key))
+  }
+
+  pub(super) fn intensity_change(
+    &self,
+    msg: &ButtplugClientMessage,
+    device_manager: &ServerDeviceManager,
+  ) -> Option<IntensityChange> {
+    let device = |index: u32| {
+      device_manager
+        .device_info(index)
+        .map(|info| TelemetryDevice {
+          device: hash_address(&self.hash_key, info.identifier().address()),
+          protocol: info.identifier().protocol().clone(),
+        })
+    };
+    match msg {
+      ButtplugClientMessage::ScalarCmd(msg) => Some(IntensityChange::Set(
+        device(msg.device_index())?,
+        msg
+          .scalars()
+          .iter()
+          .map(|scalar| (scalar.index(), scalar.actuator_type(), scalar.scalar()))
+          .collect(),
+      )),
+      ButtplugClientMessage::VibrateCmd(msg) => Some(IntensityChange::Set(
+        device(msg.device_index())?,
+        msg
+          .speeds()
+          .iter()
+          .map(|speed| (speed.index(), ActuatorType::Vibrate, speed.speed()))
+          .collect(),
+      )),
+      ButtplugClientMessage::RotateCmd(msg) => Some(IntensityChange::Set(
+        device(msg.device_index())?,
+        msg
+          .rotations()
+          .iter()
+          .map(|rotation| (rotation.index(), ActuatorType::Rotate, rotation.speed()))
+          .collect(),
+      )),
+      ButtplugClientMessage::LinearCmd(msg) => Some(IntensityChange::Set(
+        device(msg.device_index())?,
+        msg
+          .vectors()
+          .iter()
+          .map(|vector| (vector.index(), ActuatorType::Position, vector.position()))
+          .collect(),
+      )),
+      ButtplugClientMessage::StopDeviceCmd(msg) => {
+        Some(IntensityChange::StopDevice(device(msg.device_index())?))
+      }
+      ButtplugClientMessage::StopAllDevices(_) => Some(IntensityChange::StopAll),
+      _ => None,
+    }
+  }
+
+  fn stops_at_zero(actuator_type: ActuatorType) -> bool {
+    !matches!(
+      actuator_type,
+      ActuatorType::Position | ActuatorType::RotatePosition
+    )
+  }
+
+  /// Writes the samples for a change the server accepted.
+  pub(super) fn record(&self, change: IntensityChange) {
+    let elapsed_ms = self.start.elapsed().as_millis() as u64;
+    let mut state = self
+      .state
+      .lock()
+      .expect("Telemetry lock should never be poisoned.");
+    let updates: Vec<(ActuatorKey, String, f64)> = match change {
+      IntensityChange::Set(device, values) => values
+        .into_iter()
+        .map(|(index, actuator_type, value)| {
+          (
+            (device.device.clone(), index, actuator_type),
+            device.protocol.clone(),
+            value,
+          )
+        })
+        .collect(),
+      IntensityChange::StopDevice(device) => state
+        .values
+        .keys()
+        .filter(|(hashed, _, actuator_type)| {
+          *hashed == device.device && Self::stops_at_zero(*actuator_type)
+        })
+        .map(|key| (key.clone(), device.protocol.clone(), 0.0))
+        .collect(),
+      IntensityChange::StopAll => state
+        .values
+        .iter()
+        .filter(|((_, _, actuator_type), _)| Self::stops_at_zero(*actuator_type))
+        .map(|(key, (protocol, _))| (key.clone(), protocol.clone(), 0.0))
+        .collect(),
+    };
+    let mut samples = vec![];
+    for (key, protocol, value) in updates {
+      if state
+        .values
+        .get(&key)
+        .is_some_and(|(_, last)| *last == value)
+      {
+        continue;
+      }
+      samples.push(IntensitySample::new(
+        elapsed_ms, &key.0, &protocol, key.1, key.2, value,
+      ));
+      state.values.insert(key, (protocol, value));
+    }
+    // Keep files stable to diff, whatever order the map gave the stopped actuators in.
+    samples.sort_by(|a, b| (&a.device, a.index).cmp(&(&b.device, b.index)));
+    for sample in samples {
+      let line = match serde_json::to_string(&sample) {
+        Ok(line) => line,
+        Err(e) => {
+          error!("Could not serialize intensity sample: {:?}", e);
+          continue;
+        }
+      };
+      if let Err(e) = writeln!(state.writer, "{}", line).and_then(|_| state.writer.flush()) {
+        error!("Could not write intensity sample: {:?}", e);
+      }
+    }
+  }
+}
+
+/// Intensity telemetry loaded back from a file, for analysis.
+#[derive(Debug, Clone, Default, Getters)]
+pub struct IntensityTelemetryReader {
+  /// Every sample, in the order they were written.
+  #[getset(get = "pub")]
+  samples: Vec<IntensitySample>,
+}
+
+impl IntensityTelemetryReader {
+  pub fn new(samples: Vec<IntensitySample>) -> Self {
+    Self { samples }
+  }
+
+  /// Load telemetry from any reader, one sample per line. Blank lines are skipped.
+  pub fn from_reader<R: BufRead>(reader: R) -> Result<Self, IntensityTelemetryError> {
+    let mut samples = vec![];
+    for (index, line) in reader.lines().enumerate() {
+      let line = line?;
+      if line.trim().is_empty() {
+        continue;
+      }
+      samples.push(
+        serde_json::from_str(&line)
+          .map_err(|e| IntensityTelemetryError::InvalidSample(index + 1, e.to_string()))?,
+      );
+    }
+    Ok(Self::new(samples))
+  }
+
+  /// Load telemetry written by [IntensityTelemetryWriter::to_file].
+  pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, IntensityTelemetryError> {
+    Self::from_reader(BufReader::new(File::open(path)?))
+  }
+
+  /// Hashed addresses of the devices in the telemetry, in the order they were first commanded.
+  pub fn devices(&self) -> Vec<&str> {
+    let mut devices: Vec<&str> = vec![];
+    for sample in &self.samples {
+      if !devices.contains(&sample.device.as_str()) {
+        devices.push(&sample.device);
+      }
+    }
+    devices
+  }
+
+  /// Samples for one device, in the order they were written.
+  pub fn device_samples(&self, device: &str) -> Vec<&IntensitySample> {
+    self
+      .samples
+      .iter()
+      .filter(|sample| sample.device == device)
+      .collect()
+  }
+
+  /// Values of one actuator of a device over time, as milliseconds since the start of the
+  /// telemetry and the value set at that time. Each value holds until the next one.
+  pub fn timeline(&self, device: &str, index: u32, actuator_type: ActuatorType) -> Vec<(u64, f64)> {
+    self
+      .samples
+      .iter()
+      .filter(|sample| {
+        sample.device == device && sample.index == index && sample.actuator_type == actuator_type
+      })
+      .map(|sample| (sample.elapsed_ms, sample.value))
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[derive(Clone, Default)]
+  struct SharedBuffer(std::sync::Arc<Mutex<Vec<u8>>>);
+
+  impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+      self.0.lock().expect("Test, assuming infallible").write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+      Ok(())
+    }
+  }
+
+  fn device(address: &str) -> TelemetryDevice {
+    TelemetryDevice {
+      device: hash_address(b"key", address),
+      protocol: "lovense".to_owned(),
+    }
+  }
+
+  #[test]
+  fn test_intensity_telemetry_round_trip() {
+    let buffer = SharedBuffer::default();
+    let writer = IntensityTelemetryWriter::new(buffer.clone(), "key");
+    writer.record(IntensityChange::Set(
+      device("AA:BB"),
+      vec![
+        (0, ActuatorType::Vibrate, 0.5),
+        (1, ActuatorType::Rotate, 0.25),
+        (2, ActuatorType::Position, 0.75),
+      ],
+    ));
+    // Repeated values are skipped.
+    writer.record(IntensityChange::Set(
+      device("AA:BB"),
+      vec![(0, ActuatorType::Vibrate, 0.5)],
+    ));
+    writer.record(IntensityChange::Set(
+      device("CC:DD"),
+      vec![(0, ActuatorType::Vibrate, 1.0)],
+    ));
+    writer.record(IntensityChange::StopDevice(device("AA:BB")));
+    writer.record(IntensityChange::StopAll);
+
+    let contents = buffer.0.lock().expect("Test, assuming infallible").clone();
+    let text = String::from_utf8(contents.clone()).expect("Test, assuming infallible");
+    assert!(!text.contains("AA:BB"));
+    let reader =
+      IntensityTelemetryReader::from_reader(&contents[..]).expect("Test, assuming infallible");
+    let first = hash_address(b"key", "AA:BB");
+    let second = hash_address(b"key", "CC:DD");
+    assert_eq!(reader.samples().len(), 7);
+    assert_eq!(reader.devices(), vec![first.as_str(), second.as_str()]);
+    assert_eq!(reader.device_samples(&first).len(), 5);
+    assert_eq!(
+      reader.timeline(&first, 2, ActuatorType::Position),
+      vec![(0, 0.75)]
+    );
+    let values: Vec<f64> = reader
+      .timeline(&first, 0, ActuatorType::Vibrate)
+      .iter()
+      .map(|(_, value)| *value)
+      .collect();
+    assert_eq!(values, vec![0.5, 0.0]);
+    let values: Vec<f64> = reader
+      .timeline(&second, 0, ActuatorType::Vibrate)
+      .iter()
+      .map(|(_, value)| *value)
+      .collect();
+    assert_eq!(values, vec![1.0, 0.0]);
+  }
+}
@@ -30,6 +30,8 @@
 //!     connects to it directly). At this point, a [ButtplugClient](crate::client::ButtplugClient)
 //!     can connect and start the
 //!     [handshake](https://buttplug-spec.docs.buttplug.io/architecture.html#stages) process.
+//!     Hosts that serve several clients at once give each one its own
+//!     [session](ButtplugServer::session), which shares the server's devices.
 //! - Pass-thru
 //!   - Once the handshake has succeeded, the server basically becomes a pass-thru to the
 //!     [DeviceManager], which manages discovery of and communication with devices. The only thing
@@ -46,7 +48,17 @@
 //!     of the [DeviceManager] teardown.
 
 pub mod device;
+#[cfg(feature = "serialize-json")]
+pub mod intensity_telemetry;
+mod operation_progress;
+#[cfg(feature = "os-notifications")]
+pub mod os_notifications;
+pub mod persistence;
 mod ping_timer;
+mod remote_server;
+#[cfg(feature = "serialize-json")]
+pub mod session_recording;
+mod state_snapshot;
 
 use self::device::{
   configuration::{
@@ -54,11 +66,16 @@ use self::device::{
     ProtocolCommunicationSpecifier,
     ProtocolDeviceAttributes,
   },
-  hardware::communication::HardwareCommunicationManagerBuilder,
+  hardware::communication::{
+    HardwareCommunicationManagerBuilder,
+    HardwareCommunicationManagerConfig,
+  },
   protocol::ProtocolIdentifierFactory,
+  OveruseWarning,
   ServerDeviceIdentifier,
   ServerDeviceManager,
   ServerDeviceManagerBuilder,
+  UnsupportedDeviceInfo,
 };
 use crate::{
   core::{
@@ -69,15 +86,18 @@ use crate::{
       ButtplugDeviceCommandMessageUnion,
       ButtplugDeviceManagerMessageUnion,
       ButtplugMessage,
+      ButtplugMessageSpecVersion,
       ButtplugServerMessage,
+      ClientCapability,
       StopAllDevices,
       StopScanning,
       BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
     },
   },
   util::{
+    address_privacy,
     async_manager,
-    device_configuration::{load_protocol_configs, DEVICE_CONFIGURATION_JSON},
+    device_configuration::load_protocol_configs,
     stream::convert_broadcast_receiver_to_stream,
   },
 };
@@ -85,13 +105,30 @@ use futures::{
   future::{self, BoxFuture, FutureExt},
   Stream,
 };
+#[cfg(feature = "serialize-json")]
+use intensity_telemetry::IntensityTelemetryWriter;
+pub use operation_progress::ProgressReporter;
+#[cfg(feature = "os-notifications")]
+use os_notifications::{OsNotificationSettings, OsNotificationWatcher};
+use persistence::ButtplugPersistence;
 use ping_timer::PingTimer;
+pub use remote_server::{ButtplugRemoteServer, ButtplugServerConnectorError};
+#[cfg(feature = "serialize-json")]
+use session_recording::SessionRecorder;
+pub use state_snapshot::{
+  ClientStateSnapshot,
+  CommManagerStateSnapshot,
+  DeviceStateSnapshot,
+  ServerStateSnapshot,
+};
 use std::{
   fmt,
   sync::{
     atomic::{AtomicBool, Ordering},
     Arc,
+    RwLock,
   },
+  time::Duration,
 };
 use thiserror::Error;
 use tokio::sync::broadcast;
@@ -122,6 +159,23 @@ pub enum ButtplugServerError {
   /// Requested protocol has not been registered with the system.
   #[error("Buttplug Protocol of type {0} does not exist in the system and cannot be removed.")]
   ProtocolDoesNotExist(String),
+  /// Raw messages were allowed on a server that accepts unauthenticated remote connections.
+  #[error("Raw messages cannot be allowed when unauthenticated remote connections are accepted.")]
+  RawMessagesWithUnauthenticatedAccess,
+}
+
+/// Describes who will be able to reach a [ButtplugServer], so [ButtplugServerBuilder] can reject
+/// configurations that would be unsafe to expose.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ButtplugServerAccess {
+  /// Server is only reachable in-process or from the local machine.
+  #[default]
+  Local,
+  /// Server is reachable through a remote connector, and the transport authenticates clients
+  /// before they can connect.
+  AuthenticatedRemote,
+  /// Server is reachable through a remote connector with no client authentication.
+  UnauthenticatedRemote,
 }
 
 /// Configures and creates [ButtplugServer] instances.
@@ -136,8 +190,23 @@ pub struct ButtplugServerBuilder {
   device_configuration_json: Option<String>,
   /// JSON string, with the contents of the User Device Configuration file
   user_device_configuration_json: Option<String>,
+  /// Who will be able to connect to the server, used to validate other options.
+  access: ButtplugServerAccess,
+  /// If true, raw device messages have been allowed, for all devices or only some of them.
+  allow_raw_messages: bool,
+  /// Key used to hash hardware addresses in logs and messages sent to clients, if any.
+  address_hash_key: Option<String>,
   /// Device manager builder for the server
   device_manager_builder: ServerDeviceManagerBuilder,
+  /// Recorder for client messages and server events, if the session is being recorded.
+  #[cfg(feature = "serialize-json")]
+  session_recorder: Option<Arc<SessionRecorder>>,
+  /// Writer for commanded intensities, if telemetry was opted into.
+  #[cfg(feature = "serialize-json")]
+  intensity_telemetry: Option<Arc<IntensityTelemetryWriter>>,
+  /// Events to raise host OS notifications for, if any.
+  #[cfg(feature = "os-notifications")]
+  os_notifications: Option<OsNotificationSettings>,
 }
 
 impl Default for ButtplugServerBuilder {
@@ -145,9 +214,18 @@ impl Default for ButtplugServerBuilder {
     Self {
       name: "Buttplug Server".to_owned(),
       max_ping_time: None,
-      device_configuration_json: Some(DEVICE_CONFIGURATION_JSON.to_owned()),
+      device_configuration_json: None,
       user_device_configuration_json: None,
+      access: ButtplugServerAccess::default(),
+      allow_raw_messages: false,
+      address_hash_key: None,
       device_manager_builder: ServerDeviceManagerBuilder::default(),
+      #[cfg(feature = "serialize-json")]
+      session_recorder: None,
+      #[cfg(feature = "serialize-json")]
+      intensity_telemetry: None,
+      #[cfg(feature = "os-notifications")]
+      os_notifications: None,
     }
   }
 }
@@ -172,7 +250,8 @@ impl ButtplugServerBuilder {
     self
   }
 
-  /// Set the device configuration json file contents, to be loaded during build.
+  /// Set the device configuration json file contents, to be loaded during build. If None, the
+  /// configuration compiled into the library is used.
   pub fn device_configuration_json(&mut self, config_json: Option<String>) -> &mut Self {
     self.device_configuration_json = config_json;
     self
@@ -192,6 +271,14 @@ impl ButtplugServerBuilder {
     self
   }
 
+  pub fn comm_manager_config<T>(&mut self, config: T) -> &mut Self
+  where
+    T: HardwareCommunicationManagerConfig,
+  {
+    self.device_manager_builder.comm_manager_config(config);
+    self
+  }
+
   pub fn allowed_address(&mut self, address: &str) -> &mut Self {
     self.device_manager_builder.allowed_address(address);
     self
@@ -209,6 +296,20 @@ impl ButtplugServerBuilder {
     self
   }
 
+  /// Drive an actuator from readings of a sensor on the server, so simple interactive behaviors
+  /// work without a client. See [device::SensorRule].
+  pub fn sensor_rule(&mut self, rule: device::SensorRule) -> &mut Self {
+    self.device_manager_builder.sensor_rule(rule);
+    self
+  }
+
+  /// Keep device indexes, display names, allow/deny lists and sensor calibrations in `persistence`,
+  /// so they outlive the server. See [persistence] for what's stored.
+  pub fn persistence(&mut self, persistence: Arc<dyn ButtplugPersistence>) -> &mut Self {
+    self.device_manager_builder.persistence(persistence);
+    self
+  }
+
   pub fn protocol_factory<T>(&mut self, factory: T) -> &mut Self
   where
     T: ProtocolIdentifierFactory + 'static,
@@ -245,16 +346,112 @@ impl ButtplugServerBuilder {
   }
 
   pub fn allow_raw_messages(&mut self) -> &mut Self {
+    self.allow_raw_messages = true;
     self.device_manager_builder.allow_raw_messages();
     self
   }
 
+  /// Allow raw messages for the device with the given identifier only, so a trusted device can be
+  /// debugged without opening up raw access to every device. Clients can ask for
+  /// [ClientCapability::Raw] once any device is allowed raw messages, and the same access
+  /// restrictions as [Self::allow_raw_messages] apply.
+  pub fn allow_raw_messages_for(&mut self, identifier: &ServerDeviceIdentifier) -> &mut Self {
+    self.allow_raw_messages = true;
+    self
+      .device_manager_builder
+      .allow_raw_messages_for(identifier);
+    self
+  }
+
+  /// Set who will be able to connect to the server. Defaults to [ButtplugServerAccess::Local].
+  /// Options that would be unsafe for the given access level will cause [Self::finish] to fail.
+  pub fn access(&mut self, access: ButtplugServerAccess) -> &mut Self {
+    self.access = access;
+    self
+  }
+
+  /// Set the maximum number of devices sent per page to clients that request paged device lists.
+  /// Clients that do not page their requests will still receive the full list.
+  pub fn device_list_page_size(&mut self, page_size: u32) -> &mut Self {
+    self.device_manager_builder.device_list_page_size(page_size);
+    self
+  }
+
+  /// Reconnect devices that drop out (i.e. bluetooth devices going out of range) if they come back
+  /// within `window`. They keep the index they had, so clients see them removed and added again.
+  pub fn reconnect_window(&mut self, window: Duration) -> &mut Self {
+    self.device_manager_builder.reconnect_window(window);
+    self
+  }
+
+  /// Ask `hook` about every device that connects before clients are told about it, so hosts can
+  /// have users confirm new devices, or name them from a database of their own. See
+  /// [device::DeviceConnectionHook].
+  pub fn connection_hook<T>(&mut self, hook: T) -> &mut Self
+  where
+    T: device::DeviceConnectionHook + 'static,
+  {
+    self.device_manager_builder.connection_hook(hook);
+    self
+  }
+
+  /// Run device commands sent by clients and sensor readings sent by devices through a user
+  /// script, which can rewrite the commands or send new ones. See [device::CommandScript].
+  #[cfg(feature = "scripting")]
+  pub fn command_script(&mut self, script: device::CommandScript) -> &mut Self {
+    self.device_manager_builder.command_script(script);
+    self
+  }
+
+  /// Record every client message, reply and server event to `recorder`, so the session can be
+  /// replayed later. See [session_recording] for details.
+  #[cfg(feature = "serialize-json")]
+  pub fn session_recorder(&mut self, recorder: SessionRecorder) -> &mut Self {
+    self.session_recorder = Some(Arc::new(recorder));
+    self
+  }
+
+  /// Write the intensities clients command to `writer`, with device addresses hashed, for
+  /// analysing sessions afterwards. See [intensity_telemetry] for details.
+  #[cfg(feature = "serialize-json")]
+  pub fn intensity_telemetry(&mut self, writer: IntensityTelemetryWriter) -> &mut Self {
+    self.intensity_telemetry = Some(Arc::new(writer));
+    self
+  }
+
+  /// Raise host OS notifications for critical events, like a device disconnecting mid-session.
+  /// See [os_notifications] for details.
+  #[cfg(feature = "os-notifications")]
+  pub fn os_notifications(&mut self, settings: OsNotificationSettings) -> &mut Self {
+    self.os_notifications = Some(settings);
+    self
+  }
+
+  /// Hash hardware addresses with the given key anywhere they'd show up in logs or messages sent
+  /// to clients, for servers whose logs get shared or that are reached through relays. The key
+  /// should be random and stored per install. Configs and reserved indexes still use the real
+  /// addresses. See [address_privacy](crate::util::address_privacy) for details.
+  pub fn address_hash_key(&mut self, key: &str) -> &mut Self {
+    self.address_hash_key = Some(key.to_owned());
+    self
+  }
+
   /// Try to build a [ButtplugServer] using the parameters given.
   pub fn finish(&mut self) -> Result<ButtplugServer, ButtplugServerError> {
     // Create the server
     debug!("Creating server '{}'", self.name);
     info!("Buttplug Server Operating System Info: {}", os_info::get());
 
+    // Reject contradictory options before we spend time loading configs or spinning up managers.
+    if self.allow_raw_messages && self.access == ButtplugServerAccess::UnauthenticatedRemote {
+      return Err(ButtplugServerError::RawMessagesWithUnauthenticatedAccess);
+    }
+
+    // Set this before anything else logs device addresses.
+    if let Some(key) = &self.address_hash_key {
+      address_privacy::set_address_hash_key(Some(key));
+    }
+
     // First, try loading our configs. If this doesn't work, nothing else will, so get it out of
     // the way first.
     let dcm_builder = load_protocol_configs(
@@ -269,42 +466,43 @@ impl ButtplugServerBuilder {
       .device_configuration_manager_builder(&dcm_builder);
     // Set up our channels to different parts of the system.
     let (output_sender, _) = broadcast::channel(256);
-    let output_sender_clone = output_sender.clone();
 
     let device_manager = Arc::new(self.device_manager_builder.finish()?);
 
     let connected = Arc::new(AtomicBool::new(false));
-    let connected_clone = connected.clone();
+    let client = Arc::new(RwLock::new(None));
 
-    // TODO this should use a cancellation token instead of passing around the timer itself.
     let ping_time = self.max_ping_time.unwrap_or(0);
-    let ping_timer = Arc::new(PingTimer::new(ping_time));
-    let ping_timeout_notifier = ping_timer.ping_timeout_waiter();
+    let ping_timer = start_ping_timer(ping_time, &connected, &device_manager, &output_sender);
 
-    // Spawn the ping timer task, assuming the ping time is > 0.
-    if ping_time > 0 {
-      let device_manager_clone = device_manager.clone();
-      async_manager::spawn(
-        async move {
-          // This will only exit if we've pinged out.
-          ping_timeout_notifier.await;
-          error!("Ping out signal received, stopping server");
-          connected_clone.store(false, Ordering::SeqCst);
-          async_manager::spawn(async move {
-            if let Err(e) = device_manager_clone.stop_all_devices().await {
-              error!("Could not stop devices on ping timeout: {:?}", e);
-            }
-          });
-          // TODO Should the event sender return a result instead of an error message?
-          if output_sender_clone
-            .send(message::Error::from(ButtplugError::from(ButtplugPingError::PingedOut)).into())
-            .is_err()
-          {
-            error!("Server disappeared, cannot update about ping out.");
-          };
+    // Record events from the moment the server exists, so recordings see every device connect.
+    #[cfg(feature = "serialize-json")]
+    if let Some(recorder) = &self.session_recorder {
+      let recorder = recorder.clone();
+      let mut event_stream = Box::pin(device_manager.event_stream().merge(
+        convert_broadcast_receiver_to_stream(output_sender.subscribe()),
+      ));
+      async_manager::spawn(async move {
+        while let Some(msg) = event_stream.next().await {
+          recorder.record_server_event(msg);
         }
-        .instrument(tracing::info_span!("Buttplug Server Ping Timeout Task")),
-      );
+      });
+    }
+
+    #[cfg(feature = "os-notifications")]
+    if let Some(settings) = &self.os_notifications {
+      let mut watcher = OsNotificationWatcher::new(settings.clone());
+      let connected = connected.clone();
+      let mut event_stream = Box::pin(device_manager.event_stream().merge(
+        convert_broadcast_receiver_to_stream(output_sender.subscribe()),
+      ));
+      async_manager::spawn(async move {
+        while let Some(msg) = event_stream.next().await {
+          if let Some(notification) = watcher.notification(&msg, connected.load(Ordering::SeqCst)) {
+            watcher.show(notification);
+          }
+        }
+      });
     }
 
     // Assuming everything passed, return the server.
@@ -314,11 +512,60 @@ impl ButtplugServerBuilder {
       device_manager,
       ping_timer,
       connected,
+      client,
+      allow_raw_messages: self.allow_raw_messages,
       output_sender,
+      #[cfg(feature = "serialize-json")]
+      session_recorder: self.session_recorder.clone(),
+      #[cfg(feature = "serialize-json")]
+      intensity_telemetry: self.intensity_telemetry.clone(),
     })
   }
 }
 
+/// Creates the ping timer for a client, and if the ping time is > 0, spawns the task that stops
+/// devices and tells the client when it pings out.
+fn start_ping_timer(
+  ping_time: u32,
+  connected: &Arc<AtomicBool>,
+  device_manager: &Arc<ServerDeviceManager>,
+  output_sender: &broadcast::Sender<Arc<ButtplugServerMessage>>,
+) -> Arc<PingTimer> {
+  // TODO this should use a cancellation token instead of passing around the timer itself.
+  let ping_timer = Arc::new(PingTimer::new(ping_time));
+  if ping_time > 0 {
+    let ping_timeout_notifier = ping_timer.ping_timeout_waiter();
+    let connected = connected.clone();
+    let device_manager = device_manager.clone();
+    let output_sender = output_sender.clone();
+    async_manager::spawn(
+      async move {
+        if !ping_timeout_notifier.await {
+          return;
+        }
+        error!("Ping out signal received, stopping server");
+        connected.store(false, Ordering::SeqCst);
+        async_manager::spawn(async move {
+          if let Err(e) = device_manager.stop_all_devices().await {
+            error!("Could not stop devices on ping timeout: {:?}", e);
+          }
+        });
+        // TODO Should the event sender return a result instead of an error message?
+        if output_sender
+          .send(Arc::new(
+            message::Error::from(ButtplugError::from(ButtplugPingError::PingedOut)).into(),
+          ))
+          .is_err()
+        {
+          error!("Server disappeared, cannot update about ping out.");
+        };
+      }
+      .instrument(tracing::info_span!("Buttplug Server Ping Timeout Task")),
+    );
+  }
+  ping_timer
+}
+
 /// The server side of the Buttplug protocol. Frontend for connection to device management and
 /// communication.
 pub struct ButtplugServer {
@@ -339,9 +586,19 @@ pub struct ButtplugServer {
   device_manager: Arc<ServerDeviceManager>,
   /// If true, client is currently connected to server
   connected: Arc<AtomicBool>,
+  /// Client that last completed the handshake.
+  client: Arc<RwLock<Option<ClientStateSnapshot>>>,
+  /// If true, clients can be granted [ClientCapability::Raw].
+  allow_raw_messages: bool,
   /// Broadcaster for server events. Receivers for this are handed out through the
   /// [ButtplugServer::event_stream()] method.
-  output_sender: broadcast::Sender<ButtplugServerMessage>,
+  output_sender: broadcast::Sender<Arc<ButtplugServerMessage>>,
+  /// Recorder for client messages and server events, if the session is being recorded.
+  #[cfg(feature = "serialize-json")]
+  session_recorder: Option<Arc<SessionRecorder>>,
+  /// Writer for commanded intensities, if telemetry was opted into.
+  #[cfg(feature = "serialize-json")]
+  intensity_telemetry: Option<Arc<IntensityTelemetryWriter>>,
 }
 
 impl std::fmt::Debug for ButtplugServer {
@@ -368,7 +625,11 @@ impl ButtplugServer {
   /// Retreive an async stream of ButtplugServerMessages. This is how the server sends out
   /// non-query-related updates to the system, including information on devices being added/removed,
   /// client disconnection, etc...
-  pub fn event_stream(&self) -> impl Stream<Item = ButtplugServerMessage> {
+  ///
+  /// Events are shared between every stream, so each subscriber costs a reference count instead of
+  /// a copy of every message. Use [Arc::unwrap_or_clone] to get an owned message, which only
+  /// copies it if other subscribers haven't received it yet.
+  pub fn event_stream(&self) -> impl Stream<Item = Arc<ButtplugServerMessage>> {
     // Unlike the client API, we can expect anyone using the server to pin this
     // themselves.
     let server_receiver = convert_broadcast_receiver_to_stream(self.output_sender.subscribe());
@@ -376,6 +637,52 @@ impl ButtplugServer {
     device_receiver.merge(server_receiver)
   }
 
+  /// Stream of hardware found while scanning that no protocol matched, so applications hosting the
+  /// server can point users at possibly unsupported devices. See
+  /// [ServerDeviceManager::unsupported_device_stream].
+  pub fn unsupported_device_stream(&self) -> impl Stream<Item = UnsupportedDeviceInfo> {
+    self.device_manager.unsupported_device_stream()
+  }
+
+  /// Stream of devices stepped down by overuse protection, so applications hosting the server can
+  /// let users know why their device slowed down. See [ServerDeviceManager::overuse_warning_stream].
+  pub fn overuse_warning_stream(&self) -> impl Stream<Item = OveruseWarning> {
+    self.device_manager.overuse_warning_stream()
+  }
+
+  /// Creates a server for another client, sharing this server's devices, so several clients can be
+  /// connected at once.
+  ///
+  /// Each session runs its own handshake and ping timer, and only its client gets the
+  /// [ServerInfo](message::ServerInfo), ping outs and progress it causes. Everything else is shared:
+  /// device events go to every session, and device commands from any session act on the same
+  /// devices. That includes stopping them, so StopAllDevices, or any session's client disconnecting
+  /// or pinging out, stops every device.
+  pub fn session(&self) -> ButtplugServer {
+    let (output_sender, _) = broadcast::channel(256);
+    let connected = Arc::new(AtomicBool::new(false));
+    let ping_timer = start_ping_timer(
+      self.max_ping_time,
+      &connected,
+      &self.device_manager,
+      &output_sender,
+    );
+    ButtplugServer {
+      server_name: self.server_name.clone(),
+      max_ping_time: self.max_ping_time,
+      ping_timer,
+      device_manager: self.device_manager.clone(),
+      connected,
+      client: Arc::new(RwLock::new(None)),
+      allow_raw_messages: self.allow_raw_messages,
+      output_sender,
+      #[cfg(feature = "serialize-json")]
+      session_recorder: self.session_recorder.clone(),
+      #[cfg(feature = "serialize-json")]
+      intensity_telemetry: self.intensity_telemetry.clone(),
+    }
+  }
+
   /// Returns a references to the internal device manager, for handling configuration.
   pub fn device_manager(&self) -> Arc<ServerDeviceManager> {
     self.device_manager.clone()
@@ -386,6 +693,56 @@ impl ButtplugServer {
     self.connected.load(Ordering::SeqCst)
   }
 
+  /// Returns the current state of the server: its client, devices, scanning status, per transport
+  /// scan metrics and versions.
+  /// Meant for applications hosting a server that want to display its status without having to
+  /// follow the event stream from startup.
+  pub fn state_snapshot(&self) -> ServerStateSnapshot {
+    let client = if self.connected() {
+      self
+        .client
+        .read()
+        .expect("Client lock should never be poisoned.")
+        .clone()
+    } else {
+      None
+    };
+    ServerStateSnapshot::new(
+      &self.server_name,
+      client,
+      self.device_manager.device_state_snapshots(),
+      self.device_manager.unsupported_devices(),
+      self.device_manager.scanning(),
+      self
+        .device_manager
+        .comm_manager_metrics()
+        .iter()
+        .map(|metrics| CommManagerStateSnapshot::new(metrics))
+        .collect(),
+      self.device_manager.device_configuration_manager().version(),
+      BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
+    )
+  }
+
+  /// Creates a [ProgressReporter] for a long-running operation started by the request with id
+  /// `request_id`. Progress is sent out through the [event stream](ButtplugServer::event_stream).
+  /// Clients on message spec versions older than 3 have no way to receive progress, so for them
+  /// the reporter drops everything.
+  pub fn progress_reporter(&self, request_id: u32) -> ProgressReporter {
+    let client_spec_version = self
+      .client
+      .read()
+      .expect("Client lock should never be poisoned.")
+      .as_ref()
+      .map(|client| client.message_spec_version());
+    if matches!(client_spec_version, Some(version) if version >= ButtplugMessageSpecVersion::Version3)
+    {
+      ProgressReporter::new(request_id, self.output_sender.clone())
+    } else {
+      ProgressReporter::disabled()
+    }
+  }
+
   /// Disconnects the server from a client, if it is connected.
   pub fn disconnect(&self) -> BoxFuture<Result<(), message::Error>> {
     debug!("Buttplug Server {} disconnect requested", self.server_name);
@@ -420,6 +777,39 @@ impl ButtplugServer {
       self.server_name,
       msg
     );
+    #[cfg(feature = "serialize-json")]
+    let session_recorder = self.session_recorder.clone();
+    #[cfg(feature = "serialize-json")]
+    if let Some(recorder) = &session_recorder {
+      recorder.record_client_message(&msg);
+    }
+    #[cfg(feature = "serialize-json")]
+    let intensity_change = self.intensity_telemetry.as_ref().and_then(|telemetry| {
+      telemetry
+        .intensity_change(&msg, &self.device_manager)
+        .map(|change| (telemetry.clone(), change))
+    });
+    let reply_fut = self.handle_message(msg);
+    async move {
+      let reply = reply_fut.await;
+      #[cfg(feature = "serialize-json")]
+      if let Some(recorder) = session_recorder {
+        recorder.record_reply(&reply);
+      }
+      #[cfg(feature = "serialize-json")]
+      if let (Some((telemetry, change)), Ok(_)) = (intensity_change, &reply) {
+        telemetry.record(change);
+      }
+      reply
+    }
+    .boxed()
+  }
+
+  /// Produces the reply to a client message, tagged with the message id.
+  fn handle_message(
+    &self,
+    msg: ButtplugClientMessage,
+  ) -> BoxFuture<'static, Result<ButtplugServerMessage, message::Error>> {
     let id = msg.id();
     if !self.connected() {
       // Check for ping timeout first! There's no way we should've pinged out if
@@ -441,6 +831,10 @@ impl ButtplugServer {
         return future::ready(Err(return_error)).boxed();
       }
       // If we haven't pinged out and we got an RSI message, fall thru.
+    } else if let Err(err) = self.check_client_capabilities(&msg) {
+      let mut return_error = message::Error::from(ButtplugError::from(err));
+      return_error.set_id(id);
+      return future::ready(Err(return_error)).boxed();
     }
     // Produce whatever future is needed to reply to the message, this may be a
     // device command future, or something the server handles. All futures will
@@ -455,6 +849,9 @@ impl ButtplugServer {
       match msg {
         ButtplugClientMessage::RequestServerInfo(rsi_msg) => self.perform_handshake(rsi_msg),
         ButtplugClientMessage::Ping(p) => self.handle_ping(p),
+        // Test only exists in spec v0/v1, and the conversion layer will reject it for anything
+        // newer, so all we need to do here is echo it back.
+        ButtplugClientMessage::Test(t) => future::ready(Ok(t.into())).boxed(),
         _ => ButtplugMessageError::UnexpectedMessageType(format!("{:?}", msg)).into(),
       }
     };
@@ -477,6 +874,84 @@ impl ButtplugServer {
     .boxed()
   }
 
+  /// Parses a set of [ButtplugClientMessage]s that should take effect together, like a scene
+  /// changing the state of multiple devices at once.
+  ///
+  /// All messages are dispatched before any of them are waited on, so device commands go out
+  /// together instead of each one waiting for the previous device to finish its write. Replies are
+  /// returned in the same order as the messages.
+  pub fn parse_message_batch(
+    &self,
+    msgs: Vec<ButtplugClientMessage>,
+  ) -> BoxFuture<'static, Vec<Result<ButtplugServerMessage, message::Error>>> {
+    let reply_futs: Vec<_> = msgs
+      .into_iter()
+      .map(|msg| self.parse_message(msg))
+      .collect();
+    future::join_all(reply_futs).boxed()
+  }
+
+  /// Rejects messages the connected client said it wouldn't send, if it declared its capabilities
+  /// during the handshake.
+  fn check_client_capabilities(
+    &self,
+    msg: &ButtplugClientMessage,
+  ) -> Result<(), ButtplugMessageError> {
+    let client = self
+      .client
+      .read()
+      .expect("Client lock should never be poisoned.");
+    let capabilities =
+      if let Some(capabilities) = client.as_ref().and_then(|client| client.capabilities().as_ref()) {
+        capabilities
+      } else {
+        return Ok(());
+      };
+    let required_capability = match msg {
+      ButtplugClientMessage::RawWriteCmd(_)
+      | ButtplugClientMessage::RawReadCmd(_)
+      | ButtplugClientMessage::RawSubscribeCmd(_)
+      | ButtplugClientMessage::RawUnsubscribeCmd(_)
+      | ButtplugClientMessage::FirmwareModeCmd(_) => Some(ClientCapability::Raw),
+      ButtplugClientMessage::BatteryLevelCmd(_)
+      | ButtplugClientMessage::RSSILevelCmd(_)
+      | ButtplugClientMessage::SensorReadCmd(_)
+      | ButtplugClientMessage::SensorSubscribeCmd(_)
+      | ButtplugClientMessage::SensorUnsubscribeCmd(_) => Some(ClientCapability::Sensors),
+      _ => None,
+    };
+    if let Some(capability) = required_capability {
+      if !capabilities.contains(&capability) {
+        return Err(ButtplugMessageError::ClientCapabilityError(format!(
+          "{:?} capability was not declared",
+          capability
+        )));
+      }
+    }
+    // Spectators can still stop things, but nothing else that changes what devices are doing.
+    let moves_devices = matches!(
+      msg,
+      ButtplugClientMessage::StartScanning(_)
+        | ButtplugClientMessage::VibrateCmd(_)
+        | ButtplugClientMessage::LinearCmd(_)
+        | ButtplugClientMessage::RotateCmd(_)
+        | ButtplugClientMessage::ScalarCmd(_)
+        | ButtplugClientMessage::PatternCmd(_)
+        | ButtplugClientMessage::RawWriteCmd(_)
+        | ButtplugClientMessage::SingleMotorVibrateCmd(_)
+        | ButtplugClientMessage::FleshlightLaunchFW12Cmd(_)
+        | ButtplugClientMessage::LovenseCmd(_)
+        | ButtplugClientMessage::KiirooCmd(_)
+        | ButtplugClientMessage::VorzeA10CycloneCmd(_)
+    );
+    if moves_devices && capabilities.contains(&ClientCapability::Spectator) {
+      return Err(ButtplugMessageError::ClientCapabilityError(
+        "Spectator clients cannot move devices or start scanning".to_owned(),
+      ));
+    }
+    Ok(())
+  }
+
   /// Performs the [RequestServerInfo]([ServerInfo](crate::core::message::RequestServerInfo) /
   /// [ServerInfo](crate::core::message::ServerInfo) handshake, as specified in the [Buttplug
   /// Protocol Spec](https://buttplug-spec.docs.buttplug.io). This is the first thing that must
@@ -498,13 +973,38 @@ impl ButtplugServer {
       )
       .into();
     }
+    if let Some(capabilities) = msg.capabilities() {
+      if capabilities.contains(&ClientCapability::Raw) && !self.allow_raw_messages {
+        return ButtplugHandshakeError::CapabilityNotAvailable(format!("{:?}", ClientCapability::Raw))
+          .into();
+      }
+    }
     // Only start the ping timer after we've received the handshake.
     let ping_timer = self.ping_timer.clone();
-    let out_msg =
+    let mut out_msg =
       message::ServerInfo::new(&self.server_name, msg.message_version(), self.max_ping_time);
+    // Version information was added to ServerInfo in Spec v3, older clients won't expect it.
+    if msg.message_version() >= ButtplugMessageSpecVersion::Version3 {
+      out_msg.set_device_config_version(
+        self
+          .device_manager
+          .device_configuration_manager()
+          .version(),
+      );
+      out_msg.set_library_version(Some(env!("CARGO_PKG_VERSION").to_owned()));
+      out_msg.set_device_list_page_size(Some(self.device_manager.device_list_page_size()));
+    }
+    self.device_manager.set_client_locale(msg.locale().clone());
     let connected = self.connected.clone();
+    let client = self.client.clone();
+    let client_state = ClientStateSnapshot::new(
+      msg.client_name(),
+      msg.message_version(),
+      msg.capabilities().clone(),
+    );
     async move {
       ping_timer.start_ping_timer().await;
+      *client.write().expect("Client lock should never be poisoned.") = Some(client_state);
       connected.store(true, Ordering::SeqCst);
       debug!("Server handshake check successful.");
       Result::Ok(out_msg.into())
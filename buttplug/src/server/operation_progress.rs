@@ -0,0 +1,99 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Progress reporting for long-running server operations.
+
+use crate::core::message::{ButtplugServerMessage, OperationProgress};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+/// Reports how far along a long-running operation is, as
+/// [OperationProgress](crate::core::message::OperationProgress) events tagged with the id of the
+/// request that started it. Get one from
+/// [ButtplugServer::progress_reporter](super::ButtplugServer::progress_reporter).
+///
+/// Reporting progress doesn't finish the operation. Whatever started it still needs to reply to the
+/// request, which is how clients find out it's done (or failed).
+#[derive(Debug, Clone)]
+pub struct ProgressReporter {
+  request_id: u32,
+  /// Where progress events go, or None if nobody can receive them.
+  sender: Option<broadcast::Sender<Arc<ButtplugServerMessage>>>,
+  /// Last whole percentage sent, so callers can report every step without flooding the client.
+  last_percent: Arc<Mutex<Option<u32>>>,
+}
+
+impl ProgressReporter {
+  pub(super) fn new(request_id: u32, sender: broadcast::Sender<Arc<ButtplugServerMessage>>) -> Self {
+    Self {
+      request_id,
+      sender: Some(sender),
+      last_percent: Arc::new(Mutex::new(None)),
+    }
+  }
+
+  /// Reporter that drops all progress, for operations nobody is watching.
+  pub fn disabled() -> Self {
+    Self {
+      request_id: 0,
+      sender: None,
+      last_percent: Arc::new(Mutex::new(None)),
+    }
+  }
+
+  /// Reports that `completed` of `total` steps are done. An event is only sent when the whole
+  /// percentage done changes.
+  pub fn report(&self, completed: u32, total: u32) {
+    let sender = if let Some(sender) = &self.sender {
+      sender
+    } else {
+      return;
+    };
+    let progress = if total == 0 {
+      1.0
+    } else {
+      (completed.min(total) as f64) / (total as f64)
+    };
+    let percent = (progress * 100.0).floor() as u32;
+    {
+      let mut last_percent = self
+        .last_percent
+        .lock()
+        .expect("Progress lock should never be poisoned.");
+      if *last_percent == Some(percent) {
+        return;
+      }
+      *last_percent = Some(percent);
+    }
+    // No receivers just means no client is listening right now.
+    let _ = sender.send(Arc::new(
+      OperationProgress::new(self.request_id, progress).into(),
+    ));
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_progress_reporter_only_sends_percentage_changes() {
+    let (sender, mut receiver) = broadcast::channel(256);
+    let reporter = ProgressReporter::new(3, sender);
+    for step in 0..=1000 {
+      reporter.report(step, 1000);
+    }
+    let mut events = 0;
+    while let Ok(msg) = receiver.try_recv() {
+      if let ButtplugServerMessage::OperationProgress(progress) = &*msg {
+        assert_eq!(progress.request_id(), 3);
+        events += 1;
+      }
+    }
+    assert_eq!(events, 101);
+  }
+}
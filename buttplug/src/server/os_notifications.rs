@@ -0,0 +1,266 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Host OS notifications for critical server events.
+//!
+//! Applications hosting a server are often minimized or running in the tray while a session goes
+//! on, so users can miss a device going quiet. With [OsNotificationSettings] passed to
+//! [ButtplugServerBuilder::os_notifications](super::ButtplugServerBuilder::os_notifications), the
+//! server raises a desktop notification (through notify-rust, so the freedesktop notification
+//! service on Linux/BSD, Notification Center on macOS, and toasts on Windows) when:
+//!
+//! - A device battery reading comes in at or below the critical level. Each device is only
+//!   notified once until its battery reads above the level again.
+//! - A device disconnects while a client is connected.
+//! - The client stopped pinging, and the server stopped all devices.
+//!
+//! Each type of event can be turned off separately. Battery levels are only known when something
+//! reads them, so for battery notifications, devices need battery polling (see the device config
+//! `battery-poll-interval`) or a client subscribed to their battery sensor.
+
+use crate::core::{
+  errors::{ButtplugError, ButtplugPingError},
+  message::{ButtplugDeviceMessage, ButtplugServerMessage, SensorType},
+};
+use std::{collections::HashMap, thread};
+
+/// Critical events that can raise an OS notification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OsNotificationEvent {
+  /// Device battery read at or below the critical level.
+  BatteryCritical,
+  /// Device disconnected while a client was connected.
+  DeviceDisconnected,
+  /// Client stopped pinging, so all devices were stopped.
+  PingTimeout,
+}
+
+/// Which events raise OS notifications, and how they're shown.
+#[derive(Debug, Clone)]
+pub struct OsNotificationSettings {
+  app_name: String,
+  battery_critical_level: i32,
+  disabled_events: Vec<OsNotificationEvent>,
+}
+
+impl Default for OsNotificationSettings {
+  fn default() -> Self {
+    Self {
+      app_name: "Buttplug Server".to_owned(),
+      battery_critical_level: 10,
+      disabled_events: vec![],
+    }
+  }
+}
+
+impl OsNotificationSettings {
+  /// Application name notifications are shown under. Defaults to "Buttplug Server".
+  pub fn app_name(&mut self, app_name: &str) -> &mut Self {
+    self.app_name = app_name.to_owned();
+    self
+  }
+
+  /// Battery level, in percent, at or below which a battery is critically low. Defaults to 10.
+  pub fn battery_critical_level(&mut self, level: i32) -> &mut Self {
+    self.battery_critical_level = level;
+    self
+  }
+
+  /// Turn notifications for an event type on or off. All event types are on by default.
+  pub fn event(&mut self, event: OsNotificationEvent, enabled: bool) -> &mut Self {
+    self.disabled_events.retain(|disabled| *disabled != event);
+    if !enabled {
+      self.disabled_events.push(event);
+    }
+    self
+  }
+
+  pub fn event_enabled(&self, event: OsNotificationEvent) -> bool {
+    !self.disabled_events.contains(&event)
+  }
+}
+
+/// A notification to raise, worked out from a server event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct OsNotification {
+  event: OsNotificationEvent,
+  summary: String,
+  body: String,
+}
+
+/// Follows the server event stream, to tell which events are worth a notification.
+#[derive(Debug)]
+pub(super) struct OsNotificationWatcher {
+  settings: OsNotificationSettings,
+  /// Names of connected devices, by index, as removals only carry the index.
+  device_names: HashMap<u32, String>,
+  /// Devices already notified about a critical battery.
+  battery_notified: Vec<u32>,
+}
+
+impl OsNotificationWatcher {
+  pub fn new(settings: OsNotificationSettings) -> Self {
+    Self {
+      settings,
+      device_names: HashMap::new(),
+      battery_notified: vec![],
+    }
+  }
+
+  /// Returns the notification for a server event, if it calls for one.
+  pub fn notification(
+    &mut self,
+    msg: &ButtplugServerMessage,
+    client_connected: bool,
+  ) -> Option<OsNotification> {
+    let notification = match msg {
+      ButtplugServerMessage::DeviceAdded(device) => {
+        let name = device
+          .device_display_name()
+          .clone()
+          .unwrap_or_else(|| device.device_name().clone());
+        self.device_names.insert(device.device_index(), name);
+        None
+      }
+      ButtplugServerMessage::DeviceRemoved(removed) => {
+        let index = removed.device_index();
+        self.battery_notified.retain(|notified| *notified != index);
+        let name = self.device_names.remove(&index);
+        client_connected.then(|| OsNotification {
+          event: OsNotificationEvent::DeviceDisconnected,
+          summary: "Device disconnected".to_owned(),
+          body: format!(
+            "{} disconnected during the session.",
+            name.unwrap_or_else(|| format!("Device {}", index))
+          ),
+        })
+      }
+      ButtplugServerMessage::SensorReading(reading)
+        if reading.sensor_type() == SensorType::Battery =>
+      {
+        let index = reading.device_index();
+        let level = *reading.data().first()?;
+        if level > self.settings.battery_critical_level {
+          self.battery_notified.retain(|notified| *notified != index);
+          None
+        } else if self.battery_notified.contains(&index) {
+          None
+        } else {
+          self.battery_notified.push(index);
+          Some(OsNotification {
+            event: OsNotificationEvent::BatteryCritical,
+            summary: "Battery critically low".to_owned(),
+            body: format!(
+              "{} is at {}% battery.",
+              self
+                .device_names
+                .get(&index)
+                .cloned()
+                .unwrap_or_else(|| format!("Device {}", index)),
+              level
+            ),
+          })
+        }
+      }
+      ButtplugServerMessage::Error(error)
+        if error.original_error() == ButtplugError::from(ButtplugPingError::PingedOut) =>
+      {
+        Some(OsNotification {
+          event: OsNotificationEvent::PingTimeout,
+          summary: "Devices stopped".to_owned(),
+          body: "The client stopped responding, so all devices were stopped.".to_owned(),
+        })
+      }
+      _ => None,
+    }?;
+    self
+      .settings
+      .event_enabled(notification.event)
+      .then_some(notification)
+  }
+
+  /// Shows a notification on the host OS. Showing can block on the notification service, so it
+  /// happens on its own thread.
+  pub fn show(&self, notification: OsNotification) {
+    let app_name = self.settings.app_name.clone();
+    thread::spawn(move || {
+      let mut os_notification = notify_rust::Notification::new();
+      os_notification
+        .appname(&app_name)
+        .summary(&notification.summary)
+        .body(&notification.body);
+      #[cfg(all(unix, not(target_os = "macos")))]
+      os_notification.urgency(notify_rust::Urgency::Critical);
+      if let Err(e) = os_notification.show() {
+        warn!("Could not show OS notification: {:?}", e);
+      }
+    });
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::core::message::{DeviceAdded, DeviceRemoved, Error, SensorReading};
+
+  fn battery(level: i32) -> ButtplugServerMessage {
+    SensorReading::new(0, 0, SensorType::Battery, vec![level]).into()
+  }
+
+  #[test]
+  fn test_os_notification_events() {
+    let mut settings = OsNotificationSettings::default();
+    settings.battery_critical_level(15);
+    let mut watcher = OsNotificationWatcher::new(settings);
+    let added: ButtplugServerMessage =
+      DeviceAdded::new(0, "Lovense Hush", &None, &None, &Default::default()).into();
+    assert!(watcher.notification(&added, true).is_none());
+
+    assert!(watcher.notification(&battery(50), true).is_none());
+    let notification = watcher
+      .notification(&battery(15), true)
+      .expect("Test, assuming infallible");
+    assert_eq!(notification.event, OsNotificationEvent::BatteryCritical);
+    assert_eq!(notification.body, "Lovense Hush is at 15% battery.");
+    // Only once, until the battery reads above the level again.
+    assert!(watcher.notification(&battery(12), true).is_none());
+    assert!(watcher.notification(&battery(40), true).is_none());
+    assert!(watcher.notification(&battery(10), true).is_some());
+
+    let pinged_out: ButtplugServerMessage =
+      Error::from(ButtplugError::from(ButtplugPingError::PingedOut)).into();
+    assert_eq!(
+      watcher
+        .notification(&pinged_out, false)
+        .map(|notification| notification.event),
+      Some(OsNotificationEvent::PingTimeout)
+    );
+
+    let removed: ButtplugServerMessage = DeviceRemoved::new(0).into();
+    let notification = watcher
+      .notification(&removed, true)
+      .expect("Test, assuming infallible");
+    assert_eq!(notification.event, OsNotificationEvent::DeviceDisconnected);
+    assert_eq!(
+      notification.body,
+      "Lovense Hush disconnected during the session."
+    );
+    // Without a client, there's no session to interrupt.
+    assert!(watcher.notification(&added, false).is_none());
+    assert!(watcher.notification(&removed, false).is_none());
+  }
+
+  #[test]
+  fn test_os_notification_events_disabled() {
+    let mut settings = OsNotificationSettings::default();
+    settings.event(OsNotificationEvent::BatteryCritical, false);
+    assert!(!settings.event_enabled(OsNotificationEvent::BatteryCritical));
+    assert!(settings.event_enabled(OsNotificationEvent::PingTimeout));
+    let mut watcher = OsNotificationWatcher::new(settings);
+    assert!(watcher.notification(&battery(1), true).is_none());
+  }
+}
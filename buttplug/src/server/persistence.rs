@@ -0,0 +1,326 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Storage for what the server remembers between runs.
+//!
+//! A [ButtplugPersistence] given to
+//! [ButtplugServerBuilder::persistence](super::ButtplugServerBuilder::persistence) stores blobs of
+//! bytes, each under a key within a namespace. That's all the server needs from it, so hosts can
+//! back it with whatever their platform offers (preference stores on phones, IndexedDB in browsers,
+//! etc), or use [FilePersistence] if they have a writable directory. [MemoryPersistence] only keeps
+//! blobs for as long as the process runs.
+//!
+//! The server keeps its device configuration there through [PersistedDeviceConfiguration]: indexes
+//! given to devices are saved as they're handed out, so devices keep their index across restarts,
+//! display names and allow/deny list changes made through the
+//! [DeviceConfigurationManager](super::device::configuration::DeviceConfigurationManager) are saved
+//! as they're made, and everything stored (including sensor calibrations stored by the host) is
+//! loaded when the server is built.
+
+use super::device::{SensorCalibration, ServerDeviceIdentifier};
+use crate::core::message::SensorType;
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+  collections::HashMap,
+  fmt,
+  fs,
+  io,
+  path::PathBuf,
+  sync::{Arc, Mutex},
+};
+use thiserror::Error;
+
+/// Errors that can happen while loading or storing persisted data.
+#[derive(Error, Debug)]
+pub enum PersistenceError {
+  /// Persisted data could not be read or written.
+  #[error("Could not access persisted data: {0}")]
+  Io(#[from] io::Error),
+  /// Storage provided by the host failed.
+  #[error("Persistence backend failed: {0}")]
+  Backend(String),
+  /// Namespace or key can't be used by the storage.
+  #[error("{0} is not a valid persistence namespace or key")]
+  InvalidName(String),
+  /// A stored blob couldn't be parsed, by namespace and key.
+  #[error("Persisted {0}/{1} is not valid: {2}")]
+  InvalidData(String, String, String),
+}
+
+/// Storage for blobs of bytes, each stored under a key within a namespace.
+///
+/// Namespaces and keys used by the library are lowercase ASCII words separated by dashes, so
+/// implementations can use them as file names, preference keys, etc, without escaping them.
+pub trait ButtplugPersistence: Send + Sync {
+  /// Returns the blob stored under `key` in `namespace`, or None if nothing has been stored there.
+  fn get(&self, namespace: &str, key: &str) -> Result<Option<Vec<u8>>, PersistenceError>;
+  /// Stores a blob under `key` in `namespace`, replacing whatever was there.
+  fn set(&self, namespace: &str, key: &str, value: &[u8]) -> Result<(), PersistenceError>;
+}
+
+/// Stores each blob in its own file, at `<directory>/<namespace>/<key>`.
+#[derive(Debug, Clone)]
+pub struct FilePersistence {
+  directory: PathBuf,
+}
+
+impl FilePersistence {
+  /// Store blobs under `directory`, which is created when the first blob is stored.
+  pub fn new<P>(directory: P) -> Self
+  where
+    P: Into<PathBuf>,
+  {
+    Self {
+      directory: directory.into(),
+    }
+  }
+
+  fn blob_path(&self, namespace: &str, key: &str) -> Result<PathBuf, PersistenceError> {
+    // Keep names from escaping the directory, or colliding with the temporary files used by set.
+    for name in [namespace, key] {
+      if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(PersistenceError::InvalidName(name.to_owned()));
+      }
+    }
+    Ok(self.directory.join(namespace).join(key))
+  }
+}
+
+impl ButtplugPersistence for FilePersistence {
+  fn get(&self, namespace: &str, key: &str) -> Result<Option<Vec<u8>>, PersistenceError> {
+    match fs::read(self.blob_path(namespace, key)?) {
+      Ok(value) => Ok(Some(value)),
+      Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+      Err(err) => Err(err.into()),
+    }
+  }
+
+  fn set(&self, namespace: &str, key: &str, value: &[u8]) -> Result<(), PersistenceError> {
+    let path = self.blob_path(namespace, key)?;
+    let namespace_directory = self.directory.join(namespace);
+    fs::create_dir_all(&namespace_directory)?;
+    // Write to a temporary file first, so a crash mid-write leaves the old blob instead of half of
+    // the new one.
+    let temp_path = namespace_directory.join(format!(".{}.tmp", key));
+    fs::write(&temp_path, value)?;
+    fs::rename(&temp_path, &path)?;
+    Ok(())
+  }
+}
+
+/// Keeps blobs in memory, so nothing outlives the process. Useful for tests, and hosts without any
+/// storage.
+#[derive(Debug, Default)]
+pub struct MemoryPersistence {
+  blobs: Mutex<HashMap<(String, String), Vec<u8>>>,
+}
+
+impl ButtplugPersistence for MemoryPersistence {
+  fn get(&self, namespace: &str, key: &str) -> Result<Option<Vec<u8>>, PersistenceError> {
+    Ok(
+      self
+        .blobs
+        .lock()
+        .expect("Lock is never held across a panic.")
+        .get(&(namespace.to_owned(), key.to_owned()))
+        .cloned(),
+    )
+  }
+
+  fn set(&self, namespace: &str, key: &str, value: &[u8]) -> Result<(), PersistenceError> {
+    self
+      .blobs
+      .lock()
+      .expect("Lock is never held across a panic.")
+      .insert((namespace.to_owned(), key.to_owned()), value.to_vec());
+    Ok(())
+  }
+}
+
+const DEVICE_CONFIGURATION_NAMESPACE: &str = "device-configuration";
+const RESERVED_INDEXES_KEY: &str = "reserved-indexes";
+const ALLOWED_ADDRESSES_KEY: &str = "allowed-addresses";
+const DENIED_ADDRESSES_KEY: &str = "denied-addresses";
+const SENSOR_CALIBRATIONS_KEY: &str = "sensor-calibrations";
+const DISPLAY_NAMES_KEY: &str = "display-names";
+
+/// Device configuration kept in a [ButtplugPersistence], stored as JSON in the
+/// `device-configuration` namespace.
+///
+/// When a server is built, this is applied along with the configuration given to the builder.
+/// Configured reserved indexes win over persisted ones that conflict with them, while persisted
+/// calibrations replace configured calibrations for the same sensor. Changes made here by the host
+/// apply the next time a server is built.
+#[derive(Clone)]
+pub struct PersistedDeviceConfiguration {
+  persistence: Arc<dyn ButtplugPersistence>,
+}
+
+impl fmt::Debug for PersistedDeviceConfiguration {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("PersistedDeviceConfiguration").finish()
+  }
+}
+
+impl PersistedDeviceConfiguration {
+  pub fn new(persistence: Arc<dyn ButtplugPersistence>) -> Self {
+    Self { persistence }
+  }
+
+  fn get<T>(&self, key: &str) -> Result<T, PersistenceError>
+  where
+    T: DeserializeOwned + Default,
+  {
+    match self.persistence.get(DEVICE_CONFIGURATION_NAMESPACE, key)? {
+      Some(value) => serde_json::from_slice(&value).map_err(|err| {
+        PersistenceError::InvalidData(
+          DEVICE_CONFIGURATION_NAMESPACE.to_owned(),
+          key.to_owned(),
+          err.to_string(),
+        )
+      }),
+      None => Ok(T::default()),
+    }
+  }
+
+  fn set<T>(&self, key: &str, value: &T) -> Result<(), PersistenceError>
+  where
+    T: Serialize + ?Sized,
+  {
+    let value = serde_json::to_vec(value)
+      .expect("All persisted types are Serialize, so this should be infallible.");
+    self
+      .persistence
+      .set(DEVICE_CONFIGURATION_NAMESPACE, key, &value)
+  }
+
+  /// Indexes given to devices, saved by the server as it hands them out.
+  pub fn reserved_indexes(&self) -> Result<Vec<(ServerDeviceIdentifier, u32)>, PersistenceError> {
+    self.get(RESERVED_INDEXES_KEY)
+  }
+
+  pub fn set_reserved_indexes(
+    &self,
+    indexes: &[(ServerDeviceIdentifier, u32)],
+  ) -> Result<(), PersistenceError> {
+    self.set(RESERVED_INDEXES_KEY, indexes)
+  }
+
+  /// Addresses added to the allow list, in the same form as
+  /// [ButtplugServerBuilder::allowed_address](super::ButtplugServerBuilder::allowed_address).
+  pub fn allowed_addresses(&self) -> Result<Vec<String>, PersistenceError> {
+    self.get(ALLOWED_ADDRESSES_KEY)
+  }
+
+  pub fn set_allowed_addresses(&self, addresses: &[String]) -> Result<(), PersistenceError> {
+    self.set(ALLOWED_ADDRESSES_KEY, addresses)
+  }
+
+  /// Addresses added to the deny list, in the same form as
+  /// [ButtplugServerBuilder::denied_address](super::ButtplugServerBuilder::denied_address).
+  pub fn denied_addresses(&self) -> Result<Vec<String>, PersistenceError> {
+    self.get(DENIED_ADDRESSES_KEY)
+  }
+
+  pub fn set_denied_addresses(&self, addresses: &[String]) -> Result<(), PersistenceError> {
+    self.set(DENIED_ADDRESSES_KEY, addresses)
+  }
+
+  /// Calibrations for the sensors of specific devices, by sensor index and type.
+  pub fn sensor_calibrations(
+    &self,
+  ) -> Result<Vec<(ServerDeviceIdentifier, u32, SensorType, SensorCalibration)>, PersistenceError>
+  {
+    self.get(SENSOR_CALIBRATIONS_KEY)
+  }
+
+  pub fn set_sensor_calibrations(
+    &self,
+    calibrations: &[(ServerDeviceIdentifier, u32, SensorType, SensorCalibration)],
+  ) -> Result<(), PersistenceError> {
+    self.set(SENSOR_CALIBRATIONS_KEY, calibrations)
+  }
+
+  /// Display names given to specific devices, which are used over any from the user config.
+  pub fn display_names(&self) -> Result<Vec<(ServerDeviceIdentifier, String)>, PersistenceError> {
+    self.get(DISPLAY_NAMES_KEY)
+  }
+
+  pub fn set_display_names(
+    &self,
+    display_names: &[(ServerDeviceIdentifier, String)],
+  ) -> Result<(), PersistenceError> {
+    self.set(DISPLAY_NAMES_KEY, display_names)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::server::device::configuration::ProtocolAttributesType;
+
+  #[test]
+  fn test_file_persistence() {
+    let directory = std::env::temp_dir().join(format!(
+      "buttplug-test-persistence-{}",
+      std::process::id()
+    ));
+    let persistence = FilePersistence::new(&directory);
+    assert!(persistence
+      .get("namespace", "key")
+      .expect("Test, assuming infallible.")
+      .is_none());
+    persistence
+      .set("namespace", "key", b"first")
+      .expect("Test, assuming infallible.");
+    persistence
+      .set("namespace", "key", b"second")
+      .expect("Test, assuming infallible.");
+    assert_eq!(
+      persistence
+        .get("namespace", "key")
+        .expect("Test, assuming infallible."),
+      Some(b"second".to_vec())
+    );
+    for (namespace, key) in [("..", "key"), ("namespace", "a/b"), ("namespace", "")] {
+      assert!(matches!(
+        persistence.set(namespace, key, b"value"),
+        Err(PersistenceError::InvalidName(_))
+      ));
+    }
+    fs::remove_dir_all(&directory).expect("Test, assuming infallible.");
+  }
+
+  #[test]
+  fn test_persisted_device_configuration() {
+    let persistence = Arc::new(MemoryPersistence::default());
+    let config = PersistedDeviceConfiguration::new(persistence.clone());
+    assert!(config
+      .reserved_indexes()
+      .expect("Test, assuming infallible.")
+      .is_empty());
+    let indexes = vec![(
+      ServerDeviceIdentifier::new("Address", "lovense", &ProtocolAttributesType::Default),
+      3,
+    )];
+    config
+      .set_reserved_indexes(&indexes)
+      .expect("Test, assuming infallible.");
+    assert_eq!(
+      config.reserved_indexes().expect("Test, assuming infallible."),
+      indexes
+    );
+
+    persistence
+      .set(DEVICE_CONFIGURATION_NAMESPACE, DENIED_ADDRESSES_KEY, b"{")
+      .expect("Test, assuming infallible.");
+    assert!(matches!(
+      config.denied_addresses(),
+      Err(PersistenceError::InvalidData(..))
+    ));
+  }
+}
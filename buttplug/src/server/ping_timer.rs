@@ -15,6 +15,7 @@ use std::{
   time::Duration,
 };
 use tokio::sync::{mpsc, Notify};
+use tokio_util::sync::CancellationToken;
 
 pub enum PingMessage {
   Ping,
@@ -63,10 +64,12 @@ pub struct PingTimer {
   ping_msg_sender: mpsc::Sender<PingMessage>,
   ping_timeout_notifier: Arc<Notify>,
   pinged_out: Arc<AtomicBool>,
+  dropped: CancellationToken,
 }
 
 impl Drop for PingTimer {
   fn drop(&mut self) {
+    self.dropped.cancel();
     // This cannot block, otherwise it will throw in WASM contexts on
     // destruction. We must use send(), not blocking_send().
     let sender = self.ping_msg_sender.clone();
@@ -97,13 +100,19 @@ impl PingTimer {
       ping_msg_sender: sender,
       ping_timeout_notifier,
       pinged_out,
+      dropped: CancellationToken::new(),
     }
   }
 
-  pub fn ping_timeout_waiter(&self) -> impl Future<Output = ()> {
+  /// Resolves to true once the client pings out, or to false if the timer is dropped first.
+  pub fn ping_timeout_waiter(&self) -> impl Future<Output = bool> {
     let notify = self.ping_timeout_notifier.clone();
+    let dropped = self.dropped.clone();
     async move {
-      notify.notified().await;
+      select! {
+        _ = notify.notified().fuse() => true,
+        _ = dropped.cancelled().fuse() => false,
+      }
     }
   }
 
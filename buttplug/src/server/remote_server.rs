@@ -0,0 +1,282 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Runs a [ButtplugServer] behind a connector, for clients in other processes or on other machines.
+
+use super::{ButtplugServer, ButtplugServerBuilder};
+use crate::{
+  core::{
+    connector::ButtplugConnector,
+    errors::{ButtplugError, ButtplugHandshakeError},
+    message::{
+      self,
+      ButtplugClientMessage,
+      ButtplugMessage,
+      ButtplugMessageValidator,
+      ButtplugServerMessage,
+    },
+  },
+  util::async_manager,
+};
+use futures::{future::Future, stream::FuturesUnordered, FutureExt, Stream, StreamExt};
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::sync::{mpsc, Notify};
+
+/// Errors that can happen while bringing up a [ButtplugRemoteServer] connection.
+#[derive(Error, Debug)]
+pub enum ButtplugServerConnectorError {
+  #[error("Cannot bring up server for connection: {0}")]
+  ConnectorError(String),
+  #[error("Lost connection to client")]
+  ConnectionLost,
+}
+
+/// Wraps a [ButtplugServer] and relays messages between it and a server connector.
+///
+/// The server (and therefore all of its connected devices) outlives individual client
+/// connections, so [ButtplugRemoteServer::start] can be called again after a client leaves. To
+/// serve several clients at once, use [ButtplugRemoteServer::start_sessions].
+pub struct ButtplugRemoteServer {
+  server: Arc<ButtplugServer>,
+  disconnect_notifier: Arc<Notify>,
+}
+
+/// Runs a set of messages that arrived together through the server as one batch, so device commands
+/// sent together go out together. Replies are in the same order as the messages.
+async fn parse_client_messages(
+  server: &ButtplugServer,
+  client_messages: Vec<ButtplugClientMessage>,
+) -> Vec<ButtplugServerMessage> {
+  // Remote connectors turn invalid messages away before they get here, but other connectors may
+  // not. Those are answered here, and left out of the batch.
+  let mut rejections = vec![];
+  let mut valid_messages = vec![];
+  for client_message in client_messages {
+    if let Err(e) = client_message.is_valid() {
+      error!("Message not valid: {:?} - Error: {}", client_message, e);
+      let mut err_msg = message::Error::from(ButtplugError::from(e));
+      err_msg.set_id(client_message.id());
+      rejections.push(Some(err_msg.into()));
+    } else {
+      rejections.push(None);
+      valid_messages.push(client_message);
+    }
+  }
+  let mut replies = server
+    .parse_message_batch(valid_messages)
+    .await
+    .into_iter()
+    .map(|reply| reply.unwrap_or_else(|e| e.into()));
+  rejections
+    .into_iter()
+    .map(|rejection| {
+      rejection.unwrap_or_else(|| {
+        replies
+          .next()
+          .expect("The server replies once for each message in a batch.")
+      })
+    })
+    .collect()
+}
+
+async fn run_server<ConnectorType>(
+  server: Arc<ButtplugServer>,
+  connector: ConnectorType,
+  mut connector_receiver: mpsc::Receiver<Vec<ButtplugClientMessage>>,
+  disconnect_notifier: Arc<Notify>,
+) -> bool
+where
+  ConnectorType: ButtplugConnector<ButtplugServerMessage, ButtplugClientMessage> + 'static,
+{
+  info!("Starting remote server loop");
+  let shared_connector = Arc::new(connector);
+  let server_receiver = server.event_stream();
+  pin_mut!(server_receiver);
+  loop {
+    select! {
+      connector_msg = connector_receiver.recv().fuse() => match connector_msg {
+        None => {
+          info!("Connector disconnected, exiting loop.");
+          break;
+        }
+        Some(client_messages) => {
+          trace!("Got messages from connector: {:?}", client_messages);
+          let server_clone = server.clone();
+          let connector_clone = shared_connector.clone();
+          async_manager::spawn(async move {
+            let replies = parse_client_messages(&server_clone, client_messages).await;
+            if connector_clone.send_batch(replies).await.is_err() {
+              error!("Cannot send reply to client, dropping and assuming remote server thread has exited.");
+            }
+          });
+        }
+      },
+      _ = disconnect_notifier.notified().fuse() => {
+        info!("Server disconnected via controller request, exiting loop.");
+        break;
+      },
+      _ = shared_connector.takeover_requested().fuse() => {
+        info!("New client is taking over the connection, disconnecting current client.");
+        // Stops scanning and all devices, and lets the new client run the handshake again.
+        if let Err(err) = server.disconnect().await {
+          error!("Error disconnecting server for takeover: {:?}", err);
+        }
+        let takeover_error =
+          message::Error::from(ButtplugError::from(ButtplugHandshakeError::ConnectionTakenOver));
+        if shared_connector.send(takeover_error.into()).await.is_err() {
+          error!("Cannot tell current client about takeover, exiting remote server loop.");
+          break;
+        }
+        if let Err(err) = shared_connector.complete_takeover().await {
+          error!("Cannot complete client takeover, exiting remote server loop: {:?}", err);
+          break;
+        }
+      },
+      server_msg = server_receiver.next().fuse() => match server_msg {
+        None => {
+          info!("Server disconnected via server disappearance, exiting loop.");
+          break;
+        }
+        Some(msg) => {
+          if shared_connector.send(Arc::unwrap_or_clone(msg)).await.is_err() {
+            error!("Server disappeared, exiting remote server thread.");
+          }
+        }
+      },
+    };
+  }
+  if let Err(err) = server.disconnect().await {
+    error!("Error disconnecting server: {:?}", err);
+  }
+  info!("Exiting remote server loop");
+  shared_connector.connection_lost()
+}
+
+/// Connects the connector and relays messages between it and `server` until either side
+/// disconnects.
+async fn run_connection<ConnectorType>(
+  server: Arc<ButtplugServer>,
+  mut connector: ConnectorType,
+  disconnect_notifier: Arc<Notify>,
+) -> Result<(), ButtplugServerConnectorError>
+where
+  ConnectorType: ButtplugConnector<ButtplugServerMessage, ButtplugClientMessage> + 'static,
+{
+  let (connector_sender, connector_receiver) = mpsc::channel(256);
+  connector
+    .connect_batched(connector_sender)
+    .await
+    .map_err(|e| ButtplugServerConnectorError::ConnectorError(format!("{:?}", e)))?;
+  if run_server(server, connector, connector_receiver, disconnect_notifier).await {
+    Err(ButtplugServerConnectorError::ConnectionLost)
+  } else {
+    Ok(())
+  }
+}
+
+impl Default for ButtplugRemoteServer {
+  fn default() -> Self {
+    Self::new(
+      ButtplugServerBuilder::default()
+        .finish()
+        .expect("Default is infallible"),
+    )
+  }
+}
+
+impl ButtplugRemoteServer {
+  pub fn new(server: ButtplugServer) -> Self {
+    Self {
+      server: Arc::new(server),
+      disconnect_notifier: Arc::new(Notify::new()),
+    }
+  }
+
+  /// Get a reference to the wrapped server.
+  pub fn server(&self) -> &ButtplugServer {
+    &self.server
+  }
+
+  /// Connects the connector and relays messages until either side disconnects. Returns
+  /// [ButtplugServerConnectorError::ConnectionLost] if the connector's transport lost the client
+  /// rather than the client disconnecting.
+  pub fn start<ConnectorType>(
+    &self,
+    connector: ConnectorType,
+  ) -> impl Future<Output = Result<(), ButtplugServerConnectorError>>
+  where
+    ConnectorType: ButtplugConnector<ButtplugServerMessage, ButtplugClientMessage> + 'static,
+  {
+    run_connection(
+      self.server.clone(),
+      connector,
+      self.disconnect_notifier.clone(),
+    )
+  }
+
+  /// Serves a client for every connector `connectors` produces, all at once, each in its own
+  /// [session](ButtplugServer::session) of the wrapped server. Resolves once `connectors` has
+  /// ended and every client has left. [ButtplugRemoteServer::disconnect] drops every client
+  /// connected at the time.
+  ///
+  /// For websockets, the transports of a
+  /// [ButtplugWebsocketServerListener](crate::core::connector::ButtplugWebsocketServerListener)
+  /// make the connectors.
+  pub fn start_sessions<ConnectorType, ConnectorStream>(
+    &self,
+    connectors: ConnectorStream,
+  ) -> impl Future<Output = ()>
+  where
+    ConnectorType: ButtplugConnector<ButtplugServerMessage, ButtplugClientMessage> + 'static,
+    ConnectorStream: Stream<Item = ConnectorType>,
+  {
+    let server = self.server.clone();
+    let disconnect_notifier = self.disconnect_notifier.clone();
+    async move {
+      let connectors = connectors.fuse();
+      pin_mut!(connectors);
+      let mut sessions = FuturesUnordered::new();
+      loop {
+        select! {
+          connector = connectors.select_next_some() => {
+            info!("New client connected, starting session.");
+            sessions.push(run_connection(
+              Arc::new(server.session()),
+              connector,
+              disconnect_notifier.clone(),
+            ));
+          },
+          result = sessions.select_next_some() => {
+            if let Err(err) = result {
+              warn!("Client session ended with error: {:?}", err);
+            }
+          },
+          complete => break,
+        }
+      }
+    }
+  }
+
+  /// Drops the current client connection, if any, leaving the server and its devices up.
+  pub async fn disconnect(&self) -> Result<(), ButtplugError> {
+    self.disconnect_notifier.notify_waiters();
+    Ok(())
+  }
+
+  /// Shuts down the wrapped server, disconnecting all devices.
+  pub async fn shutdown(&self) -> Result<(), ButtplugError> {
+    self.server.shutdown().await?;
+    Ok(())
+  }
+}
+
+impl Drop for ButtplugRemoteServer {
+  fn drop(&mut self) {
+    self.disconnect_notifier.notify_waiters();
+  }
+}
@@ -0,0 +1,533 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Recording server sessions to a file, and replaying them against another server.
+//!
+//! A [SessionRecorder] attached through
+//! [ButtplugServerBuilder::session_recorder](super::ButtplugServerBuilder::session_recorder) writes
+//! every message a client sends, every reply, and every event the server emits to a file, one JSON
+//! object per line, each stamped with the milliseconds since recording started. Messages are stored
+//! in the current message spec, so recordings from clients on any spec version replay the same way.
+//!
+//! A [SessionReplayer] loads that file and sends the client side of it to a new server, usually one
+//! running virtual devices (see [SessionReplayer::virtual_devices]), so a user's bug report can be
+//! reproduced without their hardware. Device indexes are remapped as the replay goes: each device
+//! the recording saw connect is paired with a device connecting to the replay server, preferring one
+//! with the same name, and client messages are rewritten to address the paired device.
+
+#[cfg(feature = "virtual-device-manager")]
+use super::device::hardware::communication::virtual_device::{
+  VirtualDeviceCommunicationManagerBuilder,
+  VirtualDeviceType,
+};
+use super::ButtplugServer;
+#[cfg(feature = "virtual-device-manager")]
+use crate::core::message::SensorType;
+use crate::{
+  core::message::{
+    self,
+    ButtplugClientMessage,
+    ButtplugCurrentSpecClientMessage,
+    ButtplugCurrentSpecServerMessage,
+    ButtplugDeviceCommandMessageUnion,
+    ButtplugDeviceMessage,
+    ButtplugMessage,
+    ButtplugServerMessage,
+    DeviceAdded,
+  },
+  util::sleep,
+};
+use futures::{pin_mut, select, FutureExt, Stream, StreamExt};
+use getset::{CopyGetters, Getters};
+use serde::{Deserialize, Serialize};
+use std::{
+  collections::HashMap,
+  fmt,
+  fs::File,
+  io::{self, BufRead, BufReader, BufWriter, Write},
+  mem,
+  path::Path,
+  sync::{Arc, Mutex},
+  time::{Duration, Instant},
+};
+use thiserror::Error;
+
+/// Errors that can happen while loading or replaying a session recording.
+#[derive(Error, Debug)]
+pub enum SessionRecordingError {
+  /// Recording file could not be opened or read.
+  #[error("Could not read session recording: {0}")]
+  Io(#[from] io::Error),
+  /// A line of the recording is not a valid entry.
+  #[error("Session recording line {0} is not a valid entry: {1}")]
+  InvalidEntry(usize, String),
+  /// No device connected to the replay server to stand in for one from the recording.
+  #[error("No device connected to replace recorded device {0} ({1})")]
+  DeviceNotReplayed(u32, String),
+}
+
+/// What happened at a point in a recorded session.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SessionRecordEvent {
+  /// Message sent to the server by the client.
+  ClientMessage(ButtplugCurrentSpecClientMessage),
+  /// Server reply to a client message.
+  ServerReply(ButtplugCurrentSpecServerMessage),
+  /// Event sent by the server without the client asking, like DeviceAdded.
+  ServerEvent(ButtplugCurrentSpecServerMessage),
+}
+
+/// A single line of a session recording.
+#[derive(Debug, Clone, PartialEq, Getters, CopyGetters, Serialize, Deserialize)]
+pub struct SessionRecordEntry {
+  /// Milliseconds between the start of the recording and this entry.
+  #[serde(rename = "ElapsedMs")]
+  #[getset(get_copy = "pub")]
+  elapsed_ms: u64,
+  #[serde(flatten)]
+  #[getset(get = "pub")]
+  event: SessionRecordEvent,
+}
+
+impl SessionRecordEntry {
+  pub fn new(elapsed_ms: u64, event: SessionRecordEvent) -> Self {
+    Self { elapsed_ms, event }
+  }
+}
+
+/// Writes a server session to a file as it happens. See the [module docs](self) for the format.
+pub struct SessionRecorder {
+  writer: Mutex<Box<dyn Write + Send>>,
+  start: Instant,
+}
+
+impl fmt::Debug for SessionRecorder {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("SessionRecorder")
+      .field("start", &self.start)
+      .finish()
+  }
+}
+
+impl SessionRecorder {
+  /// Record to any writer. Each entry is flushed as it is written, so a recording is still usable
+  /// if the process hosting the server crashes.
+  pub fn new<T>(writer: T) -> Self
+  where
+    T: Write + Send + 'static,
+  {
+    Self {
+      writer: Mutex::new(Box::new(writer)),
+      start: Instant::now(),
+    }
+  }
+
+  /// Record to a file, creating or truncating it.
+  pub fn to_file<P: AsRef<Path>>(path: P) -> Result<Self, io::Error> {
+    Ok(Self::new(BufWriter::new(File::create(path)?)))
+  }
+
+  pub(super) fn record_client_message(&self, msg: &ButtplugClientMessage) {
+    match ButtplugCurrentSpecClientMessage::try_from(msg.clone()) {
+      Ok(msg) => self.record(SessionRecordEvent::ClientMessage(msg)),
+      Err(_) => debug!(
+        "Client message {:?} has no current spec equivalent, not recording it.",
+        msg
+      ),
+    }
+  }
+
+  pub(super) fn record_reply(&self, reply: &Result<ButtplugServerMessage, message::Error>) {
+    let msg = match reply {
+      Ok(msg) => msg.clone(),
+      Err(err) => ButtplugServerMessage::Error(err.clone()),
+    };
+    if let Some(msg) = Self::current_spec_server_message(msg) {
+      self.record(SessionRecordEvent::ServerReply(msg));
+    }
+  }
+
+  pub(super) fn record_server_event(&self, msg: Arc<ButtplugServerMessage>) {
+    if let Some(msg) = Self::current_spec_server_message(Arc::unwrap_or_clone(msg)) {
+      self.record(SessionRecordEvent::ServerEvent(msg));
+    }
+  }
+
+  fn current_spec_server_message(
+    msg: ButtplugServerMessage,
+  ) -> Option<ButtplugCurrentSpecServerMessage> {
+    ButtplugCurrentSpecServerMessage::try_from(msg)
+      .map_err(|e| debug!("Not recording server message: {:?}", e))
+      .ok()
+  }
+
+  fn record(&self, event: SessionRecordEvent) {
+    let entry = SessionRecordEntry::new(self.start.elapsed().as_millis() as u64, event);
+    let line = match serde_json::to_string(&entry) {
+      Ok(line) => line,
+      Err(e) => {
+        error!("Could not serialize session recording entry: {:?}", e);
+        return;
+      }
+    };
+    let mut writer = self
+      .writer
+      .lock()
+      .expect("Recorder lock should never be poisoned.");
+    if let Err(e) = writeln!(writer, "{}", line).and_then(|_| writer.flush()) {
+      error!("Could not write session recording entry: {:?}", e);
+    }
+  }
+}
+
+/// The reply a replayed server gave to a client message, next to the one in the recording.
+#[derive(Debug, Clone, PartialEq, Getters, CopyGetters)]
+pub struct SessionReplayReply {
+  /// Id of the client message, as recorded.
+  #[getset(get_copy = "pub")]
+  id: u32,
+  /// Reply stored in the recording, if it has one.
+  #[getset(get = "pub")]
+  recorded: Option<ButtplugCurrentSpecServerMessage>,
+  /// Reply from the replay server.
+  #[getset(get = "pub")]
+  replayed: ButtplugCurrentSpecServerMessage,
+}
+
+impl SessionReplayReply {
+  /// True if the replay server answered with the same message type, and for errors the same error
+  /// code, as the recording. Contents aren't compared, since virtual devices won't report the same
+  /// readings or names as the hardware the recording came from.
+  pub fn matches(&self) -> bool {
+    match (&self.recorded, &self.replayed) {
+      (None, _) => true,
+      (
+        Some(ButtplugCurrentSpecServerMessage::Error(recorded)),
+        ButtplugCurrentSpecServerMessage::Error(replayed),
+      ) => recorded.error_code() == replayed.error_code(),
+      (Some(recorded), replayed) => mem::discriminant(recorded) == mem::discriminant(replayed),
+    }
+  }
+}
+
+/// Everything the server produced while a recording was replayed.
+#[derive(Debug, Clone, Default, Getters)]
+#[getset(get = "pub")]
+pub struct SessionReplayReport {
+  /// Replies to each replayed client message, in the order the messages were sent.
+  replies: Vec<SessionReplayReply>,
+  /// Events the replay server emitted, in the order they were received.
+  events: Vec<ButtplugCurrentSpecServerMessage>,
+  /// Recorded device index to the index of the device standing in for it.
+  device_indexes: HashMap<u32, u32>,
+}
+
+impl SessionReplayReport {
+  /// Replies that don't [match](SessionReplayReply::matches) the recording.
+  pub fn mismatches(&self) -> Vec<&SessionReplayReply> {
+    self
+      .replies
+      .iter()
+      .filter(|reply| !reply.matches())
+      .collect()
+  }
+}
+
+/// Drives a server from a session recording. See the [module docs](self) for how devices are
+/// matched up.
+#[derive(Debug, Clone, Getters)]
+pub struct SessionReplayer {
+  #[getset(get = "pub")]
+  entries: Vec<SessionRecordEntry>,
+  realtime: bool,
+  device_timeout: Duration,
+}
+
+impl SessionReplayer {
+  pub fn new(entries: Vec<SessionRecordEntry>) -> Self {
+    Self {
+      entries,
+      realtime: true,
+      device_timeout: Duration::from_secs(10),
+    }
+  }
+
+  /// Load a recording from any reader, one entry per line. Blank lines are skipped.
+  pub fn from_reader<R: BufRead>(reader: R) -> Result<Self, SessionRecordingError> {
+    let mut entries = vec![];
+    for (index, line) in reader.lines().enumerate() {
+      let line = line?;
+      if line.trim().is_empty() {
+        continue;
+      }
+      entries.push(
+        serde_json::from_str(&line)
+          .map_err(|e| SessionRecordingError::InvalidEntry(index + 1, e.to_string()))?,
+      );
+    }
+    Ok(Self::new(entries))
+  }
+
+  /// Load a recording written by [SessionRecorder::to_file].
+  pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, SessionRecordingError> {
+    Self::from_reader(BufReader::new(File::open(path)?))
+  }
+
+  /// If true (the default), wait between client messages as long as the recorded client did. If
+  /// false, send each message as soon as the reply to the previous one arrives.
+  pub fn realtime(&mut self, realtime: bool) -> &mut Self {
+    self.realtime = realtime;
+    self
+  }
+
+  /// How long to wait for a device to connect to the replay server when the recording has one
+  /// connecting. Defaults to 10 seconds.
+  pub fn device_timeout(&mut self, timeout: Duration) -> &mut Self {
+    self.device_timeout = timeout;
+    self
+  }
+
+  /// Devices that recorded in this session, as the closest virtual device to each, for building a
+  /// replay server.
+  #[cfg(feature = "virtual-device-manager")]
+  pub fn virtual_devices(&self) -> VirtualDeviceCommunicationManagerBuilder {
+    let mut builder = VirtualDeviceCommunicationManagerBuilder::default();
+    for device in self.recorded_devices() {
+      let messages = device.device_messages();
+      let device_type = if messages
+        .sensor_read_cmd()
+        .iter()
+        .flatten()
+        .any(|sensor| *sensor.sensor_type() == SensorType::Pressure)
+      {
+        VirtualDeviceType::PressureSensor
+      } else if messages.linear_cmd().is_some() {
+        VirtualDeviceType::Stroker
+      } else if messages.rotate_cmd().is_some() {
+        VirtualDeviceType::Rotator
+      } else {
+        VirtualDeviceType::Vibrator
+      };
+      builder.add_device(device_type);
+    }
+    builder
+  }
+
+  #[cfg(feature = "virtual-device-manager")]
+  fn recorded_devices(&self) -> impl Iterator<Item = &DeviceAdded> {
+    self.entries.iter().filter_map(|entry| match entry.event() {
+      SessionRecordEvent::ServerEvent(ButtplugCurrentSpecServerMessage::DeviceAdded(device)) => {
+        Some(device)
+      }
+      _ => None,
+    })
+  }
+
+  /// Send the client messages of the recording to `server`, in order, each one waiting for the
+  /// reply to the previous one so the replay is deterministic. Recorded devices connecting will
+  /// wait for a device to connect to `server` to stand in for them.
+  pub async fn replay(
+    &self,
+    server: &ButtplugServer,
+  ) -> Result<SessionReplayReport, SessionRecordingError> {
+    let events = server.event_stream();
+    pin_mut!(events);
+    let mut report = SessionReplayReport::default();
+    let mut unmatched_devices: Vec<DeviceAdded> = vec![];
+    let start = Instant::now();
+    for (index, entry) in self.entries.iter().enumerate() {
+      Self::drain_events(&mut events, &mut report, &mut unmatched_devices);
+      match entry.event() {
+        SessionRecordEvent::ServerEvent(ButtplugCurrentSpecServerMessage::DeviceAdded(device)) => {
+          let replayed = self
+            .wait_for_device(device, &mut events, &mut report, &mut unmatched_devices)
+            .await?;
+          report
+            .device_indexes
+            .insert(device.device_index(), replayed.device_index());
+        }
+        SessionRecordEvent::ClientMessage(msg) => {
+          if self.realtime {
+            let target = Duration::from_millis(entry.elapsed_ms());
+            let elapsed = start.elapsed();
+            if target > elapsed {
+              sleep(target - elapsed).await;
+            }
+          }
+          let id = msg.id();
+          let msg = Self::remap_device_index(msg.clone(), &report.device_indexes);
+          let replayed = match server.parse_message(msg).await {
+            Ok(reply) => ButtplugCurrentSpecServerMessage::try_from(reply).ok(),
+            Err(err) => Some(ButtplugCurrentSpecServerMessage::Error(err)),
+          };
+          if let Some(replayed) = replayed {
+            report.replies.push(SessionReplayReply {
+              id,
+              recorded: self.recorded_reply(index, id),
+              replayed,
+            });
+          }
+        }
+        _ => {}
+      }
+    }
+    Self::drain_events(&mut events, &mut report, &mut unmatched_devices);
+    Ok(report)
+  }
+
+  fn recorded_reply(&self, index: usize, id: u32) -> Option<ButtplugCurrentSpecServerMessage> {
+    self.entries[index + 1..]
+      .iter()
+      .find_map(|entry| match entry.event() {
+        SessionRecordEvent::ServerReply(reply) if reply.id() == id => Some(reply.clone()),
+        _ => None,
+      })
+  }
+
+  fn remap_device_index(
+    msg: ButtplugCurrentSpecClientMessage,
+    device_indexes: &HashMap<u32, u32>,
+  ) -> ButtplugClientMessage {
+    let msg = ButtplugClientMessage::from(msg);
+    match ButtplugDeviceCommandMessageUnion::try_from(msg.clone()) {
+      Ok(mut device_msg) => {
+        if let Some(index) = device_indexes.get(&device_msg.device_index()) {
+          device_msg.set_device_index(*index);
+        }
+        device_msg.into()
+      }
+      // Patterns are played by the device manager, so they aren't device command messages.
+      Err(_) => match msg {
+        ButtplugClientMessage::PatternCmd(mut pattern) => {
+          if let Some(index) = device_indexes.get(&pattern.device_index()) {
+            pattern.set_device_index(*index);
+          }
+          pattern.into()
+        }
+        msg => msg,
+      },
+    }
+  }
+
+  fn handle_event(
+    msg: Arc<ButtplugServerMessage>,
+    report: &mut SessionReplayReport,
+    unmatched_devices: &mut Vec<DeviceAdded>,
+  ) {
+    if let Ok(msg) = ButtplugCurrentSpecServerMessage::try_from(Arc::unwrap_or_clone(msg)) {
+      if let ButtplugCurrentSpecServerMessage::DeviceAdded(device) = &msg {
+        unmatched_devices.push(device.clone());
+      }
+      report.events.push(msg);
+    }
+  }
+
+  fn drain_events<S>(
+    events: &mut S,
+    report: &mut SessionReplayReport,
+    unmatched_devices: &mut Vec<DeviceAdded>,
+  ) where
+    S: Stream<Item = Arc<ButtplugServerMessage>> + Unpin,
+  {
+    while let Some(Some(msg)) = events.next().now_or_never() {
+      Self::handle_event(msg, report, unmatched_devices);
+    }
+  }
+
+  async fn wait_for_device<S>(
+    &self,
+    recorded: &DeviceAdded,
+    events: &mut S,
+    report: &mut SessionReplayReport,
+    unmatched_devices: &mut Vec<DeviceAdded>,
+  ) -> Result<DeviceAdded, SessionRecordingError>
+  where
+    S: Stream<Item = Arc<ButtplugServerMessage>> + Unpin,
+  {
+    let deadline = Instant::now() + self.device_timeout;
+    loop {
+      if !unmatched_devices.is_empty() {
+        let position = unmatched_devices
+          .iter()
+          .position(|device| device.device_name() == recorded.device_name())
+          .unwrap_or(0);
+        return Ok(unmatched_devices.remove(position));
+      }
+      let remaining = deadline.saturating_duration_since(Instant::now());
+      if remaining.is_zero() {
+        break;
+      }
+      select! {
+        msg = events.next().fuse() => match msg {
+          Some(msg) => Self::handle_event(msg, report, unmatched_devices),
+          None => break,
+        },
+        _ = sleep(remaining).fuse() => {}
+      }
+    }
+    Err(SessionRecordingError::DeviceNotReplayed(
+      recorded.device_index(),
+      recorded.device_name().clone(),
+    ))
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::core::message::{ActuatorType, Ok as OkMessage, Ping, ScalarCmd, ScalarSubcommand};
+
+  #[test]
+  fn test_entry_serialization() {
+    let entry = SessionRecordEntry::new(
+      12,
+      SessionRecordEvent::ClientMessage(ButtplugCurrentSpecClientMessage::Ping(Ping::default())),
+    );
+    let json = serde_json::to_string(&entry).expect("Test, assuming infallible");
+    assert_eq!(
+      json,
+      r#"{"ElapsedMs":12,"ClientMessage":{"Ping":{"Id":1}}}"#
+    );
+    let parsed: SessionRecordEntry =
+      serde_json::from_str(&json).expect("Test, assuming infallible");
+    assert_eq!(parsed, entry);
+  }
+
+  #[test]
+  fn test_device_index_remapped() {
+    let msg = ButtplugCurrentSpecClientMessage::ScalarCmd(ScalarCmd::new(
+      4,
+      vec![ScalarSubcommand::new(0, 0.5, ActuatorType::Vibrate)],
+    ));
+    let remapped = SessionReplayer::remap_device_index(msg, &HashMap::from([(4, 1)]));
+    if let ButtplugClientMessage::ScalarCmd(cmd) = remapped {
+      assert_eq!(cmd.device_index(), 1);
+    } else {
+      panic!("Remapping should not change the message type");
+    }
+  }
+
+  #[test]
+  fn test_reply_matching() {
+    let reply = SessionReplayReply {
+      id: 1,
+      recorded: Some(OkMessage::new(1).into()),
+      replayed: OkMessage::new(1).into(),
+    };
+    assert!(reply.matches());
+    let reply = SessionReplayReply {
+      id: 1,
+      recorded: Some(OkMessage::new(1).into()),
+      replayed: ButtplugCurrentSpecServerMessage::Error(message::Error::new(
+        message::ErrorCode::ErrorDevice,
+        "Test",
+        None,
+      )),
+    };
+    assert!(!reply.matches());
+  }
+}
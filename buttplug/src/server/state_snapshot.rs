@@ -0,0 +1,193 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Point in time views of server state, for applications hosting a server that want to show its
+//! status without following the event stream.
+
+use super::device::{
+  hardware::{communication::HardwareCommunicationManagerStatus, HardwareActivity},
+  CommManagerMetrics,
+  ServerDevice,
+  UnsupportedDeviceInfo,
+};
+use crate::{
+  core::message::{ButtplugMessageSpecVersion, ClientCapability, ClientDeviceMessageAttributes},
+  util::address_privacy::display_address,
+};
+use getset::{CopyGetters, Getters};
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// State of a [ButtplugServer](super::ButtplugServer), as returned by
+/// [ButtplugServer::state_snapshot](super::ButtplugServer::state_snapshot).
+#[derive(Debug, Clone, PartialEq, Eq, Getters, CopyGetters)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct ServerStateSnapshot {
+  #[getset(get = "pub")]
+  server_name: String,
+  /// Client that has completed the handshake, if any. Servers only take one client at a time.
+  #[getset(get = "pub")]
+  client: Option<ClientStateSnapshot>,
+  /// Connected devices, sorted by device index.
+  #[getset(get = "pub")]
+  devices: Vec<DeviceStateSnapshot>,
+  /// Hardware seen since scanning last started that matched no protocol, along with the protocols
+  /// it came closest to matching. Sorted by address.
+  #[getset(get = "pub")]
+  unsupported_devices: Vec<UnsupportedDeviceInfo>,
+  #[getset(get_copy = "pub")]
+  scanning: bool,
+  /// Scan and connection counters for each communication manager, in the order they were added.
+  #[getset(get = "pub")]
+  comm_managers: Vec<CommManagerStateSnapshot>,
+  /// Version of the device configuration file loaded into the server, if it was loaded from a file.
+  #[getset(get = "pub")]
+  device_config_version: Option<String>,
+  #[getset(get = "pub")]
+  library_version: String,
+  /// Newest message spec version the server can speak.
+  #[getset(get_copy = "pub")]
+  message_spec_version: ButtplugMessageSpecVersion,
+}
+
+impl ServerStateSnapshot {
+  #[allow(clippy::too_many_arguments)]
+  pub(super) fn new(
+    server_name: &str,
+    client: Option<ClientStateSnapshot>,
+    devices: Vec<DeviceStateSnapshot>,
+    unsupported_devices: Vec<UnsupportedDeviceInfo>,
+    scanning: bool,
+    comm_managers: Vec<CommManagerStateSnapshot>,
+    device_config_version: Option<String>,
+    message_spec_version: ButtplugMessageSpecVersion,
+  ) -> Self {
+    Self {
+      server_name: server_name.to_owned(),
+      client,
+      devices,
+      unsupported_devices,
+      scanning,
+      comm_managers,
+      device_config_version,
+      library_version: env!("CARGO_PKG_VERSION").to_owned(),
+      message_spec_version,
+    }
+  }
+}
+
+/// Client connected to a server.
+#[derive(Debug, Clone, PartialEq, Eq, Getters, CopyGetters)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct ClientStateSnapshot {
+  /// Name the client sent in its RequestServerInfo message.
+  #[getset(get = "pub")]
+  name: String,
+  /// Message spec version the client connected with.
+  #[getset(get_copy = "pub")]
+  message_spec_version: ButtplugMessageSpecVersion,
+  /// Capabilities the client declared in its handshake, if it declared any.
+  #[getset(get = "pub")]
+  capabilities: Option<Vec<ClientCapability>>,
+}
+
+impl ClientStateSnapshot {
+  pub(super) fn new(
+    name: &str,
+    message_spec_version: ButtplugMessageSpecVersion,
+    capabilities: Option<Vec<ClientCapability>>,
+  ) -> Self {
+    Self {
+      name: name.to_owned(),
+      message_spec_version,
+      capabilities,
+    }
+  }
+}
+
+/// Device connected to a server.
+#[derive(Debug, Clone, PartialEq, Eq, Getters, CopyGetters)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct DeviceStateSnapshot {
+  #[getset(get_copy = "pub")]
+  device_index: u32,
+  #[getset(get = "pub")]
+  name: String,
+  #[getset(get = "pub")]
+  display_name: Option<String>,
+  #[getset(get = "pub")]
+  protocol: String,
+  /// Hardware address of the device, hashed if address hashing is on. See
+  /// [address_privacy](crate::util::address_privacy).
+  #[getset(get = "pub")]
+  address: String,
+  /// Messages the device accepts, as they'd be sent to a client.
+  #[getset(get = "pub")]
+  message_attributes: ClientDeviceMessageAttributes,
+  /// Last battery reading, in the range of the device's battery sensor, if the battery has been
+  /// read since the device connected.
+  #[getset(get_copy = "pub")]
+  battery_level: Option<i32>,
+  /// When the device was last sent commands, last sent a notification, and kept alive by its
+  /// protocol. Useful for telling a device that stopped responding apart from a server that
+  /// stopped sending to it.
+  #[getset(get_copy = "pub")]
+  activity: HardwareActivity,
+}
+
+impl DeviceStateSnapshot {
+  pub(crate) fn new(device_index: u32, device: &ServerDevice) -> Self {
+    Self {
+      device_index,
+      name: device.name(),
+      display_name: device.display_name(),
+      protocol: device.identifier().protocol().clone(),
+      address: display_address(device.identifier().address()).to_string(),
+      message_attributes: device.message_attributes().into(),
+      battery_level: device.battery_level(),
+      activity: device.activity(),
+    }
+  }
+}
+
+/// Scan and connection counters for a hardware communication manager. See [CommManagerMetrics].
+#[derive(Debug, Clone, PartialEq, Eq, Getters, CopyGetters)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct CommManagerStateSnapshot {
+  #[getset(get = "pub")]
+  name: String,
+  #[getset(get = "pub")]
+  status: HardwareCommunicationManagerStatus,
+  #[getset(get_copy = "pub")]
+  advertisements_seen: u64,
+  #[getset(get_copy = "pub")]
+  devices_matched: u64,
+  #[getset(get_copy = "pub")]
+  connects_attempted: u64,
+  #[getset(get_copy = "pub")]
+  connects_succeeded: u64,
+  #[getset(get_copy = "pub")]
+  connects_failed: u64,
+  #[getset(get_copy = "pub")]
+  average_connect_time: Option<Duration>,
+}
+
+impl CommManagerStateSnapshot {
+  pub(super) fn new(metrics: &CommManagerMetrics) -> Self {
+    Self {
+      name: metrics.name().to_owned(),
+      status: metrics.status(),
+      advertisements_seen: metrics.advertisements_seen(),
+      devices_matched: metrics.devices_matched(),
+      connects_attempted: metrics.connects_attempted(),
+      connects_succeeded: metrics.connects_succeeded(),
+      connects_failed: metrics.connects_failed(),
+      average_connect_time: metrics.average_connect_time(),
+    }
+  }
+}
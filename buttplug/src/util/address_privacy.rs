@@ -0,0 +1,131 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Hiding hardware addresses from logs and remote clients.
+//!
+//! Hardware addresses (bluetooth MACs, serial ports, etc...) can identify a user, which matters
+//! once logs get shared in bug reports or the server is reached through a relay. When an address
+//! hash key is set, anywhere the library displays an address it shows a keyed hash of it instead.
+//! The same address with the same key always hashes to the same value, so logs can still be
+//! correlated.
+//!
+//! Only display is affected. Addresses used for matching and persistence (allow/deny lists,
+//! reserved indexes, user configs) are always the real hardware address, so local configs keep
+//! working whether or not hashing is on.
+
+use sha1::{Digest, Sha1};
+use std::{
+  fmt::{self, Debug, Display},
+  sync::RwLock,
+};
+
+const SHA1_BLOCK_SIZE: usize = 64;
+/// Number of hash bytes shown, enough to tell devices apart in a log.
+const DISPLAYED_HASH_BYTES: usize = 6;
+
+static ADDRESS_HASH_KEY: RwLock<Option<Vec<u8>>> = RwLock::new(None);
+
+/// Sets the key used to hash displayed addresses, or turns hashing off if `None`.
+///
+/// This is process-wide, as logging is. The key should be random and stored per install (for
+/// instance, a random UUID kept with the application's settings), so hashes stay stable across
+/// runs but cannot be matched between installs.
+pub fn set_address_hash_key(key: Option<&str>) {
+  *ADDRESS_HASH_KEY
+    .write()
+    .expect("Address hash key lock should never be poisoned") =
+    key.map(|key| key.as_bytes().to_vec());
+}
+
+/// Returns true if displayed addresses are currently being hashed.
+pub fn address_hashing_enabled() -> bool {
+  ADDRESS_HASH_KEY
+    .read()
+    .expect("Address hash key lock should never be poisoned")
+    .is_some()
+}
+
+/// HMAC-SHA1 of the address, formatted as `addr-<hex>`.
+pub fn hash_address(key: &[u8], address: &str) -> String {
+  let mut block_key = [0u8; SHA1_BLOCK_SIZE];
+  if key.len() > SHA1_BLOCK_SIZE {
+    block_key[..20].copy_from_slice(&Sha1::digest(key));
+  } else {
+    block_key[..key.len()].copy_from_slice(key);
+  }
+  let inner_pad: Vec<u8> = block_key.iter().map(|b| b ^ 0x36).collect();
+  let outer_pad: Vec<u8> = block_key.iter().map(|b| b ^ 0x5c).collect();
+  let inner = Sha1::new()
+    .chain_update(&inner_pad)
+    .chain_update(address.as_bytes())
+    .finalize();
+  let outer = Sha1::new()
+    .chain_update(&outer_pad)
+    .chain_update(inner)
+    .finalize();
+  let hex: String = outer[..DISPLAYED_HASH_BYTES]
+    .iter()
+    .map(|b| format!("{:02x}", b))
+    .collect();
+  format!("addr-{}", hex)
+}
+
+/// Wraps an address so that displaying it follows the current hashing setting. Use this anywhere
+/// an address ends up in a log or a message that leaves the library.
+pub fn display_address(address: &str) -> DisplayAddress<'_> {
+  DisplayAddress(address)
+}
+
+/// See [display_address].
+pub struct DisplayAddress<'a>(&'a str);
+
+impl<'a> DisplayAddress<'a> {
+  fn hashed(&self) -> Option<String> {
+    ADDRESS_HASH_KEY
+      .read()
+      .expect("Address hash key lock should never be poisoned")
+      .as_ref()
+      .map(|key| hash_address(key, self.0))
+  }
+}
+
+impl<'a> Display for DisplayAddress<'a> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.hashed() {
+      Some(hashed) => Display::fmt(&hashed, f),
+      None => Display::fmt(self.0, f),
+    }
+  }
+}
+
+impl<'a> Debug for DisplayAddress<'a> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.hashed() {
+      Some(hashed) => Debug::fmt(&hashed, f),
+      None => Debug::fmt(self.0, f),
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::hash_address;
+
+  #[test]
+  fn test_hash_address() {
+    // RFC 2202 test case 2, truncated to the displayed length.
+    assert_eq!(
+      hash_address(b"Jefe", "what do ya want for nothing?"),
+      "addr-effcdf6ae5eb"
+    );
+    let hashed = hash_address(b"install-key", "00:11:22:33:44:55");
+    assert_eq!(hashed, hash_address(b"install-key", "00:11:22:33:44:55"));
+    assert_ne!(hashed, hash_address(b"other-key", "00:11:22:33:44:55"));
+    assert_ne!(hashed, hash_address(b"install-key", "00:11:22:33:44:56"));
+    assert!(!hashed.contains("00:11:22"));
+  }
+}
@@ -0,0 +1,273 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Upgrades user device configurations written for older versions of the config schema.
+//!
+//! User configs outlive library upgrades, so when the schema changes, configs written against the
+//! old schema are upgraded when they're loaded instead of being rejected. Migrations work on the
+//! raw JSON, one major version at a time, and only touch the parts of the config that changed
+//! format, so anything they don't know about is carried over as is. Every change made is listed in
+//! a [ConfigMigrationReport], which applications can show to users, along with the upgraded config
+//! to save in place of the old one.
+
+use super::device_configuration::{get_internal_config_version, ConfigVersion};
+use crate::core::errors::ButtplugDeviceError;
+use getset::{CopyGetters, Getters};
+use serde_json::{json, Map, Value};
+
+/// What was changed while upgrading a user config.
+#[derive(Debug, Clone, Getters, CopyGetters)]
+pub struct ConfigMigrationReport {
+  /// Version the config was written for.
+  #[getset(get_copy = "pub")]
+  from_version: ConfigVersion,
+  /// Version the config was upgraded to, which is the same as `from_version` if it was up to date.
+  #[getset(get_copy = "pub")]
+  to_version: ConfigVersion,
+  /// Description of each change, in the order they were made.
+  #[getset(get = "pub")]
+  changes: Vec<String>,
+}
+
+impl ConfigMigrationReport {
+  /// True if the config was written for an older version, and had to be upgraded.
+  pub fn migrated(&self) -> bool {
+    self.from_version.major != self.to_version.major
+  }
+}
+
+/// Upgrade from one major version of the schema to the next.
+struct Migration {
+  from_major: u32,
+  migrate: fn(&mut Map<String, Value>, &mut Vec<String>),
+}
+
+const MIGRATIONS: &[Migration] = &[Migration {
+  from_major: 1,
+  migrate: migrate_v1_to_v2,
+}];
+
+/// Upgrades a user config to the current major version of the schema, returning the upgraded
+/// config and what was changed. Configs that are already up to date are handed back untouched.
+///
+/// # Errors
+///
+/// Fails if the config isn't JSON with a version, or if there's no way to upgrade from its version.
+/// Configs written for newer versions than the library knows about are returned as is, and will
+/// fail version checks when loaded.
+pub fn migrate_user_config(
+  config_str: &str,
+) -> Result<(String, ConfigMigrationReport), ButtplugDeviceError> {
+  let mut config: Value = serde_json::from_str(config_str).map_err(|err| {
+    ButtplugDeviceError::DeviceConfigurationError(format!(
+      "Cannot read user config for migration: {}",
+      err
+    ))
+  })?;
+  let from_version = config_version(&config)?;
+  let target_major = get_internal_config_version().major;
+  let mut report = ConfigMigrationReport {
+    from_version,
+    to_version: from_version,
+    changes: vec![],
+  };
+  if from_version.major >= target_major {
+    return Ok((config_str.to_owned(), report));
+  }
+
+  let root = config
+    .as_object_mut()
+    .expect("Already checked for a version, so this is an object.");
+  while report.to_version.major < target_major {
+    let migration = MIGRATIONS
+      .iter()
+      .find(|migration| migration.from_major == report.to_version.major)
+      .ok_or_else(|| {
+        ButtplugDeviceError::DeviceConfigurationError(format!(
+          "User config version {} is too old to be upgraded.",
+          report.to_version
+        ))
+      })?;
+    (migration.migrate)(root, &mut report.changes);
+    report.to_version = ConfigVersion {
+      major: report.to_version.major + 1,
+      minor: 0,
+    };
+    root.insert("version".to_owned(), json!(report.to_version));
+    report.changes.push(format!(
+      "Upgraded config from version {} to {}.",
+      from_version, report.to_version
+    ));
+  }
+  let migrated = serde_json::to_string_pretty(&config)
+    .expect("Config was read from JSON, so it can be written back.");
+  Ok((migrated, report))
+}
+
+fn config_version(config: &Value) -> Result<ConfigVersion, ButtplugDeviceError> {
+  config
+    .get("version")
+    .cloned()
+    .and_then(|version| serde_json::from_value(version).ok())
+    .ok_or_else(|| {
+      ButtplugDeviceError::DeviceConfigurationError(
+        "User config has no version, so it cannot be migrated.".to_owned(),
+      )
+    })
+}
+
+/// Version 1 described each output message with a feature count and a list of step counts, while
+/// version 2 lists the features, each with a step range and actuator type. Vibrators also moved
+/// from VibrateCmd to ScalarCmd.
+fn migrate_v1_to_v2(root: &mut Map<String, Value>, changes: &mut Vec<String>) {
+  let user_configs = match root.get_mut("user-configs") {
+    Some(Value::Object(user_configs)) => user_configs,
+    _ => return,
+  };
+  if let Some(Value::Array(devices)) = user_configs.get_mut("devices") {
+    for (index, device) in devices.iter_mut().enumerate() {
+      if let Some(messages) = device.pointer_mut("/config/messages") {
+        migrate_v1_messages(&format!("devices[{}]", index), messages, changes);
+      }
+    }
+  }
+  if let Some(Value::Object(specifiers)) = user_configs.get_mut("specifiers") {
+    for (protocol, definition) in specifiers.iter_mut() {
+      if let Some(messages) = definition.pointer_mut("/defaults/messages") {
+        migrate_v1_messages(&format!("{} defaults", protocol), messages, changes);
+      }
+      if let Some(Value::Array(configurations)) = definition.get_mut("configurations") {
+        for (index, configuration) in configurations.iter_mut().enumerate() {
+          if let Some(messages) = configuration.get_mut("messages") {
+            migrate_v1_messages(
+              &format!("{} configurations[{}]", protocol, index),
+              messages,
+              changes,
+            );
+          }
+        }
+      }
+    }
+  }
+}
+
+fn migrate_v1_messages(location: &str, messages: &mut Value, changes: &mut Vec<String>) {
+  let messages = match messages {
+    Value::Object(messages) => messages,
+    _ => return,
+  };
+  for (message, new_message, actuator_type) in [
+    ("VibrateCmd", "ScalarCmd", "Vibrate"),
+    ("RotateCmd", "RotateCmd", "Rotate"),
+    ("LinearCmd", "LinearCmd", "Position"),
+  ] {
+    let attributes = match messages.get(message) {
+      Some(attributes @ Value::Object(_)) => attributes,
+      _ => continue,
+    };
+    let step_counts: Option<Vec<u64>> = attributes
+      .get("StepCount")
+      .and_then(|steps| serde_json::from_value(steps.clone()).ok());
+    let feature_count = attributes.get("FeatureCount").and_then(Value::as_u64);
+    let step_counts = match step_counts {
+      Some(step_counts)
+        if feature_count.unwrap_or(step_counts.len() as u64) == step_counts.len() as u64 =>
+      {
+        step_counts
+      }
+      // Without a step count for every feature there's nothing to build step ranges from, so leave
+      // it for the user to fix rather than guessing. Loading will point out what's wrong with it.
+      _ => {
+        changes.push(format!(
+          "{}: Could not convert {}, as it doesn't have a step count for each feature. Left as is.",
+          location, message
+        ));
+        continue;
+      }
+    };
+    if message != new_message && messages.contains_key(new_message) {
+      changes.push(format!(
+        "{}: Could not convert {}, as {} is already set. Left as is.",
+        location, message, new_message
+      ));
+      continue;
+    }
+    messages.remove(message);
+    messages.insert(
+      new_message.to_owned(),
+      step_counts
+        .iter()
+        .map(|steps| json!({ "StepRange": [0, steps], "ActuatorType": actuator_type }))
+        .collect(),
+    );
+    changes.push(format!(
+      "{}: Converted {} step counts {:?} to {} step ranges.",
+      location, message, step_counts, new_message
+    ));
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_migrate_v1_messages() {
+    let config = r#"
+    {
+      "version": { "major": 1, "minor": 3 },
+      "user-configs": {
+        "devices": [
+          {
+            "identifier": { "address": "Test", "protocol": "lovense" },
+            "config": {
+              "display-name": "Kept",
+              "messages": {
+                "VibrateCmd": { "FeatureCount": 2, "StepCount": [20, 10] },
+                "RotateCmd": { "FeatureCount": 2, "StepCount": [24] }
+              }
+            }
+          }
+        ]
+      }
+    }
+    "#;
+    let (migrated, report) = migrate_user_config(config).expect("Test, assuming infallible.");
+    assert!(report.migrated());
+    assert_eq!(
+      report.to_version().major,
+      get_internal_config_version().major
+    );
+    assert_eq!(report.changes().len(), 3);
+    let migrated: Value = serde_json::from_str(&migrated).expect("Test, assuming infallible.");
+    let device_config = &migrated["user-configs"]["devices"][0]["config"];
+    assert_eq!(device_config["display-name"], "Kept");
+    assert_eq!(
+      device_config["messages"]["ScalarCmd"],
+      json!([
+        { "StepRange": [0, 20], "ActuatorType": "Vibrate" },
+        { "StepRange": [0, 10], "ActuatorType": "Vibrate" }
+      ])
+    );
+    // Step counts that don't match the feature count are left alone.
+    assert_eq!(
+      device_config["messages"]["RotateCmd"],
+      json!({ "FeatureCount": 2, "StepCount": [24] })
+    );
+  }
+
+  #[test]
+  fn test_current_config_untouched() {
+    let config = r#"{ "version": { "major": 2, "minor": 0 }, "unknown": true }"#;
+    let (migrated, report) = migrate_user_config(config).expect("Test, assuming infallible.");
+    assert!(!report.migrated());
+    assert!(report.changes().is_empty());
+    assert_eq!(migrated, config);
+    assert!(migrate_user_config(r#"{ "version": { "major": 0, "minor": 1 } }"#).is_err());
+    assert!(migrate_user_config("{}").is_err());
+  }
+}
@@ -5,17 +5,22 @@
 // Licensed under the BSD 3-Clause license. See LICENSE file in the project root
 // for full license information.
 
-use super::json::JSONValidator;
+use super::{config_migration::migrate_user_config, json::JSONValidator};
 use crate::{
-  core::errors::ButtplugDeviceError,
+  core::{
+    errors::ButtplugDeviceError,
+    message::{ActuatorType, Endpoint, ScalarSubcommand, SensorType},
+  },
   server::device::{
     configuration::{
       BluetoothLESpecifier,
       DeviceConfigurationManager,
       DeviceConfigurationManagerBuilder,
+      DeviceLocalization,
       HIDSpecifier,
       LovenseConnectServiceSpecifier,
       ProtocolAttributesIdentifier,
+      ProtocolAttributesLoader,
       ProtocolAttributesType,
       ProtocolCommunicationSpecifier,
       ProtocolDeviceAttributes,
@@ -25,18 +30,49 @@ use crate::{
       WebsocketSpecifier,
       XInputSpecifier,
     },
+    hardware::{BluetoothLEConnectionParameters, BluetoothLEWriteCoalescing},
+    protocol::compiled_out_protocols,
+    EstimInterlock,
+    OveruseProtection,
+    SensorCalibration,
+    SensorCondition,
+    SensorRule,
     ServerDeviceIdentifier,
   },
 };
 use getset::{CopyGetters, Getters, MutGetters, Setters};
+use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fmt::Display, ops::RangeInclusive};
+use serde_json::value::RawValue;
+use std::{
+  collections::HashMap,
+  fmt::Display,
+  ops::RangeInclusive,
+  sync::Arc,
+  time::Duration,
+};
 
 pub static DEVICE_CONFIGURATION_JSON: &str =
   include_str!("../../buttplug-device-config/buttplug-device-config.json");
 static DEVICE_CONFIGURATION_JSON_SCHEMA: &str =
   include_str!("../../buttplug-device-config/buttplug-device-config-schema.json");
 
+/// The device configuration compiled into the library, split up by protocol. Only the outline of
+/// the file is parsed here, the definition of each protocol is left as raw JSON until it's needed.
+/// This file is checked against the schema by our tests rather than every time it is loaded.
+static VENDORED_DEVICE_CONFIGURATION: Lazy<SplitProtocolConfiguration<'static>> =
+  Lazy::new(|| {
+    serde_json::from_str(DEVICE_CONFIGURATION_JSON)
+      .expect("If this fails, the whole library goes with it.")
+  });
+
+#[derive(Deserialize)]
+struct SplitProtocolConfiguration<'a> {
+  version: ConfigVersion,
+  #[serde(borrow)]
+  protocols: HashMap<String, &'a RawValue>,
+}
+
 /// The top level configuration for a protocol. Contains all data about devices that can use the
 /// protocol, as well as names, message attributes, etc... for different devices.
 ///
@@ -93,6 +129,81 @@ pub struct UserDeviceConfig {
   #[serde(skip_serializing_if = "Option::is_none")]
   #[serde(default)]
   index: Option<u32>,
+  /// Interval, in milliseconds, at which the server should poll the battery level of the device and
+  /// emit updated readings.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[serde(default)]
+  #[serde(rename = "battery-poll-interval")]
+  battery_poll_interval: Option<u32>,
+  /// Interval, in milliseconds, at which subscriptions to the signal strength of the device are
+  /// sampled. Setting this exposes the signal strength as an RSSI sensor.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[serde(default)]
+  #[serde(rename = "rssi-sample-interval")]
+  rssi_sample_interval: Option<u32>,
+  /// Time, in milliseconds, after which commands sent to the device fail. Overrides the timeout of
+  /// the device's protocol.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[serde(default)]
+  #[serde(rename = "command-timeout")]
+  command_timeout: Option<u32>,
+  /// Most times a second output is sent to the device, with anything sent in between merged.
+  /// Overrides the rate of the device's protocol.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[serde(default)]
+  #[serde(rename = "max-update-rate")]
+  max_update_rate: Option<u32>,
+  /// Flips the rotation direction of the device, for hardware that turns the opposite way from
+  /// what its configuration describes.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[serde(default)]
+  #[serde(rename = "invert-rotation")]
+  invert_rotation: Option<bool>,
+  /// Time, in milliseconds, after connection during which output is capped, with the cap rising to
+  /// full output over that time.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[serde(default)]
+  #[serde(rename = "warm-up")]
+  warm_up: Option<u32>,
+  /// Time, in milliseconds, that stopping the device takes to step its output down to zero.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[serde(default)]
+  #[serde(rename = "cool-down")]
+  cool_down: Option<u32>,
+  /// Calibrations for the device's sensors, for units whose readings are off from the norm.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[serde(default)]
+  #[serde(rename = "sensor-calibration")]
+  sensor_calibration: Option<Vec<SensorCalibrationDefinition>>,
+  /// Lists each output feature of the device as a device of its own, for applications that only
+  /// control one feature per device.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[serde(default)]
+  split: Option<bool>,
+}
+
+/// Calibration for one sensor of a device, as written in a user config.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Getters, CopyGetters, Setters)]
+pub struct SensorCalibrationDefinition {
+  #[getset(get_copy = "pub", set = "pub")]
+  #[serde(rename = "sensor-index")]
+  sensor_index: u32,
+  #[getset(get_copy = "pub", set = "pub")]
+  #[serde(rename = "sensor-type")]
+  sensor_type: SensorType,
+  #[getset(get = "pub", set = "pub")]
+  #[serde(flatten)]
+  calibration: SensorCalibration,
+}
+
+impl SensorCalibrationDefinition {
+  pub fn new(sensor_index: u32, sensor_type: SensorType, calibration: SensorCalibration) -> Self {
+    Self {
+      sensor_index,
+      sensor_type,
+      calibration,
+    }
+  }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default, Getters, Setters, MutGetters)]
@@ -104,6 +215,14 @@ pub struct ProtocolAttributes {
   name: Option<String>,
   #[serde(skip_serializing_if = "Option::is_none")]
   messages: Option<ServerDeviceMessageAttributes>,
+  /// Flips the rotation direction of the device relative to the protocol defaults, so that RotateCmd
+  /// clockwise turns the same way across hardware.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[serde(rename = "invert-rotation")]
+  invert_rotation: Option<bool>,
+  /// Names and descriptions of the device in other languages, keyed by language tag.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  localizations: Option<HashMap<String, DeviceLocalization>>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone, Default, Getters, Setters, MutGetters)]
@@ -127,12 +246,200 @@ pub struct ProtocolDefinition {
   #[serde(skip_serializing_if = "Option::is_none")]
   #[serde(rename = "lovense-connect-service")]
   lovense_connect_service: Option<LovenseConnectServiceSpecifier>,
+  /// Time, in milliseconds, after which commands sent to devices using this protocol fail.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[serde(rename = "command-timeout")]
+  command_timeout: Option<u32>,
+  /// Most times a second output is sent to devices using this protocol, with anything sent in
+  /// between merged.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[serde(rename = "max-update-rate")]
+  max_update_rate: Option<u32>,
+  /// Connection parameters to request from bluetooth LE devices using this protocol.
   #[serde(skip_serializing_if = "Option::is_none")]
+  #[serde(rename = "btle-connection-parameters")]
+  btle_connection_parameters: Option<BluetoothLEConnectionParametersDefinition>,
+  /// Writes to merge for bluetooth LE devices using this protocol.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[serde(rename = "btle-write-coalescing")]
+  btle_write_coalescing: Option<BluetoothLEWriteCoalescingDefinition>,
+  /// How long devices using this protocol can run hard before being stepped down.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[serde(rename = "overuse-protection")]
+  overuse_protection: Option<OveruseProtectionDefinition>,
+  /// Marks devices using this protocol as e-stim, with the limits their output is held to.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[serde(rename = "estim-interlock")]
+  estim_interlock: Option<EstimInterlockDefinition>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  defaults: Option<ProtocolAttributes>,
+  #[serde(default)]
+  configurations: Vec<ProtocolAttributes>,
+}
+
+/// The parts of a [ProtocolDefinition] needed to match hardware to the protocol, which have to be
+/// loaded before scanning starts.
+#[derive(Deserialize, Debug, Default)]
+struct ProtocolSpecifiersDefinition {
+  usb: Option<Vec<USBSpecifier>>,
+  btle: Option<BluetoothLESpecifier>,
+  serial: Option<Vec<SerialSpecifier>>,
+  hid: Option<Vec<HIDSpecifier>>,
+  xinput: Option<XInputSpecifier>,
+  websocket: Option<WebsocketSpecifier>,
+  #[serde(rename = "lovense-connect-service")]
+  lovense_connect_service: Option<LovenseConnectServiceSpecifier>,
+  #[serde(rename = "command-timeout")]
+  command_timeout: Option<u32>,
+  #[serde(rename = "max-update-rate")]
+  max_update_rate: Option<u32>,
+  #[serde(rename = "btle-connection-parameters")]
+  btle_connection_parameters: Option<BluetoothLEConnectionParametersDefinition>,
+  #[serde(rename = "btle-write-coalescing")]
+  btle_write_coalescing: Option<BluetoothLEWriteCoalescingDefinition>,
+  #[serde(rename = "overuse-protection")]
+  overuse_protection: Option<OveruseProtectionDefinition>,
+  #[serde(rename = "estim-interlock")]
+  estim_interlock: Option<EstimInterlockDefinition>,
+}
+
+/// The parts of a [ProtocolDefinition] describing the devices using the protocol, which can be
+/// loaded once one of those devices is found.
+#[derive(Deserialize, Debug, Default)]
+struct ProtocolAttributesDefinition {
   defaults: Option<ProtocolAttributes>,
   #[serde(default)]
   configurations: Vec<ProtocolAttributes>,
 }
 
+/// Bluetooth LE connection parameters as written in a device config, with intervals in
+/// milliseconds.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default, CopyGetters, Setters)]
+pub struct BluetoothLEConnectionParametersDefinition {
+  #[getset(get_copy = "pub")]
+  #[serde(rename = "min-interval")]
+  min_interval: f64,
+  #[getset(get_copy = "pub")]
+  #[serde(rename = "max-interval")]
+  max_interval: f64,
+  #[getset(get_copy = "pub", set = "pub")]
+  #[serde(default)]
+  latency: u16,
+}
+
+impl BluetoothLEConnectionParametersDefinition {
+  /// Sets both connection intervals, in milliseconds, failing if they don't make a valid range.
+  pub fn set_intervals(
+    &mut self,
+    min_interval: f64,
+    max_interval: f64,
+  ) -> Result<&mut Self, ButtplugDeviceError> {
+    check_connection_intervals(min_interval, max_interval)?;
+    self.min_interval = min_interval;
+    self.max_interval = max_interval;
+    Ok(self)
+  }
+}
+
+fn check_connection_intervals(
+  min_interval: f64,
+  max_interval: f64,
+) -> Result<(), ButtplugDeviceError> {
+  if !min_interval.is_finite() || !max_interval.is_finite() {
+    return Err(ButtplugDeviceError::DeviceConfigurationError(
+      "Bluetooth LE connection intervals must be finite.".to_owned(),
+    ));
+  }
+  if min_interval < 0.0 || max_interval < 0.0 {
+    return Err(ButtplugDeviceError::DeviceConfigurationError(
+      "Bluetooth LE connection intervals can't be negative.".to_owned(),
+    ));
+  }
+  if min_interval > max_interval {
+    return Err(ButtplugDeviceError::DeviceConfigurationError(format!(
+      "Bluetooth LE minimum connection interval {} is above the maximum {}.",
+      min_interval, max_interval
+    )));
+  }
+  Ok(())
+}
+
+impl TryFrom<BluetoothLEConnectionParametersDefinition> for BluetoothLEConnectionParameters {
+  type Error = ButtplugDeviceError;
+
+  fn try_from(def: BluetoothLEConnectionParametersDefinition) -> Result<Self, Self::Error> {
+    check_connection_intervals(def.min_interval, def.max_interval)?;
+    Ok(BluetoothLEConnectionParameters::new(
+      Duration::from_secs_f64(def.min_interval / 1000.0),
+      Duration::from_secs_f64(def.max_interval / 1000.0),
+      def.latency,
+    ))
+  }
+}
+
+/// Bluetooth LE write coalescing as written in a device config, with the window in milliseconds.
+#[derive(
+  Deserialize, Serialize, Debug, Clone, PartialEq, Default, CopyGetters, Getters, Setters,
+)]
+pub struct BluetoothLEWriteCoalescingDefinition {
+  #[getset(get_copy = "pub", set = "pub")]
+  window: u32,
+  #[getset(get = "pub", set = "pub")]
+  endpoints: Vec<Endpoint>,
+}
+
+impl From<BluetoothLEWriteCoalescingDefinition> for BluetoothLEWriteCoalescing {
+  fn from(def: BluetoothLEWriteCoalescingDefinition) -> Self {
+    BluetoothLEWriteCoalescing::new(
+      Duration::from_millis(def.window as u64),
+      def.endpoints.into_iter().collect(),
+    )
+  }
+}
+
+/// Overuse protection as written in a device config, with the runtime in milliseconds.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default, CopyGetters, Setters)]
+#[getset(get_copy = "pub", set = "pub")]
+pub struct OveruseProtectionDefinition {
+  threshold: f64,
+  #[serde(rename = "max-runtime")]
+  max_runtime: u32,
+  #[serde(rename = "step-down")]
+  step_down: f64,
+}
+
+impl From<OveruseProtectionDefinition> for OveruseProtection {
+  fn from(def: OveruseProtectionDefinition) -> Self {
+    OveruseProtection::new(
+      def.threshold,
+      Duration::from_millis(def.max_runtime as u64),
+      def.step_down,
+    )
+  }
+}
+
+/// An e-stim interlock as written in a device config, with times in milliseconds.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default, CopyGetters, Setters)]
+#[getset(get_copy = "pub", set = "pub")]
+pub struct EstimInterlockDefinition {
+  #[serde(rename = "ramp-up")]
+  ramp_up: u32,
+  #[serde(rename = "max-step")]
+  max_step: f64,
+  #[serde(rename = "step-interval")]
+  step_interval: u32,
+}
+
+impl From<EstimInterlockDefinition> for EstimInterlock {
+  fn from(def: EstimInterlockDefinition) -> Self {
+    EstimInterlock::new(
+      Duration::from_millis(def.ramp_up as u64),
+      def.max_step,
+      Duration::from_millis(def.step_interval as u64),
+    )
+  }
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone, Default, Getters, Setters, MutGetters)]
 #[getset(get = "pub", set = "pub", get_mut = "pub(crate)")]
 pub struct UserDeviceConfigPair {
@@ -153,11 +460,111 @@ pub struct UserConfigDefinition {
   specifiers: Option<HashMap<String, ProtocolDefinition>>,
   #[serde(rename = "devices", default, skip_serializing_if = "Option::is_none")]
   user_device_configs: Option<Vec<UserDeviceConfigPair>>,
+  #[serde(rename = "merged-devices", default, skip_serializing_if = "Option::is_none")]
+  merged_devices: Option<Vec<MergedDeviceDefinition>>,
+  #[serde(rename = "sensor-rules", default, skip_serializing_if = "Option::is_none")]
+  sensor_rules: Option<Vec<SensorRuleDefinition>>,
 }
 
+/// Devices to present to clients as a single device, whenever all of them are connected.
+#[derive(Deserialize, Serialize, Debug, Clone, Default, Getters, Setters, MutGetters)]
+#[getset(get = "pub", set = "pub", get_mut = "pub(crate)")]
+pub struct MergedDeviceDefinition {
+  name: String,
+  devices: Vec<UserConfigDeviceIdentifier>,
+}
+
+impl MergedDeviceDefinition {
+  pub fn new(name: &str, devices: Vec<UserConfigDeviceIdentifier>) -> Self {
+    Self {
+      name: name.to_owned(),
+      devices,
+    }
+  }
+}
+
+/// A rule driving an actuator from readings of a sensor, as written in a user config, with the
+/// cooldown in milliseconds. Rules set exactly one of `above` and `below`, and drive an actuator of
+/// the sensor's device unless given another device.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Getters, CopyGetters, Setters)]
+pub struct SensorRuleDefinition {
+  #[getset(get = "pub", set = "pub")]
+  #[serde(rename = "sensor-device")]
+  sensor_device: UserConfigDeviceIdentifier,
+  #[getset(get_copy = "pub", set = "pub")]
+  #[serde(rename = "sensor-index")]
+  sensor_index: u32,
+  #[getset(get_copy = "pub", set = "pub")]
+  #[serde(rename = "sensor-type")]
+  sensor_type: SensorType,
+  #[getset(get_copy = "pub", set = "pub")]
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  above: Option<i32>,
+  #[getset(get_copy = "pub", set = "pub")]
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  below: Option<i32>,
+  #[getset(get_copy = "pub", set = "pub")]
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  hysteresis: Option<i32>,
+  #[getset(get_copy = "pub", set = "pub")]
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  cooldown: Option<u32>,
+  #[getset(get = "pub", set = "pub")]
+  #[serde(
+    rename = "actuator-device",
+    default,
+    skip_serializing_if = "Option::is_none"
+  )]
+  actuator_device: Option<UserConfigDeviceIdentifier>,
+  #[getset(get_copy = "pub", set = "pub")]
+  #[serde(rename = "actuator-index")]
+  actuator_index: u32,
+  #[getset(get_copy = "pub", set = "pub")]
+  #[serde(rename = "actuator-type")]
+  actuator_type: ActuatorType,
+  #[getset(get_copy = "pub", set = "pub")]
+  scalar: f64,
+}
+
+impl SensorRuleDefinition {
+  /// Converts the definition to the rule it describes.
+  pub fn sensor_rule(&self) -> Result<SensorRule, ButtplugDeviceError> {
+    let condition = match (self.above, self.below) {
+      (Some(threshold), None) => SensorCondition::Above(threshold),
+      (None, Some(threshold)) => SensorCondition::Below(threshold),
+      _ => {
+        return Err(ButtplugDeviceError::DeviceConfigurationError(
+          "Sensor rules need exactly one of above and below.".to_owned(),
+        ))
+      }
+    };
+    let sensor_device: ServerDeviceIdentifier = self.sensor_device.clone().into();
+    let actuator_device: ServerDeviceIdentifier = self
+      .actuator_device
+      .clone()
+      .map(|identifier| identifier.into())
+      .unwrap_or_else(|| sensor_device.clone());
+    let mut rule = SensorRule::new(
+      &sensor_device,
+      self.sensor_index,
+      self.sensor_type,
+      condition,
+      &actuator_device,
+      ScalarSubcommand::new(self.actuator_index, self.scalar, self.actuator_type),
+    );
+    rule
+      .set_hysteresis(self.hysteresis.unwrap_or(0))
+      .set_cooldown(Duration::from_millis(self.cooldown.unwrap_or(0) as u64));
+    Ok(rule)
+  }
+}
+
+/// Identifies the device a user config applies to. Written as an object, but can also be read from
+/// the versioned string format of [ServerDeviceIdentifier].
 #[derive(
   Deserialize, Serialize, Debug, Clone, Default, Getters, Setters, MutGetters, Eq, PartialEq, Hash,
 )]
+#[serde(try_from = "SerializedUserConfigDeviceIdentifier")]
 #[getset(get = "pub", set = "pub", get_mut = "pub(crate)")]
 pub struct UserConfigDeviceIdentifier {
   pub address: String,
@@ -166,6 +573,39 @@ pub struct UserConfigDeviceIdentifier {
   pub identifier: Option<String>,
 }
 
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SerializedUserConfigDeviceIdentifier {
+  Versioned(String),
+  Fields {
+    address: String,
+    protocol: String,
+    #[serde(default)]
+    identifier: Option<String>,
+  },
+}
+
+impl TryFrom<SerializedUserConfigDeviceIdentifier> for UserConfigDeviceIdentifier {
+  type Error = ButtplugDeviceError;
+
+  fn try_from(identifier: SerializedUserConfigDeviceIdentifier) -> Result<Self, Self::Error> {
+    match identifier {
+      SerializedUserConfigDeviceIdentifier::Versioned(identifier) => {
+        Ok(identifier.parse::<ServerDeviceIdentifier>()?.into())
+      }
+      SerializedUserConfigDeviceIdentifier::Fields {
+        address,
+        protocol,
+        identifier,
+      } => Ok(Self {
+        address,
+        protocol,
+        identifier,
+      }),
+    }
+  }
+}
+
 impl From<UserConfigDeviceIdentifier> for ServerDeviceIdentifier {
   fn from(ident: UserConfigDeviceIdentifier) -> Self {
     let server_identifier = if let Some(ident_string) = ident.identifier {
@@ -193,80 +633,131 @@ impl From<ServerDeviceIdentifier> for UserConfigDeviceIdentifier {
   }
 }
 
-#[derive(Default, Debug, Getters)]
+#[derive(Default, Getters)]
 #[getset(get = "pub")]
 struct ExternalDeviceConfiguration {
+  version: Option<String>,
   allow_list: Vec<String>,
   deny_list: Vec<String>,
   reserved_indexes: HashMap<u32, ServerDeviceIdentifier>,
+  battery_poll_intervals: HashMap<ServerDeviceIdentifier, u32>,
+  rssi_sample_intervals: HashMap<ServerDeviceIdentifier, u32>,
+  protocol_command_timeouts: HashMap<String, u32>,
+  command_timeouts: HashMap<ServerDeviceIdentifier, u32>,
+  protocol_max_update_rates: HashMap<String, u32>,
+  max_update_rates: HashMap<ServerDeviceIdentifier, u32>,
+  protocol_btle_connection_parameters: HashMap<String, BluetoothLEConnectionParametersDefinition>,
+  protocol_btle_write_coalescing: HashMap<String, BluetoothLEWriteCoalescingDefinition>,
+  protocol_overuse_protections: HashMap<String, OveruseProtectionDefinition>,
+  protocol_estim_interlocks: HashMap<String, EstimInterlockDefinition>,
+  warm_ups: HashMap<ServerDeviceIdentifier, u32>,
+  cool_downs: HashMap<ServerDeviceIdentifier, u32>,
+  sensor_calibrations: HashMap<ServerDeviceIdentifier, Vec<SensorCalibrationDefinition>>,
+  merged_devices: Vec<(String, Vec<ServerDeviceIdentifier>)>,
+  sensor_rules: Vec<SensorRuleDefinition>,
+  split_devices: Vec<ServerDeviceIdentifier>,
   protocol_specifiers: HashMap<String, Vec<ProtocolCommunicationSpecifier>>,
   protocol_attributes: HashMap<ProtocolAttributesIdentifier, ProtocolDeviceAttributes>,
+  lazy_protocol_attributes: HashMap<String, ProtocolAttributesLoader>,
   user_configs: HashMap<ServerDeviceIdentifier, ProtocolDeviceAttributes>,
 }
 
-impl From<ProtocolDefinition> for ProtocolDeviceConfiguration {
-  fn from(protocol_def: ProtocolDefinition) -> Self {
-    // Make a vector out of the protocol definition specifiers
+impl ProtocolSpecifiersDefinition {
+  /// Make a vector out of the protocol definition specifiers
+  fn specifiers(&self) -> Vec<ProtocolCommunicationSpecifier> {
     let mut specifiers = vec![];
-    if let Some(usb_vec) = &protocol_def.usb {
+    if let Some(usb_vec) = &self.usb {
       usb_vec
         .iter()
         .for_each(|spec| specifiers.push(ProtocolCommunicationSpecifier::USB(*spec)));
     }
-    if let Some(serial_vec) = &protocol_def.serial {
+    if let Some(serial_vec) = &self.serial {
       serial_vec
         .iter()
         .for_each(|spec| specifiers.push(ProtocolCommunicationSpecifier::Serial(spec.clone())));
     }
-    if let Some(hid_vec) = &protocol_def.hid {
+    if let Some(hid_vec) = &self.hid {
       hid_vec
         .iter()
         .for_each(|spec| specifiers.push(ProtocolCommunicationSpecifier::HID(*spec)));
     }
-    if let Some(btle) = &protocol_def.btle {
+    if let Some(btle) = &self.btle {
       specifiers.push(ProtocolCommunicationSpecifier::BluetoothLE(btle.clone()));
     }
-    if let Some(xinput) = &protocol_def.xinput {
+    if let Some(xinput) = &self.xinput {
       specifiers.push(ProtocolCommunicationSpecifier::XInput(*xinput));
     }
-    if let Some(websocket) = &protocol_def.websocket {
+    if let Some(websocket) = &self.websocket {
       specifiers.push(ProtocolCommunicationSpecifier::Websocket(websocket.clone()));
     }
-    if let Some(lcs) = &protocol_def.lovense_connect_service {
+    if let Some(lcs) = &self.lovense_connect_service {
       specifiers.push(ProtocolCommunicationSpecifier::LovenseConnectService(
         lcs.clone(),
       ));
     }
+    specifiers
+  }
+}
 
+impl From<ProtocolAttributesDefinition>
+  for HashMap<ProtocolAttributesType, ProtocolDeviceAttributes>
+{
+  fn from(attributes_def: ProtocolAttributesDefinition) -> Self {
     let mut configurations = HashMap::new();
 
     // TODO We should probably make a From for ProtocolAttributes into ProtocolDeviceAttributes.
-    if let Some(defaults) = protocol_def.defaults() {
-      let config_attrs = ProtocolDeviceAttributes::new(
+    if let Some(defaults) = attributes_def.defaults {
+      let mut config_attrs = ProtocolDeviceAttributes::new(
         ProtocolAttributesType::Default,
         defaults.name.clone(),
         None,
         defaults.messages.clone().unwrap_or_default(),
         None,
       );
+      config_attrs.set_invert_rotation(defaults.invert_rotation.unwrap_or(false));
+      config_attrs.set_localizations(defaults.localizations.clone().unwrap_or_default());
       configurations.insert(ProtocolAttributesType::Default, config_attrs);
     }
 
-    for config in protocol_def.configurations {
+    for config in attributes_def.configurations {
       if let Some(identifiers) = config.identifier {
         for identifier in identifiers {
-          let config_attrs = ProtocolDeviceAttributes::new(
+          let mut config_attrs = ProtocolDeviceAttributes::new(
             ProtocolAttributesType::Identifier(identifier.clone()),
             config.name.clone(),
             None,
             config.messages.clone().unwrap_or_default(),
             None,
           );
+          config_attrs.set_invert_rotation(config.invert_rotation.unwrap_or(false));
+          config_attrs.set_localizations(config.localizations.clone().unwrap_or_default());
           configurations.insert(ProtocolAttributesType::Identifier(identifier), config_attrs);
         }
       }
     }
 
+    configurations
+  }
+}
+
+impl From<ProtocolDefinition> for ProtocolDeviceConfiguration {
+  fn from(protocol_def: ProtocolDefinition) -> Self {
+    let specifiers = ProtocolSpecifiersDefinition {
+      usb: protocol_def.usb,
+      btle: protocol_def.btle,
+      serial: protocol_def.serial,
+      hid: protocol_def.hid,
+      xinput: protocol_def.xinput,
+      websocket: protocol_def.websocket,
+      lovense_connect_service: protocol_def.lovense_connect_service,
+      ..Default::default()
+    }
+    .specifiers();
+    let configurations = ProtocolAttributesDefinition {
+      defaults: protocol_def.defaults,
+      configurations: protocol_def.configurations,
+    }
+    .into();
     Self::new(specifiers, configurations)
   }
 }
@@ -284,6 +775,42 @@ fn add_user_configs_to_protocol(
         continue;
       }
 
+      if let Some(timeout) = protocol_def.command_timeout() {
+        external_config
+          .protocol_command_timeouts
+          .insert(user_config_protocol.clone(), *timeout);
+      }
+
+      if let Some(rate) = protocol_def.max_update_rate() {
+        external_config
+          .protocol_max_update_rates
+          .insert(user_config_protocol.clone(), *rate);
+      }
+
+      if let Some(parameters) = protocol_def.btle_connection_parameters() {
+        external_config
+          .protocol_btle_connection_parameters
+          .insert(user_config_protocol.clone(), *parameters);
+      }
+
+      if let Some(coalescing) = protocol_def.btle_write_coalescing() {
+        external_config
+          .protocol_btle_write_coalescing
+          .insert(user_config_protocol.clone(), coalescing.clone());
+      }
+
+      if let Some(protection) = protocol_def.overuse_protection() {
+        external_config
+          .protocol_overuse_protections
+          .insert(user_config_protocol.clone(), *protection);
+      }
+
+      if let Some(interlock) = protocol_def.estim_interlock() {
+        external_config
+          .protocol_estim_interlocks
+          .insert(user_config_protocol.clone(), *interlock);
+      }
+
       let base_protocol_def = external_config
         .protocol_specifiers
         .get_mut(user_config_protocol)
@@ -330,24 +857,83 @@ fn add_user_configs_to_protocol(
           .reserved_indexes
           .insert(*index, user_config.identifier().clone().into());
       }
+      if let Some(interval) = user_config.config().battery_poll_interval().as_ref() {
+        external_config
+          .battery_poll_intervals
+          .insert(user_config.identifier().clone().into(), *interval);
+      }
+      if let Some(interval) = user_config.config().rssi_sample_interval().as_ref() {
+        external_config
+          .rssi_sample_intervals
+          .insert(user_config.identifier().clone().into(), *interval);
+      }
+      if let Some(timeout) = user_config.config().command_timeout().as_ref() {
+        external_config
+          .command_timeouts
+          .insert(user_config.identifier().clone().into(), *timeout);
+      }
+      if let Some(rate) = user_config.config().max_update_rate().as_ref() {
+        external_config
+          .max_update_rates
+          .insert(user_config.identifier().clone().into(), *rate);
+      }
+      if let Some(warm_up) = user_config.config().warm_up().as_ref() {
+        external_config
+          .warm_ups
+          .insert(user_config.identifier().clone().into(), *warm_up);
+      }
+      if let Some(cool_down) = user_config.config().cool_down().as_ref() {
+        external_config
+          .cool_downs
+          .insert(user_config.identifier().clone().into(), *cool_down);
+      }
+      if let Some(calibrations) = user_config.config().sensor_calibration().as_ref() {
+        external_config.sensor_calibrations.insert(
+          user_config.identifier().clone().into(),
+          calibrations.clone(),
+        );
+      }
+      if *user_config.config().split().as_ref().unwrap_or(&false) {
+        external_config
+          .split_devices
+          .push(user_config.identifier().clone().into());
+      }
       let server_ident: ServerDeviceIdentifier = user_config.identifier.clone().into();
 
-      let config_attrs = ProtocolDeviceAttributes::new(
+      let mut config_attrs = ProtocolDeviceAttributes::new(
         server_ident.attributes_identifier().clone(),
         None,
         user_config.config().display_name.clone(),
         user_config.config().messages.clone().unwrap_or_default(),
         None,
       );
+      config_attrs.set_invert_rotation(user_config.config().invert_rotation.unwrap_or(false));
       info!("Adding user config for {:?}", server_ident);
       external_config
         .user_configs
         .insert(server_ident, config_attrs);
     }
   }
+  if let Some(merged_devices) = user_config_def.merged_devices() {
+    for merged_device in merged_devices {
+      let members = merged_device
+        .devices()
+        .iter()
+        .map(|identifier| identifier.clone().into())
+        .collect();
+      external_config
+        .merged_devices
+        .push((merged_device.name().clone(), members));
+    }
+  }
+  if let Some(sensor_rules) = user_config_def.sensor_rules() {
+    external_config
+      .sensor_rules
+      .extend(sensor_rules.iter().cloned());
+  }
 }
 
-#[derive(Deserialize, Serialize, Debug, CopyGetters)]
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, CopyGetters)]
 #[getset(get_copy = "pub", get_mut = "pub")]
 pub struct ConfigVersion {
   pub major: u32,
@@ -403,10 +989,8 @@ impl ProtocolConfiguration {
   }
 }
 
-fn get_internal_config_version() -> ConfigVersion {
-  let config: ProtocolConfiguration = serde_json::from_str(DEVICE_CONFIGURATION_JSON)
-    .expect("If this fails, the whole library goes with it.");
-  config.version
+pub(crate) fn get_internal_config_version() -> ConfigVersion {
+  VENDORED_DEVICE_CONFIGURATION.version
 }
 
 fn load_protocol_config_from_json(
@@ -440,21 +1024,11 @@ fn load_protocol_config_from_json(
   }
 }
 
-fn load_protocol_configs_internal(
-  main_config_str: Option<String>,
-  user_config_str: Option<String>,
+fn load_external_protocol_configs(
+  main_config_str: &str,
   skip_version_check: bool,
 ) -> Result<ExternalDeviceConfiguration, ButtplugDeviceError> {
-  if main_config_str.is_some() {
-    info!("Loading from custom base device configuration...")
-  } else {
-    info!("Loading from internal base device configuration...")
-  }
-  // Start by loading the main config
-  let main_config = load_protocol_config_from_json(
-    &main_config_str.unwrap_or_else(|| DEVICE_CONFIGURATION_JSON.to_owned()),
-    skip_version_check,
-  )?;
+  let main_config = load_protocol_config_from_json(main_config_str, skip_version_check)?;
 
   // Each protocol will need to become a ProtocolDeviceConfiguration, so we'll need to
   //
@@ -464,10 +1038,34 @@ fn load_protocol_configs_internal(
 
   let mut protocol_specifiers = HashMap::new();
   let mut protocol_attributes = HashMap::new();
+  let mut protocol_command_timeouts = HashMap::new();
+  let mut protocol_max_update_rates = HashMap::new();
+  let mut protocol_btle_connection_parameters = HashMap::new();
+  let mut protocol_btle_write_coalescing = HashMap::new();
+  let mut protocol_overuse_protections = HashMap::new();
+  let mut protocol_estim_interlocks = HashMap::new();
 
   // Iterate through all of the protocols in the main config first and build up a map of protocol
   // name to ProtocolDeviceConfiguration structs.
   for (protocol_name, protocol_def) in main_config.protocols.unwrap_or_default() {
+    if let Some(timeout) = protocol_def.command_timeout {
+      protocol_command_timeouts.insert(protocol_name.clone(), timeout);
+    }
+    if let Some(rate) = protocol_def.max_update_rate {
+      protocol_max_update_rates.insert(protocol_name.clone(), rate);
+    }
+    if let Some(parameters) = protocol_def.btle_connection_parameters {
+      protocol_btle_connection_parameters.insert(protocol_name.clone(), parameters);
+    }
+    if let Some(coalescing) = protocol_def.btle_write_coalescing.clone() {
+      protocol_btle_write_coalescing.insert(protocol_name.clone(), coalescing);
+    }
+    if let Some(protection) = protocol_def.overuse_protection {
+      protocol_overuse_protections.insert(protocol_name.clone(), protection);
+    }
+    if let Some(interlock) = protocol_def.estim_interlock {
+      protocol_estim_interlocks.insert(protocol_name.clone(), interlock);
+    }
     let protocol_device_config: ProtocolDeviceConfiguration = protocol_def.into();
     protocol_specifiers.insert(
       protocol_name.clone(),
@@ -479,15 +1077,105 @@ fn load_protocol_configs_internal(
     }
   }
 
-  let mut external_config = ExternalDeviceConfiguration {
+  Ok(ExternalDeviceConfiguration {
+    version: Some(main_config.version.to_string()),
     protocol_specifiers,
     protocol_attributes,
+    protocol_command_timeouts,
+    protocol_max_update_rates,
+    protocol_btle_connection_parameters,
+    protocol_btle_write_coalescing,
+    protocol_overuse_protections,
+    protocol_estim_interlocks,
+    ..Default::default()
+  })
+}
+
+/// Loads the device configuration compiled into the library. Only specifiers are parsed here,
+/// everything else about a protocol's devices is parsed the first time one of them connects.
+fn load_vendored_protocol_configs() -> Result<ExternalDeviceConfiguration, ButtplugDeviceError> {
+  let mut external_config = ExternalDeviceConfiguration {
+    version: Some(VENDORED_DEVICE_CONFIGURATION.version.to_string()),
     ..Default::default()
   };
 
+  let compiled_out_protocols = compiled_out_protocols();
+  for (protocol_name, protocol_json) in &VENDORED_DEVICE_CONFIGURATION.protocols {
+    if compiled_out_protocols.contains(&protocol_name.as_str()) {
+      continue;
+    }
+    let specifiers_def: ProtocolSpecifiersDefinition = serde_json::from_str(protocol_json.get())
+      .map_err(|err| ButtplugDeviceError::DeviceConfigurationError(format!("{}", err)))?;
+    if let Some(timeout) = specifiers_def.command_timeout {
+      external_config
+        .protocol_command_timeouts
+        .insert(protocol_name.clone(), timeout);
+    }
+    if let Some(rate) = specifiers_def.max_update_rate {
+      external_config
+        .protocol_max_update_rates
+        .insert(protocol_name.clone(), rate);
+    }
+    if let Some(parameters) = specifiers_def.btle_connection_parameters {
+      external_config
+        .protocol_btle_connection_parameters
+        .insert(protocol_name.clone(), parameters);
+    }
+    if let Some(coalescing) = specifiers_def.btle_write_coalescing.clone() {
+      external_config
+        .protocol_btle_write_coalescing
+        .insert(protocol_name.clone(), coalescing);
+    }
+    if let Some(protection) = specifiers_def.overuse_protection {
+      external_config
+        .protocol_overuse_protections
+        .insert(protocol_name.clone(), protection);
+    }
+    if let Some(interlock) = specifiers_def.estim_interlock {
+      external_config
+        .protocol_estim_interlocks
+        .insert(protocol_name.clone(), interlock);
+    }
+    external_config
+      .protocol_specifiers
+      .insert(protocol_name.clone(), specifiers_def.specifiers());
+    let protocol_json: &'static RawValue = protocol_json;
+    let loader: ProtocolAttributesLoader = Arc::new(move || {
+      serde_json::from_str::<ProtocolAttributesDefinition>(protocol_json.get())
+        .map(|attributes_def| attributes_def.into())
+        .map_err(|err| ButtplugDeviceError::DeviceConfigurationError(format!("{}", err)))
+    });
+    external_config
+      .lazy_protocol_attributes
+      .insert(protocol_name.clone(), loader);
+  }
+
+  Ok(external_config)
+}
+
+fn load_protocol_configs_internal(
+  main_config_str: Option<String>,
+  user_config_str: Option<String>,
+  skip_version_check: bool,
+) -> Result<ExternalDeviceConfiguration, ButtplugDeviceError> {
+  let mut external_config = if let Some(main_config_str) = main_config_str {
+    info!("Loading from custom base device configuration...");
+    load_external_protocol_configs(&main_config_str, skip_version_check)?
+  } else {
+    info!("Loading from internal base device configuration...");
+    load_vendored_protocol_configs()?
+  };
+
   // Then load the user config
   if let Some(user_config) = user_config_str {
     info!("Loading user configuration from string.");
+    // Upgrade configs written for older schemas, rather than throwing away user settings.
+    let (user_config, report) = migrate_user_config(&user_config)?;
+    if report.migrated() {
+      for change in report.changes() {
+        warn!("User configuration migration: {}", change);
+      }
+    }
     let config = load_protocol_config_from_json(&user_config, skip_version_check)?;
     if let Some(user_configs) = config.user_configs {
       add_user_configs_to_protocol(&mut external_config, user_configs);
@@ -509,6 +1197,10 @@ pub fn load_protocol_configs(
   let external_config =
     load_protocol_configs_internal(main_config_str, user_config_str, skip_version_check)?;
 
+  if let Some(version) = external_config.version() {
+    dcm_builder.version(version);
+  }
+
   for address in external_config.allow_list() {
     dcm_builder.allowed_address(address);
   }
@@ -521,6 +1213,77 @@ pub fn load_protocol_configs(
     dcm_builder.reserved_index(address, *index);
   }
 
+  for (address, interval) in external_config.battery_poll_intervals() {
+    dcm_builder.battery_poll_interval(address, Duration::from_millis(*interval as u64));
+  }
+
+  for (address, interval) in external_config.rssi_sample_intervals() {
+    dcm_builder.rssi_sample_interval(address, Duration::from_millis(*interval as u64));
+  }
+
+  for (name, timeout) in external_config.protocol_command_timeouts() {
+    dcm_builder.protocol_command_timeout(name, Duration::from_millis(*timeout as u64));
+  }
+
+  for (address, timeout) in external_config.command_timeouts() {
+    dcm_builder.command_timeout(address, Duration::from_millis(*timeout as u64));
+  }
+
+  for (name, rate) in external_config.protocol_max_update_rates() {
+    dcm_builder.protocol_max_update_rate(name, *rate);
+  }
+
+  for (address, rate) in external_config.max_update_rates() {
+    dcm_builder.max_update_rate(address, *rate);
+  }
+
+  for (name, parameters) in external_config.protocol_btle_connection_parameters() {
+    dcm_builder.protocol_btle_connection_parameters(name, (*parameters).try_into()?);
+  }
+
+  for (name, coalescing) in external_config.protocol_btle_write_coalescing() {
+    dcm_builder.protocol_btle_write_coalescing(name, coalescing.clone().into());
+  }
+
+  for (name, protection) in external_config.protocol_overuse_protections() {
+    dcm_builder.protocol_overuse_protection(name, (*protection).into());
+  }
+
+  for (name, interlock) in external_config.protocol_estim_interlocks() {
+    dcm_builder.protocol_estim_interlock(name, (*interlock).into());
+  }
+
+  for (address, warm_up) in external_config.warm_ups() {
+    dcm_builder.warm_up(address, Duration::from_millis(*warm_up as u64));
+  }
+
+  for (address, cool_down) in external_config.cool_downs() {
+    dcm_builder.cool_down(address, Duration::from_millis(*cool_down as u64));
+  }
+
+  for (address, calibrations) in external_config.sensor_calibrations() {
+    for calibration in calibrations {
+      dcm_builder.sensor_calibration(
+        address,
+        calibration.sensor_index(),
+        calibration.sensor_type(),
+        calibration.calibration().clone(),
+      );
+    }
+  }
+
+  for (name, members) in external_config.merged_devices() {
+    dcm_builder.merged_device(name, members);
+  }
+
+  for rule in external_config.sensor_rules() {
+    dcm_builder.sensor_rule(rule.sensor_rule()?);
+  }
+
+  for identifier in external_config.split_devices() {
+    dcm_builder.split_device(identifier);
+  }
+
   for (name, specifiers) in external_config.protocol_specifiers() {
     for spec in specifiers {
       dcm_builder.communication_specifier(name, spec.clone());
@@ -531,6 +1294,10 @@ pub fn load_protocol_configs(
     dcm_builder.protocol_attributes(ident.clone(), attributes.clone());
   }
 
+  for (name, loader) in external_config.lazy_protocol_attributes() {
+    dcm_builder.lazy_protocol_attributes(name, loader.clone());
+  }
+
   for (ident, attributes) in external_config.user_configs() {
     dcm_builder.protocol_attributes(ident.into(), attributes.clone());
   }
@@ -560,7 +1327,77 @@ pub fn create_test_dcm(allow_raw_messages: bool) -> DeviceConfigurationManager {
   for (ident, def) in devices.protocol_attributes {
     builder.protocol_attributes(ident, def);
   }
+  for (name, loader) in devices.lazy_protocol_attributes {
+    builder.lazy_protocol_attributes(&name, loader);
+  }
+  for (name, timeout) in devices.protocol_command_timeouts {
+    builder.protocol_command_timeout(&name, Duration::from_millis(timeout as u64));
+  }
+  for (name, rate) in devices.protocol_max_update_rates {
+    builder.protocol_max_update_rate(&name, rate);
+  }
+  for (name, parameters) in devices.protocol_btle_connection_parameters {
+    builder.protocol_btle_connection_parameters(
+      &name,
+      parameters
+        .try_into()
+        .expect("Vendored connection parameters are checked by the schema."),
+    );
+  }
+  for (name, coalescing) in devices.protocol_btle_write_coalescing {
+    builder.protocol_btle_write_coalescing(&name, coalescing.into());
+  }
+  for (name, protection) in devices.protocol_overuse_protections {
+    builder.protocol_overuse_protection(&name, protection.into());
+  }
+  for (name, interlock) in devices.protocol_estim_interlocks {
+    builder.protocol_estim_interlock(&name, interlock.into());
+  }
   builder
     .finish()
     .expect("If this fails, the whole library goes with it.")
 }
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_vendored_config_matches_schema() {
+    // The vendored config isn't validated when it's loaded, so make sure it'd pass.
+    JSONValidator::new(DEVICE_CONFIGURATION_JSON_SCHEMA)
+      .validate(DEVICE_CONFIGURATION_JSON)
+      .expect("Test, assuming infallible.");
+  }
+
+  #[test]
+  fn test_vendored_config_loads_attributes_lazily() {
+    let vendored = load_vendored_protocol_configs().expect("Test, assuming infallible.");
+    let mut external = load_external_protocol_configs(DEVICE_CONFIGURATION_JSON, false)
+      .expect("Test, assuming infallible.");
+    for protocol in compiled_out_protocols() {
+      external.protocol_specifiers.remove(protocol);
+      external.protocol_command_timeouts.remove(protocol);
+      external.protocol_max_update_rates.remove(protocol);
+    }
+    assert!(vendored.protocol_attributes.is_empty());
+    assert_eq!(vendored.protocol_specifiers, external.protocol_specifiers);
+    assert_eq!(
+      vendored.protocol_command_timeouts,
+      external.protocol_command_timeouts
+    );
+    assert_eq!(
+      vendored.protocol_max_update_rates,
+      external.protocol_max_update_rates
+    );
+    for (name, loader) in &vendored.lazy_protocol_attributes {
+      for (attributes_type, attributes) in loader().expect("Test, assuming infallible.") {
+        let ident = ProtocolAttributesIdentifier::new(name, &attributes_type, &None);
+        assert_eq!(
+          external.protocol_attributes[&ident].name(),
+          attributes.name()
+        );
+      }
+    }
+  }
+}
@@ -8,12 +8,19 @@
 //! Utility module, for storing types and functions used across other modules in
 //! the library.
 
+#[cfg(feature = "server")]
+pub mod address_privacy;
 pub mod async_manager;
 #[cfg(feature = "server")]
+pub mod config_migration;
+#[cfg(feature = "server")]
 pub mod device_configuration;
 pub mod future;
 pub mod json;
 pub mod logging;
+pub mod pairing;
+#[cfg(feature = "protocol-devtools")]
+pub mod protocol_devtools;
 pub mod stream;
 
 #[cfg(not(feature = "wasm"))]
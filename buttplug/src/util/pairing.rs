@@ -0,0 +1,352 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Pairing payloads, for connecting clients to remote servers without typing addresses.
+//!
+//! A server application (for instance, a desktop app running a websocket server) builds a
+//! [ButtplugPairingPayload] with everything a client needs to connect, and shows
+//! [ButtplugPairingPayload::encode]'s output as a QR code. The client (for instance, a mobile app)
+//! scans it and gets the same payload back with [ButtplugPairingPayload::parse].
+//!
+//! Payloads are a short binary encoding, in unpadded URL safe base64 after a `buttplug-pair:`
+//! scheme, so they fit in small QR codes and can be registered as a URL scheme by apps. The first
+//! byte of the binary encoding is a format version, so the format can change without older clients
+//! misreading newer payloads.
+//!
+//! Payloads aren't encrypted or signed. Anyone who sees the QR code can connect, which is the point,
+//! so auth tokens in payloads should be short lived or single use.
+
+use getset::{CopyGetters, Getters};
+use std::{fmt, str::FromStr};
+use thiserror::Error;
+
+/// Scheme pairing payloads start with.
+pub const PAIRING_PAYLOAD_SCHEME: &str = "buttplug-pair:";
+/// Version of the binary encoding written by this library.
+const PAIRING_PAYLOAD_VERSION: u8 = 1;
+const FLAG_TLS_FINGERPRINT: u8 = 0x01;
+const FLAG_AUTH_TOKEN: u8 = 0x02;
+const BASE64_ALPHABET: &[u8; 64] =
+  b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// Errors that can happen while building or parsing a pairing payload.
+#[derive(Debug, Error, Display, Clone, PartialEq, Eq)]
+pub enum ButtplugPairingPayloadError {
+  /// Pairing payload {0} is longer than the 255 bytes allowed
+  FieldTooLong(String),
+  /// Pairing payload host cannot be empty
+  EmptyHost,
+  /// Pairing payload does not start with "buttplug-pair:"
+  MissingScheme,
+  /// Pairing payload is not valid base64
+  InvalidEncoding,
+  /// Pairing payload version {0} is not supported, the application may need updating
+  UnsupportedVersion(u8),
+  /// Pairing payload is cut short or has unexpected data
+  Malformed,
+}
+
+/// Everything a client needs to connect to a remote server.
+#[derive(Debug, Clone, PartialEq, Eq, Getters, CopyGetters)]
+pub struct ButtplugPairingPayload {
+  /// Host name or IP address of the server.
+  #[getset(get = "pub")]
+  host: String,
+  /// Port the server is listening on.
+  #[getset(get_copy = "pub")]
+  port: u16,
+  /// SHA-256 hash of the server's TLS certificate (DER encoded), for clients to pin self signed
+  /// certificates to. Servers without TLS leave this out.
+  #[getset(get_copy = "pub")]
+  tls_fingerprint: Option<[u8; 32]>,
+  /// Token the client should present when connecting, if the server requires one.
+  #[getset(get = "pub")]
+  auth_token: Option<String>,
+}
+
+fn base64_encode(data: &[u8]) -> String {
+  let mut encoded = String::with_capacity((data.len() * 4).div_ceil(3));
+  for chunk in data.chunks(3) {
+    let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, byte)| {
+      bits | ((*byte as u32) << (16 - 8 * i))
+    });
+    // Each byte of input makes one character, plus one for the first.
+    for i in 0..=chunk.len() {
+      encoded.push(BASE64_ALPHABET[((bits >> (18 - 6 * i)) & 0x3F) as usize] as char);
+    }
+  }
+  encoded
+}
+
+fn base64_decode(encoded: &str) -> Result<Vec<u8>, ButtplugPairingPayloadError> {
+  let encoded = encoded.trim_end_matches('=').as_bytes();
+  // A single character left over can't make up a byte.
+  if encoded.len() % 4 == 1 {
+    return Err(ButtplugPairingPayloadError::InvalidEncoding);
+  }
+  let mut data = Vec::with_capacity(encoded.len() * 3 / 4);
+  for chunk in encoded.chunks(4) {
+    let mut bits = 0u32;
+    for (i, character) in chunk.iter().enumerate() {
+      let value = BASE64_ALPHABET
+        .iter()
+        .position(|c| c == character)
+        .ok_or(ButtplugPairingPayloadError::InvalidEncoding)?;
+      bits |= (value as u32) << (18 - 6 * i);
+    }
+    for i in 0..chunk.len() - 1 {
+      data.push((bits >> (16 - 8 * i)) as u8);
+    }
+  }
+  Ok(data)
+}
+
+fn push_field(
+  buffer: &mut Vec<u8>,
+  name: &str,
+  field: &[u8],
+) -> Result<(), ButtplugPairingPayloadError> {
+  let length = u8::try_from(field.len())
+    .map_err(|_| ButtplugPairingPayloadError::FieldTooLong(name.to_owned()))?;
+  buffer.push(length);
+  buffer.extend_from_slice(field);
+  Ok(())
+}
+
+/// Reads through the binary encoding, failing on anything cut short.
+struct PayloadReader<'a> {
+  data: &'a [u8],
+}
+
+impl<'a> PayloadReader<'a> {
+  fn take(&mut self, length: usize) -> Result<&'a [u8], ButtplugPairingPayloadError> {
+    if self.data.len() < length {
+      return Err(ButtplugPairingPayloadError::Malformed);
+    }
+    let (taken, rest) = self.data.split_at(length);
+    self.data = rest;
+    Ok(taken)
+  }
+
+  fn byte(&mut self) -> Result<u8, ButtplugPairingPayloadError> {
+    Ok(self.take(1)?[0])
+  }
+
+  fn string(&mut self) -> Result<String, ButtplugPairingPayloadError> {
+    let length = self.byte()? as usize;
+    String::from_utf8(self.take(length)?.to_vec())
+      .map_err(|_| ButtplugPairingPayloadError::Malformed)
+  }
+}
+
+impl ButtplugPairingPayload {
+  /// Creates a payload for the server at `host:port`. Fails if the host is empty, or if the host or
+  /// token are too long to encode.
+  pub fn new(
+    host: &str,
+    port: u16,
+    tls_fingerprint: Option<[u8; 32]>,
+    auth_token: Option<String>,
+  ) -> Result<Self, ButtplugPairingPayloadError> {
+    if host.is_empty() {
+      return Err(ButtplugPairingPayloadError::EmptyHost);
+    }
+    let payload = Self {
+      host: host.to_owned(),
+      port,
+      tls_fingerprint,
+      auth_token,
+    };
+    // Check the fields fit now, so encoding can't fail later.
+    payload.encode_binary()?;
+    Ok(payload)
+  }
+
+  /// Websocket address for connecting to the server, using a secure websocket if the server has a
+  /// TLS certificate.
+  pub fn websocket_url(&self) -> String {
+    let scheme = if self.tls_fingerprint.is_some() {
+      "wss"
+    } else {
+      "ws"
+    };
+    // IPv6 addresses need brackets to be told apart from the port.
+    if self.host.contains(':') {
+      format!("{}://[{}]:{}", scheme, self.host, self.port)
+    } else {
+      format!("{}://{}:{}", scheme, self.host, self.port)
+    }
+  }
+
+  fn encode_binary(&self) -> Result<Vec<u8>, ButtplugPairingPayloadError> {
+    let mut flags = 0;
+    if self.tls_fingerprint.is_some() {
+      flags |= FLAG_TLS_FINGERPRINT;
+    }
+    if self.auth_token.is_some() {
+      flags |= FLAG_AUTH_TOKEN;
+    }
+    let mut buffer = vec![PAIRING_PAYLOAD_VERSION, flags];
+    push_field(&mut buffer, "host", self.host.as_bytes())?;
+    buffer.extend_from_slice(&self.port.to_be_bytes());
+    if let Some(fingerprint) = &self.tls_fingerprint {
+      buffer.extend_from_slice(fingerprint);
+    }
+    if let Some(token) = &self.auth_token {
+      push_field(&mut buffer, "auth token", token.as_bytes())?;
+    }
+    Ok(buffer)
+  }
+
+  /// Encodes the payload as a string, ready to be shown as a QR code.
+  pub fn encode(&self) -> String {
+    let binary = self
+      .encode_binary()
+      .expect("Field lengths are checked when the payload is created.");
+    format!("{}{}", PAIRING_PAYLOAD_SCHEME, base64_encode(&binary))
+  }
+
+  /// Parses a payload made by [ButtplugPairingPayload::encode]. Surrounding whitespace, which QR
+  /// scanners sometimes add, is ignored.
+  pub fn parse(payload: &str) -> Result<Self, ButtplugPairingPayloadError> {
+    let encoded = payload
+      .trim()
+      .strip_prefix(PAIRING_PAYLOAD_SCHEME)
+      .ok_or(ButtplugPairingPayloadError::MissingScheme)?;
+    let data = base64_decode(encoded)?;
+    let mut reader = PayloadReader { data: &data };
+    let version = reader.byte()?;
+    if version != PAIRING_PAYLOAD_VERSION {
+      return Err(ButtplugPairingPayloadError::UnsupportedVersion(version));
+    }
+    let flags = reader.byte()?;
+    if flags & !(FLAG_TLS_FINGERPRINT | FLAG_AUTH_TOKEN) != 0 {
+      return Err(ButtplugPairingPayloadError::Malformed);
+    }
+    let host = reader.string()?;
+    let port_bytes = reader.take(2)?;
+    let port = u16::from_be_bytes([port_bytes[0], port_bytes[1]]);
+    let tls_fingerprint = if flags & FLAG_TLS_FINGERPRINT != 0 {
+      let mut fingerprint = [0; 32];
+      fingerprint.copy_from_slice(reader.take(32)?);
+      Some(fingerprint)
+    } else {
+      None
+    };
+    let auth_token = if flags & FLAG_AUTH_TOKEN != 0 {
+      Some(reader.string()?)
+    } else {
+      None
+    };
+    if !reader.data.is_empty() {
+      return Err(ButtplugPairingPayloadError::Malformed);
+    }
+    Self::new(&host, port, tls_fingerprint, auth_token)
+  }
+}
+
+impl fmt::Display for ButtplugPairingPayload {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.encode())
+  }
+}
+
+impl FromStr for ButtplugPairingPayload {
+  type Err = ButtplugPairingPayloadError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    Self::parse(s)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_base64_round_trip() {
+    // RFC 4648 test vectors, without padding.
+    for (data, encoded) in [
+      ("", ""),
+      ("f", "Zg"),
+      ("fo", "Zm8"),
+      ("foo", "Zm9v"),
+      ("foob", "Zm9vYg"),
+      ("fooba", "Zm9vYmE"),
+      ("foobar", "Zm9vYmFy"),
+    ] {
+      assert_eq!(base64_encode(data.as_bytes()), encoded);
+      assert_eq!(
+        base64_decode(encoded).expect("Test, assuming infallible."),
+        data.as_bytes()
+      );
+    }
+    // URL safe alphabet.
+    assert_eq!(base64_encode(&[0xFB, 0xFF]), "-_8");
+    assert_eq!(
+      base64_decode("Zm9vYg=="),
+      Ok(b"foob".to_vec()),
+      "Padding from other encoders should be accepted"
+    );
+    assert!(base64_decode("Zm9vY").is_err());
+    assert!(base64_decode("Zm9v+g").is_err());
+  }
+
+  #[test]
+  fn test_pairing_payload_round_trip() {
+    let payload = ButtplugPairingPayload::new(
+      "192.168.1.2",
+      12345,
+      Some([7; 32]),
+      Some("one-time-token".to_owned()),
+    )
+    .expect("Test, assuming infallible.");
+    let encoded = payload.encode();
+    assert!(encoded.starts_with(PAIRING_PAYLOAD_SCHEME));
+    assert_eq!(
+      ButtplugPairingPayload::parse(&format!(" {}\n", encoded)),
+      Ok(payload.clone())
+    );
+    assert_eq!(payload.websocket_url(), "wss://192.168.1.2:12345");
+
+    let payload = ButtplugPairingPayload::new("fe80::1", 12345, None, None)
+      .expect("Test, assuming infallible.");
+    assert_eq!(payload.encode(), "buttplug-pair:AQAHZmU4MDo6MTA5");
+    assert_eq!(payload.encode().parse(), Ok(payload.clone()));
+    assert_eq!(payload.websocket_url(), "ws://[fe80::1]:12345");
+  }
+
+  #[test]
+  fn test_pairing_payload_errors() {
+    assert_eq!(
+      ButtplugPairingPayload::new("", 12345, None, None),
+      Err(ButtplugPairingPayloadError::EmptyHost)
+    );
+    assert_eq!(
+      ButtplugPairingPayload::new("localhost", 12345, None, Some("a".repeat(256))),
+      Err(ButtplugPairingPayloadError::FieldTooLong(
+        "auth token".to_owned()
+      ))
+    );
+    assert_eq!(
+      ButtplugPairingPayload::parse("ws://localhost:12345"),
+      Err(ButtplugPairingPayloadError::MissingScheme)
+    );
+    assert_eq!(
+      ButtplugPairingPayload::parse("buttplug-pair:AQAHZmU4MDo6MTA"),
+      Err(ButtplugPairingPayloadError::Malformed)
+    );
+    assert_eq!(
+      ButtplugPairingPayload::parse(&format!(
+        "{}{}",
+        PAIRING_PAYLOAD_SCHEME,
+        base64_encode(&[2, 0])
+      )),
+      Err(ButtplugPairingPayloadError::UnsupportedVersion(2))
+    );
+  }
+}
@@ -0,0 +1,430 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Scaffolding for new protocol implementations.
+//!
+//! Adding a protocol means touching a handful of places: the protocol module itself, its entry in
+//! the device configuration file, its registration in the protocol map, and (for bluetooth
+//! protocols) a golden file for the protocol golden tests. [ProtocolScaffold] generates all of
+//! these from a protocol name and a communication specifier, laid out the way current protocols
+//! are, so contributors can start from working code and fill in the packet format.
+//!
+//! The module and golden file are new files, and can be written with
+//! [ProtocolScaffold::write_files]. The configuration entry and protocol map registration go into
+//! existing files, so they're only generated, and need to be pasted into place.
+
+use crate::server::device::{
+  configuration::ProtocolCommunicationSpecifier,
+  protocol::get_default_protocol_map,
+};
+use displaydoc::Display;
+use getset::Getters;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Header golden files start with, matching the one written when blessing golden files.
+const GOLDEN_HEADER: &str =
+  "# Protocol golden file. Regenerate with BUTTPLUG_BLESS_GOLDEN=1, see tests/util/device_test/golden.rs\n";
+
+/// Errors that can happen while generating or writing a protocol scaffold.
+#[derive(Debug, Error, Display, Clone, PartialEq, Eq)]
+pub enum ProtocolScaffoldError {
+  /// Protocol name "{0}" should be lowercase letters, numbers, and single dashes, starting with a letter
+  InvalidProtocolName(String),
+  /// Protocol "{0}" already exists
+  ProtocolAlreadyExists(String),
+  /// Scaffold file {0} already exists
+  FileAlreadyExists(String),
+  /// Could not write scaffold file {0}: {1}
+  WriteError(String, String),
+}
+
+/// Generated skeleton for a new protocol.
+#[derive(Debug, Clone, Getters)]
+#[getset(get = "pub")]
+pub struct ProtocolScaffold {
+  /// Protocol name, as used in the device configuration file (i.e. "galaku-pump").
+  name: String,
+  /// How devices using the protocol are found.
+  specifier: ProtocolCommunicationSpecifier,
+}
+
+impl ProtocolScaffold {
+  /// Create a scaffold for a protocol. Fails if the name isn't a valid protocol name, or if a
+  /// protocol with that name already exists.
+  pub fn new(
+    name: &str,
+    specifier: ProtocolCommunicationSpecifier,
+  ) -> Result<Self, ProtocolScaffoldError> {
+    let valid = name.starts_with(|c: char| c.is_ascii_lowercase())
+      && !name.ends_with('-')
+      && !name.contains("--")
+      && name
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    if !valid {
+      return Err(ProtocolScaffoldError::InvalidProtocolName(name.to_owned()));
+    }
+    if get_default_protocol_map().contains_key(name) {
+      return Err(ProtocolScaffoldError::ProtocolAlreadyExists(
+        name.to_owned(),
+      ));
+    }
+    Ok(Self {
+      name: name.to_owned(),
+      specifier,
+    })
+  }
+
+  /// Name of the Rust module for the protocol (i.e. "galaku_pump").
+  pub fn module_name(&self) -> String {
+    self.name.replace('-', "_")
+  }
+
+  /// Name of the protocol handler struct (i.e. "GalakuPump").
+  pub fn struct_name(&self) -> String {
+    self.name_parts().concat()
+  }
+
+  /// Placeholder display name for devices using the protocol (i.e. "Galaku Pump").
+  pub fn display_name(&self) -> String {
+    self.name_parts().join(" ")
+  }
+
+  fn name_parts(&self) -> Vec<String> {
+    self
+      .name
+      .split('-')
+      .map(|part| {
+        let mut chars = part.chars();
+        chars
+          .next()
+          .map(|first| first.to_ascii_uppercase().to_string() + chars.as_str())
+          .unwrap_or_default()
+      })
+      .collect()
+  }
+
+  /// Source of the protocol module, with a handler that writes vibration speeds to the tx
+  /// endpoint. The packet format is a placeholder, and will need to be replaced with whatever the
+  /// hardware expects.
+  pub fn module_source(&self) -> String {
+    format!(
+      r#"// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use crate::{{
+  core::{{errors::ButtplugDeviceError, message::Endpoint}},
+  server::device::{{
+    hardware::{{HardwareCommand, HardwareWriteCmd}},
+    protocol::{{generic_protocol_setup, ProtocolHandler}},
+  }},
+}};
+
+generic_protocol_setup!({struct_name}, "{name}");
+
+#[derive(Default)]
+pub struct {struct_name} {{}}
+
+impl ProtocolHandler for {struct_name} {{
+  fn handle_scalar_vibrate_cmd(
+    &self,
+    index: u32,
+    scalar: u32,
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {{
+    // TODO Replace with the packet format the hardware expects.
+    Ok(vec![HardwareWriteCmd::new(
+      Endpoint::Tx,
+      vec![index as u8, scalar as u8],
+      false,
+    )
+    .into()])
+  }}
+}}
+"#,
+      struct_name = self.struct_name(),
+      name = self.name
+    )
+  }
+
+  /// Entry for the protocols section of buttplug-device-config.yml, with a single vibrator as the
+  /// default device. The json version of the file needs the same entry.
+  pub fn config_entry(&self) -> String {
+    let (key, value) = match &self.specifier {
+      ProtocolCommunicationSpecifier::BluetoothLE(spec) => ("btle", serde_json::to_value(spec)),
+      ProtocolCommunicationSpecifier::HID(spec) => ("hid", serde_json::to_value(vec![spec])),
+      ProtocolCommunicationSpecifier::USB(spec) => ("usb", serde_json::to_value(vec![spec])),
+      ProtocolCommunicationSpecifier::Serial(spec) => ("serial", serde_json::to_value(vec![spec])),
+      ProtocolCommunicationSpecifier::XInput(spec) => ("xinput", serde_json::to_value(spec)),
+      ProtocolCommunicationSpecifier::LovenseConnectService(spec) => {
+        ("lovense-connect-service", serde_json::to_value(spec))
+      }
+      ProtocolCommunicationSpecifier::Websocket(spec) => ("websocket", serde_json::to_value(spec)),
+    };
+    let mut specifier = Value::Object(Default::default());
+    specifier[key] = value.expect("Specifiers always serialize.");
+    let mut entry = format!("  {}:\n", self.name);
+    write_yaml(&specifier, 4, &mut entry);
+    entry.push_str(&format!(
+      "    defaults:\n      name: {}\n      messages:\n        ScalarCmd:\n          - StepRange: [0, 100]\n            ActuatorType: Vibrate\n",
+      yaml_scalar(&Value::String(self.display_name()))
+    ));
+    entry
+  }
+
+  /// Lines registering the protocol in the protocol map, for src/server/device/protocol/mod.rs. The
+  /// module declaration and the map entry both go in alphabetical order with the others.
+  pub fn protocol_map_registration(&self) -> String {
+    format!(
+      "pub mod {module};\n\n  add_to_protocol_map(\n    &mut map,\n    {module}::setup::{struct_name}IdentifierFactory::default(),\n  );\n",
+      module = self.module_name(),
+      struct_name = self.struct_name()
+    )
+  }
+
+  /// Golden file listing a device for every bluetooth name the protocol is found by, ready to be
+  /// filled in by blessing the golden tests. Golden tests only drive bluetooth devices, so other
+  /// protocols don't get one.
+  pub fn golden_template(&self) -> Option<String> {
+    if let ProtocolCommunicationSpecifier::BluetoothLE(spec) = &self.specifier {
+      let mut names: Vec<String> = spec
+        .names()
+        .iter()
+        .map(|name| name.replace('*', "Golden"))
+        .collect();
+      names.sort();
+      let mut golden = format!("{}protocol: {}\ndevices:\n", GOLDEN_HEADER, self.name);
+      for name in names {
+        golden.push_str(&format!("- name: {}\n", yaml_scalar(&Value::String(name))));
+      }
+      Some(golden)
+    } else {
+      None
+    }
+  }
+
+  /// Write the protocol module and golden file (if the protocol has one) under the crate root,
+  /// returning the paths written. Nothing is written if any of the files already exist.
+  pub fn write_files(&self, crate_root: &Path) -> Result<Vec<PathBuf>, ProtocolScaffoldError> {
+    let mut files = vec![(
+      crate_root
+        .join("src")
+        .join("server")
+        .join("device")
+        .join("protocol")
+        .join(format!("{}.rs", self.module_name())),
+      self.module_source(),
+    )];
+    if let Some(golden) = self.golden_template() {
+      files.push((
+        crate_root
+          .join("tests")
+          .join("util")
+          .join("device_test")
+          .join("protocol_golden")
+          .join(format!("{}.yaml", self.name)),
+        golden,
+      ));
+    }
+    if let Some((path, _)) = files.iter().find(|(path, _)| path.exists()) {
+      return Err(ProtocolScaffoldError::FileAlreadyExists(
+        path.display().to_string(),
+      ));
+    }
+    for (path, contents) in &files {
+      std::fs::write(path, contents).map_err(|e| {
+        ProtocolScaffoldError::WriteError(path.display().to_string(), e.to_string())
+      })?;
+    }
+    Ok(files.into_iter().map(|(path, _)| path).collect())
+  }
+}
+
+/// Format a scalar value the way the device configuration file does, quoting strings only when
+/// YAML would read them as something else.
+fn yaml_scalar(value: &Value) -> String {
+  match value {
+    Value::String(s) => {
+      let needs_quotes = s.is_empty()
+        || s.starts_with(|c: char| "*&!|>'\"%@`#-{}[]?:,".contains(c) || c.is_whitespace())
+        || s.ends_with(char::is_whitespace)
+        || s.contains(": ")
+        || s.contains(" #")
+        || s.parse::<f64>().is_ok()
+        || ["true", "false", "null", "~"].contains(&s.to_ascii_lowercase().as_str());
+      if needs_quotes {
+        value.to_string()
+      } else {
+        s.clone()
+      }
+    }
+    value => value.to_string(),
+  }
+}
+
+/// Write a serialized specifier as block style YAML. Empty lists are left out, as specifiers
+/// serialize their optional lists even when there's nothing in them.
+fn write_yaml(value: &Value, indent: usize, out: &mut String) {
+  let pad = " ".repeat(indent);
+  match value {
+    Value::Object(map) => {
+      for (key, value) in map {
+        match value {
+          Value::Array(items) if items.is_empty() => {}
+          Value::Array(_) => {
+            out.push_str(&format!("{}{}:\n", pad, key));
+            write_yaml(value, indent + 2, out);
+          }
+          Value::Object(fields) if !fields.is_empty() => {
+            out.push_str(&format!("{}{}:\n", pad, key));
+            write_yaml(value, indent + 2, out);
+          }
+          _ => out.push_str(&format!("{}{}: {}\n", pad, key, yaml_scalar(value))),
+        }
+      }
+    }
+    Value::Array(items) => {
+      for item in items {
+        if let Value::Object(_) = item {
+          // Lay the object out one level deeper, then swap the indent of its first line for the
+          // list marker.
+          let mut item_yaml = String::new();
+          write_yaml(item, indent + 2, &mut item_yaml);
+          out.push_str(&format!(
+            "{}- {}",
+            pad,
+            item_yaml.get(indent + 2..).unwrap_or("{}\n")
+          ));
+        } else {
+          out.push_str(&format!("{}- {}\n", pad, yaml_scalar(item)));
+        }
+      }
+    }
+    value => out.push_str(&format!("{}{}\n", pad, yaml_scalar(value))),
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::{
+    core::message::Endpoint,
+    server::device::configuration::{BluetoothLESpecifier, HIDSpecifier},
+    util::device_configuration::ProtocolDefinition,
+  };
+  use std::collections::{HashMap, HashSet};
+  use uuid::Uuid;
+
+  fn btle_scaffold(name: &str, device_name: &str) -> ProtocolScaffold {
+    let service =
+      Uuid::parse_str("0000fff0-0000-1000-8000-00805f9b34fb").expect("Test, assuming infallible.");
+    let tx =
+      Uuid::parse_str("0000fff1-0000-1000-8000-00805f9b34fb").expect("Test, assuming infallible.");
+    ProtocolScaffold::new(
+      name,
+      ProtocolCommunicationSpecifier::BluetoothLE(BluetoothLESpecifier::new(
+        HashSet::from([device_name.to_owned()]),
+        vec![],
+        HashSet::new(),
+        HashMap::from([(service, HashMap::from([(Endpoint::Tx, tx)]))]),
+      )),
+    )
+    .expect("Test, assuming infallible.")
+  }
+
+  // The json configuration file is generated from the yml one, so go through json the same way.
+  fn parse_config_entry(entry: &str) -> HashMap<String, ProtocolDefinition> {
+    let value: serde_json::Value =
+      serde_yaml::from_str(entry).expect("Config entry should be valid YAML");
+    serde_json::from_value(value).expect("Config entry should be a valid protocol definition")
+  }
+
+  #[test]
+  fn test_protocol_scaffold_names() {
+    let scaffold = btle_scaffold("magic-wand-v2", "Wand");
+    assert_eq!(scaffold.module_name(), "magic_wand_v2");
+    assert_eq!(scaffold.struct_name(), "MagicWandV2");
+    assert_eq!(scaffold.display_name(), "Magic Wand V2");
+    assert!(scaffold
+      .module_source()
+      .contains("generic_protocol_setup!(MagicWandV2, \"magic-wand-v2\");"));
+    assert!(scaffold
+      .protocol_map_registration()
+      .contains("magic_wand_v2::setup::MagicWandV2IdentifierFactory::default()"));
+  }
+
+  #[test]
+  fn test_protocol_scaffold_rejects_bad_names() {
+    let specifier = ProtocolCommunicationSpecifier::HID(HIDSpecifier::new(1, 2));
+    for name in ["", "Wand", "2wand", "wand-", "magic--wand", "magic_wand"] {
+      assert_eq!(
+        ProtocolScaffold::new(name, specifier.clone()).unwrap_err(),
+        ProtocolScaffoldError::InvalidProtocolName(name.to_owned())
+      );
+    }
+    assert_eq!(
+      ProtocolScaffold::new("aneros", specifier).unwrap_err(),
+      ProtocolScaffoldError::ProtocolAlreadyExists("aneros".to_owned())
+    );
+  }
+
+  #[test]
+  fn test_protocol_scaffold_config_entry() {
+    let protocols = parse_config_entry(&btle_scaffold("magic-wand", "MW-*").config_entry());
+    let definition = &protocols["magic-wand"];
+    assert!(definition
+      .btle()
+      .as_ref()
+      .expect("Test, assuming infallible.")
+      .names()
+      .contains("MW-*"));
+    let defaults = definition
+      .defaults()
+      .as_ref()
+      .expect("Test, assuming infallible.");
+    assert_eq!(defaults.name().as_deref(), Some("Magic Wand"));
+    let scalars = defaults
+      .messages()
+      .as_ref()
+      .and_then(|messages| messages.scalar_cmd().clone())
+      .expect("Test, assuming infallible.");
+    assert_eq!(*scalars[0].step_range(), 0..=100);
+
+    let hid = ProtocolScaffold::new(
+      "magic-wand",
+      ProtocolCommunicationSpecifier::HID(HIDSpecifier::new(0xb49, 0x64f)),
+    )
+    .expect("Test, assuming infallible.");
+    let protocols = parse_config_entry(&hid.config_entry());
+    assert_eq!(
+      protocols["magic-wand"].hid().as_ref().map(|hid| hid.len()),
+      Some(1)
+    );
+  }
+
+  #[test]
+  fn test_protocol_scaffold_golden_template() {
+    assert_eq!(
+      btle_scaffold("magic-wand", "MW-*").golden_template(),
+      Some(format!(
+        "{}protocol: magic-wand\ndevices:\n- name: MW-Golden\n",
+        GOLDEN_HEADER
+      ))
+    );
+    let hid = ProtocolScaffold::new(
+      "magic-wand",
+      ProtocolCommunicationSpecifier::HID(HIDSpecifier::new(1, 2)),
+    )
+    .expect("Test, assuming infallible.");
+    assert!(hid.golden_template().is_none());
+  }
+}
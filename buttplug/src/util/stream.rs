@@ -7,7 +7,11 @@
 
 use async_stream::stream;
 use futures::{pin_mut, FutureExt, Stream};
-use tokio::sync::{broadcast, mpsc};
+use std::collections::VecDeque;
+use tokio::sync::{
+  broadcast::{self, error::RecvError, error::TryRecvError},
+  mpsc,
+};
 
 pub fn convert_broadcast_receiver_to_stream<T>(
   receiver: broadcast::Receiver<T>,
@@ -23,6 +27,56 @@ where
   }
 }
 
+/// Like [convert_broadcast_receiver_to_stream], but built for consumers that may not keep up.
+///
+/// Whenever the consumer comes back for another item, everything that queued up in the meantime is
+/// pulled from the channel, and older items are dropped in favor of newer ones that return the same
+/// conflation key. Items that return no key are always kept. If the consumer falls so far behind
+/// that the channel lags, the stream skips ahead instead of ending.
+pub fn convert_broadcast_receiver_to_conflated_stream<T, K, F>(
+  receiver: broadcast::Receiver<T>,
+  conflation_key: F,
+) -> impl Stream<Item = T>
+where
+  T: Unpin + Clone,
+  K: PartialEq,
+  F: Fn(&T) -> Option<K>,
+{
+  stream! {
+    pin_mut!(receiver);
+    let mut pending: VecDeque<T> = VecDeque::new();
+    loop {
+      if pending.is_empty() {
+        match receiver.recv().await {
+          Ok(val) => pending.push_back(val),
+          Err(RecvError::Lagged(skipped)) => {
+            warn!("Event stream consumer fell behind, skipped {} events.", skipped);
+            continue;
+          }
+          Err(RecvError::Closed) => break,
+        }
+      }
+      loop {
+        match receiver.try_recv() {
+          Ok(val) => {
+            if let Some(key) = conflation_key(&val) {
+              pending.retain(|queued| conflation_key(queued).as_ref() != Some(&key));
+            }
+            pending.push_back(val);
+          }
+          Err(TryRecvError::Lagged(skipped)) => {
+            warn!("Event stream consumer fell behind, skipped {} events.", skipped);
+          }
+          Err(TryRecvError::Empty) | Err(TryRecvError::Closed) => break,
+        }
+      }
+      if let Some(val) = pending.pop_front() {
+        yield val;
+      }
+    }
+  }
+}
+
 pub fn recv_now<T>(receiver: &mut mpsc::Receiver<T>) -> Option<Option<T>> {
   receiver.recv().now_or_never()
 }
@@ -30,3 +84,39 @@ pub fn recv_now<T>(receiver: &mut mpsc::Receiver<T>) -> Option<Option<T>> {
 pub fn iffy_is_empty_check<T>(receiver: &mut mpsc::Receiver<T>) -> bool {
   recv_now(receiver).is_none()
 }
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use futures::StreamExt;
+
+  #[tokio::test]
+  async fn test_conflated_stream_keeps_latest_per_key() {
+    let (sender, receiver) = broadcast::channel(16);
+    let stream = convert_broadcast_receiver_to_conflated_stream(receiver, |val: &(u32, u32)| {
+      (val.0 != 0).then_some(val.0)
+    });
+    pin_mut!(stream);
+    // Consumer hasn't polled yet, so all of these back up.
+    for (key, val) in [(1, 1), (0, 2), (2, 3), (1, 4), (0, 5), (2, 6), (1, 7)] {
+      sender.send((key, val)).expect("Receiver exists");
+    }
+    drop(sender);
+    let received: Vec<(u32, u32)> = stream.collect().await;
+    // Unkeyed values all get through, keyed values only keep their latest.
+    assert_eq!(received, vec![(0, 2), (0, 5), (2, 6), (1, 7)]);
+  }
+
+  #[tokio::test]
+  async fn test_conflated_stream_survives_lag() {
+    let (sender, receiver) = broadcast::channel(2);
+    let stream = convert_broadcast_receiver_to_conflated_stream(receiver, |_: &u32| None::<u32>);
+    pin_mut!(stream);
+    for i in 0..5 {
+      sender.send(i).expect("Receiver exists");
+    }
+    drop(sender);
+    let received: Vec<u32> = stream.collect().await;
+    assert_eq!(received, vec![3, 4]);
+  }
+}
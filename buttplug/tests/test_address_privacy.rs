@@ -0,0 +1,41 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+// Address hashing is process wide, so this lives in its own test binary to keep it from leaking
+// into other tests.
+
+use buttplug::{
+  server::{
+    device::{configuration::ProtocolAttributesType, ServerDeviceIdentifier},
+    ButtplugServerBuilder,
+  },
+  util::address_privacy::{address_hashing_enabled, display_address, hash_address},
+};
+
+#[tokio::test]
+async fn test_server_address_hashing() {
+  let address = "00:11:22:33:44:55";
+  let identifier =
+    ServerDeviceIdentifier::new(address, "lovense", &ProtocolAttributesType::Default);
+  assert!(!address_hashing_enabled());
+  assert!(format!("{:?}", identifier).contains(address));
+
+  ButtplugServerBuilder::default()
+    .address_hash_key("install-key")
+    .reserved_index(&identifier, 3)
+    .finish()
+    .expect("Test, assuming infallible.");
+  assert!(address_hashing_enabled());
+
+  let hashed = hash_address(b"install-key", address);
+  assert_eq!(display_address(address).to_string(), hashed);
+  let debug = format!("{:?}", identifier);
+  assert!(!debug.contains(address));
+  assert!(debug.contains(&hashed));
+  // Persisted identifiers still carry the real address.
+  assert_eq!(identifier.to_string(), format!("1;lovense;;{}", address));
+}
@@ -6,12 +6,27 @@
 // for full license information.
 
 mod util;
-use util::{test_client, test_client_with_delayed_device_manager, test_client_with_device};
+use util::{
+  test_client,
+  test_client_with_delayed_device_manager,
+  test_client_with_device,
+  test_device_manager::{
+    TestDeviceChannelHost,
+    TestDeviceCommunicationManagerBuilder,
+    TestDeviceIdentifier,
+  },
+};
 extern crate buttplug;
 extern crate tracing;
 
 use buttplug::{
-  client::{ButtplugClient, ButtplugClientError, ButtplugClientEvent},
+  client::{
+    ButtplugClient,
+    ButtplugClientDevice,
+    ButtplugClientError,
+    ButtplugClientEvent,
+    DeviceCommand,
+  },
   core::{
     connector::{
       ButtplugConnector,
@@ -19,15 +34,21 @@ use buttplug::{
       ButtplugConnectorResultFuture,
       ButtplugInProcessClientConnectorBuilder,
     },
-    errors::{ButtplugDeviceError, ButtplugError},
-    message::{ButtplugCurrentSpecClientMessage, ButtplugCurrentSpecServerMessage},
+    errors::{ButtplugDeviceError, ButtplugError, ButtplugMessageError},
+    message::{
+      self,
+      ButtplugCurrentSpecClientMessage,
+      ButtplugCurrentSpecServerMessage,
+      ButtplugServerMessage,
+      BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
+    },
   },
-  server::ButtplugServerBuilder,
+  server::{device::hardware::HardwareCommand, ButtplugServerBuilder},
   util::async_manager,
 };
 
 use futures::{future::BoxFuture, StreamExt};
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 use tokio::{sync::mpsc::Sender, time::sleep};
 
 #[derive(Default)]
@@ -62,6 +83,38 @@ async fn test_failing_connection() {
     .is_err());
 }
 
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_client_error_hints() {
+  let client = ButtplugClient::new("Test Client");
+  let err = client
+    .connect(ButtplugFailingConnector::default())
+    .await
+    .unwrap_err();
+  assert!(matches!(
+    err,
+    ButtplugClientError::ButtplugConnectorError(ButtplugConnectorError::ConnectorNotConnected)
+  ));
+  assert!(err.is_user_actionable());
+  assert!(!err.is_retryable());
+
+  let err = ButtplugClientError::from(ButtplugError::from(
+    ButtplugDeviceError::DeviceCommunicationError("Write failed".to_owned()),
+  ));
+  assert!(matches!(err, ButtplugClientError::ButtplugDeviceError(_)));
+  assert!(err.is_retryable());
+
+  let err = ButtplugClientError::from(ButtplugError::from(
+    ButtplugMessageError::InvalidMessageContents("Speed out of range".to_owned()),
+  ));
+  assert!(matches!(
+    err,
+    ButtplugClientError::ButtplugValidationError(_)
+  ));
+  assert!(!err.is_retryable());
+  assert!(!err.is_user_actionable());
+}
+
 #[cfg(feature = "server")]
 #[tokio::test]
 async fn test_disconnect_status() {
@@ -85,6 +138,162 @@ async fn test_connect_init() {
   assert_eq!(client.server_name(), Some("Buttplug Server".to_owned()));
 }
 
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_connect_server_versions() {
+  let client = test_client().await;
+  assert!(client.server_device_config_version().is_some());
+  assert_eq!(
+    client.server_library_version(),
+    Some(env!("CARGO_PKG_VERSION").to_owned())
+  );
+}
+
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_client_paged_device_list() {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let _devices: Vec<_> = (0..5)
+    .map(|_| builder.add_test_device(&TestDeviceIdentifier::new("Massage Demo", None)))
+    .collect();
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder.comm_manager(builder).device_list_page_size(2);
+  let server = server_builder.finish().unwrap();
+
+  // Get all of the devices connected to the server before the client shows up, so they all arrive
+  // via the device list during the handshake.
+  let recv = server.event_stream();
+  futures::pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Setup Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  let mut added = 0;
+  while let Some(msg) = recv.next().await {
+    if matches!(*msg, ButtplugServerMessage::DeviceAdded(_)) {
+      added += 1;
+      if added == 5 {
+        break;
+      }
+    }
+  }
+  server.disconnect().await.expect("Test, assuming infallible.");
+
+  let connector = ButtplugInProcessClientConnectorBuilder::default()
+    .server(server)
+    .finish();
+  let client = ButtplugClient::new("Test Client");
+  let mut event_stream = client.event_stream();
+  client
+    .connect(connector)
+    .await
+    .expect("Test, assuming infallible.");
+  let mut indexes = vec![];
+  while indexes.len() < 5 {
+    if let Some(ButtplugClientEvent::DeviceAdded(device)) = event_stream.next().await {
+      indexes.push(device.index());
+    }
+  }
+  indexes.sort();
+  assert_eq!(indexes, vec![0, 1, 2, 3, 4]);
+}
+
+async fn test_client_with_scene_devices() -> (
+  ButtplugClient,
+  Vec<Arc<ButtplugClientDevice>>,
+  Vec<TestDeviceChannelHost>,
+) {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let devices: Vec<_> = ["SceneAddress1", "SceneAddress2"]
+    .iter()
+    .map(|address| {
+      builder.add_test_device(&TestDeviceIdentifier::new(
+        "Massage Demo",
+        Some(address.to_string()),
+      ))
+    })
+    .collect();
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder.comm_manager(builder);
+  let connector = ButtplugInProcessClientConnectorBuilder::default()
+    .server(server_builder.finish().expect("Test, assuming infallible."))
+    .finish();
+  let client = ButtplugClient::new("Test Client");
+  let mut event_stream = client.event_stream();
+  client
+    .connect(connector)
+    .await
+    .expect("Test, assuming infallible.");
+  client
+    .start_scanning()
+    .await
+    .expect("Test, assuming infallible.");
+  let mut client_devices = vec![];
+  while client_devices.len() < 2 {
+    if let Some(ButtplugClientEvent::DeviceAdded(device)) = event_stream.next().await {
+      client_devices.push(device);
+    }
+  }
+  (client, client_devices, devices)
+}
+
+fn scene_vibrate(device: &ButtplugClientDevice, speed: f64) -> DeviceCommand {
+  DeviceCommand::Scalar(message::ScalarCmd::new(
+    device.index(),
+    vec![message::ScalarSubcommand::new(
+      0,
+      speed,
+      message::ActuatorType::Vibrate,
+    )],
+  ))
+}
+
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_client_send_scene() {
+  let (client, client_devices, mut devices) = test_client_with_scene_devices().await;
+  client
+    .send_scene(
+      client_devices
+        .iter()
+        .map(|device| scene_vibrate(device, 0.5))
+        .collect(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  for device in &mut devices {
+    assert!(matches!(
+      device.receiver.recv().await,
+      Some(HardwareCommand::Write(_))
+    ));
+  }
+}
+
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_client_send_scene_unknown_device() {
+  let (client, client_devices, mut devices) = test_client_with_scene_devices().await;
+  let mut scene = vec![scene_vibrate(&client_devices[0], 0.5)];
+  scene.push(DeviceCommand::Stop(message::StopDeviceCmd::new(100)));
+  assert!(matches!(
+    client.send_scene(scene).await,
+    Err(ButtplugClientError::ButtplugDeviceError(
+      ButtplugDeviceError::DeviceNotAvailable(100)
+    ))
+  ));
+  // Nothing in the scene should have been sent.
+  sleep(Duration::from_millis(100)).await;
+  for device in &mut devices {
+    assert!(device.receiver.try_recv().is_err());
+  }
+}
+
 #[cfg(feature = "server")]
 #[tokio::test]
 async fn test_client_connected_status() {
@@ -109,10 +318,10 @@ async fn test_start_scanning() {
 async fn test_stop_scanning_when_not_scanning() {
   let (client, _) = test_client_with_device().await;
   let should_be_err = client.stop_scanning().await;
-  if let Err(ButtplugClientError::ButtplugError(bp_err)) = should_be_err {
+  if let Err(ButtplugClientError::ButtplugDeviceError(device_err)) = should_be_err {
     assert!(matches!(
-      bp_err,
-      ButtplugError::ButtplugDeviceError(ButtplugDeviceError::DeviceScanningAlreadyStopped)
+      device_err,
+      ButtplugDeviceError::DeviceScanningAlreadyStopped
     ));
   } else {
     panic!("Should've thrown error!");
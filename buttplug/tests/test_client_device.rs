@@ -8,21 +8,48 @@
 mod util;
 use buttplug::{
   client::{
+    ButtplugClient,
+    ButtplugClientDevice,
     ButtplugClientDeviceEvent,
     ButtplugClientError,
     ButtplugClientEvent,
+    HeldFeature,
     ScalarValueCommand,
   },
   core::{
-    errors::{ButtplugDeviceError, ButtplugError, ButtplugMessageError},
-    message::{self, ButtplugClientMessage, ClientDeviceMessageAttributes},
+    connector::ButtplugInProcessClientConnectorBuilder,
+    errors::{ButtplugDeviceError, ButtplugMessageError},
+    message::{
+      self,
+      ActuatorType,
+      ButtplugClientMessage,
+      ClientDeviceMessageAttributes,
+      ClientDeviceMessageAttributesBuilder,
+      ClientGenericDeviceMessageAttributes,
+      Endpoint,
+      FeaturePlacement,
+      FeaturePosition,
+      SensorType,
+    },
+  },
+  server::{
+    device::hardware::{HardwareCommand, HardwareWriteCmd},
+    ButtplugServerBuilder,
   },
   util::async_manager,
 };
 use futures::StreamExt;
 use std::{sync::Arc, time::Duration};
 use tokio::time::sleep;
-use util::{test_client_with_device, test_device_manager::TestHardwareEvent};
+use util::{
+  test_client_with_device,
+  test_device_manager::{
+    TestDeviceChannelHost,
+    TestDeviceCommunicationManagerBuilder,
+    TestDeviceIdentifier,
+    TestHardwareEvent,
+  },
+};
 
 #[cfg(feature = "server")]
 #[tokio::test]
@@ -62,6 +89,91 @@ async fn test_client_device_connected_status() {
   assert!(!client.connected());
 }
 
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_client_device_command_after_disconnect() {
+  let (client, device) = test_client_with_device().await;
+
+  let mut event_stream = client.event_stream();
+  client
+    .start_scanning()
+    .await
+    .expect("Test, assuming infallible.");
+  let mut client_device = None;
+  while let Some(msg) = event_stream.next().await {
+    if let ButtplugClientEvent::DeviceAdded(da) = msg {
+      client_device = Some(da);
+      break;
+    }
+  }
+  let test_device = client_device.expect("Test, assuming infallible.");
+  let mut device_event_stream = test_device.event_stream();
+  device
+    .sender
+    .send(TestHardwareEvent::Disconnect)
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = device_event_stream.next().await {
+    if let ButtplugClientDeviceEvent::DeviceRemoved = msg {
+      break;
+    }
+  }
+  assert!(matches!(
+    test_device
+      .vibrate(&ScalarValueCommand::ScalarValue(0.5))
+      .await
+      .unwrap_err(),
+    ButtplugClientError::ButtplugDeviceError(ButtplugDeviceError::DeviceDisconnected(..))
+  ));
+}
+
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_client_device_disconnect_during_command() {
+  let (client, device) = test_client_with_device().await;
+  let test_device = scanned_device(&client).await;
+  let mut device_event_stream = test_device.event_stream();
+  // Test devices hold 256 writes until the test takes them, and this device takes a write per
+  // motor, so the next command is still waiting on the device when it disconnects.
+  for i in 0..128 {
+    test_device
+      .vibrate(&ScalarValueCommand::ScalarValue(if i % 2 == 0 {
+        0.2
+      } else {
+        0.4
+      }))
+      .await
+      .expect("Test, assuming infallible.");
+  }
+  let in_flight = tokio::spawn(test_device.vibrate(&ScalarValueCommand::ScalarValue(0.6)));
+  sleep(Duration::from_millis(50)).await;
+  device
+    .disconnect()
+    .await
+    .expect("Test, assuming infallible.");
+  let identifier = match in_flight.await.expect("Test, assuming infallible.") {
+    Err(ButtplugClientError::ButtplugDeviceError(ButtplugDeviceError::DeviceDisconnected(
+      identifier,
+    ))) => identifier,
+    result => panic!("Expected a disconnect, got {:?}", result),
+  };
+  assert_eq!(identifier, *test_device.name());
+  while let Some(msg) = device_event_stream.next().await {
+    if let ButtplugClientDeviceEvent::DeviceRemoved = msg {
+      break;
+    }
+  }
+  // Commands failed by the client name the device the same way the server does.
+  assert!(matches!(
+    test_device
+      .vibrate(&ScalarValueCommand::ScalarValue(0.5))
+      .await,
+    Err(ButtplugClientError::ButtplugDeviceError(ButtplugDeviceError::DeviceDisconnected(
+      fast_fail_identifier,
+    ))) if fast_fail_identifier == identifier
+  ));
+}
+
 #[cfg(feature = "server")]
 #[tokio::test]
 async fn test_client_device_client_disconnected_status() {
@@ -147,27 +259,21 @@ async fn test_client_device_invalid_command() {
       .vibrate(&ScalarValueCommand::ScalarValue(2.0))
       .await
       .unwrap_err(),
-    ButtplugClientError::ButtplugError(ButtplugError::ButtplugMessageError(
-      ButtplugMessageError::InvalidMessageContents(..)
-    ))
+    ButtplugClientError::ButtplugValidationError(ButtplugMessageError::InvalidMessageContents(..))
   ));
   assert!(matches!(
     test_device
       .vibrate(&ScalarValueCommand::ScalarValueVec(vec!(0.5, 0.5, 0.5)))
       .await
       .unwrap_err(),
-    ButtplugClientError::ButtplugError(ButtplugError::ButtplugDeviceError(
-      ButtplugDeviceError::DeviceFeatureCountMismatch(..)
-    ))
+    ButtplugClientError::ButtplugDeviceError(ButtplugDeviceError::DeviceFeatureCountMismatch(..))
   ));
   assert!(matches!(
     test_device
       .vibrate(&ScalarValueCommand::ScalarValueVec(vec!()))
       .await
       .unwrap_err(),
-    ButtplugClientError::ButtplugError(ButtplugError::ButtplugDeviceError(
-      ButtplugDeviceError::ProtocolRequirementError(..)
-    ))
+    ButtplugClientError::ButtplugDeviceError(ButtplugDeviceError::ProtocolRequirementError(..))
   ));
 }
 
@@ -219,6 +325,59 @@ async fn test_client_repeated_deviceadded_message() {
   ));
 }
 
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_client_device_feature_parts() {
+  let helper = Arc::new(util::ChannelClientTestHelper::new());
+  helper.simulate_successful_connect().await;
+  let helper_clone = helper.clone();
+  let mut event_stream = helper.client().event_stream();
+  async_manager::spawn(async move {
+    assert!(matches!(
+      helper_clone.next_client_message().await,
+      ButtplugClientMessage::StartScanning(..)
+    ));
+    helper_clone
+      .send_client_incoming(message::Ok::new(3).into())
+      .await;
+    let feature = |part: Option<&str>, position| {
+      let mut attrs = ClientGenericDeviceMessageAttributes::new("N/A", 20, ActuatorType::Vibrate);
+      attrs.set_placement(part.map(|part| FeaturePlacement::new(part, position)));
+      attrs
+    };
+    let mut builder = ClientDeviceMessageAttributesBuilder::default();
+    builder.scalar_cmd(&[
+      feature(Some("Shaft"), Some(FeaturePosition::Internal)),
+      feature(Some("Arm"), Some(FeaturePosition::External)),
+      feature(None, None),
+      feature(Some("shaft"), Some(FeaturePosition::Tip)),
+    ]);
+    let attributes = builder.finish();
+    helper_clone
+      .send_client_incoming(
+        message::DeviceAdded::new(1, "Test Device", &None, &None, &attributes).into(),
+      )
+      .await;
+  });
+  helper
+    .client()
+    .start_scanning()
+    .await
+    .expect("Test, assuming infallible.");
+  let test_device = match event_stream.next().await {
+    Some(ButtplugClientEvent::DeviceAdded(device)) => device,
+    _ => panic!("Test, expected DeviceAdded."),
+  };
+  assert_eq!(test_device.parts(), vec!["Shaft", "Arm"]);
+  let shaft: Vec<u32> = test_device
+    .scalar_attributes_on_part("SHAFT")
+    .iter()
+    .map(|attrs| *attrs.index())
+    .collect();
+  assert_eq!(shaft, vec![0, 3]);
+  assert!(test_device.scalar_attributes_on_part("Handle").is_empty());
+}
+
 #[cfg(feature = "server")]
 #[tokio::test]
 async fn test_client_repeated_deviceremoved_message() {
@@ -284,3 +443,187 @@ async fn test_client_repeated_deviceremoved_message() {
 // TODO Test DeviceList being sent followed by repeat DeviceAdded
 // TODO Test DeviceList being sent multiple times
 // TODO Test sending device return for device that doesn't exist (in client)
+
+#[cfg(feature = "server")]
+async fn scanned_device(client: &buttplug::client::ButtplugClient) -> Arc<ButtplugClientDevice> {
+  let mut event_stream = client.event_stream();
+  client
+    .start_scanning()
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = event_stream.next().await {
+    if let ButtplugClientEvent::DeviceAdded(da) = msg {
+      return da;
+    }
+  }
+  panic!("Device should have been added.");
+}
+
+#[cfg(feature = "server")]
+async fn expect_write(device: &mut TestDeviceChannelHost, data: Vec<u8>) {
+  let command = tokio::time::timeout(Duration::from_secs(1), device.receiver.recv())
+    .await
+    .expect("Write should arrive in time.");
+  assert_eq!(
+    command,
+    Some(HardwareCommand::Write(HardwareWriteCmd::new(
+      Endpoint::Tx,
+      data,
+      false
+    )))
+  );
+}
+
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_client_device_hold_stops_on_task_cancel() {
+  let (client, mut device) = test_client_with_device().await;
+  let test_device = scanned_device(&client).await;
+  test_device
+    .vibrate(&ScalarValueCommand::ScalarValue(0.5))
+    .await
+    .expect("Test, assuming infallible.");
+  expect_write(&mut device, vec![0xF1, 64]).await;
+  expect_write(&mut device, vec![0xF2, 64]).await;
+
+  let hold = test_device.hold();
+  let task = tokio::spawn(async move {
+    let _hold = hold;
+    futures::future::pending::<()>().await;
+  });
+  sleep(Duration::from_millis(50)).await;
+  task.abort();
+  assert!(task.await.expect_err("Task was aborted").is_cancelled());
+
+  expect_write(&mut device, vec![0xF1, 0]).await;
+  expect_write(&mut device, vec![0xF2, 0]).await;
+}
+
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_client_device_hold_features() {
+  let (client, mut device) = test_client_with_device().await;
+  let test_device = scanned_device(&client).await;
+  assert!(matches!(
+    test_device.hold_features(&[HeldFeature::Scalar(2)]),
+    Err(ButtplugDeviceError::DeviceFeatureIndexError(2, 2))
+  ));
+  assert!(test_device
+    .hold_features(&[HeldFeature::Rotate(0)])
+    .is_err());
+
+  test_device
+    .vibrate(&ScalarValueCommand::ScalarValue(0.5))
+    .await
+    .expect("Test, assuming infallible.");
+  expect_write(&mut device, vec![0xF1, 64]).await;
+  expect_write(&mut device, vec![0xF2, 64]).await;
+  drop(
+    test_device
+      .hold_features(&[HeldFeature::Scalar(1)])
+      .expect("Test, assuming infallible."),
+  );
+  expect_write(&mut device, vec![0xF2, 0]).await;
+
+  // Released holds leave the device running.
+  test_device.hold().release();
+  assert!(
+    tokio::time::timeout(Duration::from_millis(100), device.receiver.recv())
+      .await
+      .is_err()
+  );
+}
+
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_client_device_rssi_stream() {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let device = builder.add_test_device(&TestDeviceIdentifier::new(
+    "Flamingo",
+    Some("RssiAddress".to_owned()),
+  ));
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder.comm_manager(builder).user_device_configuration_json(Some(
+    r#"
+    {
+      "version": { "major": 2, "minor": 0 },
+      "user-configs": {
+        "devices": [
+          {
+            "identifier": {
+              "address": "RssiAddress",
+              "protocol": "magic-motion-1",
+              "identifier": "Flamingo"
+            },
+            "config": { "rssi-sample-interval": 10 }
+          }
+        ]
+      }
+    }
+    "#
+    .to_owned(),
+  ));
+  let connector = ButtplugInProcessClientConnectorBuilder::default()
+    .server(server_builder.finish().expect("Test, assuming infallible."))
+    .finish();
+  let client = ButtplugClient::new("Test Client");
+  client
+    .connect(connector)
+    .await
+    .expect("Test, assuming infallible.");
+  device
+    .sender
+    .send(TestHardwareEvent::Rssi(-40))
+    .await
+    .expect("Test, assuming infallible.");
+  let test_device = scanned_device(&client).await;
+
+  let mut levels = test_device
+    .subscribe_rssi()
+    .await
+    .expect("Test, assuming infallible.");
+  assert_eq!(levels.next().await, Some(0.6));
+  device
+    .sender
+    .send(TestHardwareEvent::Rssi(-30))
+    .await
+    .expect("Test, assuming infallible.");
+  tokio::time::timeout(Duration::from_secs(1), async {
+    while let Some(level) = levels.next().await {
+      if level == 0.7 {
+        return;
+      }
+    }
+    panic!("Stream should not end while the device is connected.");
+  })
+  .await
+  .expect("Level change should arrive in time.");
+
+  // Dropping the stream unsubscribes, so the server stops sending readings.
+  drop(levels);
+  sleep(Duration::from_millis(50)).await;
+  let mut event_stream = test_device.event_stream();
+  device
+    .sender
+    .send(TestHardwareEvent::Rssi(-20))
+    .await
+    .expect("Test, assuming infallible.");
+  assert!(
+    tokio::time::timeout(Duration::from_millis(100), event_stream.next())
+      .await
+      .is_err()
+  );
+}
+
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_client_device_sensor_stream_unsupported() {
+  let (client, _) = test_client_with_device().await;
+  let test_device = scanned_device(&client).await;
+  assert!(matches!(
+    test_device.subscribe_rssi().await,
+    Err(ButtplugClientError::ButtplugDeviceError(
+      ButtplugDeviceError::ProtocolSensorNotSupported(SensorType::RSSI)
+    ))
+  ));
+}
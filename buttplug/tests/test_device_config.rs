@@ -213,3 +213,687 @@ async fn test_invalid_step_range_device_config_wrong_order() {
     .finish()
     .is_ok());
 }
+
+#[cfg(feature = "server")]
+#[test]
+fn test_user_config_versioned_identifier() {
+  use buttplug::util::device_configuration::{load_user_configs, UserConfigDeviceIdentifier};
+  let user_config_json = r#"
+  {
+    "version": {
+      "major": 2,
+      "minor": 999
+    },
+    "user-configs": {
+      "devices": [
+        {
+          "identifier": "1;lovense;=P;00:11:22:33:44:55",
+          "config": {
+            "index": 5
+          }
+        },
+        {
+          "identifier": {
+            "address": "66:77:88:99:aa:bb",
+            "protocol": "lovense"
+          },
+          "config": {
+            "index": 6
+          }
+        }
+      ]
+    }
+  }
+  "#;
+  let user_configs = load_user_configs(user_config_json);
+  let devices = user_configs
+    .user_device_configs()
+    .as_ref()
+    .expect("Test, assuming infallible");
+  assert_eq!(
+    *devices[0].identifier(),
+    UserConfigDeviceIdentifier {
+      address: "00:11:22:33:44:55".to_owned(),
+      protocol: "lovense".to_owned(),
+      identifier: Some("P".to_owned()),
+    }
+  );
+  assert_eq!(devices[1].identifier().address(), "66:77:88:99:aa:bb");
+  assert!(devices[1].identifier().identifier().is_none());
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn test_command_timeouts() {
+  use buttplug::{
+    server::device::{configuration::ProtocolAttributesType, ServerDeviceIdentifier},
+    util::device_configuration::load_protocol_configs,
+  };
+  use std::time::Duration;
+  let user_config_json = r#"
+  {
+    "version": {
+      "major": 2,
+      "minor": 999
+    },
+    "user-configs": {
+      "specifiers": {
+        "kizuna": {
+          "command-timeout": 10000
+        }
+      },
+      "devices": [
+        {
+          "identifier": {
+            "address": "SlowMachine",
+            "protocol": "tcode-v03"
+          },
+          "config": {
+            "command-timeout": 20000
+          }
+        }
+      ]
+    }
+  }
+  "#;
+  let dcm = load_protocol_configs(None, Some(user_config_json.to_owned()), false)
+    .expect("Test, assuming infallible")
+    .finish()
+    .expect("Test, assuming infallible");
+  let identifier = |address: &str, protocol: &str| {
+    ServerDeviceIdentifier::new(address, protocol, &ProtocolAttributesType::Default)
+  };
+  // Protocol default from the base config
+  assert_eq!(
+    dcm.command_timeout(&identifier("OtherMachine", "tcode-v03")),
+    Some(Duration::from_millis(5000))
+  );
+  // Device override from the user config
+  assert_eq!(
+    dcm.command_timeout(&identifier("SlowMachine", "tcode-v03")),
+    Some(Duration::from_millis(20000))
+  );
+  // Protocol override from the user config
+  assert_eq!(
+    dcm.command_timeout(&identifier("Kizuna", "kizuna")),
+    Some(Duration::from_millis(10000))
+  );
+  // Protocols without a timeout never time out
+  assert_eq!(dcm.command_timeout(&identifier("Lovense", "lovense")), None);
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn test_max_update_rates() {
+  use buttplug::{
+    server::device::{configuration::ProtocolAttributesType, ServerDeviceIdentifier},
+    util::device_configuration::load_protocol_configs,
+  };
+  let user_config_json = r#"
+  {
+    "version": {
+      "major": 2,
+      "minor": 999
+    },
+    "user-configs": {
+      "specifiers": {
+        "lovense": {
+          "max-update-rate": 20
+        }
+      },
+      "devices": [
+        {
+          "identifier": {
+            "address": "SlowLovense",
+            "protocol": "lovense"
+          },
+          "config": {
+            "max-update-rate": 5
+          }
+        }
+      ]
+    }
+  }
+  "#;
+  let dcm = load_protocol_configs(None, Some(user_config_json.to_owned()), false)
+    .expect("Test, assuming infallible")
+    .finish()
+    .expect("Test, assuming infallible");
+  let identifier = |address: &str, protocol: &str| {
+    ServerDeviceIdentifier::new(address, protocol, &ProtocolAttributesType::Default)
+  };
+  // Protocol rate from the user config
+  assert_eq!(
+    dcm.max_update_rate(&identifier("OtherLovense", "lovense")),
+    Some(20)
+  );
+  // Device override from the user config
+  assert_eq!(
+    dcm.max_update_rate(&identifier("SlowLovense", "lovense")),
+    Some(5)
+  );
+  // Protocols without a rate aren't limited
+  assert_eq!(dcm.max_update_rate(&identifier("Kizuna", "kizuna")), None);
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn test_btle_connection_parameters() {
+  use buttplug::{
+    server::device::{
+      configuration::ProtocolAttributesType,
+      hardware::BluetoothLEConnectionParameters,
+      ServerDeviceIdentifier,
+    },
+    util::device_configuration::load_protocol_configs,
+  };
+  use std::time::Duration;
+  let user_config_json = r#"
+  {
+    "version": {
+      "major": 2,
+      "minor": 999
+    },
+    "user-configs": {
+      "specifiers": {
+        "lovense": {
+          "btle-connection-parameters": {
+            "min-interval": 7.5,
+            "max-interval": 15
+          }
+        }
+      }
+    }
+  }
+  "#;
+  let dcm = load_protocol_configs(None, Some(user_config_json.to_owned()), false)
+    .expect("Test, assuming infallible")
+    .finish()
+    .expect("Test, assuming infallible");
+  let identifier = |address: &str, protocol: &str| {
+    ServerDeviceIdentifier::new(address, protocol, &ProtocolAttributesType::Default)
+  };
+  assert_eq!(
+    dcm.btle_connection_parameters(&identifier("Lovense", "lovense")),
+    Some(BluetoothLEConnectionParameters::new(
+      Duration::from_micros(7500),
+      Duration::from_millis(15),
+      0
+    ))
+  );
+  assert_eq!(
+    dcm.btle_connection_parameters(&identifier("Kizuna", "kizuna")),
+    None
+  );
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn test_btle_connection_parameters_rejects_bad_intervals() {
+  use buttplug::{
+    server::device::hardware::BluetoothLEConnectionParameters,
+    util::device_configuration::{
+      load_protocol_configs,
+      BluetoothLEConnectionParametersDefinition,
+    },
+  };
+  // The schema can't tell a reversed range apart from a valid one, so loading has to.
+  let user_config_json = r#"
+  {
+    "version": {
+      "major": 2,
+      "minor": 999
+    },
+    "user-configs": {
+      "specifiers": {
+        "lovense": {
+          "btle-connection-parameters": {
+            "min-interval": 15,
+            "max-interval": 7.5
+          }
+        }
+      }
+    }
+  }
+  "#;
+  assert!(load_protocol_configs(None, Some(user_config_json.to_owned()), false).is_err());
+
+  let mut def = BluetoothLEConnectionParametersDefinition::default();
+  assert!(def.set_intervals(-1.0, 15.0).is_err());
+  assert!(def.set_intervals(f64::NAN, 15.0).is_err());
+  assert!(def.set_intervals(7.5, f64::INFINITY).is_err());
+  assert!(def.set_intervals(15.0, 7.5).is_err());
+  // Failed sets leave the definition as it was.
+  assert_eq!(def, BluetoothLEConnectionParametersDefinition::default());
+  def
+    .set_intervals(7.5, 15.0)
+    .expect("Test, assuming infallible");
+  assert_eq!(def.min_interval(), 7.5);
+  assert_eq!(def.max_interval(), 15.0);
+  assert!(BluetoothLEConnectionParameters::try_from(def).is_ok());
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn test_btle_write_coalescing() {
+  use buttplug::{
+    core::message::Endpoint,
+    server::device::{
+      configuration::ProtocolAttributesType,
+      hardware::BluetoothLEWriteCoalescing,
+      ServerDeviceIdentifier,
+    },
+    util::device_configuration::load_protocol_configs,
+  };
+  use std::time::Duration;
+  let user_config_json = |window: u32| {
+    format!(
+      r#"
+      {{
+        "version": {{
+          "major": 2,
+          "minor": 999
+        }},
+        "user-configs": {{
+          "specifiers": {{
+            "magic-motion-1": {{
+              "btle-write-coalescing": {{
+                "window": {},
+                "endpoints": ["tx"]
+              }}
+            }}
+          }}
+        }}
+      }}
+      "#,
+      window
+    )
+  };
+  let dcm = load_protocol_configs(None, Some(user_config_json(10)), false)
+    .expect("Test, assuming infallible")
+    .finish()
+    .expect("Test, assuming infallible");
+  let identifier = |address: &str, protocol: &str| {
+    ServerDeviceIdentifier::new(address, protocol, &ProtocolAttributesType::Default)
+  };
+  assert_eq!(
+    dcm.btle_write_coalescing(&identifier("Flamingo", "magic-motion-1")),
+    Some(BluetoothLEWriteCoalescing::new(
+      Duration::from_millis(10),
+      [Endpoint::Tx].into_iter().collect()
+    ))
+  );
+  assert_eq!(
+    dcm.btle_write_coalescing(&identifier("Lovense", "lovense")),
+    None
+  );
+  // Windows are checked against the schema.
+  assert!(load_protocol_configs(None, Some(user_config_json(0)), false).is_err());
+}
+
+#[test]
+fn test_user_config_invert_rotation() {
+  use buttplug::{
+    server::device::{configuration::ProtocolAttributesType, ServerDeviceIdentifier},
+    util::device_configuration::load_protocol_configs,
+  };
+  let user_config_json = r#"
+  {
+    "version": {
+      "major": 2,
+      "minor": 999
+    },
+    "user-configs": {
+      "devices": [
+        {
+          "identifier": {
+            "address": "BackwardsCyclone",
+            "protocol": "vorze-sa",
+            "identifier": "CycSA"
+          },
+          "config": {
+            "invert-rotation": true
+          }
+        }
+      ]
+    }
+  }
+  "#;
+  let dcm = load_protocol_configs(None, Some(user_config_json.to_owned()), false)
+    .expect("Test, assuming infallible")
+    .finish()
+    .expect("Test, assuming infallible");
+  let identifier = |address: &str| {
+    ServerDeviceIdentifier::new(
+      address,
+      "vorze-sa",
+      &ProtocolAttributesType::Identifier("CycSA".to_owned()),
+    )
+  };
+  assert!(dcm
+    .protocol_device_attributes(&identifier("BackwardsCyclone"), &[])
+    .expect("Test, assuming infallible")
+    .rotation_inverted());
+  assert!(!dcm
+    .protocol_device_attributes(&identifier("OtherCyclone"), &[])
+    .expect("Test, assuming infallible")
+    .rotation_inverted());
+}
+
+#[test]
+fn test_rotate_position_only_valid_for_linear_cmd() {
+  use buttplug::util::device_configuration::load_protocol_configs;
+  let user_config_json = r#"
+  {
+    "version": {
+      "major": 2,
+      "minor": 999
+    },
+    "user-configs": {
+      "devices": [
+        {
+          "identifier": {
+            "address": "UserConfigTest",
+            "protocol": "lovense",
+            "identifier": "F"
+          },
+          "config": {
+            "messages": {
+              "ScalarCmd": [
+                {
+                  "StepRange": [0, 10],
+                  "ActuatorType": "RotatePosition"
+                }
+              ]
+            }
+          }
+        }
+      ]
+    }
+  }
+  "#;
+  assert!(
+    load_protocol_configs(None, Some(user_config_json.to_owned()), false)
+      .expect("Test, assuming infallible")
+      .finish()
+      .is_err()
+  );
+}
+
+#[test]
+fn test_user_config_minimum_effective_value() {
+  use buttplug::{
+    server::device::{configuration::ProtocolAttributesType, ServerDeviceIdentifier},
+    util::device_configuration::load_protocol_configs,
+  };
+  let user_config_json = r#"
+  {
+    "version": {
+      "major": 2,
+      "minor": 999
+    },
+    "user-configs": {
+      "devices": [
+        {
+          "identifier": {
+            "address": "UserConfigTest",
+            "protocol": "lovense",
+            "identifier": "F"
+          },
+          "config": {
+            "messages": {
+              "ScalarCmd": [
+                {
+                  "StepRange": [0, 20],
+                  "ActuatorType": "Vibrate",
+                  "MinimumEffectiveValue": MINIMUM
+                }
+              ]
+            }
+          }
+        }
+      ]
+    }
+  }
+  "#;
+  let load = |minimum: &str| {
+    load_protocol_configs(
+      None,
+      Some(user_config_json.replace("MINIMUM", minimum)),
+      false,
+    )
+    .and_then(|mut builder| builder.finish())
+  };
+  let dcm = load("0.2").expect("Test, assuming infallible");
+  let attrs = dcm
+    .protocol_device_attributes(
+      &ServerDeviceIdentifier::new(
+        "UserConfigTest",
+        "lovense",
+        &ProtocolAttributesType::Identifier("F".to_owned()),
+      ),
+      &[],
+    )
+    .expect("Test, assuming infallible");
+  assert_eq!(
+    *attrs
+      .message_attributes()
+      .scalar_cmd()
+      .as_ref()
+      .expect("Test, assuming infallible")[0]
+      .minimum_effective_value(),
+    Some(0.2)
+  );
+  // A minimum of full output would leave nothing to remap onto.
+  assert!(load("1.0").is_err());
+}
+
+#[test]
+fn test_user_config_sensor_calibration() {
+  use buttplug::{
+    core::message::SensorType,
+    server::device::{
+      configuration::ProtocolAttributesType,
+      SensorCalibration,
+      ServerDeviceIdentifier,
+    },
+    util::device_configuration::load_protocol_configs,
+  };
+  let user_config_json = r#"
+  {
+    "version": {
+      "major": 2,
+      "minor": 999
+    },
+    "user-configs": {
+      "devices": [
+        {
+          "identifier": {
+            "address": "CalibratedFlamingo",
+            "protocol": "magic-motion-1",
+            "identifier": "Flamingo"
+          },
+          "config": {
+            "sensor-calibration": [
+              {
+                "sensor-index": 0,
+                "sensor-type": "Battery",
+                "offset": [10],
+                "scale": 2.0
+              }
+            ]
+          }
+        }
+      ]
+    }
+  }
+  "#;
+  let dcm = load_protocol_configs(None, Some(user_config_json.to_owned()), false)
+    .expect("Test, assuming infallible")
+    .finish()
+    .expect("Test, assuming infallible");
+  let identifier = |address: &str| {
+    ServerDeviceIdentifier::new(
+      address,
+      "magic-motion-1",
+      &ProtocolAttributesType::Identifier("Flamingo".to_owned()),
+    )
+  };
+  assert_eq!(
+    dcm.sensor_calibrations(&identifier("CalibratedFlamingo")),
+    vec![(
+      0,
+      SensorType::Battery,
+      SensorCalibration::new(vec![10], 2.0)
+    )]
+  );
+  assert!(dcm
+    .sensor_calibrations(&identifier("OtherFlamingo"))
+    .is_empty());
+}
+
+#[test]
+fn test_device_config_localizations() {
+  use buttplug::{
+    server::device::{
+      configuration::{DeviceLocalization, ProtocolAttributesType},
+      ServerDeviceIdentifier,
+    },
+    util::device_configuration::load_protocol_configs,
+  };
+  let main_config_json = r#"
+  {
+    "version": {
+      "major": 2,
+      "minor": 999
+    },
+    "protocols": {
+      "kiiroo-v21": {
+        "btle": {
+          "names": [
+            "OhMiBod LUMEN"
+          ],
+          "services": {
+            "a0d70001-4c16-4ba7-977a-d394920e13a3": {
+              "tx": "a0d70002-4c16-4ba7-977a-d394920e13a3"
+            }
+          }
+        },
+        "defaults": {
+          "name": "Kiiroo V2.1 Device",
+          "messages": {},
+          "localizations": {
+            "de": {
+              "display-name": "Kiiroo V2.1 Gerät",
+              "description": "Vibrator"
+            }
+          }
+        },
+        "configurations": [
+          {
+            "identifier": [
+              "OhMiBod LUMEN"
+            ],
+            "name": "OhMiBod Lumen",
+            "localizations": {
+              "de-AT": {
+                "display-name": "OhMiBod Lumen (AT)"
+              },
+              "de": {
+                "display-name": "OhMiBod Lumen"
+              }
+            }
+          }
+        ]
+      }
+    }
+  }
+  "#;
+  let dcm = load_protocol_configs(Some(main_config_json.to_owned()), None, true)
+    .expect("Test, assuming infallible")
+    .finish()
+    .expect("Test, assuming infallible");
+  let attributes = dcm
+    .protocol_device_attributes(
+      &ServerDeviceIdentifier::new(
+        "Lumen",
+        "kiiroo-v21",
+        &ProtocolAttributesType::Identifier("OhMiBod LUMEN".to_owned()),
+      ),
+      &[],
+    )
+    .expect("Test, assuming infallible");
+  // Missing fields come from less specific tags, then from the protocol defaults.
+  assert_eq!(
+    attributes.localization("de_at"),
+    DeviceLocalization::new(
+      Some("OhMiBod Lumen (AT)".to_owned()),
+      Some("Vibrator".to_owned())
+    )
+  );
+  assert_eq!(
+    attributes.localization("de-CH"),
+    DeviceLocalization::new(
+      Some("OhMiBod Lumen".to_owned()),
+      Some("Vibrator".to_owned())
+    )
+  );
+  assert_eq!(attributes.localization("fr"), DeviceLocalization::default());
+}
+
+#[test]
+fn test_user_config_migrated_from_v1() {
+  use buttplug::{
+    server::device::{configuration::ProtocolAttributesType, ServerDeviceIdentifier},
+    util::device_configuration::load_protocol_configs,
+  };
+  let user_config_json = r#"
+  {
+    "version": {
+      "major": 1,
+      "minor": 0
+    },
+    "user-configs": {
+      "devices": [
+        {
+          "identifier": {
+            "address": "UserConfigTest",
+            "protocol": "lovense",
+            "identifier": "F"
+          },
+          "config": {
+            "messages": {
+              "VibrateCmd": {
+                "FeatureCount": 1,
+                "StepCount": [10]
+              }
+            }
+          }
+        }
+      ]
+    }
+  }
+  "#;
+  let dcm = load_protocol_configs(None, Some(user_config_json.to_owned()), false)
+    .and_then(|mut builder| builder.finish())
+    .expect("Test, assuming infallible");
+  let attrs = dcm
+    .protocol_device_attributes(
+      &ServerDeviceIdentifier::new(
+        "UserConfigTest",
+        "lovense",
+        &ProtocolAttributesType::Identifier("F".to_owned()),
+      ),
+      &[],
+    )
+    .expect("Test, assuming infallible");
+  assert_eq!(
+    attrs
+      .message_attributes()
+      .scalar_cmd()
+      .as_ref()
+      .expect("Test, assuming infallible")[0]
+      .step_count(),
+    10
+  );
+}
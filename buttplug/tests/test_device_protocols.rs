@@ -38,6 +38,9 @@ async fn load_test_case(test_file: &str) -> DeviceTestCase {
 #[test_case("test_lovense_flexer_fw3.yaml" ; "Lovense Protocol - Flexer FW3")]
 #[test_case("test_lovense_edge.yaml" ; "Lovense Protocol - Edge")]
 #[test_case("test_user_config_display_name.yaml" ; "User Config Display Name")]
+#[test_case("test_tcode_osr2_user_config.yaml" ; "TCode Protocol - OSR-2 Twist Axis (User Config)")]
+#[test_case("test_tcode_sr6_axis_mapping.yaml" ; "TCode Protocol - SR-6 Axis Mapping (User Config)")]
+#[test_case("test_kiiroo_v1_onyx.yaml" ; "Kiiroo v1 Protocol - Onyx")]
 #[test_case("test_satisfyer_single_vibrator.yaml" ; "Satisfyer Protocol - Single Vibrator")]
 #[test_case("test_satisfyer_dual_vibrator.yaml" ; "Satisfyer Protocol - Dual Vibrator")]
 #[test_case("test_mysteryvibe.yaml" ; "Mysteryvibe Protocol")]
@@ -90,6 +93,8 @@ async fn load_test_case(test_file: &str) -> DeviceTestCase {
 #[test_case("test_longlosttouch_protocol.yaml" ; "LongLostTouch Protocol")]
 #[test_case("test_adrienlastic_protocol.yaml" ; "Adrien Lastic Protocol")]
 #[test_case("test_foreo_protocol.yaml" ; "Foreo Protocol")]
+#[test_case("test_gvibe_duo.yaml" ; "GVibe Protocol - Duo")]
+#[test_case("test_gvibe_default.yaml" ; "GVibe Protocol - No Model Number")]
 #[tokio::test]
 async fn test_device_protocols_embedded_v3(test_file: &str) {
   //tracing_subscriber::fmt::init();
@@ -117,6 +122,9 @@ async fn test_device_protocols_embedded_v3(test_file: &str) {
 #[test_case("test_lovense_flexer_fw3.yaml" ; "Lovense Protocol - Flexer FW3")]
 #[test_case("test_lovense_edge.yaml" ; "Lovense Protocol - Edge")]
 #[test_case("test_user_config_display_name.yaml" ; "User Config Display Name")]
+#[test_case("test_tcode_osr2_user_config.yaml" ; "TCode Protocol - OSR-2 Twist Axis (User Config)")]
+#[test_case("test_tcode_sr6_axis_mapping.yaml" ; "TCode Protocol - SR-6 Axis Mapping (User Config)")]
+#[test_case("test_kiiroo_v1_onyx.yaml" ; "Kiiroo v1 Protocol - Onyx")]
 #[test_case("test_satisfyer_single_vibrator.yaml" ; "Satisfyer Protocol - Single Vibrator")]
 #[test_case("test_satisfyer_dual_vibrator.yaml" ; "Satisfyer Protocol - Dual Vibrator")]
 #[test_case("test_satisfyer_triple_vibrator.yaml" ; "Satisfyer Protocol - Triple Vibrator")]
@@ -169,6 +177,8 @@ async fn test_device_protocols_embedded_v3(test_file: &str) {
 #[test_case("test_xiuxiuda_protocol.yaml" ; "Xiuxiuda Protocol")]
 #[test_case("test_adrienlastic_protocol.yaml" ; "Adrien Lastic Protocol")]
 #[test_case("test_foreo_protocol.yaml" ; "Foreo Protocol")]
+#[test_case("test_gvibe_duo.yaml" ; "GVibe Protocol - Duo")]
+#[test_case("test_gvibe_default.yaml" ; "GVibe Protocol - No Model Number")]
 #[tokio::test]
 async fn test_device_protocols_json_v3(test_file: &str) {
   //tracing_subscriber::fmt::init();
@@ -231,6 +241,8 @@ async fn test_device_protocols_json_v3(test_file: &str) {
 #[test_case("test_xiuxiuda_protocol.yaml" ; "Xiuxiuda Protocol")]
 #[test_case("test_adrienlastic_protocol.yaml" ; "Adrien Lastic Protocol")]
 #[test_case("test_foreo_protocol.yaml" ; "Foreo Protocol")]
+#[test_case("test_gvibe_duo.yaml" ; "GVibe Protocol - Duo")]
+#[test_case("test_gvibe_default.yaml" ; "GVibe Protocol - No Model Number")]
 #[tokio::test]
 async fn test_device_protocols_embedded_v2(test_file: &str) {
   util::device_test::client::client_v2::run_embedded_test_case(&load_test_case(test_file).await)
@@ -292,6 +304,8 @@ async fn test_device_protocols_embedded_v2(test_file: &str) {
 #[test_case("test_xiuxiuda_protocol.yaml" ; "Xiuxiuda Protocol")]
 #[test_case("test_adrienlastic_protocol.yaml" ; "Adrien Lastic Protocol")]
 #[test_case("test_foreo_protocol.yaml" ; "Foreo Protocol")]
+#[test_case("test_gvibe_duo.yaml" ; "GVibe Protocol - Duo")]
+#[test_case("test_gvibe_default.yaml" ; "GVibe Protocol - No Model Number")]
 #[tokio::test]
 async fn test_device_protocols_json_v2(test_file: &str) {
   util::device_test::client::client_v2::run_json_test_case(&load_test_case(test_file).await).await;
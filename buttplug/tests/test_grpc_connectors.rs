@@ -0,0 +1,84 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+mod util;
+
+#[cfg(all(feature = "grpc", feature = "client"))]
+mod grpc_connector_tests {
+  use crate::util::ButtplugTestServer;
+  use buttplug::{
+    client::{ButtplugClient, ButtplugClientEvent},
+    core::{
+      connector::{
+        ButtplugGrpcClientTransport,
+        ButtplugGrpcServerTransport,
+        ButtplugGrpcServerTransportBuilder,
+        ButtplugRemoteClientConnector,
+        ButtplugRemoteServerConnector,
+      },
+      message::serializer::{ButtplugClientJSONSerializer, ButtplugServerJSONSerializer},
+    },
+    util::async_manager,
+  };
+  use futures::StreamExt;
+  use std::{sync::Arc, time::Duration};
+  use tokio::time::sleep;
+
+  async fn connect_client(name: &str, address: &str) -> ButtplugClient {
+    for _ in 0..10u8 {
+      let connector = ButtplugRemoteClientConnector::<
+        ButtplugGrpcClientTransport,
+        ButtplugClientJSONSerializer,
+      >::new(ButtplugGrpcClientTransport::new(address));
+      let client = ButtplugClient::new(name);
+      if client.connect(connector).await.is_ok() {
+        return client;
+      }
+      sleep(Duration::from_millis(100)).await;
+    }
+    panic!("Could not connect to server");
+  }
+
+  #[tokio::test]
+  async fn test_client_grpc_client_server_grpc_server() {
+    let test_server = ButtplugTestServer::default();
+    let server = Arc::new(test_server);
+    let server_clone = server.clone();
+    async_manager::spawn(async move {
+      let connector = ButtplugRemoteServerConnector::<
+        ButtplugGrpcServerTransport,
+        ButtplugServerJSONSerializer,
+      >::new(
+        ButtplugGrpcServerTransportBuilder::default()
+          .port(12361)
+          .finish(),
+      );
+      server_clone
+        .start(connector)
+        .await
+        .expect("Test, assuming infallible.");
+    });
+    let client = connect_client("Test Client", "127.0.0.1:12361").await;
+    assert!(client.connected());
+    // Round trip a message after the handshake.
+    client
+      .stop_all_devices()
+      .await
+      .expect("Test, assuming infallible.");
+    let mut events = client.event_stream();
+    server
+      .disconnect()
+      .await
+      .expect("Test, assuming infallible.");
+    assert!(matches!(
+      tokio::time::timeout(Duration::from_secs(5), events.next())
+        .await
+        .expect("Test, assuming infallible."),
+      Some(ButtplugClientEvent::ServerDisconnect)
+    ));
+  }
+}
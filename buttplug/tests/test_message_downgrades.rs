@@ -67,6 +67,94 @@ async fn test_version2_connection() {
       );
 }
 
+async fn check_test_message_echo(rsi: &str) {
+  let server = ButtplugServer::default();
+  let serializer = ButtplugServerJSONSerializer::default();
+  let output = serializer
+    .deserialize(&rsi.to_owned().into())
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(output[0].clone())
+    .await
+    .expect("Test, assuming infallible.");
+  let test_msg = serializer
+    .deserialize(&ButtplugSerializedMessage::Text(
+      r#"[{"Test":{"Id":2,"TestString":"Echo Me"}}]"#.to_owned(),
+    ))
+    .expect("Test, assuming infallible.");
+  let reply = server
+    .parse_message(test_msg[0].clone())
+    .await
+    .expect("Test, assuming infallible.");
+  assert_eq!(
+    serializer.serialize(&[reply]),
+    r#"[{"Test":{"Id":2,"TestString":"Echo Me"}}]"#.to_owned().into()
+  );
+}
+
+#[tokio::test]
+async fn test_version0_test_message_echo() {
+  check_test_message_echo(r#"[{"RequestServerInfo":{"Id": 1, "ClientName": "Test Client"}}]"#)
+    .await;
+}
+
+#[tokio::test]
+async fn test_version1_test_message_echo() {
+  check_test_message_echo(
+    r#"[{"RequestServerInfo":{"Id": 1, "ClientName": "Test Client", "MessageVersion": 1}}]"#,
+  )
+  .await;
+}
+
+#[tokio::test]
+async fn test_version2_test_message_rejected() {
+  let server = ButtplugServer::default();
+  let serializer = ButtplugServerJSONSerializer::default();
+  let rsi =
+    r#"[{"RequestServerInfo":{"Id": 1, "ClientName": "Test Client", "MessageVersion": 2}}]"#;
+  let output = serializer
+    .deserialize(&rsi.to_owned().into())
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(output[0].clone())
+    .await
+    .expect("Test, assuming infallible.");
+  assert!(serializer
+    .deserialize(&ButtplugSerializedMessage::Text(
+      r#"[{"Test":{"Id":2,"TestString":"Echo Me"}}]"#.to_owned(),
+    ))
+    .is_err());
+}
+
+#[tokio::test]
+async fn test_version0_error_downgrade() {
+  let server = ButtplugServer::default();
+  let serializer = ButtplugServerJSONSerializer::default();
+  let rsi = r#"[{"RequestServerInfo":{"Id": 1, "ClientName": "Test Client"}}]"#;
+  let output = serializer
+    .deserialize(&rsi.to_owned().into())
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(output[0].clone())
+    .await
+    .expect("Test, assuming infallible.");
+  let stop_cmd = serializer
+    .deserialize(&ButtplugSerializedMessage::Text(
+      r#"[{"StopDeviceCmd":{"Id":2,"DeviceIndex":5}}]"#.to_owned(),
+    ))
+    .expect("Test, assuming infallible.");
+  let error = server
+    .parse_message(stop_cmd[0].clone())
+    .await
+    .expect_err("Device index should not exist.");
+  assert_eq!(
+    serializer.serialize(&[message::ButtplugServerMessage::from(error)]),
+    r#"[{"Error":{"Id":2,"ErrorCode":4,"ErrorMessage":"No device available at index 5"}}]"#
+      .to_owned()
+      .into()
+  );
+}
+
 #[tokio::test]
 async fn test_version0_device_added_device_list() {
   let (server, _) = test_server_with_device("Massage Demo", false).await;
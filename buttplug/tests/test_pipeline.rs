@@ -0,0 +1,117 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+// Scripted runs through the whole scan, identify, connect and command pipeline, checked against the
+// events the client sees.
+
+mod util;
+use buttplug::{
+  client::{ButtplugClientError, ScalarValueCommand},
+  core::{errors::ButtplugDeviceError, message::Endpoint},
+  server::device::hardware::{HardwareCommand, HardwareWriteCmd},
+};
+use util::{
+  pipeline_harness::{PipelineEvent, PipelineHarness},
+  test_device_manager::TestDeviceIdentifier,
+};
+
+// "Massage Demo" is identified as an Aneros Vivi, "Flamingo" as a MagicMotion Flamingo.
+const VIVI: &str = "Aneros Vivi";
+const FLAMINGO: &str = "MagicMotion Flamingo";
+
+fn added(name: &str) -> PipelineEvent {
+  PipelineEvent::DeviceAdded(name.to_owned())
+}
+
+fn removed(name: &str) -> PipelineEvent {
+  PipelineEvent::DeviceRemoved(name.to_owned())
+}
+
+#[tokio::test]
+async fn test_pipeline_device_appears_mid_scan() {
+  let mut harness = PipelineHarness::new().await;
+  let _vivi = harness.add_device(&TestDeviceIdentifier::new("Massage Demo", None));
+  harness.start_scanning().await;
+  harness.expect_events(&[added(VIVI)]).await;
+
+  // The scan is still going, so a device turned on now is found without scanning again.
+  let _flamingo = harness.add_device(&TestDeviceIdentifier::new("Flamingo", None));
+  harness.expect_events(&[added(FLAMINGO)]).await;
+  assert_eq!(harness.client().devices().len(), 2);
+
+  harness.stop_scanning().await;
+  harness
+    .expect_events(&[PipelineEvent::ScanningFinished])
+    .await;
+  harness.finish().await;
+}
+
+#[tokio::test]
+async fn test_pipeline_device_disconnects_during_command() {
+  let mut harness = PipelineHarness::new().await;
+  let mut hardware = harness.add_device(&TestDeviceIdentifier::new("Massage Demo", None));
+  harness.start_scanning().await;
+  harness.expect_events(&[added(VIVI)]).await;
+  let device = harness.device(VIVI);
+
+  device
+    .vibrate(&ScalarValueCommand::ScalarValueVec(vec![0.5, 0.0]))
+    .await
+    .expect("Test, assuming infallible.");
+  // Drop the connection as soon as the command reaches the hardware.
+  assert_eq!(
+    hardware.next_command().await,
+    Some(HardwareCommand::Write(HardwareWriteCmd::new(
+      Endpoint::Tx,
+      vec![0xF1, 64],
+      false
+    )))
+  );
+  hardware
+    .disconnect()
+    .await
+    .expect("Test, assuming infallible.");
+  harness.expect_events(&[removed(VIVI)]).await;
+  assert!(!device.connected());
+  assert!(harness.client().devices().is_empty());
+  assert!(matches!(
+    device.vibrate(&ScalarValueCommand::ScalarValue(1.0)).await,
+    Err(ButtplugClientError::ButtplugDeviceError(
+      ButtplugDeviceError::DeviceDisconnected(..)
+    ))
+  ));
+
+  // Coming back in range while still scanning connects it again.
+  let _hardware = harness.add_device(&TestDeviceIdentifier::new("Massage Demo", None));
+  harness.expect_events(&[added(VIVI)]).await;
+
+  harness.stop_scanning().await;
+  harness
+    .expect_events(&[PipelineEvent::ScanningFinished])
+    .await;
+  harness.finish().await;
+}
+
+#[tokio::test]
+async fn test_pipeline_duplicate_advertisements() {
+  let mut harness = PipelineHarness::new().await;
+  let identifier = TestDeviceIdentifier::new("Massage Demo", Some("duplicate".to_owned()));
+  // Bluetooth devices advertise over and over, so the same device can be found several times in
+  // one scan, both while it's connecting and after.
+  let _first = harness.add_device(&identifier);
+  let _second = harness.add_device(&identifier);
+  harness.start_scanning().await;
+  harness.expect_events(&[added(VIVI)]).await;
+  let _third = harness.add_device(&identifier);
+
+  harness.stop_scanning().await;
+  harness
+    .expect_events(&[PipelineEvent::ScanningFinished])
+    .await;
+  assert_eq!(harness.client().devices().len(), 1);
+  harness.finish().await;
+}
@@ -0,0 +1,18 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+mod util;
+
+#[tokio::test]
+async fn test_protocol_golden_files() {
+  let failures = util::device_test::golden::run_protocol_golden_tests().await;
+  assert!(
+    failures.is_empty(),
+    "Protocol output does not match golden files:\n{}",
+    failures.join("\n")
+  );
+}
@@ -8,23 +8,30 @@
 mod util;
 
 use buttplug::{
-  client::ButtplugClientError,
+  client::{ButtplugClientError, ButtplugClientEvent, DeviceCommand},
   core::{
-    connector::transport::ButtplugTransportIncomingMessage,
-    errors::{ButtplugError, ButtplugUnknownError},
+    connector::{transport::ButtplugTransportIncomingMessage, ButtplugConnectionQuota},
+    errors::{ButtplugError, ButtplugHandshakeError, ButtplugMessageError, ButtplugUnknownError},
     message::{
       self,
       serializer::ButtplugSerializedMessage,
       ButtplugClientMessage,
+      ButtplugCurrentSpecServerMessage,
       ButtplugMessage,
       ButtplugServerMessage,
+      ClientDeviceMessageAttributes,
     },
   },
   util::async_manager,
 };
+use futures::StreamExt;
 use std::sync::Arc;
 use tokio::sync::Notify;
-use util::ChannelClientTestHelper;
+use util::{
+  test_server::ButtplugServerConnectorError,
+  ChannelClientTestHelper,
+  ChannelServerTestHelper,
+};
 
 #[tokio::test]
 async fn test_garbled_client_rsi_response() {
@@ -82,13 +89,286 @@ async fn test_serialized_error_relay() {
   });
   assert!(matches!(
     helper.client().start_scanning().await.unwrap_err(),
-    ButtplugClientError::ButtplugError(ButtplugError::ButtplugUnknownError(
+    ButtplugClientError::ButtplugUnknownError(
       buttplug::core::errors::ButtplugUnknownError::NoDeviceCommManagers
+    )
+  ));
+}
+
+#[tokio::test]
+async fn test_client_scene_serialized_as_single_message() {
+  let helper = Arc::new(ChannelClientTestHelper::new());
+  helper.simulate_successful_connect().await;
+  let mut event_stream = helper.client().event_stream();
+  for device_index in 0..2 {
+    helper
+      .send_client_incoming(
+        message::DeviceAdded::new(
+          device_index,
+          "Test Device",
+          &None,
+          &None,
+          &ClientDeviceMessageAttributes::default(),
+        )
+        .into(),
+      )
+      .await;
+    assert!(matches!(
+      event_stream.next().await,
+      Some(ButtplugClientEvent::DeviceAdded(..))
+    ));
+  }
+  let helper_clone = helper.clone();
+  async_manager::spawn(async move {
+    // Both commands should arrive in the same frame.
+    let msgs = helper_clone.next_client_messages().await;
+    assert_eq!(msgs.len(), 2);
+    for msg in msgs {
+      assert!(matches!(msg, ButtplugClientMessage::StopDeviceCmd(..)));
+      helper_clone
+        .send_client_incoming(message::Ok::new(msg.id()).into())
+        .await;
+    }
+  });
+  helper
+    .client()
+    .send_scene(vec![
+      DeviceCommand::Stop(message::StopDeviceCmd::new(0)),
+      DeviceCommand::Stop(message::StopDeviceCmd::new(1)),
+    ])
+    .await
+    .expect("Test, assuming infallible.");
+}
+
+#[tokio::test]
+async fn test_client_connection_lost_event() {
+  let helper = Arc::new(ChannelClientTestHelper::new());
+  helper.simulate_successful_connect().await;
+  let mut event_stream = helper.client().event_stream();
+  helper
+    .send_incoming(ButtplugTransportIncomingMessage::ConnectionLost(
+      "Heartbeat timed out".to_owned(),
+    ))
+    .await;
+  // Connection loss is reported before the regular disconnect, so applications can tell a dead
+  // link from a clean shutdown.
+  assert!(matches!(
+    event_stream.next().await,
+    Some(ButtplugClientEvent::ConnectionLost)
+  ));
+  assert!(matches!(
+    event_stream.next().await,
+    Some(ButtplugClientEvent::ServerDisconnect)
+  ));
+  assert!(!helper.client().connected());
+}
+
+#[tokio::test]
+async fn test_server_connection_lost_error() {
+  let helper = ChannelServerTestHelper::new();
+  let server_task = helper.start().await;
+  helper
+    .send_incoming(ButtplugTransportIncomingMessage::ConnectionLost(
+      "Heartbeat timed out".to_owned(),
     ))
+    .await;
+  assert!(matches!(
+    server_task.await,
+    Err(ButtplugServerConnectorError::ConnectionLost)
+  ));
+}
+
+#[tokio::test]
+async fn test_server_client_takeover() {
+  let helper = Arc::new(ChannelServerTestHelper::new());
+  let server_task = helper.start().await;
+  async_manager::spawn(async move {
+    let _ = server_task.await;
+  });
+  helper
+    .send_server_incoming(
+      message::RequestServerInfo::new("Old Client", message::BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION)
+        .into(),
+    )
+    .await;
+  assert!(matches!(
+    helper.next_server_message().await,
+    ButtplugCurrentSpecServerMessage::ServerInfo(..)
+  ));
+  helper
+    .send_incoming(ButtplugTransportIncomingMessage::TakeoverRequested)
+    .await;
+  // The old client is told why it's being dropped...
+  if let ButtplugCurrentSpecServerMessage::Error(err) = helper.next_server_message().await {
+    assert_eq!(err.id(), 0);
+    assert_eq!(
+      err.original_error(),
+      ButtplugError::from(ButtplugHandshakeError::ConnectionTakenOver)
+    );
+  } else {
+    panic!("Expected takeover error");
+  }
+  assert!(!helper.server().server().connected());
+  // ...and the new client can run the handshake again.
+  helper
+    .send_server_incoming(
+      message::RequestServerInfo::new("New Client", message::BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION)
+        .into(),
+    )
+    .await;
+  assert!(matches!(
+    helper.next_server_message().await,
+    ButtplugCurrentSpecServerMessage::ServerInfo(..)
+  ));
+  assert!(helper.server().server().connected());
+}
+
+#[tokio::test]
+async fn test_server_connection_quota() {
+  let helper = ChannelServerTestHelper::new_with_quota(Some(ButtplugConnectionQuota {
+    max_payload_size: Some(256),
+    max_violations: 1,
+    ..Default::default()
+  }));
+  let server_task = tokio::spawn(helper.start().await);
+  helper
+    .send_server_incoming(
+      message::RequestServerInfo::new("Test Client", message::BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION)
+        .into(),
+    )
+    .await;
+  assert!(matches!(
+    helper.next_server_message().await,
+    ButtplugCurrentSpecServerMessage::ServerInfo(..)
+  ));
+  let oversized_msg = ButtplugSerializedMessage::Text(format!(
+    "[{{\"Ping\":{{\"Id\":2,\"Padding\":\"{}\"}}}}]",
+    "x".repeat(256)
+  ));
+  // First violation gets an error back...
+  helper
+    .send_incoming(ButtplugTransportIncomingMessage::Message(oversized_msg.clone()))
+    .await;
+  if let ButtplugCurrentSpecServerMessage::Error(err) = helper.next_server_message().await {
+    assert!(matches!(
+      err.original_error(),
+      ButtplugError::ButtplugMessageError(ButtplugMessageError::QuotaExceeded(..))
+    ));
+  } else {
+    panic!("Expected quota error");
+  }
+  // ...and the next one drops the connection.
+  helper
+    .send_incoming(ButtplugTransportIncomingMessage::Message(oversized_msg))
+    .await;
+  assert!(matches!(
+    helper.next_server_message().await,
+    ButtplugCurrentSpecServerMessage::Error(..)
+  ));
+  assert!(server_task
+    .await
+    .expect("Test, assuming infallible")
+    .is_ok());
+  assert!(!helper.server().server().connected());
+}
+
+#[tokio::test]
+async fn test_server_connector_rejects_invalid_messages() {
+  let helper = Arc::new(ChannelServerTestHelper::new());
+  let server_task = helper.start().await;
+  async_manager::spawn(async move {
+    let _ = server_task.await;
+  });
+  helper
+    .send_server_incoming(
+      message::RequestServerInfo::new("Test Client", message::BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION)
+        .into(),
+    )
+    .await;
+  assert!(matches!(
+    helper.next_server_message().await,
+    ButtplugCurrentSpecServerMessage::ServerInfo(..)
+  ));
+  for frame in ["Not the JSON we're expecting", "[]"] {
+    helper
+      .send_incoming(ButtplugTransportIncomingMessage::Message(
+        ButtplugSerializedMessage::Text(frame.to_owned()),
+      ))
+      .await;
+    if let ButtplugCurrentSpecServerMessage::Error(err) = helper.next_server_message().await {
+      // Nothing in these frames can be trusted, so errors go back as system messages.
+      assert_eq!(err.id(), 0);
+      assert!(matches!(
+        err.original_error(),
+        ButtplugError::ButtplugMessageError(..)
+      ));
+    } else {
+      panic!("Expected error for frame {}", frame);
+    }
+  }
+  // Messages that parse but fail validation are answered with their own id.
+  helper
+    .send_incoming(ButtplugTransportIncomingMessage::Message(
+      ButtplugSerializedMessage::Text(
+        r#"[{"ScalarCmd":{"Id":2,"DeviceIndex":0,"Scalars":[{"Index":0,"Scalar":2.0,"ActuatorType":"Vibrate"}]}}]"#
+          .to_owned(),
+      ),
+    ))
+    .await;
+  if let ButtplugCurrentSpecServerMessage::Error(err) = helper.next_server_message().await {
+    assert_eq!(err.id(), 2);
+    assert!(matches!(
+      err.original_error(),
+      ButtplugError::ButtplugMessageError(ButtplugMessageError::InvalidMessageContents(..))
+    ));
+  } else {
+    panic!("Expected error for out of range ScalarCmd");
+  }
+  // The connection is still usable afterwards.
+  let mut request = message::RequestDeviceList::default();
+  request.set_id(3);
+  helper.send_server_incoming(request.into()).await;
+  let reply = helper.next_server_message().await;
+  assert!(matches!(reply, ButtplugCurrentSpecServerMessage::DeviceList(..)));
+  assert_eq!(reply.id(), 3);
+}
+
+#[tokio::test]
+async fn test_server_connector_replies_to_batch_in_one_frame() {
+  let helper = Arc::new(ChannelServerTestHelper::new());
+  let server_task = helper.start().await;
+  async_manager::spawn(async move {
+    let _ = server_task.await;
+  });
+  helper
+    .send_server_incoming(
+      message::RequestServerInfo::new("Test Client", message::BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION)
+        .into(),
+    )
+    .await;
+  assert!(matches!(
+    helper.next_server_message().await,
+    ButtplugCurrentSpecServerMessage::ServerInfo(..)
+  ));
+  // A message array is handled as one batch, so the replies come back together and in order.
+  helper
+    .send_incoming(ButtplugTransportIncomingMessage::Message(
+      ButtplugSerializedMessage::Text(
+        r#"[{"RequestDeviceList":{"Id":2}},{"StopAllDevices":{"Id":3}}]"#.to_owned(),
+      ),
+    ))
+    .await;
+  let replies = helper.next_server_messages().await;
+  assert_eq!(replies.len(), 2);
+  assert!(matches!(
+    replies[0],
+    ButtplugCurrentSpecServerMessage::DeviceList(..)
   ));
+  assert_eq!(replies[0].id(), 2);
+  assert!(matches!(replies[1], ButtplugCurrentSpecServerMessage::Ok(..)));
+  assert_eq!(replies[1].id(), 3);
 }
 
-// TODO Test bad incoming JSON
 // TODO Test deserialization of concatenated messages
 // TODO Test message with negative message id
 // TODO Test device message with negative device id
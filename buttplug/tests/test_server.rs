@@ -11,35 +11,61 @@ pub use util::{
     check_test_recv_value,
     TestDeviceCommunicationManagerBuilder,
     TestDeviceIdentifier,
+    TestHardwareEvent,
+    TestHardwareNotification,
   },
   test_server_with_device,
 };
 
 use buttplug::{
   core::{
-    errors::{ButtplugDeviceError, ButtplugError, ButtplugHandshakeError},
+    errors::{ButtplugDeviceError, ButtplugError, ButtplugHandshakeError, ButtplugMessageError},
     message::{
       self,
+      ButtplugDeviceMessageType,
+      ButtplugMessage,
       ButtplugMessageSpecVersion,
       ButtplugServerMessage,
+      ClientCapability,
       Endpoint,
+      SensorType,
       BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
     },
+    ButtplugResultFuture,
   },
   server::{
-    device::hardware::{HardwareCommand, HardwareWriteCmd},
+    device::{
+      configuration::{ProtocolAttributesType, ProtocolCommunicationSpecifier},
+      hardware::{
+        api::{
+          HardwareCommunicationManager,
+          HardwareCommunicationManagerBuilder,
+          HardwareCommunicationManagerEvent,
+          HardwareCommunicationManagerStatus,
+          HardwarePermission,
+          HardwarePermissionError,
+        },
+        HardwareCommand,
+        HardwareWriteCmd,
+      },
+      ConnectingDevice,
+      DeviceConnectionDecision,
+      ServerDeviceIdentifier,
+    },
     ButtplugServer,
+    ButtplugServerAccess,
     ButtplugServerBuilder,
+    ButtplugServerError,
   },
   util::async_manager,
 };
-use futures::{pin_mut, Stream, StreamExt};
-use std::time::Duration;
-use tokio::time::sleep;
+use futures::{future, pin_mut, FutureExt, Stream, StreamExt};
+use std::{sync::Arc, time::Duration};
+use tokio::{sync::mpsc::Sender, time::sleep};
 
 async fn setup_test_server(
   msg_union: message::ButtplugClientMessage,
-) -> (ButtplugServer, impl Stream<Item = ButtplugServerMessage>) {
+) -> (ButtplugServer, impl Stream<Item = Arc<ButtplugServerMessage>>) {
   let server = ButtplugServer::default();
   let recv = server.event_stream();
   // assert_eq!(server.server_name, "Test Server");
@@ -48,10 +74,16 @@ async fn setup_test_server(
     .await
     .expect("Test, assuming infallible.")
   {
-    ButtplugServerMessage::ServerInfo(s) => assert_eq!(
-      s,
-      message::ServerInfo::new("Buttplug Server", ButtplugMessageSpecVersion::Version3, 0)
-    ),
+    ButtplugServerMessage::ServerInfo(s) => {
+      assert_eq!(s.server_name(), "Buttplug Server");
+      assert_eq!(s.message_version(), ButtplugMessageSpecVersion::Version3);
+      assert_eq!(s.max_ping_time(), 0);
+      assert!(s.device_config_version().is_some());
+      assert_eq!(
+        *s.library_version(),
+        Some(env!("CARGO_PKG_VERSION").to_owned())
+      );
+    }
     _ => panic!("Should've received ok"),
   }
   (server, recv)
@@ -98,6 +130,61 @@ async fn test_client_version_older_than_server() {
   }
 }
 
+#[tokio::test]
+async fn test_server_raw_capability_not_available() {
+  let mut msg = message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION);
+  msg.set_capabilities(Some(vec![ClientCapability::Raw]));
+  let server = ButtplugServer::default();
+  let result = server.parse_message(msg.into()).await;
+  assert!(matches!(
+    result.unwrap_err().original_error(),
+    ButtplugError::ButtplugHandshakeError(ButtplugHandshakeError::CapabilityNotAvailable(..))
+  ));
+  assert!(!server.connected());
+}
+
+#[tokio::test]
+async fn test_server_spectator_capability() {
+  let mut msg = message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION);
+  msg.set_capabilities(Some(vec![ClientCapability::Spectator]));
+  let server = ButtplugServer::default();
+  server
+    .parse_message(msg.into())
+    .await
+    .expect("Test, assuming infallible.");
+  let snapshot = server.state_snapshot();
+  assert_eq!(
+    *snapshot
+      .client()
+      .as_ref()
+      .expect("Test, assuming infallible.")
+      .capabilities(),
+    Some(vec![ClientCapability::Spectator])
+  );
+  // Spectators can look and stop, but not start anything or read sensors they didn't ask for.
+  assert!(server
+    .parse_message(message::RequestDeviceList::default().into())
+    .await
+    .is_ok());
+  assert!(server
+    .parse_message(message::StopAllDevices::default().into())
+    .await
+    .is_ok());
+  for msg in [
+    message::StartScanning::default().into(),
+    message::SensorReadCmd::new(0, 0, SensorType::Battery).into(),
+  ] {
+    assert!(matches!(
+      server
+        .parse_message(msg)
+        .await
+        .unwrap_err()
+        .original_error(),
+      ButtplugError::ButtplugMessageError(ButtplugMessageError::ClientCapabilityError(..))
+    ));
+  }
+}
+
 #[tokio::test]
 #[ignore = "Needs to be rewritten to send in via the JSON parser, otherwise we're type bound due to the enum and can't fail"]
 async fn test_server_version_older_than_client() {
@@ -135,7 +222,7 @@ async fn test_ping_timeout() {
   }
   // Check that we got an event back about the ping out.
   let msg = recv.next().await.expect("Test, assuming infallible.");
-  if let ButtplugServerMessage::Error(e) = msg {
+  if let ButtplugServerMessage::Error(e) = &*msg {
     if message::ErrorCode::ErrorPing != e.error_code() {
       panic!("Didn't get a ping error");
     }
@@ -167,9 +254,9 @@ async fn test_device_stop_on_ping_timeout() {
   // Check that we got an event back about a new device.
   let mut device_index = 100;
   while let Some(msg) = recv.next().await {
-    if let ButtplugServerMessage::ScanningFinished(_) = msg {
+    if let ButtplugServerMessage::ScanningFinished(_) = &*msg {
       continue;
-    } else if let ButtplugServerMessage::DeviceAdded(da) = msg {
+    } else if let ButtplugServerMessage::DeviceAdded(da) = &*msg {
       assert_eq!(da.device_name(), "Aneros Vivi");
       device_index = da.device_index();
       break;
@@ -220,6 +307,93 @@ async fn test_repeated_handshake() {
   ));
 }
 
+#[tokio::test]
+async fn test_server_sessions() {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let mut device = builder.add_test_device(&TestDeviceIdentifier::new("Massage Demo", None));
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder.comm_manager(builder);
+  let server = server_builder.finish().expect("Test, assuming infallible.");
+  let first = server.session();
+  let second = server.session();
+  let second_recv = second.event_stream();
+  pin_mut!(second_recv);
+  // Each session runs its own handshake.
+  for session in [&first, &second] {
+    session
+      .parse_message(
+        message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+      )
+      .await
+      .expect("Test, assuming infallible.");
+  }
+  assert!(first.connected());
+  assert!(second.connected());
+  assert!(!server.connected());
+  // Devices found through one session show up in every session.
+  first
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  let mut device_index = None;
+  while let Some(msg) = second_recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = &*msg {
+      device_index = Some(da.device_index());
+      break;
+    }
+  }
+  let device_index = device_index.expect("Test, assuming infallible.");
+  second
+    .parse_message(
+      message::VibrateCmd::new(device_index, vec![message::VibrateSubcommand::new(0, 0.5)]).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  check_test_recv_value(
+    &mut device,
+    HardwareCommand::Write(HardwareWriteCmd::new(Endpoint::Tx, vec![0xF1, 64], false)),
+  );
+  // One client leaving doesn't end the other's session, but does stop the devices.
+  first.disconnect().await.expect("Test, assuming infallible.");
+  assert!(!first.connected());
+  assert!(second.connected());
+  check_test_recv_value(
+    &mut device,
+    HardwareCommand::Write(HardwareWriteCmd::new(Endpoint::Tx, vec![0xF1, 0], false)),
+  );
+}
+
+#[tokio::test]
+async fn test_server_session_ping_timeout() {
+  let server = ButtplugServerBuilder::default()
+    .max_ping_time(100)
+    .finish()
+    .expect("Test, assuming infallible.");
+  let idle = server.session();
+  let pinging = server.session();
+  let pinging_recv = pinging.event_stream();
+  pin_mut!(pinging_recv);
+  for session in [&idle, &pinging] {
+    session
+      .parse_message(
+        message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+      )
+      .await
+      .expect("Test, assuming infallible.");
+  }
+  for _ in 0..6 {
+    sleep(Duration::from_millis(50)).await;
+    pinging
+      .parse_message(message::Ping::default().into())
+      .await
+      .expect("Test, assuming infallible.");
+  }
+  // Only the session that stopped pinging is dropped, and only its client hears about it.
+  assert!(!idle.connected());
+  assert!(pinging.connected());
+  assert!(pinging_recv.next().now_or_never().is_none());
+}
+
 #[tokio::test]
 async fn test_invalid_device_index() {
   let msg = message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION);
@@ -259,9 +433,9 @@ async fn test_device_index_generation() {
   // Check that we got an event back about a new device.
   let mut index = 0u32;
   while let Some(msg) = recv.next().await {
-    if let ButtplugServerMessage::ScanningFinished(_) = msg {
+    if let ButtplugServerMessage::ScanningFinished(_) = &*msg {
       continue;
-    } else if let ButtplugServerMessage::DeviceAdded(da) = msg {
+    } else if let ButtplugServerMessage::DeviceAdded(da) = &*msg {
       assert_eq!(da.device_name(), "Aneros Vivi");
       // Devices aren't guaranteed to be added in any specific order, the
       // scheduler will do whatever it wants. So check boundaries instead of
@@ -281,6 +455,80 @@ async fn test_device_index_generation() {
   }
 }
 
+#[tokio::test]
+async fn test_server_paged_device_list() {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let _devices: Vec<_> = (0..5)
+    .map(|_| builder.add_test_device(&TestDeviceIdentifier::new("Massage Demo", None)))
+    .collect();
+
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder.comm_manager(builder).device_list_page_size(2);
+  let server = server_builder.finish().unwrap();
+
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  match server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.")
+  {
+    ButtplugServerMessage::ServerInfo(s) => assert_eq!(s.device_list_page_size(), Some(2)),
+    msg => panic!("Should've received ServerInfo, got {:?}", msg),
+  }
+  assert!(server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .is_ok());
+  let mut added = 0;
+  while let Some(msg) = recv.next().await {
+    if matches!(*msg, ButtplugServerMessage::DeviceAdded(_)) {
+      added += 1;
+      if added == 5 {
+        break;
+      }
+    }
+  }
+
+  // Unpaged requests still get everything in one message.
+  match server
+    .parse_message(message::RequestDeviceList::default().into())
+    .await
+    .expect("Test, assuming infallible.")
+  {
+    ButtplugServerMessage::DeviceList(list) => {
+      assert_eq!(list.devices().len(), 5);
+      assert_eq!(list.next_device_index(), None);
+    }
+    msg => panic!("Should've received DeviceList, got {:?}", msg),
+  }
+
+  let mut pages = vec![];
+  let mut start_index = Some(0);
+  while let Some(start) = start_index {
+    match server
+      .parse_message(message::RequestDeviceList::new_page(start).into())
+      .await
+      .expect("Test, assuming infallible.")
+    {
+      ButtplugServerMessage::DeviceList(list) => {
+        pages.push(
+          list
+            .devices()
+            .iter()
+            .map(|device| device.device_index())
+            .collect::<Vec<u32>>(),
+        );
+        start_index = list.next_device_index();
+      }
+      msg => panic!("Should've received DeviceList, got {:?}", msg),
+    }
+  }
+  assert_eq!(pages, vec![vec![0, 1], vec![2, 3], vec![4]]);
+}
+
 #[tokio::test]
 async fn test_server_scanning_finished() {
   let mut builder = TestDeviceCommunicationManagerBuilder::default();
@@ -308,7 +556,7 @@ async fn test_server_scanning_finished() {
   let mut finish_received = false;
   // We should get 3 messages: 2 DeviceAdded, 1 ScanningFinished.
   while let Some(msg) = recv.next().await {
-    if matches!(msg, ButtplugServerMessage::ScanningFinished(_)) {
+    if matches!(*msg, ButtplugServerMessage::ScanningFinished(_)) {
       finish_received = true;
       break;
     }
@@ -320,6 +568,302 @@ async fn test_server_scanning_finished() {
   assert!(finish_received);
 }
 
+#[tokio::test]
+async fn test_server_connection_hook() {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let _rejected = builder.add_test_device(&TestDeviceIdentifier::new(
+    "Massage Demo",
+    Some("RejectedAddress".to_owned()),
+  ));
+  let _accepted = builder.add_test_device(&TestDeviceIdentifier::new(
+    "Massage Demo",
+    Some("AcceptedAddress".to_owned()),
+  ));
+  let rejected_identifier = ServerDeviceIdentifier::new(
+    "RejectedAddress",
+    "aneros",
+    &ProtocolAttributesType::Identifier("Massage Demo".to_owned()),
+  );
+  let hook_rejected_identifier = rejected_identifier.clone();
+  let (asked_sender, mut asked_receiver) = tokio::sync::mpsc::unbounded_channel();
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder
+    .comm_manager(builder)
+    .connection_hook(move |device: ConnectingDevice| {
+      let decision = if *device.identifier() == hook_rejected_identifier {
+        DeviceConnectionDecision::Reject
+      } else {
+        DeviceConnectionDecision::AcceptWithDisplayName("Living Room".to_owned())
+      };
+      asked_sender
+        .send(device.identifier().clone())
+        .expect("Test, assuming infallible.");
+      future::ready(decision).boxed()
+    });
+  let server = server_builder.finish().expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+
+  // Both devices are asked about, whatever the answer.
+  let mut asked = vec![];
+  for _ in 0..2 {
+    asked.push(
+      tokio::time::timeout(Duration::from_secs(5), asked_receiver.recv())
+        .await
+        .expect("Test, assuming infallible.")
+        .expect("Test, assuming infallible."),
+    );
+  }
+  assert!(asked.contains(&rejected_identifier));
+
+  loop {
+    let msg = tokio::time::timeout(Duration::from_secs(5), recv.next())
+      .await
+      .expect("Test, assuming infallible.")
+      .expect("Test, assuming infallible.");
+    if let ButtplugServerMessage::DeviceAdded(da) = &*msg {
+      assert_eq!(da.device_display_name().as_deref(), Some("Living Room"));
+      break;
+    }
+  }
+  let devices = server.device_manager().device_state_snapshots();
+  assert_eq!(devices.len(), 1);
+  assert_eq!(devices[0].address(), "AcceptedAddress");
+  assert_eq!(devices[0].display_name().as_deref(), Some("Living Room"));
+}
+
+/// Comm manager whose adapter is never there, or that isn't allowed to use it if given a
+/// permission error.
+#[derive(Default)]
+struct MissingAdapterCommunicationManagerBuilder {
+  permission_error: Option<HardwarePermissionError>,
+}
+
+impl HardwareCommunicationManagerBuilder for MissingAdapterCommunicationManagerBuilder {
+  fn finish(
+    &mut self,
+    sender: Sender<HardwareCommunicationManagerEvent>,
+  ) -> Box<dyn HardwareCommunicationManager> {
+    Box::new(MissingAdapterCommunicationManager {
+      _sender: sender,
+      permission_error: self.permission_error.clone(),
+    })
+  }
+}
+
+struct MissingAdapterCommunicationManager {
+  // The device manager stops once every comm manager has dropped its sender.
+  _sender: Sender<HardwareCommunicationManagerEvent>,
+  permission_error: Option<HardwarePermissionError>,
+}
+
+impl HardwareCommunicationManager for MissingAdapterCommunicationManager {
+  fn name(&self) -> &'static str {
+    "MissingAdapterCommunicationManager"
+  }
+
+  fn start_scanning(&mut self) -> ButtplugResultFuture {
+    panic!("Should not be asked to scan without an adapter");
+  }
+
+  fn stop_scanning(&mut self) -> ButtplugResultFuture {
+    future::ready(Ok(())).boxed()
+  }
+
+  fn status(&self) -> HardwareCommunicationManagerStatus {
+    HardwareCommunicationManagerStatus::AdapterMissing
+  }
+
+  fn check_permissions(&self) -> Result<(), HardwarePermissionError> {
+    match &self.permission_error {
+      Some(err) => Err(err.clone()),
+      None => Ok(()),
+    }
+  }
+}
+
+#[tokio::test]
+async fn test_server_scanning_finished_without_adapter() {
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder.comm_manager(MissingAdapterCommunicationManagerBuilder::default());
+  let server = server_builder.finish().expect("Test, assuming infallible.");
+
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  // Nothing can scan, so scanning finishes right away.
+  let msg = tokio::time::timeout(Duration::from_secs(1), recv.next())
+    .await
+    .expect("Test, assuming infallible.");
+  assert!(matches!(msg.as_deref(), Some(ButtplugServerMessage::ScanningFinished(_))));
+  let snapshot = server.state_snapshot();
+  assert!(!snapshot.scanning());
+  assert_eq!(
+    *snapshot.comm_managers()[0].status(),
+    HardwareCommunicationManagerStatus::AdapterMissing
+  );
+}
+
+#[tokio::test]
+async fn test_server_scanning_finished_without_permission() {
+  let permission_error =
+    HardwarePermissionError::new(HardwarePermission::SerialPort, "Join the dialout group.");
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder.comm_manager(MissingAdapterCommunicationManagerBuilder {
+    permission_error: Some(permission_error.clone()),
+  });
+  let server = server_builder.finish().expect("Test, assuming infallible.");
+  // Hosts can see what's wrong before scanning.
+  let expected_status = HardwareCommunicationManagerStatus::NeedsPermission(permission_error);
+  assert_eq!(
+    *server.state_snapshot().comm_managers()[0].status(),
+    expected_status
+  );
+
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  let msg = tokio::time::timeout(Duration::from_secs(1), recv.next())
+    .await
+    .expect("Test, assuming infallible.");
+  assert!(matches!(msg.as_deref(), Some(ButtplugServerMessage::ScanningFinished(_))));
+  assert_eq!(
+    *server.state_snapshot().comm_managers()[0].status(),
+    expected_status
+  );
+}
+
+#[tokio::test]
+async fn test_server_unsupported_device_reported() {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let mut _device = builder.add_test_device(&TestDeviceIdentifier::new("Flamingo2", None));
+
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder.comm_manager(builder);
+  let server = server_builder.finish().unwrap();
+
+  let unsupported = server.unsupported_device_stream();
+  pin_mut!(unsupported);
+  assert!(server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into()
+    )
+    .await
+    .is_ok());
+  assert!(server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .is_ok());
+  let info = tokio::time::timeout(Duration::from_secs(5), unsupported.next())
+    .await
+    .expect("Should report the unsupported device before timing out.")
+    .expect("Stream should still be open.");
+  assert_eq!(info.name(), "Flamingo2");
+  assert!(matches!(
+    info.specifier(),
+    ProtocolCommunicationSpecifier::BluetoothLE(_)
+  ));
+  assert_eq!(info.rssi(), None);
+  let suggestion = info
+    .suggestions()
+    .first()
+    .expect("Name is close enough to a known device to get a suggestion.");
+  assert_eq!(suggestion.protocol(), "magic-motion-1");
+  assert_eq!(suggestion.closest_name().as_deref(), Some("Flamingo"));
+  assert_eq!(*server.state_snapshot().unsupported_devices(), vec![info]);
+}
+
+#[tokio::test]
+async fn test_server_reload_configuration() {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let mut _device = builder.add_test_device(&TestDeviceIdentifier::new("Flamingo2", None));
+
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder.comm_manager(builder);
+  let server = server_builder.finish().unwrap();
+
+  assert!(server
+    .device_manager()
+    .reload_configuration("Not JSON")
+    .is_err());
+  server
+    .device_manager()
+    .reload_configuration(
+      r#"
+      {
+        "version": {
+          "major": 2,
+          "minor": 999
+        },
+        "protocols": {
+          "magic-motion-1": {
+            "btle": {
+              "names": [
+                "Flamingo2"
+              ],
+              "services": {
+                "78667579-7b48-43db-b8c5-7928a6b0a335": {
+                  "tx": "78667579-a914-49a4-8333-aa3c0cd8fedc"
+                }
+              }
+            }
+          }
+        }
+      }
+      "#,
+    )
+    .expect("Test, assuming infallible.");
+
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  assert!(server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into()
+    )
+    .await
+    .is_ok());
+  assert!(server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .is_ok());
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(device_added) = &*msg {
+      // Attributes loaded before the reload still apply to devices it adds names for.
+      assert_eq!(device_added.device_name(), "Magic Motion V1 Device");
+      return;
+    }
+  }
+  panic!("Should have added the device before the event stream closed.");
+}
+
 #[tokio::test]
 async fn test_server_builder_null_device_config() {
   let mut builder = ButtplugServerBuilder::default();
@@ -467,6 +1011,834 @@ async fn test_server_builder_user_device_config_old_config_version() {
     .is_err());
 }
 
+#[tokio::test]
+async fn test_server_builder_raw_messages_with_unauthenticated_access() {
+  let mut builder = ButtplugServerBuilder::default();
+  assert!(matches!(
+    builder
+      .allow_raw_messages()
+      .access(ButtplugServerAccess::UnauthenticatedRemote)
+      .finish(),
+    Err(ButtplugServerError::RawMessagesWithUnauthenticatedAccess)
+  ));
+  let mut builder = ButtplugServerBuilder::default();
+  assert!(builder
+    .allow_raw_messages()
+    .access(ButtplugServerAccess::AuthenticatedRemote)
+    .finish()
+    .is_ok());
+  // Allowing raw messages for a single device is still allowing raw messages.
+  let mut builder = ButtplugServerBuilder::default();
+  assert!(matches!(
+    builder
+      .allow_raw_messages_for(&ServerDeviceIdentifier::new(
+        "DebugAddress",
+        "aneros",
+        &ProtocolAttributesType::Identifier("Massage Demo".to_owned()),
+      ))
+      .access(ButtplugServerAccess::UnauthenticatedRemote)
+      .finish(),
+    Err(ButtplugServerError::RawMessagesWithUnauthenticatedAccess)
+  ));
+}
+
+#[tokio::test]
+async fn test_server_builder_address_allowed_and_denied() {
+  let mut builder = ButtplugServerBuilder::default();
+  assert!(matches!(
+    builder
+      .allowed_address("AllowedAndDenied")
+      .denied_address("AllowedAndDenied")
+      .finish(),
+    Err(ButtplugServerError::DeviceConfigurationManagerError(_))
+  ));
+}
+
+#[tokio::test]
+async fn test_server_builder_reserved_index_conflict() {
+  let identifier = |address: &str| {
+    ServerDeviceIdentifier::new(address, "lovense", &ProtocolAttributesType::Default)
+  };
+  let mut builder = ButtplugServerBuilder::default();
+  assert!(matches!(
+    builder
+      .reserved_index(&identifier("FirstAddress"), 1)
+      .reserved_index(&identifier("SecondAddress"), 1)
+      .finish(),
+    Err(ButtplugServerError::DeviceConfigurationManagerError(_))
+  ));
+  let mut builder = ButtplugServerBuilder::default();
+  assert!(matches!(
+    builder
+      .reserved_index(&identifier("FirstAddress"), 1)
+      .reserved_index(&identifier("FirstAddress"), 2)
+      .finish(),
+    Err(ButtplugServerError::DeviceConfigurationManagerError(_))
+  ));
+}
+
+#[tokio::test]
+async fn test_server_state_snapshot() {
+  let (server, device) = test_server_with_device("Flamingo", false).await;
+  let recv = server.event_stream();
+  pin_mut!(recv);
+
+  let snapshot = server.state_snapshot();
+  assert!(snapshot.client().is_none());
+  assert!(snapshot.devices().is_empty());
+  assert!(!snapshot.scanning());
+  assert!(snapshot.device_config_version().is_some());
+  assert_eq!(
+    snapshot.message_spec_version(),
+    BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION
+  );
+
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", ButtplugMessageSpecVersion::Version2).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  let mut device_added = false;
+  let mut scanning_finished = false;
+  while let Some(msg) = recv.next().await {
+    match *msg {
+      ButtplugServerMessage::DeviceAdded(_) => device_added = true,
+      ButtplugServerMessage::ScanningFinished(_) => scanning_finished = true,
+      _ => {}
+    }
+    if device_added && scanning_finished {
+      break;
+    }
+  }
+
+  let snapshot = server.state_snapshot();
+  let client = snapshot
+    .client()
+    .as_ref()
+    .expect("Test, assuming infallible.");
+  assert_eq!(client.name(), "Test Client");
+  assert_eq!(
+    client.message_spec_version(),
+    ButtplugMessageSpecVersion::Version2
+  );
+  assert!(!snapshot.scanning());
+  assert_eq!(snapshot.devices().len(), 1);
+  let device_snapshot = &snapshot.devices()[0];
+  assert_eq!(device_snapshot.protocol(), "magic-motion-1");
+  assert!(device_snapshot
+    .message_attributes()
+    .sensor_read_cmd()
+    .is_some());
+  assert!(device_snapshot.battery_level().is_none());
+  assert_eq!(snapshot.comm_managers().len(), 1);
+  let comm_manager = &snapshot.comm_managers()[0];
+  assert!(comm_manager.advertisements_seen() >= 1);
+  assert!(comm_manager.devices_matched() >= 1);
+  assert_eq!(comm_manager.connects_attempted(), 1);
+  assert_eq!(comm_manager.connects_succeeded(), 1);
+  assert_eq!(comm_manager.connects_failed(), 0);
+  assert!(comm_manager.average_connect_time().is_some());
+  assert_eq!(
+    *comm_manager.status(),
+    HardwareCommunicationManagerStatus::Ready
+  );
+
+  // Battery level shows up once it has been read.
+  device
+    .sender
+    .send(TestHardwareEvent::Reads(vec![
+      TestHardwareNotification::new(Endpoint::RxBLEBattery, &[75]),
+    ]))
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(
+      message::SensorReadCmd::new(device_snapshot.device_index(), 0, SensorType::Battery).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  assert_eq!(server.state_snapshot().devices()[0].battery_level(), Some(75));
+
+  // Commands show up in the device's activity once they've been written.
+  server
+    .parse_message(
+      message::ScalarCmd::new(
+        device_snapshot.device_index(),
+        vec![message::ScalarSubcommand::new(
+          0,
+          0.5,
+          message::ActuatorType::Vibrate,
+        )],
+      )
+      .into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  let activity = server.state_snapshot().devices()[0].activity();
+  assert!(activity.last_command_sent().is_some());
+  assert!(activity.last_notification_received().is_none());
+  assert!(activity.keepalive_active_since().is_none());
+
+  assert!(server.disconnect().await.is_ok());
+  assert!(server.state_snapshot().client().is_none());
+}
+
+#[tokio::test]
+async fn test_server_disconnect_all_devices() {
+  let (server, mut device) = test_server_with_device("Massage Demo", false).await;
+  let recv = server.event_stream();
+  pin_mut!(recv);
+
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  let mut device_index = None;
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = &*msg {
+      device_index = Some(da.device_index());
+      break;
+    }
+  }
+  let device_index = device_index.expect("Test, assuming infallible.");
+
+  server
+    .parse_message(message::DisconnectAllDevices::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  // Devices are stopped before being let go.
+  check_test_recv_value(
+    &mut device,
+    HardwareCommand::Write(HardwareWriteCmd::new(Endpoint::Tx, vec![0xF1, 0], false)),
+  );
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceRemoved(dr) = &*msg {
+      assert_eq!(dr.device_index(), device_index);
+      break;
+    }
+  }
+
+  // The server stays up, just without any devices.
+  assert!(server.connected());
+  assert!(server.device_manager().device_state_snapshots().is_empty());
+  assert!(server
+    .parse_message(message::RequestDeviceList::default().into())
+    .await
+    .is_ok());
+}
+
+#[tokio::test]
+async fn test_server_device_attributes() {
+  let (server, mut device) = test_server_with_device("Massage Demo", false).await;
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  let mut device_index = None;
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = &*msg {
+      device_index = Some(da.device_index());
+      break;
+    }
+  }
+  let device_index = device_index.expect("Test, assuming infallible.");
+  let first_step_count = |attributes: &message::DeviceAttributes| {
+    *attributes
+      .device()
+      .device_messages()
+      .scalar_cmd()
+      .as_ref()
+      .expect("Test, assuming infallible.")[0]
+      .step_count()
+  };
+
+  let reply = server
+    .parse_message(message::RequestDeviceAttributes::new(device_index).into())
+    .await
+    .expect("Test, assuming infallible.");
+  if let ButtplugServerMessage::DeviceAttributes(attributes) = reply {
+    assert_eq!(attributes.id(), 1);
+    assert_eq!(attributes.device().device_name(), "Aneros Vivi");
+    assert_eq!(first_step_count(&attributes), 127);
+  } else {
+    panic!("Expected DeviceAttributes, got {:?}", reply);
+  }
+  assert!(server
+    .parse_message(message::RequestDeviceAttributes::new(device_index + 1).into())
+    .await
+    .is_err());
+
+  // Live changes are pushed to clients.
+  let device_manager = server.device_manager();
+  device_manager
+    .set_device_display_name(device_index, Some("Bedside".to_owned()))
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAttributes(attributes) = &*msg {
+      assert_eq!(attributes.id(), 0);
+      assert_eq!(
+        *attributes.device().device_display_name(),
+        Some("Bedside".to_owned())
+      );
+      break;
+    }
+  }
+  // Limits can't go past what the device config allows.
+  assert!(device_manager
+    .set_device_step_limit(device_index, ButtplugDeviceMessageType::ScalarCmd, 0, 0..=200)
+    .is_err());
+  device_manager
+    .set_device_step_limit(device_index, ButtplugDeviceMessageType::ScalarCmd, 0, 0..=63)
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAttributes(attributes) = &*msg {
+      assert_eq!(first_step_count(attributes), 63);
+      break;
+    }
+  }
+  server
+    .parse_message(
+      message::ScalarCmd::new(
+        device_index,
+        vec![message::ScalarSubcommand::new(
+          0,
+          1.0,
+          message::ActuatorType::Vibrate,
+        )],
+      )
+      .into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  check_test_recv_value(
+    &mut device,
+    HardwareCommand::Write(HardwareWriteCmd::new(Endpoint::Tx, vec![0xF1, 63], false)),
+  );
+}
+
+#[tokio::test]
+async fn test_server_targeted_scanning() {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let _target = builder.add_test_device(&TestDeviceIdentifier::new(
+    "Massage Demo",
+    Some("TargetAddress".to_owned()),
+  ));
+  let _other = builder.add_test_device(&TestDeviceIdentifier::new(
+    "Flamingo",
+    Some("OtherAddress".to_owned()),
+  ));
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder.comm_manager(builder).reserved_index(
+    &ServerDeviceIdentifier::new(
+      "TargetAddress",
+      "aneros",
+      &ProtocolAttributesType::Identifier("Massage Demo".to_owned()),
+    ),
+    5,
+  );
+  let server = server_builder.finish().expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+
+  // No device has ever had this index, so there's nothing to look for.
+  assert!(server
+    .parse_message(message::StartTargetedScanning::new(6).into())
+    .await
+    .is_err());
+  server
+    .parse_message(message::StartTargetedScanning::new(5).into())
+    .await
+    .expect("Test, assuming infallible.");
+  let mut added_indexes = vec![];
+  let mut scanning_finished = false;
+  while added_indexes.is_empty() || !scanning_finished {
+    let msg = tokio::time::timeout(Duration::from_secs(5), recv.next())
+      .await
+      .expect("Test, assuming infallible.")
+      .expect("Test, assuming infallible.");
+    match &*msg {
+      ButtplugServerMessage::DeviceAdded(da) => added_indexes.push(da.device_index()),
+      ButtplugServerMessage::ScanningFinished(_) => scanning_finished = true,
+      _ => {}
+    }
+  }
+  // The other device was found by the same scan, but ignored.
+  assert_eq!(added_indexes, vec![5]);
+  assert_eq!(server.device_manager().device_state_snapshots().len(), 1);
+}
+
+#[tokio::test]
+async fn test_server_device_reconnect() {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let identifier = TestDeviceIdentifier::new("Massage Demo", Some("ReconnectAddress".to_owned()));
+  let device = builder.add_test_device(&identifier);
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder
+    .comm_manager(builder.clone())
+    .reconnect_window(Duration::from_secs(5));
+  let server = server_builder.finish().expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  let mut device_index = None;
+  let mut scanning_finished = false;
+  while device_index.is_none() || !scanning_finished {
+    let msg = tokio::time::timeout(Duration::from_secs(5), recv.next())
+      .await
+      .expect("Test, assuming infallible.")
+      .expect("Test, assuming infallible.");
+    match &*msg {
+      ButtplugServerMessage::DeviceAdded(da) => device_index = Some(da.device_index()),
+      ButtplugServerMessage::ScanningFinished(_) => scanning_finished = true,
+      _ => {}
+    }
+  }
+  let device_index = device_index.expect("Test, assuming infallible.");
+
+  // The device comes back in range along with another one, then drops out.
+  let _device = builder.add_test_device(&identifier);
+  let _other = builder.add_test_device(&TestDeviceIdentifier::new(
+    "Flamingo",
+    Some("OtherAddress".to_owned()),
+  ));
+  device
+    .sender
+    .send(TestHardwareEvent::Disconnect)
+    .await
+    .expect("Test, assuming infallible.");
+
+  let mut events = vec![];
+  while let Ok(Some(msg)) = tokio::time::timeout(Duration::from_millis(500), recv.next()).await {
+    match &*msg {
+      ButtplugServerMessage::DeviceRemoved(dr) => events.push(("removed", dr.device_index())),
+      ButtplugServerMessage::DeviceAdded(da) => events.push(("added", da.device_index())),
+      // Nobody asked for the reconnect scan, so it shouldn't report finishing.
+      ButtplugServerMessage::ScanningFinished(_) => panic!("Reconnect scan sent ScanningFinished"),
+      _ => {}
+    }
+  }
+  assert_eq!(
+    events,
+    vec![("removed", device_index), ("added", device_index)]
+  );
+  // Only the device being reconnected was picked up.
+  assert_eq!(server.device_manager().device_state_snapshots().len(), 1);
+}
+
+const SPLIT_DEVICE_USER_CONFIG_JSON: &str = r#"
+{
+  "version": {
+    "major": 2,
+    "minor": 0
+  },
+  "user-configs": {
+    "devices": [
+      {
+        "identifier": {
+          "address": "SplitMe",
+          "protocol": "aneros",
+          "identifier": "Massage Demo"
+        },
+        "config": {
+          "split": true
+        }
+      }
+    ]
+  }
+}
+"#;
+
+#[tokio::test]
+async fn test_server_split_device() {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let mut device = builder.add_test_device(&TestDeviceIdentifier::new(
+    "Massage Demo",
+    Some("SplitMe".to_owned()),
+  ));
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder
+    .comm_manager(builder)
+    .user_device_configuration_json(Some(SPLIT_DEVICE_USER_CONFIG_JSON.to_owned()));
+  let server = server_builder.finish().expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  // The device itself, then one device for each of its two vibrators.
+  let mut split_index = None;
+  let mut added = 0;
+  while added < 3 {
+    let msg = tokio::time::timeout(Duration::from_secs(5), recv.next())
+      .await
+      .expect("Test, assuming infallible.")
+      .expect("Test, assuming infallible.");
+    if let ButtplugServerMessage::DeviceAdded(da) = &*msg {
+      added += 1;
+      if da.device_name() == "Aneros Vivi 2" {
+        assert_eq!(
+          da.device_messages()
+            .scalar_cmd()
+            .as_ref()
+            .expect("Test, assuming infallible.")
+            .len(),
+          1
+        );
+        split_index = Some(da.device_index());
+      }
+    }
+  }
+  let split_index = split_index.expect("Test, assuming infallible.");
+
+  server
+    .parse_message(
+      message::ScalarCmd::new(
+        split_index,
+        vec![message::ScalarSubcommand::new(
+          0,
+          1.0,
+          message::ActuatorType::Vibrate,
+        )],
+      )
+      .into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  check_test_recv_value(
+    &mut device,
+    HardwareCommand::Write(HardwareWriteCmd::new(Endpoint::Tx, vec![0xF2, 127], false)),
+  );
+  // Split devices only have the one feature.
+  assert!(server
+    .parse_message(
+      message::ScalarCmd::new(
+        split_index,
+        vec![message::ScalarSubcommand::new(
+          1,
+          1.0,
+          message::ActuatorType::Vibrate
+        )],
+      )
+      .into(),
+    )
+    .await
+    .is_err());
+  server
+    .parse_message(message::StopDeviceCmd::new(split_index).into())
+    .await
+    .expect("Test, assuming infallible.");
+  check_test_recv_value(
+    &mut device,
+    HardwareCommand::Write(HardwareWriteCmd::new(Endpoint::Tx, vec![0xF2, 0], false)),
+  );
+}
+
+const MERGED_DEVICE_USER_CONFIG_JSON: &str = r#"
+{
+  "version": {
+    "major": 2,
+    "minor": 0
+  },
+  "user-configs": {
+    "merged-devices": [
+      {
+        "name": "Both Vivis",
+        "devices": [
+          {
+            "address": "MergeFirst",
+            "protocol": "aneros",
+            "identifier": "Massage Demo"
+          },
+          {
+            "address": "MergeSecond",
+            "protocol": "aneros",
+            "identifier": "Massage Demo"
+          }
+        ]
+      }
+    ]
+  }
+}
+"#;
+
+#[tokio::test]
+async fn test_server_merged_device() {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let mut first = builder.add_test_device(&TestDeviceIdentifier::new(
+    "Massage Demo",
+    Some("MergeFirst".to_owned()),
+  ));
+  let mut second = builder.add_test_device(&TestDeviceIdentifier::new(
+    "Massage Demo",
+    Some("MergeSecond".to_owned()),
+  ));
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder
+    .comm_manager(builder)
+    .user_device_configuration_json(Some(MERGED_DEVICE_USER_CONFIG_JSON.to_owned()));
+  let server = server_builder.finish().expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  let mut merged_index = None;
+  let mut added = 0;
+  while added < 3 {
+    let msg = tokio::time::timeout(Duration::from_secs(5), recv.next())
+      .await
+      .expect("Test, assuming infallible.")
+      .expect("Test, assuming infallible.");
+    if let ButtplugServerMessage::DeviceAdded(da) = &*msg {
+      added += 1;
+      if da.device_name() == "Both Vivis" {
+        // Each member has two vibrators.
+        assert_eq!(
+          da.device_messages()
+            .scalar_cmd()
+            .as_ref()
+            .expect("Test, assuming infallible.")
+            .len(),
+          4
+        );
+        merged_index = Some(da.device_index());
+      }
+    }
+  }
+  let merged_index = merged_index.expect("Test, assuming infallible.");
+  let reply = server
+    .parse_message(message::RequestDeviceList::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  if let ButtplugServerMessage::DeviceList(list) = reply {
+    assert_eq!(list.devices().len(), 3);
+  } else {
+    panic!("Expected DeviceList, got {:?}", reply);
+  }
+
+  // Second vibrator of the first member, and first vibrator of the second.
+  server
+    .parse_message(
+      message::ScalarCmd::new(
+        merged_index,
+        vec![
+          message::ScalarSubcommand::new(1, 1.0, message::ActuatorType::Vibrate),
+          message::ScalarSubcommand::new(2, 1.0, message::ActuatorType::Vibrate),
+        ],
+      )
+      .into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  check_test_recv_value(
+    &mut first,
+    HardwareCommand::Write(HardwareWriteCmd::new(Endpoint::Tx, vec![0xF2, 127], false)),
+  );
+  check_test_recv_value(
+    &mut second,
+    HardwareCommand::Write(HardwareWriteCmd::new(Endpoint::Tx, vec![0xF1, 127], false)),
+  );
+  assert!(server
+    .parse_message(
+      message::ScalarCmd::new(
+        merged_index,
+        vec![message::ScalarSubcommand::new(
+          4,
+          1.0,
+          message::ActuatorType::Vibrate
+        )],
+      )
+      .into(),
+    )
+    .await
+    .is_err());
+
+  server
+    .device_manager()
+    .unmerge_devices("Both Vivis")
+    .expect("Test, assuming infallible.");
+  let msg = tokio::time::timeout(Duration::from_secs(5), recv.next())
+    .await
+    .expect("Test, assuming infallible.")
+    .expect("Test, assuming infallible.");
+  if let ButtplugServerMessage::DeviceRemoved(dr) = &*msg {
+    assert_eq!(dr.device_index(), merged_index);
+  } else {
+    panic!("Expected DeviceRemoved, got {:?}", msg);
+  }
+  assert!(server
+    .parse_message(message::StopDeviceCmd::new(merged_index).into())
+    .await
+    .is_err());
+}
+
+const SENSOR_RULE_USER_CONFIG_JSON: &str = r#"
+{
+  "version": {
+    "major": 2,
+    "minor": 0
+  },
+  "user-configs": {
+    "sensor-rules": [
+      {
+        "sensor-device": {
+          "address": "RuleSensor",
+          "protocol": "kgoal-boost",
+          "identifier": "Boost"
+        },
+        "sensor-index": 0,
+        "sensor-type": "Pressure",
+        "above": 600,
+        "hysteresis": 100,
+        "actuator-device": {
+          "address": "RuleActuator",
+          "protocol": "aneros",
+          "identifier": "Massage Demo"
+        },
+        "actuator-index": 0,
+        "actuator-type": "Vibrate",
+        "scalar": 1.0
+      }
+    ]
+  }
+}
+"#;
+
+/// KGoal Boost pressure notification with the given normalized reading.
+fn boost_pressure(reading: u16) -> Vec<u8> {
+  let [high, low] = reading.to_be_bytes();
+  vec![0x00, 0x01, 0x04, high, low, 0x00, 0x00]
+}
+
+#[tokio::test]
+async fn test_server_sensor_rule() {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let mut sensor = builder.add_test_device(&TestDeviceIdentifier::new(
+    "Boost",
+    Some("RuleSensor".to_owned()),
+  ));
+  let mut actuator = builder.add_test_device(&TestDeviceIdentifier::new(
+    "Massage Demo",
+    Some("RuleActuator".to_owned()),
+  ));
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder
+    .comm_manager(builder)
+    .user_device_configuration_json(Some(SENSOR_RULE_USER_CONFIG_JSON.to_owned()));
+  let server = server_builder.finish().expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  let mut added = 0;
+  while added < 2 {
+    let msg = tokio::time::timeout(Duration::from_secs(5), recv.next())
+      .await
+      .expect("Test, assuming infallible.")
+      .expect("Test, assuming infallible.");
+    if let ButtplugServerMessage::DeviceAdded(_) = &*msg {
+      added += 1;
+    }
+  }
+  // The server subscribes to the sensor itself, no client needed.
+  assert!(matches!(
+    tokio::time::timeout(Duration::from_secs(5), sensor.next_command())
+      .await
+      .expect("Test, assuming infallible."),
+    Some(HardwareCommand::Subscribe(_))
+  ));
+
+  sensor
+    .notify(Endpoint::RxPressure, &boost_pressure(700))
+    .await
+    .expect("Test, assuming infallible.");
+  assert_eq!(
+    tokio::time::timeout(Duration::from_secs(5), actuator.next_command())
+      .await
+      .expect("Test, assuming infallible."),
+    Some(HardwareCommand::Write(HardwareWriteCmd::new(
+      Endpoint::Tx,
+      vec![0xF1, 127],
+      false
+    )))
+  );
+  // 550 is within the hysteresis, so only 450 turns the vibrator off.
+  sensor
+    .notify(Endpoint::RxPressure, &boost_pressure(550))
+    .await
+    .expect("Test, assuming infallible.");
+  sensor
+    .notify(Endpoint::RxPressure, &boost_pressure(450))
+    .await
+    .expect("Test, assuming infallible.");
+  assert_eq!(
+    tokio::time::timeout(Duration::from_secs(5), actuator.next_command())
+      .await
+      .expect("Test, assuming infallible."),
+    Some(HardwareCommand::Write(HardwareWriteCmd::new(
+      Endpoint::Tx,
+      vec![0xF1, 0],
+      false
+    )))
+  );
+}
+
 // TODO Test sending system message (Id 0)
 // TODO Test sending system message (Ok but Id > 0)
 // TODO Test scan with no comm managers
@@ -6,14 +6,59 @@
 // for full license information.
 
 mod util;
-use buttplug::core::{
-  errors::{ButtplugDeviceError, ButtplugError},
-  message::{self, ButtplugServerMessage, Endpoint, BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION},
+use buttplug::{
+  core::{
+    errors::{ButtplugDeviceError, ButtplugError},
+    message::{
+      self,
+      ActuatorType,
+      ButtplugDeviceMessage,
+      ButtplugServerMessage,
+      Endpoint,
+      SensorType,
+      BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
+    },
+    ButtplugResultFuture,
+  },
+  server::{
+    device::{
+      hardware::{
+        api::{
+          HardwareCommunicationManager,
+          HardwareCommunicationManagerBuilder,
+          HardwareCommunicationManagerEvent,
+          HardwareCommunicationManagerStatus,
+        },
+        HardwareCommand,
+        HardwareWriteCmd,
+      },
+      BatterySaverSettings,
+      DutyCycle,
+    },
+    ButtplugServer,
+    ButtplugServerBuilder,
+  },
 };
-use futures::{pin_mut, StreamExt};
-use std::matches;
+use futures::{future, pin_mut, FutureExt, StreamExt};
+use std::{
+  matches,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
+  time::Duration,
+};
+use tokio::sync::{mpsc::Sender, Notify};
 pub use util::test_device_manager::TestDeviceCommunicationManagerBuilder;
-use util::test_server_with_device;
+use util::{
+  test_device_manager::{
+    TestDeviceChannelHost,
+    TestDeviceIdentifier,
+    TestHardwareEvent,
+    TestHardwareNotification,
+  },
+  test_server_with_device,
+};
 
 // Test devices that have protocols that support movements not all devices do.
 // For instance, the Onyx+ is part of a protocol that supports vibration, but
@@ -36,7 +81,7 @@ async fn test_capabilities_exposure() {
     .await
     .expect("Test, assuming infallible.");
   while let Some(msg) = recv.next().await {
-    if let ButtplugServerMessage::DeviceAdded(device) = msg {
+    if let ButtplugServerMessage::DeviceAdded(device) = &*msg {
       assert!(device.device_messages().scalar_cmd().is_none());
       assert!(device.device_messages().linear_cmd().is_some());
       return;
@@ -60,9 +105,9 @@ async fn test_server_raw_message() {
     .await
     .is_ok());
   while let Some(msg) = recv.next().await {
-    if let ButtplugServerMessage::ScanningFinished(_) = msg {
+    if let ButtplugServerMessage::ScanningFinished(_) = &*msg {
       continue;
-    } else if let ButtplugServerMessage::DeviceAdded(da) = msg {
+    } else if let ButtplugServerMessage::DeviceAdded(da) = &*msg {
       assert!(da.device_messages().raw_read_cmd().is_some());
       assert!(da.device_messages().raw_write_cmd().is_some());
       assert!(da.device_messages().raw_subscribe_cmd().is_some());
@@ -93,9 +138,9 @@ async fn test_server_no_raw_message() {
     .await
     .is_ok());
   while let Some(msg) = recv.next().await {
-    if let ButtplugServerMessage::ScanningFinished(_) = msg {
+    if let ButtplugServerMessage::ScanningFinished(_) = &*msg {
       continue;
-    } else if let ButtplugServerMessage::DeviceAdded(da) = msg {
+    } else if let ButtplugServerMessage::DeviceAdded(da) = &*msg {
       assert_eq!(da.device_name(), "Aneros Vivi");
       assert!(da.device_messages().raw_read_cmd().is_none());
       assert!(da.device_messages().raw_write_cmd().is_none());
@@ -126,9 +171,9 @@ async fn test_reject_on_no_raw_message() {
     .await
     .is_ok());
   while let Some(msg) = recv.next().await {
-    if let ButtplugServerMessage::ScanningFinished(_) = msg {
+    if let ButtplugServerMessage::ScanningFinished(_) = &*msg {
       continue;
-    } else if let ButtplugServerMessage::DeviceAdded(da) = msg {
+    } else if let ButtplugServerMessage::DeviceAdded(da) = &*msg {
       assert_eq!(da.device_name(), "Aneros Vivi");
       let mut should_be_err;
       should_be_err = server
@@ -178,6 +223,160 @@ async fn test_reject_on_no_raw_message() {
   }
 }
 
+const BATTERY_POLL_USER_CONFIG_JSON: &str = r#"
+{
+  "version": {
+    "major": 2,
+    "minor": 0
+  },
+  "user-configs": {
+    "devices": [
+      {
+        "identifier": {
+          "address": "BatteryPollAddress",
+          "protocol": "magic-motion-1",
+          "identifier": "Flamingo"
+        },
+        "config": {
+          "battery-poll-interval": 50
+        }
+      }
+    ]
+  }
+}
+"#;
+
+#[tokio::test]
+async fn test_server_battery_polling() {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let device = builder.add_test_device(&TestDeviceIdentifier::new(
+    "Flamingo",
+    Some("BatteryPollAddress".to_owned()),
+  ));
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder
+    .comm_manager(builder)
+    .user_device_configuration_json(Some(BATTERY_POLL_USER_CONFIG_JSON.to_owned()));
+  let server = server_builder.finish().expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+
+  // Queue up two battery levels, so the poller sees a change.
+  device
+    .sender
+    .send(TestHardwareEvent::Reads(vec![
+      TestHardwareNotification::new(Endpoint::RxBLEBattery, &[90]),
+      TestHardwareNotification::new(Endpoint::RxBLEBattery, &[80]),
+    ]))
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+
+  let mut device_index = None;
+  let mut readings = vec![];
+  while let Some(msg) = recv.next().await {
+    match &*msg {
+      ButtplugServerMessage::DeviceAdded(da) => device_index = Some(da.device_index()),
+      ButtplugServerMessage::SensorReading(reading) => {
+        assert_eq!(Some(reading.device_index()), device_index);
+        assert_eq!(reading.sensor_type(), SensorType::Battery);
+        readings.push(reading.data()[0]);
+        if readings.len() == 2 {
+          break;
+        }
+      }
+      _ => continue,
+    }
+  }
+  assert_eq!(readings, vec![90, 80]);
+  device
+    .sender
+    .send(TestHardwareEvent::Disconnect)
+    .await
+    .expect("Test, assuming infallible.");
+}
+
+const COMMAND_TIMEOUT_USER_CONFIG_JSON: &str = r#"
+{
+  "version": {
+    "major": 2,
+    "minor": 0
+  },
+  "user-configs": {
+    "devices": [
+      {
+        "identifier": {
+          "address": "CommandTimeoutAddress",
+          "protocol": "magic-motion-1",
+          "identifier": "Flamingo"
+        },
+        "config": {
+          "command-timeout": 10
+        }
+      }
+    ]
+  }
+}
+"#;
+
+#[tokio::test]
+async fn test_server_command_timeout() {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let _device = builder.add_test_device(&TestDeviceIdentifier::new(
+    "Flamingo",
+    Some("CommandTimeoutAddress".to_owned()),
+  ));
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder
+    .comm_manager(builder)
+    .user_device_configuration_json(Some(COMMAND_TIMEOUT_USER_CONFIG_JSON.to_owned()));
+  let server = server_builder.finish().expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  let mut device_index = None;
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = &*msg {
+      device_index = Some(da.device_index());
+      break;
+    }
+  }
+  // No battery reading is queued on the test device, so the read never finishes.
+  let err = server
+    .parse_message(
+      message::SensorReadCmd::new(
+        device_index.expect("Test, assuming infallible."),
+        0,
+        SensorType::Battery,
+      )
+      .into(),
+    )
+    .await
+    .expect_err("Read should time out.");
+  assert_eq!(
+    err.original_error(),
+    ButtplugError::ButtplugDeviceError(ButtplugDeviceError::DeviceCommandTimeout(10))
+  );
+}
+
 /*
 #[cfg(target_os = "windows")]
 #[ignore = "Has weird timeout issues"]
@@ -242,3 +441,1652 @@ async fn test_repeated_address_additions() {
     }
 }
 */
+
+async fn stop_all_devices_with_failing_writes(failing_writes: u32) -> Result<(), ButtplugError> {
+  let (server, device) = test_server_with_device("Massage Demo", false).await;
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  device
+    .sender
+    .send(TestHardwareEvent::FailWrites(failing_writes))
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(_) = &*msg {
+      break;
+    }
+  }
+  server
+    .parse_message(message::StopAllDevices::default().into())
+    .await
+    .map(|_| ())
+    .map_err(|err| err.original_error())
+}
+
+#[tokio::test]
+async fn test_stop_all_devices_retries_failed_stops() {
+  assert!(stop_all_devices_with_failing_writes(1).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_stop_all_devices_reports_failed_stops() {
+  let err = stop_all_devices_with_failing_writes(u32::MAX)
+    .await
+    .expect_err("Device never stops, so this should fail.");
+  if let ButtplugError::ButtplugDeviceError(ButtplugDeviceError::DevicesFailedToStop(failures)) =
+    err
+  {
+    assert_eq!(failures.0.len(), 1);
+    assert_eq!(failures.0[0].device_index(), 0);
+    assert!(failures.0[0].reason().contains("Test write failure"));
+  } else {
+    panic!("Unexpected error: {:?}", err);
+  }
+}
+
+const OUTPUT_RAMP_USER_CONFIG_JSON: &str = r#"
+{
+  "version": {
+    "major": 2,
+    "minor": 0
+  },
+  "user-configs": {
+    "devices": [
+      {
+        "identifier": {
+          "address": "WarmUpAddress",
+          "protocol": "magic-motion-1",
+          "identifier": "Flamingo"
+        },
+        "config": {
+          "warm-up": 60000
+        }
+      },
+      {
+        "identifier": {
+          "address": "CoolDownAddress",
+          "protocol": "magic-motion-1",
+          "identifier": "Flamingo"
+        },
+        "config": {
+          "cool-down": 300
+        }
+      }
+    ]
+  }
+}
+"#;
+
+// Sets up a server with a Flamingo at the given address, and turns its vibrator to full. Returns
+// the vibration level the device was sent.
+async fn ramped_flamingo_at_full(
+  address: &str,
+) -> (ButtplugServer, TestDeviceChannelHost, u32, u8) {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let mut device = builder.add_test_device(&TestDeviceIdentifier::new(
+    "Flamingo",
+    Some(address.to_owned()),
+  ));
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder
+    .comm_manager(builder)
+    .user_device_configuration_json(Some(OUTPUT_RAMP_USER_CONFIG_JSON.to_owned()));
+  let server = server_builder.finish().expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  let mut device_index = None;
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = &*msg {
+      device_index = Some(da.device_index());
+      break;
+    }
+  }
+  let device_index = device_index.expect("Test, assuming infallible.");
+  server
+    .parse_message(
+      message::ScalarCmd::new(
+        device_index,
+        vec![message::ScalarSubcommand::new(
+          0,
+          1.0,
+          ActuatorType::Vibrate,
+        )],
+      )
+      .into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  let level = next_vibration_level(&mut device).await;
+  (server, device, device_index, level)
+}
+
+async fn next_vibration_level(device: &mut TestDeviceChannelHost) -> u8 {
+  match device.receiver.recv().await {
+    // Vibration level is the 10th byte of a Magic Motion V1 write.
+    Some(HardwareCommand::Write(write)) => write.data()[9],
+    cmd => panic!("Expected a write, got {:?}", cmd),
+  }
+}
+
+#[tokio::test]
+async fn test_server_device_warm_up() {
+  let (_server, _device, _, level) = ramped_flamingo_at_full("WarmUpAddress").await;
+  // We're barely into a minute long warm up, so the device shouldn't get anywhere near full power.
+  assert!(
+    level < 10,
+    "Level {} should be capped during warm up",
+    level
+  );
+}
+
+#[tokio::test]
+async fn test_server_device_cool_down() {
+  let (server, mut device, device_index, level) =
+    ramped_flamingo_at_full("CoolDownAddress").await;
+  assert_eq!(level, 100);
+  server
+    .parse_message(message::StopDeviceCmd::new(device_index).into())
+    .await
+    .expect("Test, assuming infallible.");
+  let mut levels = vec![];
+  loop {
+    let level = next_vibration_level(&mut device).await;
+    levels.push(level);
+    if level == 0 {
+      break;
+    }
+  }
+  assert_eq!(levels, vec![67, 34, 0]);
+}
+
+#[tokio::test]
+async fn test_server_battery_saver() {
+  let (server, mut device) = test_server_with_device("Flamingo", false).await;
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  let mut device_index = None;
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = &*msg {
+      device_index = Some(da.device_index());
+      break;
+    }
+  }
+  let device_index = device_index.expect("Test, assuming infallible.");
+  server
+    .parse_message(
+      message::ScalarCmd::new(
+        device_index,
+        vec![message::ScalarSubcommand::new(
+          0,
+          1.0,
+          ActuatorType::Vibrate,
+        )],
+      )
+      .into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  assert_eq!(next_vibration_level(&mut device).await, 100);
+
+  let device_manager = server.device_manager();
+  assert!(device_manager
+    .set_battery_saver(Some(BatterySaverSettings::new(2.0, None)))
+    .is_err());
+  let duty_cycle = DutyCycle::new(Duration::from_millis(100), Duration::from_millis(100));
+  device_manager
+    .set_battery_saver(Some(BatterySaverSettings::new(0.5, Some(duty_cycle))))
+    .expect("Test, assuming infallible.");
+  // Output that's already running is scaled down, then rests and runs again with the duty cycle.
+  let mut levels = vec![];
+  for _ in 0..3 {
+    levels.push(next_vibration_level(&mut device).await);
+  }
+  assert_eq!(levels, vec![50, 0, 50]);
+
+  device_manager
+    .set_battery_saver(None)
+    .expect("Test, assuming infallible.");
+  assert_eq!(device_manager.battery_saver(), None);
+  assert_eq!(next_vibration_level(&mut device).await, 100);
+}
+
+const OVERUSE_PROTECTION_USER_CONFIG_JSON: &str = r#"
+{
+  "version": {
+    "major": 2,
+    "minor": 0
+  },
+  "user-configs": {
+    "specifiers": {
+      "magic-motion-1": {
+        "overuse-protection": {
+          "threshold": 0.8,
+          "max-runtime": 100,
+          "step-down": 0.5
+        }
+      }
+    }
+  }
+}
+"#;
+
+#[tokio::test]
+async fn test_server_overuse_protection() {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let mut device = builder.add_test_device(&TestDeviceIdentifier::new("Flamingo", None));
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder
+    .comm_manager(builder)
+    .user_device_configuration_json(Some(OVERUSE_PROTECTION_USER_CONFIG_JSON.to_owned()));
+  let server = server_builder.finish().expect("Test, assuming infallible.");
+  let warnings = server.overuse_warning_stream();
+  pin_mut!(warnings);
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  let mut device_index = None;
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = &*msg {
+      device_index = Some(da.device_index());
+      break;
+    }
+  }
+  let device_index = device_index.expect("Test, assuming infallible.");
+  let vibrate = |level| {
+    message::ScalarCmd::new(
+      device_index,
+      vec![message::ScalarSubcommand::new(
+        0,
+        level,
+        ActuatorType::Vibrate,
+      )],
+    )
+    .into()
+  };
+  server
+    .parse_message(vibrate(1.0))
+    .await
+    .expect("Test, assuming infallible.");
+  assert_eq!(next_vibration_level(&mut device).await, 100);
+
+  // After running hard for too long, the device is stepped down and the host is told.
+  let warning = warnings.next().await.expect("Test, assuming infallible.");
+  assert_eq!(warning.device_index(), device_index);
+  assert_eq!(
+    warning.protection().max_runtime(),
+    Duration::from_millis(100)
+  );
+  assert_eq!(next_vibration_level(&mut device).await, 50);
+
+  // Dropping below the threshold lifts the cap.
+  server
+    .parse_message(vibrate(0.2))
+    .await
+    .expect("Test, assuming infallible.");
+  assert_eq!(next_vibration_level(&mut device).await, 20);
+  server
+    .parse_message(vibrate(1.0))
+    .await
+    .expect("Test, assuming infallible.");
+  assert_eq!(next_vibration_level(&mut device).await, 100);
+}
+
+const MAX_UPDATE_RATE_USER_CONFIG_JSON: &str = r#"
+{
+  "version": {
+    "major": 2,
+    "minor": 0
+  },
+  "user-configs": {
+    "specifiers": {
+      "magic-motion-1": {
+        "max-update-rate": 10
+      }
+    }
+  }
+}
+"#;
+
+#[tokio::test]
+async fn test_server_max_update_rate() {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let mut device = builder.add_test_device(&TestDeviceIdentifier::new("Flamingo", None));
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder
+    .comm_manager(builder)
+    .user_device_configuration_json(Some(MAX_UPDATE_RATE_USER_CONFIG_JSON.to_owned()));
+  let server = server_builder.finish().expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  let mut device_index = None;
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = &*msg {
+      device_index = Some(da.device_index());
+      break;
+    }
+  }
+  let device_index = device_index.expect("Test, assuming infallible.");
+  let vibrate = |level| {
+    message::ScalarCmd::new(
+      device_index,
+      vec![message::ScalarSubcommand::new(
+        0,
+        level,
+        ActuatorType::Vibrate,
+      )],
+    )
+    .into()
+  };
+
+  // The first command goes straight out, everything else sent during the next 100ms is merged and
+  // only the latest level is written.
+  for level in [0.2, 0.4, 0.6, 0.8] {
+    server
+      .parse_message(vibrate(level))
+      .await
+      .expect("Test, assuming infallible.");
+  }
+  assert_eq!(next_vibration_level(&mut device).await, 20);
+  assert_eq!(next_vibration_level(&mut device).await, 80);
+  assert!(device.try_next_command().is_none());
+
+  // Stopping throws away merged output that hasn't gone out yet.
+  server
+    .parse_message(vibrate(0.5))
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StopDeviceCmd::new(device_index).into())
+    .await
+    .expect("Test, assuming infallible.");
+  assert_eq!(next_vibration_level(&mut device).await, 0);
+  tokio::time::sleep(Duration::from_millis(200)).await;
+  assert!(device.try_next_command().is_none());
+}
+
+#[tokio::test]
+async fn test_server_merges_output_while_device_busy() {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let mut device = builder.add_test_device(&TestDeviceIdentifier::new("Flamingo", None));
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder.comm_manager(builder);
+  let server = server_builder.finish().expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  let mut device_index = None;
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = &*msg {
+      device_index = Some(da.device_index());
+      break;
+    }
+  }
+  let device_index = device_index.expect("Test, assuming infallible.");
+  let vibrate = |level| {
+    message::ScalarCmd::new(
+      device_index,
+      vec![message::ScalarSubcommand::new(
+        0,
+        level,
+        ActuatorType::Vibrate,
+      )],
+    )
+    .into()
+  };
+
+  // Test devices hold 256 writes until the test takes them, so after that many, the next write
+  // waits like a device that can't keep up.
+  for i in 0..256 {
+    server
+      .parse_message(vibrate(if i % 2 == 0 { 0.2 } else { 0.4 }))
+      .await
+      .expect("Test, assuming infallible.");
+  }
+  let busy = tokio::spawn(server.parse_message(vibrate(0.6)));
+  tokio::time::sleep(Duration::from_millis(50)).await;
+  assert!(!busy.is_finished());
+  // Output sent while the device is busy is answered right away, and merged.
+  for level in [0.8, 0.1, 0.3] {
+    server
+      .parse_message(vibrate(level))
+      .await
+      .expect("Test, assuming infallible.");
+  }
+  for _ in 0..256 {
+    next_vibration_level(&mut device).await;
+  }
+  busy
+    .await
+    .expect("Test, assuming infallible.")
+    .expect("Test, assuming infallible.");
+  // Only the latest level follows the write that was waiting.
+  assert_eq!(next_vibration_level(&mut device).await, 60);
+  assert_eq!(next_vibration_level(&mut device).await, 30);
+  tokio::time::sleep(Duration::from_millis(50)).await;
+  assert!(device.try_next_command().is_none());
+
+  // Stopping throws away merged output that hasn't gone out yet.
+  for i in 0..256 {
+    server
+      .parse_message(vibrate(if i % 2 == 0 { 0.2 } else { 0.4 }))
+      .await
+      .expect("Test, assuming infallible.");
+  }
+  let busy = tokio::spawn(server.parse_message(vibrate(0.6)));
+  tokio::time::sleep(Duration::from_millis(50)).await;
+  server
+    .parse_message(vibrate(0.9))
+    .await
+    .expect("Test, assuming infallible.");
+  let stop = tokio::spawn(server.parse_message(message::StopDeviceCmd::new(device_index).into()));
+  // Let the stop get in line behind the waiting write.
+  tokio::time::sleep(Duration::from_millis(50)).await;
+  for _ in 0..256 {
+    next_vibration_level(&mut device).await;
+  }
+  assert_eq!(next_vibration_level(&mut device).await, 60);
+  assert_eq!(next_vibration_level(&mut device).await, 0);
+  busy
+    .await
+    .expect("Test, assuming infallible.")
+    .expect("Test, assuming infallible.");
+  stop
+    .await
+    .expect("Test, assuming infallible.")
+    .expect("Test, assuming infallible.");
+  tokio::time::sleep(Duration::from_millis(50)).await;
+  assert!(device.try_next_command().is_none());
+}
+
+#[tokio::test]
+async fn test_server_device_disconnect_during_command() {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let device = builder.add_test_device(&TestDeviceIdentifier::new(
+    "Flamingo",
+    Some("DisconnectingAddress".to_owned()),
+  ));
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder.comm_manager(builder);
+  let server = server_builder.finish().expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  let mut device_added = None;
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = &*msg {
+      device_added = Some((da.device_index(), da.device_name().clone()));
+      break;
+    }
+  }
+  let (device_index, device_name) = device_added.expect("Test, assuming infallible.");
+  let vibrate = |level| {
+    message::ScalarCmd::new(
+      device_index,
+      vec![message::ScalarSubcommand::new(
+        0,
+        level,
+        ActuatorType::Vibrate,
+      )],
+    )
+    .into()
+  };
+  // Test devices hold 256 writes until the test takes them, so the next command is still waiting
+  // on the device when it disconnects.
+  for i in 0..256 {
+    server
+      .parse_message(vibrate(if i % 2 == 0 { 0.2 } else { 0.4 }))
+      .await
+      .expect("Test, assuming infallible.");
+  }
+  let in_flight = tokio::spawn(server.parse_message(vibrate(0.6)));
+  tokio::time::sleep(Duration::from_millis(50)).await;
+  assert!(!in_flight.is_finished());
+  device
+    .disconnect()
+    .await
+    .expect("Test, assuming infallible.");
+  let err = tokio::time::timeout(Duration::from_secs(1), in_flight)
+    .await
+    .expect("Test, assuming infallible.")
+    .expect("Test, assuming infallible.")
+    .expect_err("Command should fail once the device disconnects.");
+  // The error names the device the way DeviceAdded did, not by its address.
+  assert!(matches!(
+    err.original_error(),
+    ButtplugError::ButtplugDeviceError(ButtplugDeviceError::DeviceDisconnected(identifier))
+      if *identifier == device_name
+  ));
+}
+
+#[tokio::test]
+async fn test_server_busy_device_does_not_hold_up_others() {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let _busy_device = builder.add_test_device(&TestDeviceIdentifier::new(
+    "Flamingo",
+    Some("BusyAddress".to_owned()),
+  ));
+  let mut quiet_device = builder.add_test_device(&TestDeviceIdentifier::new(
+    "Flamingo",
+    Some("QuietAddress".to_owned()),
+  ));
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder.comm_manager(builder);
+  let server = server_builder.finish().expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  let mut added = 0;
+  while added < 2 {
+    if let Some(ButtplugServerMessage::DeviceAdded(_)) = recv.next().await.as_deref() {
+      added += 1;
+    }
+  }
+  let index_of = |address: &str| {
+    server
+      .device_manager()
+      .device_state_snapshots()
+      .iter()
+      .find(|device| device.address() == address)
+      .map(|device| device.device_index())
+      .expect("Test, assuming infallible.")
+  };
+  let busy_index = index_of("BusyAddress");
+  let quiet_index = index_of("QuietAddress");
+  let vibrate = |index, level| {
+    message::ScalarCmd::new(
+      index,
+      vec![message::ScalarSubcommand::new(
+        0,
+        level,
+        ActuatorType::Vibrate,
+      )],
+    )
+    .into()
+  };
+  server
+    .parse_message(vibrate(quiet_index, 0.5))
+    .await
+    .expect("Test, assuming infallible.");
+  assert_eq!(next_vibration_level(&mut quiet_device).await, 50);
+
+  // Back the busy device up with writes it never takes, then pile more output and a stop on top.
+  for i in 0..256 {
+    server
+      .parse_message(vibrate(busy_index, if i % 2 == 0 { 0.2 } else { 0.4 }))
+      .await
+      .expect("Test, assuming infallible.");
+  }
+  let _stuck_write = tokio::spawn(server.parse_message(vibrate(busy_index, 0.6)));
+  tokio::time::sleep(Duration::from_millis(50)).await;
+  for _ in 0..16 {
+    server
+      .parse_message(vibrate(busy_index, 0.8))
+      .await
+      .expect("Test, assuming infallible.");
+  }
+  let _stuck_stop =
+    tokio::spawn(server.parse_message(message::StopDeviceCmd::new(busy_index).into()));
+  // Commands go straight to the device they're for, so the quiet device is stopped right away.
+  tokio::time::timeout(
+    Duration::from_secs(1),
+    server.parse_message(message::StopDeviceCmd::new(quiet_index).into()),
+  )
+  .await
+  .expect("Test, assuming infallible.")
+  .expect("Test, assuming infallible.");
+  assert_eq!(next_vibration_level(&mut quiet_device).await, 0);
+}
+
+fn vibrate_keyframe(time: u32, level: f64) -> message::PatternKeyframe {
+  message::PatternKeyframe::new(
+    time,
+    vec![message::ScalarSubcommand::new(
+      0,
+      level,
+      ActuatorType::Vibrate,
+    )],
+    vec![],
+    vec![],
+  )
+}
+
+#[tokio::test]
+async fn test_server_pattern_playback() {
+  let (server, mut device) = test_server_with_device("Flamingo", false).await;
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  let mut device_index = None;
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = &*msg {
+      device_index = Some(da.device_index());
+      break;
+    }
+  }
+  let device_index = device_index.expect("Test, assuming infallible.");
+
+  // Flamingos can't rotate, so nothing gets played.
+  let rotate_keyframe = message::PatternKeyframe::new(
+    0,
+    vec![],
+    vec![],
+    vec![message::RotationSubcommand::new(0, 0.5, true)],
+  );
+  assert!(server
+    .parse_message(message::PatternCmd::new(device_index, vec![rotate_keyframe], false).into())
+    .await
+    .is_err());
+
+  let keyframes = vec![
+    vibrate_keyframe(0, 1.0),
+    vibrate_keyframe(20, 0.5),
+    vibrate_keyframe(40, 0.0),
+  ];
+  server
+    .parse_message(message::PatternCmd::new(device_index, keyframes, false).into())
+    .await
+    .expect("Test, assuming infallible.");
+  let mut levels = vec![];
+  for _ in 0..3 {
+    levels.push(next_vibration_level(&mut device).await);
+  }
+  assert_eq!(levels, vec![100, 50, 0]);
+
+  let keyframes = vec![vibrate_keyframe(0, 1.0), vibrate_keyframe(20, 0.0)];
+  server
+    .parse_message(message::PatternCmd::new(device_index, keyframes, true).into())
+    .await
+    .expect("Test, assuming infallible.");
+  let mut levels = vec![];
+  for _ in 0..3 {
+    levels.push(next_vibration_level(&mut device).await);
+  }
+  assert_eq!(levels, vec![100, 0, 100]);
+
+  // Stopping the device ends the loop.
+  server
+    .parse_message(message::StopDeviceCmd::new(device_index).into())
+    .await
+    .expect("Test, assuming infallible.");
+  assert_eq!(next_vibration_level(&mut device).await, 0);
+  assert!(
+    tokio::time::timeout(Duration::from_millis(100), device.receiver.recv())
+      .await
+      .is_err(),
+    "Pattern should not keep playing after the device is stopped"
+  );
+}
+
+const SENSOR_CALIBRATION_USER_CONFIG_JSON: &str = r#"
+{
+  "version": {
+    "major": 2,
+    "minor": 0
+  },
+  "user-configs": {
+    "devices": [
+      {
+        "identifier": {
+          "address": "SensorCalibrationAddress",
+          "protocol": "magic-motion-1",
+          "identifier": "Flamingo"
+        },
+        "config": {
+          "sensor-calibration": [
+            {
+              "sensor-index": 0,
+              "sensor-type": "Battery",
+              "offset": [10],
+              "scale": 2.0
+            }
+          ]
+        }
+      }
+    ]
+  }
+}
+"#;
+
+#[tokio::test]
+async fn test_server_sensor_calibration() {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let device = builder.add_test_device(&TestDeviceIdentifier::new(
+    "Flamingo",
+    Some("SensorCalibrationAddress".to_owned()),
+  ));
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder
+    .comm_manager(builder)
+    .user_device_configuration_json(Some(SENSOR_CALIBRATION_USER_CONFIG_JSON.to_owned()));
+  let server = server_builder.finish().expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  let mut device_index = None;
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = &*msg {
+      device_index = Some(da.device_index());
+      break;
+    }
+  }
+  let device_index = device_index.expect("Test, assuming infallible.");
+  device
+    .sender
+    .send(TestHardwareEvent::Reads(vec![
+      // Calibrated by the user config.
+      TestHardwareNotification::new(Endpoint::RxBLEBattery, &[40]),
+      // Baseline samples.
+      TestHardwareNotification::new(Endpoint::RxBLEBattery, &[41]),
+      TestHardwareNotification::new(Endpoint::RxBLEBattery, &[43]),
+      // Calibrated by the captured baseline.
+      TestHardwareNotification::new(Endpoint::RxBLEBattery, &[52]),
+    ]))
+    .await
+    .expect("Test, assuming infallible.");
+  let read_battery = || async {
+    match server
+      .parse_message(message::SensorReadCmd::new(device_index, 0, SensorType::Battery).into())
+      .await
+      .expect("Test, assuming infallible.")
+    {
+      ButtplugServerMessage::SensorReading(reading) => reading.data().clone(),
+      msg => panic!("Unexpected reply: {:?}", msg),
+    }
+  };
+  assert_eq!(read_battery().await, vec![60]);
+
+  let calibration = server
+    .device_manager()
+    .capture_sensor_baseline(
+      device_index,
+      0,
+      SensorType::Battery,
+      2,
+      server.progress_reporter(5),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  let mut progress = vec![];
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::OperationProgress(msg) = &*msg {
+      assert_eq!(msg.request_id(), 5);
+      progress.push(msg.progress());
+      if msg.progress() == 1.0 {
+        break;
+      }
+    }
+  }
+  assert_eq!(progress, vec![0.0, 0.5, 1.0]);
+  assert_eq!(calibration.offset(), &vec![42]);
+  assert_eq!(calibration.scale(), 2.0);
+  assert_eq!(read_battery().await, vec![20]);
+}
+
+const RSSI_USER_CONFIG_JSON: &str = r#"
+{
+  "version": {
+    "major": 2,
+    "minor": 0
+  },
+  "user-configs": {
+    "devices": [
+      {
+        "identifier": {
+          "address": "RssiAddress",
+          "protocol": "magic-motion-1",
+          "identifier": "Flamingo"
+        },
+        "config": {
+          "rssi-sample-interval": 10
+        }
+      }
+    ]
+  }
+}
+"#;
+
+#[tokio::test]
+async fn test_server_rssi_sensor() {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let device = builder.add_test_device(&TestDeviceIdentifier::new(
+    "Flamingo",
+    Some("RssiAddress".to_owned()),
+  ));
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder
+    .comm_manager(builder)
+    .user_device_configuration_json(Some(RSSI_USER_CONFIG_JSON.to_owned()));
+  let server = server_builder.finish().expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+
+  // Queued before scanning, so the device has it by the time it's added.
+  device
+    .sender
+    .send(TestHardwareEvent::Rssi(-40))
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  let mut device_added = None;
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = &*msg {
+      device_added = Some(da.clone());
+      break;
+    }
+  }
+  let device_added = device_added.expect("Test, assuming infallible.");
+  let device_index = device_added.device_index();
+  let rssi_index = |sensors: &Option<Vec<message::SensorDeviceMessageAttributes>>| {
+    sensors
+      .as_ref()
+      .and_then(|sensors| {
+        sensors
+          .iter()
+          .position(|sensor| *sensor.sensor_type() == SensorType::RSSI)
+      })
+      .expect("Test, assuming infallible.") as u32
+  };
+  let read_index = rssi_index(device_added.device_messages().sensor_read_cmd());
+  let subscribe_index = rssi_index(device_added.device_messages().sensor_subscribe_cmd());
+
+  match server
+    .parse_message(message::SensorReadCmd::new(device_index, read_index, SensorType::RSSI).into())
+    .await
+    .expect("Test, assuming infallible.")
+  {
+    ButtplugServerMessage::SensorReading(reading) => assert_eq!(reading.data(), &vec![60]),
+    msg => panic!("Unexpected reply: {:?}", msg),
+  }
+
+  server
+    .parse_message(
+      message::SensorSubscribeCmd::new(device_index, subscribe_index, SensorType::RSSI).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  let mut strengths = vec![];
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::SensorReading(reading) = &*msg {
+      assert_eq!(reading.sensor_type(), SensorType::RSSI);
+      strengths.push(reading.data()[0]);
+      if strengths.len() == 1 {
+        // Device moves closer.
+        device
+          .sender
+          .send(TestHardwareEvent::Rssi(-30))
+          .await
+          .expect("Test, assuming infallible.");
+      } else {
+        break;
+      }
+    }
+  }
+  assert_eq!(strengths, vec![60, 70]);
+  server
+    .parse_message(
+      message::SensorUnsubscribeCmd::new(device_index, subscribe_index, SensorType::RSSI).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+}
+
+#[cfg(feature = "scripting")]
+#[tokio::test]
+async fn test_server_command_script() {
+  use buttplug::server::device::{CommandScript, CommandScriptLimits};
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let mut device = builder.add_test_device(&TestDeviceIdentifier::new("Flamingo", None));
+  let script = CommandScript::new(
+    r#"
+    fn on_command(cmd) {
+      let halved = #{ device: cmd.device, scalars: [] };
+      for s in cmd.scalars {
+        halved.scalars.push(#{ index: s.index, scalar: s.scalar / 2.0, actuator: s.actuator });
+      }
+      [halved]
+    }
+    "#,
+    CommandScriptLimits::default(),
+  )
+  .expect("Test, assuming infallible.");
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder.comm_manager(builder).command_script(script);
+  let server = server_builder.finish().expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  let mut device_index = None;
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = &*msg {
+      device_index = Some(da.device_index());
+      break;
+    }
+  }
+  let device_index = device_index.expect("Test, assuming infallible.");
+  let vibrate = |device_index| {
+    message::ScalarCmd::new(
+      device_index,
+      vec![message::ScalarSubcommand::new(
+        0,
+        1.0,
+        ActuatorType::Vibrate,
+      )],
+    )
+  };
+  server
+    .parse_message(vibrate(device_index).into())
+    .await
+    .expect("Test, assuming infallible.");
+  assert_eq!(next_vibration_level(&mut device).await, 50);
+  // Commands the script sends to missing devices fail like the client had sent them.
+  let err = server
+    .parse_message(vibrate(device_index + 1).into())
+    .await
+    .expect_err("Device doesn't exist.");
+  assert_eq!(
+    err.original_error(),
+    ButtplugError::ButtplugDeviceError(ButtplugDeviceError::DeviceNotAvailable(device_index + 1))
+  );
+}
+
+#[tokio::test]
+async fn test_server_test_device_extra_endpoints() {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let mut device = builder.add_test_device(
+    &TestDeviceIdentifier::new("Massage Demo", None).endpoints(&[Endpoint::Generic0]),
+  );
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder.comm_manager(builder).allow_raw_messages();
+  let server = server_builder
+    .finish()
+    .expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(_) = &*msg {
+      break;
+    }
+  }
+  // The extra endpoint can be written to, on top of the ones from the device config.
+  server
+    .parse_message(message::RawWriteCmd::new(0, Endpoint::Generic0, &[1, 2], false).into())
+    .await
+    .expect("Test, assuming infallible.");
+  assert_eq!(
+    device.next_command().await,
+    Some(HardwareCommand::Write(HardwareWriteCmd::new(
+      Endpoint::Generic0,
+      vec![1, 2],
+      false
+    )))
+  );
+  server
+    .parse_message(message::RawSubscribeCmd::new(0, Endpoint::Generic0).into())
+    .await
+    .expect("Test, assuming infallible.");
+  assert!(matches!(
+    device.next_command().await,
+    Some(HardwareCommand::Subscribe(_))
+  ));
+  assert!(device.try_next_command().is_none());
+  device
+    .notify(Endpoint::Generic0, &[3])
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::RawReading(reading) = &*msg {
+      assert_eq!(reading.endpoint(), Endpoint::Generic0);
+      assert_eq!(reading.data(), &vec![3]);
+      return;
+    }
+  }
+  panic!("Notification never made it to the server event stream");
+}
+
+/// Lets a test hold up the device manager, by having scans it starts wait until released.
+#[derive(Default)]
+struct ScanGate {
+  closed: AtomicBool,
+  scan_waiting: Notify,
+  released: Notify,
+}
+
+struct GatedScanCommunicationManagerBuilder {
+  gate: Arc<ScanGate>,
+}
+
+impl HardwareCommunicationManagerBuilder for GatedScanCommunicationManagerBuilder {
+  fn finish(
+    &mut self,
+    sender: Sender<HardwareCommunicationManagerEvent>,
+  ) -> Box<dyn HardwareCommunicationManager> {
+    Box::new(GatedScanCommunicationManager {
+      _sender: sender,
+      gate: self.gate.clone(),
+    })
+  }
+}
+
+struct GatedScanCommunicationManager {
+  // The device manager stops once every comm manager has dropped its sender.
+  _sender: Sender<HardwareCommunicationManagerEvent>,
+  gate: Arc<ScanGate>,
+}
+
+impl HardwareCommunicationManager for GatedScanCommunicationManager {
+  fn name(&self) -> &'static str {
+    "GatedScanCommunicationManager"
+  }
+
+  fn start_scanning(&mut self) -> ButtplugResultFuture {
+    let gate = self.gate.clone();
+    async move {
+      if gate.closed.load(Ordering::SeqCst) {
+        gate.scan_waiting.notify_one();
+        gate.released.notified().await;
+      }
+      Ok(())
+    }
+    .boxed()
+  }
+
+  fn stop_scanning(&mut self) -> ButtplugResultFuture {
+    future::ready(Ok(())).boxed()
+  }
+
+  fn status(&self) -> HardwareCommunicationManagerStatus {
+    HardwareCommunicationManagerStatus::from_availability(true, false)
+  }
+}
+
+#[tokio::test]
+async fn test_server_reports_dropped_notifications() {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let device = builder.add_test_device(
+    &TestDeviceIdentifier::new("Massage Demo", None).endpoints(&[Endpoint::Generic0]),
+  );
+  let gate = Arc::new(ScanGate::default());
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder
+    .comm_manager(builder)
+    .comm_manager(GatedScanCommunicationManagerBuilder { gate: gate.clone() })
+    .allow_raw_messages();
+  let server = server_builder.finish().expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  // The next scan can only start once this one is finished.
+  let mut device_index = None;
+  let mut scanning_finished = false;
+  while device_index.is_none() || !scanning_finished {
+    match recv.next().await.as_deref() {
+      Some(ButtplugServerMessage::DeviceAdded(da)) => device_index = Some(da.device_index()),
+      Some(ButtplugServerMessage::ScanningFinished(_)) => scanning_finished = true,
+      Some(_) => {}
+      None => panic!("Event stream ended before the device was added"),
+    }
+  }
+  let device_index = device_index.expect("Test, assuming infallible.");
+  server
+    .parse_message(message::RawSubscribeCmd::new(device_index, Endpoint::Generic0).into())
+    .await
+    .expect("Test, assuming infallible.");
+
+  // Hold up the device manager with a scan that doesn't finish starting, so readings back up.
+  gate.closed.store(true, Ordering::SeqCst);
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  gate.scan_waiting.notified().await;
+  // More readings than the device manager and the device's notification queue can hold between
+  // them.
+  for i in 0..1024u32 {
+    device
+      .notify(Endpoint::Generic0, &i.to_le_bytes())
+      .await
+      .expect("Test, assuming infallible.");
+    tokio::task::yield_now().await;
+  }
+  tokio::time::sleep(Duration::from_millis(100)).await;
+  gate.released.notify_one();
+
+  let dropped = tokio::time::timeout(Duration::from_secs(5), async {
+    while let Some(msg) = recv.next().await {
+      if let ButtplugServerMessage::Error(err) = &*msg {
+        return err.original_error();
+      }
+    }
+    panic!("Event stream ended before readings were reported dropped");
+  })
+  .await
+  .expect("Test, assuming infallible.");
+  match dropped {
+    ButtplugError::ButtplugDeviceError(ButtplugDeviceError::DeviceNotificationsDropped(
+      index,
+      count,
+    )) => {
+      assert_eq!(index, device_index);
+      assert!(count > 0);
+    }
+    err => panic!("Expected dropped notifications, got {:?}", err),
+  }
+}
+
+#[tokio::test]
+async fn test_server_firmware_mode() {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  builder.add_test_device(
+    &TestDeviceIdentifier::new("Massage Demo", None).endpoints(&[Endpoint::Firmware]),
+  );
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder.comm_manager(builder).allow_raw_messages();
+  let server = server_builder.finish().expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(_) = &*msg {
+      break;
+    }
+  }
+  for enabled in [true, false] {
+    assert!(matches!(
+      server
+        .parse_message(message::FirmwareModeCmd::new(0, enabled).into())
+        .await
+        .expect("Test, assuming infallible."),
+      ButtplugServerMessage::Ok(_)
+    ));
+  }
+}
+
+#[tokio::test]
+async fn test_server_firmware_mode_needs_firmware_endpoint() {
+  let (server, _device) = test_server_with_device("Massage Demo", true).await;
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(_) = &*msg {
+      break;
+    }
+  }
+  let err = server
+    .parse_message(message::FirmwareModeCmd::new(0, true).into())
+    .await
+    .expect_err("Test, assuming infallible.");
+  assert_eq!(
+    err.original_error(),
+    ButtplugError::ButtplugDeviceError(ButtplugDeviceError::InvalidEndpoint(Endpoint::Firmware))
+  );
+}
+
+#[cfg(feature = "serialize-json")]
+#[tokio::test]
+async fn test_server_intensity_telemetry() {
+  use buttplug::server::intensity_telemetry::{IntensityTelemetryReader, IntensityTelemetryWriter};
+
+  let path = std::env::temp_dir().join(format!(
+    "buttplug-test-telemetry-{}.jsonl",
+    std::process::id()
+  ));
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  builder.add_test_device(&TestDeviceIdentifier::new("Massage Demo", None));
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder.comm_manager(builder).intensity_telemetry(
+    IntensityTelemetryWriter::to_file(&path, "install-key").expect("Test, assuming infallible."),
+  );
+  let server = server_builder.finish().expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(_) = &*msg {
+      break;
+    }
+  }
+  server
+    .parse_message(
+      message::ScalarCmd::new(
+        0,
+        vec![message::ScalarSubcommand::new(0, 0.5, ActuatorType::Vibrate)],
+      )
+      .into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StopDeviceCmd::new(0).into())
+    .await
+    .expect("Test, assuming infallible.");
+  // Rejected commands aren't recorded.
+  assert!(server
+    .parse_message(
+      message::ScalarCmd::new(
+        0,
+        vec![message::ScalarSubcommand::new(9, 1.0, ActuatorType::Vibrate)],
+      )
+      .into(),
+    )
+    .await
+    .is_err());
+
+  let reader = IntensityTelemetryReader::from_file(&path).expect("Test, assuming infallible.");
+  std::fs::remove_file(&path).expect("Test, assuming infallible.");
+  let devices = reader.devices();
+  assert_eq!(devices.len(), 1);
+  assert!(devices[0].starts_with("addr-"));
+  assert_eq!(reader.samples()[0].protocol(), "aneros");
+  let values: Vec<f64> = reader
+    .timeline(devices[0], 0, ActuatorType::Vibrate)
+    .iter()
+    .map(|(_, value)| *value)
+    .collect();
+  assert_eq!(values, vec![0.5, 0.0]);
+  assert_eq!(reader.samples().len(), 2);
+}
+
+const ESTIM_INTERLOCK_USER_CONFIG_JSON: &str = r#"
+{
+  "version": {
+    "major": 2,
+    "minor": 0
+  },
+  "user-configs": {
+    "specifiers": {
+      "magic-motion-1": {
+        "estim-interlock": {
+          "ramp-up": RAMP_UP,
+          "max-step": 0.2,
+          "step-interval": 100
+        }
+      }
+    }
+  }
+}
+"#;
+
+// Sets up a server treating the Flamingo as an e-stim device with the given ramp up time, in
+// milliseconds. Returns the server, the device and its index.
+async fn estim_flamingo(
+  ramp_up: u32,
+  ping_time: u32,
+) -> (ButtplugServer, TestDeviceChannelHost, u32) {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let device = builder.add_test_device(&TestDeviceIdentifier::new("Flamingo", None));
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder
+    .comm_manager(builder)
+    .max_ping_time(ping_time)
+    .user_device_configuration_json(Some(
+      ESTIM_INTERLOCK_USER_CONFIG_JSON.replace("RAMP_UP", &ramp_up.to_string()),
+    ));
+  let server = server_builder.finish().expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  let mut device_index = None;
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = &*msg {
+      device_index = Some(da.device_index());
+      break;
+    }
+  }
+  (
+    server,
+    device,
+    device_index.expect("Test, assuming infallible."),
+  )
+}
+
+fn vibrate(device_index: u32, level: f64) -> message::ButtplugClientMessage {
+  message::ScalarCmd::new(
+    device_index,
+    vec![message::ScalarSubcommand::new(
+      0,
+      level,
+      ActuatorType::Vibrate,
+    )],
+  )
+  .into()
+}
+
+fn is_unarmed_error(err: &message::Error) -> bool {
+  matches!(
+    err.original_error(),
+    ButtplugError::ButtplugDeviceError(ButtplugDeviceError::ProtocolRequirementError(reason))
+      if reason.contains("armed")
+  )
+}
+
+#[tokio::test]
+async fn test_server_estim_arming() {
+  let (server, mut device, device_index) = estim_flamingo(0, 0).await;
+  let err = server
+    .parse_message(vibrate(device_index, 0.5))
+    .await
+    .expect_err("Test, assuming infallible.");
+  assert!(is_unarmed_error(&err), "Unexpected error: {:?}", err);
+  // Stopping doesn't need arming.
+  server
+    .parse_message(vibrate(device_index, 0.0))
+    .await
+    .expect("Test, assuming infallible.");
+  assert_eq!(next_vibration_level(&mut device).await, 0);
+
+  server
+    .parse_message(message::EstimArmCmd::new(device_index, true).into())
+    .await
+    .expect("Test, assuming infallible.");
+  tokio::time::sleep(Duration::from_millis(150)).await;
+  server
+    .parse_message(vibrate(device_index, 0.2))
+    .await
+    .expect("Test, assuming infallible.");
+  assert_eq!(next_vibration_level(&mut device).await, 20);
+
+  // Disarming stops the device, and output is refused again.
+  server
+    .parse_message(message::EstimArmCmd::new(device_index, false).into())
+    .await
+    .expect("Test, assuming infallible.");
+  assert_eq!(next_vibration_level(&mut device).await, 0);
+  let err = server
+    .parse_message(vibrate(device_index, 0.5))
+    .await
+    .expect_err("Test, assuming infallible.");
+  assert!(is_unarmed_error(&err), "Unexpected error: {:?}", err);
+}
+
+#[tokio::test]
+async fn test_server_estim_arm_needs_estim_device() {
+  let (server, _device) = test_server_with_device("Flamingo", false).await;
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(_) = &*msg {
+      break;
+    }
+  }
+  assert!(server
+    .parse_message(message::EstimArmCmd::new(0, true).into())
+    .await
+    .is_err());
+}
+
+#[tokio::test]
+async fn test_server_estim_ramp_from_zero() {
+  let (server, mut device, device_index) = estim_flamingo(60000, 0).await;
+  server
+    .parse_message(message::EstimArmCmd::new(device_index, true).into())
+    .await
+    .expect("Test, assuming infallible.");
+  tokio::time::sleep(Duration::from_millis(150)).await;
+  server
+    .parse_message(vibrate(device_index, 1.0))
+    .await
+    .expect("Test, assuming infallible.");
+  // We're barely into a minute long ramp, so the device should only just be above nothing.
+  let level = next_vibration_level(&mut device).await;
+  assert!(level < 5, "Level {} should be capped by the ramp", level);
+}
+
+#[tokio::test]
+async fn test_server_estim_step_limit() {
+  let (server, mut device, device_index) = estim_flamingo(0, 0).await;
+  server
+    .parse_message(message::EstimArmCmd::new(device_index, true).into())
+    .await
+    .expect("Test, assuming infallible.");
+  let mut levels = vec![];
+  for _ in 0..2 {
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    server
+      .parse_message(vibrate(device_index, 1.0))
+      .await
+      .expect("Test, assuming infallible.");
+    levels.push(next_vibration_level(&mut device).await);
+  }
+  assert_eq!(levels, vec![20, 40]);
+  // Drops aren't limited.
+  server
+    .parse_message(vibrate(device_index, 0.1))
+    .await
+    .expect("Test, assuming infallible.");
+  assert_eq!(next_vibration_level(&mut device).await, 10);
+}
+
+// Arms the device and gets it running, so there's something to zero.
+async fn armed_and_running(
+  server: &ButtplugServer,
+  device: &mut TestDeviceChannelHost,
+  index: u32,
+) {
+  server
+    .parse_message(message::EstimArmCmd::new(index, true).into())
+    .await
+    .expect("Test, assuming infallible.");
+  tokio::time::sleep(Duration::from_millis(150)).await;
+  server
+    .parse_message(vibrate(index, 0.2))
+    .await
+    .expect("Test, assuming infallible.");
+  assert_eq!(next_vibration_level(device).await, 20);
+}
+
+// Connects a new session to the server, and checks the device has to be armed again.
+async fn assert_disarmed(server: &ButtplugServer, device_index: u32) {
+  let session = server.session();
+  session
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  let err = session
+    .parse_message(vibrate(device_index, 0.2))
+    .await
+    .expect_err("Test, assuming infallible.");
+  assert!(is_unarmed_error(&err), "Unexpected error: {:?}", err);
+}
+
+#[tokio::test]
+async fn test_server_estim_zeroed_on_disconnect() {
+  let (server, mut device, device_index) = estim_flamingo(0, 0).await;
+  armed_and_running(&server, &mut device, device_index).await;
+  server
+    .disconnect()
+    .await
+    .expect("Test, assuming infallible.");
+  assert_eq!(next_vibration_level(&mut device).await, 0);
+  assert_disarmed(&server, device_index).await;
+}
+
+#[tokio::test]
+async fn test_server_estim_zeroed_on_ping_timeout() {
+  let (server, mut device, device_index) = estim_flamingo(0, 200).await;
+  armed_and_running(&server, &mut device, device_index).await;
+  // No pings are sent, so the server times out and stops everything.
+  assert_eq!(next_vibration_level(&mut device).await, 0);
+  // Disarming happens once the stop is done.
+  tokio::time::sleep(Duration::from_millis(50)).await;
+  assert_disarmed(&server, device_index).await;
+}
@@ -0,0 +1,335 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+#![cfg(all(feature = "virtual-device-manager", feature = "client"))]
+
+#[cfg(feature = "serialize-json")]
+use buttplug::server::session_recording::{SessionRecordEvent, SessionRecorder, SessionReplayer};
+use buttplug::{
+  client::{
+    ButtplugClient,
+    ButtplugClientDevice,
+    ButtplugClientDeviceEvent,
+    ButtplugClientEvent,
+    LinearCommand,
+    RotateCommand,
+    ScalarValueCommand,
+  },
+  core::{
+    connector::ButtplugInProcessClientConnectorBuilder,
+    message::{ButtplugCurrentSpecServerMessage, SensorType},
+  },
+  server::{
+    device::hardware::communication::virtual_device::{
+      VirtualDeviceCommunicationManagerBuilder,
+      VirtualDeviceFaults,
+      VirtualDeviceType,
+    },
+    ButtplugServerBuilder,
+  },
+};
+use futures::StreamExt;
+use std::{
+  sync::Arc,
+  time::{Duration, Instant},
+};
+
+async fn virtual_device_client(
+  builder: VirtualDeviceCommunicationManagerBuilder,
+  device_count: usize,
+) -> (ButtplugClient, Vec<Arc<ButtplugClientDevice>>) {
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder.comm_manager(builder);
+  server_device_client(server_builder, device_count).await
+}
+
+async fn server_device_client(
+  mut server_builder: ButtplugServerBuilder,
+  device_count: usize,
+) -> (ButtplugClient, Vec<Arc<ButtplugClientDevice>>) {
+  let connector = ButtplugInProcessClientConnectorBuilder::default()
+    .server(server_builder.finish().expect("Test, assuming infallible."))
+    .finish();
+  let client = ButtplugClient::new("Test Client");
+  let mut event_stream = client.event_stream();
+  client
+    .connect(connector)
+    .await
+    .expect("Test, assuming infallible.");
+  client
+    .start_scanning()
+    .await
+    .expect("Test, assuming infallible.");
+  let mut devices = vec![];
+  while let Some(event) = event_stream.next().await {
+    if let ButtplugClientEvent::DeviceAdded(device) = event {
+      devices.push(device);
+      if devices.len() == device_count {
+        break;
+      }
+    }
+  }
+  devices.sort_by_key(|device| device.index());
+  (client, devices)
+}
+
+#[tokio::test]
+async fn test_virtual_devices_found_and_controllable() {
+  let mut builder = VirtualDeviceCommunicationManagerBuilder::default();
+  builder
+    .add_devices(VirtualDeviceType::Vibrator, 2)
+    .add_device(VirtualDeviceType::Stroker)
+    .add_device(VirtualDeviceType::Rotator);
+  let (_client, devices) = virtual_device_client(builder, 4).await;
+
+  let names: Vec<&str> = devices
+    .iter()
+    .map(|device| device.name().as_str())
+    .collect();
+  assert_eq!(
+    names,
+    vec![
+      "MagicMotion Flamingo",
+      "MagicMotion Flamingo",
+      "Vorze Piston",
+      "Vorze UFO SA"
+    ]
+  );
+  for vibrator in &devices[0..2] {
+    vibrator
+      .vibrate(&ScalarValueCommand::ScalarValue(0.5))
+      .await
+      .expect("Test, assuming infallible.");
+    assert_eq!(
+      vibrator
+        .battery_level()
+        .await
+        .expect("Test, assuming infallible."),
+      1.0
+    );
+  }
+  devices[2]
+    .linear(&LinearCommand::Linear(500, 0.75))
+    .await
+    .expect("Test, assuming infallible.");
+  devices[3]
+    .rotate(&RotateCommand::Rotate(0.5, true))
+    .await
+    .expect("Test, assuming infallible.");
+}
+
+#[tokio::test]
+async fn test_virtual_pressure_sensor_readings() {
+  let mut builder = VirtualDeviceCommunicationManagerBuilder::default();
+  builder.add_device(VirtualDeviceType::PressureSensor);
+  let (_client, devices) = virtual_device_client(builder, 1).await;
+  let sensor = &devices[0];
+  let mut device_events = sensor.event_stream();
+  sensor
+    .subscribe_sensor(0, SensorType::Pressure)
+    .await
+    .expect("Test, assuming infallible.");
+  let mut readings = vec![];
+  while let Some(event) = device_events.next().await {
+    if let ButtplugClientDeviceEvent::Message(ButtplugCurrentSpecServerMessage::SensorReading(
+      reading,
+    )) = event
+    {
+      assert_eq!(reading.sensor_type(), SensorType::Pressure);
+      readings.push(reading.data()[0]);
+      if readings.len() == 3 {
+        break;
+      }
+    }
+  }
+  assert!(readings.iter().all(|value| (0..=1000).contains(value)));
+  // The synthetic wave is always moving, so consecutive readings shouldn't be flat.
+  assert!(readings.windows(2).any(|pair| pair[0] != pair[1]));
+}
+
+#[tokio::test]
+async fn test_virtual_stroker_estimated_position() {
+  let mut builder = VirtualDeviceCommunicationManagerBuilder::default();
+  builder.add_device(VirtualDeviceType::Stroker);
+  let (_client, devices) = virtual_device_client(builder, 1).await;
+  let stroker = &devices[0];
+  let sensor_index = *stroker
+    .message_attributes()
+    .sensor_subscribe_cmd()
+    .as_ref()
+    .expect("Test, assuming infallible.")
+    .iter()
+    .find(|sensor| *sensor.sensor_type() == SensorType::Position)
+    .expect("Test, assuming infallible.")
+    .index();
+  let mut device_events = stroker.event_stream();
+  stroker
+    .subscribe_sensor(sensor_index, SensorType::Position)
+    .await
+    .expect("Test, assuming infallible.");
+  stroker
+    .linear(&LinearCommand::Linear(300, 1.0))
+    .await
+    .expect("Test, assuming infallible.");
+  let mut readings = vec![];
+  while let Some(event) = device_events.next().await {
+    if let ButtplugClientDeviceEvent::Message(ButtplugCurrentSpecServerMessage::SensorReading(
+      reading,
+    )) = event
+    {
+      assert_eq!(reading.sensor_type(), SensorType::Position);
+      readings.push(reading.data()[0]);
+      if reading.data()[0] == 1000 {
+        break;
+      }
+    }
+  }
+  // The estimate moves up toward the target, and stops there.
+  assert!(readings.windows(2).all(|pair| pair[0] < pair[1]));
+  assert!(readings.len() > 1);
+  stroker
+    .unsubscribe_sensor(sensor_index, SensorType::Position)
+    .await
+    .expect("Test, assuming infallible.");
+}
+
+#[tokio::test]
+async fn test_virtual_device_injected_latency() {
+  let mut builder = VirtualDeviceCommunicationManagerBuilder::default();
+  builder.add_device_with_faults(
+    VirtualDeviceType::Vibrator,
+    VirtualDeviceFaults {
+      min_latency: Duration::from_millis(100),
+      max_latency: Duration::from_millis(150),
+      ..Default::default()
+    },
+  );
+  let (_client, devices) = virtual_device_client(builder, 1).await;
+  let start = Instant::now();
+  devices[0]
+    .vibrate(&ScalarValueCommand::ScalarValue(0.5))
+    .await
+    .expect("Test, assuming infallible.");
+  assert!(start.elapsed() >= Duration::from_millis(100));
+}
+
+#[tokio::test]
+async fn test_virtual_device_injected_disconnect() {
+  let mut builder = VirtualDeviceCommunicationManagerBuilder::default();
+  builder.add_device_with_faults(
+    VirtualDeviceType::Vibrator,
+    VirtualDeviceFaults {
+      disconnect_after: Some(Duration::from_millis(100)),
+      ..Default::default()
+    },
+  );
+  let (_client, devices) = virtual_device_client(builder, 1).await;
+  let mut device_events = devices[0].event_stream();
+  while let Some(event) = device_events.next().await {
+    if let ButtplugClientDeviceEvent::DeviceRemoved = event {
+      break;
+    }
+  }
+  assert!(!devices[0].connected());
+}
+
+#[tokio::test]
+async fn test_virtual_device_malformed_battery_read() {
+  let mut builder = VirtualDeviceCommunicationManagerBuilder::default();
+  builder.add_device_with_faults(
+    VirtualDeviceType::Vibrator,
+    VirtualDeviceFaults {
+      malformed_response_rate: 1.0,
+      ..Default::default()
+    },
+  );
+  let (_client, devices) = virtual_device_client(builder, 1).await;
+  // A garbage read should come back as an error, not take down the server.
+  assert!(devices[0].battery_level().await.is_err());
+  devices[0]
+    .vibrate(&ScalarValueCommand::ScalarValue(0.5))
+    .await
+    .expect("Test, assuming infallible.");
+}
+
+#[tokio::test]
+async fn test_virtual_device_dropped_notifications() {
+  let mut builder = VirtualDeviceCommunicationManagerBuilder::default();
+  builder.add_device_with_faults(
+    VirtualDeviceType::PressureSensor,
+    VirtualDeviceFaults {
+      notification_drop_rate: 1.0,
+      ..Default::default()
+    },
+  );
+  let (_client, devices) = virtual_device_client(builder, 1).await;
+  let mut device_events = devices[0].event_stream();
+  devices[0]
+    .subscribe_sensor(0, SensorType::Pressure)
+    .await
+    .expect("Test, assuming infallible.");
+  assert!(
+    tokio::time::timeout(Duration::from_millis(500), device_events.next())
+      .await
+      .is_err()
+  );
+}
+
+#[cfg(feature = "serialize-json")]
+#[tokio::test]
+async fn test_session_record_and_replay() {
+  let path = std::env::temp_dir().join(format!(
+    "buttplug-test-session-{}.jsonl",
+    std::process::id()
+  ));
+  let mut builder = VirtualDeviceCommunicationManagerBuilder::default();
+  builder
+    .add_device(VirtualDeviceType::Vibrator)
+    .add_device(VirtualDeviceType::Stroker);
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder
+    .comm_manager(builder)
+    .session_recorder(SessionRecorder::to_file(&path).expect("Test, assuming infallible."));
+  let (client, devices) = server_device_client(server_builder, 2).await;
+  devices[0]
+    .vibrate(&ScalarValueCommand::ScalarValue(0.5))
+    .await
+    .expect("Test, assuming infallible.");
+  devices[1]
+    .linear(&LinearCommand::Linear(500, 0.75))
+    .await
+    .expect("Test, assuming infallible.");
+  client
+    .disconnect()
+    .await
+    .expect("Test, assuming infallible.");
+
+  let mut replayer = SessionReplayer::from_file(&path).expect("Test, assuming infallible.");
+  std::fs::remove_file(&path).expect("Test, assuming infallible.");
+  replayer.realtime(false);
+  let mut replay_builder = ButtplugServerBuilder::default();
+  replay_builder.comm_manager(replayer.virtual_devices());
+  let server = replay_builder.finish().expect("Test, assuming infallible.");
+  let report = replayer
+    .replay(&server)
+    .await
+    .expect("Test, assuming infallible.");
+
+  let recorded_client_messages = replayer
+    .entries()
+    .iter()
+    .filter(|entry| matches!(entry.event(), SessionRecordEvent::ClientMessage(_)))
+    .count();
+  assert!(recorded_client_messages >= 4);
+  assert_eq!(report.replies().len(), recorded_client_messages);
+  assert!(report.mismatches().is_empty());
+  assert_eq!(report.device_indexes().len(), 2);
+  assert!(report
+    .events()
+    .iter()
+    .any(|event| matches!(event, ButtplugCurrentSpecServerMessage::DeviceAdded(_))));
+}
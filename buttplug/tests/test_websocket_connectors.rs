@@ -13,19 +13,22 @@ mod util;
 mod websocket_connector_tests {
   use crate::util::ButtplugTestServer;
   use buttplug::{
-    client::ButtplugClient,
+    client::{ButtplugClient, ButtplugClientEvent},
     core::{
       connector::{
+        transport::ButtplugTakeoverPolicy,
         ButtplugRemoteClientConnector,
         ButtplugRemoteServerConnector,
         ButtplugWebsocketClientTransport,
         ButtplugWebsocketServerTransport,
         ButtplugWebsocketServerTransportBuilder,
       },
+      errors::{ButtplugError, ButtplugHandshakeError},
       message::serializer::{ButtplugClientJSONSerializer, ButtplugServerJSONSerializer},
     },
     util::async_manager,
   };
+  use futures::StreamExt;
   use std::{sync::Arc, time::Duration};
   use tokio::time::sleep;
 
@@ -113,6 +116,192 @@ mod websocket_connector_tests {
       .await
       .expect("Test, assuming infallible.");
   }
+
+  async fn connect_client(name: &str, address: &str) -> ButtplugClient {
+    for _ in 0..10u8 {
+      let connector = ButtplugRemoteClientConnector::<
+        ButtplugWebsocketClientTransport,
+        ButtplugClientJSONSerializer,
+      >::new(ButtplugWebsocketClientTransport::new_insecure_connector(
+        address,
+      ));
+      let client = ButtplugClient::new(name);
+      if client.connect(connector).await.is_ok() {
+        return client;
+      }
+      sleep(Duration::from_secs(1)).await;
+    }
+    panic!("Could not connect to server");
+  }
+
+  #[tokio::test]
+  async fn test_client_takeover() {
+    let test_server = ButtplugTestServer::default();
+    let server = Arc::new(test_server);
+    let server_clone = server.clone();
+    async_manager::spawn(async move {
+      let connector = ButtplugRemoteServerConnector::<
+        ButtplugWebsocketServerTransport,
+        ButtplugServerJSONSerializer,
+      >::new(
+        ButtplugWebsocketServerTransportBuilder::default()
+          .port(12350)
+          .takeover_policy(ButtplugTakeoverPolicy::Takeover)
+          .finish(),
+      );
+      server_clone
+        .start(connector)
+        .await
+        .expect("Test, assuming infallible.");
+    });
+    let old_client = connect_client("Old Client", "ws://127.0.0.1:12350").await;
+    let mut old_events = old_client.event_stream();
+    let new_client = connect_client("New Client", "ws://127.0.0.1:12350").await;
+    assert!(matches!(
+      old_events.next().await,
+      Some(ButtplugClientEvent::Error(
+        ButtplugError::ButtplugHandshakeError(ButtplugHandshakeError::ConnectionTakenOver)
+      ))
+    ));
+    assert!(matches!(
+      old_events.next().await,
+      Some(ButtplugClientEvent::ServerDisconnect)
+    ));
+    assert!(new_client.connected());
+    new_client
+      .stop_all_devices()
+      .await
+      .expect("Test, assuming infallible.");
+    server
+      .disconnect()
+      .await
+      .expect("Test, assuming infallible.");
+  }
+
+  #[tokio::test]
+  async fn test_client_sessions() {
+    let server = Arc::new(ButtplugTestServer::default());
+    let listener = ButtplugWebsocketServerTransportBuilder::default()
+      .port(12351)
+      .listen()
+      .await
+      .expect("Test, assuming infallible.");
+    let server_clone = server.clone();
+    async_manager::spawn(async move {
+      server_clone
+        .start_sessions(listener.transports().map(|transport| {
+          ButtplugRemoteServerConnector::<
+            ButtplugWebsocketServerTransport,
+            ButtplugServerJSONSerializer,
+          >::new(transport)
+        }))
+        .await;
+    });
+    // Both clients stay connected, neither one takes over from the other.
+    let first_client = connect_client("First Client", "ws://127.0.0.1:12351").await;
+    let second_client = connect_client("Second Client", "ws://127.0.0.1:12351").await;
+    assert!(first_client.connected());
+    assert!(second_client.connected());
+    first_client
+      .stop_all_devices()
+      .await
+      .expect("Test, assuming infallible.");
+    second_client
+      .stop_all_devices()
+      .await
+      .expect("Test, assuming infallible.");
+    // Clients can come and go without affecting each other.
+    first_client
+      .disconnect()
+      .await
+      .expect("Test, assuming infallible.");
+    second_client
+      .stop_all_devices()
+      .await
+      .expect("Test, assuming infallible.");
+    let mut second_events = second_client.event_stream();
+    server
+      .disconnect()
+      .await
+      .expect("Test, assuming infallible.");
+    assert!(matches!(
+      second_events.next().await,
+      Some(ButtplugClientEvent::ServerDisconnect)
+    ));
+  }
 }
 
 // TODO Test disconnection event from server side
+
+#[cfg(all(feature = "mdns", feature = "client"))]
+mod mdns_tests {
+  use crate::util::ButtplugTestServer;
+  use buttplug::{
+    client::ButtplugClient,
+    core::{
+      connector::{
+        discover_buttplug_servers,
+        ButtplugRemoteClientConnector,
+        ButtplugRemoteServerConnector,
+        ButtplugWebsocketClientTransport,
+        ButtplugWebsocketServerTransport,
+        ButtplugWebsocketServerTransportBuilder,
+      },
+      message::serializer::{ButtplugClientJSONSerializer, ButtplugServerJSONSerializer},
+    },
+    util::async_manager,
+  };
+  use std::{sync::Arc, time::Duration};
+
+  #[tokio::test]
+  async fn test_discover_advertised_ws_server() {
+    let server = Arc::new(ButtplugTestServer::default());
+    let server_clone = server.clone();
+    async_manager::spawn(async move {
+      let connector = ButtplugRemoteServerConnector::<
+        ButtplugWebsocketServerTransport,
+        ButtplugServerJSONSerializer,
+      >::new(
+        ButtplugWebsocketServerTransportBuilder::default()
+          .port(12363)
+          .listen_on_all_interfaces(true)
+          .advertise(Some("mDNS Test Server".to_owned()))
+          .finish(),
+      );
+      server_clone
+        .start(connector)
+        .await
+        .expect("Test, assuming infallible.");
+    });
+    let mut discovered = None;
+    for _ in 0..10u8 {
+      discovered = discover_buttplug_servers(Duration::from_millis(500))
+        .await
+        .expect("Test, assuming infallible.")
+        .into_iter()
+        .find(|server| server.name() == "mDNS Test Server");
+      if discovered.is_some() {
+        break;
+      }
+    }
+    let discovered = discovered.expect("Test, assuming infallible.");
+    assert_eq!(discovered.address().port(), 12363);
+    assert_eq!(discovered.version(), Some(3));
+    // The advertised address should be one we can actually connect to.
+    let connector = ButtplugRemoteClientConnector::<
+      ButtplugWebsocketClientTransport,
+      ButtplugClientJSONSerializer,
+    >::new(ButtplugWebsocketClientTransport::new_insecure_connector(
+      &format!("ws://{}", discovered.address()),
+    ));
+    let client = ButtplugClient::new("Test Client");
+    client
+      .connect(connector)
+      .await
+      .expect("Test, assuming infallible.");
+    server
+      .disconnect()
+      .await
+      .expect("Test, assuming infallible.");
+  }
+}
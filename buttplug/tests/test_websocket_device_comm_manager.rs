@@ -13,8 +13,11 @@ mod test {
   use buttplug::{
     client::ButtplugClient,
     core::connector::ButtplugInProcessClientConnectorBuilder,
-    server::device::hardware::communication::websocket_server::websocket_server_comm_manager::WebsocketServerDeviceCommunicationManagerBuilder,
-    server::ButtplugServerBuilder,
+    server::device::hardware::communication::websocket_server::websocket_server_comm_manager::{
+      WebsocketServerDeviceCommunicationManagerBuilder,
+      WebsocketServerDeviceCommunicationManagerConfig,
+    },
+    server::{ButtplugServerBuilder, ButtplugServerError},
   };
 
   async fn setup_test_client() -> ButtplugClient {
@@ -43,4 +46,37 @@ mod test {
     let client = setup_test_client().await;
     assert!(client.connected());
   }
+
+  #[tokio::test]
+  async fn test_websocket_server_dcm_config() {
+    let mut builder = ButtplugServerBuilder::default();
+    builder.comm_manager_config(WebsocketServerDeviceCommunicationManagerConfig {
+      listen_on_all_interfaces: false,
+      server_port: 51284,
+    });
+    let server = builder.finish().expect("Test, assuming infallible.");
+    let connector = ButtplugInProcessClientConnectorBuilder::default()
+      .server(server)
+      .finish();
+    let client = ButtplugClient::new("Websocket DCM Config Test Client");
+    client
+      .connect(connector)
+      .await
+      .expect("Test, assuming infallible.");
+    assert!(client.connected());
+  }
+
+  #[tokio::test]
+  async fn test_websocket_server_dcm_config_duplicate_manager() {
+    // Configs resolve to the same comm manager as their builders, so adding both is still a
+    // duplicate.
+    let mut builder = ButtplugServerBuilder::default();
+    builder
+      .comm_manager(WebsocketServerDeviceCommunicationManagerBuilder::default())
+      .comm_manager_config(WebsocketServerDeviceCommunicationManagerConfig::default());
+    assert!(matches!(
+      builder.finish(),
+      Err(ButtplugServerError::DeviceCommunicationManagerTypeAlreadyAdded(_))
+    ));
+  }
 }
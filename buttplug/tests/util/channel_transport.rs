@@ -7,12 +7,13 @@
 
 #![allow(dead_code)]
 
-use crate::util::ButtplugTestServer;
+use crate::util::{test_server::ButtplugServerConnectorError, ButtplugTestServer};
 use buttplug::{
   client::{ButtplugClient, ButtplugClientError},
   core::{
     connector::{
       transport::{ButtplugConnectorTransport, ButtplugTransportIncomingMessage},
+      ButtplugConnectionQuota,
       ButtplugConnectorError,
       ButtplugRemoteClientConnector,
       ButtplugRemoteServerConnector,
@@ -27,6 +28,7 @@ use buttplug::{
       },
       ButtplugClientMessage,
       ButtplugCurrentSpecClientMessage,
+      ButtplugCurrentSpecServerMessage,
       ButtplugMessage,
       ButtplugServerMessage,
       BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
@@ -35,7 +37,7 @@ use buttplug::{
   util::async_manager,
 };
 use futures::{
-  future::{self, BoxFuture},
+  future::{self, BoxFuture, Future},
   select,
   FutureExt,
 };
@@ -113,6 +115,11 @@ impl ButtplugConnectorTransport for ChannelTransport {
     self.disconnect_notifier.notify_waiters();
     future::ready(Ok(())).boxed()
   }
+
+  // There's only ever the one channel, so tests play both the old and new client over it.
+  fn complete_takeover(&self) -> BoxFuture<'static, Result<(), ButtplugConnectorError>> {
+    future::ready(Ok(())).boxed()
+  }
 }
 
 pub struct ChannelClientTestHelper {
@@ -136,11 +143,10 @@ impl ChannelClientTestHelper {
       outgoing_sender,
     )))));
     let client_serializer = ButtplugClientJSONSerializer::default();
-    let rsi_setup_msg = client_serializer.serialize(&vec![message::RequestServerInfo::new(
-      "Test client",
-      BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
-    )
-    .into()]);
+    let rsi_setup_msg =
+      client_serializer.serialize(&[ButtplugCurrentSpecClientMessage::from(
+        message::RequestServerInfo::new("Test client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION),
+      )]);
     let server_serializer = ButtplugServerJSONSerializer::default();
     server_serializer
       .deserialize(&rsi_setup_msg)
@@ -213,6 +219,11 @@ impl ChannelClientTestHelper {
   }
 
   pub async fn next_client_message(&self) -> ButtplugClientMessage {
+    self.next_client_messages().await[0].clone()
+  }
+
+  /// Every message in the next serialized frame the client sends.
+  pub async fn next_client_messages(&self) -> Vec<ButtplugClientMessage> {
     self
       .server_serializer
       .deserialize(
@@ -221,8 +232,7 @@ impl ChannelClientTestHelper {
           .await
           .expect("Test, assuming infallible"),
       )
-      .expect("Test, assuming infallible")[0]
-      .clone()
+      .expect("Test, assuming infallible")
   }
 
   pub async fn recv_outgoing(&self) -> Option<ButtplugSerializedMessage> {
@@ -273,16 +283,21 @@ pub struct ChannelServerTestHelper {
 
 impl ChannelServerTestHelper {
   pub fn new() -> Self {
+    Self::new_with_quota(None)
+  }
+
+  pub fn new_with_quota(quota: Option<ButtplugConnectionQuota>) -> Self {
     let server = Arc::new(ButtplugTestServer::default());
     let (incoming_sender, incoming_receiver) = channel(256);
     let (outgoing_sender, outgoing_receiver) = channel(256);
-    let connector = Arc::new(Mutex::new(Some(ButtplugRemoteServerConnector::<
-      ChannelTransport,
-      ButtplugServerJSONSerializer,
-    >::new(ChannelTransport::new(
-      incoming_receiver,
-      outgoing_sender,
-    )))));
+    let transport = ChannelTransport::new(incoming_receiver, outgoing_sender);
+    let connector = Arc::new(Mutex::new(Some(if let Some(quota) = quota {
+      ButtplugRemoteServerConnector::<ChannelTransport, ButtplugServerJSONSerializer>::new_with_quota(
+        transport, quota,
+      )
+    } else {
+      ButtplugRemoteServerConnector::<ChannelTransport, ButtplugServerJSONSerializer>::new(transport)
+    })));
     let client_serializer = ButtplugClientJSONSerializer::default();
     let server_serializer = ButtplugServerJSONSerializer::default();
     Self {
@@ -299,6 +314,34 @@ impl ChannelServerTestHelper {
     &self.server
   }
 
+  /// Runs the server over the channel connector, resolving once the connection ends.
+  pub async fn start(&self) -> impl Future<Output = Result<(), ButtplugServerConnectorError>> {
+    let connector = self
+      .connector
+      .lock()
+      .await
+      .take()
+      .expect("Test, assuming infallible");
+    self.server.start(connector)
+  }
+
+  pub async fn next_server_message(&self) -> ButtplugCurrentSpecServerMessage {
+    self.next_server_messages().await[0].clone()
+  }
+
+  /// Every message in the next serialized frame the server sends.
+  pub async fn next_server_messages(&self) -> Vec<ButtplugCurrentSpecServerMessage> {
+    self
+      .client_serializer
+      .deserialize(
+        &self
+          .recv_outgoing()
+          .await
+          .expect("Test, assuming infallible"),
+      )
+      .expect("Test, assuming infallible")
+  }
+
   pub async fn recv_outgoing(&self) -> Option<ButtplugSerializedMessage> {
     // If this ever conflicts, its the tests fault, so just panic.
     self
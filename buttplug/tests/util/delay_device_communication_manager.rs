@@ -7,10 +7,11 @@
 
 use buttplug::{
   core::ButtplugResultFuture,
-  server::device::hardware::communication::{
+  server::device::hardware::api::{
     HardwareCommunicationManager,
     HardwareCommunicationManagerBuilder,
     HardwareCommunicationManagerEvent,
+    HardwareCommunicationManagerStatus,
   },
 };
 use futures::FutureExt;
@@ -74,11 +75,10 @@ impl HardwareCommunicationManager for DelayDeviceCommunicationManager {
     .boxed()
   }
 
-  fn scanning_status(&self) -> bool {
-    self.is_scanning.load(Ordering::SeqCst)
-  }
-
-  fn can_scan(&self) -> bool {
-    true
+  fn status(&self) -> HardwareCommunicationManagerStatus {
+    HardwareCommunicationManagerStatus::from_availability(
+      true,
+      self.is_scanning.load(Ordering::SeqCst),
+    )
   }
 }
@@ -138,7 +138,7 @@ impl ButtplugConnector<ButtplugSpecV2ClientMessage, ButtplugSpecV2ServerMessage>
             // in-process conversion, we can unwrap because we know our
             // try_into() will always succeed (which may not be the case with
             // remote connections that have different spec versions).
-            if send.send(event.try_into().expect("This is in-process so we're always on the latest message spec, this will always work.")).await.is_err() {
+            if send.send(Arc::unwrap_or_clone(event).try_into().expect("This is in-process so we're always on the latest message spec, this will always work.")).await.is_err() {
               break;
             }
           }
@@ -22,7 +22,7 @@ use buttplug::core::{
     ButtplugSpecV2ServerMessage,
   },
 };
-use std::sync::Arc;
+use std::{borrow::Borrow, sync::Arc};
 use tokio::sync::{mpsc, Notify};
 
 use self::channel_transport::ChannelTransport;
@@ -43,8 +43,11 @@ impl ButtplugMessageSerializer for ButtplugClientJSONSerializerV2 {
     self.serializer_impl.deserialize(msg)
   }
 
-  fn serialize(&self, msg: &[Self::Outbound]) -> ButtplugSerializedMessage {
-    self.serializer_impl.serialize(msg)
+  fn serialize<T>(&self, msgs: &[T]) -> ButtplugSerializedMessage
+  where
+    T: Borrow<Self::Outbound>,
+  {
+    self.serializer_impl.serialize::<Self::Outbound, _>(msgs)
   }
 }
 
@@ -0,0 +1,463 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Golden files of the hardware commands each protocol generates for a set of canonical inputs.
+//!
+//! Every file in the `protocol_golden` directory covers one protocol. For each device listed in the
+//! file, the harness identifies and initializes the protocol against a test device, then hands the
+//! listed inputs straight to the protocol handler. Since this skips the server and the generic
+//! command manager, the results only depend on the protocol implementation and its device
+//! configuration, and any change to the bytes a protocol writes shows up as a golden mismatch.
+//!
+//! After an intentional protocol change, regenerate the files by running the golden test with
+//! `BUTTPLUG_BLESS_GOLDEN=1` set. Blessing also creates files for protocols that don't have one
+//! yet, using the names in the device configuration file and inputs derived from the device's
+//! attributes.
+
+use crate::util::test_device_manager::{
+  new_device_channel,
+  TestDevice,
+  TestHardwareConnector,
+  TestHardwareEvent,
+};
+use buttplug::{
+  core::message::{ActuatorType, LinearCmd, VectorSubcommand},
+  server::device::{
+    configuration::{
+      BluetoothLESpecifier,
+      DeviceConfigurationManager,
+      ProtocolCommunicationSpecifier,
+      ProtocolDeviceAttributes,
+    },
+    hardware::{HardwareCommand, HardwareConnector},
+    protocol::{get_default_protocol_map, ProtocolHandler, ProtocolIdentifierFactory},
+  },
+  util::device_configuration::load_protocol_configs,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+  collections::HashMap,
+  path::{Path, PathBuf},
+  sync::Arc,
+  time::Duration,
+};
+
+/// Environment variable that turns golden checks into golden updates.
+const BLESS_ENV_VAR: &str = "BUTTPLUG_BLESS_GOLDEN";
+const GOLDEN_HEADER: &str =
+  "# Protocol golden file. Regenerate with BUTTPLUG_BLESS_GOLDEN=1, see tests/util/device_test/golden.rs\n";
+// Long enough for handshakes that wait between writes, short enough that protocols waiting on
+// device responses we didn't provide don't stall the test run.
+const SETUP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Input handed directly to a protocol handler, in the form the generic command manager produces.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+enum GoldenInput {
+  Scalar(Vec<Option<(ActuatorType, u32)>>),
+  Rotate(Vec<Option<(u32, bool)>>),
+  Linear(Vec<VectorSubcommand>),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct GoldenCommand {
+  input: GoldenInput,
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  output: Vec<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct GoldenDevice {
+  name: String,
+  /// Reads and notifications sent to the test device before identification, for protocols that
+  /// need device responses during their handshake.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  init_events: Vec<TestHardwareEvent>,
+  /// Commands written to the hardware while identifying and initializing the protocol.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  initialize: Vec<String>,
+  #[serde(default)]
+  commands: Vec<GoldenCommand>,
+}
+
+impl GoldenDevice {
+  fn new(name: &str) -> Self {
+    Self {
+      name: name.to_owned(),
+      init_events: vec![],
+      initialize: vec![],
+      commands: vec![],
+    }
+  }
+
+  fn same_results(&self, other: &GoldenDevice) -> bool {
+    self.initialize == other.initialize && self.commands == other.commands
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct ProtocolGolden {
+  protocol: String,
+  /// Protocols that can't be driven without a live device (i.e. ones that identify themselves
+  /// through notifications) give the reason here instead of listing devices.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  skip: Option<String>,
+  #[serde(default)]
+  devices: Vec<GoldenDevice>,
+}
+
+fn golden_dir() -> PathBuf {
+  Path::new(&std::env::var("CARGO_MANIFEST_DIR").expect("Should have manifest path"))
+    .join("tests")
+    .join("util")
+    .join("device_test")
+    .join("protocol_golden")
+}
+
+fn golden_path(protocol: &str) -> PathBuf {
+  golden_dir().join(format!("{}.yaml", protocol))
+}
+
+fn format_command(command: &HardwareCommand) -> String {
+  match command {
+    HardwareCommand::Write(cmd) => format!(
+      "{} {} [{}]",
+      if cmd.write_with_response() {
+        "write-with-response"
+      } else {
+        "write"
+      },
+      cmd.endpoint(),
+      cmd
+        .data()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<String>>()
+        .join(" ")
+    ),
+    HardwareCommand::Subscribe(cmd) => format!("subscribe {}", cmd.endpoint()),
+    HardwareCommand::Unsubscribe(cmd) => format!("unsubscribe {}", cmd.endpoint()),
+  }
+}
+
+/// Full power, half power, then stop for every actuator the device has.
+fn canonical_inputs(attributes: &ProtocolDeviceAttributes) -> Vec<GoldenInput> {
+  let message_attributes = attributes.message_attributes();
+  let mut inputs = vec![];
+  if let Some(scalars) = message_attributes.scalar_cmd() {
+    let half = |start: u32, end: u32| start + ((end - start) as f64 / 2.0).ceil() as u32;
+    inputs.push(GoldenInput::Scalar(
+      scalars
+        .iter()
+        .map(|attr| Some((*attr.actuator_type(), *attr.step_range().end())))
+        .collect(),
+    ));
+    inputs.push(GoldenInput::Scalar(
+      scalars
+        .iter()
+        .map(|attr| {
+          let range = attr.step_range();
+          Some((*attr.actuator_type(), half(*range.start(), *range.end())))
+        })
+        .collect(),
+    ));
+    inputs.push(GoldenInput::Scalar(
+      scalars
+        .iter()
+        .map(|attr| Some((*attr.actuator_type(), 0)))
+        .collect(),
+    ));
+  }
+  if let Some(rotations) = message_attributes.rotate_cmd() {
+    inputs.push(GoldenInput::Rotate(
+      rotations
+        .iter()
+        .map(|attr| Some((*attr.step_range().end(), true)))
+        .collect(),
+    ));
+    inputs.push(GoldenInput::Rotate(
+      rotations
+        .iter()
+        .map(|attr| Some(((attr.step_count() as f64 / 2.0).ceil() as u32, false)))
+        .collect(),
+    ));
+    inputs.push(GoldenInput::Rotate(
+      rotations.iter().map(|_| Some((0, true))).collect(),
+    ));
+  }
+  if let Some(linears) = message_attributes.linear_cmd() {
+    inputs.push(GoldenInput::Linear(
+      (0..linears.len() as u32)
+        .map(|index| VectorSubcommand::new(index, 500, 1.0))
+        .collect(),
+    ));
+    inputs.push(GoldenInput::Linear(
+      (0..linears.len() as u32)
+        .map(|index| VectorSubcommand::new(index, 1000, 0.25))
+        .collect(),
+    ));
+  }
+  inputs
+}
+
+fn run_input(handler: &Arc<dyn ProtocolHandler>, input: GoldenInput) -> GoldenCommand {
+  let result = match &input {
+    GoldenInput::Scalar(commands) => handler.handle_scalar_cmd(commands),
+    GoldenInput::Rotate(commands) => handler.handle_rotate_cmd(commands),
+    GoldenInput::Linear(vectors) => handler.handle_linear_cmd(LinearCmd::new(0, vectors.clone())),
+  };
+  match result {
+    Ok(commands) => GoldenCommand {
+      input,
+      output: commands.iter().map(format_command).collect(),
+      error: None,
+    },
+    Err(err) => GoldenCommand {
+      input,
+      output: vec![],
+      error: Some(err.to_string()),
+    },
+  }
+}
+
+/// Identify and initialize the protocol for a device, then run its inputs. Devices without inputs
+/// get the canonical set for their attributes.
+async fn run_golden_device(
+  dcm: Arc<DeviceConfigurationManager>,
+  factory: Arc<dyn ProtocolIdentifierFactory>,
+  specifiers: Vec<ProtocolCommunicationSpecifier>,
+  device: GoldenDevice,
+) -> Result<GoldenDevice, String> {
+  let specifier = ProtocolCommunicationSpecifier::BluetoothLE(
+    BluetoothLESpecifier::new_from_device(&device.name, &HashMap::new(), &[]),
+  );
+  if !specifiers.contains(&specifier) {
+    return Err("device name does not match the protocol".to_owned());
+  }
+
+  let (mut host, device_channel) = new_device_channel();
+  for event in &device.init_events {
+    host
+      .sender
+      .send(event.clone())
+      .await
+      .expect("Test device should be alive");
+  }
+  let mut connector = TestHardwareConnector::new(
+    specifier,
+    TestDevice::new(&device.name, "golden-test-device", device_channel),
+  );
+  let hardware = Arc::new(
+    connector
+      .connect()
+      .await
+      .map_err(|e| e.to_string())?
+      .specialize(&specifiers)
+      .await
+      .map_err(|e| e.to_string())?,
+  );
+
+  let mut identifier = factory.create();
+  let (device_identifier, mut initializer) =
+    tokio::time::timeout(SETUP_TIMEOUT, identifier.identify(hardware.clone()))
+      .await
+      .map_err(|_| "timed out identifying device".to_owned())?
+      .map_err(|e| e.to_string())?;
+  let attributes = dcm
+    .protocol_device_attributes(&device_identifier, &hardware.endpoints())
+    .ok_or_else(|| "no device attributes found".to_owned())?;
+  let handler = tokio::time::timeout(
+    SETUP_TIMEOUT,
+    initializer.initialize(hardware.clone(), &attributes),
+  )
+  .await
+  .map_err(|_| "timed out initializing device".to_owned())?
+  .map_err(|e| e.to_string())?;
+
+  let mut initialize = vec![];
+  while let Ok(command) = host.receiver.try_recv() {
+    initialize.push(format_command(&command));
+  }
+
+  let inputs = if device.commands.is_empty() {
+    canonical_inputs(&attributes)
+  } else {
+    device.commands.iter().map(|c| c.input.clone()).collect()
+  };
+  Ok(GoldenDevice {
+    initialize,
+    commands: inputs
+      .into_iter()
+      .map(|input| run_input(&handler, input))
+      .collect(),
+    ..device
+  })
+}
+
+/// Runs the device on its own task, so that test devices panicking on reads the golden file didn't
+/// provide fail that device instead of the whole harness.
+async fn run_golden_device_task(
+  dcm: Arc<DeviceConfigurationManager>,
+  factory: Arc<dyn ProtocolIdentifierFactory>,
+  specifiers: &[ProtocolCommunicationSpecifier],
+  device: &GoldenDevice,
+) -> Result<GoldenDevice, String> {
+  tokio::spawn(run_golden_device(
+    dcm,
+    factory,
+    specifiers.to_vec(),
+    device.clone(),
+  ))
+  .await
+  .unwrap_or_else(|_| Err("panicked while running device".to_owned()))
+}
+
+/// Golden file to create for a protocol that doesn't have one, with a device for every name in its
+/// bluetooth specifier.
+fn new_protocol_golden(
+  protocol: &str,
+  specifiers: &[ProtocolCommunicationSpecifier],
+) -> ProtocolGolden {
+  let mut names: Vec<String> = specifiers
+    .iter()
+    .filter_map(|specifier| match specifier {
+      ProtocolCommunicationSpecifier::BluetoothLE(btle) => Some(btle.names().clone()),
+      _ => None,
+    })
+    .flatten()
+    .map(|name| name.replace('*', "Golden"))
+    .collect();
+  names.sort();
+  ProtocolGolden {
+    protocol: protocol.to_owned(),
+    skip: None,
+    devices: names.iter().map(|name| GoldenDevice::new(name)).collect(),
+  }
+}
+
+fn write_golden(golden: &ProtocolGolden) {
+  let yaml = serde_yaml::to_string(golden).expect("Golden file should serialize");
+  std::fs::write(
+    golden_path(&golden.protocol),
+    format!("{}{}", GOLDEN_HEADER, yaml),
+  )
+  .expect("Should be able to write golden file");
+}
+
+fn compare_device(protocol: &str, expected: &GoldenDevice, actual: &GoldenDevice) -> Vec<String> {
+  let mut failures = vec![];
+  if expected.initialize != actual.initialize {
+    failures.push(format!(
+      "{} ({}): initialization wrote {:?}, expected {:?}",
+      protocol, expected.name, actual.initialize, expected.initialize
+    ));
+  }
+  for (expected_command, actual_command) in expected.commands.iter().zip(actual.commands.iter()) {
+    if expected_command != actual_command {
+      failures.push(format!(
+        "{} ({}): {:?} produced {:?} / {:?}, expected {:?} / {:?}",
+        protocol,
+        expected.name,
+        expected_command.input,
+        actual_command.output,
+        actual_command.error,
+        expected_command.output,
+        expected_command.error
+      ));
+    }
+  }
+  failures
+}
+
+/// Check (or with [BLESS_ENV_VAR] set, update) the golden files for every bluetooth protocol.
+///
+/// Returns a description of every mismatch found, so a single run shows the whole damage of a
+/// protocol change.
+pub async fn run_protocol_golden_tests() -> Vec<String> {
+  let bless = std::env::var(BLESS_ENV_VAR).is_ok();
+  let dcm = Arc::new(
+    load_protocol_configs(None, None, false)
+      .expect("Built in device configuration should load")
+      .finish()
+      .expect("Built in device configuration should be valid"),
+  );
+  let protocol_map = get_default_protocol_map();
+  let mut protocols: Vec<(String, Vec<ProtocolCommunicationSpecifier>)> = dcm
+    .protocol_device_configurations()
+    .into_iter()
+    .filter(|(protocol, specifiers)| {
+      protocol_map.contains_key(protocol)
+        && specifiers
+          .iter()
+          .any(|s| matches!(s, ProtocolCommunicationSpecifier::BluetoothLE(_)))
+    })
+    .collect();
+  protocols.sort_by(|a, b| a.0.cmp(&b.0));
+
+  let mut failures = vec![];
+  for (protocol, specifiers) in protocols {
+    let path = golden_path(&protocol);
+    let (golden, new_file) = if path.exists() {
+      let yaml =
+        std::fs::read_to_string(&path).unwrap_or_else(|_| panic!("Cannot read file {:?}", path));
+      let golden: ProtocolGolden = serde_yaml::from_str(&yaml)
+        .unwrap_or_else(|e| panic!("Could not parse golden file {:?}: {}", path, e));
+      (golden, false)
+    } else if bless {
+      (new_protocol_golden(&protocol, &specifiers), true)
+    } else {
+      failures.push(format!(
+        "{}: no golden file, run with {}=1 to create one",
+        protocol, BLESS_ENV_VAR
+      ));
+      continue;
+    };
+    if golden.skip.is_some() {
+      continue;
+    }
+
+    let mut blessed_devices: Vec<GoldenDevice> = vec![];
+    for device in &golden.devices {
+      let factory = protocol_map[&protocol].clone();
+      match run_golden_device_task(dcm.clone(), factory, &specifiers, device).await {
+        Ok(actual) => {
+          if bless {
+            // New files list every name the protocol knows, only keep the ones that behave
+            // differently from the devices already covered.
+            if !new_file || !blessed_devices.iter().any(|d| d.same_results(&actual)) {
+              blessed_devices.push(actual);
+            }
+          } else {
+            failures.append(&mut compare_device(&protocol, device, &actual));
+          }
+        }
+        Err(err) if bless && new_file => {
+          eprintln!(
+            "Not adding {} ({}) to new golden file: {}",
+            protocol, device.name, err
+          );
+        }
+        Err(err) => failures.push(format!("{} ({}): {}", protocol, device.name, err)),
+      }
+    }
+    if bless {
+      if blessed_devices.is_empty() {
+        failures.push(format!(
+          "{}: no device could be run, add init_events or a skip reason to its golden file",
+          protocol
+        ));
+      } else {
+        write_golden(&ProtocolGolden {
+          devices: blessed_devices,
+          ..golden
+        });
+      }
+    }
+  }
+  failures
+}
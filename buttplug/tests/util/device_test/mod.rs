@@ -2,6 +2,7 @@
 #![allow(dead_code)]
 pub mod client;
 pub mod connector;
+pub mod golden;
 use super::{TestDeviceIdentifier, TestHardwareEvent};
 use buttplug::{
   core::message::{RotationSubcommand, ScalarSubcommand, VectorSubcommand, VibrateSubcommand},
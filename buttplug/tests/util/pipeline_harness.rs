@@ -0,0 +1,171 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! End to end harness for the scan, identify, connect and command pipeline.
+//!
+//! Runs a real client against a server over the in process connector, with test devices that show
+//! up whenever the scenario adds them, scanning that runs until the scenario stops it, and hardware
+//! the scenario disconnects when it wants to. Scenarios then check the exact events the client saw,
+//! in order.
+
+#![allow(dead_code)]
+
+use super::{TestDeviceChannelHost, TestDeviceCommunicationManagerBuilder, TestDeviceIdentifier};
+use buttplug::{
+  client::{ButtplugClient, ButtplugClientDevice, ButtplugClientEvent},
+  core::connector::ButtplugInProcessClientConnectorBuilder,
+  server::ButtplugServerBuilder,
+};
+use futures::{Stream, StreamExt};
+use std::{pin::Pin, sync::Arc, time::Duration};
+use tokio::time::timeout;
+
+/// How long to wait for an event before deciding it isn't coming.
+const EVENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Client event, cut down to what scenarios check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PipelineEvent {
+  /// Device added, by client visible name.
+  DeviceAdded(String),
+  /// Device removed, by client visible name.
+  DeviceRemoved(String),
+  ScanningFinished,
+  ServerDisconnect,
+  /// Anything else, by its debug output.
+  Other(String),
+}
+
+impl From<ButtplugClientEvent> for PipelineEvent {
+  fn from(event: ButtplugClientEvent) -> Self {
+    match event {
+      ButtplugClientEvent::DeviceAdded(device) => Self::DeviceAdded(device.name().clone()),
+      ButtplugClientEvent::DeviceRemoved(device) => Self::DeviceRemoved(device.name().clone()),
+      ButtplugClientEvent::ScanningFinished => Self::ScanningFinished,
+      ButtplugClientEvent::ServerDisconnect => Self::ServerDisconnect,
+      event => Self::Other(format!("{:?}", event)),
+    }
+  }
+}
+
+pub struct PipelineHarness {
+  client: ButtplugClient,
+  events: Pin<Box<dyn Stream<Item = ButtplugClientEvent> + Send>>,
+  devices: TestDeviceCommunicationManagerBuilder,
+}
+
+impl PipelineHarness {
+  /// Starts a server with no devices and connects a client to it.
+  pub async fn new() -> Self {
+    let mut devices = TestDeviceCommunicationManagerBuilder::default();
+    devices.scan_until_stopped(true);
+    let mut server_builder = ButtplugServerBuilder::default();
+    server_builder.comm_manager(devices.clone());
+    let connector = ButtplugInProcessClientConnectorBuilder::default()
+      .server(server_builder.finish().expect("Test, assuming infallible."))
+      .finish();
+    let client = ButtplugClient::new("Pipeline Test Client");
+    let events = Box::pin(client.event_stream());
+    client
+      .connect(connector)
+      .await
+      .expect("Test, assuming infallible.");
+    Self {
+      client,
+      events,
+      devices,
+    }
+  }
+
+  pub fn client(&self) -> &ButtplugClient {
+    &self.client
+  }
+
+  /// Makes a device show up, found right away if a scan is running or on the next scan otherwise.
+  /// Adding the same identifier again acts like the device advertising again.
+  pub fn add_device(&mut self, identifier: &TestDeviceIdentifier) -> TestDeviceChannelHost {
+    self.devices.add_test_device(identifier)
+  }
+
+  /// Client side handle for a connected device, by client visible name.
+  pub fn device(&self, name: &str) -> Arc<ButtplugClientDevice> {
+    self
+      .client
+      .devices()
+      .into_iter()
+      .find(|device| device.name() == name)
+      .unwrap_or_else(|| panic!("{} should be connected", name))
+  }
+
+  pub async fn start_scanning(&self) {
+    self
+      .client
+      .start_scanning()
+      .await
+      .expect("Test, assuming infallible.");
+  }
+
+  pub async fn stop_scanning(&self) {
+    self
+      .client
+      .stop_scanning()
+      .await
+      .expect("Test, assuming infallible.");
+  }
+
+  /// Waits for the next client events, and checks they're exactly `expected`. Events that were
+  /// already waiting before the client connected (i.e. ServerConnect) are skipped.
+  pub async fn expect_events(&mut self, expected: &[PipelineEvent]) {
+    let mut received = vec![];
+    while received.len() < expected.len() {
+      match timeout(EVENT_TIMEOUT, self.events.next()).await {
+        Ok(Some(ButtplugClientEvent::ServerConnect)) => continue,
+        Ok(Some(event)) => received.push(PipelineEvent::from(event)),
+        Ok(None) => panic!("Client event stream closed, received {:?}", received),
+        Err(_) => panic!(
+          "Timed out waiting for {:?}, received {:?}",
+          expected, received
+        ),
+      }
+    }
+    assert_eq!(received, expected);
+  }
+
+  /// Disconnects the client, checking that nothing but the removal of the devices still connected
+  /// was left to see. Scenarios end with this, so stray events show up as failures.
+  pub async fn finish(mut self) {
+    let mut connected: Vec<String> = self
+      .client
+      .devices()
+      .iter()
+      .map(|device| device.name().clone())
+      .collect();
+    self
+      .client
+      .disconnect()
+      .await
+      .expect("Test, assuming infallible.");
+    // Devices are removed in no particular order, so compare them sorted.
+    connected.sort();
+    let mut expected: Vec<PipelineEvent> = connected
+      .into_iter()
+      .map(PipelineEvent::DeviceRemoved)
+      .collect();
+    expected.push(PipelineEvent::ServerDisconnect);
+    let mut received = vec![];
+    while received.last() != Some(&PipelineEvent::ServerDisconnect) {
+      match timeout(EVENT_TIMEOUT, self.events.next()).await {
+        Ok(Some(ButtplugClientEvent::ServerConnect)) => continue,
+        Ok(Some(event)) => received.push(PipelineEvent::from(event)),
+        _ => panic!("Timed out waiting for disconnect, received {:?}", received),
+      }
+    }
+    let removed = received.len() - 1;
+    received[..removed].sort_by_key(|event| format!("{:?}", event));
+    assert_eq!(received, expected);
+  }
+}
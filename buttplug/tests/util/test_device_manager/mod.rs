@@ -5,29 +5,21 @@
 // Licensed under the BSD 3-Clause license. See LICENSE file in the project root
 // for full license information.
 
-mod test_device;
-#[cfg(feature = "server")]
-mod test_device_comm_manager;
-
 use buttplug::{
   server::device::hardware::HardwareCommand,
   util::stream::{iffy_is_empty_check, recv_now},
 };
-use std::sync::{Arc, Mutex};
-pub use test_device::{
+pub use buttplug::server::device::hardware::communication::test_device::{
+  new_device_channel,
   TestDevice,
   TestDeviceChannelHost,
+  TestDeviceCommunicationManagerBuilder,
+  TestDeviceIdentifier,
   TestHardwareConnector,
   TestHardwareEvent,
   TestHardwareNotification,
 };
-#[cfg(feature = "server")]
-pub use test_device_comm_manager::{
-  //new_bluetoothle_test_device,
-  TestDeviceCommunicationManager,
-  TestDeviceCommunicationManagerBuilder,
-  TestDeviceIdentifier,
-};
+use std::sync::{Arc, Mutex};
 use tokio::sync::mpsc::Receiver;
 
 #[allow(dead_code)]